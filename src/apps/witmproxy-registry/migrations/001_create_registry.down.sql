@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_plugin_versions_name;
+DROP TABLE IF EXISTS plugin_versions;
+DROP TABLE IF EXISTS publishers;
@@ -0,0 +1,25 @@
+CREATE TABLE publishers (
+    namespace TEXT PRIMARY KEY,
+    -- Hex-encoded wasmsign2 public key every submission must be signed with
+    public_key TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE plugin_versions (
+    namespace TEXT NOT NULL REFERENCES publishers(namespace),
+    name TEXT NOT NULL,
+    version TEXT NOT NULL,
+    description TEXT NOT NULL DEFAULT '',
+    author TEXT NOT NULL DEFAULT '',
+    license TEXT NOT NULL DEFAULT '',
+    url TEXT NOT NULL DEFAULT '',
+    -- Comma-separated capability kinds requested by the manifest
+    capabilities TEXT NOT NULL DEFAULT '',
+    component_sha256 TEXT NOT NULL,
+    source_sha256 TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    published_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (namespace, name, version)
+);
+
+CREATE INDEX idx_plugin_versions_name ON plugin_versions(name);
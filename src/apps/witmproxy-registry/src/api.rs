@@ -0,0 +1,405 @@
+use std::sync::Arc;
+
+use salvo::http::StatusError;
+use salvo::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use salvo::oapi::extract::{JsonBody, PathParam, QueryParam};
+use salvo::oapi::{OpenApi, ToSchema};
+use salvo::prelude::*;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::config::RegistryConfig;
+use crate::db::{PluginVersion, Publisher, RegistryDb};
+use crate::error::RegistryError;
+use crate::storage::BlobStore;
+use crate::verify::{Submission, Verifier};
+
+/// Shared state injected into every endpoint
+#[derive(Clone)]
+pub struct AppState {
+    pub db: RegistryDb,
+    pub blobs: BlobStore,
+    pub verifier: Arc<Verifier>,
+    pub config: Arc<RegistryConfig>,
+}
+
+pub fn router(state: AppState) -> Router {
+    let router = Router::new()
+        .hoop(affix_state::inject(state))
+        .push(Router::with_path("/api/v1/publishers").post(create_publisher))
+        .push(Router::with_path("/api/v1/publishers/{namespace}").get(get_publisher))
+        .push(
+            Router::with_path("/api/v1/plugins")
+                .get(list_plugins)
+                .post(submit_plugin),
+        )
+        .push(Router::with_path("/api/v1/plugins/{namespace}/{name}").get(get_plugin))
+        .push(Router::with_path("/api/v1/plugins/{namespace}/{name}/{version}").get(get_version))
+        .push(
+            Router::with_path("/api/v1/plugins/{namespace}/{name}/{version}/download")
+                .get(download_version),
+        );
+
+    let doc = OpenApi::new("witmproxy-registry", env!("CARGO_PKG_VERSION"))
+        .merge_router(&router)
+        .add_security_scheme(
+            "bearer",
+            salvo::oapi::security::SecurityScheme::Http(
+                salvo::oapi::security::Http::new(salvo::oapi::security::HttpAuthScheme::Bearer)
+                    .description("The registry's admin token"),
+            ),
+        );
+    router.unshift(doc.into_router("/api/docs/openapi.json"))
+}
+
+fn state(depot: &Depot) -> Result<&AppState, StatusError> {
+    depot.obtain::<AppState>().map_err(|_| {
+        warn!("Failed to obtain AppState");
+        StatusError::internal_server_error()
+    })
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreatePublisher {
+    /// Namespace to claim, including the leading `@`
+    pub namespace: String,
+    /// Hex-encoded wasmsign2 public key
+    pub public_key: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PublisherSummary {
+    pub namespace: String,
+    pub public_key: String,
+    pub created_at: String,
+}
+
+impl From<Publisher> for PublisherSummary {
+    fn from(publisher: Publisher) -> Self {
+        Self {
+            namespace: publisher.namespace,
+            public_key: publisher.public_key,
+            created_at: publisher.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PluginSummary {
+    pub namespace: String,
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub author: String,
+    pub license: String,
+    pub url: String,
+    pub capabilities: Vec<String>,
+    /// SHA-256 of the signed component as served for download
+    pub component_sha256: String,
+    /// SHA-256 of the source archive the component was rebuilt from
+    pub source_sha256: String,
+    pub size: i64,
+    pub published_at: String,
+}
+
+impl From<PluginVersion> for PluginSummary {
+    fn from(version: PluginVersion) -> Self {
+        Self {
+            capabilities: version
+                .capabilities
+                .split(',')
+                .filter(|kind| !kind.is_empty())
+                .map(str::to_string)
+                .collect(),
+            namespace: version.namespace,
+            name: version.name,
+            version: version.version,
+            description: version.description,
+            author: version.author,
+            license: version.license,
+            url: version.url,
+            component_sha256: version.component_sha256,
+            source_sha256: version.source_sha256,
+            size: version.size,
+            published_at: version.published_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PluginDetail {
+    pub latest: PluginSummary,
+    /// Every published version, newest first
+    pub versions: Vec<String>,
+}
+
+/// Register a publisher namespace and the key its plugins are signed with
+#[endpoint(security(("bearer" = [])), status_codes(201, 400, 401, 409, 500))]
+async fn create_publisher(
+    body: JsonBody<CreatePublisher>,
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<Json<PublisherSummary>, StatusError> {
+    let state = state(depot)?;
+    let authorized = match (
+        &state.config.admin_token,
+        req.header::<String>("authorization"),
+    ) {
+        (Some(token), Some(header)) => header.strip_prefix("Bearer ") == Some(token.as_str()),
+        _ => false,
+    };
+    if !authorized {
+        return Err(StatusError::unauthorized());
+    }
+
+    let body = body.into_inner();
+    if !is_valid_namespace(&body.namespace) {
+        return Err(StatusError::bad_request()
+            .brief("Namespaces start with @ and contain only lowercase letters, digits and -"));
+    }
+    let key = hex::decode(&body.public_key)
+        .map_err(|_| StatusError::bad_request().brief("Public key must be hex-encoded"))?;
+    wasmsign2::PublicKey::from_bytes(&key)
+        .map_err(|e| StatusError::bad_request().brief(format!("Invalid public key: {}", e)))?;
+
+    Publisher::create(&state.db, &body.namespace, &hex::encode(&key)).await?;
+    let publisher = Publisher::find(&state.db, &body.namespace)
+        .await?
+        .ok_or_else(StatusError::internal_server_error)?;
+    info!("Registered publisher {}", publisher.namespace);
+    res.status_code(StatusCode::CREATED);
+    Ok(Json(publisher.into()))
+}
+
+/// Get a publisher and its public key
+#[endpoint(status_codes(200, 404, 500))]
+async fn get_publisher(
+    namespace: PathParam<String>,
+    depot: &mut Depot,
+) -> Result<Json<PublisherSummary>, StatusError> {
+    let state = state(depot)?;
+    let namespace = namespace.into_inner();
+    let publisher = Publisher::find(&state.db, &namespace)
+        .await?
+        .ok_or(RegistryError::UnknownNamespace(namespace))?;
+    Ok(Json(publisher.into()))
+}
+
+/// Submit a plugin version as a multipart form with a `namespace` field, the
+/// signed `component`, and a gzipped tarball of its `source`. The version is
+/// listed once it passes signature, smoke test and rebuild checks.
+#[endpoint(status_codes(201, 400, 404, 409, 422, 500))]
+async fn submit_plugin(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<Json<PluginSummary>, StatusError> {
+    let state = state(depot)?.clone();
+    let max_size = state.config.max_upload_bytes as usize;
+    let form = req
+        .form_data_max_size(max_size.saturating_mul(2))
+        .await
+        .map_err(|e| StatusError::bad_request().brief(format!("Invalid form: {}", e)))?;
+
+    let namespace = form
+        .fields
+        .get("namespace")
+        .cloned()
+        .ok_or_else(|| StatusError::bad_request().brief("Missing namespace"))?;
+    let read_file = async |field: &str| -> Result<Vec<u8>, StatusError> {
+        let file = form
+            .files
+            .get(field)
+            .ok_or_else(|| StatusError::bad_request().brief(format!("Missing {}", field)))?;
+        let bytes = tokio::fs::read(file.path())
+            .await
+            .map_err(RegistryError::from)?;
+        if bytes.len() > max_size {
+            return Err(StatusError::payload_too_large());
+        }
+        Ok(bytes)
+    };
+    let submission = Submission {
+        component: read_file("component").await?,
+        source: read_file("source").await?,
+    };
+
+    let publisher = Publisher::find(&state.db, &namespace)
+        .await?
+        .ok_or(RegistryError::UnknownNamespace(namespace))?;
+    let verified = state.verifier.verify(&publisher, &submission).await?;
+    let manifest = verified.manifest;
+    if PluginVersion::find(
+        &state.db,
+        &manifest.namespace,
+        &manifest.name,
+        &manifest.version,
+    )
+    .await?
+    .is_some()
+    {
+        return Err(RegistryError::VersionExists(format!(
+            "{}/{}@{}",
+            manifest.namespace, manifest.name, manifest.version
+        ))
+        .into());
+    }
+
+    let component_sha256 = state.blobs.put(&submission.component).await?;
+    let source_sha256 = state.blobs.put(&submission.source).await?;
+    let version = PluginVersion {
+        namespace: manifest.namespace,
+        name: manifest.name,
+        version: manifest.version,
+        description: manifest.description,
+        author: manifest.author,
+        license: manifest.license,
+        url: manifest.url,
+        capabilities: manifest.capabilities.join(","),
+        component_sha256,
+        source_sha256,
+        size: submission.component.len() as i64,
+        published_at: String::new(),
+    };
+    version.insert(&state.db).await?;
+    info!(
+        "Published {} (unsigned sha256 {})",
+        version.id(),
+        verified.unsigned_sha256
+    );
+
+    let version = PluginVersion::find(
+        &state.db,
+        &version.namespace,
+        &version.name,
+        &version.version,
+    )
+    .await?
+    .ok_or_else(StatusError::internal_server_error)?;
+    res.status_code(StatusCode::CREATED);
+    Ok(Json(version.into()))
+}
+
+/// List the latest version of each plugin
+#[endpoint(status_codes(200, 500))]
+async fn list_plugins(
+    q: QueryParam<String, false>,
+    limit: QueryParam<i64, false>,
+    offset: QueryParam<i64, false>,
+    depot: &mut Depot,
+) -> Result<Json<Vec<PluginSummary>>, StatusError> {
+    let state = state(depot)?;
+    let limit = limit.into_inner().unwrap_or(50).clamp(1, 200);
+    let offset = offset.into_inner().unwrap_or(0).max(0);
+    let query = q.into_inner();
+    let plugins = PluginVersion::list_latest(&state.db, query.as_deref(), limit, offset).await?;
+    Ok(Json(plugins.into_iter().map(Into::into).collect()))
+}
+
+/// Get a plugin's latest version and the versions published before it
+#[endpoint(status_codes(200, 404, 500))]
+async fn get_plugin(
+    namespace: PathParam<String>,
+    name: PathParam<String>,
+    depot: &mut Depot,
+) -> Result<Json<PluginDetail>, StatusError> {
+    let state = state(depot)?;
+    let versions = PluginVersion::versions(&state.db, &namespace, &name).await?;
+    let versions_list = versions.iter().map(|v| v.version.clone()).collect();
+    let latest = versions.into_iter().next().ok_or(RegistryError::NotFound)?;
+    Ok(Json(PluginDetail {
+        latest: latest.into(),
+        versions: versions_list,
+    }))
+}
+
+async fn find_version(
+    state: &AppState,
+    namespace: &str,
+    name: &str,
+    version: &str,
+) -> Result<PluginVersion, StatusError> {
+    let found = if version == "latest" {
+        PluginVersion::latest(&state.db, namespace, name).await?
+    } else {
+        PluginVersion::find(&state.db, namespace, name, version).await?
+    };
+    Ok(found.ok_or(RegistryError::NotFound)?)
+}
+
+/// Get a plugin version, or `latest` for the highest published version
+#[endpoint(status_codes(200, 404, 500))]
+async fn get_version(
+    namespace: PathParam<String>,
+    name: PathParam<String>,
+    version: PathParam<String>,
+    depot: &mut Depot,
+) -> Result<Json<PluginSummary>, StatusError> {
+    let state = state(depot)?;
+    let version = find_version(state, &namespace, &name, &version).await?;
+    Ok(Json(version.into()))
+}
+
+/// Download a plugin version's signed component. The publisher's key is
+/// returned in `X-Publisher-Key` so clients can pin it.
+#[endpoint(status_codes(200, 404, 500))]
+async fn download_version(
+    namespace: PathParam<String>,
+    name: PathParam<String>,
+    version: PathParam<String>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<(), StatusError> {
+    let state = state(depot)?;
+    let version = find_version(state, &namespace, &name, &version).await?;
+    let publisher = Publisher::find(&state.db, &version.namespace)
+        .await?
+        .ok_or(RegistryError::UnknownNamespace(version.namespace.clone()))?;
+    let component = state.blobs.get(&version.component_sha256).await?;
+
+    let headers = res.headers_mut();
+    headers.insert(CONTENT_TYPE, "application/wasm".parse().unwrap());
+    if let Ok(disposition) = format!(
+        "attachment; filename=\"{}-{}.wasm\"",
+        version.name, version.version
+    )
+    .parse()
+    {
+        headers.insert(CONTENT_DISPOSITION, disposition);
+    }
+    if let Ok(sha) = version.component_sha256.parse() {
+        headers.insert("x-component-sha256", sha);
+    }
+    if let Ok(key) = publisher.public_key.parse() {
+        headers.insert("x-publisher-key", key);
+    }
+    res.write_body(component)
+        .map_err(|_| StatusError::internal_server_error())?;
+    Ok(())
+}
+
+fn is_valid_namespace(namespace: &str) -> bool {
+    namespace.strip_prefix('@').is_some_and(|rest| {
+        !rest.is_empty()
+            && rest.len() <= 64
+            && rest
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespaces_are_validated() {
+        assert!(is_valid_namespace("@ezco"));
+        assert!(is_valid_namespace("@my-org2"));
+        assert!(!is_valid_namespace("ezco"));
+        assert!(!is_valid_namespace("@"));
+        assert!(!is_valid_namespace("@Ezco"));
+        assert!(!is_valid_namespace("@ez/co"));
+    }
+}
@@ -0,0 +1,442 @@
+//! Reproducible rebuilds of submitted plugin sources.
+//!
+//! Building runs the submission's build scripts and proc macros, so sources
+//! are built in a throwaway container rather than on the registry's host.
+//! Dependencies are fetched first with `cargo fetch --locked`, which runs
+//! none of the crate's code, then the component is built with
+//! `cargo build --release --locked --offline --target wasm32-wasip2` without
+//! network access. Both steps share a cargo home which starts out empty, and
+//! only see the unpacked source, mounted read-only at [REMAPPED_SOURCE_DIR]. Cargo
+//! configuration in the source is refused, as it could point cargo at
+//! other registries or wrap `rustc`. Containers get [MEMORY_LIMIT] of memory
+//! and [PIDS_LIMIT] processes, so a build can't exhaust the registry's host.
+//!
+//! The source and cargo home are mounted at the paths publishers remap
+//! theirs to, so the output doesn't depend on where the build ran.
+//! Publishers must build with the same remapping for their component to
+//! match; see the crate README.
+
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use flate2::read::GzDecoder;
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+use crate::error::{RegistryError, RegistryResult};
+
+/// Where sources are remapped to in the built component
+pub const REMAPPED_SOURCE_DIR: &str = "/build";
+/// Where the cargo home is remapped to in the built component
+pub const REMAPPED_CARGO_HOME: &str = "/cargo";
+
+const TARGET: &str = "wasm32-wasip2";
+/// Where the target directory is mounted in the container
+const CONTAINER_TARGET_DIR: &str = "/target";
+/// Memory a build container may use, swap included
+const MEMORY_LIMIT: &str = "4g";
+/// Processes a build container may run at once
+const PIDS_LIMIT: &str = "1024";
+
+/// The container submitted sources are built in
+#[derive(Debug, Clone)]
+pub struct BuildSandbox {
+    /// Container runtime, ex: `docker` or `podman`
+    pub runtime: String,
+    /// Image with cargo and the `wasm32-wasip2` target installed
+    pub image: String,
+    /// Time allowed for fetching dependencies and building
+    pub timeout: Duration,
+}
+
+/// The host directories a build container mounts
+struct Mounts {
+    source: PathBuf,
+    cargo_home: PathBuf,
+    target: PathBuf,
+}
+
+impl BuildSandbox {
+    /// Unpack a gzipped tarball of a plugin crate and build it, returning
+    /// the unsigned component
+    pub async fn build_source(&self, source: &[u8]) -> RegistryResult<Vec<u8>> {
+        let dir = tempfile::tempdir()?;
+        let src = dir.path().join("src");
+        unpack(source, &src)?;
+        let mounts = Mounts {
+            source: find_crate_root(&src)?,
+            cargo_home: dir.path().join("cargo"),
+            target: dir.path().join("target"),
+        };
+        std::fs::create_dir_all(&mounts.cargo_home)?;
+        std::fs::create_dir_all(&mounts.target)?;
+        let name = container_name(dir.path());
+
+        info!("Building submitted source in container {}", name);
+        let build = async {
+            self.run(
+                &format!("{name}-fetch"),
+                &mounts,
+                true,
+                &["cargo", "fetch", "--locked", "--target", TARGET],
+            )
+            .await?;
+            self.run(
+                &format!("{name}-build"),
+                &mounts,
+                false,
+                &[
+                    "cargo",
+                    "build",
+                    "--release",
+                    "--locked",
+                    "--offline",
+                    "--target",
+                    TARGET,
+                ],
+            )
+            .await
+        };
+        match tokio::time::timeout(self.timeout, build).await {
+            Ok(built) => built?,
+            Err(_) => {
+                // Dropping the runtime's client doesn't stop its container
+                for step in ["fetch", "build"] {
+                    self.kill(&format!("{name}-{step}")).await;
+                }
+                return Err(RegistryError::Build(format!(
+                    "Build did not finish within {:?}",
+                    self.timeout
+                )));
+            }
+        }
+
+        let component = find_component(&mounts.target, &Path::new(TARGET).join("release"))?;
+        Ok(tokio::fs::read(component).await?)
+    }
+
+    /// Run `command` in a container named `name`, with network access only
+    /// if `network`
+    async fn run(
+        &self,
+        name: &str,
+        mounts: &Mounts,
+        network: bool,
+        command: &[&str],
+    ) -> RegistryResult<()> {
+        let output = Command::new(&self.runtime)
+            .args(self.run_args(name, mounts, network, command))
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output()
+            .await
+            .map_err(|e| RegistryError::Build(format!("Failed to run {}: {}", self.runtime, e)))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            debug!("{} failed: {}", command.join(" "), stderr);
+            return Err(RegistryError::Build(format!(
+                "{} exited with {}: {}",
+                command[..2].join(" "),
+                output.status,
+                tail(&stderr, 20)
+            )));
+        }
+        Ok(())
+    }
+
+    /// Arguments to the container runtime for running `command`. The
+    /// container starts from the image's environment, with only the cargo
+    /// paths set, and no capabilities.
+    fn run_args(
+        &self,
+        name: &str,
+        mounts: &Mounts,
+        network: bool,
+        command: &[&str],
+    ) -> Vec<OsString> {
+        let volume = |host: &Path, container: &str, options: &str| {
+            let mut volume = host.as_os_str().to_owned();
+            volume.push(format!(":{container}{options}"));
+            volume
+        };
+        let mut args: Vec<OsString> = ["run", "--rm", "--name", name]
+            .into_iter()
+            .map(OsString::from)
+            .collect();
+        if !network {
+            args.extend(["--network", "none"].map(OsString::from));
+        }
+        args.extend(
+            [
+                "--cap-drop",
+                "ALL",
+                "--security-opt",
+                "no-new-privileges",
+                "--memory",
+                MEMORY_LIMIT,
+                "--memory-swap",
+                MEMORY_LIMIT,
+                "--pids-limit",
+                PIDS_LIMIT,
+            ]
+            .map(OsString::from),
+        );
+        if let Some(user) = owner(&mounts.target) {
+            args.extend([OsString::from("--user"), OsString::from(user)]);
+        }
+        args.extend([
+            "--volume".into(),
+            volume(&mounts.source, REMAPPED_SOURCE_DIR, ":ro"),
+            "--volume".into(),
+            volume(&mounts.cargo_home, REMAPPED_CARGO_HOME, ""),
+            "--volume".into(),
+            volume(&mounts.target, CONTAINER_TARGET_DIR, ""),
+            "--workdir".into(),
+            REMAPPED_SOURCE_DIR.into(),
+            "--env".into(),
+            format!("CARGO_HOME={REMAPPED_CARGO_HOME}").into(),
+            "--env".into(),
+            format!("CARGO_TARGET_DIR={CONTAINER_TARGET_DIR}").into(),
+            self.image.as_str().into(),
+        ]);
+        args.extend(command.iter().map(OsString::from));
+        args
+    }
+
+    async fn kill(&self, name: &str) {
+        let killed = Command::new(&self.runtime)
+            .args(["kill", name])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await;
+        if let Err(e) = killed {
+            warn!("Failed to kill build container {}: {}", name, e);
+        }
+    }
+}
+
+/// A container name unique to the build in `dir`
+fn container_name(dir: &Path) -> String {
+    let suffix = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().trim_start_matches('.').to_string())
+        .unwrap_or_default();
+    format!("witm-build-{suffix}")
+}
+
+/// The `uid:gid` owning `path`, for the container to write files the
+/// registry can clean up
+#[cfg(unix)]
+fn owner(path: &Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::metadata(path).ok()?;
+    Some(format!("{}:{}", metadata.uid(), metadata.gid()))
+}
+
+#[cfg(not(unix))]
+fn owner(_path: &Path) -> Option<String> {
+    None
+}
+
+fn unpack(source: &[u8], dest: &Path) -> RegistryResult<()> {
+    std::fs::create_dir_all(dest)?;
+    let mut archive = tar::Archive::new(GzDecoder::new(source));
+    for entry in archive
+        .entries()
+        .map_err(|e| RegistryError::InvalidSubmission(format!("Invalid source archive: {}", e)))?
+    {
+        let mut entry = entry.map_err(|e| {
+            RegistryError::InvalidSubmission(format!("Invalid source archive: {}", e))
+        })?;
+        let path = entry.path()?;
+        if path
+            .components()
+            .any(|component| component == Component::Normal(".cargo".as_ref()))
+        {
+            return Err(RegistryError::InvalidSubmission(
+                "Source archive can't contain cargo configuration (.cargo)".to_string(),
+            ));
+        }
+        // `unpack_in` refuses entries which would land outside `dest`
+        if !entry.unpack_in(dest)? {
+            return Err(RegistryError::InvalidSubmission(
+                "Source archive contains paths outside its root".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The directory containing `Cargo.toml`, allowing the archive to wrap the
+/// crate in a single top-level directory
+fn find_crate_root(src: &Path) -> RegistryResult<PathBuf> {
+    if src.join("Cargo.toml").is_file() {
+        return Ok(src.to_path_buf());
+    }
+    let entries = std::fs::read_dir(src)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    match entries.as_slice() {
+        [dir] if dir.join("Cargo.toml").is_file() => Ok(dir.clone()),
+        _ => Err(RegistryError::InvalidSubmission(
+            "Source archive has no Cargo.toml at its root".to_string(),
+        )),
+    }
+}
+
+/// The component built into `release_dir` under `target`. The container
+/// wrote the target directory, so it could have left symlinks there to any
+/// file on the host: the component must be a regular file, and the
+/// directory it's in must resolve to somewhere under `target`.
+fn find_component(target: &Path, release_dir: &Path) -> RegistryResult<PathBuf> {
+    let target = target.canonicalize()?;
+    let release_dir = target.join(release_dir).canonicalize()?;
+    if !release_dir.starts_with(&target) {
+        return Err(RegistryError::Build(
+            "Build output is outside the target directory".to_string(),
+        ));
+    }
+    let wasm = std::fs::read_dir(release_dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "wasm"))
+        .collect::<Vec<_>>();
+    match wasm.as_slice() {
+        [component] if !std::fs::symlink_metadata(component)?.file_type().is_file() => Err(
+            RegistryError::Build("Build produced a component which isn't a file".to_string()),
+        ),
+        [component] => Ok(component.clone()),
+        [] => Err(RegistryError::Build(
+            "Build produced no component".to_string(),
+        )),
+        _ => Err(RegistryError::Build(
+            "Build produced more than one component".to_string(),
+        )),
+    }
+}
+
+fn tail(output: &str, lines: usize) -> String {
+    let all = output.lines().collect::<Vec<_>>();
+    all[all.len().saturating_sub(lines)..].join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn crate_root_may_be_wrapped() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        unpack(
+            &archive(&[("plugin/Cargo.toml", ""), ("plugin/src/lib.rs", "")]),
+            &src,
+        )
+        .unwrap();
+        assert_eq!(find_crate_root(&src).unwrap(), src.join("plugin"));
+
+        let src = dir.path().join("empty");
+        unpack(&archive(&[("README.md", "")]), &src).unwrap();
+        assert!(find_crate_root(&src).is_err());
+    }
+
+    #[test]
+    fn cargo_configuration_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            ("plugin/Cargo.toml", ""),
+            (
+                "plugin/.cargo/config.toml",
+                "[build]\nrustc-wrapper = \"./run.sh\"\n",
+            ),
+        ];
+        assert!(matches!(
+            unpack(&archive(&files), &dir.path().join("src")),
+            Err(RegistryError::InvalidSubmission(_))
+        ));
+    }
+
+    #[test]
+    fn only_fetching_has_network_access() {
+        let sandbox = BuildSandbox {
+            runtime: "docker".to_string(),
+            image: "witm-builder".to_string(),
+            timeout: Duration::from_secs(60),
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let mounts = Mounts {
+            source: dir.path().join("src"),
+            cargo_home: dir.path().join("cargo"),
+            target: dir.path().to_path_buf(),
+        };
+        let args = |network| {
+            sandbox
+                .run_args("witm-build-test", &mounts, network, &["cargo", "build"])
+                .into_iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        let fetch = args(true);
+        assert!(!fetch.contains(&"none".to_string()));
+        let build = args(false);
+        assert!(build.windows(2).any(|w| w == ["--network", "none"]));
+        assert!(build.windows(2).any(|w| w == ["--memory", MEMORY_LIMIT]));
+        assert!(build.windows(2).any(|w| w == ["--pids-limit", PIDS_LIMIT]));
+        let source = format!("{}:/build:ro", mounts.source.display());
+        assert!(build.contains(&source));
+        assert_eq!(build[build.len() - 3..], ["witm-builder", "cargo", "build"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn components_must_be_files_in_the_target_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        let release = Path::new(TARGET).join("release");
+        std::fs::create_dir_all(target.join(&release)).unwrap();
+        let secret = dir.path().join("secret");
+        std::fs::write(&secret, "registry key").unwrap();
+
+        let component = target.join(&release).join("plugin.wasm");
+        std::os::unix::fs::symlink(&secret, &component).unwrap();
+        assert!(find_component(&target, &release).is_err());
+
+        std::fs::remove_file(&component).unwrap();
+        std::fs::write(&component, "\0asm").unwrap();
+        assert_eq!(
+            find_component(&target, &release).unwrap(),
+            component.canonicalize().unwrap()
+        );
+
+        // The release directory itself may not lead out of the target
+        std::fs::remove_dir_all(target.join(TARGET)).unwrap();
+        std::fs::create_dir_all(dir.path().join("elsewhere")).unwrap();
+        std::fs::write(dir.path().join("elsewhere/plugin.wasm"), "\0asm").unwrap();
+        std::os::unix::fs::symlink(dir.path(), target.join(TARGET)).unwrap();
+        assert!(find_component(&target, Path::new(TARGET).join("elsewhere").as_path()).is_err());
+    }
+}
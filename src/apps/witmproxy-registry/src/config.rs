@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::Args;
+
+use crate::build::BuildSandbox;
+
+#[derive(Debug, Clone, Args)]
+pub struct RegistryConfig {
+    /// The address the registry API binds to
+    #[arg(long, env = "REGISTRY_BIND_ADDR", default_value = "127.0.0.1:8787")]
+    pub bind_addr: String,
+
+    /// Directory holding the registry database and stored components
+    #[arg(long, env = "REGISTRY_DATA_DIR", default_value = "./registry-data")]
+    pub data_dir: PathBuf,
+
+    /// Bearer token required to register publishers. Registration is
+    /// disabled when unset.
+    #[arg(long, env = "REGISTRY_ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+
+    /// Largest accepted component or source archive, in bytes
+    #[arg(long, env = "REGISTRY_MAX_UPLOAD_BYTES", default_value = "52428800")]
+    pub max_upload_bytes: u64,
+
+    /// Seconds allowed for rebuilding a submission's source
+    #[arg(long, env = "REGISTRY_BUILD_TIMEOUT_SECS", default_value = "600")]
+    pub build_timeout_secs: u64,
+
+    /// Container runtime submitted sources are built with, ex: docker or
+    /// podman
+    #[arg(long, env = "REGISTRY_BUILD_RUNTIME", default_value = "docker")]
+    pub build_runtime: String,
+
+    /// Image submitted sources are built in, with cargo and the
+    /// wasm32-wasip2 target installed. Required unless builds are skipped.
+    #[arg(long, env = "REGISTRY_BUILD_IMAGE")]
+    pub build_image: Option<String>,
+
+    /// Seconds allowed for a plugin to handle the smoke test request
+    #[arg(long, env = "REGISTRY_SMOKE_TIMEOUT_SECS", default_value = "10")]
+    pub smoke_timeout_secs: u64,
+
+    /// Accept components without rebuilding their source. Only meant for
+    /// local development, since listed components are then unverified.
+    #[arg(long, env = "REGISTRY_SKIP_BUILD")]
+    pub skip_build: bool,
+}
+
+impl RegistryConfig {
+    pub fn build_timeout(&self) -> Duration {
+        Duration::from_secs(self.build_timeout_secs)
+    }
+
+    /// The container sources are built in, unless builds are skipped
+    pub fn build_sandbox(&self) -> anyhow::Result<Option<BuildSandbox>> {
+        if self.skip_build {
+            return Ok(None);
+        }
+        let Some(image) = &self.build_image else {
+            anyhow::bail!(
+                "REGISTRY_BUILD_IMAGE must name an image to build submissions in, unless builds are skipped"
+            );
+        };
+        Ok(Some(BuildSandbox {
+            runtime: self.build_runtime.clone(),
+            image: image.clone(),
+            timeout: self.build_timeout(),
+        }))
+    }
+
+    pub fn smoke_timeout(&self) -> Duration {
+        Duration::from_secs(self.smoke_timeout_secs)
+    }
+}
@@ -0,0 +1,250 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{FromRow, SqlitePool};
+
+use crate::error::{RegistryError, RegistryResult};
+
+#[derive(Clone)]
+pub struct RegistryDb {
+    pub pool: SqlitePool,
+}
+
+impl RegistryDb {
+    pub async fn open(path: &Path) -> RegistryResult<Self> {
+        let options = SqliteConnectOptions::from_str(&format!("sqlite://{}", path.display()))?
+            .create_if_missing(true);
+        let pool = SqlitePool::connect_with(options).await?;
+        Ok(Self { pool })
+    }
+
+    /// Run embedded registry database migrations
+    pub async fn migrate(&self) -> RegistryResult<()> {
+        sqlx::migrate!("./migrations")
+            .run(&self.pool)
+            .await
+            .map_err(|e| anyhow::anyhow!("Database migration failed: {}", e))?;
+        Ok(())
+    }
+}
+
+/// The owner of a namespace, whose key every plugin under it must be signed with
+#[derive(Debug, Clone, FromRow)]
+pub struct Publisher {
+    pub namespace: String,
+    /// Hex-encoded wasmsign2 public key
+    pub public_key: String,
+    pub created_at: String,
+}
+
+impl Publisher {
+    pub async fn create(db: &RegistryDb, namespace: &str, public_key: &str) -> RegistryResult<()> {
+        if Self::find(db, namespace).await?.is_some() {
+            return Err(RegistryError::NamespaceTaken(namespace.to_string()));
+        }
+        sqlx::query("INSERT INTO publishers (namespace, public_key) VALUES (?, ?)")
+            .bind(namespace)
+            .bind(public_key)
+            .execute(&db.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn find(db: &RegistryDb, namespace: &str) -> RegistryResult<Option<Self>> {
+        Ok(
+            sqlx::query_as::<_, Self>("SELECT * FROM publishers WHERE namespace = ?")
+                .bind(namespace)
+                .fetch_optional(&db.pool)
+                .await?,
+        )
+    }
+}
+
+/// A verified, published plugin version
+#[derive(Debug, Clone, FromRow)]
+pub struct PluginVersion {
+    pub namespace: String,
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub author: String,
+    pub license: String,
+    pub url: String,
+    /// Comma-separated capability kinds requested by the manifest
+    pub capabilities: String,
+    pub component_sha256: String,
+    pub source_sha256: String,
+    pub size: i64,
+    pub published_at: String,
+}
+
+impl PluginVersion {
+    pub fn id(&self) -> String {
+        format!("{}/{}@{}", self.namespace, self.name, self.version)
+    }
+
+    pub async fn insert(&self, db: &RegistryDb) -> RegistryResult<()> {
+        if Self::find(db, &self.namespace, &self.name, &self.version)
+            .await?
+            .is_some()
+        {
+            return Err(RegistryError::VersionExists(self.id()));
+        }
+        sqlx::query(
+            "INSERT INTO plugin_versions (namespace, name, version, description, author, license, url, capabilities, component_sha256, source_sha256, size)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&self.namespace)
+        .bind(&self.name)
+        .bind(&self.version)
+        .bind(&self.description)
+        .bind(&self.author)
+        .bind(&self.license)
+        .bind(&self.url)
+        .bind(&self.capabilities)
+        .bind(&self.component_sha256)
+        .bind(&self.source_sha256)
+        .bind(self.size)
+        .execute(&db.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn find(
+        db: &RegistryDb,
+        namespace: &str,
+        name: &str,
+        version: &str,
+    ) -> RegistryResult<Option<Self>> {
+        Ok(sqlx::query_as::<_, Self>(
+            "SELECT * FROM plugin_versions WHERE namespace = ? AND name = ? AND version = ?",
+        )
+        .bind(namespace)
+        .bind(name)
+        .bind(version)
+        .fetch_optional(&db.pool)
+        .await?)
+    }
+
+    /// Every published version of a plugin, newest first
+    pub async fn versions(
+        db: &RegistryDb,
+        namespace: &str,
+        name: &str,
+    ) -> RegistryResult<Vec<Self>> {
+        let mut versions = sqlx::query_as::<_, Self>(
+            "SELECT * FROM plugin_versions WHERE namespace = ? AND name = ?",
+        )
+        .bind(namespace)
+        .bind(name)
+        .fetch_all(&db.pool)
+        .await?;
+        versions.sort_by(|a, b| compare_versions(&b.version, &a.version));
+        Ok(versions)
+    }
+
+    /// The highest published version of a plugin
+    pub async fn latest(
+        db: &RegistryDb,
+        namespace: &str,
+        name: &str,
+    ) -> RegistryResult<Option<Self>> {
+        Ok(Self::versions(db, namespace, name)
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    /// The most recently published version of each plugin, optionally
+    /// filtered by a substring of its name, namespace or description
+    pub async fn list_latest(
+        db: &RegistryDb,
+        query: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> RegistryResult<Vec<Self>> {
+        let pattern = format!("%{}%", query.unwrap_or_default());
+        Ok(sqlx::query_as::<_, Self>(
+            "SELECT * FROM plugin_versions
+             WHERE rowid IN (SELECT MAX(rowid) FROM plugin_versions GROUP BY namespace, name)
+               AND (name LIKE ?1 OR namespace LIKE ?1 OR description LIKE ?1)
+             ORDER BY namespace, name
+             LIMIT ?2 OFFSET ?3",
+        )
+        .bind(pattern)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&db.pool)
+        .await?)
+    }
+}
+
+/// Order versions by semver, falling back to string order for versions
+/// which don't parse
+fn compare_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    match (semver::Version::parse(a), semver::Version::parse(b)) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(name: &str, version: &str) -> PluginVersion {
+        PluginVersion {
+            namespace: "@test".to_string(),
+            name: name.to_string(),
+            version: version.to_string(),
+            description: format!("The {} plugin", name),
+            author: String::new(),
+            license: String::new(),
+            url: String::new(),
+            capabilities: "request".to_string(),
+            component_sha256: "00".to_string(),
+            source_sha256: "00".to_string(),
+            size: 0,
+            published_at: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn versions_are_ordered_and_unique() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = RegistryDb::open(&dir.path().join("registry.db"))
+            .await
+            .unwrap();
+        db.migrate().await.unwrap();
+
+        Publisher::create(&db, "@test", "ab").await.unwrap();
+        assert!(matches!(
+            Publisher::create(&db, "@test", "cd").await,
+            Err(RegistryError::NamespaceTaken(_))
+        ));
+
+        for v in ["0.2.0", "0.10.0", "0.9.1"] {
+            version("noop", v).insert(&db).await.unwrap();
+        }
+        version("other", "1.0.0").insert(&db).await.unwrap();
+        assert!(matches!(
+            version("noop", "0.2.0").insert(&db).await,
+            Err(RegistryError::VersionExists(_))
+        ));
+
+        let latest = PluginVersion::latest(&db, "@test", "noop")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(latest.version, "0.10.0");
+
+        let listed = PluginVersion::list_latest(&db, None, 10, 0).await.unwrap();
+        assert_eq!(listed.len(), 2);
+        let listed = PluginVersion::list_latest(&db, Some("oth"), 10, 0)
+            .await
+            .unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].name, "other");
+    }
+}
@@ -0,0 +1,64 @@
+use salvo::http::StatusError;
+use thiserror::Error;
+use tracing::warn;
+
+#[derive(Debug, Error)]
+pub enum RegistryError {
+    #[error("Unknown namespace: {0}")]
+    UnknownNamespace(String),
+
+    #[error("Namespace {0} is already registered")]
+    NamespaceTaken(String),
+
+    #[error("Invalid submission: {0}")]
+    InvalidSubmission(String),
+
+    #[error("Signature verification failed: {0}")]
+    Signature(String),
+
+    #[error("Smoke test failed: {0}")]
+    Smoke(String),
+
+    #[error("Build verification failed: {0}")]
+    Build(String),
+
+    #[error("{0} is already published")]
+    VersionExists(String),
+
+    #[error("Not found")]
+    NotFound,
+
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("{0}")]
+    Generic(#[from] anyhow::Error),
+}
+
+pub type RegistryResult<T> = Result<T, RegistryError>;
+
+impl From<RegistryError> for StatusError {
+    fn from(err: RegistryError) -> Self {
+        match err {
+            RegistryError::UnknownNamespace(_) | RegistryError::NotFound => {
+                StatusError::not_found().brief(err.to_string())
+            }
+            RegistryError::NamespaceTaken(_) | RegistryError::VersionExists(_) => {
+                StatusError::conflict().brief(err.to_string())
+            }
+            RegistryError::InvalidSubmission(_) => {
+                StatusError::bad_request().brief(err.to_string())
+            }
+            RegistryError::Signature(_) | RegistryError::Smoke(_) | RegistryError::Build(_) => {
+                StatusError::unprocessable_entity().brief(err.to_string())
+            }
+            RegistryError::Database(_) | RegistryError::Io(_) | RegistryError::Generic(_) => {
+                warn!("Internal registry error: {}", err);
+                StatusError::internal_server_error().brief("Internal error")
+            }
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! A registry for witmproxy plugins.
+//!
+//! Publishers claim a namespace (e.g. `@ezco`) with their wasmsign2 public
+//! key, then submit plugins as a signed WASM component together with the
+//! source it was built from. Before a version is listed, the registry:
+//!
+//! 1. checks the component's signature against the namespace's key,
+//! 2. loads it into a sandboxed witmproxy runtime and passes it a request,
+//! 3. rebuilds the source in a container and compares the result with the
+//!    component.
+//!
+//! Verified components are listed and served to `witm plugin add @namespace/name`.
+
+pub mod api;
+pub mod build;
+pub mod config;
+pub mod db;
+pub mod error;
+pub mod smoke;
+pub mod storage;
+pub mod verify;
+
+pub use config::RegistryConfig;
+pub use error::{RegistryError, RegistryResult};
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use salvo::prelude::*;
+use tracing::info;
+
+use crate::db::RegistryDb;
+use crate::storage::BlobStore;
+use crate::verify::Verifier;
+
+/// Open the registry's storage and serve its API until the process exits
+pub async fn serve(config: RegistryConfig) -> Result<()> {
+    std::fs::create_dir_all(&config.data_dir)?;
+    let db = RegistryDb::open(&config.data_dir.join("registry.db")).await?;
+    db.migrate().await?;
+    let blobs = BlobStore::open(config.data_dir.join("blobs"))?;
+    let verifier = Verifier::new(&config).await?;
+
+    let state = api::AppState {
+        db,
+        blobs,
+        verifier: Arc::new(verifier),
+        config: Arc::new(config.clone()),
+    };
+    let router = api::router(state);
+
+    let acceptor = TcpListener::new(config.bind_addr.as_str()).bind().await;
+    info!("Registry listening on {}", config.bind_addr);
+    Server::new(acceptor).serve(router).await;
+    Ok(())
+}
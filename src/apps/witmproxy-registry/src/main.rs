@@ -0,0 +1,28 @@
+use clap::Parser;
+use tracing_subscriber::EnvFilter;
+use witmproxy_registry::RegistryConfig;
+
+#[derive(Parser)]
+#[command(name = "witmproxy-registry")]
+#[command(about = "Verifies, stores and serves witmproxy plugins")]
+struct Cli {
+    #[command(flatten)]
+    config: RegistryConfig,
+
+    /// Enable debug logging
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let default_level = if cli.verbose { "debug" } else { "info" };
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_level)),
+        )
+        .init();
+
+    witmproxy_registry::serve(cli.config).await
+}
@@ -0,0 +1,125 @@
+//! Behavioral smoke tests: load a submitted component into a throwaway
+//! witmproxy runtime and pass it a request, to catch plugins which fail to
+//! instantiate, trap or hang before they reach users.
+//!
+//! Plugins are only granted the capabilities their manifest declares, and
+//! their outbound HTTP requests are refused, so a submission can't reach the
+//! network from the registry.
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::Request;
+use tokio::sync::Mutex;
+use wasmtime_wasi_http::p3::Request as WasiRequest;
+use witmproxy::events::Event;
+use witmproxy::plugins::WitmPlugin;
+use witmproxy::plugins::registry::PluginBlocked;
+use witmproxy::{Db, PluginRegistry, Runtime};
+
+use crate::error::{RegistryError, RegistryResult};
+
+/// The parts of a plugin's manifest the registry lists
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub namespace: String,
+    pub name: String,
+    pub version: String,
+    pub author: String,
+    pub description: String,
+    pub license: String,
+    pub url: String,
+    pub capabilities: Vec<String>,
+}
+
+impl From<&WitmPlugin> for Manifest {
+    fn from(plugin: &WitmPlugin) -> Self {
+        Self {
+            namespace: plugin.namespace.clone(),
+            name: plugin.name.clone(),
+            version: plugin.version.clone(),
+            author: plugin.author.clone(),
+            description: plugin.description.clone(),
+            license: plugin.license.clone(),
+            url: plugin.url.clone(),
+            capabilities: plugin
+                .capabilities
+                .iter()
+                .map(|c| c.inner.kind.to_string())
+                .collect(),
+        }
+    }
+}
+
+pub struct SmokeTester {
+    /// Plugins are registered and removed one at a time, so the registry
+    /// only ever holds the plugin under test
+    registry: Mutex<PluginRegistry>,
+    timeout: Duration,
+    // Holds the sandbox database until the tester is dropped
+    _dir: tempfile::TempDir,
+}
+
+impl SmokeTester {
+    pub async fn new(timeout: Duration) -> RegistryResult<Self> {
+        let dir = tempfile::tempdir()?;
+        let db = Db::from_path(dir.path().join("smoke.db"), "smoke").await?;
+        db.migrate().await?;
+        let registry = PluginRegistry::new(db, Runtime::try_default()?.deny_egress())?;
+        Ok(Self {
+            registry: Mutex::new(registry),
+            timeout,
+            _dir: dir,
+        })
+    }
+
+    /// Load `component`, checking its embedded key matches `public_key`, and
+    /// run it against a request. Returns the loaded plugin's manifest.
+    pub async fn run(&self, component: &[u8], public_key: &[u8]) -> RegistryResult<Manifest> {
+        let mut registry = self.registry.lock().await;
+        let mut plugin = registry
+            .plugin_from_component_with_key(component.to_vec(), Some(public_key))
+            .await
+            .map_err(|e| RegistryError::Smoke(format!("Failed to load component: {}", e)))?;
+        let manifest = Manifest::from(&plugin);
+
+        // A plugin's capabilities are those its manifest declares, within
+        // the scopes it declares; granting them exercises each one's code
+        // path, and the runtime refuses anything else
+        for cap in plugin.capabilities.iter_mut() {
+            cap.granted = true;
+        }
+        registry.register_plugin(plugin).await?;
+
+        let result =
+            tokio::time::timeout(self.timeout, registry.handle_event(smoke_request())).await;
+        registry
+            .remove_plugin(&manifest.name, Some(&manifest.namespace))
+            .await?;
+
+        match result {
+            Err(_) => Err(RegistryError::Smoke(format!(
+                "Plugin did not handle a request within {:?}",
+                self.timeout
+            ))),
+            // Blocking the request is a legitimate outcome for a plugin
+            Ok(Err(e)) if e.downcast_ref::<PluginBlocked>().is_some() => Ok(manifest),
+            Ok(Err(e)) => Err(RegistryError::Smoke(format!(
+                "Plugin failed to handle a request: {}",
+                e
+            ))),
+            Ok(Ok(_)) => Ok(manifest),
+        }
+    }
+}
+
+fn smoke_request() -> Box<dyn Event> {
+    let req = Request::get("https://example.com/")
+        .header("host", "example.com")
+        .header("user-agent", "witmproxy-registry")
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+    let (request, _io) = WasiRequest::from_http(req);
+    Box::new(request)
+}
@@ -0,0 +1,75 @@
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::error::{RegistryError, RegistryResult};
+
+/// Hex SHA-256 digest of `bytes`
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Content-addressed storage for components and source archives, one file
+/// per SHA-256 digest
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    dir: PathBuf,
+}
+
+impl BlobStore {
+    pub fn open(dir: PathBuf) -> RegistryResult<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// Store `bytes`, returning their digest
+    pub async fn put(&self, bytes: &[u8]) -> RegistryResult<String> {
+        let digest = sha256_hex(bytes);
+        let path = self.path(&digest);
+        if tokio::fs::try_exists(&path).await? {
+            return Ok(digest);
+        }
+        // Write then rename, so a partially written blob is never served
+        let partial = path.with_extension("partial");
+        tokio::fs::write(&partial, bytes).await?;
+        tokio::fs::rename(&partial, &path).await?;
+        Ok(digest)
+    }
+
+    pub async fn get(&self, digest: &str) -> RegistryResult<Vec<u8>> {
+        match tokio::fs::read(self.path(digest)).await {
+            Ok(bytes) => Ok(bytes),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(RegistryError::NotFound),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn path(&self, digest: &str) -> PathBuf {
+        // Digests come from our own database, but never let one escape the store
+        let name = Path::new(digest)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        self.dir.join(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn blobs_are_content_addressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = BlobStore::open(dir.path().join("blobs")).unwrap();
+
+        let digest = store.put(b"component").await.unwrap();
+        assert_eq!(digest, sha256_hex(b"component"));
+        assert_eq!(store.put(b"component").await.unwrap(), digest);
+        assert_eq!(store.get(&digest).await.unwrap(), b"component");
+        assert!(matches!(
+            store.get(&sha256_hex(b"missing")).await,
+            Err(RegistryError::NotFound)
+        ));
+    }
+}
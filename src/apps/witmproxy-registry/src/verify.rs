@@ -0,0 +1,127 @@
+use std::io::Cursor;
+
+use tracing::{info, warn};
+use wasmsign2::{Module, PublicKey};
+
+use crate::build::BuildSandbox;
+use crate::config::RegistryConfig;
+use crate::db::Publisher;
+use crate::error::{RegistryError, RegistryResult};
+use crate::smoke::{Manifest, SmokeTester};
+use crate::storage::sha256_hex;
+
+/// A plugin version as submitted by its publisher
+pub struct Submission {
+    /// The signed WASM component
+    pub component: Vec<u8>,
+    /// A gzipped tarball of the crate the component was built from
+    pub source: Vec<u8>,
+}
+
+/// A submission which passed every check
+pub struct Verified {
+    pub manifest: Manifest,
+    /// Digest of the component with its signature sections removed, which
+    /// is what the rebuilt source is compared against
+    pub unsigned_sha256: String,
+}
+
+pub struct Verifier {
+    smoke: SmokeTester,
+    /// None when builds are skipped
+    build: Option<BuildSandbox>,
+}
+
+impl Verifier {
+    pub async fn new(config: &RegistryConfig) -> RegistryResult<Self> {
+        let build = config.build_sandbox()?;
+        if build.is_none() {
+            warn!("Build verification is disabled; submitted components won't be rebuilt");
+        }
+        Ok(Self {
+            smoke: SmokeTester::new(config.smoke_timeout()).await?,
+            build,
+        })
+    }
+
+    /// Check `submission` was signed by `publisher`, behaves, and was built
+    /// from its source. Cheap checks run first, so most bad submissions are
+    /// rejected before a build is started.
+    pub async fn verify(
+        &self,
+        publisher: &Publisher,
+        submission: &Submission,
+    ) -> RegistryResult<Verified> {
+        let public_key = hex::decode(&publisher.public_key).map_err(|e| {
+            anyhow::anyhow!("Stored key for {} is invalid: {}", publisher.namespace, e)
+        })?;
+        check_signature(&submission.component, &public_key)?;
+
+        let manifest = self.smoke.run(&submission.component, &public_key).await?;
+        if manifest.namespace != publisher.namespace {
+            return Err(RegistryError::InvalidSubmission(format!(
+                "Plugin namespace {} doesn't match the publisher namespace {}",
+                manifest.namespace, publisher.namespace
+            )));
+        }
+        if semver::Version::parse(&manifest.version).is_err() {
+            return Err(RegistryError::InvalidSubmission(format!(
+                "Plugin version {} is not a semantic version",
+                manifest.version
+            )));
+        }
+
+        let unsigned_sha256 = sha256_hex(&strip_signatures(&submission.component)?);
+        let Some(build) = &self.build else {
+            return Ok(Verified {
+                manifest,
+                unsigned_sha256,
+            });
+        };
+        let rebuilt = build.build_source(&submission.source).await?;
+        let rebuilt_sha256 = sha256_hex(&strip_signatures(&rebuilt)?);
+        if rebuilt_sha256 != unsigned_sha256 {
+            return Err(RegistryError::Build(format!(
+                "Rebuilt component ({}) doesn't match the submitted component ({})",
+                rebuilt_sha256, unsigned_sha256
+            )));
+        }
+        info!(
+            "Verified {}/{}@{}",
+            manifest.namespace, manifest.name, manifest.version
+        );
+        Ok(Verified {
+            manifest,
+            unsigned_sha256,
+        })
+    }
+}
+
+/// Verify `component` carries a valid wasmsign2 signature from `public_key`
+pub fn check_signature(component: &[u8], public_key: &[u8]) -> RegistryResult<()> {
+    let public_key =
+        PublicKey::from_bytes(public_key).map_err(|e| RegistryError::Signature(e.to_string()))?;
+    public_key
+        .verify(&mut Cursor::new(component), None)
+        .map_err(|e| RegistryError::Signature(e.to_string()))
+}
+
+/// Serialize `component` without its signature sections, so a signed
+/// component can be compared with an unsigned build of the same source
+pub fn strip_signatures(component: &[u8]) -> RegistryResult<Vec<u8>> {
+    let module = Module::deserialize(&mut Cursor::new(component))
+        .map_err(|e| RegistryError::InvalidSubmission(format!("Invalid component: {}", e)))?;
+    let sections = module
+        .sections
+        .into_iter()
+        .filter(|section| !section.is_signature_header() && !section.is_signature_delimiter())
+        .collect();
+    let mut stripped = Vec::new();
+    Module {
+        header: module.header,
+        sections,
+    }
+    .serialize(&mut stripped)
+    .map_err(|e| anyhow::anyhow!("Failed to serialize component: {}", e))?;
+    Ok(stripped)
+}
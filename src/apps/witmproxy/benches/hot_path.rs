@@ -0,0 +1,287 @@
+//! Latency and throughput of the proxy hot path, from a client's request to
+//! the proxied response, for each way a flow can be handled:
+//!
+//! - `passthrough`: CONNECT tunnels forwarded without interception
+//! - `mitm_no_plugin`: intercepted, with no plugins installed
+//! - `mitm_noop_plugin`: intercepted, with a plugin handling every request
+//!   and response without changing them
+//! - `mitm_body_rewrite`: intercepted, with a plugin rewriting HTML bodies
+//!
+//! Run with `cargo bench -p witmproxy --features test-helpers --bench hot_path`.
+//!
+//! Before the criterion benchmarks run, the latency each scenario adds over
+//! requesting the server directly is sampled and compared with [BUDGETS].
+//! `WITM_BENCH_ENFORCE_BUDGET=1` fails the run when a budget is exceeded, for
+//! checking on a quiet machine, and `WITM_BENCH_BUDGET_ONLY=1` skips the
+//! criterion benchmarks. CI only reports the budgets, as latency on shared
+//! runners varies too much to enforce them.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use criterion::{Criterion, Throughput};
+use tokio::runtime::Runtime;
+use tokio::sync::RwLock;
+use witmproxy::proxy::listener::{ListenerConfig, MitmPolicy};
+use witmproxy::test_utils::{
+    Protocol, ServerHandle, create_ca_and_config, create_client, create_html_server,
+    create_plugin_registry, register_noop_plugin, register_test_component,
+};
+use witmproxy::{AppConfig, CertificateAuthority, ProxyServer};
+
+/// The most latency each scenario may add to a request at the 99th
+/// percentile. Generous, since shared CI runners are noisy; they're meant to
+/// catch regressions of whole milliseconds, not to measure the proxy.
+const BUDGETS: &[(&str, Duration)] = &[
+    ("passthrough", Duration::from_millis(3)),
+    ("mitm_no_plugin", Duration::from_millis(5)),
+    ("mitm_noop_plugin", Duration::from_millis(20)),
+    ("mitm_body_rewrite", Duration::from_millis(30)),
+];
+
+const WARMUP_REQUESTS: usize = 20;
+const SAMPLED_REQUESTS: usize = 200;
+const CONCURRENT_CLIENTS: usize = 16;
+const REQUESTS_PER_CLIENT: usize = 20;
+
+struct Scenario {
+    name: &'static str,
+    client: reqwest::Client,
+    // Kept alive for as long as the scenario is benchmarked
+    _proxy: ProxyServer,
+    _registry_dir: Option<tempfile::TempDir>,
+}
+
+struct Bench {
+    url: String,
+    body_len: u64,
+    direct: reqwest::Client,
+    scenarios: Vec<Scenario>,
+    _server: ServerHandle,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Plugins {
+    None,
+    Noop,
+    BodyRewrite,
+}
+
+async fn start_proxy(
+    ca: &CertificateAuthority,
+    plugins: Plugins,
+    always_mitm: bool,
+) -> (ProxyServer, String, Option<tempfile::TempDir>) {
+    let mut config = AppConfig::default();
+    config.proxy.proxy_bind_addr = Some("127.0.0.1:0".to_string());
+    if always_mitm {
+        config.proxy.listeners = vec![ListenerConfig {
+            name: Some("mitm".to_string()),
+            bind_addr: "127.0.0.1:0".to_string(),
+            mitm: MitmPolicy::Always,
+            plugins: Vec::new(),
+            require_auth: false,
+            tls_hostname: None,
+        }];
+    }
+
+    let (registry, registry_dir) = match plugins {
+        Plugins::None => (None, None),
+        Plugins::Noop | Plugins::BodyRewrite => {
+            let (mut registry, dir) = create_plugin_registry().await.unwrap();
+            if plugins == Plugins::Noop {
+                register_noop_plugin(&mut registry).await.unwrap();
+            } else {
+                register_test_component(&mut registry).await.unwrap();
+            }
+            (Some(Arc::new(RwLock::new(registry))), Some(dir))
+        }
+    };
+
+    let mut proxy = ProxyServer::new(ca.clone(), registry, config).unwrap();
+    proxy.start().await.unwrap();
+    let addr = proxy
+        .stats()
+        .listeners()
+        .last()
+        .map(|listener| listener.addr)
+        .unwrap();
+    (proxy, format!("http://{}", addr), registry_dir)
+}
+
+async fn setup() -> Bench {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let (ca, _) = create_ca_and_config().await;
+    let server = create_html_server("127.0.0.1", None, ca.clone(), Protocol::Http1).await;
+    let url = format!("https://127.0.0.1:{}/", server.listen_addr().port());
+
+    let direct = reqwest::Client::builder()
+        .add_root_certificate(
+            reqwest::Certificate::from_der(&ca.get_root_certificate_der().unwrap()).unwrap(),
+        )
+        .http1_only()
+        .build()
+        .unwrap();
+    let body_len = direct
+        .get(&url)
+        .send()
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap()
+        .len() as u64;
+
+    let mut scenarios = Vec::new();
+    for (name, plugins, always_mitm) in [
+        ("passthrough", Plugins::None, false),
+        ("mitm_no_plugin", Plugins::None, true),
+        ("mitm_noop_plugin", Plugins::Noop, false),
+        ("mitm_body_rewrite", Plugins::BodyRewrite, false),
+    ] {
+        let (proxy, proxy_url, registry_dir) = start_proxy(&ca, plugins, always_mitm).await;
+        scenarios.push(Scenario {
+            name,
+            client: create_client(ca.clone(), &proxy_url, Protocol::Http1).await,
+            _proxy: proxy,
+            _registry_dir: registry_dir,
+        });
+    }
+
+    Bench {
+        url,
+        body_len,
+        direct,
+        scenarios,
+        _server: server,
+    }
+}
+
+async fn fetch(client: &reqwest::Client, url: &str) {
+    let resp = client.get(url).send().await.unwrap();
+    assert!(
+        resp.status().is_success(),
+        "{} answered {}",
+        url,
+        resp.status()
+    );
+    std::hint::black_box(resp.bytes().await.unwrap());
+}
+
+/// Request latencies, sorted
+async fn sample(client: &reqwest::Client, url: &str) -> Vec<Duration> {
+    for _ in 0..WARMUP_REQUESTS {
+        fetch(client, url).await;
+    }
+    let mut latencies = Vec::with_capacity(SAMPLED_REQUESTS);
+    for _ in 0..SAMPLED_REQUESTS {
+        let start = Instant::now();
+        fetch(client, url).await;
+        latencies.push(start.elapsed());
+    }
+    latencies.sort();
+    latencies
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+/// Requests per second served to several clients at once
+async fn throughput(client: &reqwest::Client, url: &str) -> f64 {
+    let start = Instant::now();
+    let tasks: Vec<_> = (0..CONCURRENT_CLIENTS)
+        .map(|_| {
+            let client = client.clone();
+            let url = url.to_string();
+            tokio::spawn(async move {
+                for _ in 0..REQUESTS_PER_CLIENT {
+                    fetch(&client, &url).await;
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    (CONCURRENT_CLIENTS * REQUESTS_PER_CLIENT) as f64 / start.elapsed().as_secs_f64()
+}
+
+/// Report each scenario's added latency and throughput, returning the names
+/// of scenarios over budget
+async fn check_budgets(bench: &Bench) -> Vec<&'static str> {
+    let direct = sample(&bench.direct, &bench.url).await;
+    let (direct_p50, direct_p99) = (percentile(&direct, 0.5), percentile(&direct, 0.99));
+    println!(
+        "{:<20} {:>12} {:>12} {:>12} {:>10}",
+        "scenario", "added p50", "added p99", "budget p99", "req/s"
+    );
+    println!(
+        "{:<20} {:>12?} {:>12?} {:>12} {:>10.0}",
+        "direct",
+        direct_p50,
+        direct_p99,
+        "-",
+        throughput(&bench.direct, &bench.url).await
+    );
+
+    let mut over_budget = Vec::new();
+    for scenario in &bench.scenarios {
+        let latencies = sample(&scenario.client, &bench.url).await;
+        let added_p50 = percentile(&latencies, 0.5).saturating_sub(direct_p50);
+        let added_p99 = percentile(&latencies, 0.99).saturating_sub(direct_p99);
+        let budget = BUDGETS
+            .iter()
+            .find(|(name, _)| *name == scenario.name)
+            .map(|(_, budget)| *budget)
+            .unwrap();
+        println!(
+            "{:<20} {:>12?} {:>12?} {:>12?} {:>10.0}",
+            scenario.name,
+            added_p50,
+            added_p99,
+            budget,
+            throughput(&scenario.client, &bench.url).await
+        );
+        if added_p99 > budget {
+            over_budget.push(scenario.name);
+        }
+    }
+    over_budget
+}
+
+fn env_flag(name: &str) -> bool {
+    std::env::var(name).is_ok_and(|value| value == "1" || value == "true")
+}
+
+fn main() {
+    let runtime = Runtime::new().unwrap();
+    let bench = runtime.block_on(setup());
+
+    let over_budget = runtime.block_on(check_budgets(&bench));
+    if !over_budget.is_empty() {
+        eprintln!("Over the latency budget: {}", over_budget.join(", "));
+        if env_flag("WITM_BENCH_ENFORCE_BUDGET") {
+            std::process::exit(1);
+        }
+    }
+    if env_flag("WITM_BENCH_BUDGET_ONLY") {
+        return;
+    }
+
+    let mut criterion = Criterion::default().configure_from_args();
+    let mut group = criterion.benchmark_group("hot_path");
+    group.throughput(Throughput::Bytes(bench.body_len));
+    group.bench_function("direct", |b| {
+        b.to_async(&runtime)
+            .iter(|| fetch(&bench.direct, &bench.url))
+    });
+    for scenario in &bench.scenarios {
+        group.bench_function(scenario.name, |b| {
+            b.to_async(&runtime)
+                .iter(|| fetch(&scenario.client, &bench.url))
+        });
+    }
+    group.finish();
+    criterion.final_summary();
+}
@@ -0,0 +1,41 @@
+#![no_main]
+
+use std::sync::LazyLock;
+
+use libfuzzer_sys::fuzz_target;
+use witmproxy::plugins::dry_run::{FlowFixture, evaluate};
+use witmproxy::wasm::bindgen::witmproxy::plugin::capabilities::EventKind;
+
+const KINDS: [EventKind; 7] = [
+    EventKind::Connect,
+    EventKind::Request,
+    EventKind::Response,
+    EventKind::InboundContent,
+    EventKind::Timer,
+    EventKind::RawStream,
+    EventKind::MqttMessage,
+];
+
+static FIXTURE: LazyLock<FlowFixture> = LazyLock::new(|| {
+    serde_json::from_str(
+        r#"{
+            "url": "https://example.com/api?q=1",
+            "headers": {"content-type": "application/json"},
+            "body": "{}",
+            "topic": "devices/42/telemetry"
+        }"#,
+    )
+    .expect("Failed to parse fixture")
+});
+
+// A capability scope expression as written by a plugin author, compiled and
+// evaluated for the event kind picked by the first byte
+fuzz_target!(|data: &[u8]| {
+    let Some((&kind, expression)) = data.split_first() else {
+        return;
+    };
+    let Ok(expression) = std::str::from_utf8(expression) else {
+        return;
+    };
+    let _ = evaluate(KINDS[kind as usize % KINDS.len()], expression, &FIXTURE);
+});
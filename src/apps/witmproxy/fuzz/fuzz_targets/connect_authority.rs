@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use witmproxy::proxy::parse_authority_host_port;
+use witmproxy::proxy::vhost::ConnectionInfo;
+
+// The target of a CONNECT request, which names the host to intercept and
+// is later compared with the Host of each request in the tunnel
+fuzz_target!(|data: &[u8]| {
+    let Ok(authority) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok((host, _port)) = parse_authority_host_port(authority, 443) {
+        let connection = ConnectionInfo::new(authority, Some(&host));
+        let _ = connection.mismatch(&host);
+    }
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use witmproxy::http::sniff::sniff;
+
+// A response's declared content type up to the first NUL byte, followed by
+// the start of its body
+fuzz_target!(|data: &[u8]| {
+    let (declared, prefix) = match data.iter().position(|&b| b == 0) {
+        Some(i) => (&data[..i], &data[i + 1..]),
+        None => (data, &[][..]),
+    };
+    let _ = sniff(&String::from_utf8_lossy(declared), prefix);
+});
@@ -0,0 +1,35 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use witmproxy::proxy::normalize::normalize_request;
+use witmproxy::proxy::vhost::ConnectionInfo;
+
+// A request head as a client sends it, parsed the way hyper's HTTP/1 server
+// does, then normalized and checked against its connection. The first byte
+// picks whether the request arrived inside a TLS tunnel.
+fuzz_target!(|data: &[u8]| {
+    let Some((&tls, head)) = data.split_first() else {
+        return;
+    };
+    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut parsed = httparse::Request::new(&mut headers);
+    if !matches!(parsed.parse(head), Ok(httparse::Status::Complete(_))) {
+        return;
+    }
+
+    let mut builder = hyper::Request::builder()
+        .method(parsed.method.unwrap_or_default())
+        .uri(parsed.path.unwrap_or_default());
+    for header in parsed.headers.iter() {
+        builder = builder.header(header.name, header.value);
+    }
+    let Ok(req) = builder.body(()) else {
+        return;
+    };
+
+    let req = normalize_request(req, tls & 1 == 1);
+    if let Some(authority) = req.uri().authority() {
+        let connection = ConnectionInfo::new("example.com", Some("example.com"));
+        let _ = connection.mismatch(authority.as_str());
+    }
+});
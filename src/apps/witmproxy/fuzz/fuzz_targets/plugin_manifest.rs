@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use witmproxy::plugins::bundle::PluginBundle;
+use witmproxy::wasm::bindgen::exports::witmproxy::plugin::witm_plugin::UserInput;
+use witmproxy::wasm::bindgen::witmproxy::plugin::capabilities::Capability;
+
+// The serialized parts of a plugin's manifest: the capabilities and
+// configuration stored with it, and plugin bundles shared between instances
+fuzz_target!(|data: &[u8]| {
+    let Ok(json) = std::str::from_utf8(data) else {
+        return;
+    };
+    let _ = serde_json::from_str::<Capability>(json);
+    let _ = serde_json::from_str::<Vec<UserInput>>(json);
+    let _ = PluginBundle::from_json(json);
+});
@@ -0,0 +1,123 @@
+//! Builder for embedding witmproxy in other programs, such as the
+//! integration tests of crates whose traffic goes through the proxy.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::sync::RwLock;
+
+use crate::error::WitmError;
+use crate::plugins::integrity::TrustedKeys;
+use crate::plugins::redaction::Redactions;
+use crate::proxy::flows::FlowRecord;
+use crate::proxy::hooks::ProxyHooks;
+use crate::proxy::pages::FlowInfo;
+use crate::{AppConfig, CertificateAuthority, Db, PluginRegistry, Runtime, WitmProxy};
+
+/// Assembles a [WitmProxy]. Anything left unset gets a throwaway default: a
+/// CA generated in a temporary directory, an in-memory database, and a
+/// registry without plugins.
+#[derive(Default)]
+pub struct WitmProxyBuilder {
+    ca: Option<CertificateAuthority>,
+    config: AppConfig,
+    config_path: Option<PathBuf>,
+    db: Option<Db>,
+    plugin_registry: Option<Arc<RwLock<PluginRegistry>>>,
+    plugins: Vec<Vec<u8>>,
+    hooks: ProxyHooks,
+}
+
+impl WitmProxyBuilder {
+    /// Use `ca` to sign the certificates of intercepted hosts
+    pub fn ca(mut self, ca: CertificateAuthority) -> Self {
+        self.ca = Some(ca);
+        self
+    }
+
+    pub fn config(mut self, config: AppConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Set the config file path so the management API can persist changes
+    pub fn config_path(mut self, path: PathBuf) -> Self {
+        self.config_path = Some(path);
+        self
+    }
+
+    /// Store plugins and management state in `db`, migrated on build
+    pub fn db(mut self, db: Db) -> Self {
+        self.db = Some(db);
+        self
+    }
+
+    /// Use `registry` rather than creating one backed by the database
+    pub fn plugin_registry(mut self, registry: Arc<RwLock<PluginRegistry>>) -> Self {
+        self.plugin_registry = Some(registry);
+        self
+    }
+
+    /// Register the plugin compiled to `component` on build
+    pub fn plugin(mut self, component: Vec<u8>) -> Self {
+        self.plugins.push(component);
+        self
+    }
+
+    /// Run `hook` with each intercepted flow once its response status is known
+    pub fn on_flow(mut self, hook: impl Fn(&FlowRecord) + Send + Sync + 'static) -> Self {
+        self.hooks = self.hooks.on_flow(hook);
+        self
+    }
+
+    /// Run `hook` when a plugin fails while handling a flow
+    pub fn on_plugin_error(
+        mut self,
+        hook: impl Fn(&FlowInfo, &WitmError) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks = self.hooks.on_plugin_error(hook);
+        self
+    }
+
+    /// Create the proxy, ready to [start](WitmProxy::start)
+    pub async fn build(self) -> Result<WitmProxy> {
+        let (ca, temp_dir) = match self.ca {
+            Some(ca) => (ca, None),
+            None => {
+                let dir = tempfile::tempdir()?;
+                (CertificateAuthority::new(dir.path()).await?, Some(dir))
+            }
+        };
+        let db = match self.db {
+            Some(db) => db,
+            None => Db::in_memory().await?,
+        };
+        db.migrate().await?;
+
+        let registry = match self.plugin_registry {
+            Some(registry) => registry,
+            None => Arc::new(RwLock::new(
+                PluginRegistry::new(db.clone(), Runtime::try_default()?)?
+                    .with_redactions(Redactions::new(self.config.plugins.redactions.clone())?)
+                    .with_trusted_keys(TrustedKeys::new(&self.config.plugins.trusted_keys)?),
+            )),
+        };
+        {
+            let mut registry = registry.write().await;
+            for component in self.plugins {
+                let plugin = registry.plugin_from_component(component).await?;
+                registry.register_plugin(plugin).await?;
+            }
+        }
+
+        let mut proxy = WitmProxy::new(ca, Some(registry), self.config)
+            .with_db_pool(db.pool)
+            .with_hooks(self.hooks);
+        if let Some(path) = self.config_path {
+            proxy = proxy.with_config_path(path);
+        }
+        proxy._temp_dir = temp_dir;
+        Ok(proxy)
+    }
+}
@@ -1,12 +1,15 @@
-use super::{CertError, CertResult, Certificate, CertificateCache};
+use super::faults::{CertFault, CertFaultRule, fault_for};
+use super::{CertError, CertResult, Certificate, CertificateCache, ocsp};
+use crate::config::TlsConfig;
 use anyhow::{Result, anyhow};
-use rcgen::{CertificateParams, DistinguishedName, DnType, Issuer, KeyPair, SanType};
+use rcgen::{CertificateParams, DistinguishedName, DnType, Issuer, KeyPair, SanType, SerialNumber};
 use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+use std::collections::HashSet;
 use std::io;
 use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use tokio::fs;
 use tracing::{debug, error, info, warn};
 
@@ -17,6 +20,10 @@ pub struct CertificateAuthority {
     root_issuer: Arc<Issuer<'static, KeyPair>>,
     cert_cache: Arc<CertificateCache>,
     cert_dir: PathBuf,
+    ocsp_responder: Option<String>,
+    cert_faults: Arc<Vec<CertFaultRule>>,
+    /// Normalized serials of minted certificates reported as revoked
+    revoked: Arc<RwLock<HashSet<Vec<u8>>>>,
 }
 
 impl std::fmt::Debug for CertificateAuthority {
@@ -28,6 +35,13 @@ impl std::fmt::Debug for CertificateAuthority {
     }
 }
 
+/// Whether a trust store holds the root certificate
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TrustStore {
+    pub name: String,
+    pub trusted: bool,
+}
+
 pub fn get_root_cert_path(cert_dir: &Path) -> PathBuf {
     cert_dir.join("ca.crt")
 }
@@ -67,9 +81,67 @@ impl CertificateAuthority {
             root_issuer: Arc::new(root_issuer),
             cert_cache,
             cert_dir,
+            ocsp_responder: None,
+            cert_faults: Arc::new(Vec::new()),
+            revoked: Arc::default(),
         })
     }
 
+    /// A CA with a newly generated root certificate that's never written to
+    /// disk, so nothing is left behind once it's dropped. Its certificate
+    /// can't be installed into the system trust store.
+    pub async fn in_memory() -> Result<Self> {
+        let (cert, key, params) = Self::generate_root_certificate().await?;
+        Ok(Self {
+            root_cert_pem: Arc::new(cert.pem()),
+            root_cert_der: Arc::new(cert.der().to_vec()),
+            root_issuer: Arc::new(Issuer::new(params, key)),
+            cert_cache: Arc::new(CertificateCache::new(1000)),
+            cert_dir: PathBuf::new(),
+            ocsp_responder: None,
+            cert_faults: Arc::new(Vec::new()),
+            revoked: Arc::default(),
+        })
+    }
+
+    /// Applies the OCSP responder and certificate faults from `config` to
+    /// the certificates minted from now on
+    pub fn with_minting(mut self, config: &TlsConfig) -> Self {
+        self.ocsp_responder = config.ocsp_responder_url.clone();
+        self.cert_faults = Arc::new(config.cert_faults.clone());
+        self
+    }
+
+    fn is_revoked(&self, serial: &[u8]) -> bool {
+        self.revoked
+            .read()
+            .is_ok_and(|revoked| revoked.contains(serial))
+    }
+
+    /// Answers a DER-encoded OCSP request for certificates minted by this CA
+    pub fn ocsp_response(&self, request: &[u8]) -> Vec<u8> {
+        ocsp::respond(
+            &self.root_cert_der,
+            self.root_issuer.key(),
+            request,
+            |serial| self.is_revoked(serial),
+        )
+    }
+
+    /// The OCSP response to staple to `cert`, when an OCSP responder is set
+    pub fn ocsp_staple(&self, cert: &Certificate) -> Option<Vec<u8>> {
+        self.ocsp_responder.as_ref()?;
+        ocsp::staple(
+            &self.root_cert_der,
+            self.root_issuer.key(),
+            &cert.cert_der,
+            |serial| self.is_revoked(serial),
+        )
+        .inspect_err(|e| warn!("Failed to staple OCSP response: {}", e))
+        .ok()
+        .flatten()
+    }
+
     async fn generate_root_certificate()
     -> CertResult<(rcgen::Certificate, KeyPair, CertificateParams)> {
         let mut params = CertificateParams::default();
@@ -155,6 +227,14 @@ impl CertificateAuthority {
     }
     async fn generate_domain_certificate(&self, domain: &str) -> CertResult<Certificate> {
         let mut params = CertificateParams::default();
+        let fault = fault_for(&self.cert_faults, domain);
+        if let Some(fault) = fault {
+            debug!("Minting certificate for {} with fault {:?}", domain, fault);
+        }
+        let domain = match fault {
+            Some(CertFault::WrongHost) => "wrong-host.invalid",
+            _ => domain,
+        };
 
         // Add subject alternative names
         if let Ok(ip) = domain.parse::<IpAddr>() {
@@ -188,17 +268,38 @@ impl CertificateAuthority {
             rcgen::KeyUsagePurpose::KeyEncipherment,
         ];
         params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ServerAuth];
+        if let Some(url) = &self.ocsp_responder {
+            params.custom_extensions.push(ocsp::aia_extension(url));
+        }
 
         // Set validity period (1 year)
-        let not_before = time::OffsetDateTime::now_utc() - time::Duration::days(1);
+        let now = time::OffsetDateTime::now_utc();
+        let not_before = now - time::Duration::days(1);
         let not_after = not_before + time::Duration::days(365);
         params.not_before = not_before;
         params.not_after = not_after;
+        match fault {
+            Some(CertFault::Expired) => params.not_after = now - time::Duration::days(1),
+            Some(CertFault::NotYetValid) => params.not_before = now + time::Duration::days(30),
+            Some(CertFault::Revoked) => {
+                // A positive serial we can recognise when the responder is asked
+                let mut serial = *uuid::Uuid::new_v4().as_bytes();
+                serial[0] = (serial[0] & 0x7f) | 0x40;
+                params.serial_number = Some(SerialNumber::from_slice(&serial));
+                if let Ok(mut revoked) = self.revoked.write() {
+                    revoked.insert(ocsp::normalize_serial(&serial));
+                }
+            }
+            _ => {}
+        }
 
         // Generate key pair and create certificate
         let key_pair = KeyPair::generate()?;
 
-        let cert = params.signed_by(&key_pair, &self.root_issuer)?;
+        let cert = match fault {
+            Some(CertFault::SelfSigned) => params.self_signed(&key_pair)?,
+            _ => params.signed_by(&key_pair, &self.root_issuer)?,
+        };
         let pem_cert = cert.pem();
         let cert_der = cert.der();
 
@@ -221,6 +322,13 @@ impl CertificateAuthority {
         Ok((*self.root_cert_der).clone())
     }
 
+    /// Returns the expiry (`notAfter`) of the root certificate
+    pub fn root_certificate_expiry(&self) -> CertResult<time::OffsetDateTime> {
+        let (_, cert) = x509_parser::parse_x509_certificate(&self.root_cert_der)
+            .map_err(|_| CertError::InvalidFormat)?;
+        Ok(cert.validity().not_after.to_datetime())
+    }
+
     pub async fn clear_cache(&self) {
         self.cert_cache.clear().await;
         info!("Certificate cache cleared");
@@ -325,19 +433,36 @@ impl CertificateAuthority {
         info!("Certificate path: {:?}", root_cert_path);
         info!("Certificate exists: {}", root_cert_path.exists());
 
-        if root_cert_path.exists() {
-            match platform {
-                Platform::MacOS => self.check_macos_status().await,
-                Platform::Linux => self.check_linux_status().await,
-                Platform::Windows => self.check_windows_status().await,
-                Platform::Unknown(os) => {
-                    info!("Trust status: Unknown (unsupported platform: {})", os);
-                    Ok(())
-                }
-            }
-        } else {
+        if !root_cert_path.exists() {
             info!("Trust status: Certificate not found");
-            Ok(())
+            return Ok(());
+        }
+        if let Platform::Unknown(os) = platform {
+            info!("Trust status: Unknown (unsupported platform: {})", os);
+            return Ok(());
+        }
+        for store in self.trust_stores()? {
+            if store.trusted {
+                info!("{}: ✓ Trusted", store.name);
+            } else {
+                info!("{}: ✗ Not trusted", store.name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the root certificate is trusted by each of this machine's
+    /// trust stores (the system's, and on Linux, browsers' NSS databases)
+    pub fn trust_stores(&self) -> Result<Vec<TrustStore>> {
+        match detect_platform()? {
+            Platform::MacOS => Ok(vec![Self::macos_trust_store()?]),
+            Platform::Linux => {
+                let mut stores = vec![Self::linux_trust_store()];
+                stores.extend(self.nss_trust_stores());
+                Ok(stores)
+            }
+            Platform::Windows => Ok(vec![Self::windows_trust_store()?]),
+            Platform::Unknown(_) => Ok(Vec::new()),
         }
     }
 
@@ -547,31 +672,28 @@ impl CertificateAuthority {
         }
     }
 
-    /// Check NSS database trust status.
-    fn check_nss_databases(&self) {
+    /// Trust status of the NSS databases browsers keep their roots in
+    fn nss_trust_stores(&self) -> Vec<TrustStore> {
         if !Self::has_certutil() {
             info!("NSS databases: certutil not available (install libnss3-tools)");
-            return;
+            return Vec::new();
         }
 
         let cert_name = "witmproxy-ca";
-        let nss_dbs = Self::find_nss_databases();
-        if nss_dbs.is_empty() {
-            info!("NSS databases: none found");
-            return;
-        }
-
-        for (browser, db_path) in &nss_dbs {
-            let db_arg = format!("sql:{}", db_path.display());
-            match Self::run_certutil_as_user(&["-L", "-d", &db_arg, "-n", cert_name]) {
-                Ok(output) if output.status.success() => {
-                    info!("NSS: ✓ Trusted by {}", browser);
-                }
-                _ => {
-                    info!("NSS: ✗ Not trusted by {}", browser);
+        Self::find_nss_databases()
+            .into_iter()
+            .map(|(browser, db_path)| {
+                let db_arg = format!("sql:{}", db_path.display());
+                let trusted = matches!(
+                    Self::run_certutil_as_user(&["-L", "-d", &db_arg, "-n", cert_name]),
+                    Ok(output) if output.status.success()
+                );
+                TrustStore {
+                    name: format!("NSS ({})", browser),
+                    trusted,
                 }
-            }
-        }
+            })
+            .collect()
     }
 
     /// Check if certutil (from libnss3-tools / nss-tools) is available.
@@ -835,7 +957,7 @@ impl CertificateAuthority {
     }
 
     // Platform-specific status checking methods
-    async fn check_macos_status(&self) -> Result<()> {
+    fn macos_trust_store() -> Result<TrustStore> {
         let output = Command::new("security")
             .args([
                 "find-certificate",
@@ -845,44 +967,32 @@ impl CertificateAuthority {
             ])
             .output()?;
 
-        if output.status.success() {
-            info!("Trust status: ✓ Installed in System keychain");
-        } else {
-            info!("Trust status: ✗ Not found in System keychain");
-        }
-
-        Ok(())
+        Ok(TrustStore {
+            name: "System keychain".to_string(),
+            trusted: output.status.success(),
+        })
     }
 
-    async fn check_linux_status(&self) -> Result<()> {
+    fn linux_trust_store() -> TrustStore {
         let ubuntu_path = Path::new("/usr/local/share/ca-certificates/witmproxy-root-ca.crt");
         let rhel_path = Path::new("/etc/pki/ca-trust/source/anchors/witmproxy-root-ca.crt");
 
-        if ubuntu_path.exists() {
-            info!("System trust store: ✓ Installed (ca-certificates)");
-        } else if rhel_path.exists() {
-            info!("System trust store: ✓ Installed (ca-trust)");
-        } else {
-            info!("System trust store: ✗ Not found");
+        TrustStore {
+            name: "System trust store".to_string(),
+            trusted: ubuntu_path.exists() || rhel_path.exists(),
         }
-
-        self.check_nss_databases();
-
-        Ok(())
     }
 
-    async fn check_windows_status(&self) -> Result<()> {
+    fn windows_trust_store() -> Result<TrustStore> {
         let output = Command::new("certutil")
             .args(["-store", "Root", "witmproxy Root CA"])
             .output()?;
 
-        if output.status.success() && !String::from_utf8_lossy(&output.stdout).contains("ERROR") {
-            info!("Trust status: ✓ Installed in Root certificate store");
-        } else {
-            info!("Trust status: ✗ Not found in Root certificate store");
-        }
-
-        Ok(())
+        Ok(TrustStore {
+            name: "Root certificate store".to_string(),
+            trusted: output.status.success()
+                && !String::from_utf8_lossy(&output.stdout).contains("ERROR"),
+        })
     }
 
     async fn print_manual_instructions(&self, cert_path: &Path) -> Result<()> {
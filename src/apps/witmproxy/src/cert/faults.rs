@@ -0,0 +1,51 @@
+//! Deliberately invalid certificates, minted for selected hosts to test how
+//! clients validate the certificates they're served:
+//!
+//! ```toml
+//! [[tls.cert_faults]]
+//! host = "revoked.test.example"
+//! fault = "revoked"
+//!
+//! [[tls.cert_faults]]
+//! host = "*.expired.test.example"
+//! fault = "expired"
+//! ```
+//!
+//! Revocation is only visible to clients which check it, through the OCSP
+//! responder set with `tls.ocsp_responder_url`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::proxy::host_limits::matches_host;
+
+/// What's wrong with the certificate a host is served
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CertFault {
+    /// Reported as revoked by the OCSP responder
+    Revoked,
+    /// Valid until yesterday
+    Expired,
+    /// Valid from next month
+    NotYetValid,
+    /// Issued for another name than the host's
+    WrongHost,
+    /// Signed by its own key rather than the CA
+    SelfSigned,
+}
+
+/// A fault applied to the certificates of matching hosts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertFaultRule {
+    /// Host name, or `*.` followed by a domain to match its subdomains
+    pub host: String,
+    pub fault: CertFault,
+}
+
+/// The fault of the first rule matching `host`, if any
+pub fn fault_for(rules: &[CertFaultRule], host: &str) -> Option<CertFault> {
+    rules
+        .iter()
+        .find(|rule| matches_host(&rule.host, host))
+        .map(|rule| rule.fault)
+}
@@ -1,5 +1,7 @@
 pub mod ca;
+pub mod faults;
 pub mod generator;
+pub mod ocsp;
 
 pub use ca::CertificateAuthority;
 pub use generator::{CertificateFormat, CertificateGenerator};
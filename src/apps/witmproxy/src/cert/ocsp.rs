@@ -0,0 +1,364 @@
+//! A minimal OCSP responder (RFC 6960) for minted certificates, and the
+//! Authority Information Access extension pointing clients at it.
+//!
+//! Responses are signed by the root CA itself. Serials minted with the
+//! `revoked` fault are reported revoked and every other serial good, so
+//! clients can be tested against both without a real CA's infrastructure.
+
+use rcgen::{CustomExtension, KeyPair, SigningKey};
+use sha2::{Digest, Sha256};
+use yasna::models::{GeneralizedTime, ObjectIdentifier};
+use yasna::{ASN1Result, Tag};
+
+use super::{CertError, CertResult};
+
+const AUTHORITY_INFO_ACCESS: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 1, 1];
+const ACCESS_OCSP: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 48, 1];
+const OCSP_BASIC: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 48, 1, 1];
+const SHA256: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 2, 1];
+
+const SUCCESSFUL: i64 = 0;
+const MALFORMED_REQUEST: i64 = 1;
+const INTERNAL_ERROR: i64 = 2;
+
+/// How long clients may rely on a response
+const RESPONSE_LIFETIME: time::Duration = time::Duration::days(7);
+
+/// An Authority Information Access extension naming `responder_url` as
+/// the OCSP responder
+pub fn aia_extension(responder_url: &str) -> CustomExtension {
+    let content = yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer.next().write_sequence(|writer| {
+                writer
+                    .next()
+                    .write_oid(&ObjectIdentifier::from_slice(ACCESS_OCSP));
+                // GeneralName's uniformResourceIdentifier
+                writer
+                    .next()
+                    .write_tagged_implicit(Tag::context(6), |writer| {
+                        writer.write_ia5_string(responder_url)
+                    });
+            })
+        })
+    });
+    CustomExtension::from_oid_content(AUTHORITY_INFO_ACCESS, content)
+}
+
+/// What responses need of the CA certificate
+struct Issuer {
+    subject: Vec<u8>,
+    public_key: Vec<u8>,
+    signature_algorithm: Vec<u8>,
+}
+
+impl Issuer {
+    fn parse(ca_der: &[u8]) -> CertResult<Self> {
+        let (_, cert) =
+            x509_parser::parse_x509_certificate(ca_der).map_err(|_| CertError::InvalidFormat)?;
+        // The CA is self-signed, so its own signature algorithm is the one
+        // its key signs responses with
+        let signature_algorithm = yasna::parse_der(ca_der, |reader| {
+            reader.read_sequence(|reader| {
+                reader.next().read_der()?;
+                let algorithm = reader.next().read_der()?;
+                reader.next().read_der()?;
+                Ok(algorithm)
+            })
+        })
+        .map_err(|_| CertError::InvalidFormat)?;
+        Ok(Self {
+            subject: cert.tbs_certificate.subject.as_raw().to_vec(),
+            public_key: cert
+                .tbs_certificate
+                .subject_pki
+                .subject_public_key
+                .data
+                .to_vec(),
+            signature_algorithm,
+        })
+    }
+
+    /// The CertID identifying the certificate with `serial` issued by this
+    /// CA, hashed with SHA-256
+    fn cert_id(&self, serial: &[u8]) -> Vec<u8> {
+        yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                writer.next().write_sequence(|writer| {
+                    writer
+                        .next()
+                        .write_oid(&ObjectIdentifier::from_slice(SHA256));
+                    writer.next().write_null();
+                });
+                writer.next().write_bytes(&Sha256::digest(&self.subject));
+                writer.next().write_bytes(&Sha256::digest(&self.public_key));
+                writer.next().write_bigint_bytes(serial, true);
+            })
+        })
+    }
+}
+
+/// A serial number without the leading zeros DER may give it
+pub fn normalize_serial(serial: &[u8]) -> Vec<u8> {
+    let start = serial.iter().position(|&b| b != 0).unwrap_or(serial.len());
+    serial[start..].to_vec()
+}
+
+/// The CertIDs asked about in a DER-encoded OCSPRequest
+fn requested_cert_ids(request: &[u8]) -> ASN1Result<Vec<Vec<u8>>> {
+    yasna::parse_ber(request, |reader| {
+        reader.read_sequence(|reader| {
+            let cert_ids = reader.next().read_sequence(|reader| {
+                // version and requestorName
+                reader.read_optional(|reader| {
+                    reader.read_tagged(Tag::context(0), |reader| reader.read_u8())
+                })?;
+                reader.read_optional(|reader| {
+                    reader.read_tagged(Tag::context(1), |reader| reader.read_der())
+                })?;
+                let mut cert_ids = Vec::new();
+                reader.next().read_sequence_of(|reader| {
+                    reader.read_sequence(|reader| {
+                        cert_ids.push(reader.next().read_der()?);
+                        reader.read_optional(|reader| reader.read_der())?;
+                        Ok(())
+                    })
+                })?;
+                reader.read_optional(|reader| reader.read_der())?;
+                Ok(cert_ids)
+            })?;
+            // optionalSignature
+            reader.read_optional(|reader| reader.read_der())?;
+            Ok(cert_ids)
+        })
+    })
+}
+
+/// The serial number in a CertID
+fn serial_of(cert_id: &[u8]) -> ASN1Result<Vec<u8>> {
+    yasna::parse_ber(cert_id, |reader| {
+        reader.read_sequence(|reader| {
+            reader.next().read_der()?;
+            reader.next().read_bytes()?;
+            reader.next().read_bytes()?;
+            let (serial, _) = reader.next().read_bigint_bytes()?;
+            Ok(normalize_serial(&serial))
+        })
+    })
+}
+
+fn status_only(status: i64) -> Vec<u8> {
+    yasna::construct_der(|writer| writer.write_sequence(|writer| writer.next().write_enum(status)))
+}
+
+/// A signed OCSPResponse reporting on each `(cert_id, revoked)`
+fn sign_response(
+    issuer: &Issuer,
+    key: &KeyPair,
+    responses: &[(Vec<u8>, bool)],
+) -> CertResult<Vec<u8>> {
+    let now = time::OffsetDateTime::now_utc()
+        .replace_nanosecond(0)
+        .map_err(|_| CertError::InvalidFormat)?;
+    let this_update = GeneralizedTime::from_datetime(now);
+    let next_update = GeneralizedTime::from_datetime(now + RESPONSE_LIFETIME);
+    let response_data = yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            // responderID, byName
+            writer
+                .next()
+                .write_tagged(Tag::context(1), |writer| writer.write_der(&issuer.subject));
+            writer.next().write_generalized_time(&this_update);
+            writer.next().write_sequence(|writer| {
+                for (cert_id, revoked) in responses {
+                    writer.next().write_sequence(|writer| {
+                        writer.next().write_der(cert_id);
+                        if *revoked {
+                            writer
+                                .next()
+                                .write_tagged_implicit(Tag::context(1), |writer| {
+                                    writer.write_sequence(|writer| {
+                                        writer.next().write_generalized_time(&this_update)
+                                    })
+                                });
+                        } else {
+                            writer
+                                .next()
+                                .write_tagged_implicit(Tag::context(0), |writer| {
+                                    writer.write_null()
+                                });
+                        }
+                        writer.next().write_generalized_time(&this_update);
+                        writer.next().write_tagged(Tag::context(0), |writer| {
+                            writer.write_generalized_time(&next_update)
+                        });
+                    });
+                }
+            });
+        })
+    });
+    let signature = key.sign(&response_data)?;
+    let basic = yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer.next().write_der(&response_data);
+            writer.next().write_der(&issuer.signature_algorithm);
+            writer
+                .next()
+                .write_bitvec_bytes(&signature, signature.len() * 8);
+        })
+    });
+    Ok(yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer.next().write_enum(SUCCESSFUL);
+            writer.next().write_tagged(Tag::context(0), |writer| {
+                writer.write_sequence(|writer| {
+                    writer
+                        .next()
+                        .write_oid(&ObjectIdentifier::from_slice(OCSP_BASIC));
+                    writer.next().write_bytes(&basic);
+                })
+            });
+        })
+    }))
+}
+
+/// Answer a DER-encoded OCSPRequest on behalf of the CA with `ca_der` and
+/// `key`, with serials for which `is_revoked` holds reported revoked
+pub fn respond(
+    ca_der: &[u8],
+    key: &KeyPair,
+    request: &[u8],
+    is_revoked: impl Fn(&[u8]) -> bool,
+) -> Vec<u8> {
+    let Ok(cert_ids) = requested_cert_ids(request) else {
+        return status_only(MALFORMED_REQUEST);
+    };
+    let responses: Vec<_> = cert_ids
+        .into_iter()
+        .map(|cert_id| {
+            let revoked = serial_of(&cert_id).is_ok_and(|serial| is_revoked(&serial));
+            (cert_id, revoked)
+        })
+        .collect();
+    Issuer::parse(ca_der)
+        .and_then(|issuer| sign_response(&issuer, key, &responses))
+        .unwrap_or_else(|_| status_only(INTERNAL_ERROR))
+}
+
+/// A response to staple to the leaf `cert_der`, if it was issued by the CA
+/// with `ca_der`
+pub fn staple(
+    ca_der: &[u8],
+    key: &KeyPair,
+    cert_der: &[u8],
+    is_revoked: impl Fn(&[u8]) -> bool,
+) -> CertResult<Option<Vec<u8>>> {
+    let issuer = Issuer::parse(ca_der)?;
+    let (_, cert) =
+        x509_parser::parse_x509_certificate(cert_der).map_err(|_| CertError::InvalidFormat)?;
+    if cert.tbs_certificate.issuer.as_raw() != issuer.subject.as_slice() {
+        return Ok(None);
+    }
+    let serial = normalize_serial(cert.tbs_certificate.raw_serial());
+    let cert_id = issuer.cert_id(&serial);
+    sign_response(&issuer, key, &[(cert_id, is_revoked(&serial))]).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, IsCa, SerialNumber};
+
+    fn request(cert_ids: &[Vec<u8>]) -> Vec<u8> {
+        yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                writer.next().write_sequence(|writer| {
+                    writer.next().write_sequence(|writer| {
+                        for cert_id in cert_ids {
+                            writer
+                                .next()
+                                .write_sequence(|writer| writer.next().write_der(cert_id));
+                        }
+                    })
+                })
+            })
+        })
+    }
+
+    /// The `(cert_id, revoked)` pairs of a successful response
+    fn statuses(response: &[u8]) -> Vec<(Vec<u8>, bool)> {
+        let basic = yasna::parse_der(response, |reader| {
+            reader.read_sequence(|reader| {
+                assert_eq!(reader.next().read_enum()?, SUCCESSFUL);
+                reader.next().read_tagged(Tag::context(0), |reader| {
+                    reader.read_sequence(|reader| {
+                        reader.next().read_oid()?;
+                        reader.next().read_bytes()
+                    })
+                })
+            })
+        })
+        .unwrap();
+        yasna::parse_der(&basic, |reader| {
+            reader.read_sequence(|reader| {
+                let statuses = reader.next().read_sequence(|reader| {
+                    reader.next().read_der()?;
+                    reader.next().read_der()?;
+                    let mut statuses = Vec::new();
+                    reader.next().read_sequence_of(|reader| {
+                        reader.read_sequence(|reader| {
+                            let cert_id = reader.next().read_der()?;
+                            let status = reader.next().read_der()?;
+                            reader.next().read_der()?;
+                            reader.read_optional(|reader| reader.read_der())?;
+                            statuses.push((cert_id, status[0] & 0x1f == 1));
+                            Ok(())
+                        })
+                    })?;
+                    Ok(statuses)
+                })?;
+                reader.next().read_der()?;
+                reader.next().read_bitvec_bytes()?;
+                Ok(statuses)
+            })
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn responder_reports_revoked_serials() {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::default();
+        params.is_ca = IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = params.self_signed(&key).unwrap();
+        let issuer = Issuer::parse(ca.der()).unwrap();
+        let good = issuer.cert_id(&[0x01, 0x02]);
+        let revoked = issuer.cert_id(&[0x7f, 0x03]);
+
+        let response = respond(
+            ca.der(),
+            &key,
+            &request(&[good.clone(), revoked.clone()]),
+            |serial| serial == [0x7f, 0x03],
+        );
+        assert_eq!(
+            statuses(&response),
+            [(good, false), (revoked.clone(), true)]
+        );
+        assert_eq!(
+            respond(ca.der(), &key, b"junk", |_| false),
+            status_only(MALFORMED_REQUEST)
+        );
+
+        let mut leaf = CertificateParams::new(vec!["revoked.example".to_string()]).unwrap();
+        leaf.serial_number = Some(SerialNumber::from_slice(&[0x7f, 0x03]));
+        let issuer_key = rcgen::Issuer::new(params, &key);
+        let leaf = leaf
+            .signed_by(&KeyPair::generate().unwrap(), &issuer_key)
+            .unwrap();
+        let stapled = staple(ca.der(), &key, leaf.der(), |serial| serial == [0x7f, 0x03])
+            .unwrap()
+            .unwrap();
+        assert_eq!(statuses(&stapled), [(revoked, true)]);
+    }
+}
@@ -1,6 +1,6 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Stored authentication credentials.
 #[derive(Debug, Serialize, Deserialize)]
@@ -74,7 +74,37 @@ impl ApiClient {
         }
     }
 
-    async fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+    /// Client for a remote instance given with `--remote`, optionally trusting
+    /// an extra root certificate (e.g. the instance's own CA from `/cert`).
+    pub fn remote(base_url: &str, token: Option<&str>, ca_cert: Option<&Path>) -> Result<Self> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(path) = ca_cert {
+            let pem = std::fs::read(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read CA certificate {:?}: {}", path, e))?;
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+        }
+        Ok(Self {
+            client: builder.build()?,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: token.map(|t| t.to_string()),
+        })
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Fail with the status and response body unless the request succeeded.
+    pub async fn check(resp: reqwest::Response) -> Result<reqwest::Response> {
+        if resp.status().is_success() {
+            return Ok(resp);
+        }
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("Server returned {}: {}", status, body)
+    }
+
+    pub async fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
         let url = format!("{}{}", self.base_url, path);
         let mut req = self.client.request(method, &url);
         if let Some(ref token) = self.token {
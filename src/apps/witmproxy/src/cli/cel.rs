@@ -0,0 +1,117 @@
+use anyhow::Result;
+use clap::Subcommand;
+use std::path::PathBuf;
+
+use super::api_client::ApiClient;
+use crate::plugins::dry_run::{self, DryRunResult, FlowFixture};
+use crate::wasm::bindgen::witmproxy::plugin::capabilities::EventKind;
+
+#[derive(Subcommand)]
+pub enum CelCommands {
+    /// Evaluate a capability scope expression against an example flow
+    Test {
+        /// Event kind the expression scopes (connect, request, response,
+        /// inbound_content, timer, raw_stream, mqtt_message)
+        #[arg(long, value_parser = parse_event_kind)]
+        event: EventKind,
+        /// The CEL expression to evaluate
+        #[arg(long)]
+        expr: String,
+        /// JSON file describing the flow to evaluate against
+        #[arg(long, conflicts_with_all = ["url", "flow"])]
+        fixture: Option<PathBuf>,
+        /// Evaluate against a GET request to this URL
+        #[arg(long, conflicts_with = "flow")]
+        url: Option<String>,
+        /// ID of a flow recently captured by the --remote instance
+        #[arg(long)]
+        flow: Option<String>,
+    },
+}
+
+pub(super) fn parse_event_kind(s: &str) -> Result<EventKind, String> {
+    serde_json::from_value(serde_json::Value::String(s.to_string())).map_err(|e| e.to_string())
+}
+
+#[derive(Default)]
+pub struct CelHandler {
+    remote: Option<ApiClient>,
+}
+
+impl CelHandler {
+    pub fn new() -> Self {
+        Self { remote: None }
+    }
+
+    pub fn with_remote(mut self, remote: Option<ApiClient>) -> Self {
+        self.remote = remote;
+        self
+    }
+
+    pub async fn handle(&self, command: &CelCommands) -> Result<()> {
+        match command {
+            CelCommands::Test {
+                event,
+                expr,
+                fixture,
+                url,
+                flow,
+            } => {
+                let fixture = match (fixture, url) {
+                    (Some(path), _) => Some(serde_json::from_str::<FlowFixture>(
+                        &std::fs::read_to_string(path).map_err(|e| {
+                            anyhow::anyhow!("Failed to read fixture {:?}: {}", path, e)
+                        })?,
+                    )?),
+                    (None, Some(url)) => {
+                        Some(serde_json::from_value(serde_json::json!({ "url": url }))?)
+                    }
+                    (None, None) => None,
+                };
+
+                let result = match flow {
+                    Some(flow_id) => {
+                        let remote = self.remote.as_ref().ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "--flow evaluates against a running instance; pass --remote"
+                            )
+                        })?;
+                        let body = serde_json::json!({
+                            "event": event,
+                            "expression": expr,
+                            "flow_id": flow_id,
+                        });
+                        let resp =
+                            ApiClient::check(remote.post_json("/api/cel/test", &body).await?)
+                                .await?;
+                        resp.json::<DryRunResult>().await?
+                    }
+                    None => {
+                        let fixture = fixture.ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "Provide a flow to evaluate with --fixture, --url or --flow"
+                            )
+                        })?;
+                        dry_run::evaluate(*event, expr, &fixture)
+                    }
+                };
+
+                if let Some(value) = &result.value {
+                    println!("Value:   {}", value);
+                }
+                if let Some(error) = &result.error {
+                    anyhow::bail!("{}", error);
+                }
+                println!(
+                    "Result:  {}",
+                    if result.matched {
+                        "matched, the plugin would handle this event"
+                    } else {
+                        "not matched, the plugin would skip this event"
+                    }
+                );
+                Ok(())
+            }
+        }
+    }
+}
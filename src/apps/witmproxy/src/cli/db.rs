@@ -0,0 +1,164 @@
+use anyhow::Result;
+use clap::Subcommand;
+use std::path::PathBuf;
+
+use crate::config::AppConfig;
+use crate::db::audit::{AuditAction, AuditEntry, cli_actor};
+use crate::db::retention::{self, RetentionPolicy};
+use crate::db::{Db, backup};
+use crate::plugins::bundle::PluginBundle;
+use crate::plugins::registry::PluginRegistry;
+use crate::wasm::Runtime;
+
+#[derive(Subcommand)]
+pub enum DbCommands {
+    /// Remove audit log entries beyond the configured retention limits
+    Prune {
+        /// Remove entries older than this many days (overrides db.audit_max_age_days)
+        #[arg(long)]
+        max_age_days: Option<u32>,
+        /// Keep at most this many entries (overrides db.audit_max_entries)
+        #[arg(long)]
+        max_entries: Option<u64>,
+        /// Vacuum the database afterwards, even if little space was freed
+        #[arg(long)]
+        vacuum: bool,
+    },
+    /// Write a consistent copy of the database, encrypted with the same
+    /// password, while the proxy keeps running
+    Backup {
+        /// Where to write the backup (must not exist)
+        path: PathBuf,
+    },
+    /// Replace the database with a backup (stop the proxy first)
+    ///
+    /// The current database is kept next to it, with `-before-restore`
+    /// appended to its name.
+    Restore {
+        /// Backup taken with `witm db backup`
+        path: PathBuf,
+    },
+    /// Export installed plugins, with their capability grants and
+    /// configuration, as a portable bundle
+    Export {
+        /// Where to write the bundle (JSON)
+        path: PathBuf,
+    },
+    /// Install the plugins from a bundle written by `witm db export`
+    Import {
+        /// The bundle to import
+        path: PathBuf,
+    },
+}
+
+pub struct DbHandler {
+    config: AppConfig,
+}
+
+impl DbHandler {
+    pub fn new(config: AppConfig) -> Self {
+        Self { config }
+    }
+
+    async fn open_db(&self) -> Result<Db> {
+        let db = Db::from_path(self.config.db.db_path.clone(), &self.config.db.db_password).await?;
+        db.migrate().await?;
+        Ok(db)
+    }
+
+    pub async fn handle(&self, command: &DbCommands) -> Result<()> {
+        match command {
+            DbCommands::Prune {
+                max_age_days,
+                max_entries,
+                vacuum,
+            } => {
+                let configured = RetentionPolicy::from(&self.config.db);
+                let policy = RetentionPolicy {
+                    audit_max_age_days: max_age_days.or(configured.audit_max_age_days),
+                    audit_max_entries: max_entries.or(configured.audit_max_entries),
+                };
+                if policy.is_unlimited() && !vacuum {
+                    println!(
+                        "No retention limits configured; pass --max-age-days or --max-entries, \
+                         or set them in the [db] config section."
+                    );
+                    return Ok(());
+                }
+
+                let db = self.open_db().await?;
+                let report = retention::prune(&db.pool, &policy, &cli_actor(), *vacuum).await?;
+                println!("Pruned {} audit log entries", report.audit_entries);
+                if report.vacuumed {
+                    println!("Vacuumed the database");
+                }
+            }
+            DbCommands::Backup { path } => {
+                let db = self.open_db().await?;
+                backup::backup(&db.pool, path).await?;
+                println!("Backed up the database to {}", path.display());
+            }
+            DbCommands::Restore { path } => {
+                let previous =
+                    backup::restore(path, &self.config.db.db_path, &self.config.db.db_password)
+                        .await?;
+                println!(
+                    "Restored {} from {}",
+                    self.config.db.db_path.display(),
+                    path.display()
+                );
+                if let Some(previous) = previous {
+                    println!("The previous database was kept at {}", previous.display());
+                }
+            }
+            DbCommands::Export { path } => {
+                let mut registry =
+                    PluginRegistry::new(self.open_db().await?, Runtime::try_default()?)?;
+                registry.load_plugins().await?;
+                let bundle = PluginBundle::new(registry.plugins().values());
+                std::fs::write(path, serde_json::to_string_pretty(&bundle)?)?;
+                println!(
+                    "Exported {} plugins to {}",
+                    bundle.plugins.len(),
+                    path.display()
+                );
+            }
+            DbCommands::Import { path } => {
+                let bundle =
+                    PluginBundle::from_json(&std::fs::read_to_string(path).map_err(|e| {
+                        anyhow::anyhow!("Failed to read bundle {:?}: {}", path, e)
+                    })?)?;
+                let mut registry =
+                    PluginRegistry::new(self.open_db().await?, Runtime::try_default()?)?;
+                let actor = cli_actor();
+                for bundled in &bundle.plugins {
+                    let plugin_id = registry.import_plugin(bundled).await?;
+                    let granted: Vec<String> = bundled
+                        .capabilities
+                        .iter()
+                        .filter(|c| c.granted)
+                        .map(|c| c.inner.kind.to_string())
+                        .collect();
+                    AuditEntry::record(
+                        &registry.db.pool,
+                        &actor,
+                        AuditAction::PluginInstall,
+                        Some(&plugin_id),
+                        serde_json::json!({ "source": path.display().to_string() }),
+                    )
+                    .await?;
+                    AuditEntry::record(
+                        &registry.db.pool,
+                        &actor,
+                        AuditAction::CapabilityGrant,
+                        Some(&plugin_id),
+                        serde_json::json!({ "capabilities": granted }),
+                    )
+                    .await?;
+                    println!("Imported {}", plugin_id);
+                }
+            }
+        }
+        Ok(())
+    }
+}
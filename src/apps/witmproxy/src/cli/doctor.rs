@@ -0,0 +1,513 @@
+//! `witm doctor`: checks the things that most often stop witmproxy from
+//! working (an untrusted CA, ports taken, a system proxy pointing elsewhere,
+//! a damaged database or plugin, no network, a wrong clock) and says how to
+//! fix each. `--json` prints the report for attaching to bug reports.
+
+use super::{Services, proxy::ProxyHandler};
+use crate::{AppConfig, CertificateAuthority, cert::ca::get_root_cert_path, db::Db};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Clock skew beyond which certificates and tokens may be rejected
+const MAX_CLOCK_SKEW_SECS: i64 = 120;
+
+/// Days before the root certificate expires to start warning
+const CA_EXPIRY_WARNING_DAYS: i64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+    Skip,
+}
+
+impl Status {
+    fn symbol(self) -> &'static str {
+        match self {
+            Status::Ok => "✓",
+            Status::Warn => "⚠",
+            Status::Fail => "✗",
+            Status::Skip => "-",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    /// What to do about a warning or failure
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn fix(mut self, fix: impl Into<String>) -> Self {
+        self.fix = Some(fix.into());
+        self
+    }
+}
+
+#[derive(Serialize)]
+struct Report {
+    version: &'static str,
+    target: String,
+    config_path: PathBuf,
+    checks: Vec<Check>,
+}
+
+pub struct DoctorHandler {
+    config: AppConfig,
+    config_path: PathBuf,
+}
+
+impl DoctorHandler {
+    pub fn new(config: AppConfig, config_path: PathBuf) -> Self {
+        Self {
+            config,
+            config_path,
+        }
+    }
+
+    /// Run every check against `upstream` (the plugin registry by default),
+    /// failing if any check failed
+    pub async fn run(&self, upstream: Option<&str>, json: bool) -> Result<()> {
+        let upstream = upstream.unwrap_or(&self.config.plugins.registry_url);
+        let mut checks = vec![self.check_ca().await, self.check_ports()];
+        checks.push(self.check_system_proxy().await);
+        checks.extend(self.check_db().await);
+        checks.extend(check_upstream(upstream).await);
+
+        let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+        if json {
+            let report = Report {
+                version: env!("CARGO_PKG_VERSION"),
+                target: format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS),
+                config_path: self.config_path.clone(),
+                checks,
+            };
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            for check in &checks {
+                println!(
+                    "{} {:<20} {}",
+                    check.status.symbol(),
+                    check.name,
+                    check.detail
+                );
+                if let Some(fix) = &check.fix {
+                    println!("  {:<20} fix: {}", "", fix);
+                }
+            }
+        }
+
+        if failed > 0 {
+            anyhow::bail!("{} of the checks failed", failed);
+        }
+        Ok(())
+    }
+
+    async fn check_ca(&self) -> Check {
+        const NAME: &str = "Certificate trust";
+        let cert_path = get_root_cert_path(&self.config.tls.cert_dir);
+        if !cert_path.exists() {
+            return Check::new(NAME, Status::Fail, format!("No root CA at {:?}", cert_path))
+                .fix("Run `witm init` or `witm start` to generate one");
+        }
+        let ca = match CertificateAuthority::new(&self.config.tls.cert_dir).await {
+            Ok(ca) => ca,
+            Err(e) => {
+                return Check::new(NAME, Status::Fail, format!("Failed to load the CA: {}", e))
+                    .fix(format!(
+                        "Move {:?} aside and run `witm start` to generate a new CA, then trust it again",
+                        self.config.tls.cert_dir
+                    ));
+            }
+        };
+        if let Ok(expires_at) = ca.root_certificate_expiry() {
+            let days = (expires_at - time::OffsetDateTime::now_utc()).whole_days();
+            if days < CA_EXPIRY_WARNING_DAYS {
+                return Check::new(
+                    NAME,
+                    Status::Warn,
+                    format!("The root CA expires in {} days", days),
+                )
+                .fix("Move the cert directory aside and run `witm start` to generate a new CA, then `witm ca install`");
+            }
+        }
+
+        let stores = match ca.trust_stores() {
+            Ok(stores) if !stores.is_empty() => stores,
+            Ok(_) => {
+                return Check::new(
+                    NAME,
+                    Status::Skip,
+                    "Trust stores can't be checked on this platform",
+                );
+            }
+            Err(e) => {
+                return Check::new(NAME, Status::Skip, format!("Failed to check: {}", e));
+            }
+        };
+        let untrusted: Vec<&str> = stores
+            .iter()
+            .filter(|s| !s.trusted)
+            .map(|s| s.name.as_str())
+            .collect();
+        if untrusted.is_empty() {
+            let trusted: Vec<&str> = stores.iter().map(|s| s.name.as_str()).collect();
+            return Check::new(
+                NAME,
+                Status::Ok,
+                format!("Trusted by {}", trusted.join(", ")),
+            );
+        }
+        let status = if untrusted.len() == stores.len() {
+            Status::Fail
+        } else {
+            Status::Warn
+        };
+        Check::new(
+            NAME,
+            status,
+            format!("Not trusted by {}", untrusted.join(", ")),
+        )
+        .fix("Run `witm ca install`")
+    }
+
+    fn check_ports(&self) -> Check {
+        const NAME: &str = "Ports";
+        let mut addrs: Vec<(String, &str)> = Vec::new();
+        if let Some(addr) = &self.config.proxy.proxy_bind_addr {
+            addrs.push(("proxy.proxy_bind_addr".to_string(), addr));
+        }
+        if let Some(addr) = &self.config.web.web_bind_addr {
+            addrs.push(("web.web_bind_addr".to_string(), addr));
+        }
+        for listener in &self.config.proxy.listeners {
+            addrs.push((
+                format!("listener {}", listener.display_name()),
+                &listener.bind_addr,
+            ));
+        }
+
+        let ours = self.running_ports();
+        let mut problems = Vec::new();
+        let mut checked = 0;
+        for (setting, addr) in addrs {
+            match check_port(addr, &ours) {
+                Ok(PortState::Free | PortState::Ours) => checked += 1,
+                Ok(PortState::Unset) => {}
+                Err(problem) => problems.push(format!("{} ({})", problem, setting)),
+            }
+        }
+
+        if !problems.is_empty() {
+            return Check::new(NAME, Status::Fail, problems.join("; ")).fix(
+                "Stop the program using the port, or choose another in the config file (0 lets the OS pick)",
+            );
+        }
+        if checked == 0 {
+            Check::new(NAME, Status::Ok, "Every listener uses a port the OS picks")
+        } else {
+            Check::new(
+                NAME,
+                Status::Ok,
+                format!(
+                    "{} configured address(es) free or held by witmproxy",
+                    checked
+                ),
+            )
+        }
+    }
+
+    /// Ports held by the running instance, from its services file
+    fn running_ports(&self) -> HashSet<u16> {
+        let services_path = self
+            .config
+            .tls
+            .cert_dir
+            .parent()
+            .unwrap_or(&PathBuf::from("."))
+            .join("services.json");
+        std::fs::read_to_string(services_path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<Services>(&content).ok())
+            .map(|services| {
+                [services.proxy, services.web]
+                    .iter()
+                    .filter_map(|addr| addr.rsplit_once(':')?.1.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    async fn check_system_proxy(&self) -> Check {
+        const NAME: &str = "System proxy";
+        let handler = ProxyHandler::new(self.config.clone());
+        let current = match handler.get_current_system_proxy().await {
+            Ok(current) => current,
+            Err(e) => {
+                return Check::new(NAME, Status::Skip, format!("Failed to read: {}", e));
+            }
+        };
+        let Some(current) = current else {
+            return Check::new(
+                NAME,
+                Status::Ok,
+                "Not set; only clients pointed at the proxy go through it",
+            );
+        };
+        match handler.get_proxy_url().await {
+            Ok(ours) if current.contains(ours.trim_start_matches("http://")) => {
+                Check::new(NAME, Status::Ok, format!("Points at witmproxy ({})", ours))
+            }
+            Ok(ours) => Check::new(
+                NAME,
+                Status::Warn,
+                format!("Points at {}, not witmproxy ({})", current, ours),
+            )
+            .fix("Run `witm proxy enable` to route the system through witmproxy"),
+            Err(_) => Check::new(
+                NAME,
+                Status::Fail,
+                format!(
+                    "Points at {} but witmproxy isn't running, so connections will fail",
+                    current
+                ),
+            )
+            .fix("Start witmproxy with `witm start`, or run `witm proxy restore`"),
+        }
+    }
+
+    /// The database's integrity and the signatures of the plugins in it
+    async fn check_db(&self) -> Vec<Check> {
+        const DB: &str = "Database";
+        const PLUGINS: &str = "Plugin signatures";
+        let db_path = &self.config.db.db_path;
+        if crate::db::is_in_memory(db_path) || !db_path.exists() {
+            return vec![
+                Check::new(
+                    DB,
+                    Status::Skip,
+                    format!("No database at {:?} yet", db_path),
+                ),
+                Check::new(PLUGINS, Status::Skip, "No plugins installed"),
+            ];
+        }
+        let db = match Db::from_path(db_path.clone(), &self.config.db.db_password).await {
+            Ok(db) => db,
+            Err(e) => {
+                return vec![
+                    Check::new(
+                        DB,
+                        Status::Fail,
+                        format!("Failed to open {:?}: {}", db_path, e),
+                    )
+                    .fix("Check db.db_password matches the one the database was created with"),
+                    Check::new(PLUGINS, Status::Skip, "The database could not be opened"),
+                ];
+            }
+        };
+
+        let db_check = match db.integrity_problems().await {
+            Ok(None) => Check::new(DB, Status::Ok, format!("{:?} is intact", db_path)),
+            Ok(Some(problems)) => Check::new(DB, Status::Fail, problems)
+                .fix("Stop witmproxy and restore a backup with `witm db restore <backup>`"),
+            Err(e) => Check::new(DB, Status::Fail, format!("Failed to read: {}", e))
+                .fix("Check db.db_password matches the one the database was created with"),
+        };
+
+        let rows: Vec<(String, String, Vec<u8>, Vec<u8>)> =
+            match sqlx::query_as("SELECT namespace, name, publickey, component FROM plugins")
+                .fetch_all(&db.pool)
+                .await
+            {
+                Ok(rows) => rows,
+                Err(e) => {
+                    return vec![
+                        db_check,
+                        Check::new(
+                            PLUGINS,
+                            Status::Skip,
+                            format!("Failed to list plugins: {}", e),
+                        ),
+                    ];
+                }
+            };
+        let invalid: Vec<String> = rows
+            .iter()
+            .filter(|(_, _, publickey, component)| !signature_valid(publickey, component))
+            .map(|(namespace, name, _, _)| format!("{}/{}", namespace, name))
+            .collect();
+        let plugins_check = if rows.is_empty() {
+            Check::new(PLUGINS, Status::Ok, "No plugins installed")
+        } else if invalid.is_empty() {
+            Check::new(
+                PLUGINS,
+                Status::Ok,
+                format!("{} plugin(s) verified", rows.len()),
+            )
+        } else {
+            Check::new(
+                PLUGINS,
+                Status::Fail,
+                format!("Invalid signature: {}", invalid.join(", ")),
+            )
+            .fix("Remove them with `witm plugin remove` and add them again from a trusted source")
+        };
+        db.pool.close().await;
+        vec![db_check, plugins_check]
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum PortState {
+    Free,
+    /// Held by the running instance
+    Ours,
+    /// Port 0, picked by the OS when bound
+    Unset,
+}
+
+/// Whether `addr` can be bound, given the ports `ours` the running instance
+/// holds, or a description of why not
+fn check_port(addr: &str, ours: &HashSet<u16>) -> Result<PortState, String> {
+    let addr: SocketAddr = addr
+        .parse()
+        .map_err(|_| format!("{} is not a valid address", addr))?;
+    if addr.port() == 0 {
+        return Ok(PortState::Unset);
+    }
+    match TcpListener::bind(addr) {
+        Ok(_) => Ok(PortState::Free),
+        Err(_) if ours.contains(&addr.port()) => Ok(PortState::Ours),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+            Err(format!("{} is in use by another program", addr))
+        }
+        Err(e) => Err(format!("{} can't be bound: {}", addr, e)),
+    }
+}
+
+fn signature_valid(publickey: &[u8], component: &[u8]) -> bool {
+    wasmsign2::PublicKey::from_bytes(publickey).is_ok_and(|key| {
+        key.verify(&mut std::io::Cursor::new(component), None)
+            .is_ok()
+    })
+}
+
+/// Seconds this machine's clock is ahead of the server that sent `date`
+fn clock_skew(date: &str, now: DateTime<Utc>) -> Option<i64> {
+    let server = DateTime::parse_from_rfc2822(date).ok()?;
+    Some((now - server.with_timezone(&Utc)).num_seconds())
+}
+
+/// Reachability of `url` without any proxy, and the clock skew against it
+async fn check_upstream(url: &str) -> Vec<Check> {
+    const UPSTREAM: &str = "Upstream connectivity";
+    const CLOCK: &str = "Clock skew";
+    let client = match reqwest::Client::builder()
+        .user_agent("witmproxy")
+        .no_proxy()
+        .timeout(Duration::from_secs(10))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            return vec![
+                Check::new(UPSTREAM, Status::Skip, e.to_string()),
+                Check::new(CLOCK, Status::Skip, "No server to compare against"),
+            ];
+        }
+    };
+    let resp = match client.head(url).send().await {
+        Ok(resp) => resp,
+        Err(e) => {
+            return vec![
+                Check::new(
+                    UPSTREAM,
+                    Status::Fail,
+                    format!("Can't reach {}: {}", url, e),
+                )
+                .fix("Check the network connection, DNS and firewall"),
+                Check::new(CLOCK, Status::Skip, "No server to compare against"),
+            ];
+        }
+    };
+
+    let upstream = Check::new(UPSTREAM, Status::Ok, format!("Reached {}", url));
+    let skew = resp
+        .headers()
+        .get(reqwest::header::DATE)
+        .and_then(|date| date.to_str().ok())
+        .and_then(|date| clock_skew(date, Utc::now()));
+    let clock = match skew {
+        None => Check::new(CLOCK, Status::Skip, format!("{} sent no date", url)),
+        Some(secs) if secs.abs() <= MAX_CLOCK_SKEW_SECS => Check::new(
+            CLOCK,
+            Status::Ok,
+            format!("Within {}s of {}", secs.abs(), url),
+        ),
+        Some(secs) => Check::new(
+            CLOCK,
+            Status::Fail,
+            format!(
+                "{}s {} {}",
+                secs.abs(),
+                if secs > 0 { "ahead of" } else { "behind" },
+                url
+            ),
+        )
+        .fix("Sync the system clock (e.g. enable NTP); certificates are rejected outside their validity"),
+    };
+    vec![upstream, clock]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ports_in_use_fail_unless_held_by_the_running_instance() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        assert!(check_port(&addr.to_string(), &HashSet::new()).is_err());
+        assert_eq!(
+            check_port(&addr.to_string(), &HashSet::from([addr.port()])),
+            Ok(PortState::Ours)
+        );
+        assert_eq!(
+            check_port("127.0.0.1:0", &HashSet::new()),
+            Ok(PortState::Unset)
+        );
+        assert!(check_port("localhost", &HashSet::new()).is_err());
+    }
+
+    #[test]
+    fn clock_skew_is_measured_against_the_date_header() {
+        let now = DateTime::parse_from_rfc3339("2026-10-15T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(clock_skew("Thu, 15 Oct 2026 11:55:00 GMT", now), Some(300));
+        assert_eq!(clock_skew("Thu, 15 Oct 2026 12:00:30 GMT", now), Some(-30));
+        assert_eq!(clock_skew("yesterday", now), None);
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::Result;
+use clap::Subcommand;
+
+use super::api_client::ApiClient;
+use crate::proxy::header_overrides::{HeaderOverridesConfig, HeaderProfile};
+
+#[derive(Subcommand)]
+pub enum HeaderCommands {
+    /// List the header profiles and the rules applying them
+    List,
+    /// Force a header profile's headers on the requests sent to a host, or
+    /// every host
+    Use {
+        /// Profile name, ex: iphone, android, desktop or googlebot
+        profile: String,
+        /// Only apply the profile to this host, or `*.` followed by a domain
+        /// for its subdomains (default: every host)
+        #[arg(long)]
+        host: Option<String>,
+    },
+    /// Stop forcing headers on the requests sent to a host, or every host
+    Clear {
+        /// Host the profile was applied to (default: every host)
+        #[arg(long)]
+        host: Option<String>,
+    },
+}
+
+pub struct HeaderHandler {
+    remote: Option<ApiClient>,
+}
+
+impl HeaderHandler {
+    pub fn new(remote: Option<ApiClient>) -> Self {
+        Self { remote }
+    }
+
+    pub async fn handle(self, command: &HeaderCommands) -> Result<()> {
+        let client = match self.remote {
+            Some(remote) => remote,
+            None => ApiClient::from_auth_store()?.ok_or_else(|| {
+                anyhow::anyhow!("Not authenticated. Run 'witm auth login' or pass --remote.")
+            })?,
+        };
+
+        match command {
+            HeaderCommands::List => {
+                let resp =
+                    ApiClient::check(client.get("/api/manage/header-overrides/profiles").await?)
+                        .await?;
+                let profiles: Vec<HeaderProfile> = resp.json().await?;
+                println!("Profiles:");
+                for profile in &profiles {
+                    let mut headers: Vec<String> = profile.headers.keys().cloned().collect();
+                    if profile.accept_language.is_some() {
+                        headers.insert(0, "Accept-Language".to_string());
+                    }
+                    if profile.user_agent.is_some() {
+                        headers.insert(0, "User-Agent".to_string());
+                    }
+                    println!("  {} ({})", profile.name, headers.join(", "));
+                }
+
+                let resp =
+                    ApiClient::check(client.get("/api/manage/header-overrides").await?).await?;
+                let overrides: HeaderOverridesConfig = resp.json().await?;
+                if overrides.rules.is_empty() {
+                    println!("No header overrides.");
+                    return Ok(());
+                }
+                println!("Rules:");
+                for rule in &overrides.rules {
+                    println!(
+                        "  {} -> {}",
+                        rule.host.as_deref().unwrap_or("every host"),
+                        rule.profile
+                    );
+                }
+            }
+            HeaderCommands::Use { profile, host } => {
+                let selection = serde_json::json!({ "host": host, "profile": profile });
+                ApiClient::check(
+                    client
+                        .put_json("/api/manage/header-overrides/selection", &selection)
+                        .await?,
+                )
+                .await?;
+                println!(
+                    "Forcing the {} profile on requests to {}",
+                    profile,
+                    host.as_deref().unwrap_or("every host")
+                );
+            }
+            HeaderCommands::Clear { host } => {
+                let selection = serde_json::json!({ "host": host, "profile": null });
+                ApiClient::check(
+                    client
+                        .put_json("/api/manage/header-overrides/selection", &selection)
+                        .await?,
+                )
+                .await?;
+                println!(
+                    "Cleared the header profile for {}",
+                    host.as_deref().unwrap_or("every host")
+                );
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+//! `witm init`: a first-run walkthrough that generates the CA, optionally
+//! trusts it and routes the system's traffic through the proxy, picks which
+//! connections are intercepted and adds starter plugins, then writes the
+//! answers to the config file once they load as a valid configuration.
+
+use super::plugin::{PluginCommands, PluginHandler};
+use crate::{
+    AppConfig, CertificateAuthority,
+    config::{confique_app_config_layer::AppConfigLayer, expand_home_in_path},
+    proxy::listener::MitmPolicy,
+};
+use anyhow::{Context, Result};
+use confique::{Config, Layer};
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+/// Port suggested for the proxy when the system is routed through it
+const DEFAULT_PROXY_PORT: u16 = 8080;
+
+/// Reads answers to the walkthrough's questions, one line each. With
+/// `defaults` set, every question takes its default without reading.
+pub struct Prompter<R> {
+    input: R,
+    defaults: bool,
+}
+
+impl<R: BufRead> Prompter<R> {
+    pub fn new(input: R, defaults: bool) -> Self {
+        Self { input, defaults }
+    }
+
+    /// The trimmed answer to `question`, or `None` when left blank
+    fn ask(&mut self, question: &str) -> Result<Option<String>> {
+        print!("{} ", question);
+        std::io::stdout().flush()?;
+        if self.defaults {
+            println!();
+            return Ok(None);
+        }
+        let mut line = String::new();
+        self.input.read_line(&mut line)?;
+        let answer = line.trim();
+        Ok((!answer.is_empty()).then(|| answer.to_string()))
+    }
+
+    pub fn confirm(&mut self, question: &str, default: bool) -> Result<bool> {
+        let hint = if default { "[Y/n]" } else { "[y/N]" };
+        loop {
+            match self.ask(&format!("{} {}", question, hint))? {
+                None => return Ok(default),
+                Some(answer) => match answer.to_lowercase().as_str() {
+                    "y" | "yes" => return Ok(true),
+                    "n" | "no" => return Ok(false),
+                    _ => println!("Please answer y or n"),
+                },
+            }
+        }
+    }
+
+    /// The index of the option chosen from `options`, numbered from 1
+    pub fn choose(&mut self, question: &str, options: &[&str], default: usize) -> Result<usize> {
+        println!("{}", question);
+        for (i, option) in options.iter().enumerate() {
+            println!("  {}) {}", i + 1, option);
+        }
+        loop {
+            match self.ask(&format!("Choice [{}]:", default + 1))? {
+                None => return Ok(default),
+                Some(answer) => match answer.parse::<usize>() {
+                    Ok(n) if (1..=options.len()).contains(&n) => return Ok(n - 1),
+                    _ => println!("Please enter a number from 1 to {}", options.len()),
+                },
+            }
+        }
+    }
+
+    /// A port, `default` when left blank
+    pub fn port(&mut self, question: &str, default: u16) -> Result<u16> {
+        loop {
+            match self.ask(&format!("{} [{}]:", question, default))? {
+                None => return Ok(default),
+                Some(answer) => match answer.parse::<u16>() {
+                    Ok(port) if port != 0 => return Ok(port),
+                    _ => println!("Please enter a port from 1 to 65535"),
+                },
+            }
+        }
+    }
+
+    /// Whitespace-separated words, none when left blank
+    pub fn words(&mut self, question: &str) -> Result<Vec<String>> {
+        Ok(self
+            .ask(question)?
+            .map(|answer| answer.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default())
+    }
+}
+
+pub struct InitHandler {
+    config_path: PathBuf,
+    verbose: bool,
+}
+
+impl InitHandler {
+    pub fn new(config_path: PathBuf, verbose: bool) -> Self {
+        Self {
+            config_path,
+            verbose,
+        }
+    }
+
+    pub async fn run(&self, defaults: bool) -> Result<()> {
+        let stdin = std::io::stdin();
+        let mut prompter = Prompter::new(stdin.lock(), defaults);
+
+        if self.config_path.exists() {
+            println!(
+                "Updating the configuration at {:?}; settings not asked about are kept",
+                self.config_path
+            );
+        } else {
+            println!("Creating a configuration at {:?}", self.config_path);
+        }
+        let existing = self.load(AppConfigLayer::empty())?;
+        let mut layer = AppConfigLayer::empty();
+
+        // Certificate authority
+        let cert_dir = expand_home_in_path(&existing.tls.cert_dir)?;
+        std::fs::create_dir_all(&cert_dir)
+            .with_context(|| format!("Failed to create {:?}", cert_dir))?;
+        let ca = CertificateAuthority::new(&cert_dir).await?;
+        println!("\nCertificate authority ready in {:?}", cert_dir);
+        if prompter.confirm(
+            "Install its root certificate into the system trust store (may ask for your password)?",
+            false,
+        )? {
+            ca.install_root_certificate(true, false).await?;
+        }
+
+        // What gets intercepted
+        let current = match existing.proxy.mitm {
+            MitmPolicy::Always => 1,
+            _ => 0,
+        };
+        let choice = prompter.choose(
+            "\nWhich HTTPS connections should be intercepted?",
+            &[
+                "Allowlist: only hosts your plugins and rules ask for",
+                "Everything: every HTTPS connection through the proxy",
+            ],
+            current,
+        )?;
+        layer.proxy.mitm = Some(if choice == 1 {
+            MitmPolicy::Always
+        } else {
+            MitmPolicy::Auto
+        });
+
+        // System proxy, which needs a port known before the proxy runs
+        let route_system = prompter.confirm(
+            "\nRoute this machine's HTTP(S) traffic through witmproxy while it runs?",
+            false,
+        )?;
+        if route_system {
+            let port = prompter.port("Port for the proxy to listen on", DEFAULT_PROXY_PORT)?;
+            layer.proxy.proxy_bind_addr = Some(format!("127.0.0.1:{}", port));
+        }
+
+        let plugins = prompter.words(
+            "\nStarter plugins to add from the registry, separated by spaces (e.g. @ezco/noop), blank for none:",
+        )?;
+
+        // Only write answers that load as a valid configuration
+        let config = self.load(layer)?;
+        config
+            .clone()
+            .with_resolved_paths()
+            .context("The configuration is invalid")?;
+        if let Some(parent) = self.config_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        config
+            .save(&self.config_path)
+            .context("Failed to save configuration")?;
+        println!("\nConfiguration saved to {:?}", self.config_path);
+
+        if !plugins.is_empty() {
+            let handler = PluginHandler::new(config.with_resolved_paths()?, self.verbose);
+            for source in plugins {
+                let command = PluginCommands::Add {
+                    source: source.clone(),
+                    public_key: None,
+                };
+                match handler.handle(&command).await {
+                    Ok(()) => println!("Added plugin {}", source),
+                    Err(e) => eprintln!("Failed to add plugin {}: {}", source, e),
+                }
+            }
+        }
+
+        println!("\nStart witmproxy with:");
+        if route_system {
+            println!("  witm start --auto   # sets the system proxy while it runs");
+        } else {
+            println!("  witm start");
+        }
+        Ok(())
+    }
+
+    /// The configuration from `layer` over the config file (when it exists)
+    /// and defaults, without the environment so it isn't written to the file
+    fn load(&self, layer: AppConfigLayer) -> Result<AppConfig> {
+        let mut builder = AppConfig::builder().preloaded(layer);
+        if self.config_path.exists() {
+            builder = builder.file(&self.config_path);
+        }
+        builder
+            .load()
+            .with_context(|| format!("Failed to load configuration from {:?}", self.config_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_fall_back_to_defaults_and_reprompt_when_invalid() {
+        let input = "\nmaybe\ny\n7\n2\n0\n9090\n@ezco/noop ./local.wasm\n";
+        let mut prompter = Prompter::new(input.as_bytes(), false);
+        assert!(!prompter.confirm("Trust?", false).unwrap());
+        assert!(prompter.confirm("Route?", false).unwrap());
+        assert_eq!(prompter.choose("Policy?", &["a", "b"], 0).unwrap(), 1);
+        assert_eq!(prompter.port("Port", DEFAULT_PROXY_PORT).unwrap(), 9090);
+        assert_eq!(
+            prompter.words("Plugins:").unwrap(),
+            ["@ezco/noop", "./local.wasm"]
+        );
+
+        let mut defaults = Prompter::new("y\n".as_bytes(), true);
+        assert!(defaults.confirm("Route?", true).unwrap());
+        assert_eq!(defaults.port("Port", 8080).unwrap(), 8080);
+        assert!(defaults.words("Plugins:").unwrap().is_empty());
+    }
+}
@@ -1,17 +1,33 @@
 use crate::{
     AppConfig, CertificateAuthority, WitmProxy,
     config::{confique_app_config_layer::AppConfigLayer, expand_home_in_path},
-    db::Db,
-    plugins::registry::PluginRegistry,
+    db::{
+        Db,
+        audit::{self, AuditAction, AuditEntry},
+        retention::{self, RetentionPolicy},
+    },
+    http::jwt::KeySets,
+    plugins::{
+        inference::Models, integrity::TrustedKeys, redaction::Redactions, registry::PluginRegistry,
+        storage_snapshots::StorageSnapshots,
+    },
     proxy::tenant_resolver,
     wasm::Runtime,
 };
 use auth::AuthCommands;
+use cel::CelCommands;
+use db::DbCommands;
 use group::GroupCommands;
+use headers::HeaderCommands;
 use plugin::PluginCommands;
+use profile::ProfileCommands;
+use protobuf::ProtobufCommands;
 use proxy::ProxyCommands;
+use schema::SchemaCommands;
 use service::ServiceCommands;
+use session::SessionCommands;
 use tenant::TenantCommands;
+use token::TokenCommands;
 use trust::CaCommands;
 
 use anyhow::Result;
@@ -25,12 +41,22 @@ use tracing::{error, info, warn};
 
 pub mod api_client;
 pub mod auth;
+mod cel;
+mod db;
+mod doctor;
 pub mod group;
+mod headers;
+mod init;
 mod plugin;
+pub mod profile;
+mod protobuf;
 mod proxy;
+mod schema;
 pub mod service;
+mod session;
 mod tailscale;
 pub mod tenant;
+mod token;
 mod trust;
 pub mod update;
 
@@ -58,6 +84,38 @@ pub struct Cli {
     /// Enable verbose logging
     #[arg(short, long)]
     verbose: bool,
+
+    #[command(flatten)]
+    remote: RemoteOptions,
+}
+
+/// Options for managing a running instance through its web API instead of
+/// the local database (used by the `plugin` and `ca` commands)
+#[derive(Args, Clone, Default)]
+pub struct RemoteOptions {
+    /// URL of a running witmproxy web server to manage (e.g. https://host:port)
+    #[arg(long, global = true, value_name = "URL")]
+    pub remote: Option<String>,
+
+    /// Bearer token for --remote: an API token from `witm token create`, or a JWT
+    #[arg(long, global = true, requires = "remote")]
+    pub token: Option<String>,
+
+    /// PEM certificate to trust for --remote, e.g. the instance's CA downloaded from /cert
+    #[arg(long, global = true, value_name = "PATH", requires = "remote")]
+    pub remote_ca: Option<PathBuf>,
+}
+
+impl RemoteOptions {
+    /// API client for the remote instance, if `--remote` was given
+    pub fn client(&self) -> Result<Option<api_client::ApiClient>> {
+        self.remote
+            .as_deref()
+            .map(|url| {
+                api_client::ApiClient::remote(url, self.token.as_deref(), self.remote_ca.as_deref())
+            })
+            .transpose()
+    }
 }
 
 /// Shared options for commands that start or configure the proxy server
@@ -84,6 +142,8 @@ pub struct ResolvedCli {
     plugin_dir: Option<PathBuf>,
     auto: bool,
     detach: bool,
+    /// Keep the CA and database in memory and write nothing to disk
+    ephemeral: bool,
 }
 
 #[derive(Subcommand)]
@@ -105,6 +165,16 @@ enum Commands {
     ///
     /// Stops the running witmproxy daemon service.
     Stop,
+    /// Walk through first-time setup and write the config file
+    ///
+    /// Generates the CA, optionally trusts it and routes the system's traffic
+    /// through the proxy, chooses which connections are intercepted and adds
+    /// starter plugins. Run again to change the answers.
+    Init {
+        /// Take the default answer to every question
+        #[arg(long)]
+        defaults: bool,
+    },
     /// Run the proxy server directly in the foreground (no daemon)
     ///
     /// This starts the web and proxy servers directly in the current terminal.
@@ -113,6 +183,11 @@ enum Commands {
     Run {
         #[command(flatten)]
         options: ProxyRunOptions,
+
+        /// Keep all state in memory: a throwaway CA and database, and nothing
+        /// written to disk, for CI tests and demos
+        #[arg(long, conflicts_with = "auto")]
+        ephemeral: bool,
     },
     /// Run the proxy server in daemon mode (internal, called by service manager)
     #[command(hide = true)]
@@ -129,11 +204,22 @@ enum Commands {
         #[command(subcommand)]
         command: PluginCommands,
     },
+    /// Test capability scope expressions
+    Cel {
+        #[command(subcommand)]
+        command: CelCommands,
+    },
     /// Certificate authority management commands
     Ca {
         #[command(subcommand)]
         command: CaCommands,
     },
+    /// Configuration profiles (work, personal, pentest...) layered over the
+    /// config file
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommands,
+    },
     /// System proxy management commands
     Proxy {
         #[command(subcommand)]
@@ -146,6 +232,17 @@ enum Commands {
     },
     /// Show the status of the witmproxy service (alias for `service status`)
     Status,
+    /// Check CA trust, ports, the system proxy, the database, plugin
+    /// signatures, connectivity and the clock, suggesting fixes
+    Doctor {
+        /// Print the report as JSON, e.g. to attach to a bug report
+        #[arg(long)]
+        json: bool,
+        /// URL to check connectivity and clock skew against (default: the
+        /// plugin registry)
+        #[arg(long)]
+        upstream: Option<String>,
+    },
     /// Show the daemon log file (alias for `service logs`)
     Logs {
         /// Follow the log output (like tail -f)
@@ -170,6 +267,36 @@ enum Commands {
         #[command(subcommand)]
         command: GroupCommands,
     },
+    /// API token management commands (local)
+    Token {
+        #[command(subcommand)]
+        command: TokenCommands,
+    },
+    /// Database maintenance commands (local)
+    Db {
+        #[command(subcommand)]
+        command: DbCommands,
+    },
+    /// API schemas inferred from traffic (local)
+    Schema {
+        #[command(subcommand)]
+        command: SchemaCommands,
+    },
+    /// Protobuf descriptor sets for decoding bodies (local)
+    Protobuf {
+        #[command(subcommand)]
+        command: ProtobufCommands,
+    },
+    /// Capture sessions grouping and exporting flows (remote)
+    Session {
+        #[command(subcommand)]
+        command: SessionCommands,
+    },
+    /// Header profiles forced on requests sent upstream (remote)
+    Headers {
+        #[command(subcommand)]
+        command: HeaderCommands,
+    },
     /// Check for updates and update the CLI binary
     Update {
         /// Force update even if already on the latest version
@@ -238,6 +365,7 @@ impl Cli {
 
         let config_path = expand_home_in_path(&self.config_path)?;
         let verbose = self.verbose;
+        let remote = self.remote.client()?;
 
         match self.command {
             Commands::Start { options, detach } => {
@@ -256,9 +384,17 @@ impl Cli {
                 Self::show_update_warning(check).await;
                 result
             }
-            Commands::Run { options } => {
-                let resolved =
+            Commands::Init { defaults } => {
+                init::InitHandler::new(config_path, verbose)
+                    .run(defaults)
+                    .await
+            }
+            Commands::Run { options, ephemeral } => {
+                let mut resolved =
                     ResolvedCli::from_proxy_options(options, &config_path, verbose, false)?;
+                if ephemeral {
+                    resolved = resolved.ephemeral();
+                }
                 let check = Self::maybe_spawn_update_check(&resolved.config);
                 let result = resolved.run_foreground().await;
                 Self::show_update_warning(check).await;
@@ -270,7 +406,11 @@ impl Cli {
                 resolved.run_serve(log_file).await
             }
             Commands::Service { command } => match command {
-                ServiceCommands::Install { options, yes } => {
+                ServiceCommands::Install {
+                    options,
+                    service,
+                    yes,
+                } => {
                     let ProxyRunOptions {
                         config: layer,
                         plugin_dir,
@@ -284,7 +424,9 @@ impl Cli {
                     let service_handler =
                         service::ServiceHandler::new(resolved_config, verbose, plugin_dir, *auto);
                     let check = Self::maybe_spawn_update_check(&service_handler.config);
-                    let result = service_handler.install_service(layer.clone(), yes).await;
+                    let result = service_handler
+                        .install_service(layer.clone(), &service, yes)
+                        .await;
                     Self::show_update_warning(check).await;
                     result
                 }
@@ -302,12 +444,15 @@ impl Cli {
                 let config = Self::load_config(&config_path)?;
                 let check = Self::maybe_spawn_update_check(&config);
                 let service_handler = service::ServiceHandler::new(config, verbose, None, false);
-                let result = service_handler
-                    .handle(&ServiceCommands::Status)
-                    .await;
+                let result = service_handler.handle(&ServiceCommands::Status).await;
                 Self::show_update_warning(check).await;
                 result
             }
+            Commands::Doctor { json, upstream } => {
+                let config = Self::load_config(&config_path)?;
+                let doctor_handler = doctor::DoctorHandler::new(config, config_path.clone());
+                doctor_handler.run(upstream.as_deref(), json).await
+            }
             Commands::Logs { follow, lines } => {
                 let config = Self::load_config(&config_path)?;
                 let check = Self::maybe_spawn_update_check(&config);
@@ -321,19 +466,30 @@ impl Cli {
             Commands::Plugin { command } => {
                 let config = Self::load_config(&config_path)?;
                 let check = Self::maybe_spawn_update_check(&config);
-                let plugin_handler = plugin::PluginHandler::new(config, verbose);
+                let plugin_handler =
+                    plugin::PluginHandler::new(config, verbose).with_remote(remote);
                 let result = plugin_handler.handle(&command).await;
                 Self::show_update_warning(check).await;
                 result
             }
+            Commands::Cel { command } => {
+                let cel_handler = cel::CelHandler::new().with_remote(remote);
+                cel_handler.handle(&command).await
+            }
             Commands::Ca { command } => {
                 let config = Self::load_config(&config_path)?;
                 let check = Self::maybe_spawn_update_check(&config);
-                let ca_handler = trust::CaHandler::new(config);
+                let ca_handler = trust::CaHandler::new(config).with_remote(remote);
                 let result = ca_handler.handle(&command).await;
                 Self::show_update_warning(check).await;
                 result
             }
+            Commands::Profile { command } => {
+                let config = Self::load_config(&config_path)?;
+                let profile_handler =
+                    profile::ProfileHandler::new(config_path.clone(), config.profile);
+                profile_handler.handle(&command).await
+            }
             Commands::Proxy { command } => {
                 let config = Self::load_config(&config_path)?;
                 let check = Self::maybe_spawn_update_check(&config);
@@ -366,6 +522,54 @@ impl Cli {
                 Self::show_update_warning(check).await;
                 result
             }
+            Commands::Token { command } => {
+                let config = Self::load_config(&config_path)?;
+                let check = Self::maybe_spawn_update_check(&config);
+                let token_handler = token::TokenHandler::new(config);
+                let result = token_handler.handle(&command).await;
+                Self::show_update_warning(check).await;
+                result
+            }
+            Commands::Db { command } => {
+                let config = Self::load_config(&config_path)?;
+                let check = Self::maybe_spawn_update_check(&config);
+                let db_handler = db::DbHandler::new(config);
+                let result = db_handler.handle(&command).await;
+                Self::show_update_warning(check).await;
+                result
+            }
+            Commands::Schema { command } => {
+                let config = Self::load_config(&config_path)?;
+                let check = Self::maybe_spawn_update_check(&config);
+                let schema_handler = schema::SchemaHandler::new(config);
+                let result = schema_handler.handle(&command).await;
+                Self::show_update_warning(check).await;
+                result
+            }
+            Commands::Protobuf { command } => {
+                let config = Self::load_config(&config_path)?;
+                let check = Self::maybe_spawn_update_check(&config);
+                let protobuf_handler = protobuf::ProtobufHandler::new(config);
+                let result = protobuf_handler.handle(&command).await;
+                Self::show_update_warning(check).await;
+                result
+            }
+            Commands::Session { command } => {
+                let config = Self::load_config(&config_path)?;
+                let check = Self::maybe_spawn_update_check(&config);
+                let session_handler = session::SessionHandler::new(remote);
+                let result = session_handler.handle(&command).await;
+                Self::show_update_warning(check).await;
+                result
+            }
+            Commands::Headers { command } => {
+                let config = Self::load_config(&config_path)?;
+                let check = Self::maybe_spawn_update_check(&config);
+                let header_handler = headers::HeaderHandler::new(remote);
+                let result = header_handler.handle(&command).await;
+                Self::show_update_warning(check).await;
+                result
+            }
             Commands::Update { force, from_source } => {
                 let config = Self::load_config(&config_path)?;
                 let handler = update::UpdateHandler::new(config);
@@ -452,14 +656,21 @@ impl Cli {
         Self::resolve_config(AppConfigLayer::default_values(), config_path)
     }
 
-    /// Resolve configuration with the given CLI layer
+    /// Resolve configuration with the given CLI layer, and the profile it
+    /// selects layered over the config file
     fn resolve_config(layer: AppConfigLayer, config_path: &std::path::Path) -> Result<AppConfig> {
-        AppConfig::builder()
-            .preloaded(layer)
-            .env()
-            .file(config_path)
-            .load()?
-            .with_resolved_paths()
+        let profile = profile::selected(config_path, layer.profile.as_deref());
+        let mut builder = AppConfig::builder().preloaded(layer).env();
+        if let Some(name) = &profile {
+            for file in profile::files(config_path, name)? {
+                builder = builder.file(file);
+            }
+        }
+        let mut config = builder.file(config_path).load()?.with_resolved_paths()?;
+        config.profile = profile;
+        // Attribute audit entries written by this process to this instance
+        audit::set_instance(config.telemetry.instance_id());
+        Ok(config)
     }
 
     /// Spawn a background update check if enabled
@@ -511,9 +722,17 @@ impl ResolvedCli {
             plugin_dir,
             auto: options.auto,
             detach,
+            ephemeral: false,
         })
     }
 
+    /// Run with an in-memory CA and database, writing nothing to disk
+    fn ephemeral(mut self) -> Self {
+        self.config.db.db_path = PathBuf::from(crate::db::IN_MEMORY_PATH);
+        self.ephemeral = true;
+        self
+    }
+
     /// Install/restart the daemon service and optionally attach to logs
     async fn run_start(&self) -> Result<()> {
         let service_handler = service::ServiceHandler::new(
@@ -622,6 +841,11 @@ impl ResolvedCli {
 
     /// Internal proxy run method (used by both run_proxy and run_serve)
     async fn run_proxy_internal(&self) -> Result<()> {
+        match &self.config.profile {
+            Some(profile) => info!("Using configuration profile {}", profile),
+            None => info!("Using the configuration file without a profile"),
+        }
+
         // Create app directory based on the resolved cert_dir parent
         let app_dir = self
             .config
@@ -630,7 +854,9 @@ impl ResolvedCli {
             .parent()
             .unwrap_or(&PathBuf::from("."))
             .to_path_buf();
-        std::fs::create_dir_all(&app_dir)?;
+        if !self.ephemeral {
+            std::fs::create_dir_all(&app_dir)?;
+        }
 
         info!("Loaded proxy configuration");
 
@@ -646,8 +872,12 @@ impl ResolvedCli {
             };
 
         // Create certificate authority using pre-resolved cert_dir
-        std::fs::create_dir_all(&self.config.tls.cert_dir)?;
-        let ca = CertificateAuthority::new(self.config.tls.cert_dir.clone()).await?;
+        let ca = if self.ephemeral {
+            CertificateAuthority::in_memory().await?
+        } else {
+            std::fs::create_dir_all(&self.config.tls.cert_dir)?;
+            CertificateAuthority::new(self.config.tls.cert_dir.clone()).await?
+        };
         info!("Certificate Authority initialized");
 
         // Handle --auto flag: trust CA if needed
@@ -657,7 +887,9 @@ impl ResolvedCli {
         }
 
         // Initialize database using pre-resolved path
-        if let Some(parent) = self.config.db.db_path.parent() {
+        if !crate::db::is_in_memory(&self.config.db.db_path)
+            && let Some(parent) = self.config.db.db_path.parent()
+        {
             std::fs::create_dir_all(parent)?;
         }
 
@@ -734,10 +966,23 @@ impl ResolvedCli {
         // Keep a pool handle for transparent proxy tenant resolution
         let db_pool = db.pool.clone();
 
+        let retention = RetentionPolicy::from(&self.config.db);
+        if !retention.is_unlimited() && self.config.db.prune_interval_seconds > 0 {
+            let interval = std::time::Duration::from_secs(self.config.db.prune_interval_seconds);
+            tokio::spawn(retention::prune_loop(db_pool.clone(), retention, interval));
+        }
+
         // Plugin registry which will be shared across the proxy and web server
         let plugin_registry = if self.config.plugins.enabled {
             let runtime = Runtime::try_default()?;
-            let mut registry = PluginRegistry::new(db, runtime)?;
+            let mut registry = PluginRegistry::new(db, runtime)?
+                .with_key_sets(KeySets::new(self.config.plugins.jwks_urls.clone()))
+                .with_models(Models::load(&self.config.plugins.models)?)
+                .with_redactions(Redactions::new(self.config.plugins.redactions.clone())?)
+                .with_trusted_keys(TrustedKeys::new(&self.config.plugins.trusted_keys)?);
+            if self.config.plugins.snapshot_storage {
+                registry = registry.with_storage_snapshots(StorageSnapshots::enabled());
+            }
             registry.load_plugins().await?;
             info!("Number of plugins loaded: {}", registry.plugins().len());
             Some(Arc::new(RwLock::new(registry)))
@@ -759,11 +1004,14 @@ impl ResolvedCli {
             }
         }
 
-        let ca_for_proxy = CertificateAuthority::new(self.config.tls.cert_dir.clone()).await?;
-        let config_path = app_dir.join("config.toml");
-        let mut proxy = WitmProxy::new(ca_for_proxy, plugin_registry.clone(), self.config.clone())
-            .with_config_path(config_path)
+        let mut proxy = WitmProxy::new(ca.clone(), plugin_registry.clone(), self.config.clone())
             .with_db_pool(db_pool.clone());
+        // Management API changes are kept for the session only
+        if !self.ephemeral {
+            proxy = proxy
+                .with_config_path(app_dir.join("config.toml"))
+                .with_journal_dir(app_dir.join("journal"));
+        }
         proxy.start().await?;
 
         // Capture the bound addresses
@@ -781,14 +1029,104 @@ impl ResolvedCli {
         };
 
         // Write services.json to config root (app_dir)
-        let services_path = app_dir.join("services.json");
-        let services_json = serde_json::to_string_pretty(&services)?;
-        std::fs::write(&services_path, services_json)?;
-        info!("Services information written to: {:?}", services_path);
+        if !self.ephemeral {
+            let services_path = app_dir.join("services.json");
+            let services_json = serde_json::to_string_pretty(&services)?;
+            std::fs::write(&services_path, services_json)?;
+            info!("Services information written to: {:?}", services_path);
+        }
 
         // Detect Tailscale and display QR code for cert distribution
         tailscale::discover_and_display(web_addr).await;
 
+        // Start reverse proxy if enabled
+        let mut _reverse_proxy = None;
+        if self.config.reverse.enabled {
+            info!("Reverse proxy mode enabled, starting...");
+            let limits = crate::proxy::limits::FlowLimits::from(&self.config.proxy);
+            let upstream = crate::proxy::client(ca.clone(), &limits, &self.config.tls)?;
+            let pages = crate::proxy::pages::ErrorPages::from_config(&self.config.proxy)?;
+            let shutdown_notify = Arc::new(tokio::sync::Notify::new());
+            let mut rp = crate::proxy::reverse::ReverseProxy::new(
+                Arc::new(ca.clone()),
+                plugin_registry.clone(),
+                upstream,
+                self.config.reverse.clone(),
+                shutdown_notify,
+            )
+            .with_limits(limits)
+            .with_error_pages(pages)
+            .with_host_mismatch_policy(self.config.proxy.host_mismatch)
+            .with_sensitive_data(crate::proxy::findings::SensitiveData::from(
+                &self.config.proxy,
+            ));
+            if let Some(security_headers) = proxy.security_headers() {
+                rp = rp.with_security_headers(security_headers);
+            }
+            if let Some(flow_tags) = proxy.flow_tags() {
+                rp = rp.with_flow_tags(flow_tags);
+            }
+            if let Some(sessions) = proxy.sessions() {
+                rp = rp.with_sessions(sessions);
+            }
+            if let Some(host_limiter) = proxy.host_limiter() {
+                rp = rp.with_host_limiter(host_limiter);
+            }
+            if let Some(network_conditions) = proxy.network_conditions() {
+                rp = rp.with_network_conditions(network_conditions);
+            }
+            if let Some(header_overrides) = proxy.header_overrides() {
+                rp = rp.with_header_overrides(header_overrides);
+            }
+            if let Some(egress) = proxy.egress() {
+                rp = rp.with_egress(egress);
+            }
+            if let Some(block_rules) = proxy.block_rules() {
+                rp = rp.with_block_rules(block_rules);
+            }
+            if let Some(tls_policies) = proxy.tls_policies() {
+                rp = rp.with_tls_policies(tls_policies);
+            }
+            if let Some(mocks) = proxy.mocks() {
+                rp = rp.with_mocks(mocks);
+            }
+            if let Some(user_scripts) = proxy.user_scripts() {
+                rp = rp.with_user_scripts(user_scripts);
+            }
+            if let Some(images) = proxy.images() {
+                rp = rp.with_images(images);
+            }
+            if let Some(compression) = proxy.compression() {
+                rp = rp.with_compression(compression);
+            }
+            if let Some(translation) = proxy.translation() {
+                rp = rp.with_translation(translation);
+            }
+            if let Some(privacy) = proxy.privacy() {
+                rp = rp.with_privacy(privacy);
+            }
+            if let Some(schemas) = proxy
+                .api_schemas()
+                .filter(|_| self.config.proxy.infer_schemas)
+            {
+                rp = rp.with_api_schemas(schemas);
+            }
+            if let Some(traffic) = proxy
+                .traffic()
+                .filter(|_| self.config.proxy.account_traffic)
+            {
+                rp = rp.with_traffic(traffic);
+            }
+            if let Some(protobuf) = proxy.protobuf() {
+                rp = rp.with_protobuf(protobuf);
+            }
+            if let Some(traces) = proxy.flow_traces() {
+                rp = rp.with_flow_traces(traces);
+            }
+            rp.start().await?;
+            _reverse_proxy = Some(rp);
+        }
+
         // Start transparent proxy if enabled
         let mut _transparent_proxy = None;
         if self.config.transparent.enabled {
@@ -798,7 +1136,9 @@ impl ResolvedCli {
                 db_pool.clone(),
                 self.config.proxy.tenant_header.clone(),
             );
-            let upstream = crate::proxy::client(ca.clone())?;
+            let limits = crate::proxy::limits::FlowLimits::from(&self.config.proxy);
+            let upstream = crate::proxy::client(ca.clone(), &limits, &self.config.tls)?;
+            let pages = crate::proxy::pages::ErrorPages::from_config(&self.config.proxy)?;
             let shutdown_notify = Arc::new(tokio::sync::Notify::new());
             let mut tp = crate::proxy::transparent::TransparentProxy::new(
                 Arc::new(ca),
@@ -807,7 +1147,77 @@ impl ResolvedCli {
                 upstream,
                 self.config.transparent.clone(),
                 shutdown_notify,
-            );
+            )
+            .with_limits(limits)
+            .with_error_pages(pages)
+            .with_host_mismatch_policy(self.config.proxy.host_mismatch)
+            .with_sensitive_data(crate::proxy::findings::SensitiveData::from(
+                &self.config.proxy,
+            ));
+            // Share the proxy's rules, so updates from the management API apply to both
+            if let Some(security_headers) = proxy.security_headers() {
+                tp = tp.with_security_headers(security_headers);
+            }
+            if let Some(flow_tags) = proxy.flow_tags() {
+                tp = tp.with_flow_tags(flow_tags);
+            }
+            if let Some(sessions) = proxy.sessions() {
+                tp = tp.with_sessions(sessions);
+            }
+            if let Some(host_limiter) = proxy.host_limiter() {
+                tp = tp.with_host_limiter(host_limiter);
+            }
+            if let Some(network_conditions) = proxy.network_conditions() {
+                tp = tp.with_network_conditions(network_conditions);
+            }
+            if let Some(header_overrides) = proxy.header_overrides() {
+                tp = tp.with_header_overrides(header_overrides);
+            }
+            if let Some(egress) = proxy.egress() {
+                tp = tp.with_egress(egress);
+            }
+            if let Some(block_rules) = proxy.block_rules() {
+                tp = tp.with_block_rules(block_rules);
+            }
+            if let Some(tls_policies) = proxy.tls_policies() {
+                tp = tp.with_tls_policies(tls_policies);
+            }
+            if let Some(mocks) = proxy.mocks() {
+                tp = tp.with_mocks(mocks);
+            }
+            if let Some(user_scripts) = proxy.user_scripts() {
+                tp = tp.with_user_scripts(user_scripts);
+            }
+            if let Some(images) = proxy.images() {
+                tp = tp.with_images(images);
+            }
+            if let Some(compression) = proxy.compression() {
+                tp = tp.with_compression(compression);
+            }
+            if let Some(translation) = proxy.translation() {
+                tp = tp.with_translation(translation);
+            }
+            if let Some(privacy) = proxy.privacy() {
+                tp = tp.with_privacy(privacy);
+            }
+            if let Some(schemas) = proxy
+                .api_schemas()
+                .filter(|_| self.config.proxy.infer_schemas)
+            {
+                tp = tp.with_api_schemas(schemas);
+            }
+            if let Some(traffic) = proxy
+                .traffic()
+                .filter(|_| self.config.proxy.account_traffic)
+            {
+                tp = tp.with_traffic(traffic);
+            }
+            if let Some(protobuf) = proxy.protobuf() {
+                tp = tp.with_protobuf(protobuf);
+            }
+            if let Some(traces) = proxy.flow_traces() {
+                tp = tp.with_flow_traces(traces);
+            }
             tp.start().await?;
             info!(
                 "Transparent proxy listening on {}",
@@ -816,15 +1226,22 @@ impl ResolvedCli {
             _transparent_proxy = Some(tp);
         }
 
+        // Roll back system proxy settings left behind by a crashed session
+        let proxy_handler = proxy::ProxyHandler::new(self.config.clone());
+        if !self.ephemeral
+            && let Err(e) = proxy_handler.recover_stale_snapshot().await
+        {
+            warn!("Failed to restore stale system proxy settings: {}", e);
+        }
+
         // Handle --auto flag: enable system proxy
         if self.auto {
             info!("Auto mode: enabling system proxy");
-            let proxy_handler = proxy::ProxyHandler::new(self.config.clone());
             proxy_handler.enable_proxy_internal(false).await?;
         }
 
         // Spawn auto-update loop if enabled
-        if self.config.update.auto_update {
+        if self.config.update.auto_update && !self.ephemeral {
             let update_config = self.config.clone();
             let interval = self.config.update.check_interval_seconds;
             tokio::spawn(async move {
@@ -847,14 +1264,15 @@ impl ResolvedCli {
         };
 
         // Continue running the proxy
-        proxy.join().await?;
+        let join_result = proxy.join().await;
 
-        // Handle --auto flag: disable system proxy on shutdown
+        // Handle --auto flag: restore system proxy on shutdown, even if the
+        // proxy exited with an error
         if self.auto {
             info!("Auto mode: disabling system proxy on shutdown");
-            let proxy_handler = proxy::ProxyHandler::new(self.config.clone());
             proxy_handler.disable_proxy_internal(false).await?;
         }
+        join_result?;
 
         proxy.shutdown().await;
 
@@ -898,9 +1316,30 @@ async fn load_plugin_from_file(
     let plugin = registry.plugin_from_component(component_bytes).await?;
     let plugin_id = plugin.id();
     registry.register_plugin(plugin).await?;
+    audit_plugin_dir(&registry, AuditAction::PluginInstall, &plugin_id, path).await;
     Ok(plugin_id)
 }
 
+/// Record a plugin (un)loaded by the plugin directory watcher in the audit log
+async fn audit_plugin_dir(
+    registry: &PluginRegistry,
+    action: AuditAction,
+    plugin_id: &str,
+    path: &std::path::Path,
+) {
+    if let Err(e) = AuditEntry::record(
+        &registry.db.pool,
+        "system:plugin-dir",
+        action,
+        Some(plugin_id),
+        serde_json::json!({ "path": path.display().to_string() }),
+    )
+    .await
+    {
+        warn!("Failed to record audit entry for {}: {}", plugin_id, e);
+    }
+}
+
 /// Set up a file watcher for the plugin directory
 fn setup_plugin_dir_watcher(
     plugin_dir: PathBuf,
@@ -1035,6 +1474,13 @@ async fn handle_plugin_file_event(
                             Ok(removed) => {
                                 if !removed.is_empty() {
                                     info!("Removed plugin: {}", plugin_id);
+                                    audit_plugin_dir(
+                                        &reg,
+                                        AuditAction::PluginRemove,
+                                        &plugin_id,
+                                        &path,
+                                    )
+                                    .await;
                                 }
                             }
                             Err(e) => {
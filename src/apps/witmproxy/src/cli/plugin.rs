@@ -1,8 +1,18 @@
 use super::Services;
+use super::api_client::ApiClient;
 use crate::cert::ca::get_root_cert_path;
+use crate::db::audit::{AuditAction, AuditEntry, cli_actor};
+use crate::plugins::dry_run::FlowFixture;
+use crate::plugins::exercise::{self, Verdict};
+use crate::plugins::integrity::TrustedKeys;
+use crate::plugins::posture::{EventPosture, PluginPosture};
+use crate::plugins::settings;
+use crate::proxy::flows::FlowRecord;
+use crate::wasm::bindgen::witmproxy::plugin::capabilities::EventKind;
 use crate::{AppConfig, db::Db, plugins::registry::PluginRegistry, wasm::Runtime};
 use anyhow::Result;
 use clap::Subcommand;
+use serde::Deserialize;
 use std::path::{Path, PathBuf};
 use tracing::{debug, info, warn};
 
@@ -23,7 +33,8 @@ pub enum PluginCommands {
     },
     /// Add a plugin from a local path or URL
     Add {
-        /// Local .wasm file path or URL (https://...)
+        /// Local .wasm file path, URL (https://...), or registry plugin
+        /// (@namespace/name, optionally @namespace/name@version)
         source: String,
         /// Path to a trusted public key file to verify the plugin was signed
         /// by a known author (not just self-signed)
@@ -43,6 +54,37 @@ pub enum PluginCommands {
         #[arg(short, long = "set", value_name = "KEY=VALUE")]
         set_values: Vec<String>,
     },
+    /// Replay captured flows through a plugin without installing it,
+    /// reporting what it would change or block
+    Exercise {
+        /// The plugin's .wasm component
+        plugin: PathBuf,
+        /// Flows to replay: a JSON file holding a flow fixture or a list of
+        /// them or, with --remote, filters on the flows the instance recently
+        /// captured (ex: "host=example.com,limit=20", or "all")
+        #[arg(long)]
+        flows: String,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show what installed plugins can see and do: each plugin's
+    /// capabilities with their scopes and quotas or, with --event, the
+    /// plugins given events of a kind. How often capabilities were used
+    /// recently is only known to a running instance, with --remote.
+    Audit {
+        /// Event kind to list the plugins given (connect, request, response,
+        /// inbound_content, timer, raw_stream, mqtt_message)
+        #[arg(long, value_parser = super::cel::parse_event_kind)]
+        event: Option<EventKind>,
+        /// Only list plugins whose scope matches a GET request to this host,
+        /// ex: "www.bank.com"
+        #[arg(long, requires = "event")]
+        host: Option<String>,
+        /// Print the audit as JSON
+        #[arg(long)]
+        json: bool,
+    },
 }
 
 /// Plugin command handler that contains the resolved configuration and verbose flag
@@ -50,14 +92,29 @@ pub struct PluginHandler {
     pub config: AppConfig,
     #[cfg_attr(not(feature = "plugin-new"), allow(dead_code))]
     pub verbose: bool,
+    /// When set, commands go through this instance's web API instead of the local DB
+    pub remote: Option<ApiClient>,
 }
 
 impl PluginHandler {
     pub fn new(config: AppConfig, verbose: bool) -> Self {
-        Self { config, verbose }
+        Self {
+            config,
+            verbose,
+            remote: None,
+        }
+    }
+
+    pub fn with_remote(mut self, remote: Option<ApiClient>) -> Self {
+        self.remote = remote;
+        self
     }
 
     pub async fn handle(&self, command: &PluginCommands) -> Result<()> {
+        if let Some(ref remote) = self.remote {
+            return self.handle_remote(remote, command).await;
+        }
+
         match command {
             PluginCommands::List => self.list_plugins().await,
             PluginCommands::New {
@@ -73,9 +130,137 @@ impl PluginHandler {
                 plugin_name,
                 set_values,
             } => self.configure_plugin(plugin_name, set_values).await,
+            PluginCommands::Exercise {
+                plugin,
+                flows,
+                json,
+            } => self.exercise_plugin(plugin, flows, *json).await,
+            PluginCommands::Audit { event, host, json } => {
+                self.audit_plugins(*event, host.as_deref(), *json).await
+            }
         }
     }
 
+    /// Print what the plugins installed in the local DB can see and do
+    async fn audit_plugins(
+        &self,
+        event: Option<EventKind>,
+        host: Option<&str>,
+        json: bool,
+    ) -> Result<()> {
+        let db = Db::from_path(self.config.db.db_path.clone(), &self.config.db.db_password).await?;
+        db.migrate().await?;
+        let mut registry = PluginRegistry::new(db, Runtime::try_default()?)?;
+        registry.load_plugins().await?;
+        match event {
+            Some(kind) => print_event_audit(&registry.event_posture(kind, host)?, json),
+            None => print_capability_audit(&registry.posture(), json),
+        }
+    }
+
+    /// Replay the flows `selection` picks through the plugin at `path`,
+    /// loaded into a throwaway registry with the capabilities it requests
+    async fn exercise_plugin(&self, path: &Path, selection: &str, json: bool) -> Result<()> {
+        let flows = self.select_flows(selection).await?;
+        if flows.is_empty() {
+            anyhow::bail!("No flows match {}", selection);
+        }
+
+        let component_bytes = std::fs::read(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read plugin {:?}: {}", path, e))?;
+        let db = Db::in_memory().await?;
+        db.migrate().await?;
+        let mut registry = PluginRegistry::new(db, Runtime::try_default()?)?;
+        let plugin = registry.plugin_from_component(component_bytes).await?;
+        let plugin_id = plugin.id();
+        registry.register_plugin(plugin).await?;
+
+        let mut reports = Vec::with_capacity(flows.len());
+        for flow in &flows {
+            reports.push(exercise::exercise(&registry, flow).await?);
+        }
+        if json {
+            println!("{}", serde_json::to_string_pretty(&reports)?);
+            return Ok(());
+        }
+
+        println!(
+            "Replayed {} flow(s) through {}:\n",
+            reports.len(),
+            plugin_id
+        );
+        for report in &reports {
+            println!("  {}", report.flow);
+            for (kind, verdict) in &report.events {
+                match verdict {
+                    Verdict::Skipped => println!("    {}: skipped (out of scope)", kind),
+                    Verdict::Unchanged => println!("    {}: unchanged", kind),
+                    Verdict::Changed { changes } => {
+                        println!("    {}: changed", kind);
+                        for change in changes {
+                            println!("      - {}", change);
+                        }
+                    }
+                    Verdict::Answered { status } => {
+                        println!("    {}: answered by the plugin with {}", kind, status)
+                    }
+                    Verdict::Blocked { plugin_id } => {
+                        println!("    {}: blocked by {}", kind, plugin_id)
+                    }
+                    Verdict::Failed { error } => println!("    {}: failed: {}", kind, error),
+                }
+            }
+            println!();
+        }
+        Ok(())
+    }
+
+    /// The flows picked by `selection`: a fixture file, or filters on the
+    /// remote instance's recent flows
+    async fn select_flows(&self, selection: &str) -> Result<Vec<FlowFixture>> {
+        let path = Path::new(selection);
+        if path.is_file() {
+            let json = std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read flows {:?}: {}", path, e))?;
+            return Ok(match serde_json::from_str::<Vec<FlowFixture>>(&json) {
+                Ok(flows) => flows,
+                Err(_) => vec![serde_json::from_str::<FlowFixture>(&json)?],
+            });
+        }
+
+        let remote = self.remote.as_ref().ok_or_else(|| {
+            anyhow::anyhow!(
+                "{} is not a file; to replay flows captured by a running instance, pass --remote",
+                selection
+            )
+        })?;
+        let mut filters = Vec::new();
+        if selection != "all" {
+            for filter in selection.split(',') {
+                let (key, value) = filter.split_once('=').ok_or_else(|| {
+                    anyhow::anyhow!("Invalid filter '{}': expected key=value", filter)
+                })?;
+                if !FLOW_FILTERS.contains(&key) {
+                    anyhow::bail!(
+                        "Unknown filter '{}', expected one of: {}",
+                        key,
+                        FLOW_FILTERS.join(", ")
+                    );
+                }
+                filters.push((key, value));
+            }
+        }
+        let request = remote
+            .request(reqwest::Method::GET, "/api/manage/flows")
+            .await
+            .query(&filters);
+        let flows: Vec<FlowRecord> = ApiClient::check(request.send().await?)
+            .await?
+            .json()
+            .await?;
+        Ok(flows.iter().map(FlowFixture::from).collect())
+    }
+
     /// Try to read the web server URL from services.json
     fn get_web_url(&self) -> Option<String> {
         let app_dir = self
@@ -348,6 +533,80 @@ impl PluginHandler {
         );
     }
 
+    /// Fetch WASM bytes for `source`, along with the public key the plugin
+    /// must be signed with: the key at `public_key_path` if given, otherwise
+    /// the publisher's key for registry plugins.
+    async fn resolve_plugin(
+        &self,
+        source: &str,
+        public_key_path: Option<&Path>,
+    ) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+        let (component_bytes, publisher_key) = match parse_registry_source(source) {
+            Some((namespace, name, version)) => {
+                let (bytes, key) = self
+                    .download_from_registry(namespace, name, version)
+                    .await?;
+                (bytes, Some(key))
+            }
+            None => (self.read_wasm_source(source).await?, None),
+        };
+
+        let expected_key = match public_key_path {
+            Some(path) => Some(
+                std::fs::read(path)
+                    .map_err(|e| anyhow::anyhow!("Failed to read public key {:?}: {}", path, e))?,
+            ),
+            None => publisher_key,
+        };
+        Ok((component_bytes, expected_key))
+    }
+
+    /// Download a verified plugin component from the registry, returning it
+    /// with its publisher's public key.
+    async fn download_from_registry(
+        &self,
+        namespace: &str,
+        name: &str,
+        version: &str,
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        let url = format!(
+            "{}/api/v1/plugins/{}/{}/{}/download",
+            self.config.plugins.registry_url.trim_end_matches('/'),
+            namespace,
+            name,
+            version
+        );
+        eprintln!(
+            "Downloading {}/{}@{} from the registry...",
+            namespace, name, version
+        );
+        let client = reqwest::Client::builder()
+            .user_agent("witmproxy")
+            .redirect(reqwest::redirect::Policy::limited(10))
+            .build()?;
+        let resp = client.get(&url).send().await?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            anyhow::bail!(
+                "Plugin {}/{}@{} not found in the registry",
+                namespace,
+                name,
+                version
+            );
+        }
+        if !resp.status().is_success() {
+            anyhow::bail!("Download failed: HTTP {}", resp.status());
+        }
+        let key = resp
+            .headers()
+            .get("x-publisher-key")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|hex_str| hex::decode(hex_str).ok())
+            .ok_or_else(|| anyhow::anyhow!("Registry response has no publisher key"))?;
+        let bytes = resp.bytes().await?.to_vec();
+        eprintln!("Downloaded {} bytes.", bytes.len());
+        Ok((bytes, key))
+    }
+
     /// Fetch WASM bytes from a URL or local file path.
     async fn read_wasm_source(&self, source: &str) -> Result<Vec<u8>> {
         if source.starts_with("https://") || source.starts_with("http://") {
@@ -380,17 +639,7 @@ impl PluginHandler {
     }
 
     async fn add_plugin(&self, source: &str, public_key_path: Option<&Path>) -> Result<()> {
-        let component_bytes = self.read_wasm_source(source).await?;
-
-        // Read expected public key if provided
-        let expected_key = match public_key_path {
-            Some(path) => {
-                let key_bytes = std::fs::read(path)
-                    .map_err(|e| anyhow::anyhow!("Failed to read public key {:?}: {}", path, e))?;
-                Some(key_bytes)
-            }
-            None => None,
-        };
+        let (component_bytes, expected_key) = self.resolve_plugin(source, public_key_path).await?;
 
         // Try the web API first (daemon may be running)
         match self
@@ -410,7 +659,8 @@ impl PluginHandler {
 
         // Create runtime and registry
         let runtime = Runtime::try_default()?;
-        let mut registry = PluginRegistry::new(db, runtime)?;
+        let mut registry = PluginRegistry::new(db, runtime)?
+            .with_trusted_keys(TrustedKeys::new(&self.config.plugins.trusted_keys)?);
 
         // Create plugin from component bytes (including signature verification)
         let mut plugin = registry
@@ -427,9 +677,35 @@ impl PluginHandler {
             plugin.namespace, plugin.name, plugin.version
         );
 
+        let plugin_id = plugin.id();
+        let version = plugin.version.clone();
+        let granted: Vec<String> = plugin
+            .capabilities
+            .iter()
+            .map(|c| c.inner.kind.to_string())
+            .collect();
+
         // Register the plugin
         registry.register_plugin(plugin).await?;
 
+        let actor = cli_actor();
+        AuditEntry::record(
+            &registry.db.pool,
+            &actor,
+            AuditAction::PluginInstall,
+            Some(&plugin_id),
+            serde_json::json!({ "source": source, "version": version }),
+        )
+        .await?;
+        AuditEntry::record(
+            &registry.db.pool,
+            &actor,
+            AuditAction::CapabilityGrant,
+            Some(&plugin_id),
+            serde_json::json!({ "capabilities": granted }),
+        )
+        .await?;
+
         info!("Plugin successfully added from {}", source);
         Ok(())
     }
@@ -464,29 +740,50 @@ impl PluginHandler {
                 }
             }
         } else {
-            // Set configuration values
+            // Parse values into the types the plugin declares, before storing any
+            let runtime = Runtime::try_default()?;
+            let mut registry = PluginRegistry::new(db, runtime)?;
+            registry.load_plugins().await?;
+            let plugin_id = format!("{}/{}", namespace, name);
+            let schema = &registry
+                .plugins()
+                .get(&plugin_id)
+                .ok_or_else(|| anyhow::anyhow!("Plugin {} is not installed", plugin_id))?
+                .configuration_schema;
+            let mut inputs = Vec::with_capacity(set_values.len());
             for kv in set_values {
                 let (key, value) = kv.split_once('=').ok_or_else(|| {
                     anyhow::anyhow!("Invalid format '{}': expected key=value", kv)
                 })?;
+                inputs.push((key, value, settings::check_input(schema, key, value)?));
+            }
 
-                // Store as a JSON-serialized ActualInput::Str by default
-                let value_json = serde_json::to_string(&crate::wasm::bindgen::ActualInput::Str(
-                    value.to_string(),
-                ))?;
-
+            for (key, value, input) in inputs {
                 sqlx::query(
                     "INSERT OR REPLACE INTO plugin_configuration (namespace, name, input_name, input_value) VALUES (?, ?, ?, ?)",
                 )
                 .bind(&namespace)
                 .bind(&name)
                 .bind(key)
-                .bind(&value_json)
-                .execute(&db.pool)
+                .bind(serde_json::to_string(&input)?)
+                .execute(&registry.db.pool)
                 .await?;
 
                 info!("Set {}/{} config: {} = {}", namespace, name, key, value);
             }
+
+            let keys: Vec<&str> = set_values
+                .iter()
+                .filter_map(|kv| kv.split_once('=').map(|(k, _)| k))
+                .collect();
+            AuditEntry::record(
+                &registry.db.pool,
+                &cli_actor(),
+                AuditAction::PluginConfigure,
+                Some(&format!("{}/{}", namespace, name)),
+                serde_json::json!({ "inputs": keys }),
+            )
+            .await?;
             println!(
                 "Configuration updated for {}/{}. Restart the daemon for changes to take effect.",
                 namespace, name
@@ -518,7 +815,278 @@ impl PluginHandler {
         let runtime = Runtime::try_default()?;
         let mut registry = PluginRegistry::new(db, runtime)?;
 
-        registry.remove_plugin(&name, namespace.as_deref()).await?;
+        let removed = registry.remove_plugin(&name, namespace.as_deref()).await?;
+        for plugin_id in &removed {
+            AuditEntry::record(
+                &registry.db.pool,
+                &cli_actor(),
+                AuditAction::PluginRemove,
+                Some(plugin_id),
+                serde_json::json!({}),
+            )
+            .await?;
+        }
         Ok(())
     }
 }
+
+// ---------------------------------------------------------------------------
+// Remote management (--remote)
+// ---------------------------------------------------------------------------
+
+#[derive(Deserialize)]
+struct RemotePlugin {
+    namespace: String,
+    name: String,
+    version: String,
+    author: String,
+    description: String,
+    license: String,
+    url: String,
+    enabled: bool,
+    capabilities: Vec<RemoteCapability>,
+}
+
+#[derive(Deserialize)]
+struct RemoteCapability {
+    kind: String,
+    granted: bool,
+}
+
+/// Split `namespace/name` (namespace defaults to `default`)
+/// Parse a registry plugin reference, `@namespace/name[@version]`, into its
+/// namespace, name and version (`latest` when omitted).
+fn parse_registry_source(source: &str) -> Option<(&str, &str, &str)> {
+    if !source.starts_with('@') {
+        return None;
+    }
+    let (namespace, rest) = source.split_once('/')?;
+    let (name, version) = rest.split_once('@').unwrap_or((rest, "latest"));
+    if namespace.len() < 2 || name.is_empty() || version.is_empty() || name.contains('/') {
+        return None;
+    }
+    Some((namespace, name, version))
+}
+
+/// Filters `plugin exercise --flows` accepts on a remote instance's flows
+const FLOW_FILTERS: &[&str] = &[
+    "host",
+    "annotation",
+    "since_millis",
+    "until_millis",
+    "limit",
+];
+
+fn split_plugin_id(plugin_name: &str) -> (&str, &str) {
+    plugin_name
+        .split_once('/')
+        .unwrap_or(("default", plugin_name))
+}
+
+impl PluginHandler {
+    async fn handle_remote(&self, remote: &ApiClient, command: &PluginCommands) -> Result<()> {
+        match command {
+            PluginCommands::List => {
+                let resp = ApiClient::check(remote.get("/api/plugins").await?).await?;
+                let mut plugins: Vec<RemotePlugin> = resp.json().await?;
+                plugins.sort_by(|a, b| (&a.namespace, &a.name).cmp(&(&b.namespace, &b.name)));
+
+                if plugins.is_empty() {
+                    println!("No plugins installed on {}.", remote.base_url());
+                    return Ok(());
+                }
+
+                println!("Installed plugins on {}:\n", remote.base_url());
+                for p in &plugins {
+                    println!("  {}/{} v{}", p.namespace, p.name, p.version);
+                    if !p.description.is_empty() {
+                        println!("    {}", p.description);
+                    }
+                    if !p.author.is_empty() {
+                        println!("    Author:  {}", p.author);
+                    }
+                    if !p.license.is_empty() {
+                        println!("    License: {}", p.license);
+                    }
+                    if !p.url.is_empty() {
+                        println!("    URL:     {}", p.url);
+                    }
+                    println!("    Enabled: {}", if p.enabled { "yes" } else { "no" });
+                    if !p.capabilities.is_empty() {
+                        let caps: Vec<String> = p
+                            .capabilities
+                            .iter()
+                            .map(|c| {
+                                if c.granted {
+                                    c.kind.clone()
+                                } else {
+                                    format!("{} (denied)", c.kind)
+                                }
+                            })
+                            .collect();
+                        println!("    Capabilities: {}", caps.join(", "));
+                    }
+                    println!();
+                }
+                println!("{} plugin(s) installed.", plugins.len());
+                Ok(())
+            }
+            PluginCommands::New {
+                plugin_name,
+                language,
+                dest,
+            } => self.create_new_plugin(plugin_name, language, dest).await,
+            PluginCommands::Add { source, public_key } => {
+                let (wasm_bytes, expected_key) =
+                    self.resolve_plugin(source, public_key.as_deref()).await?;
+                let part = reqwest::multipart::Part::bytes(wasm_bytes)
+                    .file_name("plugin.wasm")
+                    .mime_str("application/wasm")?;
+                let form = reqwest::multipart::Form::new().part("file", part);
+
+                let mut request = remote
+                    .request(reqwest::Method::POST, "/api/plugins")
+                    .await
+                    .multipart(form);
+                if let Some(key) = expected_key {
+                    request = request.header("X-Expected-Public-Key", hex::encode(key));
+                }
+                ApiClient::check(request.send().await?).await?;
+                info!("Plugin added to {} from {}", remote.base_url(), source);
+                Ok(())
+            }
+            PluginCommands::Remove { plugin_name } => {
+                let (namespace, name) = split_plugin_id(plugin_name);
+                let path = format!("/api/plugins/{}/{}", namespace, name);
+                ApiClient::check(remote.delete(&path).await?).await?;
+                info!(
+                    "Plugin {}/{} removed from {}",
+                    namespace,
+                    name,
+                    remote.base_url()
+                );
+                Ok(())
+            }
+            PluginCommands::Configure {
+                plugin_name,
+                set_values,
+            } => {
+                let (namespace, name) = split_plugin_id(plugin_name);
+                let path = format!("/api/plugins/{}/{}/config", namespace, name);
+
+                if set_values.is_empty() {
+                    let resp = ApiClient::check(remote.get(&path).await?).await?;
+                    let config: std::collections::BTreeMap<String, String> = resp.json().await?;
+                    if config.is_empty() {
+                        println!("No configuration set for plugin {}/{}.", namespace, name);
+                    } else {
+                        println!("Configuration for {}/{}:", namespace, name);
+                        for (input_name, input_value) in config {
+                            println!("  {} = {}", input_name, input_value);
+                        }
+                    }
+                    return Ok(());
+                }
+
+                let mut config = std::collections::HashMap::new();
+                for kv in set_values {
+                    let (key, value) = kv.split_once('=').ok_or_else(|| {
+                        anyhow::anyhow!("Invalid format '{}': expected key=value", kv)
+                    })?;
+                    config.insert(key.to_string(), value.to_string());
+                }
+                let body = serde_json::json!({ "config": config });
+                ApiClient::check(remote.put_json(&path, &body).await?).await?;
+                println!("Configuration updated for {}/{}.", namespace, name);
+                Ok(())
+            }
+            PluginCommands::Exercise {
+                plugin,
+                flows,
+                json,
+            } => self.exercise_plugin(plugin, flows, *json).await,
+            PluginCommands::Audit { event, host, json } => match event {
+                Some(kind) => {
+                    let mut path = format!("/api/manage/capability-audit/{}", kind);
+                    if let Some(host) = host {
+                        path.push_str(&format!("?host={}", host));
+                    }
+                    let resp = ApiClient::check(remote.get(&path).await?).await?;
+                    print_event_audit(&resp.json().await?, *json)
+                }
+                None => {
+                    let resp =
+                        ApiClient::check(remote.get("/api/manage/capability-audit").await?).await?;
+                    print_capability_audit(&resp.json().await?, *json)
+                }
+            },
+        }
+    }
+}
+
+fn print_capability_audit(plugins: &[PluginPosture], json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(plugins)?);
+        return Ok(());
+    }
+    if plugins.is_empty() {
+        println!("No plugins installed.");
+        return Ok(());
+    }
+    for plugin in plugins {
+        let disabled = if plugin.enabled { "" } else { " (disabled)" };
+        println!("  {} v{}{}", plugin.plugin, plugin.version, disabled);
+        for cap in &plugin.capabilities {
+            let state = match (cap.granted, cap.effective) {
+                (false, _) => "denied",
+                (true, true) => "in effect",
+                (true, false) => "granted, not in effect",
+            };
+            println!(
+                "    {}: {}, {} use(s) in the last hour",
+                cap.kind, state, cap.recent_uses
+            );
+            println!("      scope: {}", cap.scope);
+            if !cap.quota.is_unlimited() {
+                println!("      quota: {}", serde_json::to_string(&cap.quota)?);
+            }
+        }
+        println!();
+    }
+    Ok(())
+}
+
+fn print_event_audit(posture: &EventPosture, json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(posture)?);
+        return Ok(());
+    }
+    let target = match &posture.host {
+        Some(host) => format!("{} events for {}", posture.event, host),
+        None => format!("{} events", posture.event),
+    };
+    if posture.plugins.is_empty() {
+        println!("No plugins are given {}.", target);
+        return Ok(());
+    }
+    println!("Plugins given {}:\n", target);
+    for access in &posture.plugins {
+        let inactive = if access.active {
+            ""
+        } else {
+            ", outside its active windows now"
+        };
+        println!(
+            "  {}: {} in the last hour{}",
+            access.plugin, access.recent_uses, inactive
+        );
+        println!("    scope: {}", access.scope);
+        if !access.alongside.is_empty() {
+            println!("    also holds: {}", access.alongside.join(", "));
+        }
+        if let Some(error) = &access.scope_error {
+            println!("    scope could not be evaluated: {}", error);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,250 @@
+//! Named configuration profiles, kept next to the config file as
+//! `profiles/<name>.toml`. A profile holds only the settings it changes,
+//! and is layered over the config file it sits next to, or over another
+//! profile named with `extends`:
+//!
+//! ```toml
+//! # profiles/pentest.toml
+//! extends = "work"
+//!
+//! [proxy]
+//! host_mismatch = "allow"
+//! ```
+//!
+//! The profile applied is the one given with `--profile` (or
+//! `WITMPROXY_PROFILE`), else the one chosen with `witm profile switch`, else
+//! the `profile` set in the config file, if any.
+
+use anyhow::{Context, Result, bail};
+use clap::Subcommand;
+use std::path::{Path, PathBuf};
+
+/// File in the profiles directory naming the profile switched to
+const ACTIVE_FILE: &str = "active";
+
+#[derive(Subcommand)]
+pub enum ProfileCommands {
+    /// List the profiles, marking the one applied
+    List,
+    /// Apply a profile from now on (restart the proxy to apply it to a
+    /// running instance)
+    Switch {
+        /// Profile name, as in profiles/<name>.toml
+        name: String,
+    },
+    /// Stop applying the profile switched to, going back to the config file
+    Reset,
+    /// Print the name of the profile applied
+    Current,
+}
+
+/// The directory profiles are kept in, next to `config_path`
+pub fn profiles_dir(config_path: &Path) -> PathBuf {
+    config_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join("profiles")
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        bail!(
+            "Invalid profile name {:?}: use letters, digits, '-' and '_'",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// The profile switched to with `witm profile switch`, if any
+pub fn switched(config_path: &Path) -> Option<String> {
+    std::fs::read_to_string(profiles_dir(config_path).join(ACTIVE_FILE))
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// Make `name` the profile applied when none is given with `--profile`
+pub fn switch(config_path: &Path, name: &str) -> Result<()> {
+    files(config_path, name)?;
+    let dir = profiles_dir(config_path);
+    std::fs::write(dir.join(ACTIVE_FILE), format!("{}\n", name))
+        .with_context(|| format!("Failed to write {:?}", dir.join(ACTIVE_FILE)))
+}
+
+/// The profile applied: `requested` with `--profile` or from the
+/// environment, else the one switched to, else the config file's
+pub fn selected(config_path: &Path, requested: Option<&str>) -> Option<String> {
+    requested
+        .map(str::to_string)
+        .or_else(|| std::env::var("WITMPROXY_PROFILE").ok())
+        .filter(|name| !name.is_empty())
+        .or_else(|| switched(config_path))
+        .or_else(|| {
+            let content = std::fs::read_to_string(config_path).ok()?;
+            let table: toml::Table = toml::from_str(&content).ok()?;
+            table.get("profile")?.as_str().map(str::to_string)
+        })
+}
+
+/// The files of profile `name` and those it extends, most specific first,
+/// so they can be layered over the config file in that order
+pub fn files(config_path: &Path, name: &str) -> Result<Vec<PathBuf>> {
+    let dir = profiles_dir(config_path);
+    let mut files: Vec<PathBuf> = Vec::new();
+    let mut next = Some(name.to_string());
+    while let Some(name) = next {
+        validate_name(&name)?;
+        let path = dir.join(format!("{}.toml", name));
+        if files.contains(&path) {
+            bail!("Profile {} extends itself", name);
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Profile {} not found at {:?}", name, path))?;
+        let table: toml::Table = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse profile {:?}", path))?;
+        next = match table.get("extends") {
+            Some(toml::Value::String(parent)) => Some(parent.clone()),
+            Some(_) => bail!("`extends` in profile {} must be a profile name", name),
+            None => None,
+        };
+        files.push(path);
+    }
+    Ok(files)
+}
+
+/// Names of the profiles in the profiles directory, sorted
+pub fn list(config_path: &Path) -> Result<Vec<String>> {
+    let dir = profiles_dir(config_path);
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", dir)),
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.extension()? != "toml" {
+                return None;
+            }
+            path.file_stem()?.to_str().map(str::to_string)
+        })
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+pub struct ProfileHandler {
+    config_path: PathBuf,
+    /// The profile applied to this invocation
+    current: Option<String>,
+}
+
+impl ProfileHandler {
+    pub fn new(config_path: PathBuf, current: Option<String>) -> Self {
+        Self {
+            config_path,
+            current,
+        }
+    }
+
+    pub async fn handle(&self, command: &ProfileCommands) -> Result<()> {
+        match command {
+            ProfileCommands::List => {
+                let names = list(&self.config_path)?;
+                if names.is_empty() {
+                    println!("No profiles in {:?}", profiles_dir(&self.config_path));
+                }
+                for name in names {
+                    let marker = if self.current.as_deref() == Some(name.as_str()) {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    println!("{} {}", marker, name);
+                }
+                Ok(())
+            }
+            ProfileCommands::Switch { name } => {
+                switch(&self.config_path, name)?;
+                println!("Switched to profile {}", name);
+                println!("Restart witmproxy (witm start) to apply it to a running instance");
+                Ok(())
+            }
+            ProfileCommands::Reset => {
+                let active = profiles_dir(&self.config_path).join(ACTIVE_FILE);
+                if active.exists() {
+                    std::fs::remove_file(&active)
+                        .with_context(|| format!("Failed to remove {:?}", active))?;
+                }
+                println!("Using the configuration file without a switched profile");
+                Ok(())
+            }
+            ProfileCommands::Current => {
+                match &self.current {
+                    Some(name) => println!("{}", name),
+                    None => println!("(no profile)"),
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_profile(config_path: &Path, name: &str, content: &str) {
+        let dir = profiles_dir(config_path);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(format!("{}.toml", name)), content).unwrap();
+    }
+
+    #[test]
+    fn profiles_are_layered_over_those_they_extend() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.toml");
+        write_profile(&config_path, "work", "[proxy]\nhost_mismatch = \"block\"\n");
+        write_profile(&config_path, "pentest", "extends = \"work\"\n");
+
+        let files = files(&config_path, "pentest").unwrap();
+        let names: Vec<_> = files
+            .iter()
+            .map(|f| f.file_stem().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, ["pentest", "work"]);
+        assert_eq!(list(&config_path).unwrap(), ["pentest", "work"]);
+    }
+
+    #[test]
+    fn extending_in_a_loop_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.toml");
+        write_profile(&config_path, "a", "extends = \"b\"\n");
+        write_profile(&config_path, "b", "extends = \"a\"\n");
+        assert!(files(&config_path, "a").is_err());
+        assert!(files(&config_path, "../a").is_err());
+    }
+
+    #[test]
+    fn switched_profile_is_applied_unless_one_is_requested() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.toml");
+        write_profile(&config_path, "work", "");
+        write_profile(&config_path, "personal", "");
+
+        assert!(switch(&config_path, "missing").is_err());
+        switch(&config_path, "work").unwrap();
+        assert_eq!(switched(&config_path).as_deref(), Some("work"));
+        assert_eq!(
+            selected(&config_path, Some("personal")).as_deref(),
+            Some("personal")
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::{Result, bail};
+use clap::Subcommand;
+use std::path::PathBuf;
+
+use crate::config::AppConfig;
+use crate::db::Db;
+use crate::db::protobuf_descriptors::StoredDescriptorSet;
+use crate::proxy::protobuf::DescriptorSet;
+
+#[derive(Subcommand)]
+pub enum ProtobufCommands {
+    /// List the uploaded descriptor sets
+    List,
+    /// Add a descriptor set, as written by `protoc --descriptor_set_out`
+    ///
+    /// The running proxy loads descriptor sets at startup; to add one without
+    /// restarting, upload it with `PUT /api/manage/protobuf/descriptors/{name}`.
+    Add {
+        /// Name to store the descriptor set as
+        name: String,
+        /// Path to the serialized `FileDescriptorSet`
+        file: PathBuf,
+    },
+    /// Remove a descriptor set
+    Remove {
+        /// Name the descriptor set is stored as
+        name: String,
+    },
+}
+
+pub struct ProtobufHandler {
+    config: AppConfig,
+}
+
+impl ProtobufHandler {
+    pub fn new(config: AppConfig) -> Self {
+        Self { config }
+    }
+
+    pub async fn handle(&self, command: &ProtobufCommands) -> Result<()> {
+        let db = Db::from_path(self.config.db.db_path.clone(), &self.config.db.db_password).await?;
+        db.migrate().await?;
+
+        match command {
+            ProtobufCommands::List => {
+                let sets = StoredDescriptorSet::list(&db.pool).await?;
+                if sets.is_empty() {
+                    println!("No descriptor sets uploaded.");
+                    return Ok(());
+                }
+
+                println!("Descriptor sets:\n");
+                for stored in sets {
+                    let summary = DescriptorSet::new(stored.name, stored.descriptor)?.summary();
+                    println!(
+                        "  {} ({} files, {} messages; added {})",
+                        summary.name,
+                        summary.files,
+                        summary.messages.len(),
+                        stored.created_at
+                    );
+                }
+            }
+            ProtobufCommands::Add { name, file } => {
+                let set = DescriptorSet::new(name.clone(), std::fs::read(file)?)?;
+                StoredDescriptorSet::upsert(&db.pool, name, set.bytes()).await?;
+                let summary = set.summary();
+                println!(
+                    "Added descriptor set {} ({} messages)",
+                    name,
+                    summary.messages.len()
+                );
+            }
+            ProtobufCommands::Remove { name } => {
+                if !StoredDescriptorSet::delete(&db.pool, name).await? {
+                    bail!("No descriptor set named {}", name);
+                }
+                println!("Removed descriptor set {}", name);
+            }
+        }
+        Ok(())
+    }
+}
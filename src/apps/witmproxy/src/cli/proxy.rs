@@ -2,12 +2,21 @@ use super::Services;
 use crate::config::AppConfig;
 use anyhow::Result;
 use clap::Subcommand;
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use std::process::Command;
 #[cfg(target_os = "macos")]
 use tracing::error;
 use tracing::{info, warn};
 
+/// Name of the file (in the app directory) holding the system proxy settings
+/// captured before witmproxy reconfigured them.
+pub const RESTORE_FILE_NAME: &str = "proxy_restore.json";
+
+#[cfg(target_os = "windows")]
+const INTERNET_SETTINGS_KEY: &str =
+    "HKCU\\Software\\Microsoft\\Windows\\CurrentVersion\\Internet Settings";
+
 #[derive(Subcommand)]
 pub enum ProxyCommands {
     /// Enable system HTTP proxy to route through witmproxy
@@ -22,10 +31,115 @@ pub enum ProxyCommands {
         #[arg(short = 'n', long)]
         dry_run: bool,
     },
+    /// Restore the system proxy settings saved by the last `proxy enable`
+    Restore {
+        /// Show what would be done without actually doing it
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
     /// Show current proxy status
     Status,
 }
 
+/// System proxy settings captured before witmproxy took over, persisted to
+/// [`RESTORE_FILE_NAME`] so they can be rolled back after a shutdown or crash.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProxySnapshot {
+    /// The witmproxy URL that was applied on top of these settings
+    pub proxy_url: String,
+    /// When the snapshot was taken
+    pub captured_at: chrono::DateTime<chrono::Utc>,
+    /// What took the snapshot; manual for snapshots saved before it was
+    /// recorded
+    #[serde(default)]
+    pub mode: SnapshotMode,
+    /// The original platform-specific settings
+    pub settings: SystemProxySettings,
+}
+
+/// What enabled the system proxy, taking a [ProxySnapshot]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotMode {
+    /// `witm proxy enable`, kept until `witm proxy restore` or `disable`
+    #[default]
+    Manual,
+    /// `witm proxy start --auto`, restored as the session shuts down, or as
+    /// the next one starts if it crashed
+    Auto,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "platform", rename_all = "snake_case")]
+pub enum SystemProxySettings {
+    Macos {
+        /// Per network service (interface) proxy settings
+        services: Vec<MacosServiceProxy>,
+    },
+    Linux {
+        mode: String,
+        http_host: String,
+        http_port: String,
+        https_host: String,
+        https_port: String,
+    },
+    Windows {
+        wininet: WinInetSettings,
+        winhttp: WinHttpSettings,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MacosServiceProxy {
+    pub service: String,
+    pub web: MacosProxyEntry,
+    pub secure_web: MacosProxyEntry,
+}
+
+/// A single `networksetup -get[secure]webproxy` entry
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MacosProxyEntry {
+    pub enabled: bool,
+    pub server: String,
+    pub port: String,
+}
+
+/// Per-user WinINET settings (`HKCU\...\Internet Settings`)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WinInetSettings {
+    pub proxy_enable: bool,
+    pub proxy_server: Option<String>,
+    pub proxy_override: Option<String>,
+    pub auto_config_url: Option<String>,
+}
+
+/// Machine-wide WinHTTP settings (`netsh winhttp show proxy`)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WinHttpSettings {
+    /// `None` means direct access
+    pub proxy_server: Option<String>,
+    pub bypass_list: Option<String>,
+}
+
+impl ProxySnapshot {
+    /// Load a snapshot from disk, returning `None` if no restore file exists
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(path)?;
+        Ok(Some(serde_json::from_str(&content)?))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
 pub struct ProxyHandler {
     config: AppConfig,
 }
@@ -39,6 +153,7 @@ impl ProxyHandler {
         match command {
             ProxyCommands::Enable { dry_run } => self.enable_proxy(*dry_run).await,
             ProxyCommands::Disable { dry_run } => self.disable_proxy(*dry_run).await,
+            ProxyCommands::Restore { dry_run } => self.restore_proxy(*dry_run).await,
             ProxyCommands::Status => self.show_proxy_status().await,
         }
     }
@@ -47,6 +162,10 @@ impl ProxyHandler {
         let proxy_url = self.get_proxy_url().await?;
 
         if dry_run {
+            println!(
+                "Would save current system proxy settings to {:?}",
+                self.restore_path()
+            );
             println!("Would enable system proxy with URL: {}", proxy_url);
             return Ok(());
         }
@@ -58,8 +177,10 @@ impl ProxyHandler {
         warn!("This action requires administrator privileges and may prompt for your password.");
 
         info!("Enabling system proxy: {}", proxy_url);
-        self.set_system_proxy(&proxy_url, true).await?;
+        self.apply_with_rollback(&proxy_url, SnapshotMode::Manual)
+            .await?;
         println!("System proxy enabled: {}", proxy_url);
+        println!("Previous settings saved, run `witm proxy restore` to roll back if needed");
         Ok(())
     }
 
@@ -73,8 +194,37 @@ impl ProxyHandler {
         warn!("This action requires administrator privileges and may prompt for your password.");
 
         info!("Disabling system proxy");
-        self.set_system_proxy("", false).await?;
-        println!("System proxy disabled");
+        if self.restore_snapshot().await? {
+            println!("System proxy restored to previous settings");
+        } else {
+            self.set_system_proxy("", false).await?;
+            println!("System proxy disabled");
+        }
+        Ok(())
+    }
+
+    async fn restore_proxy(&self, dry_run: bool) -> Result<()> {
+        let restore_path = self.restore_path();
+        let Some(snapshot) = ProxySnapshot::load(&restore_path)? else {
+            println!("No saved proxy settings found at {:?}", restore_path);
+            return Ok(());
+        };
+
+        if dry_run {
+            println!(
+                "Would restore system proxy settings captured at {}:",
+                snapshot.captured_at
+            );
+            println!("{}", serde_json::to_string_pretty(&snapshot.settings)?);
+            return Ok(());
+        }
+
+        warn!("This action requires administrator privileges and may prompt for your password.");
+        self.restore_snapshot().await?;
+        println!(
+            "System proxy settings restored (captured at {})",
+            snapshot.captured_at
+        );
         Ok(())
     }
 
@@ -88,7 +238,8 @@ impl ProxyHandler {
         }
 
         info!("Enabling system proxy: {}", proxy_url);
-        self.set_system_proxy(&proxy_url, true).await?;
+        self.apply_with_rollback(&proxy_url, SnapshotMode::Auto)
+            .await?;
         info!("System proxy enabled: {}", proxy_url);
         Ok(())
     }
@@ -101,11 +252,90 @@ impl ProxyHandler {
         }
 
         info!("Disabling system proxy");
-        self.set_system_proxy("", false).await?;
+        if !self.restore_snapshot().await? {
+            self.set_system_proxy("", false).await?;
+        }
         info!("System proxy disabled");
         Ok(())
     }
 
+    /// Roll back settings left behind by a previous `--auto` session that
+    /// did not shut down cleanly. The saved witmproxy address is stale at this
+    /// point, so the system proxy would otherwise point at a dead listener.
+    /// Snapshots of `witm proxy enable` are left for the user to restore.
+    pub async fn recover_stale_snapshot(&self) -> Result<bool> {
+        let restore_path = self.restore_path();
+        let Some(snapshot) = ProxySnapshot::load(&restore_path)? else {
+            return Ok(false);
+        };
+        if snapshot.mode != SnapshotMode::Auto {
+            info!(
+                "Keeping proxy settings saved by `witm proxy enable` at {:?}, run `witm proxy restore` to roll back",
+                restore_path
+            );
+            return Ok(false);
+        }
+
+        warn!(
+            "Found stale proxy restore file at {:?}, previous session did not shut down cleanly; restoring system proxy settings",
+            restore_path
+        );
+        self.restore_snapshot().await
+    }
+
+    /// Snapshot the current settings (unless a snapshot already exists), then
+    /// apply the witmproxy settings, rolling back if applying them fails.
+    async fn apply_with_rollback(&self, proxy_url: &str, mode: SnapshotMode) -> Result<()> {
+        let restore_path = self.restore_path();
+        match ProxySnapshot::load(&restore_path)? {
+            Some(existing) => {
+                // Keep the original snapshot, otherwise we would record our own
+                // settings as the ones to restore.
+                info!(
+                    "Keeping existing proxy snapshot captured at {}",
+                    existing.captured_at
+                );
+            }
+            None => {
+                let snapshot = ProxySnapshot {
+                    proxy_url: proxy_url.to_string(),
+                    captured_at: chrono::Utc::now(),
+                    mode,
+                    settings: self.capture_system_proxy().await?,
+                };
+                snapshot.save(&restore_path)?;
+                info!("Saved current system proxy settings to {:?}", restore_path);
+            }
+        }
+
+        if let Err(e) = self.set_system_proxy(proxy_url, true).await {
+            warn!("Failed to enable system proxy, rolling back: {}", e);
+            if let Err(rollback_err) = self.restore_snapshot().await {
+                warn!(
+                    "Failed to roll back system proxy settings: {}",
+                    rollback_err
+                );
+            }
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Restore the persisted snapshot (if any) and remove the restore file.
+    /// Returns `false` when there was nothing to restore.
+    async fn restore_snapshot(&self) -> Result<bool> {
+        let restore_path = self.restore_path();
+        let Some(snapshot) = ProxySnapshot::load(&restore_path)? else {
+            return Ok(false);
+        };
+
+        self.apply_system_proxy_settings(&snapshot.settings).await?;
+        std::fs::remove_file(&restore_path)?;
+        info!("Restored system proxy settings from {:?}", restore_path);
+        Ok(true)
+    }
+
     async fn show_proxy_status(&self) -> Result<()> {
         match self.get_current_system_proxy().await {
             Ok(Some(proxy)) => {
@@ -125,20 +355,33 @@ impl ProxyHandler {
                 println!("Proxy status unknown");
             }
         }
+
+        if let Ok(Some(snapshot)) = ProxySnapshot::load(&self.restore_path()) {
+            println!(
+                "Saved settings from {} can be restored with `witm proxy restore`",
+                snapshot.captured_at
+            );
+        }
         Ok(())
     }
 
-    async fn get_proxy_url(&self) -> Result<String> {
+    fn app_dir(&self) -> PathBuf {
         // Get app directory from cert_dir parent
-        let app_dir = self
-            .config
+        self.config
             .tls
             .cert_dir
             .parent()
             .unwrap_or(&PathBuf::from("."))
-            .to_path_buf();
+            .to_path_buf()
+    }
 
-        let services_path = app_dir.join("services.json");
+    fn restore_path(&self) -> PathBuf {
+        self.app_dir().join(RESTORE_FILE_NAME)
+    }
+
+    /// The running instance's proxy URL, from its services file
+    pub async fn get_proxy_url(&self) -> Result<String> {
+        let services_path = self.app_dir().join("services.json");
 
         if !services_path.exists() {
             anyhow::bail!(
@@ -179,7 +422,51 @@ impl ProxyHandler {
         }
     }
 
-    async fn get_current_system_proxy(&self) -> Result<Option<String>> {
+    async fn capture_system_proxy(&self) -> Result<SystemProxySettings> {
+        #[cfg(target_os = "macos")]
+        {
+            self.capture_macos_proxy().await
+        }
+        #[cfg(target_os = "linux")]
+        {
+            self.capture_linux_proxy().await
+        }
+        #[cfg(target_os = "windows")]
+        {
+            self.capture_windows_proxy().await
+        }
+        #[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
+        {
+            anyhow::bail!("Proxy configuration not supported on this platform")
+        }
+    }
+
+    async fn apply_system_proxy_settings(&self, settings: &SystemProxySettings) -> Result<()> {
+        match settings {
+            #[cfg(target_os = "macos")]
+            SystemProxySettings::Macos { services } => self.restore_macos_proxy(services).await,
+            #[cfg(target_os = "linux")]
+            SystemProxySettings::Linux {
+                mode,
+                http_host,
+                http_port,
+                https_host,
+                https_port,
+            } => {
+                self.restore_linux_proxy(mode, http_host, http_port, https_host, https_port)
+                    .await
+            }
+            #[cfg(target_os = "windows")]
+            SystemProxySettings::Windows { wininet, winhttp } => {
+                self.restore_windows_proxy(wininet, winhttp).await
+            }
+            #[allow(unreachable_patterns)]
+            _ => anyhow::bail!("Saved proxy settings were captured on a different platform"),
+        }
+    }
+
+    /// The proxy the system is configured to use, if any
+    pub async fn get_current_system_proxy(&self) -> Result<Option<String>> {
         #[cfg(target_os = "macos")]
         {
             self.get_macos_proxy().await
@@ -198,9 +485,10 @@ impl ProxyHandler {
         }
     }
 
+    /// Lists enabled network services (interfaces), skipping the header line
+    /// and services marked as disabled with a leading `*`.
     #[cfg(target_os = "macos")]
-    async fn set_macos_proxy(&self, proxy_url: &str, enable: bool) -> Result<()> {
-        // Get network services
+    fn macos_network_services() -> Result<Vec<String>> {
         let output = Command::new("networksetup")
             .args(["-listallnetworkservices"])
             .output()?;
@@ -210,17 +498,88 @@ impl ProxyHandler {
         }
 
         let services_output = String::from_utf8(output.stdout)?;
-        let services: Vec<&str> = services_output
+        Ok(services_output
             .lines()
             .skip(1) // Skip header line
             .filter(|line| !line.starts_with('*')) // Skip disabled services
-            .collect();
+            .map(|line| line.to_string())
+            .collect())
+    }
+
+    #[cfg(target_os = "macos")]
+    fn get_macos_proxy_entry(flag: &str, service: &str) -> Result<MacosProxyEntry> {
+        let output = Command::new("networksetup")
+            .args([flag, service])
+            .output()?;
+        if !output.status.success() {
+            anyhow::bail!("networksetup {} failed for {}", flag, service);
+        }
+        Ok(parse_networksetup_proxy(&String::from_utf8(output.stdout)?))
+    }
+
+    #[cfg(target_os = "macos")]
+    async fn capture_macos_proxy(&self) -> Result<SystemProxySettings> {
+        let mut services = Vec::new();
+        for service in Self::macos_network_services()? {
+            services.push(MacosServiceProxy {
+                web: Self::get_macos_proxy_entry("-getwebproxy", &service)?,
+                secure_web: Self::get_macos_proxy_entry("-getsecurewebproxy", &service)?,
+                service,
+            });
+        }
+        Ok(SystemProxySettings::Macos { services })
+    }
+
+    #[cfg(target_os = "macos")]
+    async fn restore_macos_proxy(&self, services: &[MacosServiceProxy]) -> Result<()> {
+        for saved in services {
+            info!("Restoring proxy for network service: {}", saved.service);
+            for (set_flag, state_flag, entry) in [
+                ("-setwebproxy", "-setwebproxystate", &saved.web),
+                (
+                    "-setsecurewebproxy",
+                    "-setsecurewebproxystate",
+                    &saved.secure_web,
+                ),
+            ] {
+                // Put back the previous server even when it was disabled, so the
+                // user's configuration is preserved exactly.
+                if !entry.server.is_empty() {
+                    let status = Command::new("sudo")
+                        .args([
+                            "networksetup",
+                            set_flag,
+                            &saved.service,
+                            &entry.server,
+                            &entry.port,
+                        ])
+                        .status()?;
+                    if !status.success() {
+                        error!("Failed to restore {} for {}", set_flag, saved.service);
+                    }
+                }
+
+                let state = if entry.enabled { "on" } else { "off" };
+                let status = Command::new("sudo")
+                    .args(["networksetup", state_flag, &saved.service, state])
+                    .status()?;
+                if !status.success() {
+                    error!("Failed to restore {} for {}", state_flag, saved.service);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "macos")]
+    async fn set_macos_proxy(&self, proxy_url: &str, enable: bool) -> Result<()> {
+        let services = Self::macos_network_services()?;
 
         if services.is_empty() {
             anyhow::bail!("No active network services found");
         }
 
-        for service in services {
+        for service in services.iter().map(String::as_str) {
             info!("Configuring proxy for network service: {}", service);
 
             if enable {
@@ -284,36 +643,17 @@ impl ProxyHandler {
 
     #[cfg(target_os = "macos")]
     async fn get_macos_proxy(&self) -> Result<Option<String>> {
-        let output = Command::new("networksetup")
-            .args(["-getwebproxy", "Wi-Fi"]) // Try Wi-Fi first
-            .output()?;
-
-        if !output.status.success() {
-            return Ok(None);
-        }
-
-        let proxy_output = String::from_utf8(output.stdout)?;
-
-        // Parse networksetup output
-        let mut enabled = false;
-        let mut server = String::new();
-        let mut port = String::new();
-
-        for line in proxy_output.lines() {
-            if line.starts_with("Enabled: Yes") {
-                enabled = true;
-            } else if line.starts_with("Server: ") {
-                server = line.strip_prefix("Server: ").unwrap_or("").to_string();
-            } else if line.starts_with("Port: ") {
-                port = line.strip_prefix("Port: ").unwrap_or("").to_string();
+        // Report the first service with a proxy enabled rather than assuming Wi-Fi
+        for service in Self::macos_network_services()? {
+            let Ok(entry) = Self::get_macos_proxy_entry("-getwebproxy", &service) else {
+                continue;
+            };
+            if entry.enabled && !entry.server.is_empty() {
+                return Ok(Some(format!("http://{}:{}", entry.server, entry.port)));
             }
         }
 
-        if enabled && !server.is_empty() {
-            Ok(Some(format!("http://{}:{}", server, port)))
-        } else {
-            Ok(None)
-        }
+        Ok(None)
     }
 
     #[cfg(target_os = "linux")]
@@ -365,6 +705,55 @@ impl ProxyHandler {
         Ok(())
     }
 
+    #[cfg(target_os = "linux")]
+    fn gsettings_get(schema: &str, key: &str) -> Result<String> {
+        let output = Self::gsettings_cmd().args(["get", schema, key]).output()?;
+        if !output.status.success() {
+            anyhow::bail!("gsettings get {} {} failed", schema, key);
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .trim_matches('\'')
+            .to_string())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn capture_linux_proxy(&self) -> Result<SystemProxySettings> {
+        Ok(SystemProxySettings::Linux {
+            mode: Self::gsettings_get("org.gnome.system.proxy", "mode")?,
+            http_host: Self::gsettings_get("org.gnome.system.proxy.http", "host")?,
+            http_port: Self::gsettings_get("org.gnome.system.proxy.http", "port")?,
+            https_host: Self::gsettings_get("org.gnome.system.proxy.https", "host")?,
+            https_port: Self::gsettings_get("org.gnome.system.proxy.https", "port")?,
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn restore_linux_proxy(
+        &self,
+        mode: &str,
+        http_host: &str,
+        http_port: &str,
+        https_host: &str,
+        https_port: &str,
+    ) -> Result<()> {
+        for (schema, key, value) in [
+            ("org.gnome.system.proxy.http", "host", http_host),
+            ("org.gnome.system.proxy.http", "port", http_port),
+            ("org.gnome.system.proxy.https", "host", https_host),
+            ("org.gnome.system.proxy.https", "port", https_port),
+            ("org.gnome.system.proxy", "mode", mode),
+        ] {
+            let status = Self::gsettings_cmd()
+                .args(["set", schema, key, value])
+                .status()?;
+            if !status.success() {
+                warn!("Failed to restore {} {}", schema, key);
+            }
+        }
+        Ok(())
+    }
+
     /// Returns a configured `Command` for running gsettings.
     /// When running as root (e.g. via sudo), uses `sudo -u <user>` and
     /// passes through the D-Bus session address so gsettings can reach
@@ -432,64 +821,147 @@ impl ProxyHandler {
         Ok(None)
     }
 
+    #[cfg(target_os = "windows")]
+    fn reg_query(name: &str) -> Result<Option<String>> {
+        let output = Command::new("reg")
+            .args(["query", INTERNET_SETTINGS_KEY, "/v", name])
+            .output()?;
+        // `reg query` fails when the value does not exist
+        if !output.status.success() {
+            return Ok(None);
+        }
+        Ok(parse_reg_query_value(
+            &String::from_utf8_lossy(&output.stdout),
+            name,
+        ))
+    }
+
+    #[cfg(target_os = "windows")]
+    fn reg_set(name: &str, kind: &str, value: &str) -> Result<()> {
+        let status = Command::new("reg")
+            .args([
+                "add",
+                INTERNET_SETTINGS_KEY,
+                "/v",
+                name,
+                "/t",
+                kind,
+                "/d",
+                value,
+                "/f",
+            ])
+            .status()?;
+        if !status.success() {
+            anyhow::bail!("Failed to set {} in registry", name);
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "windows")]
+    fn reg_restore_string(name: &str, value: &Option<String>) -> Result<()> {
+        match value {
+            Some(value) => Self::reg_set(name, "REG_SZ", value),
+            None => {
+                // Value did not exist before; deleting a missing value is fine
+                let _ = Command::new("reg")
+                    .args(["delete", INTERNET_SETTINGS_KEY, "/v", name, "/f"])
+                    .status();
+                Ok(())
+            }
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    async fn capture_windows_proxy(&self) -> Result<SystemProxySettings> {
+        let wininet = WinInetSettings {
+            proxy_enable: Self::reg_query("ProxyEnable")?
+                .is_some_and(|v| v.trim_start_matches("0x") != "0"),
+            proxy_server: Self::reg_query("ProxyServer")?,
+            proxy_override: Self::reg_query("ProxyOverride")?,
+            auto_config_url: Self::reg_query("AutoConfigURL")?,
+        };
+
+        let output = Command::new("netsh")
+            .args(["winhttp", "show", "proxy"])
+            .output()?;
+        let winhttp = if output.status.success() {
+            parse_netsh_winhttp(&String::from_utf8_lossy(&output.stdout))
+        } else {
+            WinHttpSettings::default()
+        };
+
+        Ok(SystemProxySettings::Windows { wininet, winhttp })
+    }
+
+    #[cfg(target_os = "windows")]
+    async fn restore_windows_proxy(
+        &self,
+        wininet: &WinInetSettings,
+        winhttp: &WinHttpSettings,
+    ) -> Result<()> {
+        Self::reg_restore_string("ProxyServer", &wininet.proxy_server)?;
+        Self::reg_restore_string("ProxyOverride", &wininet.proxy_override)?;
+        Self::reg_restore_string("AutoConfigURL", &wininet.auto_config_url)?;
+        Self::reg_set(
+            "ProxyEnable",
+            "REG_DWORD",
+            if wininet.proxy_enable { "1" } else { "0" },
+        )?;
+
+        let mut args = vec!["winhttp".to_string()];
+        match &winhttp.proxy_server {
+            Some(server) => {
+                args.extend([
+                    "set".into(),
+                    "proxy".into(),
+                    format!("proxy-server={}", server),
+                ]);
+                if let Some(bypass) = &winhttp.bypass_list {
+                    args.push(format!("bypass-list={}", bypass));
+                }
+            }
+            None => args.extend(["reset".into(), "proxy".into()]),
+        }
+        let status = Command::new("netsh").args(&args).status()?;
+        if !status.success() {
+            warn!("Failed to restore WinHTTP proxy settings (requires an elevated prompt)");
+        }
+        Ok(())
+    }
+
     #[cfg(target_os = "windows")]
     async fn set_windows_proxy(&self, proxy_url: &str, enable: bool) -> Result<()> {
         if enable {
             let proxy_server = proxy_url.strip_prefix("http://").unwrap_or(proxy_url);
 
-            // Enable proxy via registry
-            let status = Command::new("reg")
-                .args([
-                    "add",
-                    "HKCU\\Software\\Microsoft\\Windows\\CurrentVersion\\Internet Settings",
-                    "/v",
-                    "ProxyEnable",
-                    "/t",
-                    "REG_DWORD",
-                    "/d",
-                    "1",
-                    "/f",
-                ])
-                .status()?;
-
-            if !status.success() {
-                anyhow::bail!("Failed to enable proxy in registry");
-            }
+            // Enable proxy via registry (WinINET, used by browsers and most apps)
+            Self::reg_set("ProxyEnable", "REG_DWORD", "1")
+                .map_err(|_| anyhow::anyhow!("Failed to enable proxy in registry"))?;
+            Self::reg_set("ProxyServer", "REG_SZ", proxy_server)
+                .map_err(|_| anyhow::anyhow!("Failed to set proxy server in registry"))?;
 
-            let status = Command::new("reg")
+            // WinHTTP is used by system services; this needs an elevated prompt
+            let status = Command::new("netsh")
                 .args([
-                    "add",
-                    "HKCU\\Software\\Microsoft\\Windows\\CurrentVersion\\Internet Settings",
-                    "/v",
-                    "ProxyServer",
-                    "/t",
-                    "REG_SZ",
-                    "/d",
-                    proxy_server,
-                    "/f",
+                    "winhttp",
+                    "set",
+                    "proxy",
+                    &format!("proxy-server={}", proxy_server),
+                    "bypass-list=<local>",
                 ])
                 .status()?;
-
             if !status.success() {
-                anyhow::bail!("Failed to set proxy server in registry");
+                warn!("Failed to set WinHTTP proxy (requires an elevated prompt)");
             }
         } else {
-            let status = Command::new("reg")
-                .args([
-                    "add",
-                    "HKCU\\Software\\Microsoft\\Windows\\CurrentVersion\\Internet Settings",
-                    "/v",
-                    "ProxyEnable",
-                    "/t",
-                    "REG_DWORD",
-                    "/d",
-                    "0",
-                    "/f",
-                ])
-                .status()?;
+            Self::reg_set("ProxyEnable", "REG_DWORD", "0")
+                .map_err(|_| anyhow::anyhow!("Failed to disable proxy in registry"))?;
 
+            let status = Command::new("netsh")
+                .args(["winhttp", "reset", "proxy"])
+                .status()?;
             if !status.success() {
-                anyhow::bail!("Failed to disable proxy in registry");
+                warn!("Failed to reset WinHTTP proxy (requires an elevated prompt)");
             }
         }
 
@@ -498,42 +970,178 @@ impl ProxyHandler {
 
     #[cfg(target_os = "windows")]
     async fn get_windows_proxy(&self) -> Result<Option<String>> {
-        let output = Command::new("reg")
-            .args([
-                "query",
-                "HKCU\\Software\\Microsoft\\Windows\\CurrentVersion\\Internet Settings",
-                "/v",
-                "ProxyEnable",
-            ])
-            .output()?;
+        let enabled =
+            Self::reg_query("ProxyEnable")?.is_some_and(|v| v.trim_start_matches("0x") != "0");
 
-        if output.status.success() {
-            let reg_output = String::from_utf8_lossy(&output.stdout);
-            if reg_output.contains("0x1") {
-                // Proxy is enabled, get the server
-                let server_output = Command::new("reg")
-                    .args([
-                        "query",
-                        "HKCU\\Software\\Microsoft\\Windows\\CurrentVersion\\Internet Settings",
-                        "/v",
-                        "ProxyServer",
-                    ])
-                    .output()?;
-
-                if server_output.status.success() {
-                    let server_reg = String::from_utf8_lossy(&server_output.stdout);
-                    // Parse the registry output to extract proxy server
-                    for line in server_reg.lines() {
-                        if line.contains("ProxyServer") && line.contains("REG_SZ") {
-                            if let Some(server) = line.split_whitespace().last() {
-                                return Ok(Some(format!("http://{}", server)));
-                            }
-                        }
-                    }
-                }
+        if enabled {
+            if let Some(server) = Self::reg_query("ProxyServer")? {
+                return Ok(Some(format!("http://{}", server)));
             }
         }
 
         Ok(None)
     }
 }
+
+/// Parses the output of `networksetup -getwebproxy <service>`:
+///
+/// ```text
+/// Enabled: Yes
+/// Server: 127.0.0.1
+/// Port: 8080
+/// Authenticated Proxy Enabled: 0
+/// ```
+#[cfg(any(target_os = "macos", test))]
+fn parse_networksetup_proxy(output: &str) -> MacosProxyEntry {
+    let mut entry = MacosProxyEntry::default();
+    for line in output.lines() {
+        if let Some(enabled) = line.strip_prefix("Enabled: ") {
+            entry.enabled = enabled.trim() == "Yes";
+        } else if let Some(server) = line.strip_prefix("Server: ") {
+            entry.server = server.trim().to_string();
+        } else if let Some(port) = line.strip_prefix("Port: ") {
+            entry.port = port.trim().to_string();
+        }
+    }
+    entry
+}
+
+/// Extracts a single value from `reg query <key> /v <name>` output, where the
+/// value line looks like `    ProxyServer    REG_SZ    127.0.0.1:8080`.
+#[cfg(any(target_os = "windows", test))]
+fn parse_reg_query_value(output: &str, name: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let rest = line.trim_start().strip_prefix(name)?;
+        if !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let (kind, value) = rest.trim_start().split_once(char::is_whitespace)?;
+        kind.starts_with("REG_").then(|| value.trim().to_string())
+    })
+}
+
+/// Parses the output of `netsh winhttp show proxy`, which either reports
+/// `Direct access (no proxy server).` or the configured server and bypass list.
+#[cfg(any(target_os = "windows", test))]
+fn parse_netsh_winhttp(output: &str) -> WinHttpSettings {
+    let mut settings = WinHttpSettings::default();
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if key.trim().starts_with("Proxy Server") && !value.is_empty() {
+            settings.proxy_server = Some(value.to_string());
+        } else if key.trim().starts_with("Bypass List") && !value.is_empty() && value != "(none)" {
+            settings.bypass_list = Some(value.to_string());
+        }
+    }
+    settings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_networksetup_output() {
+        let entry = parse_networksetup_proxy(
+            "Enabled: Yes\nServer: 10.0.0.1\nPort: 3128\nAuthenticated Proxy Enabled: 0\n",
+        );
+        assert_eq!(
+            entry,
+            MacosProxyEntry {
+                enabled: true,
+                server: "10.0.0.1".into(),
+                port: "3128".into(),
+            }
+        );
+
+        let disabled = parse_networksetup_proxy("Enabled: No\nServer: \nPort: 0\n");
+        assert!(!disabled.enabled);
+        assert!(disabled.server.is_empty());
+    }
+
+    #[test]
+    fn parses_reg_query_values() {
+        let output = "\r\nHKEY_CURRENT_USER\\Software\\Microsoft\\Windows\\CurrentVersion\\Internet Settings\r\n    ProxyOverride    REG_SZ    <local>;*.corp example\r\n    ProxyEnable    REG_DWORD    0x1\r\n";
+        assert_eq!(
+            parse_reg_query_value(output, "ProxyOverride").as_deref(),
+            Some("<local>;*.corp example")
+        );
+        assert_eq!(
+            parse_reg_query_value(output, "ProxyEnable").as_deref(),
+            Some("0x1")
+        );
+        // Prefix of another value name must not match
+        assert_eq!(parse_reg_query_value(output, "Proxy"), None);
+        assert_eq!(parse_reg_query_value(output, "AutoConfigURL"), None);
+    }
+
+    #[test]
+    fn parses_netsh_winhttp_output() {
+        let direct = parse_netsh_winhttp(
+            "\nCurrent WinHTTP proxy settings:\n\n    Direct access (no proxy server).\n",
+        );
+        assert_eq!(direct, WinHttpSettings::default());
+
+        let proxied = parse_netsh_winhttp(
+            "\nCurrent WinHTTP proxy settings:\n\n    Proxy Server(s) :  proxy.corp:8080\n    Bypass List     :  <local>\n",
+        );
+        assert_eq!(proxied.proxy_server.as_deref(), Some("proxy.corp:8080"));
+        assert_eq!(proxied.bypass_list.as_deref(), Some("<local>"));
+    }
+
+    #[test]
+    fn snapshot_round_trips_through_restore_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(RESTORE_FILE_NAME);
+        assert!(ProxySnapshot::load(&path)?.is_none());
+
+        let snapshot = ProxySnapshot {
+            proxy_url: "http://127.0.0.1:8080".into(),
+            captured_at: chrono::Utc::now(),
+            mode: SnapshotMode::Auto,
+            settings: SystemProxySettings::Windows {
+                wininet: WinInetSettings {
+                    proxy_enable: true,
+                    proxy_server: Some("corp:3128".into()),
+                    proxy_override: Some("<local>".into()),
+                    auto_config_url: None,
+                },
+                winhttp: WinHttpSettings::default(),
+            },
+        };
+        snapshot.save(&path)?;
+
+        assert_eq!(ProxySnapshot::load(&path)?, Some(snapshot));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_snapshots_of_auto_sessions_are_recovered() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut config = AppConfig::default();
+        config.tls.cert_dir = dir.path().join("certs");
+        let handler = ProxyHandler::new(config);
+        let path = handler.restore_path();
+        assert!(!handler.recover_stale_snapshot().await?);
+
+        // Saved before the mode was recorded, so taken by `proxy enable`
+        std::fs::write(
+            &path,
+            r#"{
+                "proxy_url": "http://127.0.0.1:8080",
+                "captured_at": "2026-01-01T00:00:00Z",
+                "settings": {"platform": "windows", "wininet": {"proxy_enable": false, "proxy_server": null, "proxy_override": null, "auto_config_url": null}, "winhttp": {"proxy_server": null, "bypass_list": null}}
+            }"#,
+        )?;
+        assert_eq!(
+            ProxySnapshot::load(&path)?.unwrap().mode,
+            SnapshotMode::Manual
+        );
+        assert!(!handler.recover_stale_snapshot().await?);
+        assert!(path.exists());
+        Ok(())
+    }
+}
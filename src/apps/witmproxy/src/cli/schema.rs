@@ -0,0 +1,88 @@
+use anyhow::{Result, bail};
+use clap::Subcommand;
+use std::path::PathBuf;
+
+use crate::config::AppConfig;
+use crate::db::Db;
+use crate::db::api_schemas::StoredApiSchema;
+use crate::proxy::api_schemas;
+
+#[derive(Subcommand)]
+pub enum SchemaCommands {
+    /// List the hosts with API schemas inferred from traffic
+    ///
+    /// Schemas are only inferred with `proxy.infer_schemas` enabled, and are
+    /// saved by the running proxy every 30 seconds.
+    List,
+    /// Export the API schema inferred for a host as an OpenAPI document
+    Export {
+        /// The host the API is served from
+        host: String,
+        /// Write the document as JSON rather than YAML
+        #[arg(long)]
+        json: bool,
+        /// Output file path (prints to stdout if not specified)
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+}
+
+pub struct SchemaHandler {
+    config: AppConfig,
+}
+
+impl SchemaHandler {
+    pub fn new(config: AppConfig) -> Self {
+        Self { config }
+    }
+
+    pub async fn handle(&self, command: &SchemaCommands) -> Result<()> {
+        let db = Db::from_path(self.config.db.db_path.clone(), &self.config.db.db_password).await?;
+        db.migrate().await?;
+
+        match command {
+            SchemaCommands::List => {
+                let schemas = StoredApiSchema::list(&db.pool).await?;
+                if schemas.is_empty() {
+                    println!("No API schemas inferred.");
+                    if !self.config.proxy.infer_schemas {
+                        println!("Enable proxy.infer_schemas to infer them from traffic.");
+                    }
+                    return Ok(());
+                }
+
+                println!("Inferred API schemas:\n");
+                for stored in &schemas {
+                    let summary = stored.schema()?.summary(&stored.host);
+                    println!(
+                        "  {} ({} paths, {} operations, {} requests seen; updated {})",
+                        summary.host,
+                        summary.paths,
+                        summary.operations,
+                        summary.observations,
+                        stored.updated_at
+                    );
+                }
+            }
+            SchemaCommands::Export { host, json, output } => {
+                let Some(stored) = StoredApiSchema::get(&db.pool, host).await? else {
+                    bail!("No API schema inferred for {}", host);
+                };
+                let document = stored.schema()?.to_openapi(&stored.host);
+                let text = if *json {
+                    serde_json::to_string_pretty(&document)?
+                } else {
+                    api_schemas::to_yaml(&document)?
+                };
+                match output {
+                    Some(path) => {
+                        std::fs::write(path, text)?;
+                        println!("Wrote the API schema for {} to {}", host, path.display());
+                    }
+                    None => print!("{}", text),
+                }
+            }
+        }
+        Ok(())
+    }
+}
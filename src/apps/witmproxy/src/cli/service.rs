@@ -2,11 +2,11 @@
 use crate::config::TransparentProxyConfig;
 use crate::config::{AppConfig, confique_app_config_layer::AppConfigLayer};
 use anyhow::{Context, Result};
-use clap::Subcommand;
+use clap::{Args, Subcommand, ValueEnum};
 use confique::Config;
 use service_manager::{
-    ServiceInstallCtx, ServiceLabel, ServiceManager, ServiceStartCtx, ServiceStopCtx,
-    ServiceUninstallCtx,
+    RestartPolicy, ServiceInstallCtx, ServiceLabel, ServiceManager, ServiceStartCtx, ServiceStatus,
+    ServiceStatusCtx, ServiceStopCtx, ServiceUninstallCtx,
 };
 use std::ffi::OsString;
 use std::path::{Path, PathBuf};
@@ -30,6 +30,75 @@ const SERVICE_FILE_NAME: &str = "ez-witmproxy.service";
 /// Log file name within the app directory
 const LOG_FILE_NAME: &str = "witmproxy.log";
 
+/// File within the app directory capturing the service's stdout and stderr,
+/// which includes panics and anything written before logging is set up
+const OUTPUT_LOG_FILE_NAME: &str = "witmproxy.out.log";
+
+/// When the service manager restarts the proxy after it exits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum RestartMode {
+    /// Restart whenever the proxy exits
+    Always,
+    /// Restart only when the proxy exits with an error
+    #[default]
+    OnFailure,
+    /// Never restart the proxy
+    Never,
+}
+
+impl RestartMode {
+    fn policy(self, delay_secs: u32) -> RestartPolicy {
+        match self {
+            RestartMode::Always => RestartPolicy::Always {
+                delay_secs: Some(delay_secs),
+            },
+            RestartMode::OnFailure => RestartPolicy::OnFailure {
+                delay_secs: Some(delay_secs),
+                max_retries: None,
+                reset_after_secs: None,
+            },
+            RestartMode::Never => RestartPolicy::Never,
+        }
+    }
+
+    /// The value of systemd's `Restart=` setting
+    #[cfg(target_os = "linux")]
+    fn systemd_value(self) -> &'static str {
+        match self {
+            RestartMode::Always => "always",
+            RestartMode::OnFailure => "on-failure",
+            RestartMode::Never => "no",
+        }
+    }
+}
+
+/// How the installed service is run
+#[derive(Args, Debug, Clone)]
+pub struct ServiceOptions {
+    /// User the Linux system service runs as (default: root). Services on
+    /// other platforms run as the installing user.
+    #[arg(long)]
+    pub user: Option<String>,
+
+    /// When to restart the proxy after it exits
+    #[arg(long, value_enum, default_value_t)]
+    pub restart: RestartMode,
+
+    /// Seconds to wait before restarting the proxy
+    #[arg(long, default_value = "1")]
+    pub restart_delay_secs: u32,
+}
+
+impl Default for ServiceOptions {
+    fn default() -> Self {
+        Self {
+            user: None,
+            restart: RestartMode::default(),
+            restart_delay_secs: 1,
+        }
+    }
+}
+
 #[derive(Subcommand)]
 pub enum ServiceCommands {
     /// Install the witmproxy service (does not start it)
@@ -37,6 +106,9 @@ pub enum ServiceCommands {
         #[command(flatten)]
         options: Box<super::ProxyRunOptions>,
 
+        #[command(flatten)]
+        service: ServiceOptions,
+
         /// Skip confirmation prompts
         #[arg(short, long)]
         yes: bool,
@@ -149,6 +221,11 @@ impl ServiceHandler {
         self.get_app_dir().join(LOG_FILE_NAME)
     }
 
+    /// Get the path capturing the service's stdout and stderr
+    pub fn get_output_log_path(&self) -> PathBuf {
+        self.get_app_dir().join(OUTPUT_LOG_FILE_NAME)
+    }
+
     /// Get the config file path
     fn get_config_path(&self) -> PathBuf {
         self.get_app_dir().join("config.toml")
@@ -219,7 +296,12 @@ impl ServiceHandler {
     }
 
     /// Install the service
-    pub async fn install_service(&self, layer: AppConfigLayer, skip_confirm: bool) -> Result<()> {
+    pub async fn install_service(
+        &self,
+        layer: AppConfigLayer,
+        service: &ServiceOptions,
+        skip_confirm: bool,
+    ) -> Result<()> {
         #[cfg(target_os = "linux")]
         Self::ensure_root()?;
 
@@ -300,6 +382,12 @@ impl ServiceHandler {
                 .unwrap_or_default()
         };
 
+        // A profile given with --profile is switched to rather than saved,
+        // so the saved file stays the base profiles are layered over
+        let switched_profile = layer.profile.is_some();
+        if let Some(profile) = &layer.profile {
+            super::profile::switch(&config_path, profile)?;
+        }
         let mut builder = AppConfig::builder().preloaded(layer).env();
         if source_config_path.exists() {
             builder = builder.file(&source_config_path);
@@ -315,6 +403,9 @@ impl ServiceHandler {
             }
         };
 
+        if switched_profile {
+            config_to_save.profile = None;
+        }
         // Always use the daemon's standard paths regardless of source
         config_to_save.db.db_path = app_dir.join("witmproxy.db");
         config_to_save.tls.cert_dir = app_dir.join("certs");
@@ -323,6 +414,26 @@ impl ServiceHandler {
             .context("Failed to save configuration")?;
         info!("Configuration saved to {:?}", config_path);
 
+        // A non-root service user needs to own its config, certs, db and logs
+        #[cfg(target_os = "linux")]
+        if let Some(ref user) = service.user {
+            let status = std::process::Command::new("chown")
+                .arg("-R")
+                .arg(format!("{}:", user))
+                .arg(&app_dir)
+                .status()
+                .context("Failed to run chown")?;
+            if !status.success() {
+                anyhow::bail!("Failed to give {} ownership of {:?}", user, app_dir);
+            }
+            if config_to_save.transparent.enabled && user != "root" {
+                warn!(
+                    "The transparent proxy manages iptables rules and may fail to start as {}",
+                    user
+                );
+            }
+        }
+
         // Build arguments for the 'serve' subcommand.
         // Global args (--config-path, --verbose) go BEFORE the subcommand;
         // subcommand args (--plugin-dir, --auto, --log-file) go AFTER it.
@@ -366,13 +477,29 @@ impl ServiceHandler {
         info!("Service arguments: {:?}", args);
 
         // On Linux, generate a custom unit file with ExecStopPost for iptables cleanup
+        let output_log_path = self.get_output_log_path();
         #[cfg(target_os = "linux")]
         let contents = {
-            let unit =
-                generate_systemd_unit(&exe_path, &args, &app_dir, &config_to_save.transparent);
+            let unit = generate_systemd_unit(
+                &exe_path,
+                &args,
+                &app_dir,
+                &output_log_path,
+                service,
+                &config_to_save.transparent,
+            );
             Some(unit)
         };
-        #[cfg(not(target_os = "linux"))]
+        // On macOS, generate the plist so the service's output is captured
+        #[cfg(target_os = "macos")]
+        let contents = Some(generate_launchd_plist(
+            &exe_path,
+            &args,
+            &app_dir,
+            &output_log_path,
+            service,
+        ));
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
         let contents: Option<String> = None;
 
         let install_ctx = ServiceInstallCtx {
@@ -380,15 +507,11 @@ impl ServiceHandler {
             program: exe_path,
             args,
             contents,
-            username: None, // Run as current user
+            username: service.user.clone(),
             working_directory: Some(app_dir),
             environment: None,
             autostart: true, // Start on boot
-            restart_policy: service_manager::RestartPolicy::OnFailure {
-                delay_secs: Some(1),
-                max_retries: None,
-                reset_after_secs: None,
-            },
+            restart_policy: service.restart.policy(service.restart_delay_secs),
         };
 
         manager
@@ -396,7 +519,9 @@ impl ServiceHandler {
             .context("Failed to install service. On macOS, ensure ~/Library/LaunchAgents exists. On Linux, ensure you are running as root.")?;
 
         println!("✓ Service installed successfully.");
+        println!("  Config file: {:?}", config_path);
         println!("  Log file: {:?}", log_path);
+        println!("  Output log: {:?}", output_log_path);
         println!();
         println!("To start the service, run: witm service start");
         println!("To check status, run: witm service status");
@@ -536,41 +661,47 @@ impl ServiceHandler {
         false
     }
 
-    /// Show service status
+    /// Show service status, as reported by the platform's service manager
     pub async fn show_status(&self) -> Result<()> {
-        let is_installed = self.is_service_installed();
-
-        if !is_installed {
-            println!("Service status: Not installed");
-            println!();
-            println!("To install: witm service install");
-            return Ok(());
-        }
-
-        println!("Service status: Installed");
+        let status = Self::get_manager().and_then(|manager| {
+            manager
+                .status(ServiceStatusCtx {
+                    label: Self::service_label(),
+                })
+                .context("Failed to query service status")
+        });
 
-        // Check if running by looking at the log file modification time or PID file
-        // This is a simplified check - actual implementation would vary by platform
-        let log_path = self.get_log_path();
-        if log_path.exists() {
-            if let Ok(metadata) = std::fs::metadata(&log_path)
-                && let Ok(modified) = metadata.modified()
-            {
-                let duration = std::time::SystemTime::now()
-                    .duration_since(modified)
-                    .unwrap_or_default();
-                if duration.as_secs() < 60 {
-                    println!("Service appears to be: Running (log recently updated)");
-                } else {
-                    println!("Service appears to be: Stopped (log not recently updated)");
+        match status {
+            Ok(ServiceStatus::NotInstalled) => {
+                println!("Service status: Not installed");
+                println!();
+                println!("To install: witm service install");
+                return Ok(());
+            }
+            Ok(ServiceStatus::Running) => println!("Service status: Running"),
+            Ok(ServiceStatus::Stopped(reason)) => match reason {
+                Some(reason) => println!("Service status: Stopped ({})", reason),
+                None => println!("Service status: Stopped"),
+            },
+            // Fall back to looking for the service file
+            Err(e) => {
+                warn!("{:#}", e);
+                if !self.is_service_installed() {
+                    println!("Service status: Not installed");
+                    println!();
+                    println!("To install: witm service install");
+                    return Ok(());
                 }
+                println!("Service status: Installed (state unknown)");
             }
-        } else {
-            println!("Service appears to be: Stopped (no log file)");
         }
 
+        if let Some(profile) = &self.config.profile {
+            println!("Profile: {}", profile);
+        }
         println!();
-        println!("Log file: {:?}", log_path);
+        println!("Log file: {:?}", self.get_log_path());
+        println!("Output log: {:?}", self.get_output_log_path());
 
         // Show services.json if it exists
         let services_path = self.get_app_dir().join("services.json");
@@ -662,6 +793,8 @@ fn generate_systemd_unit(
     exe_path: &Path,
     args: &[OsString],
     app_dir: &Path,
+    output_log_path: &Path,
+    service: &ServiceOptions,
     transparent_config: &TransparentProxyConfig,
 ) -> String {
     use crate::proxy::netfilter::NetfilterManager;
@@ -700,6 +833,12 @@ fn generate_systemd_unit(
         })
         .collect();
 
+    let user = service
+        .user
+        .as_ref()
+        .map(|user| format!("User={}\n", user))
+        .unwrap_or_default();
+
     format!(
         "\
 [Unit]
@@ -710,8 +849,10 @@ After=network.target
 Type=simple
 ExecStart={exec_start}
 WorkingDirectory={work_dir}
-Restart=on-failure
-RestartSec=1
+{user}StandardOutput=append:{output_log}
+StandardError=append:{output_log}
+Restart={restart}
+RestartSec={restart_sec}
 {exec_stop_post}
 
 [Install]
@@ -719,10 +860,93 @@ WantedBy=multi-user.target
 ",
         exec_start = exec_start,
         work_dir = app_dir.display(),
+        user = user,
+        output_log = output_log_path.display(),
+        restart = service.restart.systemd_value(),
+        restart_sec = service.restart_delay_secs,
         exec_stop_post = exec_stop_post_lines.join("\n"),
     )
 }
 
+/// Generate a launchd plist which, unlike the one service-manager writes,
+/// captures the service's stdout and stderr
+#[cfg(any(target_os = "macos", test))]
+fn generate_launchd_plist(
+    exe_path: &Path,
+    args: &[OsString],
+    app_dir: &Path,
+    output_log_path: &Path,
+    service: &ServiceOptions,
+) -> String {
+    let program_arguments = std::iter::once(exe_path.as_os_str())
+        .chain(args.iter().map(OsString::as_os_str))
+        .map(|arg| {
+            format!(
+                "\t\t<string>{}</string>\n",
+                xml_escape(&arg.to_string_lossy())
+            )
+        })
+        .collect::<String>();
+    let output_log = xml_escape(&output_log_path.to_string_lossy());
+
+    // As with service-manager's own plists, a service kept alive starts out
+    // disabled so installing it doesn't start it; `start` re-enables it
+    let keep_alive = match service.restart {
+        RestartMode::Always => "\t<key>KeepAlive</key>\n\t<true/>\n",
+        RestartMode::OnFailure => {
+            "\t<key>KeepAlive</key>\n\t<dict>\n\t\t<key>SuccessfulExit</key>\n\t\t<false/>\n\t</dict>\n"
+        }
+        RestartMode::Never => "",
+    };
+    let disabled = if keep_alive.is_empty() {
+        ""
+    } else {
+        "\t<key>Disabled</key>\n\t<true/>\n"
+    };
+
+    format!(
+        "\
+<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">
+<plist version=\"1.0\">
+<dict>
+\t<key>Label</key>
+\t<string>{label}</string>
+\t<key>ProgramArguments</key>
+\t<array>
+{program_arguments}\t</array>
+\t<key>WorkingDirectory</key>
+\t<string>{work_dir}</string>
+\t<key>StandardOutPath</key>
+\t<string>{output_log}</string>
+\t<key>StandardErrorPath</key>
+\t<string>{output_log}</string>
+\t<key>RunAtLoad</key>
+\t<true/>
+{keep_alive}\t<key>ThrottleInterval</key>
+\t<integer>{throttle}</integer>
+{disabled}</dict>
+</plist>
+",
+        label = SERVICE_LABEL,
+        program_arguments = program_arguments,
+        work_dir = xml_escape(&app_dir.to_string_lossy()),
+        output_log = output_log,
+        keep_alive = keep_alive,
+        throttle = service.restart_delay_secs,
+        disabled = disabled,
+    )
+}
+
+#[cfg(any(target_os = "macos", test))]
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
 /// Check if the daemon is already running by checking the services.json file
 pub fn is_daemon_running(app_dir: &Path) -> bool {
     let services_path = app_dir.join("services.json");
@@ -772,6 +996,8 @@ mod tests {
                 "serve".into(),
             ],
             Path::new("/var/lib/witmproxy"),
+            Path::new("/var/lib/witmproxy/witmproxy.out.log"),
+            &ServiceOptions::default(),
             &default_transparent_config(),
         );
 
@@ -790,6 +1016,8 @@ mod tests {
             Path::new("/usr/bin/witm"),
             &["serve".into()],
             Path::new("/var/lib/witmproxy"),
+            Path::new("/var/lib/witmproxy/witmproxy.out.log"),
+            &ServiceOptions::default(),
             &default_transparent_config(),
         );
 
@@ -838,6 +1066,8 @@ mod tests {
             Path::new("/usr/bin/witm"),
             &["serve".into()],
             Path::new("/var/lib/witmproxy"),
+            Path::new("/var/lib/witmproxy/witmproxy.out.log"),
+            &ServiceOptions::default(),
             &config,
         );
 
@@ -885,6 +1115,8 @@ mod tests {
             Path::new("/usr/bin/witm"),
             &["serve".into()],
             Path::new("/var/lib/witmproxy"),
+            Path::new("/var/lib/witmproxy/witmproxy.out.log"),
+            &ServiceOptions::default(),
             &config,
         );
 
@@ -915,4 +1147,77 @@ mod tests {
             assert!(line.contains(expected_path), "missing path: {}", line);
         }
     }
+
+    #[test]
+    fn generate_unit_applies_service_options() {
+        let service = ServiceOptions {
+            user: Some("witm".to_string()),
+            restart: RestartMode::Always,
+            restart_delay_secs: 5,
+        };
+        let unit = generate_systemd_unit(
+            Path::new("/usr/bin/witm"),
+            &["serve".into()],
+            Path::new("/var/lib/witmproxy"),
+            Path::new("/var/lib/witmproxy/witmproxy.out.log"),
+            &service,
+            &default_transparent_config(),
+        );
+
+        assert!(unit.contains("User=witm\n"));
+        assert!(unit.contains("StandardOutput=append:/var/lib/witmproxy/witmproxy.out.log"));
+        assert!(unit.contains("StandardError=append:/var/lib/witmproxy/witmproxy.out.log"));
+        assert!(unit.contains("Restart=always"));
+        assert!(unit.contains("RestartSec=5"));
+
+        let unit = generate_systemd_unit(
+            Path::new("/usr/bin/witm"),
+            &["serve".into()],
+            Path::new("/var/lib/witmproxy"),
+            Path::new("/var/lib/witmproxy/witmproxy.out.log"),
+            &ServiceOptions {
+                restart: RestartMode::Never,
+                ..Default::default()
+            },
+            &default_transparent_config(),
+        );
+        assert!(!unit.contains("User="));
+        assert!(unit.contains("Restart=no"));
+    }
+
+    #[test]
+    fn generate_plist_captures_output() {
+        let plist = generate_launchd_plist(
+            Path::new("/Applications/witm"),
+            &[
+                "--config-path".into(),
+                "/Users/a&b/config.toml".into(),
+                "serve".into(),
+            ],
+            Path::new("/Users/a&b/.witmproxy"),
+            Path::new("/Users/a&b/.witmproxy/witmproxy.out.log"),
+            &ServiceOptions::default(),
+        );
+
+        assert!(plist.contains("<string>co.ez.witmproxy</string>"));
+        assert!(plist.contains("<string>/Applications/witm</string>"));
+        assert!(plist.contains("<string>/Users/a&amp;b/config.toml</string>"));
+        assert!(plist.contains("<key>StandardErrorPath</key>"));
+        assert!(plist.contains("<string>/Users/a&amp;b/.witmproxy/witmproxy.out.log</string>"));
+        assert!(plist.contains("<key>SuccessfulExit</key>"));
+        assert!(plist.contains("<key>Disabled</key>"));
+
+        let plist = generate_launchd_plist(
+            Path::new("/Applications/witm"),
+            &["serve".into()],
+            Path::new("/tmp"),
+            Path::new("/tmp/witmproxy.out.log"),
+            &ServiceOptions {
+                restart: RestartMode::Never,
+                ..Default::default()
+            },
+        );
+        assert!(!plist.contains("KeepAlive"));
+        assert!(!plist.contains("Disabled"));
+    }
 }
@@ -0,0 +1,164 @@
+use anyhow::Result;
+use clap::Subcommand;
+
+use super::api_client::ApiClient;
+use crate::proxy::flows::FlowRecord;
+use crate::proxy::sessions::{SessionSpec, SessionSummary};
+
+#[derive(Subcommand)]
+pub enum SessionCommands {
+    /// Start a capture session, grouping the flows completed until it stops
+    Start {
+        /// Session name
+        #[arg(long)]
+        name: String,
+        /// Stop the session after this long, ex: 90s, 30m, 1h or 2d
+        #[arg(long, value_parser = parse_duration)]
+        duration: Option<u64>,
+        /// Write the session's flows here when it stops, ex: har:///tmp/x.har
+        /// or json://flows.json (repeatable)
+        #[arg(long)]
+        export: Vec<String>,
+        /// Only capture the flows of hosts matching this pattern, ex:
+        /// *.example.com (repeatable)
+        #[arg(long)]
+        host: Vec<String>,
+        /// Keep at most this many flows, dropping the oldest
+        #[arg(long)]
+        max_flows: Option<usize>,
+    },
+    /// List running and recently stopped sessions
+    List,
+    /// Stop a session and run its exports
+    Stop {
+        /// Session ID
+        id: String,
+    },
+    /// Print the flows captured by a session as JSON
+    Flows {
+        /// Session ID
+        id: String,
+    },
+}
+
+/// Seconds in a duration such as `90s`, `30m`, `1h` or `2d`; bare numbers
+/// are seconds
+fn parse_duration(s: &str) -> Result<u64, String> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(at) => s.split_at(at),
+        None => (s, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration {s:?}, expected ex: 30m or 1h"))?;
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "Unknown duration unit {unit:?}, expected s, m, h or d"
+            ));
+        }
+    };
+    Ok(number * unit)
+}
+
+pub struct SessionHandler {
+    remote: Option<ApiClient>,
+}
+
+impl SessionHandler {
+    pub fn new(remote: Option<ApiClient>) -> Self {
+        Self { remote }
+    }
+
+    pub async fn handle(self, command: &SessionCommands) -> Result<()> {
+        let client = match self.remote {
+            Some(remote) => remote,
+            None => ApiClient::from_auth_store()?.ok_or_else(|| {
+                anyhow::anyhow!("Not authenticated. Run 'witm auth login' or pass --remote.")
+            })?,
+        };
+
+        match command {
+            SessionCommands::Start {
+                name,
+                duration,
+                export,
+                host,
+                max_flows,
+            } => {
+                let spec = SessionSpec {
+                    name: name.clone(),
+                    duration_secs: *duration,
+                    export: export.clone(),
+                    hosts: host.clone(),
+                    max_flows: *max_flows,
+                };
+                let resp = ApiClient::check(client.post_json("/api/manage/sessions", &spec).await?)
+                    .await?;
+                let session: SessionSummary = resp.json().await?;
+                println!("Started session {} ({})", session.name, session.id);
+                if let Some(secs) = duration {
+                    println!("It stops itself in {}s", secs);
+                }
+            }
+            SessionCommands::List => {
+                let resp = ApiClient::check(client.get("/api/manage/sessions").await?).await?;
+                let sessions: Vec<SessionSummary> = resp.json().await?;
+                if sessions.is_empty() {
+                    println!("No capture sessions.");
+                    return Ok(());
+                }
+                for session in &sessions {
+                    let state = if session.stopped_millis.is_some() {
+                        "stopped"
+                    } else {
+                        "running"
+                    };
+                    println!(
+                        "  {} {} ({}, {} flows)",
+                        session.id, session.name, state, session.flows
+                    );
+                }
+            }
+            SessionCommands::Stop { id } => {
+                let path = format!("/api/manage/sessions/{}/stop", id);
+                let resp = ApiClient::check(
+                    client
+                        .request(reqwest::Method::POST, &path)
+                        .await
+                        .send()
+                        .await?,
+                )
+                .await?;
+                let session: SessionSummary = resp.json().await?;
+                println!(
+                    "Stopped session {} with {} flows",
+                    session.name, session.flows
+                );
+                for target in &session.export {
+                    if !session
+                        .export_errors
+                        .iter()
+                        .any(|error| error.starts_with(target.as_str()))
+                    {
+                        println!("  Exported to {}", target);
+                    }
+                }
+                for error in &session.export_errors {
+                    println!("  Export failed: {}", error);
+                }
+            }
+            SessionCommands::Flows { id } => {
+                let path = format!("/api/manage/sessions/{}/flows", id);
+                let resp = ApiClient::check(client.get(&path).await?).await?;
+                let flows: Vec<FlowRecord> = resp.json().await?;
+                println!("{}", serde_json::to_string_pretty(&flows)?);
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,10 +1,11 @@
 use crate::{
     AppConfig, Db, Runtime,
-    cli::load_plugins_from_directory,
+    cli::{api_client::ApiClient, load_plugins_from_directory},
     config::confique_app_config_layer::AppConfigLayer,
     plugins::{WitmPlugin, registry::PluginRegistry},
-    test_utils::test_component_path,
+    test_utils::{create_ca_and_config, test_component_path},
     wasm::bindgen::Event,
+    web::WebServer,
 };
 use anyhow::Result;
 use cel_cxx::{Env, EnvBuilder};
@@ -403,3 +404,73 @@ async fn test_plugin_dir_non_wasm_files_ignored() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_witm_plugin_remote_management() -> Result<()> {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    // A running instance with its own database
+    let (ca, config) = create_ca_and_config().await;
+    let temp_dir = tempdir().unwrap();
+    let db = Db::from_path(temp_dir.path().join("remote.db"), "test_password").await?;
+    db.migrate().await?;
+    let registry = Arc::new(RwLock::new(PluginRegistry::new(
+        db,
+        Runtime::try_default()?,
+    )?));
+    let mut web_server = WebServer::new(ca.clone(), Some(registry.clone()), config);
+    web_server.start().await?;
+
+    let ca_path = temp_dir.path().join("remote-ca.pem");
+    std::fs::write(&ca_path, ca.get_root_certificate_pem()?)?;
+    let remote = ApiClient::remote(
+        &format!("https://{}", web_server.listen_addr().unwrap()),
+        None,
+        Some(&ca_path),
+    )?;
+
+    // The local config points elsewhere; every command must go through the API
+    let local_dir = temp_dir.path().join("local");
+    let plugin_handler =
+        plugin::PluginHandler::new(create_test_config(&local_dir), false).with_remote(Some(remote));
+
+    plugin_handler
+        .handle(&plugin::PluginCommands::Add {
+            source: test_component_path()?,
+            public_key: None,
+        })
+        .await?;
+    let plugin_id = registry
+        .read()
+        .await
+        .plugins()
+        .keys()
+        .next()
+        .cloned()
+        .expect("Plugin should be registered on the remote instance");
+
+    plugin_handler
+        .handle(&plugin::PluginCommands::Configure {
+            plugin_name: plugin_id.clone(),
+            set_values: vec!["greeting=hello".to_string()],
+        })
+        .await?;
+    assert!(
+        registry.read().await.plugins()[&plugin_id]
+            .configuration
+            .iter()
+            .any(|input| input.name == "greeting")
+    );
+
+    plugin_handler.handle(&plugin::PluginCommands::List).await?;
+    plugin_handler
+        .handle(&plugin::PluginCommands::Remove {
+            plugin_name: plugin_id,
+        })
+        .await?;
+    assert!(registry.read().await.plugins().is_empty());
+    assert!(!local_dir.join("test.db").exists());
+
+    web_server.shutdown().await;
+    Ok(())
+}
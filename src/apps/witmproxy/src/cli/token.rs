@@ -0,0 +1,141 @@
+use anyhow::Result;
+use clap::Subcommand;
+
+use crate::config::AppConfig;
+use crate::db::Db;
+use crate::db::api_tokens::{ApiToken, Role};
+use crate::db::audit::{AuditAction, AuditEntry, cli_actor};
+
+#[derive(Subcommand)]
+pub enum TokenCommands {
+    /// Issue a new API token for the management API
+    Create {
+        /// Unique name identifying the token (e.g. the CI job or dashboard using it)
+        name: String,
+        /// Role granted to the token
+        #[arg(long, value_enum, default_value = "viewer")]
+        role: Role,
+        /// Expire the token after this many days (never expires if omitted)
+        #[arg(long)]
+        expires_in_days: Option<i64>,
+    },
+    /// List API tokens
+    List,
+    /// Revoke an API token
+    Revoke {
+        /// Token ID or name
+        token: String,
+    },
+    /// Issue a new secret for an API token, invalidating the old one
+    Rotate {
+        /// Token ID or name
+        token: String,
+    },
+}
+
+pub struct TokenHandler {
+    config: AppConfig,
+}
+
+impl TokenHandler {
+    pub fn new(config: AppConfig) -> Self {
+        Self { config }
+    }
+
+    pub async fn handle(&self, command: &TokenCommands) -> Result<()> {
+        let db = Db::from_path(self.config.db.db_path.clone(), &self.config.db.db_password).await?;
+        db.migrate().await?;
+
+        match command {
+            TokenCommands::Create {
+                name,
+                role,
+                expires_in_days,
+            } => {
+                let expires_at =
+                    expires_in_days.map(|days| chrono::Utc::now() + chrono::Duration::days(days));
+                let (token, secret) = ApiToken::create(&db.pool, name, *role, expires_at).await?;
+                AuditEntry::record(
+                    &db.pool,
+                    &cli_actor(),
+                    AuditAction::TokenCreate,
+                    Some(&token.id),
+                    serde_json::json!({ "name": token.name, "role": token.role }),
+                )
+                .await?;
+
+                println!(
+                    "Created {} token '{}' ({})",
+                    token.role, token.name, token.id
+                );
+                print_secret(&secret);
+            }
+            TokenCommands::List => {
+                let tokens = ApiToken::list(&db.pool).await?;
+                if tokens.is_empty() {
+                    println!("No API tokens.");
+                    return Ok(());
+                }
+
+                println!("API tokens:\n");
+                for token in &tokens {
+                    let status = if token.revoked { " (revoked)" } else { "" };
+                    println!("  {} [{}]{}", token.name, token.role, status);
+                    println!("    ID:        {}", token.id);
+                    println!("    Created:   {}", token.created_at);
+                    println!(
+                        "    Last used: {}",
+                        token.last_used_at.as_deref().unwrap_or("never")
+                    );
+                    println!(
+                        "    Expires:   {}",
+                        token.expires_at.as_deref().unwrap_or("never")
+                    );
+                }
+            }
+            TokenCommands::Revoke { token } => {
+                let token = Self::find(&db, token).await?;
+                ApiToken::revoke(&db.pool, &token.id).await?;
+                AuditEntry::record(
+                    &db.pool,
+                    &cli_actor(),
+                    AuditAction::TokenRevoke,
+                    Some(&token.id),
+                    serde_json::json!({ "name": token.name }),
+                )
+                .await?;
+                println!("Revoked token '{}'", token.name);
+            }
+            TokenCommands::Rotate { token } => {
+                let token = Self::find(&db, token).await?;
+                let secret = ApiToken::rotate(&db.pool, &token.id)
+                    .await?
+                    .ok_or_else(|| anyhow::anyhow!("Token '{}' is revoked", token.name))?;
+                AuditEntry::record(
+                    &db.pool,
+                    &cli_actor(),
+                    AuditAction::TokenRotate,
+                    Some(&token.id),
+                    serde_json::json!({ "name": token.name }),
+                )
+                .await?;
+                println!("Rotated token '{}'", token.name);
+                print_secret(&secret);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn find(db: &Db, id_or_name: &str) -> Result<ApiToken> {
+        ApiToken::find(&db.pool, id_or_name)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("No API token named or with ID '{}'", id_or_name))
+    }
+}
+
+fn print_secret(secret: &str) {
+    println!("\n  {}\n", secret);
+    println!("This is the only time the token is shown. Use it as a bearer token:");
+    println!("  Authorization: Bearer <token>");
+}
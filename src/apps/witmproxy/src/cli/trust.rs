@@ -1,6 +1,10 @@
+use crate::cli::api_client::ApiClient;
+use crate::db::Db;
+use crate::db::audit::{AuditAction, AuditEntry, cli_actor};
 use crate::{cert::CertificateAuthority, config::AppConfig};
 use anyhow::Result;
 use clap::Subcommand;
+use tracing::warn;
 
 #[derive(Subcommand)]
 pub enum CaCommands {
@@ -28,25 +32,96 @@ pub enum CaCommands {
 
 pub struct CaHandler {
     config: AppConfig,
+    /// When set, commands target this instance's CA through its web API
+    remote: Option<ApiClient>,
 }
 
 impl CaHandler {
     pub fn new(config: AppConfig) -> Self {
-        Self { config }
+        Self {
+            config,
+            remote: None,
+        }
+    }
+
+    pub fn with_remote(mut self, remote: Option<ApiClient>) -> Self {
+        self.remote = remote;
+        self
     }
 
     pub async fn handle(&self, command: &CaCommands) -> Result<()> {
+        if let Some(ref remote) = self.remote {
+            return Self::handle_remote(remote, command).await;
+        }
+
         // Create certificate authority to access the root certificate
         let ca = CertificateAuthority::new(&self.config.tls.cert_dir).await?;
 
         match command {
             CaCommands::Install { yes, dry_run } => {
-                ca.install_root_certificate(*yes, *dry_run).await
+                ca.install_root_certificate(*yes, *dry_run).await?;
+                if !dry_run {
+                    self.audit(AuditAction::TrustInstall).await;
+                }
+                Ok(())
             }
             CaCommands::Uninstall { yes, dry_run } => {
-                ca.remove_root_certificate(*yes, *dry_run).await
+                ca.remove_root_certificate(*yes, *dry_run).await?;
+                if !dry_run {
+                    self.audit(AuditAction::TrustUninstall).await;
+                }
+                Ok(())
             }
             CaCommands::Status => ca.check_root_certificate_status().await,
         }
     }
+
+    /// The trust store being managed is always this machine's, so only the
+    /// status of a remote instance's CA can be queried.
+    async fn handle_remote(remote: &ApiClient, command: &CaCommands) -> Result<()> {
+        match command {
+            CaCommands::Status => {
+                let resp = ApiClient::check(remote.get("/api/status").await?).await?;
+                let status: serde_json::Value = resp.json().await?;
+                let ca = &status["ca"];
+                println!("Root CA of {}", remote.base_url());
+                match (ca["expires_at"].as_str(), ca["days_remaining"].as_i64()) {
+                    (Some(expires_at), Some(days)) => {
+                        println!("  Expires: {} ({} days remaining)", expires_at, days)
+                    }
+                    _ => println!("  Expiry unknown (the server could not read its certificate)"),
+                }
+                println!("  Download: {}/cert", remote.base_url());
+                Ok(())
+            }
+            CaCommands::Install { .. } | CaCommands::Uninstall { .. } => anyhow::bail!(
+                "The trust store commands manage this machine's trust store and cannot be used with --remote. \
+                 To trust a remote instance, download its certificate from {}/cert",
+                remote.base_url()
+            ),
+        }
+    }
+
+    /// Record a trust store change in the audit log. The trust store has
+    /// already been modified at this point, so failures are only logged.
+    async fn audit(&self, action: AuditAction) {
+        let result = async {
+            let db =
+                Db::from_path(self.config.db.db_path.clone(), &self.config.db.db_password).await?;
+            db.migrate().await?;
+            AuditEntry::record(
+                &db.pool,
+                &cli_actor(),
+                action,
+                Some(&self.config.tls.cert_dir.display().to_string()),
+                serde_json::json!({}),
+            )
+            .await
+        }
+        .await;
+
+        if let Err(e) = result {
+            warn!("Failed to record audit entry for {}: {}", action, e);
+        }
+    }
 }
@@ -37,6 +37,12 @@ pub fn expand_home_in_path(path: &Path) -> Result<PathBuf> {
 #[derive(Config, Clone, Default, Serialize, Deserialize)]
 #[config(layer_attr(derive(Args, Serialize, Clone)))]
 pub struct AppConfig {
+    /// Configuration profile layered over this file, from
+    /// `profiles/<name>.toml` next to it (default: the one chosen with
+    /// `witm profile switch`, if any)
+    #[config(env = "WITMPROXY_PROFILE", layer_attr(arg(long)))]
+    pub profile: Option<String>,
+
     #[config(nested, layer_attr(command(flatten)))]
     pub proxy: ProxyConfig,
 
@@ -58,6 +64,9 @@ pub struct AppConfig {
     #[config(nested, layer_attr(command(flatten)))]
     pub transparent: TransparentProxyConfig,
 
+    #[config(nested, layer_attr(command(flatten)))]
+    pub reverse: ReverseProxyConfig,
+
     #[config(nested, layer_attr(command(flatten)))]
     pub update: UpdateConfig,
 
@@ -71,10 +80,21 @@ pub struct AppConfig {
 #[derive(Clone, Config, Deserialize, Serialize, Default)]
 #[config(layer_attr(derive(Args, Clone, Serialize,)))]
 pub struct ProxyConfig {
-    /// The address the proxy server will bind to (default: 127.0.0.1:0)
+    /// The address the proxy server will bind to (default: 127.0.0.1:0).
+    /// `[::]:<port>` binds dual-stack, accepting both IPv6 and IPv4 clients.
     #[config(env = "PROXY_BIND_ADDR", layer_attr(arg(long)))]
     pub proxy_bind_addr: Option<String>,
 
+    /// Which CONNECT tunnels the proxy intercepts: auto (only hosts plugins
+    /// and rules ask for), always, or passthrough (default: auto)
+    #[config(default = "auto", env = "PROXY_MITM", layer_attr(arg(long)))]
+    pub mitm: crate::proxy::listener::MitmPolicy,
+
+    /// Terminate TLS on `proxy_bind_addr` with a certificate minted for this
+    /// hostname, so clients reach the proxy over HTTPS (default: plain HTTP)
+    #[config(env = "PROXY_TLS_HOSTNAME", layer_attr(arg(long)))]
+    pub proxy_tls_hostname: Option<String>,
+
     /// Tenant resolver strategy: ip-mapping, tailscale, or header (default: ip-mapping)
     #[config(
         default = "ip-mapping",
@@ -86,6 +106,260 @@ pub struct ProxyConfig {
     /// Header name for header-based tenant resolution
     #[config(env = "PROXY_TENANT_HEADER", layer_attr(arg(long)))]
     pub tenant_header: Option<String>,
+
+    /// Additional listeners, each with its own MITM policy, plugin filter and
+    /// auth requirement (config file only, as `[[proxy.listeners]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub listeners: Vec<crate::proxy::listener::ListenerConfig>,
+
+    /// Reject requests with bodies larger than this many bytes (default: unlimited)
+    #[config(env = "PROXY_MAX_REQUEST_BODY_BYTES", layer_attr(arg(long)))]
+    pub max_request_body_bytes: Option<u64>,
+
+    /// Pass responses larger than this many bytes through without handing them
+    /// to content plugins (default: unlimited)
+    #[config(env = "PROXY_MAX_BUFFERED_RESPONSE_BYTES", layer_attr(arg(long)))]
+    pub max_buffered_response_bytes: Option<u64>,
+
+    /// Seconds to wait when connecting to an upstream server (default: 30)
+    #[config(env = "PROXY_UPSTREAM_CONNECT_TIMEOUT_SECS", layer_attr(arg(long)))]
+    pub upstream_connect_timeout_secs: Option<u64>,
+
+    /// Seconds to wait between reads from an upstream server (default: 30)
+    #[config(env = "PROXY_UPSTREAM_READ_TIMEOUT_SECS", layer_attr(arg(long)))]
+    pub upstream_read_timeout_secs: Option<u64>,
+
+    /// Seconds allowed for a whole request/response exchange (default: 60)
+    #[config(env = "PROXY_FLOW_DEADLINE_SECS", layer_attr(arg(long)))]
+    pub flow_deadline_secs: Option<u64>,
+
+    /// Requests allowed in flight to each upstream host at once; further
+    /// requests queue, taking turns between clients (default: unlimited)
+    #[config(env = "PROXY_MAX_REQUESTS_PER_HOST", layer_attr(arg(long)))]
+    pub max_requests_per_host: Option<usize>,
+
+    /// Per-host overrides of `max_requests_per_host`, matching host names or
+    /// `*.domain` patterns (config file only, as `[[proxy.host_limits]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub host_limits: Vec<crate::proxy::host_limits::HostLimitRule>,
+
+    /// Directory with `error.html` and/or `block.html` templates replacing the
+    /// built-in error and block pages
+    #[config(env = "PROXY_ERROR_TEMPLATE_DIR", layer_attr(arg(long)))]
+    pub error_template_dir: Option<PathBuf>,
+
+    /// How to handle intercepted requests whose Host differs from the TLS SNI
+    /// or CONNECT authority: allow, log, or block (default: log)
+    #[config(default = "log", env = "PROXY_HOST_MISMATCH", layer_attr(arg(long)))]
+    pub host_mismatch: crate::proxy::vhost::HostMismatchPolicy,
+
+    /// Hosts refused with a reset, TLS alert, empty response, redirect or
+    /// block page (config file only, as `[[proxy.block_rules]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub block_rules: Vec<crate::proxy::block_rules::BlockRule>,
+
+    /// Headers such as HSTS or CSP to set on responses matching CEL rules
+    /// (config file only, as `[[proxy.security_headers]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub security_headers: Vec<crate::proxy::security_headers::SecurityHeaderRule>,
+
+    /// Tags given to flows matching CEL rules as they complete (config file
+    /// only, as `[[proxy.flow_tags]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub flow_tags: Vec<crate::proxy::flow_tags::FlowTagRule>,
+
+    /// Network profiles defined besides the built-in "3g", "flaky-wifi" and
+    /// "satellite" (config file only, as `[[proxy.network_profiles]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub network_profiles: Vec<crate::proxy::network_profiles::NetworkProfile>,
+
+    /// Network profiles simulated for flows matching a host and/or client
+    /// (config file only, as `[[proxy.network_conditions]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub network_conditions: Vec<crate::proxy::network_profiles::NetworkConditionRule>,
+
+    /// Header profiles defined besides the built-in "iphone", "android",
+    /// "desktop" and "googlebot" (config file only, as
+    /// `[[proxy.header_profiles]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub header_profiles: Vec<crate::proxy::header_overrides::HeaderProfile>,
+
+    /// Header profiles forced on the requests sent to matching hosts (config
+    /// file only, as `[[proxy.header_overrides]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub header_overrides: Vec<crate::proxy::header_overrides::HeaderOverrideRule>,
+
+    /// Count the bytes each client profile transfers with each host, by day
+    /// and month, enforcing the profiles' quotas (default: false)
+    #[config(default = false, env = "PROXY_ACCOUNT_TRAFFIC", layer_attr(arg(long)))]
+    pub account_traffic: bool,
+
+    /// Clients whose traffic is counted and limited together (config file
+    /// only, as `[[proxy.client_profiles]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub client_profiles: Vec<crate::proxy::traffic::ClientProfile>,
+
+    /// Hosts whose flows are sent out through another network interface,
+    /// such as a WireGuard tunnel's, or a SOCKS proxy, or with a browser's
+    /// TLS client hello (config file only, as `[[proxy.egress]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub egress: Vec<crate::proxy::egress::EgressRoute>,
+
+    /// Message types to decode protobuf bodies as, by host and path, naming
+    /// types from descriptor sets uploaded with `witm protobuf add` (config
+    /// file only, as `[[proxy.protobuf]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub protobuf: Vec<crate::proxy::protobuf::ProtobufMapping>,
+
+    /// Infer API schemas from intercepted JSON traffic, for export with
+    /// `witm schema export` (default: false)
+    #[config(default = false, env = "PROXY_INFER_SCHEMAS", layer_attr(arg(long)))]
+    pub infer_schemas: bool,
+
+    /// Send plain HTTP requests to hosts on the HSTS preload list, or which
+    /// sent a `Strict-Transport-Security` header before, over HTTPS instead
+    /// (default: false)
+    #[config(default = false, env = "PROXY_HTTPS_UPGRADE", layer_attr(arg(long)))]
+    pub https_upgrade: bool,
+
+    /// URL the HSTS preload list is fetched from daily, in the format of
+    /// Chromium's `transport_security_state_static.json`. Until then, a
+    /// bundled seed of the list is used.
+    #[config(env = "PROXY_HSTS_PRELOAD_URL", layer_attr(arg(long)))]
+    pub hsts_preload_url: Option<String>,
+
+    /// Remove tracking query parameters such as `utm_*`, `fbclid` and
+    /// `gclid` from requests before plugins or origins see them (default: false)
+    #[config(
+        default = false,
+        env = "PROXY_STRIP_TRACKING_PARAMS",
+        layer_attr(arg(long))
+    )]
+    pub strip_tracking_params: bool,
+
+    /// Tracking parameters stripped besides the built-in ones, where a
+    /// trailing `*` matches any suffix (config file only)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub tracking_params: Vec<String>,
+
+    /// URL the tracking parameter list is fetched from daily, with one
+    /// parameter per line, replacing the built-in list
+    #[config(env = "PROXY_TRACKING_PARAMS_URL", layer_attr(arg(long)))]
+    pub tracking_params_url: Option<String>,
+
+    /// Remove cookies from cross-site requests, and those their responses
+    /// set (default: false)
+    #[config(
+        default = false,
+        env = "PROXY_STRIP_THIRD_PARTY_COOKIES",
+        layer_attr(arg(long))
+    )]
+    pub strip_third_party_cookies: bool,
+
+    /// Per-host overrides of `strip_tracking_params` and
+    /// `strip_third_party_cookies` (config file only, as
+    /// `[[proxy.privacy_overrides]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub privacy_overrides: Vec<crate::proxy::privacy::PrivacyOverride>,
+
+    /// Transcode JPEG, PNG and WebP images in responses, dropping their
+    /// metadata, scaling them down to `image_max_dimension` and converting
+    /// them to `image_format` (default: false)
+    #[config(default = false, env = "PROXY_OPTIMIZE_IMAGES", layer_attr(arg(long)))]
+    pub optimize_images: bool,
+
+    /// Scale images wider or taller than this many pixels down to fit
+    /// (default: unlimited)
+    #[config(env = "PROXY_IMAGE_MAX_DIMENSION", layer_attr(arg(long)))]
+    pub image_max_dimension: Option<u32>,
+
+    /// Convert images to webp, avif, jpeg or png, for clients whose `Accept`
+    /// header allows it (default: keep their format)
+    #[config(env = "PROXY_IMAGE_FORMAT", layer_attr(arg(long)))]
+    pub image_format: Option<crate::plugins::images::ImageFormat>,
+
+    /// Quality of lossy image encodings, from 1 to 100 (default: 80)
+    #[config(env = "PROXY_IMAGE_QUALITY", layer_attr(arg(long)))]
+    pub image_quality: Option<u8>,
+
+    /// Pass images larger than this many bytes through untouched
+    /// (default: 16 MiB)
+    #[config(env = "PROXY_MAX_IMAGE_BYTES", layer_attr(arg(long)))]
+    pub max_image_bytes: Option<usize>,
+
+    /// Compress responses upstream sent uncompressed with brotli or gzip
+    /// for clients which accept them, and decompress responses for clients
+    /// which don't accept their encoding (default: false)
+    #[config(
+        default = false,
+        env = "PROXY_COMPRESS_RESPONSES",
+        layer_attr(arg(long))
+    )]
+    pub compress_responses: bool,
+
+    /// Prefixes of the content types compressed, ex: `text/` (config file
+    /// only; default: text, JSON, JavaScript, XML and SVG)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub compress_content_types: Vec<String>,
+
+    /// Leave responses shorter than this many bytes uncompressed
+    /// (default: 1024)
+    #[config(env = "PROXY_COMPRESS_MIN_BYTES", layer_attr(arg(long)))]
+    pub compress_min_bytes: Option<u64>,
+
+    /// Language code, ex: `en`, pages from `translate_hosts` are translated
+    /// into before they're delivered (default: not translated)
+    #[config(env = "PROXY_TRANSLATE_TO", layer_attr(arg(long)))]
+    pub translate_to: Option<String>,
+
+    /// Hosts whose pages are translated, as host names or `*.domain`
+    /// patterns (config file only)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub translate_hosts: Vec<String>,
+
+    /// Service pages are translated with: libre-translate or openai, for any
+    /// OpenAI-compatible chat completions API (default: libre-translate)
+    #[config(
+        default = "libre-translate",
+        env = "PROXY_TRANSLATION_BACKEND",
+        layer_attr(arg(long))
+    )]
+    pub translation_backend: crate::proxy::translation::TranslationBackend,
+
+    /// The LibreTranslate server, or the base URL of the OpenAI-compatible
+    /// API (ex: `https://api.openai.com/v1`), pages are translated with
+    #[config(env = "PROXY_TRANSLATION_URL", layer_attr(arg(long)))]
+    pub translation_url: Option<String>,
+
+    /// API key sent to the translation backend
+    #[config(env = "PROXY_TRANSLATION_API_KEY", layer_attr(arg(long)))]
+    pub translation_api_key: Option<String>,
+
+    /// Model asked to translate, for the openai backend (default: gpt-4o-mini)
+    #[config(env = "PROXY_TRANSLATION_MODEL", layer_attr(arg(long)))]
+    pub translation_model: Option<String>,
+
+    /// Scan intercepted flows for sensitive data (JWTs, API tokens, card
+    /// numbers, emails) and annotate them with what's found (default: true)
+    #[config(
+        default = true,
+        env = "PROXY_DETECT_SENSITIVE_DATA",
+        layer_attr(arg(long = "detect-sensitive-data", id = "detect-sensitive-data"))
+    )]
+    pub detect_sensitive_data: bool,
+
+    /// Redact sensitive data from the URLs kept in the flow log (default: false)
+    #[config(
+        default = false,
+        env = "PROXY_REDACT_SENSITIVE_DATA",
+        layer_attr(arg(long))
+    )]
+    pub redact_sensitive_data: bool,
+
+    /// Client addresses allowed to trace their intercepted flows by sending
+    /// `X-Witmproxy-Debug: 1` (config file only, as a list of IP addresses)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub debug_clients: Vec<std::net::IpAddr>,
 }
 
 #[derive(Clone, Config, Deserialize, Serialize, Default)]
@@ -165,6 +439,30 @@ pub struct TransparentProxyConfig {
     pub auto_iptables: bool,
 }
 
+#[derive(Clone, Config, Deserialize, Serialize, Default)]
+#[config(layer_attr(derive(Args, Clone, Serialize,)))]
+pub struct ReverseProxyConfig {
+    /// Enable reverse proxy mode (default: false)
+    #[config(
+        default = false,
+        env = "REVERSE_ENABLED",
+        layer_attr(arg(long = "reverse-enabled", id = "reverse-enabled"))
+    )]
+    pub enabled: bool,
+
+    /// Listen address for the reverse proxy (default: 0.0.0.0:8443)
+    #[config(
+        env = "REVERSE_LISTEN_ADDR",
+        layer_attr(arg(long = "reverse-listen-addr"))
+    )]
+    pub listen_addr: Option<String>,
+
+    /// Host names served by the reverse proxy and the origins they're
+    /// forwarded to (config file only, as `[[reverse.routes]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub routes: Vec<crate::proxy::reverse::ReverseRoute>,
+}
+
 #[derive(Clone, Config, Deserialize, Serialize, Default)]
 #[config(layer_attr(derive(Args, Clone, Serialize,)))]
 pub struct DbConfig {
@@ -190,6 +488,23 @@ pub struct DbConfig {
     /// The database password
     #[config(env = "DB_PASSWORD", layer_attr(arg(long)))]
     pub db_password: String,
+
+    /// Prune audit log entries older than this many days (default: kept
+    /// forever). Entries are always kept for at least a day.
+    #[config(env = "DB_AUDIT_MAX_AGE_DAYS", layer_attr(arg(long)))]
+    pub audit_max_age_days: Option<u32>,
+
+    /// Prune the oldest audit log entries beyond this many (default: unlimited)
+    #[config(env = "DB_AUDIT_MAX_ENTRIES", layer_attr(arg(long)))]
+    pub audit_max_entries: Option<u64>,
+
+    /// How often to apply the retention limits while running (default: 3600)
+    #[config(
+        default = 3600,
+        env = "DB_PRUNE_INTERVAL_SECONDS",
+        layer_attr(arg(long))
+    )]
+    pub prune_interval_seconds: u64,
 }
 
 #[derive(Clone, Config, Deserialize, Serialize, Default)]
@@ -221,6 +536,79 @@ pub struct TlsConfig {
         )
     )]
     pub cert_dir: PathBuf,
+
+    /// PEM bundle of additional CAs trusted for upstream servers, on top of
+    /// the system trust store (e.g. a corporate TLS inspection root)
+    #[config(env = "TLS_UPSTREAM_CA_BUNDLE", layer_attr(arg(long)))]
+    pub upstream_ca_bundle: Option<PathBuf>,
+
+    /// Upstream hosts whose certificates must match one of the given SHA-256
+    /// fingerprints (config file only, as `[[tls.upstream_pins]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub upstream_pins: Vec<crate::proxy::upstream_tls::UpstreamPin>,
+
+    /// Upstream hosts whose certificates are not verified at all. Only takes
+    /// effect together with allow_insecure_upstream (config file only)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub upstream_insecure_hosts: Vec<String>,
+
+    /// Permit upstream_insecure_hosts to disable certificate verification (default: false)
+    #[config(
+        default = false,
+        env = "TLS_ALLOW_INSECURE_UPSTREAM",
+        layer_attr(arg(long))
+    )]
+    pub allow_insecure_upstream: bool,
+
+    /// Oldest TLS version offered to clients, 1.2 or 1.3 (default: 1.2)
+    #[config(env = "TLS_CLIENT_MIN_VERSION", layer_attr(arg(long)))]
+    pub client_min_version: Option<crate::proxy::tls_policy::TlsVersion>,
+
+    /// Newest TLS version offered to clients, 1.2 or 1.3 (default: 1.3)
+    #[config(env = "TLS_CLIENT_MAX_VERSION", layer_attr(arg(long)))]
+    pub client_max_version: Option<crate::proxy::tls_policy::TlsVersion>,
+
+    /// Cipher suites offered to clients, in order, ex:
+    /// "TLS13_AES_256_GCM_SHA384" (config file only)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub client_cipher_suites: Vec<String>,
+
+    /// ALPN protocols offered to clients, in order (default: h2, http/1.1;
+    /// config file only)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub client_alpn: Vec<String>,
+
+    /// Oldest TLS version offered to upstream servers, 1.2 or 1.3 (default: 1.2)
+    #[config(env = "TLS_UPSTREAM_MIN_VERSION", layer_attr(arg(long)))]
+    pub upstream_min_version: Option<crate::proxy::tls_policy::TlsVersion>,
+
+    /// Newest TLS version offered to upstream servers, 1.2 or 1.3 (default: 1.3)
+    #[config(env = "TLS_UPSTREAM_MAX_VERSION", layer_attr(arg(long)))]
+    pub upstream_max_version: Option<crate::proxy::tls_policy::TlsVersion>,
+
+    /// Cipher suites offered to upstream servers, in order (config file only)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub upstream_cipher_suites: Vec<String>,
+
+    /// ALPN protocols offered to upstream servers, in order (default: h2,
+    /// http/1.1; config file only)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub upstream_alpn: Vec<String>,
+
+    /// Hosts whose TLS version, cipher suite and ALPN policies differ from
+    /// the ones above (config file only, as `[[tls.host_policies]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub host_policies: Vec<crate::proxy::tls_policy::TlsHostPolicy>,
+
+    /// URL of the web server's `/ocsp` endpoint, written into minted
+    /// certificates. When set, an OCSP response is also stapled to them.
+    #[config(env = "TLS_OCSP_RESPONDER_URL", layer_attr(arg(long)))]
+    pub ocsp_responder_url: Option<String>,
+
+    /// Hosts served deliberately invalid certificates (config file only, as
+    /// `[[tls.cert_faults]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub cert_faults: Vec<crate::cert::faults::CertFaultRule>,
 }
 
 #[derive(Clone, Config, Deserialize, Serialize, Default)]
@@ -245,6 +633,45 @@ pub struct PluginConfig {
     /// WASM fuel limit per plugin execution (default: 1000000)
     #[config(default = 1_000_000, env = "PLUGINS_MAX_FUEL", layer_attr(arg(long)))]
     pub max_fuel: u64,
+
+    /// Registry `@namespace/name` plugins are installed from
+    /// (default: https://registry.witmproxy.rs)
+    #[config(
+        default = "https://registry.witmproxy.rs",
+        env = "PLUGINS_REGISTRY_URL",
+        layer_attr(arg(long))
+    )]
+    pub registry_url: String,
+
+    /// JWKS endpoints plugins with the `jwt` capability verify tokens against
+    /// (config file only, as `jwks_urls = [...]`)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub jwks_urls: Vec<String>,
+
+    /// Hex-encoded public keys plugins have to be signed with, checked
+    /// against the keys stored with them; plugins signed with any key are
+    /// run when empty (config file only, as `trusted_keys = [...]`)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub trusted_keys: Vec<String>,
+
+    /// Headers, cookies and body fields removed from events before plugins
+    /// are given them (config file only, as `[[plugins.redactions]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub redactions: Vec<crate::plugins::redaction::RedactionRule>,
+
+    /// ONNX models plugins with the `inference` capability can run (config
+    /// file only, as `[[plugins.models]]` tables)
+    #[config(default = [], layer_attr(arg(skip)))]
+    pub models: Vec<crate::plugins::inference::ModelConfig>,
+
+    /// Record the local storage keys plugins read and write while handling
+    /// each flow, for inspecting their decisions later (default: false)
+    #[config(
+        default = false,
+        env = "PLUGINS_SNAPSHOT_STORAGE",
+        layer_attr(arg(long))
+    )]
+    pub snapshot_storage: bool,
 }
 
 #[derive(Clone, Config, Deserialize, Serialize, Default)]
@@ -375,6 +802,27 @@ pub struct TelemetryConfig {
         layer_attr(arg(long = "otel-resource-metrics-interval"))
     )]
     pub resource_metrics_interval_secs: u64,
+
+    /// Identifies this instance in audit entries, telemetry and the status
+    /// endpoint when several run side by side (default: the host name)
+    #[config(env = "INSTANCE_ID", layer_attr(arg(long = "instance-id")))]
+    pub instance_id: Option<String>,
+}
+
+impl TelemetryConfig {
+    /// The configured instance ID, or else the host name
+    pub fn instance_id(&self) -> String {
+        self.instance_id
+            .clone()
+            .or_else(|| std::env::var("HOSTNAME").ok())
+            .or_else(|| {
+                std::fs::read_to_string("/etc/hostname")
+                    .ok()
+                    .map(|name| name.trim().to_string())
+            })
+            .filter(|id| !id.is_empty())
+            .unwrap_or_else(|| "unknown".to_string())
+    }
 }
 
 impl AppConfig {
@@ -412,6 +860,16 @@ impl AppConfig {
             self.web.web_tls_key_path = Some(expand_home_in_path(p)?);
         }
 
+        // Resolve reverse proxy certificate paths
+        for route in &mut self.reverse.routes {
+            if let Some(ref p) = route.tls_cert_path {
+                route.tls_cert_path = Some(expand_home_in_path(p)?);
+            }
+            if let Some(ref p) = route.tls_key_path {
+                route.tls_key_path = Some(expand_home_in_path(p)?);
+            }
+        }
+
         Ok(self)
     }
 }
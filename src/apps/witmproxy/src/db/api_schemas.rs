@@ -0,0 +1,158 @@
+//! Schemas inferred from traffic, saved so they accumulate across restarts.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use sqlx::SqlitePool;
+use tracing::warn;
+
+use crate::proxy::api_schemas::{ApiSchemas, HostSchema};
+
+/// How often the running proxy saves schemas which have changed
+pub const PERSIST_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct StoredApiSchema {
+    pub host: String,
+    /// The [HostSchema], as JSON
+    pub schema: String,
+    pub updated_at: String,
+}
+
+impl StoredApiSchema {
+    pub fn schema(&self) -> Result<HostSchema> {
+        Ok(serde_json::from_str(&self.schema)?)
+    }
+
+    pub async fn list(pool: &SqlitePool) -> Result<Vec<Self>> {
+        let schemas =
+            sqlx::query_as::<_, StoredApiSchema>("SELECT * FROM api_schemas ORDER BY host")
+                .fetch_all(pool)
+                .await?;
+        Ok(schemas)
+    }
+
+    pub async fn get(pool: &SqlitePool, host: &str) -> Result<Option<Self>> {
+        let schema =
+            sqlx::query_as::<_, StoredApiSchema>("SELECT * FROM api_schemas WHERE host = ?")
+                .bind(host.to_ascii_lowercase())
+                .fetch_optional(pool)
+                .await?;
+        Ok(schema)
+    }
+
+    pub async fn upsert(pool: &SqlitePool, host: &str, schema: &HostSchema) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO api_schemas (host, schema) VALUES (?, ?)
+             ON CONFLICT(host) DO UPDATE SET schema = excluded.schema, updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(host.to_ascii_lowercase())
+        .bind(serde_json::to_string(schema)?)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Delete the schema inferred for `host`. Returns false if there was none.
+    pub async fn delete(pool: &SqlitePool, host: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM api_schemas WHERE host = ?")
+            .bind(host.to_ascii_lowercase())
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// Load every saved schema into `schemas`, skipping any which can't be read
+pub async fn load(pool: &SqlitePool, schemas: &ApiSchemas) -> Result<()> {
+    for stored in StoredApiSchema::list(pool).await? {
+        match stored.schema() {
+            Ok(schema) => schemas.load(&stored.host, schema),
+            Err(e) => warn!("Skipping inferred schema for {}: {}", stored.host, e),
+        }
+    }
+    Ok(())
+}
+
+/// Save the schemas which have changed since they were last saved
+pub async fn persist(pool: &SqlitePool, schemas: &ApiSchemas) -> Result<()> {
+    for (host, schema) in schemas.take_changed() {
+        StoredApiSchema::upsert(pool, &host, &schema).await?;
+    }
+    Ok(())
+}
+
+/// Save changed schemas every `interval` until the task is dropped
+pub async fn persist_loop(pool: SqlitePool, schemas: ApiSchemas, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = persist(&pool, &schemas).await {
+            warn!("Failed to save inferred API schemas: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn schemas_are_saved_and_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Db::from_path(dir.path().join("test.db"), "password")
+            .await
+            .unwrap();
+        db.migrate().await.unwrap();
+
+        let schemas = ApiSchemas::default();
+        let req = hyper::Request::get("https://api.example.com/status")
+            .body(
+                http_body_util::Empty::new()
+                    .map_err(|never| match never {})
+                    .boxed_unsync(),
+            )
+            .unwrap();
+        schemas.observe_request(req, "api.example.com");
+        persist(&db.pool, &schemas).await.unwrap();
+
+        let loaded = ApiSchemas::default();
+        load(&db.pool, &loaded).await.unwrap();
+        assert_eq!(
+            loaded.get("api.example.com"),
+            schemas.get("api.example.com")
+        );
+        assert!(
+            StoredApiSchema::delete(&db.pool, "API.example.com")
+                .await
+                .unwrap()
+        );
+        assert!(StoredApiSchema::list(&db.pool).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn mixed_case_hosts_are_found_after_loading() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Db::from_path(dir.path().join("test.db"), "password")
+            .await
+            .unwrap();
+        db.migrate().await.unwrap();
+
+        let schema = HostSchema::default();
+        StoredApiSchema::upsert(&db.pool, "API.Example.com", &schema)
+            .await
+            .unwrap();
+        let loaded = ApiSchemas::default();
+        load(&db.pool, &loaded).await.unwrap();
+        assert_eq!(loaded.get("api.example.com"), Some(schema.clone()));
+        assert_eq!(loaded.get("Api.EXAMPLE.com"), Some(schema));
+        assert!(
+            StoredApiSchema::get(&db.pool, "api.example.com")
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+}
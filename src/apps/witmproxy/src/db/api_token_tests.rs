@@ -0,0 +1,129 @@
+use crate::db::Db;
+use crate::db::api_tokens::*;
+
+async fn setup_db() -> (Db, tempfile::TempDir) {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("test.db");
+    let db = Db::from_path(db_path, "test_password").await.unwrap();
+    db.migrate().await.unwrap();
+    (db, temp_dir)
+}
+
+#[tokio::test]
+async fn create_authenticate_and_revoke() {
+    let (db, _dir) = setup_db().await;
+    let pool = &db.pool;
+
+    let (token, secret) = ApiToken::create(pool, "ci", Role::Viewer, None)
+        .await
+        .unwrap();
+    assert!(secret.starts_with(API_TOKEN_PREFIX));
+    assert_ne!(token.token_hash, secret, "plaintext must not be stored");
+    assert_eq!(token.role().unwrap(), Role::Viewer);
+    assert!(token.last_used_at.is_none());
+
+    let found = ApiToken::authenticate(pool, &secret)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.id, token.id);
+    let reloaded = ApiToken::by_id(pool, &token.id).await.unwrap().unwrap();
+    assert!(reloaded.last_used_at.is_some());
+
+    assert!(
+        ApiToken::authenticate(pool, "wpt_bogus")
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    assert!(ApiToken::revoke(pool, &token.id).await.unwrap());
+    assert!(
+        ApiToken::authenticate(pool, &secret)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(!ApiToken::revoke(pool, "missing").await.unwrap());
+}
+
+#[tokio::test]
+async fn rotate_invalidates_old_secret() {
+    let (db, _dir) = setup_db().await;
+    let pool = &db.pool;
+
+    let (token, old) = ApiToken::create(pool, "deploy", Role::Admin, None)
+        .await
+        .unwrap();
+    let new = ApiToken::rotate(pool, &token.id).await.unwrap().unwrap();
+    assert_ne!(old, new);
+    assert!(ApiToken::authenticate(pool, &old).await.unwrap().is_none());
+    assert!(ApiToken::authenticate(pool, &new).await.unwrap().is_some());
+
+    // Revoked tokens cannot be brought back by rotating them
+    ApiToken::revoke(pool, &token.id).await.unwrap();
+    assert!(ApiToken::rotate(pool, &token.id).await.unwrap().is_none());
+
+    let by_name = ApiToken::find(pool, "deploy").await.unwrap().unwrap();
+    assert_eq!(by_name.id, token.id);
+}
+
+#[tokio::test]
+async fn expired_tokens_are_rejected() {
+    let (db, _dir) = setup_db().await;
+    let pool = &db.pool;
+
+    let past = chrono::Utc::now() - chrono::Duration::hours(1);
+    let (_, secret) = ApiToken::create(pool, "old", Role::Admin, Some(past))
+        .await
+        .unwrap();
+    assert!(
+        ApiToken::authenticate(pool, &secret)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let future = chrono::Utc::now() + chrono::Duration::days(1);
+    let (_, secret) = ApiToken::create(pool, "new", Role::Admin, Some(future))
+        .await
+        .unwrap();
+    assert!(
+        ApiToken::authenticate(pool, &secret)
+            .await
+            .unwrap()
+            .is_some()
+    );
+}
+
+#[test]
+fn viewer_role_only_reads_listed_routes() {
+    assert!(Role::Admin.permits("POST", "/api/plugins"));
+    assert!(Role::Admin.permits("GET", "/api/plugins/ns/name/config"));
+    assert!(Role::Admin.permits("GET", "/api/manage/flows/1/storage"));
+
+    assert!(Role::Viewer.permits("GET", "/api/plugins"));
+    assert!(Role::Viewer.permits("HEAD", "/api/manage/audit"));
+    assert!(Role::Viewer.permits("GET", "/api/manage/flows"));
+    assert!(Role::Viewer.permits("GET", "/api/manage/flows/1/timeline"));
+    assert!(Role::Viewer.permits("GET", "/api/manage/flows//1/%74imeline/"));
+    assert!(!Role::Viewer.permits("POST", "/api/plugins"));
+    assert!(!Role::Viewer.permits("DELETE", "/api/plugins/ns/name"));
+
+    for path in [
+        "/api/plugins/ns/name/config",
+        "/api/plugins/ns/name/config/",
+        "/api/plugins/ns/name//config",
+        "/api/manage/flows/1/%73torage",
+        "/api/manage/tenants/acme/plugins/ns/name/config",
+        "/api/manage/flows/1/trace",
+        "/api/manage/flows/1/storage",
+        "/api/manage/schemas/api.example.com",
+        "/api/manage/sessions/1/flows",
+        "/api/manage/traffic",
+        "/api/manage/routes-added-later",
+    ] {
+        assert!(!Role::Viewer.permits("GET", path), "{path}");
+        assert!(!Role::Viewer.permits("HEAD", path), "{path}");
+    }
+}
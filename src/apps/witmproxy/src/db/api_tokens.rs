@@ -0,0 +1,239 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+
+/// Prefix of every API token, used to tell them apart from JWTs
+pub const API_TOKEN_PREFIX: &str = "wpt_";
+
+/// Routes viewers may read. Anything else, including routes added later,
+/// is only for admins until it's listed here: plugin configuration may
+/// include credentials, and flow traces, plugin storage, captured sessions,
+/// inferred API schemas and traffic reports hold the data flows carried.
+/// `*` matches any segment.
+pub(crate) const VIEWER_ROUTES: &[&str] = &[
+    "/api/status",
+    "/api/manage/audit",
+    "/api/manage/tenants",
+    "/api/manage/tenants/*",
+    "/api/manage/groups",
+    "/api/manage/groups/*/members",
+    "/api/manage/groups/*/permissions",
+    "/api/manage/security-headers",
+    "/api/manage/flow-tags",
+    "/api/manage/network-conditions",
+    "/api/manage/header-overrides/profiles",
+    "/api/manage/mocks",
+    "/api/manage/scripts",
+    "/api/manage/protobuf/descriptors",
+    "/api/manage/protobuf/mappings",
+    "/api/manage/flows",
+    "/api/manage/flows/*/timeline",
+    "/api/manage/sessions",
+    "/api/manage/capability-audit",
+    "/api/manage/capability-audit/*",
+    "/api/manage/flow-filters",
+    "/api/plugins",
+    "/api/plugins/*/*/candidate",
+];
+
+/// Role attached to an API token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Full access to the management API
+    Admin,
+    /// Read-only access to the routes of [VIEWER_ROUTES]
+    Viewer,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    /// Whether a request with the given method and path is allowed for this role.
+    ///
+    /// Viewers may only issue safe (`GET`/`HEAD`) requests, and only to the
+    /// routes of [VIEWER_ROUTES].
+    pub fn permits(&self, method: &str, path: &str) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::Viewer => {
+                matches!(method, "GET" | "HEAD")
+                    && VIEWER_ROUTES.iter().any(|route| route_matches(route, path))
+            }
+        }
+    }
+}
+
+/// Whether `path` is one of `route`'s, with its segments split and decoded
+/// the way the router does, so `//` or escapes can't route around the check
+fn route_matches(route: &str, path: &str) -> bool {
+    let mut route = route.split('/').filter(|segment| !segment.is_empty());
+    let mut path = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy());
+    loop {
+        match (route.next(), path.next()) {
+            (None, None) => return true,
+            (Some(expected), Some(segment)) if expected == "*" || expected == segment => {}
+            _ => return false,
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "admin" => Ok(Role::Admin),
+            "viewer" => Ok(Role::Viewer),
+            other => anyhow::bail!("Unknown role: {}", other),
+        }
+    }
+}
+
+/// A long-lived bearer token for the management API. Only a SHA-256 hash of
+/// the token is stored; the plaintext is shown once when issued or rotated.
+#[derive(Debug, Clone, sqlx::FromRow, Serialize)]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub role: String,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+    /// Expiry (`YYYY-MM-DD HH:MM:SS`, UTC); never expires when unset
+    pub expires_at: Option<String>,
+    pub revoked: bool,
+}
+
+fn generate_secret() -> String {
+    format!(
+        "{}{}{}",
+        API_TOKEN_PREFIX,
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+impl ApiToken {
+    /// The token's role, parsed from the stored column
+    pub fn role(&self) -> Result<Role> {
+        self.role.parse()
+    }
+
+    /// Issue a new token. Returns the stored record and the plaintext token.
+    pub async fn create(
+        pool: &SqlitePool,
+        name: &str,
+        role: Role,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(Self, String)> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let secret = generate_secret();
+        sqlx::query(
+            "INSERT INTO api_tokens (id, name, token_hash, role, expires_at) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(&id)
+        .bind(name)
+        .bind(hash_secret(&secret))
+        .bind(role.as_str())
+        .bind(expires_at.map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()))
+        .execute(pool)
+        .await?;
+
+        let token = Self::by_id(pool, &id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Token {} vanished after insert", id))?;
+        Ok((token, secret))
+    }
+
+    pub async fn by_id(pool: &SqlitePool, id: &str) -> Result<Option<Self>> {
+        let token = sqlx::query_as::<_, ApiToken>("SELECT * FROM api_tokens WHERE id = ?")
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+        Ok(token)
+    }
+
+    /// Look up a token by ID or, failing that, by name
+    pub async fn find(pool: &SqlitePool, id_or_name: &str) -> Result<Option<Self>> {
+        let token =
+            sqlx::query_as::<_, ApiToken>("SELECT * FROM api_tokens WHERE id = ? OR name = ?")
+                .bind(id_or_name)
+                .bind(id_or_name)
+                .fetch_optional(pool)
+                .await?;
+        Ok(token)
+    }
+
+    pub async fn list(pool: &SqlitePool) -> Result<Vec<Self>> {
+        let tokens =
+            sqlx::query_as::<_, ApiToken>("SELECT * FROM api_tokens ORDER BY created_at, name")
+                .fetch_all(pool)
+                .await?;
+        Ok(tokens)
+    }
+
+    /// Resolve a plaintext bearer token to an active (unrevoked, unexpired)
+    /// token, recording its use.
+    pub async fn authenticate(pool: &SqlitePool, secret: &str) -> Result<Option<Self>> {
+        let token = sqlx::query_as::<_, ApiToken>(
+            "SELECT * FROM api_tokens WHERE token_hash = ? AND revoked = 0
+             AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)",
+        )
+        .bind(hash_secret(secret))
+        .fetch_optional(pool)
+        .await?;
+
+        if let Some(ref token) = token {
+            sqlx::query("UPDATE api_tokens SET last_used_at = CURRENT_TIMESTAMP WHERE id = ?")
+                .bind(&token.id)
+                .execute(pool)
+                .await?;
+        }
+        Ok(token)
+    }
+
+    /// Revoke a token. Returns false if no such token exists.
+    pub async fn revoke(pool: &SqlitePool, id: &str) -> Result<bool> {
+        let result = sqlx::query("UPDATE api_tokens SET revoked = 1 WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Replace the secret of an unrevoked token, invalidating the old one.
+    /// Returns the new plaintext token, or `None` if no such token is active.
+    pub async fn rotate(pool: &SqlitePool, id: &str) -> Result<Option<String>> {
+        let secret = generate_secret();
+        let result =
+            sqlx::query("UPDATE api_tokens SET token_hash = ? WHERE id = ? AND revoked = 0")
+                .bind(hash_secret(&secret))
+                .bind(id)
+                .execute(pool)
+                .await?;
+        Ok((result.rows_affected() > 0).then_some(secret))
+    }
+}
@@ -0,0 +1,204 @@
+use std::sync::OnceLock;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+
+/// The instance recorded on entries written by this process
+static INSTANCE: OnceLock<String> = OnceLock::new();
+
+/// Record `instance` on every entry this process writes from now on, so
+/// entries from several instances sharing a deployment can be told apart.
+/// Only the first call has any effect.
+pub fn set_instance(instance: String) {
+    let _ = INSTANCE.set(instance);
+}
+
+/// Administrative actions recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    PluginInstall,
+    PluginRemove,
+    PluginEnable,
+    PluginConfigure,
+    PluginIntegrityFailure,
+    CapabilityGrant,
+    ConfigUpdate,
+    ConfigReload,
+    TrustInstall,
+    TrustUninstall,
+    TenantUpdate,
+    TenantDelete,
+    GroupCreate,
+    GroupDelete,
+    GroupMemberAdd,
+    GroupMemberRemove,
+    PermissionGrant,
+    PermissionRevoke,
+    IpMappingAdd,
+    IpMappingRemove,
+    FlowDelete,
+    FlowTag,
+    FlowUntag,
+    FlowFilterSave,
+    FlowFilterDelete,
+    SessionStart,
+    SessionStop,
+    TokenCreate,
+    TokenRevoke,
+    TokenRotate,
+    AuditPrune,
+    MockUpload,
+    MockRemove,
+    ProtobufUpload,
+    ProtobufRemove,
+    SchemaClear,
+    ScriptUpload,
+    ScriptRemove,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::PluginInstall => "plugin.install",
+            AuditAction::PluginRemove => "plugin.remove",
+            AuditAction::PluginEnable => "plugin.enable",
+            AuditAction::PluginConfigure => "plugin.configure",
+            AuditAction::PluginIntegrityFailure => "plugin.integrity_failure",
+            AuditAction::CapabilityGrant => "capability.grant",
+            AuditAction::ConfigUpdate => "config.update",
+            AuditAction::ConfigReload => "config.reload",
+            AuditAction::TrustInstall => "trust.install",
+            AuditAction::TrustUninstall => "trust.uninstall",
+            AuditAction::TenantUpdate => "tenant.update",
+            AuditAction::TenantDelete => "tenant.delete",
+            AuditAction::GroupCreate => "group.create",
+            AuditAction::GroupDelete => "group.delete",
+            AuditAction::GroupMemberAdd => "group.member.add",
+            AuditAction::GroupMemberRemove => "group.member.remove",
+            AuditAction::PermissionGrant => "permission.grant",
+            AuditAction::PermissionRevoke => "permission.revoke",
+            AuditAction::IpMappingAdd => "ip_mapping.add",
+            AuditAction::IpMappingRemove => "ip_mapping.remove",
+            AuditAction::FlowDelete => "flow.delete",
+            AuditAction::FlowTag => "flow.tag",
+            AuditAction::FlowUntag => "flow.untag",
+            AuditAction::FlowFilterSave => "flow_filter.save",
+            AuditAction::FlowFilterDelete => "flow_filter.delete",
+            AuditAction::SessionStart => "session.start",
+            AuditAction::SessionStop => "session.stop",
+            AuditAction::TokenCreate => "token.create",
+            AuditAction::TokenRevoke => "token.revoke",
+            AuditAction::TokenRotate => "token.rotate",
+            AuditAction::AuditPrune => "audit.prune",
+            AuditAction::MockUpload => "mock.upload",
+            AuditAction::MockRemove => "mock.remove",
+            AuditAction::ProtobufUpload => "protobuf.upload",
+            AuditAction::ProtobufRemove => "protobuf.remove",
+            AuditAction::SchemaClear => "schema.clear",
+            AuditAction::ScriptUpload => "script.upload",
+            AuditAction::ScriptRemove => "script.remove",
+        }
+    }
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A single row of the append-only `audit_log` table
+#[derive(Debug, Clone, sqlx::FromRow, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub timestamp: String,
+    /// Who performed the action, e.g. `cli:alice` or `tenant:<id>`
+    pub actor: String,
+    pub action: String,
+    /// The object acted upon, e.g. a plugin ID or group ID
+    pub target: Option<String>,
+    /// JSON-encoded, action-specific details
+    pub details: String,
+    /// The instance which recorded the entry, if it identified itself
+    pub instance: Option<String>,
+}
+
+/// Filters for [`AuditEntry::query`]; unset fields match everything
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub instance: Option<String>,
+    /// Only entries at or after this timestamp (`YYYY-MM-DD HH:MM:SS`, UTC)
+    pub since: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Default and maximum number of entries returned by a query
+pub const DEFAULT_AUDIT_LIMIT: i64 = 100;
+pub const MAX_AUDIT_LIMIT: i64 = 1000;
+
+impl AuditEntry {
+    /// Append an entry to the audit log
+    pub async fn record(
+        pool: &SqlitePool,
+        actor: &str,
+        action: AuditAction,
+        target: Option<&str>,
+        details: serde_json::Value,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO audit_log (actor, action, target, details, instance) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(actor)
+        .bind(action.as_str())
+        .bind(target)
+        .bind(details.to_string())
+        .bind(INSTANCE.get())
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Query the audit log, newest entries first
+    pub async fn query(pool: &SqlitePool, filter: &AuditFilter) -> Result<Vec<Self>> {
+        let mut qb: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "SELECT id, timestamp, actor, action, target, details, instance FROM audit_log WHERE 1 = 1",
+        );
+        if let Some(ref actor) = filter.actor {
+            qb.push(" AND actor = ").push_bind(actor);
+        }
+        if let Some(ref action) = filter.action {
+            qb.push(" AND action = ").push_bind(action);
+        }
+        if let Some(ref target) = filter.target {
+            qb.push(" AND target = ").push_bind(target);
+        }
+        if let Some(ref instance) = filter.instance {
+            qb.push(" AND instance = ").push_bind(instance);
+        }
+        if let Some(ref since) = filter.since {
+            qb.push(" AND timestamp >= ").push_bind(since);
+        }
+        let limit = filter
+            .limit
+            .unwrap_or(DEFAULT_AUDIT_LIMIT)
+            .clamp(1, MAX_AUDIT_LIMIT);
+        qb.push(" ORDER BY id DESC LIMIT ").push_bind(limit);
+
+        let entries = qb.build_query_as::<AuditEntry>().fetch_all(pool).await?;
+        Ok(entries)
+    }
+}
+
+/// The actor recorded for actions taken directly through the CLI: the invoking
+/// user (preferring the user behind `sudo`), prefixed with `cli:`.
+pub fn cli_actor() -> String {
+    let user = std::env::var("SUDO_USER")
+        .or_else(|_| std::env::var("USER"))
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string());
+    format!("cli:{}", user)
+}
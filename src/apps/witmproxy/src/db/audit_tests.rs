@@ -0,0 +1,148 @@
+use crate::db::Db;
+use crate::db::audit::*;
+
+async fn setup_db() -> (Db, tempfile::TempDir) {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("test.db");
+    let db = Db::from_path(db_path, "test_password").await.unwrap();
+    db.migrate().await.unwrap();
+    (db, temp_dir)
+}
+
+#[tokio::test]
+async fn record_and_query_entries() {
+    let (db, _dir) = setup_db().await;
+    let pool = &db.pool;
+
+    AuditEntry::record(
+        pool,
+        "cli:alice",
+        AuditAction::PluginInstall,
+        Some("ns/one"),
+        serde_json::json!({ "version": "1.0.0" }),
+    )
+    .await
+    .unwrap();
+    AuditEntry::record(
+        pool,
+        "tenant:t1",
+        AuditAction::PluginRemove,
+        Some("ns/one"),
+        serde_json::json!({}),
+    )
+    .await
+    .unwrap();
+    AuditEntry::record(
+        pool,
+        "cli:alice",
+        AuditAction::TrustInstall,
+        None,
+        serde_json::json!({}),
+    )
+    .await
+    .unwrap();
+
+    // Newest first
+    let all = AuditEntry::query(pool, &AuditFilter::default())
+        .await
+        .unwrap();
+    assert_eq!(all.len(), 3);
+    assert_eq!(all[0].action, "trust.install");
+    assert_eq!(all[2].action, "plugin.install");
+    assert_eq!(all[2].details, r#"{"version":"1.0.0"}"#);
+
+    let by_actor = AuditEntry::query(
+        pool,
+        &AuditFilter {
+            actor: Some("cli:alice".into()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(by_actor.len(), 2);
+
+    let by_target = AuditEntry::query(
+        pool,
+        &AuditFilter {
+            target: Some("ns/one".into()),
+            action: Some("plugin.remove".into()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(by_target.len(), 1);
+    assert_eq!(by_target[0].actor, "tenant:t1");
+
+    let limited = AuditEntry::query(
+        pool,
+        &AuditFilter {
+            limit: Some(1),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(limited.len(), 1);
+}
+
+#[tokio::test]
+async fn audit_log_is_append_only() {
+    let (db, _dir) = setup_db().await;
+    let pool = &db.pool;
+
+    AuditEntry::record(
+        pool,
+        "cli:alice",
+        AuditAction::ConfigUpdate,
+        None,
+        serde_json::json!({}),
+    )
+    .await
+    .unwrap();
+
+    let update = sqlx::query("UPDATE audit_log SET actor = 'cli:mallory'")
+        .execute(pool)
+        .await;
+    assert!(update.is_err(), "audit entries must not be modifiable");
+
+    let delete = sqlx::query("DELETE FROM audit_log").execute(pool).await;
+    assert!(delete.is_err(), "audit entries must not be deletable");
+
+    let all = AuditEntry::query(pool, &AuditFilter::default())
+        .await
+        .unwrap();
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0].actor, "cli:alice");
+}
+
+#[tokio::test]
+async fn entries_record_their_instance() {
+    let (db, _dir) = setup_db().await;
+    let pool = &db.pool;
+
+    set_instance("node-a".to_string());
+    set_instance("node-b".to_string());
+    AuditEntry::record(
+        pool,
+        "cli:alice",
+        AuditAction::ConfigReload,
+        None,
+        serde_json::json!({}),
+    )
+    .await
+    .unwrap();
+
+    let by_instance = AuditEntry::query(
+        pool,
+        &AuditFilter {
+            instance: Some("node-a".into()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(by_instance.len(), 1);
+    assert_eq!(by_instance[0].instance.as_deref(), Some("node-a"));
+}
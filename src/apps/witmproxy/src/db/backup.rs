@@ -0,0 +1,139 @@
+//! Whole-database backups, taken with `VACUUM INTO` so they're consistent
+//! while the proxy is running, and encrypted with the same password as the
+//! database they were taken from.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use sqlx::SqlitePool;
+
+use crate::db::Db;
+
+/// Suffix of the copy of the current database kept by [restore]
+pub const PRE_RESTORE_SUFFIX: &str = "before-restore";
+
+/// Write a consistent copy of the database to `dest`, which must not exist
+pub async fn backup(pool: &SqlitePool, dest: &Path) -> Result<()> {
+    if dest.exists() {
+        bail!("{} already exists", dest.display());
+    }
+    sqlx::query("VACUUM INTO ?")
+        .bind(dest.to_string_lossy())
+        .execute(pool)
+        .await
+        .with_context(|| format!("Failed to back up the database to {}", dest.display()))?;
+    Ok(())
+}
+
+/// Replace the database at `db_path` with the backup at `src`, after checking
+/// the backup opens with `password` and is intact. The current database is
+/// first backed up next to it, with [PRE_RESTORE_SUFFIX] appended to its
+/// name, which is returned. The proxy must not be running.
+pub async fn restore(src: &Path, db_path: &Path, password: &str) -> Result<Option<PathBuf>> {
+    if !src.is_file() {
+        bail!("{} does not exist", src.display());
+    }
+    let backup_db = Db::from_path(src.to_path_buf(), password)
+        .await
+        .with_context(|| format!("Failed to open {}", src.display()))?;
+    let problems = backup_db.integrity_problems().await.with_context(|| {
+        format!(
+            "Failed to read {}; is it a backup taken with this database password?",
+            src.display()
+        )
+    })?;
+    if let Some(problems) = problems {
+        bail!("{} is corrupt: {}", src.display(), problems);
+    }
+    backup_db.pool.close().await;
+
+    let previous = if db_path.exists() {
+        let previous = with_suffix(db_path, PRE_RESTORE_SUFFIX);
+        if previous.exists() {
+            std::fs::remove_file(&previous)?;
+        }
+        let current = Db::from_path(db_path.to_path_buf(), password).await?;
+        backup(&current.pool, &previous).await?;
+        current.pool.close().await;
+        Some(previous)
+    } else {
+        None
+    };
+
+    // A leftover write-ahead log would be replayed onto the restored database
+    for suffix in ["wal", "shm"] {
+        let path = with_suffix(db_path, suffix);
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    let staged = with_suffix(db_path, "restoring");
+    std::fs::copy(src, &staged)?;
+    std::fs::rename(&staged, db_path)?;
+    Ok(previous)
+}
+
+/// `path` with `-suffix` appended to its file name, as SQLite names its
+/// journal files
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!("-{}", suffix));
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::audit::{AuditAction, AuditEntry, AuditFilter};
+
+    async fn audit_targets(pool: &SqlitePool) -> Vec<String> {
+        AuditEntry::query(pool, &AuditFilter::default())
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|entry| entry.target)
+            .collect()
+    }
+
+    async fn record(pool: &SqlitePool, target: &str) {
+        AuditEntry::record(
+            pool,
+            "cli:alice",
+            AuditAction::ConfigUpdate,
+            Some(target),
+            serde_json::json!({}),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn backups_restore_the_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("witmproxy.db");
+        let backup_path = dir.path().join("backup.db");
+
+        let db = Db::from_path(db_path.clone(), "password").await.unwrap();
+        db.migrate().await.unwrap();
+        record(&db.pool, "before").await;
+        backup(&db.pool, &backup_path).await.unwrap();
+        assert!(backup(&db.pool, &backup_path).await.is_err());
+        record(&db.pool, "after").await;
+        db.pool.close().await;
+
+        assert!(
+            restore(&backup_path, &db_path, "wrong password")
+                .await
+                .is_err()
+        );
+        let previous = restore(&backup_path, &db_path, "password")
+            .await
+            .unwrap()
+            .unwrap();
+
+        let restored = Db::from_path(db_path, "password").await.unwrap();
+        assert_eq!(audit_targets(&restored.pool).await, vec!["before"]);
+        let previous = Db::from_path(previous, "password").await.unwrap();
+        assert_eq!(audit_targets(&previous.pool).await, vec!["after", "before"]);
+    }
+}
@@ -0,0 +1,145 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use sqlx::SqlitePool;
+
+use crate::plugins::quota::Quota;
+
+/// How much plugins have used their capabilities in the current minute and
+/// hour, counted against the capabilities' [Quota]s
+pub struct CapabilityUsage;
+
+impl CapabilityUsage {
+    /// Count an event with `body_bytes` of body handled by plugin
+    /// `namespace/name` with `capability` at `now`, unless that would exceed
+    /// `quota`. Returns whether the event was counted.
+    pub async fn consume(
+        pool: &SqlitePool,
+        namespace: &str,
+        name: &str,
+        capability: &str,
+        quota: &Quota,
+        body_bytes: u64,
+        now: DateTime<Utc>,
+    ) -> Result<bool> {
+        let counters = [
+            (
+                "events-per-minute",
+                quota.max_events_per_minute.map(u64::from),
+                1,
+                60,
+            ),
+            (
+                "body-bytes-per-hour",
+                quota.max_body_bytes_per_hour,
+                body_bytes,
+                3600,
+            ),
+        ];
+        if counters.iter().all(|(_, limit, _, _)| limit.is_none()) {
+            return Ok(true);
+        }
+
+        // Counters are only updated together, so an event refused by one
+        // limit isn't counted against the others
+        let mut tx = pool.begin().await?;
+        for (counter, limit, amount, period) in counters {
+            let Some(limit) = limit else {
+                continue;
+            };
+            if amount > limit {
+                return Ok(false);
+            }
+            let period_start = now.timestamp() - now.timestamp().rem_euclid(period);
+            let result = sqlx::query(
+                "INSERT INTO capability_usage (namespace, name, capability, counter, period_start, amount)
+                 VALUES (?, ?, ?, ?, ?, ?)
+                 ON CONFLICT(namespace, name, capability, counter) DO UPDATE SET
+                     amount = CASE WHEN period_start = excluded.period_start
+                         THEN amount + excluded.amount ELSE excluded.amount END,
+                     period_start = excluded.period_start
+                 WHERE period_start != excluded.period_start OR amount + excluded.amount <= ?",
+            )
+            .bind(namespace)
+            .bind(name)
+            .bind(capability)
+            .bind(counter)
+            .bind(period_start)
+            .bind(amount as i64)
+            .bind(limit as i64)
+            .execute(&mut *tx)
+            .await?;
+            if result.rows_affected() == 0 {
+                return Ok(false);
+            }
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+
+    #[tokio::test]
+    async fn events_are_counted_against_the_quota() {
+        let db = Db::in_memory().await.unwrap();
+        db.migrate().await.unwrap();
+        let pool = &db.pool;
+        sqlx::query(
+            "INSERT INTO plugins (namespace, name, version, author, description, license, url, publickey, enabled, component)
+             VALUES ('@test', 'limited', '0.0.1', '', '', '', '', x'', 1, x'')",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let quota = Quota {
+            max_events_per_minute: Some(2),
+            max_body_bytes_per_hour: Some(100),
+            ..Default::default()
+        };
+        let consume = |body_bytes, now: &str| {
+            let now = now.parse().unwrap();
+            CapabilityUsage::consume(
+                pool,
+                "@test",
+                "limited",
+                "handle_event_request",
+                &quota,
+                body_bytes,
+                now,
+            )
+        };
+
+        assert!(consume(40, "2026-10-15T12:00:01Z").await.unwrap());
+        // Over the body limit, so not counted against the event limit either
+        assert!(!consume(80, "2026-10-15T12:00:02Z").await.unwrap());
+        assert!(consume(50, "2026-10-15T12:00:03Z").await.unwrap());
+        assert!(!consume(0, "2026-10-15T12:00:59Z").await.unwrap());
+
+        // A new minute, but the same hour
+        assert!(consume(10, "2026-10-15T12:01:00Z").await.unwrap());
+        assert!(!consume(1, "2026-10-15T12:01:01Z").await.unwrap());
+        assert!(consume(100, "2026-10-15T13:00:00Z").await.unwrap());
+        assert!(!consume(101, "2026-10-15T14:00:00Z").await.unwrap());
+
+        let unlimited = Quota::default();
+        for _ in 0..5 {
+            assert!(
+                CapabilityUsage::consume(
+                    pool,
+                    "@test",
+                    "limited",
+                    "handle_event_request",
+                    &unlimited,
+                    u64::MAX,
+                    Utc::now(),
+                )
+                .await
+                .unwrap()
+            );
+        }
+    }
+}
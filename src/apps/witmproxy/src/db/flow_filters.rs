@@ -0,0 +1,52 @@
+use anyhow::Result;
+use sqlx::SqlitePool;
+
+/// A flow query saved by name, ex: "api-errors"
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct StoredFlowFilter {
+    pub name: String,
+    /// The [crate::proxy::flows::FlowQuery], as JSON
+    pub query: String,
+    pub created_at: String,
+}
+
+impl StoredFlowFilter {
+    pub async fn list(pool: &SqlitePool) -> Result<Vec<Self>> {
+        let filters =
+            sqlx::query_as::<_, StoredFlowFilter>("SELECT * FROM flow_filters ORDER BY name")
+                .fetch_all(pool)
+                .await?;
+        Ok(filters)
+    }
+
+    pub async fn get(pool: &SqlitePool, name: &str) -> Result<Option<Self>> {
+        let filter =
+            sqlx::query_as::<_, StoredFlowFilter>("SELECT * FROM flow_filters WHERE name = ?")
+                .bind(name)
+                .fetch_optional(pool)
+                .await?;
+        Ok(filter)
+    }
+
+    /// Store `query` as `name`, replacing any filter already stored as it
+    pub async fn upsert(pool: &SqlitePool, name: &str, query: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO flow_filters (name, query) VALUES (?, ?)
+             ON CONFLICT(name) DO UPDATE SET query = excluded.query",
+        )
+        .bind(name)
+        .bind(query)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Delete the filter stored as `name`. Returns false if there was none.
+    pub async fn delete(pool: &SqlitePool, name: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM flow_filters WHERE name = ?")
+            .bind(name)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,134 @@
+//! `Strict-Transport-Security` policies hosts have sent, saved so HTTPS
+//! upgrades for them last across restarts.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use sqlx::SqlitePool;
+use tracing::warn;
+
+use crate::proxy::https_upgrade::{HttpsUpgrades, ObservedHsts};
+
+/// How often the running proxy saves policies which have changed
+pub const PERSIST_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct StoredHsts {
+    pub host: String,
+    pub include_subdomains: bool,
+    /// Seconds since the Unix epoch
+    pub expires_at: i64,
+}
+
+impl StoredHsts {
+    /// Policies which haven't expired
+    pub async fn list(pool: &SqlitePool) -> Result<Vec<Self>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let policies = sqlx::query_as::<_, StoredHsts>(
+            "SELECT * FROM hsts_hosts WHERE expires_at > ? ORDER BY host",
+        )
+        .bind(now)
+        .fetch_all(pool)
+        .await?;
+        Ok(policies)
+    }
+
+    pub async fn upsert(pool: &SqlitePool, host: &str, policy: &ObservedHsts) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO hsts_hosts (host, include_subdomains, expires_at) VALUES (?, ?, ?)
+             ON CONFLICT(host) DO UPDATE SET include_subdomains = excluded.include_subdomains,
+             expires_at = excluded.expires_at",
+        )
+        .bind(host)
+        .bind(policy.include_subdomains)
+        .bind(policy.expires_at)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete(pool: &SqlitePool, host: &str) -> Result<()> {
+        sqlx::query("DELETE FROM hsts_hosts WHERE host = ?")
+            .bind(host)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Load every saved policy which hasn't expired into `upgrades`
+pub async fn load(pool: &SqlitePool, upgrades: &HttpsUpgrades) -> Result<()> {
+    for stored in StoredHsts::list(pool).await? {
+        upgrades.load(
+            &stored.host,
+            ObservedHsts {
+                include_subdomains: stored.include_subdomains,
+                expires_at: stored.expires_at,
+            },
+        );
+    }
+    Ok(())
+}
+
+/// Save the policies which have changed since they were last saved
+pub async fn persist(pool: &SqlitePool, upgrades: &HttpsUpgrades) -> Result<()> {
+    for (host, policy) in upgrades.take_changed() {
+        match policy {
+            Some(policy) => StoredHsts::upsert(pool, &host, &policy).await?,
+            None => StoredHsts::delete(pool, &host).await?,
+        }
+    }
+    Ok(())
+}
+
+/// Save changed policies every `interval` until the task is dropped
+pub async fn persist_loop(pool: SqlitePool, upgrades: HttpsUpgrades, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = persist(&pool, &upgrades).await {
+            warn!("Failed to save HSTS policies: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::proxy::https_upgrade::UpgradeSource;
+    use hyper::header::{HeaderMap, HeaderValue, STRICT_TRANSPORT_SECURITY};
+
+    #[tokio::test]
+    async fn policies_are_saved_and_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Db::from_path(dir.path().join("test.db"), "password")
+            .await
+            .unwrap();
+        db.migrate().await.unwrap();
+
+        let upgrades = HttpsUpgrades::new();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            STRICT_TRANSPORT_SECURITY,
+            HeaderValue::from_static("max-age=31536000; includeSubDomains"),
+        );
+        upgrades.observe("example.com", &headers);
+        persist(&db.pool, &upgrades).await.unwrap();
+
+        let loaded = HttpsUpgrades::new();
+        load(&db.pool, &loaded).await.unwrap();
+        assert_eq!(
+            loaded.source_for("www.example.com"),
+            Some(UpgradeSource::Observed)
+        );
+
+        headers.insert(
+            STRICT_TRANSPORT_SECURITY,
+            HeaderValue::from_static("max-age=0"),
+        );
+        upgrades.observe("example.com", &headers);
+        persist(&db.pool, &upgrades).await.unwrap();
+        assert!(StoredHsts::list(&db.pool).await.unwrap().is_empty());
+    }
+}
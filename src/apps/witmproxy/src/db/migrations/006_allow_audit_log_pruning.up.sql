@@ -0,0 +1,8 @@
+-- Retention may prune audit entries, but only once they're a day old, so
+-- recent history can't be removed
+DROP TRIGGER IF EXISTS audit_log_no_delete;
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+WHEN OLD.timestamp > datetime('now', '-1 day')
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log entries younger than a day cannot be deleted');
+END;
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_audit_log_instance;
+ALTER TABLE audit_log DROP COLUMN instance;
@@ -0,0 +1,3 @@
+-- The instance which recorded each entry, when several share a deployment
+ALTER TABLE audit_log ADD COLUMN instance TEXT;
+CREATE INDEX idx_audit_log_instance ON audit_log(instance);
@@ -0,0 +1,5 @@
+CREATE TABLE mock_specs (
+    name TEXT PRIMARY KEY,
+    document TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,5 @@
+CREATE TABLE api_schemas (
+    host TEXT PRIMARY KEY,
+    schema TEXT NOT NULL,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS protobuf_descriptors;
@@ -0,0 +1,5 @@
+CREATE TABLE protobuf_descriptors (
+    name TEXT PRIMARY KEY,
+    descriptor BLOB NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,9 @@
+CREATE TABLE plugin_ratings (
+    namespace TEXT NOT NULL,
+    name TEXT NOT NULL,
+    rater TEXT NOT NULL,
+    stars INTEGER NOT NULL CHECK (stars BETWEEN 1 AND 5),
+    rated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (namespace, name, rater),
+    FOREIGN KEY (namespace, name) REFERENCES plugins(namespace, name) ON DELETE CASCADE
+);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS capability_usage;
+ALTER TABLE plugin_capabilities DROP COLUMN quota;
@@ -0,0 +1,14 @@
+-- Limits on how much each capability can be used, as JSON
+ALTER TABLE plugin_capabilities ADD COLUMN quota TEXT NOT NULL DEFAULT '{}';
+
+-- How much each capability has been used in the current period of each limit
+CREATE TABLE capability_usage (
+    namespace TEXT NOT NULL,
+    name TEXT NOT NULL,
+    capability TEXT NOT NULL,
+    counter TEXT NOT NULL,
+    period_start INTEGER NOT NULL,
+    amount INTEGER NOT NULL,
+    PRIMARY KEY (namespace, name, capability, counter),
+    FOREIGN KEY (namespace, name) REFERENCES plugins(namespace, name) ON DELETE CASCADE
+);
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS hsts_hosts;
@@ -0,0 +1,6 @@
+-- Strict-Transport-Security policies hosts have sent, for HTTPS upgrades
+CREATE TABLE hsts_hosts (
+    host TEXT PRIMARY KEY,
+    include_subdomains INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Scripts injected into the HTML pages they match
+CREATE TABLE user_scripts (
+    name TEXT PRIMARY KEY,
+    source TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
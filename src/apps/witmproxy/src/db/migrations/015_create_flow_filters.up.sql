@@ -0,0 +1,6 @@
+-- Flow queries saved by name for the management API's flow list
+CREATE TABLE flow_filters (
+    name TEXT PRIMARY KEY,
+    query TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
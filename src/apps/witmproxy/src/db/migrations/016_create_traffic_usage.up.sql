@@ -0,0 +1,9 @@
+-- Bytes transferred by client profile and host, by day and month
+CREATE TABLE traffic_usage (
+    profile TEXT NOT NULL,
+    host TEXT NOT NULL,
+    period TEXT NOT NULL,
+    period_start INTEGER NOT NULL,
+    bytes INTEGER NOT NULL,
+    PRIMARY KEY (profile, host, period, period_start)
+);
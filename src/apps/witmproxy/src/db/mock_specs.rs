@@ -0,0 +1,42 @@
+use anyhow::Result;
+use sqlx::SqlitePool;
+
+/// An uploaded OpenAPI document, served by mock mode
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct StoredMockSpec {
+    pub name: String,
+    /// The document, as JSON
+    pub document: String,
+    pub created_at: String,
+}
+
+impl StoredMockSpec {
+    pub async fn list(pool: &SqlitePool) -> Result<Vec<Self>> {
+        let specs = sqlx::query_as::<_, StoredMockSpec>("SELECT * FROM mock_specs ORDER BY name")
+            .fetch_all(pool)
+            .await?;
+        Ok(specs)
+    }
+
+    /// Store `document` as `name`, replacing any document already stored as it
+    pub async fn upsert(pool: &SqlitePool, name: &str, document: &serde_json::Value) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO mock_specs (name, document) VALUES (?, ?)
+             ON CONFLICT(name) DO UPDATE SET document = excluded.document",
+        )
+        .bind(name)
+        .bind(document.to_string())
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Delete the document stored as `name`. Returns false if there was none.
+    pub async fn delete(pool: &SqlitePool, name: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM mock_specs WHERE name = ?")
+            .bind(name)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -1,13 +1,34 @@
+pub mod api_schemas;
+pub mod api_tokens;
+pub mod audit;
+pub mod backup;
+pub mod capability_usage;
+pub mod flow_filters;
+pub mod hsts;
+pub mod mock_specs;
+pub mod plugin_ratings;
+pub mod protobuf_descriptors;
+pub mod retention;
 pub mod tenants;
+pub mod traffic;
+pub mod user_scripts;
 
+#[cfg(test)]
+mod api_token_tests;
+#[cfg(test)]
+mod audit_tests;
 #[cfg(test)]
 mod tenant_tests;
 
 use anyhow::Result;
-use sqlx::{Sqlite, SqlitePool, Transaction, sqlite::SqliteConnectOptions};
-use std::path::PathBuf;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+/// Database path that keeps the database in memory rather than on disk
+pub const IN_MEMORY_PATH: &str = "sqlite::memory:";
+
 #[derive(Clone)]
 pub struct Db {
     pub pool: SqlitePool,
@@ -33,7 +54,11 @@ impl Db {
         Db { pool }
     }
 
+    /// Open the database at `db_path`, or one in memory for [IN_MEMORY_PATH]
     pub async fn from_path(db_path: PathBuf, password: &str) -> Result<Self> {
+        if is_in_memory(&db_path) {
+            return Self::in_memory().await;
+        }
         let db_path_str = db_path.to_string_lossy();
         let db_path = if !db_path_str.starts_with("sqlite://") {
             format!("sqlite://{}", db_path_str)
@@ -50,6 +75,29 @@ impl Db {
         Ok(Db { pool })
     }
 
+    /// A database held in memory, gone once the last clone is dropped
+    pub async fn in_memory() -> Result<Self> {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:")?;
+        // Every connection to `:memory:` opens a database of its own, so the
+        // pool keeps exactly one open for as long as it lives
+        let pool = SqlitePoolOptions::new()
+            .min_connections(1)
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(options)
+            .await?;
+        Ok(Db { pool })
+    }
+
+    /// What `PRAGMA integrity_check` found wrong with the database, if anything
+    pub async fn integrity_problems(&self) -> Result<Option<String>> {
+        let (integrity,): (String,) = sqlx::query_as("PRAGMA integrity_check")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok((integrity != "ok").then_some(integrity))
+    }
+
     /// Run embedded application database migrations
     pub async fn migrate(&self) -> Result<()> {
         sqlx::migrate!("src/db/migrations")
@@ -60,6 +108,11 @@ impl Db {
     }
 }
 
+/// Whether `db_path` names an in-memory database rather than a file
+pub fn is_in_memory(db_path: &Path) -> bool {
+    matches!(db_path.to_str(), Some(IN_MEMORY_PATH | ":memory:"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -140,4 +193,28 @@ mod tests {
             .await
             .expect("Database should open successfully with correct password");
     }
+
+    #[tokio::test]
+    async fn test_in_memory() {
+        let db = Db::from_path(PathBuf::from(IN_MEMORY_PATH), "unused")
+            .await
+            .expect("Failed to create in-memory database");
+        db.migrate().await.expect("Failed to run migrations");
+
+        // Migrations are visible to later queries, which share the connection
+        let (plugins,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM plugins")
+            .fetch_one(&db.pool)
+            .await
+            .expect("Failed to query migrated table");
+        assert_eq!(plugins, 0);
+
+        // Each in-memory database is separate
+        let other = Db::in_memory().await.unwrap();
+        assert!(
+            sqlx::query("SELECT COUNT(*) FROM plugins")
+                .fetch_one(&other.pool)
+                .await
+                .is_err()
+        );
+    }
 }
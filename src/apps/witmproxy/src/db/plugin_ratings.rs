@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+/// Ratings of installed plugins, from one to five stars, one per rater
+pub struct PluginRating;
+
+/// The ratings a plugin has been given
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RatingSummary {
+    /// Mean number of stars
+    pub average: f64,
+    pub count: i64,
+}
+
+impl PluginRating {
+    /// Rate plugin `namespace/name` for `rater`, replacing their earlier rating
+    pub async fn rate(
+        pool: &SqlitePool,
+        namespace: &str,
+        name: &str,
+        rater: &str,
+        stars: u8,
+    ) -> Result<()> {
+        if !(1..=5).contains(&stars) {
+            anyhow::bail!("Ratings are from 1 to 5 stars, not {}", stars);
+        }
+        sqlx::query(
+            "INSERT INTO plugin_ratings (namespace, name, rater, stars) VALUES (?, ?, ?, ?)
+             ON CONFLICT(namespace, name, rater) DO UPDATE
+             SET stars = excluded.stars, rated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(namespace)
+        .bind(name)
+        .bind(rater)
+        .bind(stars)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The ratings of every rated plugin, by plugin id
+    pub async fn summaries(pool: &SqlitePool) -> Result<HashMap<String, RatingSummary>> {
+        let rows: Vec<(String, String, f64, i64)> = sqlx::query_as(
+            "SELECT namespace, name, AVG(stars), COUNT(*) FROM plugin_ratings
+             GROUP BY namespace, name",
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(namespace, name, average, count)| {
+                (
+                    format!("{}/{}", namespace, name),
+                    RatingSummary { average, count },
+                )
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+
+    #[tokio::test]
+    async fn ratings_are_summarised_per_plugin() {
+        let db = Db::in_memory().await.unwrap();
+        db.migrate().await.unwrap();
+        let pool = &db.pool;
+        sqlx::query(
+            "INSERT INTO plugins (namespace, name, version, author, description, license, url, publickey, enabled, component)
+             VALUES ('@test', 'rated', '0.0.1', '', '', '', '', x'', 1, x'')",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        PluginRating::rate(pool, "@test", "rated", "token:ci", 2)
+            .await
+            .unwrap();
+        PluginRating::rate(pool, "@test", "rated", "tenant:t1", 5)
+            .await
+            .unwrap();
+        // Rating again replaces the rater's earlier rating
+        PluginRating::rate(pool, "@test", "rated", "token:ci", 4)
+            .await
+            .unwrap();
+        assert!(
+            PluginRating::rate(pool, "@test", "rated", "token:ci", 6)
+                .await
+                .is_err()
+        );
+
+        let summaries = PluginRating::summaries(pool).await.unwrap();
+        assert_eq!(
+            summaries["@test/rated"],
+            RatingSummary {
+                average: 4.5,
+                count: 2
+            }
+        );
+    }
+}
@@ -0,0 +1,44 @@
+use anyhow::Result;
+use sqlx::SqlitePool;
+
+/// An uploaded protobuf descriptor set
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct StoredDescriptorSet {
+    pub name: String,
+    /// The serialized `FileDescriptorSet`
+    pub descriptor: Vec<u8>,
+    pub created_at: String,
+}
+
+impl StoredDescriptorSet {
+    pub async fn list(pool: &SqlitePool) -> Result<Vec<Self>> {
+        let sets = sqlx::query_as::<_, StoredDescriptorSet>(
+            "SELECT * FROM protobuf_descriptors ORDER BY name",
+        )
+        .fetch_all(pool)
+        .await?;
+        Ok(sets)
+    }
+
+    /// Store `descriptor` as `name`, replacing any set already stored as it
+    pub async fn upsert(pool: &SqlitePool, name: &str, descriptor: &[u8]) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO protobuf_descriptors (name, descriptor) VALUES (?, ?)
+             ON CONFLICT(name) DO UPDATE SET descriptor = excluded.descriptor",
+        )
+        .bind(name)
+        .bind(descriptor)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Delete the set stored as `name`. Returns false if there was none.
+    pub async fn delete(pool: &SqlitePool, name: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM protobuf_descriptors WHERE name = ?")
+            .bind(name)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,218 @@
+//! Retention limits for the audit log, the only table which grows with use.
+//!
+//! Limits are applied periodically while the proxy runs and on demand with
+//! `witm db prune`. Pruning removes rows without shrinking the database file,
+//! so it's followed by a `VACUUM` once enough of the file is free pages.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use sqlx::SqlitePool;
+use tracing::{info, warn};
+
+use crate::config::DbConfig;
+use crate::db::audit::{AuditAction, AuditEntry};
+
+/// Fraction of the database's pages which must be free before it's vacuumed
+pub const VACUUM_FREE_RATIO: f64 = 0.25;
+
+/// The actor recorded for pruning done by the running proxy
+const RETENTION_ACTOR: &str = "system:retention";
+
+/// How long and how many audit entries to keep; unset limits keep everything
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub audit_max_age_days: Option<u32>,
+    pub audit_max_entries: Option<u64>,
+}
+
+impl From<&DbConfig> for RetentionPolicy {
+    fn from(config: &DbConfig) -> Self {
+        Self {
+            audit_max_age_days: config.audit_max_age_days,
+            audit_max_entries: config.audit_max_entries,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    pub fn is_unlimited(&self) -> bool {
+        self.audit_max_age_days.is_none() && self.audit_max_entries.is_none()
+    }
+}
+
+/// What a call to [prune] removed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PruneReport {
+    pub audit_entries: u64,
+    pub vacuumed: bool,
+}
+
+/// Remove audit entries beyond `policy`'s limits, recording the pruning
+/// itself as an entry by `actor`. Entries less than a day old are never
+/// removed. Vacuums afterwards if `force_vacuum`, or if the file has become
+/// mostly free pages.
+pub async fn prune(
+    pool: &SqlitePool,
+    policy: &RetentionPolicy,
+    actor: &str,
+    force_vacuum: bool,
+) -> Result<PruneReport> {
+    let mut removed = 0;
+    if let Some(days) = policy.audit_max_age_days {
+        removed += sqlx::query("DELETE FROM audit_log WHERE timestamp < datetime('now', ?)")
+            .bind(format!("-{} days", days.max(1)))
+            .execute(pool)
+            .await?
+            .rows_affected();
+    }
+    if let Some(max_entries) = policy.audit_max_entries {
+        removed += sqlx::query(
+            "DELETE FROM audit_log WHERE timestamp < datetime('now', '-1 day') AND id <= \
+             (SELECT id FROM audit_log ORDER BY id DESC LIMIT 1 OFFSET ?)",
+        )
+        .bind(max_entries as i64)
+        .execute(pool)
+        .await?
+        .rows_affected();
+    }
+    if removed > 0 {
+        AuditEntry::record(
+            pool,
+            actor,
+            AuditAction::AuditPrune,
+            None,
+            serde_json::json!({
+                "removed": removed,
+                "max_age_days": policy.audit_max_age_days,
+                "max_entries": policy.audit_max_entries,
+            }),
+        )
+        .await?;
+    }
+
+    let vacuumed = force_vacuum || free_ratio(pool).await? >= VACUUM_FREE_RATIO;
+    if vacuumed {
+        sqlx::query("VACUUM").execute(pool).await?;
+    }
+    Ok(PruneReport {
+        audit_entries: removed,
+        vacuumed,
+    })
+}
+
+/// The fraction of the database file made up of unused pages
+async fn free_ratio(pool: &SqlitePool) -> Result<f64> {
+    let (free,): (i64,) = sqlx::query_as("PRAGMA freelist_count")
+        .fetch_one(pool)
+        .await?;
+    let (total,): (i64,) = sqlx::query_as("PRAGMA page_count").fetch_one(pool).await?;
+    Ok(if total == 0 {
+        0.0
+    } else {
+        free as f64 / total as f64
+    })
+}
+
+/// Apply `policy` every `interval` until the task is dropped
+pub async fn prune_loop(pool: SqlitePool, policy: RetentionPolicy, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        match prune(&pool, &policy, RETENTION_ACTOR, false).await {
+            Ok(report) if report.audit_entries > 0 || report.vacuumed => info!(
+                "Retention: pruned {} audit entries{}",
+                report.audit_entries,
+                if report.vacuumed {
+                    " and vacuumed the database"
+                } else {
+                    ""
+                }
+            ),
+            Ok(_) => {}
+            Err(e) => warn!("Retention: failed to prune the database: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::db::audit::AuditFilter;
+
+    async fn setup_db() -> (Db, tempfile::TempDir) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = Db::from_path(db_path, "test_password").await.unwrap();
+        db.migrate().await.unwrap();
+        (db, temp_dir)
+    }
+
+    async fn insert_entry(pool: &SqlitePool, target: &str, days_ago: u32) {
+        sqlx::query(
+            "INSERT INTO audit_log (timestamp, actor, action, target) \
+             VALUES (datetime('now', ?), 'cli:alice', 'config.update', ?)",
+        )
+        .bind(format!("-{} days", days_ago))
+        .bind(target)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    async fn targets(pool: &SqlitePool) -> Vec<String> {
+        AuditEntry::query(pool, &AuditFilter::default())
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|entry| entry.target)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn entries_beyond_the_limits_are_pruned() {
+        let (db, _dir) = setup_db().await;
+        let pool = &db.pool;
+        for (target, days_ago) in [("a", 60), ("b", 20), ("c", 10), ("d", 5), ("e", 0)] {
+            insert_entry(pool, target, days_ago).await;
+        }
+
+        let by_age = RetentionPolicy {
+            audit_max_age_days: Some(30),
+            audit_max_entries: None,
+        };
+        let report = prune(pool, &by_age, "cli:alice", false).await.unwrap();
+        assert_eq!(report.audit_entries, 1);
+        assert_eq!(targets(pool).await, vec!["e", "d", "c", "b"]);
+
+        // The entry recording the first pruning counts towards the limit
+        let by_count = RetentionPolicy {
+            audit_max_age_days: None,
+            audit_max_entries: Some(2),
+        };
+        let report = prune(pool, &by_count, "cli:alice", true).await.unwrap();
+        assert_eq!(report.audit_entries, 3);
+        assert!(report.vacuumed);
+        assert_eq!(targets(pool).await, vec!["e"]);
+
+        let actions: Vec<_> = AuditEntry::query(pool, &AuditFilter::default())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.action)
+            .collect();
+        assert_eq!(actions, vec!["audit.prune", "audit.prune", "config.update"]);
+    }
+
+    #[tokio::test]
+    async fn nothing_is_pruned_without_limits() {
+        let (db, _dir) = setup_db().await;
+        insert_entry(&db.pool, "a", 400).await;
+        let report = prune(&db.pool, &RetentionPolicy::default(), "cli:alice", false)
+            .await
+            .unwrap();
+        assert_eq!(report.audit_entries, 0);
+        assert_eq!(targets(&db.pool).await, vec!["a"]);
+    }
+}
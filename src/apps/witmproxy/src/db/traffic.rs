@@ -0,0 +1,143 @@
+//! Bytes transferred by client profile and host, saved so daily and monthly
+//! counters, and the quotas enforced on them, last across restarts.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::Utc;
+use sqlx::SqlitePool;
+use tracing::warn;
+
+use crate::proxy::traffic::{Counted, Period, Traffic};
+
+/// How often the running proxy saves the traffic counted since it last did
+pub const PERSIST_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct StoredTraffic {
+    pub profile: String,
+    pub host: String,
+    /// "day" or "month"
+    pub period: String,
+    /// Unix timestamp of the start of the period
+    pub period_start: i64,
+    pub bytes: i64,
+}
+
+impl StoredTraffic {
+    /// Add `counted` to the saved counters
+    pub async fn add(pool: &SqlitePool, counted: &Counted) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO traffic_usage (profile, host, period, period_start, bytes)
+             VALUES (?, ?, ?, ?, ?)
+             ON CONFLICT(profile, host, period, period_start) DO UPDATE SET
+                 bytes = bytes + excluded.bytes",
+        )
+        .bind(&counted.profile)
+        .bind(&counted.host)
+        .bind(counted.period.as_str())
+        .bind(counted.period_start)
+        .bind(counted.bytes as i64)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The counters of the period starting at `period_start`
+    pub async fn list(
+        pool: &SqlitePool,
+        period: Period,
+        period_start: i64,
+    ) -> Result<Vec<Counted>> {
+        let stored = sqlx::query_as::<_, StoredTraffic>(
+            "SELECT * FROM traffic_usage WHERE period = ? AND period_start = ?
+             ORDER BY profile, host",
+        )
+        .bind(period.as_str())
+        .bind(period_start)
+        .fetch_all(pool)
+        .await?;
+        Ok(stored
+            .into_iter()
+            .map(|stored| Counted {
+                profile: stored.profile,
+                host: stored.host,
+                period,
+                period_start: stored.period_start,
+                bytes: stored.bytes.max(0) as u64,
+            })
+            .collect())
+    }
+}
+
+/// Load each profile's traffic in the current day and month into `traffic`
+pub async fn load(pool: &SqlitePool, traffic: &Traffic) -> Result<()> {
+    let now = Utc::now();
+    for period in Period::ALL {
+        let start = period.start(now);
+        for counted in StoredTraffic::list(pool, period, start).await? {
+            traffic.load(&counted.profile, period, start, counted.bytes);
+        }
+    }
+    Ok(())
+}
+
+/// Save the traffic counted since it was last saved
+pub async fn persist(pool: &SqlitePool, traffic: &Traffic) -> Result<()> {
+    for counted in traffic.take_pending() {
+        StoredTraffic::add(pool, &counted).await?;
+    }
+    Ok(())
+}
+
+/// Save counted traffic every `interval` until the task is dropped
+pub async fn persist_loop(pool: SqlitePool, traffic: Traffic, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = persist(&pool, &traffic).await {
+            warn!("Failed to save traffic counters: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Db;
+    use crate::proxy::traffic::ClientProfile;
+
+    #[tokio::test]
+    async fn counters_accumulate_and_are_loaded() {
+        let db = Db::in_memory().await.unwrap();
+        db.migrate().await.unwrap();
+        let profiles = vec![ClientProfile {
+            name: "lab".to_string(),
+            clients: vec!["10.0.0.5".parse().unwrap()],
+            daily_soft_limit: None,
+            daily_hard_limit: Some(1000),
+            monthly_soft_limit: None,
+            monthly_hard_limit: None,
+        }];
+        let now = Utc::now();
+        let traffic = Traffic::new(profiles.clone()).unwrap();
+        traffic.count("lab", "example.com", 600, now);
+        persist(&db.pool, &traffic).await.unwrap();
+        traffic.count("lab", "example.com", 300, now);
+        traffic.count("lab", "cdn.example.com", 50, now);
+        persist(&db.pool, &traffic).await.unwrap();
+
+        let day = Period::Day.start(now);
+        let stored = StoredTraffic::list(&db.pool, Period::Day, day)
+            .await
+            .unwrap();
+        let bytes: Vec<_> = stored.iter().map(|c| (c.host.as_str(), c.bytes)).collect();
+        assert_eq!(bytes, [("cdn.example.com", 50), ("example.com", 900)]);
+
+        // A restarted proxy picks up where it left off
+        let restarted = Traffic::new(profiles).unwrap();
+        load(&db.pool, &restarted).await.unwrap();
+        assert_eq!(restarted.used("lab", Period::Day, now), 950);
+        assert_eq!(restarted.used("lab", Period::Month, now), 950);
+    }
+}
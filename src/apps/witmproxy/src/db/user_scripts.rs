@@ -0,0 +1,43 @@
+use anyhow::Result;
+use sqlx::SqlitePool;
+
+/// A registered script, injected into the pages it matches
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct StoredUserScript {
+    pub name: String,
+    /// The script as registered, a userscript or a JSON snippet
+    pub source: String,
+    pub created_at: String,
+}
+
+impl StoredUserScript {
+    pub async fn list(pool: &SqlitePool) -> Result<Vec<Self>> {
+        let scripts =
+            sqlx::query_as::<_, StoredUserScript>("SELECT * FROM user_scripts ORDER BY name")
+                .fetch_all(pool)
+                .await?;
+        Ok(scripts)
+    }
+
+    /// Store `source` as `name`, replacing any script already stored as it
+    pub async fn upsert(pool: &SqlitePool, name: &str, source: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO user_scripts (name, source) VALUES (?, ?)
+             ON CONFLICT(name) DO UPDATE SET source = excluded.source",
+        )
+        .bind(name)
+        .bind(source)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Delete the script stored as `name`. Returns false if there was none.
+    pub async fn delete(pool: &SqlitePool, name: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM user_scripts WHERE name = ?")
+            .bind(name)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,215 @@
+//! Errors the proxy reports to clients in place of an upstream response.
+//!
+//! Each error has a stable code, ex: `upstream.unreachable`, shown on error
+//! pages, sent in the `x-witmproxy-error` response header, logged, and
+//! counted by the `witmproxy.errors` metric, so failures can be aggregated
+//! by kind rather than by message.
+
+use std::time::Duration;
+
+use hyper::StatusCode;
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+
+use crate::cert::CertError;
+use crate::plugins::registry::PluginBlocked;
+
+/// Response header carrying the code of the error a page was served for
+pub const ERROR_CODE_HEADER: &str = "x-witmproxy-error";
+
+#[derive(Debug, thiserror::Error)]
+pub enum WitmError {
+    /// The request's Host differs from the connection it arrived on
+    #[error("{0}")]
+    HostMismatch(String),
+
+    #[error("Failed to read request body: {0}")]
+    RequestBody(String),
+
+    #[error("Failed to convert request: {0}")]
+    InvalidRequest(String),
+
+    #[error("The proxy only forwards request bodies up to {0} bytes.")]
+    RequestTooLarge(u64),
+
+    #[error("Clients in profile {profile} have used their {period} quota of {limit} bytes.")]
+    QuotaExceeded {
+        profile: String,
+        period: &'static str,
+        limit: u64,
+    },
+
+    #[error("The request could not be completed within {} seconds.", .0.as_secs())]
+    DeadlineExceeded(Duration),
+
+    #[error("Failed to get streaming response: {0}")]
+    Response(String),
+
+    #[error("The upstream server took too long to respond.")]
+    UpstreamTimeout,
+
+    #[error("The proxy could not connect to the upstream server.")]
+    UpstreamUnreachable,
+
+    #[error("The upstream server could not be reached: {0}")]
+    Upstream(String),
+
+    #[error("This request was blocked by a plugin.")]
+    PluginBlocked { plugin_id: String },
+
+    #[error("A plugin failed while handling this request: {0}")]
+    Plugin(String),
+
+    #[error("Unexpected event data type from plugin")]
+    PluginOutput,
+
+    #[error("Certificate error: {0}")]
+    Cert(#[from] CertError),
+
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl WitmError {
+    /// The error from a failed plugin chain: blocked if a plugin ended it,
+    /// otherwise failed
+    pub fn from_plugin(err: &anyhow::Error) -> Self {
+        match err.downcast_ref::<PluginBlocked>() {
+            Some(blocked) => WitmError::PluginBlocked {
+                plugin_id: blocked.plugin_id.clone(),
+            },
+            None => WitmError::Plugin(err.to_string()),
+        }
+    }
+
+    /// The error from a failed upstream exchange
+    pub fn from_upstream(err: &reqwest::Error) -> Self {
+        let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+        while let Some(e) = source {
+            if let Some(ErrorCode::HttpRequestBodySize(limit)) = e.downcast_ref::<ErrorCode>() {
+                return WitmError::RequestTooLarge(limit.unwrap_or_default());
+            }
+            source = e.source();
+        }
+
+        if err.is_timeout() {
+            WitmError::UpstreamTimeout
+        } else if err.is_connect() {
+            WitmError::UpstreamUnreachable
+        } else {
+            WitmError::Upstream(err.to_string())
+        }
+    }
+
+    /// The stable code identifying the kind of error, `<layer>.<kind>`
+    pub fn code(&self) -> &'static str {
+        match self {
+            WitmError::HostMismatch(_) => "proxy.host_mismatch",
+            WitmError::RequestBody(_) => "proxy.request_body",
+            WitmError::InvalidRequest(_) => "proxy.invalid_request",
+            WitmError::RequestTooLarge(_) => "proxy.request_too_large",
+            WitmError::QuotaExceeded { .. } => "proxy.quota_exceeded",
+            WitmError::DeadlineExceeded(_) => "proxy.deadline_exceeded",
+            WitmError::Response(_) => "proxy.response",
+            WitmError::UpstreamTimeout => "upstream.timeout",
+            WitmError::UpstreamUnreachable => "upstream.unreachable",
+            WitmError::Upstream(_) => "upstream.error",
+            WitmError::PluginBlocked { .. } => "plugin.blocked",
+            WitmError::Plugin(_) => "plugin.failed",
+            WitmError::PluginOutput => "plugin.invalid_output",
+            WitmError::Cert(CertError::Generation(_)) => "cert.generation",
+            WitmError::Cert(CertError::Io(_)) => "cert.io",
+            WitmError::Cert(CertError::InvalidFormat) => "cert.invalid_format",
+            WitmError::Cert(CertError::NotFound(_)) => "cert.not_found",
+            WitmError::Database(_) => "db.error",
+        }
+    }
+
+    /// The status of the response served in place of the upstream's
+    pub fn status(&self) -> StatusCode {
+        match self {
+            WitmError::HostMismatch(_) => StatusCode::MISDIRECTED_REQUEST,
+            WitmError::RequestBody(_) | WitmError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            WitmError::RequestTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            WitmError::QuotaExceeded { .. } => StatusCode::TOO_MANY_REQUESTS,
+            WitmError::DeadlineExceeded(_) | WitmError::UpstreamTimeout => {
+                StatusCode::GATEWAY_TIMEOUT
+            }
+            WitmError::UpstreamUnreachable | WitmError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            WitmError::PluginBlocked { .. } => StatusCode::FORBIDDEN,
+            WitmError::Response(_)
+            | WitmError::Plugin(_)
+            | WitmError::PluginOutput
+            | WitmError::Cert(_)
+            | WitmError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The heading of the error page
+    pub fn title(&self) -> &'static str {
+        match self {
+            WitmError::HostMismatch(_) => "Misdirected request",
+            WitmError::RequestBody(_) | WitmError::InvalidRequest(_) => "Bad request",
+            WitmError::RequestTooLarge(_) => "Request too large",
+            WitmError::QuotaExceeded { .. } => "Quota exceeded",
+            WitmError::DeadlineExceeded(_) => "Request timed out",
+            WitmError::Response(_) => "Proxy error",
+            WitmError::UpstreamTimeout => "Upstream timed out",
+            WitmError::UpstreamUnreachable => "Could not reach upstream",
+            WitmError::Upstream(_) => "Upstream error",
+            WitmError::PluginBlocked { .. } => "Blocked",
+            WitmError::Plugin(_) | WitmError::PluginOutput => "Plugin error",
+            WitmError::Cert(_) => "Certificate error",
+            WitmError::Database(_) => "Database error",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_map_to_codes_and_statuses() {
+        let cases = [
+            (
+                WitmError::from_plugin(&anyhow::Error::new(PluginBlocked {
+                    plugin_id: "ops/blocker".to_string(),
+                })),
+                "plugin.blocked",
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                WitmError::from_plugin(&anyhow::anyhow!("trap")),
+                "plugin.failed",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                WitmError::DeadlineExceeded(Duration::from_secs(30)),
+                "proxy.deadline_exceeded",
+                StatusCode::GATEWAY_TIMEOUT,
+            ),
+            (
+                WitmError::QuotaExceeded {
+                    profile: "kids".to_string(),
+                    period: "daily",
+                    limit: 1000,
+                },
+                "proxy.quota_exceeded",
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (
+                WitmError::Cert(CertError::InvalidFormat),
+                "cert.invalid_format",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ];
+        for (err, code, status) in cases {
+            assert_eq!(err.code(), code);
+            assert_eq!(err.status(), status);
+        }
+        assert_eq!(
+            WitmError::DeadlineExceeded(Duration::from_secs(30)).to_string(),
+            "The request could not be completed within 30 seconds."
+        );
+    }
+}
@@ -1,14 +1,23 @@
+use std::sync::Arc;
+
 use anyhow::Result;
 use bytes::Bytes;
+use http_body::Body;
 use http_body_util::BodyExt;
 use http_body_util::combinators::UnsyncBoxBody;
 use hyper::Response;
+use hyper::header::{CONTENT_ENCODING, HeaderValue};
+use prost_reflect::MessageDescriptor;
 use salvo::http::response::Parts;
+use tracing::debug;
 use wasmtime::{Store, component::Resource};
 use wasmtime_wasi::runtime::with_ambient_tokio_runtime;
 
+use crate::http::sniff;
 use crate::http::utils::ContentEncoding;
 use crate::http::utils::Encoded;
+use crate::plugins::html::HtmlSummary;
+use crate::proxy::protobuf;
 use crate::{
     events::Event,
     plugins::cel::{CelContent, CelTime},
@@ -25,7 +34,18 @@ use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
 pub struct InboundContent {
     parts: Parts,
     content_type: String,
+    /// The MIME type essence detected from the body, once [InboundContent::sniff] has run
+    sniffed_type: Option<String>,
     body: Option<UnsyncBoxBody<Bytes, ErrorCode>>,
+    /// The message type of a protobuf body decoded to JSON, and the content
+    /// type it's encoded back into
+    protobuf: Option<(MessageDescriptor, HeaderValue)>,
+    /// Whether the plugin handling this content asked for its body to be
+    /// passed through its `body-transform` export
+    transform_requested: bool,
+    /// The summary of the HTML body, shared by the plugins summarizing it
+    /// until one replaces it
+    html_summary: Option<Arc<HtmlSummary>>,
 }
 
 impl Event for InboundContent {
@@ -33,6 +53,16 @@ impl Event for InboundContent {
         CapabilityKind::HandleEvent(EventKind::InboundContent)
     }
 
+    /// The length of the decoded body, if known
+    fn body_size(&self) -> Option<u64> {
+        match self.body.as_ref()?.size_hint().exact() {
+            Some(size) => Some(size),
+            // The length of an encoded body isn't its decoded length
+            None if self.parts.headers.contains_key(CONTENT_ENCODING) => None,
+            None => crate::events::body_size(&self.parts.headers),
+        }
+    }
+
     fn into_event_data(self: Box<Self>, store: &mut Store<Host>) -> Result<WasmEvent> {
         let handle: Resource<InboundContent> = store.data_mut().table.push(*self)?;
         Ok(WasmEvent::InboundContent(handle))
@@ -44,7 +74,8 @@ impl Event for InboundContent {
     {
         let env = env
             .declare_variable::<CelContent>("content")?
-            .register_member_function("content_type", CelContent::content_type)?;
+            .register_member_function("content_type", CelContent::content_type)?
+            .register_member_function("sniffed_type", CelContent::sniffed_type)?;
         Ok(env)
     }
 
@@ -59,6 +90,12 @@ impl Event for InboundContent {
     }
 }
 
+fn full_body(bytes: Bytes) -> UnsyncBoxBody<Bytes, ErrorCode> {
+    http_body_util::Full::new(bytes)
+        .map_err(|_| ErrorCode::InternalError(Some("conversion error".to_string())))
+        .boxed_unsync()
+}
+
 // TODO: InboundContent is currently only used for responses, but could easily be made generic
 // over any bundle of bytes with a content-type and encoding. Consider refactoring.
 /// [`InboundContent`] is a wrapper around an HTTP [`Response`]
@@ -78,10 +115,160 @@ impl InboundContent {
         Ok(Self {
             parts,
             content_type,
+            sniffed_type: None,
             body: Some(body),
+            protobuf: None,
+            transform_requested: false,
+            html_summary: None,
         })
     }
 
+    /// Decode a protobuf body holding a `message` into JSON for plugins,
+    /// leaving it as it is if it doesn't decode. The body is encoded back
+    /// into protobuf by [InboundContent::into_response].
+    pub async fn decode_protobuf(&mut self, message: MessageDescriptor) {
+        let Some(body) = self.body.take() else {
+            return;
+        };
+        let body = match body.collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(e) => {
+                let error = futures::stream::once(async move { Err(e) });
+                self.body = Some(http_body_util::StreamBody::new(error).boxed_unsync());
+                return;
+            }
+        };
+        match protobuf::decode(&message, &body) {
+            Ok(json) => {
+                let content_type = self
+                    .parts
+                    .headers
+                    .get(hyper::header::CONTENT_TYPE)
+                    .cloned()
+                    .unwrap_or_else(|| HeaderValue::from_static("application/x-protobuf"));
+                protobuf::set_json_headers(&mut self.parts.headers, &message);
+                self.content_type = "application/json".to_string();
+                self.protobuf = Some((message, content_type));
+                self.body = Some(full_body(Bytes::from(json)));
+            }
+            Err(e) => {
+                debug!("Passing protobuf content through undecoded: {:#}", e);
+                self.body = Some(full_body(body));
+            }
+        }
+    }
+
+    /// The message type of the body, if it was decoded from protobuf
+    pub fn protobuf_message(&self) -> Option<&MessageDescriptor> {
+        self.protobuf.as_ref().map(|(message, _)| message)
+    }
+
+    /// Detect the content's MIME type from its first bytes (see [sniff]) and,
+    /// for text in another charset, transcode the body to UTF-8 and update
+    /// `Content-Type` to match.
+    pub async fn sniff(&mut self) {
+        use futures::StreamExt;
+        use http_body::Frame;
+        use http_body_util::{BodyStream, StreamBody};
+
+        let Some(mut body) = self.body.take() else {
+            return;
+        };
+        let mut prefix = Vec::new();
+        let mut error = None;
+        while prefix.len() < sniff::SNIFF_LEN {
+            match body.frame().await {
+                Some(Ok(frame)) => {
+                    if let Some(data) = frame.data_ref() {
+                        prefix.extend_from_slice(data);
+                    }
+                }
+                Some(Err(e)) => {
+                    error = Some(e);
+                    break;
+                }
+                None => break,
+            }
+        }
+
+        let sniffed_type = sniff::sniff(&self.content_type, &prefix);
+        let encoding = sniff::is_text(&sniffed_type).then(|| {
+            sniff::detect_charset(&self.content_type, &prefix, sniffed_type == "text/html")
+        });
+
+        // Put the bytes read back in front of the rest of the body
+        let head = futures::stream::iter(
+            std::iter::once(Ok(Frame::data(Bytes::from(prefix)))).chain(error.map(Err)),
+        );
+        let mut body = StreamBody::new(head.chain(BodyStream::new(body))).boxed_unsync();
+
+        if let Some(encoding) = encoding
+            && encoding != encoding_rs::UTF_8
+        {
+            debug!(
+                "Transcoding {} content from {} to UTF-8",
+                sniffed_type,
+                encoding.name()
+            );
+            body = InboundContent::transcode(body, encoding);
+            let essence = match sniff::essence(&self.content_type) {
+                declared if declared.is_empty() || declared == "unknown" => sniffed_type.clone(),
+                declared => declared,
+            };
+            self.content_type = format!("{}; charset=utf-8", essence);
+            if let Ok(value) = hyper::header::HeaderValue::from_str(&self.content_type) {
+                self.parts
+                    .headers
+                    .insert(hyper::header::CONTENT_TYPE, value);
+            }
+        }
+        self.sniffed_type = Some(sniffed_type);
+        self.body = Some(body);
+    }
+
+    /// Decode a body in `encoding` into UTF-8 as it streams
+    fn transcode(
+        body: UnsyncBoxBody<Bytes, ErrorCode>,
+        encoding: &'static encoding_rs::Encoding,
+    ) -> UnsyncBoxBody<Bytes, ErrorCode> {
+        use http_body::Frame;
+        use http_body_util::StreamBody;
+
+        fn decode(decoder: &mut encoding_rs::Decoder, input: &[u8], last: bool) -> Bytes {
+            let capacity = decoder
+                .max_utf8_buffer_length(input.len())
+                .unwrap_or(input.len() * 3 + 16);
+            let mut output = String::with_capacity(capacity);
+            let _ = decoder.decode_to_string(input, &mut output, last);
+            Bytes::from(output)
+        }
+
+        let decoder = encoding.new_decoder_with_bom_removal();
+        let stream = futures::stream::unfold(Some((body, decoder)), |state| async move {
+            let (mut body, mut decoder) = state?;
+            loop {
+                match body.frame().await {
+                    Some(Ok(frame)) => {
+                        let Some(data) = frame.data_ref() else {
+                            continue;
+                        };
+                        let decoded = decode(&mut decoder, data, false);
+                        // Skip frames holding only part of a character
+                        if !decoded.is_empty() {
+                            return Some((Ok(Frame::data(decoded)), Some((body, decoder))));
+                        }
+                    }
+                    Some(Err(e)) => return Some((Err(e), None)),
+                    None => {
+                        let decoded = decode(&mut decoder, &[], true);
+                        return (!decoded.is_empty()).then(|| (Ok(Frame::data(decoded)), None));
+                    }
+                }
+            }
+        });
+        StreamBody::new(stream).boxed_unsync()
+    }
+
     #[cfg(test)]
     fn compress(
         parts: &Parts,
@@ -207,12 +394,63 @@ impl InboundContent {
         self.content_type.clone()
     }
 
+    /// Replace the content type, in the response's `Content-Type` too
+    pub fn set_content_type(&mut self, content_type: &str) {
+        if let Ok(value) = HeaderValue::from_str(content_type) {
+            self.parts
+                .headers
+                .insert(hyper::header::CONTENT_TYPE, value);
+        }
+        self.content_type = content_type.to_string();
+    }
+
+    /// The MIME type essence detected from the body, if it has been sniffed
+    pub fn sniffed_type(&self) -> Option<String> {
+        self.sniffed_type.clone()
+    }
+
     pub fn body(&mut self) -> Result<Option<UnsyncBoxBody<Bytes, ErrorCode>>> {
         Ok(self.body.take())
     }
 
     pub fn set_body(&mut self, content: UnsyncBoxBody<Bytes, ErrorCode>) {
         self.body = Some(content);
+        self.html_summary = None;
+    }
+
+    /// The summary of the HTML body, if it was summarized since it was last
+    /// replaced
+    pub fn html_summary(&self) -> Option<Arc<HtmlSummary>> {
+        self.html_summary.clone()
+    }
+
+    /// Keep `summary` of the body, for the plugins summarizing it next
+    pub fn set_html_summary(&mut self, summary: Arc<HtmlSummary>) {
+        self.html_summary = Some(summary);
+    }
+
+    /// A copy of this content with `body` in place of its own
+    pub(crate) fn with_body(&self, body: UnsyncBoxBody<Bytes, ErrorCode>) -> Self {
+        Self {
+            parts: self.parts.clone(),
+            content_type: self.content_type.clone(),
+            sniffed_type: self.sniffed_type.clone(),
+            body: Some(body),
+            protobuf: self.protobuf.clone(),
+            transform_requested: false,
+            html_summary: None,
+        }
+    }
+
+    /// Ask for the body to be passed through the handling plugin's
+    /// `body-transform` export
+    pub(crate) fn request_transform(&mut self) {
+        self.transform_requested = true;
+    }
+
+    /// Whether a transform was asked for since the last call
+    pub(crate) fn take_transform_request(&mut self) -> bool {
+        std::mem::take(&mut self.transform_requested)
     }
 
     pub fn into_response(self) -> Result<Response<UnsyncBoxBody<Bytes, ErrorCode>>> {
@@ -228,6 +466,13 @@ impl InboundContent {
         // let body = InboundContent::compress(&self.parts, body)?;
 
         let mut parts = self.parts;
+        let body = match self.protobuf {
+            Some((message, content_type)) => {
+                protobuf::restore_headers(&mut parts.headers, content_type);
+                protobuf::encode_body(body, message)
+            }
+            None => body,
+        };
         // Content length is no longer valid after decompression/modification
         parts.headers.remove(hyper::header::CONTENT_LENGTH);
         // Remove content-encoding as we have decompressed the body
@@ -826,4 +1071,70 @@ mod tests {
             "Full lifecycle should preserve data"
         );
     }
+
+    #[tokio::test]
+    async fn test_sniff_transcodes_text_to_utf8() {
+        let mut response = hyper::Response::new(());
+        response.headers_mut().insert(
+            hyper::header::CONTENT_TYPE,
+            "text/html; charset=windows-1252".parse().unwrap(),
+        );
+        let (parts, _) = response.into_parts();
+        let body = create_body(b"<html><p>caf\xe9 na\xefve</p></html>");
+        let mut content =
+            InboundContent::new(parts, "text/html; charset=windows-1252".into(), body)
+                .expect("InboundContent::new should succeed");
+
+        content.sniff().await;
+        assert_eq!(content.sniffed_type().as_deref(), Some("text/html"));
+        assert_eq!(content.content_type(), "text/html; charset=utf-8");
+
+        let response = content
+            .into_response()
+            .expect("into_response should succeed");
+        assert_eq!(
+            response.headers()[hyper::header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
+        );
+        let data = body_to_bytes(response.into_body()).await;
+        assert_eq!(
+            String::from_utf8(data).unwrap(),
+            "<html><p>café naïve</p></html>"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sniff_detects_mislabelled_binary() {
+        let (parts, _) = hyper::Response::new(()).into_parts();
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+        let mut content = InboundContent::new(parts, "text/plain".into(), create_body(png))
+            .expect("InboundContent::new should succeed");
+
+        content.sniff().await;
+        assert_eq!(content.sniffed_type().as_deref(), Some("image/png"));
+        assert_eq!(content.content_type(), "text/plain");
+        let body = content.body().unwrap().unwrap();
+        assert_eq!(body_to_bytes(body).await, png);
+    }
+
+    /// Redaction rules replace the body of content they redact, so a plugin
+    /// summarizing it after one exempt from them summarizes its own body
+    #[tokio::test]
+    async fn replacing_the_body_drops_its_html_summary() {
+        let parts = create_parts_with_encoding("");
+        let body = create_body(TEST_HTML.as_bytes());
+        let mut content = InboundContent::new(parts, "text/html".to_string(), body).unwrap();
+
+        let body = content.body().unwrap().unwrap();
+        let (body, summary) = crate::plugins::html::summarize(body, "text/html").await;
+        content.set_body(body);
+        content.set_html_summary(summary.unwrap());
+        assert_eq!(
+            content.html_summary().unwrap().title.as_deref(),
+            Some("Test Page")
+        );
+
+        content.set_body(create_body(b"<title>Redacted</title>"));
+        assert!(content.html_summary().is_none());
+    }
 }
@@ -1,7 +1,14 @@
 use anyhow::{Result, bail};
 use cel_cxx::Activation;
+use hyper::HeaderMap;
+use hyper::header::{CONTENT_ENCODING, CONTENT_LENGTH, TRANSFER_ENCODING};
+use std::net::SocketAddr;
 use wasmtime::Store;
 
+use crate::http::graphql::GraphqlOperation;
+use crate::proxy::findings::FindingKind;
+use crate::proxy::upstream_cert::UpstreamCert;
+use crate::proxy::vhost::ConnectionInfo;
 use crate::wasm::{
     Host,
     bindgen::{
@@ -12,6 +19,8 @@ use crate::wasm::{
 
 pub mod connect;
 pub mod content;
+pub mod mqtt;
+pub mod raw_stream;
 pub mod request;
 pub mod response;
 pub mod timer;
@@ -29,6 +38,46 @@ pub trait Event: Send {
         }
     }
 
+    /// The upstream server the event's response was received from, for
+    /// events that carry one
+    fn upstream_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// The certificate the upstream server presented, for events that carry
+    /// one
+    fn upstream_cert(&self) -> Option<UpstreamCert> {
+        None
+    }
+
+    /// The intercepted connection the event's request arrived on, for events
+    /// that carry one
+    fn connection(&self) -> Option<ConnectionInfo> {
+        None
+    }
+
+    /// Sensitive data found in the request of the flow the event belongs to
+    fn findings(&self) -> Vec<FindingKind> {
+        Vec::new()
+    }
+
+    /// The GraphQL operation run by the request of the flow the event
+    /// belongs to, if it's a GraphQL request
+    fn graphql(&self) -> Option<GraphqlOperation> {
+        None
+    }
+
+    /// Bytes of body plugins handling the event can read, or `None` if it
+    /// isn't known before the body is read
+    fn body_size(&self) -> Option<u64> {
+        Some(0)
+    }
+
+    /// Whether plugins handling the event read its body encoded, ex: gzipped
+    fn body_encoded(&self) -> bool {
+        false
+    }
+
     /// Converts into Event by consuming the event and storing it in the provided Store
     fn into_event_data(self: Box<Self>, store: &mut Store<Host>) -> Result<WasmEvent>;
 
@@ -41,6 +90,24 @@ pub trait Event: Send {
     fn bind_cel_activation<'a>(&'a self, a: Activation<'a>) -> Option<Activation<'a>>;
 }
 
+/// The size of the body of an HTTP message with `headers`, from its
+/// `Content-Length`. Messages with neither a length nor chunked encoding have
+/// no body.
+pub(crate) fn body_size(headers: &HeaderMap) -> Option<u64> {
+    match headers.get(CONTENT_LENGTH) {
+        Some(length) => length.to_str().ok()?.parse().ok(),
+        None if headers.contains_key(TRANSFER_ENCODING) => None,
+        None => Some(0),
+    }
+}
+
+/// Whether the body of an HTTP message with `headers` has a content coding
+pub(crate) fn body_encoded(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_ENCODING)
+        .is_some_and(|encoding| encoding != "identity")
+}
+
 macro_rules! ensure_matches {
     ($expr:expr, $pat:pat $(if $guard:expr)? $(,)?) => {
         match $expr {
@@ -66,6 +133,8 @@ impl EventKind {
             }
             EventKind::InboundContent => ensure_matches!(event_data, WasmEvent::InboundContent(_)),
             EventKind::Timer => ensure_matches!(event_data, WasmEvent::Timer(_)),
+            EventKind::RawStream => ensure_matches!(event_data, WasmEvent::RawStream(_)),
+            EventKind::MqttMessage => ensure_matches!(event_data, WasmEvent::MqttMessage(_)),
         }
     }
 }
@@ -78,6 +147,8 @@ impl std::fmt::Display for EventKind {
             EventKind::Connect => write!(f, "connect"),
             EventKind::InboundContent => write!(f, "inbound_content"),
             EventKind::Timer => write!(f, "timer"),
+            EventKind::RawStream => write!(f, "raw_stream"),
+            EventKind::MqttMessage => write!(f, "mqtt_message"),
         }
     }
 }
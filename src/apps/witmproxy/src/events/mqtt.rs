@@ -0,0 +1,100 @@
+use anyhow::Result;
+use cel_cxx::Activation;
+use wasmtime::Store;
+
+use crate::events::Event;
+use crate::plugins::cel::{CelMqtt, CelTime};
+use crate::wasm::{
+    Host,
+    bindgen::{
+        Event as WasmEvent,
+        witmproxy::plugin::capabilities::{
+            CapabilityKind, EventKind, MqttMessage as WitMqttMessage,
+        },
+    },
+};
+
+/// A message published over an MQTT stream, in either direction, offered to
+/// plugins before it's forwarded. Plugins can rewrite it or drop it.
+#[derive(Debug, Clone)]
+pub struct MqttMessage {
+    pub host: String,
+    pub port: u16,
+    pub from_client: bool,
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: u8,
+    pub retain: bool,
+}
+
+impl From<WitMqttMessage> for MqttMessage {
+    fn from(message: WitMqttMessage) -> Self {
+        Self {
+            host: message.host,
+            port: message.port,
+            from_client: message.from_client,
+            topic: message.topic,
+            payload: message.payload,
+            qos: message.qos,
+            retain: message.retain,
+        }
+    }
+}
+
+impl From<&MqttMessage> for CelMqtt {
+    fn from(message: &MqttMessage) -> Self {
+        CelMqtt {
+            host: message.host.clone(),
+            port: message.port,
+            from_client: message.from_client,
+            topic: message.topic.clone(),
+            qos: message.qos,
+            retain: message.retain,
+        }
+    }
+}
+
+impl Event for MqttMessage {
+    fn capability(&self) -> CapabilityKind {
+        CapabilityKind::HandleEvent(EventKind::MqttMessage)
+    }
+
+    fn body_size(&self) -> Option<u64> {
+        Some(self.payload.len() as u64)
+    }
+
+    fn into_event_data(self: Box<Self>, _store: &mut Store<Host>) -> Result<WasmEvent> {
+        Ok(WasmEvent::MqttMessage(WitMqttMessage {
+            host: self.host,
+            port: self.port,
+            from_client: self.from_client,
+            topic: self.topic,
+            payload: self.payload,
+            qos: self.qos,
+            retain: self.retain,
+        }))
+    }
+
+    fn register_cel_env<'a>(env: cel_cxx::EnvBuilder<'a>) -> Result<cel_cxx::EnvBuilder<'a>>
+    where
+        Self: Sized,
+    {
+        let env = env
+            .declare_variable::<CelMqtt>("mqtt")?
+            .register_member_function("host", CelMqtt::host)?
+            .register_member_function("port", CelMqtt::port)?
+            .register_member_function("from_client", CelMqtt::from_client)?
+            .register_member_function("topic", CelMqtt::topic)?
+            .register_member_function("qos", CelMqtt::qos)?
+            .register_member_function("retain", CelMqtt::retain)?
+            .register_member_function("topic_matches", CelMqtt::topic_matches)?;
+        Ok(env)
+    }
+
+    fn bind_cel_activation<'a>(&'a self, activation: Activation<'a>) -> Option<Activation<'a>> {
+        activation
+            .bind_variable("mqtt", CelMqtt::from(self))
+            .ok()
+            .and_then(|a| a.bind_variable("time", CelTime::now()).ok())
+    }
+}
@@ -0,0 +1,78 @@
+use anyhow::Result;
+use cel_cxx::Activation;
+use wasmtime::Store;
+
+use crate::events::Event;
+use crate::plugins::cel::{CelStream, CelTime};
+use crate::wasm::{
+    Host,
+    bindgen::{
+        Event as WasmEvent,
+        witmproxy::plugin::capabilities::{CapabilityKind, EventKind, StreamContext},
+    },
+};
+
+/// A tunneled TCP stream which isn't TLS or HTTP, offered to plugins once
+/// its protocol has been classified. Plugins can let it through or close it.
+#[derive(Debug, Clone)]
+pub struct RawStream {
+    pub host: String,
+    pub port: u16,
+    pub protocol: String,
+    pub preface: Vec<u8>,
+}
+
+impl From<StreamContext> for RawStream {
+    fn from(ctx: StreamContext) -> Self {
+        Self {
+            host: ctx.host,
+            port: ctx.port,
+            protocol: ctx.protocol,
+            preface: ctx.preface,
+        }
+    }
+}
+
+impl From<&RawStream> for CelStream {
+    fn from(stream: &RawStream) -> Self {
+        CelStream {
+            host: stream.host.clone(),
+            port: stream.port,
+            protocol: stream.protocol.clone(),
+        }
+    }
+}
+
+impl Event for RawStream {
+    fn capability(&self) -> CapabilityKind {
+        CapabilityKind::HandleEvent(EventKind::RawStream)
+    }
+
+    fn into_event_data(self: Box<Self>, _store: &mut Store<Host>) -> Result<WasmEvent> {
+        Ok(WasmEvent::RawStream(StreamContext {
+            host: self.host,
+            port: self.port,
+            protocol: self.protocol,
+            preface: self.preface,
+        }))
+    }
+
+    fn register_cel_env<'a>(env: cel_cxx::EnvBuilder<'a>) -> Result<cel_cxx::EnvBuilder<'a>>
+    where
+        Self: Sized,
+    {
+        let env = env
+            .declare_variable::<CelStream>("stream")?
+            .register_member_function("host", CelStream::host)?
+            .register_member_function("port", CelStream::port)?
+            .register_member_function("protocol", CelStream::protocol)?;
+        Ok(env)
+    }
+
+    fn bind_cel_activation<'a>(&'a self, activation: Activation<'a>) -> Option<Activation<'a>> {
+        activation
+            .bind_variable("stream", CelStream::from(self))
+            .ok()
+            .and_then(|a| a.bind_variable("time", CelTime::now()).ok())
+    }
+}
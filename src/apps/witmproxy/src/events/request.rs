@@ -1,5 +1,8 @@
 use crate::events::Event;
-use crate::plugins::cel::{CelRequest, CelTime};
+use crate::http::graphql::GraphqlOperation;
+use crate::plugins::cel::{CelConnection, CelFlow, CelGraphql, CelRequest, CelTime};
+use crate::proxy::findings::FindingKind;
+use crate::proxy::vhost::ConnectionInfo;
 use crate::wasm::Host;
 use crate::wasm::bindgen::witmproxy::plugin::capabilities::CapabilityKind;
 use crate::wasm::bindgen::witmproxy::plugin::capabilities::Event as WasmEvent;
@@ -18,6 +21,14 @@ impl Event for WasiRequest {
         CapabilityKind::HandleEvent(EventKind::Request)
     }
 
+    fn body_size(&self) -> Option<u64> {
+        crate::events::body_size(&self.headers)
+    }
+
+    fn body_encoded(&self) -> bool {
+        crate::events::body_encoded(&self.headers)
+    }
+
     fn into_event_data(self: Box<Self>, store: &mut Store<Host>) -> Result<WasmEvent> {
         let handle: Resource<WasiRequest> = store.data_mut().http().table.push(*self)?;
         Ok(WasmEvent::Request(handle))
@@ -31,7 +42,20 @@ impl Event for WasiRequest {
             .register_member_function("path", CelRequest::path)?
             .register_member_function("query", CelRequest::query)?
             .register_member_function("method", CelRequest::method)?
-            .register_member_function("headers", CelRequest::headers)?;
+            .register_member_function("headers", CelRequest::headers)?
+            .register_member_function("host_mismatch", CelRequest::host_mismatch)?
+            .register_member_function("jwt_claim", CelRequest::jwt_claim)?
+            .declare_variable::<CelConnection>("connection")?
+            .register_member_function("authority", CelConnection::authority)?
+            .register_member_function("sni", CelConnection::sni)?
+            .declare_variable::<CelFlow>("flow")?
+            .register_member_function("findings", CelFlow::findings)?
+            .register_member_function("has_finding", CelFlow::has_finding)?
+            .declare_variable::<CelGraphql>("graphql")?
+            .register_member_function("operation", CelGraphql::operation)?
+            .register_member_function("operation_type", CelGraphql::operation_type)?
+            .register_member_function("fields", CelGraphql::fields)?
+            .register_member_function("has_field", CelGraphql::has_field)?;
         Ok(env)
     }
 
@@ -39,6 +63,79 @@ impl Event for WasiRequest {
         activation
             .bind_variable("request", CelRequest::from(self))
             .ok()
+            .and_then(|a| a.bind_variable("connection", CelConnection::default()).ok())
+            .and_then(|a| a.bind_variable("flow", CelFlow::default()).ok())
+            .and_then(|a| a.bind_variable("graphql", CelGraphql::default()).ok())
+            .and_then(|a| a.bind_variable("time", CelTime::now()).ok())
+    }
+}
+
+/// A request received over an intercepted TLS connection, which knows the
+/// names the client used for that connection
+pub struct InterceptedRequest {
+    pub request: WasiRequest,
+    pub connection: ConnectionInfo,
+    /// Sensitive data found in the request's URL and headers
+    pub findings: Vec<FindingKind>,
+    /// The GraphQL operation the request runs, if it's a GraphQL request
+    pub graphql: Option<GraphqlOperation>,
+}
+
+impl Event for InterceptedRequest {
+    fn capability(&self) -> CapabilityKind {
+        CapabilityKind::HandleEvent(EventKind::Request)
+    }
+
+    fn connection(&self) -> Option<ConnectionInfo> {
+        Some(self.connection.clone())
+    }
+
+    fn findings(&self) -> Vec<FindingKind> {
+        self.findings.clone()
+    }
+
+    fn graphql(&self) -> Option<GraphqlOperation> {
+        self.graphql.clone()
+    }
+
+    fn body_size(&self) -> Option<u64> {
+        self.request.body_size()
+    }
+
+    fn body_encoded(&self) -> bool {
+        self.request.body_encoded()
+    }
+
+    fn into_event_data(self: Box<Self>, store: &mut Store<Host>) -> Result<WasmEvent> {
+        Box::new(self.request).into_event_data(store)
+    }
+
+    fn register_cel_env<'a>(env: cel_cxx::EnvBuilder<'a>) -> Result<cel_cxx::EnvBuilder<'a>>
+    where
+        Self: Sized,
+    {
+        // No-op as this is handled by WasiRequest
+        Ok(env)
+    }
+
+    fn bind_cel_activation<'a>(&'a self, activation: Activation<'a>) -> Option<Activation<'a>> {
+        let mut request = CelRequest::from(&self.request);
+        request.host_mismatch = self.connection.mismatch(&request.host).is_some();
+        activation
+            .bind_variable("request", request)
+            .ok()
+            .and_then(|a| {
+                a.bind_variable("connection", CelConnection::from(&self.connection))
+                    .ok()
+            })
+            .and_then(|a| {
+                a.bind_variable("flow", CelFlow::from(self.findings.as_slice()))
+                    .ok()
+            })
+            .and_then(|a| {
+                a.bind_variable("graphql", CelGraphql::from(self.graphql.as_ref()))
+                    .ok()
+            })
             .and_then(|a| a.bind_variable("time", CelTime::now()).ok())
     }
 }
@@ -72,6 +169,9 @@ where
         activation
             .bind_variable("request", CelRequest::from(self))
             .ok()
+            .and_then(|a| a.bind_variable("connection", CelConnection::default()).ok())
+            .and_then(|a| a.bind_variable("flow", CelFlow::default()).ok())
+            .and_then(|a| a.bind_variable("graphql", CelGraphql::default()).ok())
             .and_then(|a| a.bind_variable("time", CelTime::now()).ok())
     }
 }
@@ -1,9 +1,14 @@
 use anyhow::Result;
+use std::net::SocketAddr;
 use wasmtime::Store;
 use wasmtime_wasi_http::p3::{Response, WasiHttpView};
 
 use crate::events::Event;
-use crate::plugins::cel::{CelRequest, CelResponse, CelTime};
+use crate::http::graphql::GraphqlOperation;
+use crate::plugins::cel::{CelFlow, CelGraphql, CelRequest, CelResponse, CelTime, CelUpstream};
+use crate::proxy::dial::AddressFamily;
+use crate::proxy::findings::FindingKind;
+use crate::proxy::upstream_cert::UpstreamCert;
 use crate::wasm::bindgen::witmproxy::plugin::capabilities::{
     ContextualResponse as WasiContextualResponse, RequestContext,
 };
@@ -18,6 +23,15 @@ use crate::wasm::{
 pub struct ContextualResponse {
     pub request: RequestContext,
     pub response: Response,
+    /// The upstream server the response was received from, if known
+    pub upstream_addr: Option<SocketAddr>,
+    /// The certificate the upstream server presented, if it was reached
+    /// over TLS
+    pub upstream_cert: Option<UpstreamCert>,
+    /// Sensitive data found in the request's URL and headers
+    pub findings: Vec<FindingKind>,
+    /// The GraphQL operation the request ran, if it was a GraphQL request
+    pub graphql: Option<GraphqlOperation>,
 }
 
 impl Event for ContextualResponse {
@@ -25,6 +39,30 @@ impl Event for ContextualResponse {
         CapabilityKind::HandleEvent(EventKind::Response)
     }
 
+    fn upstream_addr(&self) -> Option<SocketAddr> {
+        self.upstream_addr
+    }
+
+    fn upstream_cert(&self) -> Option<UpstreamCert> {
+        self.upstream_cert.clone()
+    }
+
+    fn findings(&self) -> Vec<FindingKind> {
+        self.findings.clone()
+    }
+
+    fn graphql(&self) -> Option<GraphqlOperation> {
+        self.graphql.clone()
+    }
+
+    fn body_size(&self) -> Option<u64> {
+        crate::events::body_size(&self.response.headers)
+    }
+
+    fn body_encoded(&self) -> bool {
+        crate::events::body_encoded(&self.response.headers)
+    }
+
     fn into_event_data(self: Box<Self>, store: &mut Store<Host>) -> Result<WasmEvent> {
         let handle = store.data_mut().http().table.push(self.response)?;
         let response = WasiContextualResponse {
@@ -41,7 +79,12 @@ impl Event for ContextualResponse {
         let env = env
             .declare_variable::<CelResponse>("response")?
             .register_member_function("status", CelResponse::status)?
-            .register_member_function("headers", CelResponse::headers)?;
+            .register_member_function("headers", CelResponse::headers)?
+            .register_member_function("address_family", CelResponse::address_family)?
+            .declare_variable::<CelUpstream>("upstream")?
+            .register_member_function("cert_fingerprint", CelUpstream::cert_fingerprint)?
+            .register_member_function("cert_issuer", CelUpstream::cert_issuer)?
+            .register_member_function("cert_sans", CelUpstream::cert_sans)?;
         Ok(env)
     }
 
@@ -53,7 +96,22 @@ impl Event for ContextualResponse {
             .bind_variable("request", CelRequest::from(&self.request))
             .ok()
             .and_then(|a| {
-                a.bind_variable("response", CelResponse::from(&self.response))
+                let mut response = CelResponse::from(&self.response);
+                if let Some(addr) = self.upstream_addr {
+                    response.address_family = AddressFamily::of(addr.ip()).to_string();
+                }
+                a.bind_variable("response", response).ok()
+            })
+            .and_then(|a| {
+                a.bind_variable("upstream", CelUpstream::from(self.upstream_cert.as_ref()))
+                    .ok()
+            })
+            .and_then(|a| {
+                a.bind_variable("flow", CelFlow::from(self.findings.as_slice()))
+                    .ok()
+            })
+            .and_then(|a| {
+                a.bind_variable("graphql", CelGraphql::from(self.graphql.as_ref()))
                     .ok()
             })
             .and_then(|a| a.bind_variable("time", CelTime::now()).ok())
@@ -0,0 +1,465 @@
+//! Recognizing GraphQL requests and the operation they run, so scopes can
+//! match operations (`graphql.operation() == 'DeleteUser'`) rather than
+//! opaque JSON bodies.
+//!
+//! Only as much of the document is parsed as is needed to find each
+//! operation's type, name and top-level fields; the rest is skipped.
+
+use hyper::Request;
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Largest request body buffered to find the GraphQL operation it runs
+pub const MAX_GRAPHQL_BODY: usize = 256 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OperationType {
+    Query,
+    Mutation,
+    Subscription,
+}
+
+impl OperationType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OperationType::Query => "query",
+            OperationType::Mutation => "mutation",
+            OperationType::Subscription => "subscription",
+        }
+    }
+
+    fn from_keyword(keyword: &str) -> Option<Self> {
+        match keyword {
+            "query" => Some(OperationType::Query),
+            "mutation" => Some(OperationType::Mutation),
+            "subscription" => Some(OperationType::Subscription),
+            _ => None,
+        }
+    }
+}
+
+/// The operation a GraphQL request runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphqlOperation {
+    /// Empty for anonymous operations
+    pub name: String,
+    pub operation_type: OperationType,
+    /// Names (not aliases) of the fields selected at the top level, including
+    /// those of inline fragments but not of fragment spreads
+    pub fields: Vec<String>,
+}
+
+/// Whether `req` looks like a GraphQL request whose body holds the
+/// operation: a POST to a path with a `graphql` segment, or one sent as
+/// `application/graphql`
+pub fn is_graphql_post<B>(req: &Request<B>) -> bool {
+    if req.method() != hyper::Method::POST {
+        return false;
+    }
+    let graphql_path = req
+        .uri()
+        .path()
+        .split('/')
+        .any(|segment| segment.to_ascii_lowercase().starts_with("graphql"));
+    graphql_path || content_type(req).starts_with("application/graphql")
+}
+
+/// Whether the body of a GraphQL POST is small enough to be buffered
+pub fn fits_buffer<B>(req: &Request<B>) -> bool {
+    req.headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok())
+        .is_some_and(|length| length <= MAX_GRAPHQL_BODY)
+}
+
+fn content_type<B>(req: &Request<B>) -> String {
+    req.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// The operation run by a GraphQL request with `body`. JSON bodies hold the
+/// document as `query`, alongside an optional `operationName`; batches run
+/// several, of which the first is returned. `application/graphql` bodies are
+/// the document itself, with the operation named in the URL's query string.
+pub fn parse_request<B>(req: &Request<B>, body: &[u8]) -> Option<GraphqlOperation> {
+    if content_type(req).starts_with("application/graphql") {
+        let operation_name = req.uri().query().and_then(|query| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .find(|(key, _)| key == "operationName")
+                .map(|(_, value)| value.into_owned())
+        });
+        return parse_document(std::str::from_utf8(body).ok()?, operation_name.as_deref());
+    }
+    parse_json(body)
+}
+
+/// The operation run by a JSON GraphQL request body, or by the first request
+/// of a batch
+pub fn parse_json(body: &[u8]) -> Option<GraphqlOperation> {
+    let json: Value = serde_json::from_slice(body).ok()?;
+    let request = match &json {
+        Value::Array(batch) => batch.first()?,
+        request => request,
+    };
+    parse_document(
+        request.get("query")?.as_str()?,
+        request.get("operationName").and_then(Value::as_str),
+    )
+}
+
+/// The operation named `operation_name` in `document`, or its first one
+pub fn parse_document(document: &str, operation_name: Option<&str>) -> Option<GraphqlOperation> {
+    let mut tokens = Tokens::new(document);
+    let mut first = None;
+    while let Some(token) = tokens.next() {
+        let operation = match token {
+            // The query shorthand: an anonymous query with no variables
+            Token::Punct('{') => GraphqlOperation {
+                name: String::new(),
+                operation_type: OperationType::Query,
+                fields: selected_fields(&mut tokens),
+            },
+            Token::Name(keyword) => match OperationType::from_keyword(keyword) {
+                Some(operation_type) => {
+                    let mut name = String::new();
+                    // Skip the name, variables and directives up to the selection set
+                    let mut depth = 0;
+                    loop {
+                        match tokens.next()? {
+                            Token::Name(n) if depth == 0 && name.is_empty() => name = n.to_string(),
+                            Token::Punct('(' | '[') => depth += 1,
+                            Token::Punct(')' | ']') => depth -= 1,
+                            Token::Punct('{') if depth == 0 => break,
+                            Token::Punct('{') => depth += 1,
+                            Token::Punct('}') => depth -= 1,
+                            _ => {}
+                        }
+                    }
+                    GraphqlOperation {
+                        name,
+                        operation_type,
+                        fields: selected_fields(&mut tokens),
+                    }
+                }
+                // Fragments, and type system definitions which have no place
+                // in requests but are skipped all the same
+                None => {
+                    tokens.skip_definition();
+                    continue;
+                }
+            },
+            _ => continue,
+        };
+        match operation_name {
+            Some(wanted) if operation.name == wanted => return Some(operation),
+            Some(_) => {
+                first.get_or_insert(operation);
+            }
+            None => return Some(operation),
+        }
+    }
+    first
+}
+
+/// Names of the fields in a selection set whose `{` has been consumed,
+/// consuming it up to its closing `}`
+fn selected_fields(tokens: &mut Tokens<'_>) -> Vec<String> {
+    let mut fields = Vec::new();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Punct('}') => break,
+            Token::Name(name) => {
+                // An alias is followed by `:` and the field's name
+                let mut field = name;
+                if tokens.peek() == Some(Token::Punct(':')) {
+                    tokens.next();
+                    match tokens.next() {
+                        Some(Token::Name(name)) => field = name,
+                        _ => break,
+                    }
+                }
+                fields.push(field.to_string());
+                tokens.skip_arguments_and_directives();
+                if tokens.peek() == Some(Token::Punct('{')) {
+                    tokens.next();
+                    tokens.skip_block();
+                }
+            }
+            Token::Spread => match tokens.next() {
+                // Inline fragment: `... on Type { fields }`
+                Some(Token::Name("on")) => {
+                    tokens.next();
+                    tokens.skip_arguments_and_directives();
+                    if tokens.next() == Some(Token::Punct('{')) {
+                        fields.extend(selected_fields(tokens));
+                    }
+                }
+                // Inline fragment without a type condition
+                Some(Token::Punct('{')) => fields.extend(selected_fields(tokens)),
+                // Fragment spread
+                _ => tokens.skip_arguments_and_directives(),
+            },
+            _ => {}
+        }
+    }
+    fields
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token<'a> {
+    Punct(char),
+    Spread,
+    Name(&'a str),
+    /// A string or number, whose value isn't needed
+    Value,
+}
+
+/// The tokens of a GraphQL document, skipping whitespace, commas and comments
+struct Tokens<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            position: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<Token<'a>> {
+        let mut ahead = Tokens {
+            source: self.source,
+            position: self.position,
+        };
+        ahead.next()
+    }
+
+    /// Skip a block whose `{` has been consumed, up to its closing `}`
+    fn skip_block(&mut self) {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next() {
+                Some(Token::Punct('{')) => depth += 1,
+                Some(Token::Punct('}')) => depth -= 1,
+                Some(_) => {}
+                None => return,
+            }
+        }
+    }
+
+    /// Skip any `(arguments)` and `@directive(arguments)` that follow
+    fn skip_arguments_and_directives(&mut self) {
+        loop {
+            match self.peek() {
+                Some(Token::Punct('(')) => {
+                    self.next();
+                    let mut depth = 1;
+                    while depth > 0 {
+                        match self.next() {
+                            Some(Token::Punct('(')) => depth += 1,
+                            Some(Token::Punct(')')) => depth -= 1,
+                            Some(_) => {}
+                            None => return,
+                        }
+                    }
+                }
+                Some(Token::Punct('@')) => {
+                    self.next();
+                    self.next();
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Skip the rest of a definition up to the end of its first block
+    fn skip_definition(&mut self) {
+        while let Some(token) = self.next() {
+            if token == Token::Punct('{') {
+                self.skip_block();
+                return;
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let bytes = self.source.as_bytes();
+        loop {
+            let c = *bytes.get(self.position)?;
+            match c {
+                b' ' | b'\t' | b'\n' | b'\r' | b',' => self.position += 1,
+                // The byte order mark may start a document
+                0xef if self.source[self.position..].starts_with('\u{feff}') => self.position += 3,
+                b'#' => {
+                    while bytes.get(self.position).is_some_and(|c| *c != b'\n') {
+                        self.position += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        let start = self.position;
+        let c = bytes[start];
+        if c.is_ascii_alphabetic() || c == b'_' {
+            while bytes
+                .get(self.position)
+                .is_some_and(|c| c.is_ascii_alphanumeric() || *c == b'_')
+            {
+                self.position += 1;
+            }
+            return Some(Token::Name(&self.source[start..self.position]));
+        }
+        if c.is_ascii_digit() || c == b'-' {
+            while bytes
+                .get(self.position)
+                .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, b'-' | b'+' | b'.'))
+            {
+                self.position += 1;
+            }
+            return Some(Token::Value);
+        }
+        if self.source[start..].starts_with("\"\"\"") {
+            // Block strings end at the first unescaped `"""`
+            self.position += 3;
+            loop {
+                let rest = &self.source[self.position..];
+                match rest.find("\"\"\"") {
+                    Some(end) if rest[..end].ends_with('\\') => self.position += end + 3,
+                    Some(end) => {
+                        self.position += end + 3;
+                        break;
+                    }
+                    None => {
+                        self.position = self.source.len();
+                        break;
+                    }
+                }
+            }
+            return Some(Token::Value);
+        }
+        if c == b'"' {
+            self.position += 1;
+            while let Some(c) = bytes.get(self.position) {
+                self.position += match c {
+                    b'\\' => 2,
+                    b'"' => {
+                        self.position += 1;
+                        break;
+                    }
+                    _ => 1,
+                };
+            }
+            self.position = self.position.min(self.source.len());
+            return Some(Token::Value);
+        }
+        if self.source[start..].starts_with("...") {
+            self.position += 3;
+            return Some(Token::Spread);
+        }
+        // Punctuators, and any other character, one at a time
+        let c = self.source[start..].chars().next()?;
+        self.position += c.len_utf8();
+        Some(Token::Punct(c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operation(
+        document: &str,
+        name: Option<&str>,
+    ) -> Option<(String, &'static str, Vec<String>)> {
+        parse_document(document, name).map(|operation| {
+            (
+                operation.name,
+                operation.operation_type.as_str(),
+                operation.fields,
+            )
+        })
+    }
+
+    #[test]
+    fn operations_are_parsed() {
+        let document = r#"
+            # Remove a user and everything they own
+            mutation DeleteUser($id: ID!, $opts: Options = { cascade: true, tags: ["a", "b"] })
+                @audit(reason: "cleanup") {
+                removed: deleteUser(id: $id) { id }
+                deletePosts(owner: $id, note: """a "quoted" note""") @include(if: true)
+                ... on Mutation { auditLog { entries } }
+                ...Tracking
+            }
+            fragment Tracking on Mutation { track }
+        "#;
+        assert_eq!(
+            operation(document, None),
+            Some((
+                "DeleteUser".to_string(),
+                "mutation",
+                vec![
+                    "deleteUser".to_string(),
+                    "deletePosts".to_string(),
+                    "auditLog".to_string()
+                ]
+            ))
+        );
+        assert_eq!(
+            operation("{ me { name } viewer }", None),
+            Some((
+                String::new(),
+                "query",
+                vec!["me".to_string(), "viewer".to_string()]
+            ))
+        );
+        assert_eq!(operation("not graphql at all", None), None);
+    }
+
+    #[test]
+    fn operations_are_chosen_by_name() {
+        let document = "query A { a } subscription B { b }";
+        assert_eq!(operation(document, Some("B")).unwrap().1, "subscription");
+        assert_eq!(operation(document, Some("C")).unwrap().0, "A");
+        assert_eq!(operation(document, None).unwrap().0, "A");
+    }
+
+    #[test]
+    fn requests_are_parsed() {
+        let json = Request::post("https://api.example.com/graphql")
+            .header("content-type", "application/json")
+            .header("content-length", "100")
+            .body(())
+            .unwrap();
+        assert!(is_graphql_post(&json) && fits_buffer(&json));
+        let body = br#"[{"query": "query A { a } query B { b }", "operationName": "B"}]"#;
+        assert_eq!(parse_request(&json, body).unwrap().name, "B");
+
+        let raw = Request::post("https://api.example.com/api?operationName=Me")
+            .header("content-type", "application/graphql")
+            .body(())
+            .unwrap();
+        assert!(is_graphql_post(&raw) && !fits_buffer(&raw));
+        let operation = parse_request(&raw, b"query Me { me }").unwrap();
+        assert_eq!(operation.fields, ["me"]);
+
+        let rest = Request::post("https://api.example.com/users")
+            .body(())
+            .unwrap();
+        assert!(!is_graphql_post(&rest));
+    }
+}
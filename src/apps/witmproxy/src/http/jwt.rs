@@ -0,0 +1,300 @@
+//! Decoding JSON Web Tokens carried by requests, and verifying their
+//! signatures against the JWKS endpoints listed in `plugins.jwks_urls`.
+//!
+//! Scope expressions read claims with `request.jwt_claim('aud')`, and
+//! plugins granted the `jwt` capability can decode and verify tokens.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, anyhow, bail};
+use base64::Engine;
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::warn;
+
+/// How long a fetched key set is used before it's fetched again
+pub const KEY_SET_TTL: Duration = Duration::from_secs(600);
+
+/// Shortest time between fetches of a key set, when tokens name a key it
+/// doesn't have (as after the issuer rotates its keys)
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A decoded JWT
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Jwt {
+    pub header: Map<String, Value>,
+    pub claims: Map<String, Value>,
+    /// Whether the signature was verified against a configured key set
+    pub verified: bool,
+}
+
+impl Jwt {
+    /// Decode `token` without verifying its signature
+    pub fn decode(token: &str) -> Result<Self> {
+        let mut parts = token.trim().split('.');
+        let (Some(header), Some(claims), Some(_signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            bail!("A JWT has three dot-separated parts");
+        };
+        Ok(Self {
+            header: decode_part(header).map_err(|e| anyhow!("Invalid JWT header: {}", e))?,
+            claims: decode_part(claims).map_err(|e| anyhow!("Invalid JWT claims: {}", e))?,
+            verified: false,
+        })
+    }
+
+    pub fn claim(&self, name: &str) -> Option<&Value> {
+        self.claims.get(name)
+    }
+
+    /// The signing algorithm named by the header, ex: "RS256"
+    pub fn algorithm(&self) -> &str {
+        self.header
+            .get("alg")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+    }
+
+    /// The ID of the key the token was signed with, if the header names one
+    pub fn key_id(&self) -> Option<&str> {
+        self.header.get("kid").and_then(Value::as_str)
+    }
+}
+
+fn decode_part(part: &str) -> Result<Map<String, Value>> {
+    let json =
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(part.trim_end_matches('='))?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+/// A claim as text: strings as they are, other values as JSON
+pub fn claim_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// The JWT sent as `Authorization: Bearer`, or else the first cookie whose
+/// value decodes as one
+pub fn find_token<'a>(
+    authorization: impl IntoIterator<Item = &'a str>,
+    cookies: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let bearer = authorization.into_iter().find_map(|value| {
+        let (scheme, token) = value.trim().split_once(' ')?;
+        let token = token.trim();
+        (scheme.eq_ignore_ascii_case("bearer") && Jwt::decode(token).is_ok()).then_some(token)
+    });
+    bearer.or_else(|| {
+        cookies
+            .into_iter()
+            .flat_map(|header| header.split(';'))
+            .filter_map(|cookie| cookie.split_once('='))
+            .map(|(_, value)| value.trim().trim_matches('"'))
+            .find(|value| value.starts_with("eyJ") && Jwt::decode(value).is_ok())
+    })
+}
+
+/// Key sets fetched from the configured JWKS endpoints, which tokens are
+/// verified against. Cheap to clone; all clones share the same cache.
+#[derive(Clone, Default)]
+pub struct KeySets {
+    urls: Arc<Vec<String>>,
+    client: reqwest::Client,
+    fetched: Arc<Mutex<HashMap<String, (Instant, JwkSet)>>>,
+}
+
+impl KeySets {
+    pub fn new(urls: Vec<String>) -> Self {
+        Self {
+            urls: Arc::new(urls),
+            ..Self::default()
+        }
+    }
+
+    /// Decode `token`, verifying its signature with a key from one of the
+    /// configured key sets, and its `exp` and `nbf` claims if it has them
+    pub async fn verify(&self, token: &str) -> Result<Jwt> {
+        if self.urls.is_empty() {
+            bail!("No JWKS endpoints are configured to verify tokens against");
+        }
+        let header = jsonwebtoken::decode_header(token)?;
+        let mut validation = Validation::new(header.alg);
+        validation.validate_aud = false;
+        validation.required_spec_claims.clear();
+
+        for url in self.urls.iter() {
+            let keys = match self.keys(url, header.kid.as_deref()).await {
+                Ok(keys) => keys,
+                Err(e) => {
+                    warn!("Failed to fetch JWKS from {}: {}", url, e);
+                    continue;
+                }
+            };
+            let candidates = keys.keys.iter().filter(|jwk| match &header.kid {
+                Some(kid) => jwk.common.key_id.as_deref() == Some(kid.as_str()),
+                None => true,
+            });
+            for jwk in candidates {
+                let Ok(key) = DecodingKey::from_jwk(jwk) else {
+                    continue;
+                };
+                match jsonwebtoken::decode::<Map<String, Value>>(token, &key, &validation) {
+                    Ok(_) => {
+                        return Ok(Jwt {
+                            verified: true,
+                            ..Jwt::decode(token)?
+                        });
+                    }
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            ErrorKind::InvalidSignature
+                                | ErrorKind::InvalidAlgorithm
+                                | ErrorKind::InvalidKeyFormat
+                        ) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        bail!("No key from the configured JWKS endpoints verifies the token")
+    }
+
+    /// The key set at `url`, fetched again once it's stale or doesn't have
+    /// key `kid`
+    async fn keys(&self, url: &str, kid: Option<&str>) -> Result<JwkSet> {
+        let cached = self.fetched.lock().unwrap().get(url).cloned();
+        if let Some((fetched_at, keys)) = &cached {
+            let has_key = kid.is_none_or(|kid| keys.find(kid).is_some());
+            let age = fetched_at.elapsed();
+            if age < MIN_REFETCH_INTERVAL || (age < KEY_SET_TTL && has_key) {
+                return Ok(keys.clone());
+            }
+        }
+        let fetched = async {
+            self.client
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<JwkSet>()
+                .await
+        }
+        .await;
+        match (fetched, cached) {
+            (Ok(keys), _) => {
+                self.fetched
+                    .lock()
+                    .unwrap()
+                    .insert(url.to_string(), (Instant::now(), keys.clone()));
+                Ok(keys)
+            }
+            // Keep using the keys we have while the endpoint is unavailable
+            (Err(e), Some((_, keys))) => {
+                warn!("Failed to refresh JWKS from {}: {}", url, e);
+                Ok(keys)
+            }
+            (Err(e), None) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+
+    fn token(claims: Value) -> String {
+        jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn tokens_are_decoded_without_verification() {
+        let token = token(serde_json::json!({ "sub": "alice", "aud": ["api"], "exp": 1 }));
+        let jwt = Jwt::decode(&token).unwrap();
+        assert_eq!(jwt.algorithm(), "HS256");
+        assert_eq!(jwt.key_id(), None);
+        assert!(!jwt.verified);
+        assert_eq!(claim_text(jwt.claim("sub").unwrap()), "alice");
+        assert_eq!(claim_text(jwt.claim("aud").unwrap()), r#"["api"]"#);
+        assert!(Jwt::decode("not.a.jwt").is_err());
+        assert!(Jwt::decode(&format!("{}.extra", token)).is_err());
+    }
+
+    #[test]
+    fn tokens_are_found_in_authorization_or_cookies() {
+        let token = token(serde_json::json!({ "sub": "bob" }));
+        let cookie = format!("theme=dark; session={}", token);
+        assert_eq!(
+            find_token([format!("Bearer {}", token).as_str()], []),
+            Some(token.as_str())
+        );
+        assert_eq!(
+            find_token(["Basic dXNlcjpwYXNz"], [cookie.as_str()]),
+            Some(token.as_str())
+        );
+        assert_eq!(find_token([], ["theme=dark; lang=en"]), None);
+    }
+
+    #[tokio::test]
+    async fn tokens_are_verified_against_key_sets() {
+        let url = "https://issuer.example/.well-known/jwks.json";
+        let key_sets = KeySets::new(vec![url.to_string()]);
+        let keys: JwkSet = serde_json::from_value(serde_json::json!({
+            "keys": [{ "kty": "oct", "kid": "k1", "alg": "HS256", "k": "c2VjcmV0" }]
+        }))
+        .unwrap();
+        key_sets
+            .fetched
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), (Instant::now(), keys));
+
+        let sign = |secret: &[u8], claims: Value| {
+            let header = Header {
+                kid: Some("k1".to_string()),
+                ..Header::default()
+            };
+            jsonwebtoken::encode(&header, &claims, &EncodingKey::from_secret(secret)).unwrap()
+        };
+        let jwt = key_sets
+            .verify(&sign(b"secret", serde_json::json!({ "sub": "carol" })))
+            .await
+            .unwrap();
+        assert!(jwt.verified);
+        assert_eq!(jwt.key_id(), Some("k1"));
+        assert!(
+            key_sets
+                .verify(&sign(b"forged", serde_json::json!({ "sub": "carol" })))
+                .await
+                .is_err()
+        );
+        assert!(
+            key_sets
+                .verify(&sign(
+                    b"secret",
+                    serde_json::json!({ "sub": "carol", "exp": 1 })
+                ))
+                .await
+                .is_err()
+        );
+        assert!(
+            KeySets::default()
+                .verify(&token(serde_json::json!({})))
+                .await
+                .is_err()
+        );
+    }
+}
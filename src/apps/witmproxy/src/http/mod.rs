@@ -1 +1,4 @@
+pub mod graphql;
+pub mod jwt;
+pub mod sniff;
 pub mod utils;
@@ -0,0 +1,345 @@
+//! MIME type sniffing and charset detection for response bodies, since
+//! `Content-Type` headers are frequently missing or wrong.
+//!
+//! Sniffing follows the WHATWG MIME Sniffing standard
+//! (<https://mimesniff.spec.whatwg.org/>), matching the first bytes of a body
+//! against the signatures of common web formats.
+
+use encoding_rs::{Encoding, UTF_8};
+
+/// How many leading bytes of a body are inspected
+pub const SNIFF_LEN: usize = 1445;
+
+/// Whitespace bytes skipped before HTML signatures
+const WHITESPACE: &[u8] = b"\t\n\x0c\r ";
+
+/// Tags which identify HTML when they start a document
+const HTML_TAGS: &[&[u8]] = &[
+    b"<!DOCTYPE HTML",
+    b"<HTML",
+    b"<HEAD",
+    b"<SCRIPT",
+    b"<IFRAME",
+    b"<H1",
+    b"<DIV",
+    b"<FONT",
+    b"<TABLE",
+    b"<A",
+    b"<STYLE",
+    b"<TITLE",
+    b"<B",
+    b"<BODY",
+    b"<BR",
+    b"<P",
+    b"<!--",
+];
+
+/// Exact signatures, with `_` in the pattern matching any byte
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"%PDF-", "application/pdf"),
+    (b"%!PS-Adobe-", "application/postscript"),
+    (b"\xfe\xff", "text/plain"),
+    (b"\xff\xfe", "text/plain"),
+    (b"\xef\xbb\xbf", "text/plain"),
+    // Images
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"RIFF____WEBPVP", "image/webp"),
+    (b"\x00\x00\x01\x00", "image/x-icon"),
+    (b"\x00\x00\x02\x00", "image/x-icon"),
+    (b"BM", "image/bmp"),
+    // Audio and video
+    (b"\x1a\x45\xdf\xa3", "video/webm"),
+    (b"OggS\x00", "application/ogg"),
+    (b"RIFF____WAVE", "audio/wave"),
+    (b"RIFF____AVI ", "video/avi"),
+    (b"FORM____AIFF", "audio/aiff"),
+    (b"ID3", "audio/mpeg"),
+    (b"MThd\x00\x00\x00\x06", "audio/midi"),
+    // Fonts
+    (b"wOFF", "font/woff"),
+    (b"wOF2", "font/woff2"),
+    (b"OTTO", "font/otf"),
+    (b"ttcf", "font/collection"),
+    (b"\x00\x01\x00\x00", "font/ttf"),
+    // Archives
+    (b"\x1f\x8b\x08", "application/x-gzip"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"Rar!\x1a\x07\x00", "application/x-rar-compressed"),
+];
+
+/// The MIME type essence (e.g. `text/html`) of a `Content-Type` value
+pub fn essence(content_type: &str) -> String {
+    content_type
+        .split([';', ','])
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// The MIME type of a body declared as `content_type` and starting with
+/// `prefix`. Declared types are kept unless they are missing or generic, or
+/// the body doesn't match the declared image, audio or video type.
+pub fn sniff(content_type: &str, prefix: &[u8]) -> String {
+    let declared = essence(content_type);
+    let sniffed = match declared.as_str() {
+        ""
+        | "unknown"
+        | "unknown/unknown"
+        | "application/unknown"
+        | "*/*"
+        | "application/octet-stream" => Some(identify(prefix)),
+        // Common misconfiguration, where binary content is labelled as text
+        "text/plain" if is_binary(prefix) => {
+            Some(match_signature(prefix).unwrap_or("application/octet-stream"))
+        }
+        media => {
+            let top_level = media.split('/').next().unwrap_or_default();
+            match_signature(prefix).filter(|sniffed| {
+                matches!(top_level, "image" | "audio" | "video")
+                    && sniffed.starts_with(top_level)
+                    && sniffed[top_level.len()..].starts_with('/')
+            })
+        }
+    };
+    sniffed.map(str::to_string).unwrap_or(declared)
+}
+
+/// Whether a MIME type essence is textual and can be transcoded
+pub fn is_text(essence: &str) -> bool {
+    essence.starts_with("text/")
+        || essence.ends_with("+xml")
+        || essence.ends_with("+json")
+        || matches!(
+            essence,
+            "application/json"
+                | "application/javascript"
+                | "application/x-javascript"
+                | "application/ecmascript"
+                | "application/xml"
+        )
+}
+
+/// The encoding of a text body: its BOM, the `charset` parameter of
+/// `content_type`, a `<meta>` declaration for HTML, or finally a guess from
+/// the bytes themselves
+pub fn detect_charset(content_type: &str, prefix: &[u8], html: bool) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(prefix) {
+        return encoding;
+    }
+    if let Some(encoding) = charset_param(content_type).and_then(Encoding::for_label) {
+        return encoding;
+    }
+    if html && let Some(encoding) = meta_charset(prefix) {
+        return encoding;
+    }
+    match std::str::from_utf8(prefix) {
+        Ok(_) => UTF_8,
+        // The prefix may end partway through a character
+        Err(e) if e.error_len().is_none() => UTF_8,
+        Err(_) => {
+            let mut detector = chardetng::EncodingDetector::new();
+            detector.feed(prefix, false);
+            detector.guess(None, true)
+        }
+    }
+}
+
+/// The MIME type of a body with no usable declared type
+fn identify(prefix: &[u8]) -> &'static str {
+    let trimmed = trim_whitespace(prefix);
+    if HTML_TAGS.iter().any(|tag| starts_with_tag(trimmed, tag)) {
+        return "text/html";
+    }
+    if trimmed.starts_with(b"<?xml") {
+        return "text/xml";
+    }
+    if let Some(sniffed) = match_signature(prefix) {
+        return sniffed;
+    }
+    if is_binary(prefix) {
+        "application/octet-stream"
+    } else {
+        "text/plain"
+    }
+}
+
+fn match_signature(prefix: &[u8]) -> Option<&'static str> {
+    SIGNATURES
+        .iter()
+        .find(|(pattern, _)| {
+            prefix.len() >= pattern.len()
+                && pattern
+                    .iter()
+                    .zip(prefix)
+                    .all(|(expected, actual)| *expected == b'_' || expected == actual)
+        })
+        .map(|(_, mime)| *mime)
+        .or_else(|| is_mp4(prefix).then_some("video/mp4"))
+}
+
+/// An ISO base media file whose `ftyp` box lists an `mp4` brand
+fn is_mp4(prefix: &[u8]) -> bool {
+    let Some(size) = prefix.get(..4) else {
+        return false;
+    };
+    let size = u32::from_be_bytes(size.try_into().unwrap()) as usize;
+    if size < 12 || !size.is_multiple_of(4) || prefix.len() < size || &prefix[4..8] != b"ftyp" {
+        return false;
+    }
+    // The major brand, then compatible brands after the minor version
+    std::iter::once(&prefix[8..12])
+        .chain(prefix.get(16..size).unwrap_or_default().chunks_exact(4))
+        .any(|brand| brand.starts_with(b"mp4"))
+}
+
+/// A tag match is case-insensitive and must be followed by a space or `>`
+fn starts_with_tag(bytes: &[u8], tag: &[u8]) -> bool {
+    bytes.len() > tag.len()
+        && bytes[..tag.len()].eq_ignore_ascii_case(tag)
+        && matches!(bytes[tag.len()], b' ' | b'>')
+}
+
+fn trim_whitespace(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|b| !WHITESPACE.contains(b))
+        .unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+/// Whether the bytes contain control characters that never appear in text
+fn is_binary(prefix: &[u8]) -> bool {
+    if Encoding::for_bom(prefix).is_some() {
+        return false;
+    }
+    prefix
+        .iter()
+        .any(|&b| matches!(b, 0x00..=0x08 | 0x0b | 0x0e..=0x1a | 0x1c..=0x1f))
+}
+
+fn charset_param(content_type: &str) -> Option<&[u8]> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"').as_bytes())
+    })
+}
+
+/// A simplified prescan for `<meta charset=...>` or
+/// `<meta http-equiv="Content-Type" content="...; charset=...">`
+fn meta_charset(prefix: &[u8]) -> Option<&'static Encoding> {
+    let lower = prefix.to_ascii_lowercase();
+    let mut rest = lower.as_slice();
+    while let Some(start) = find(rest, b"<meta") {
+        rest = &rest[start + 5..];
+        let tag = &rest[..find(rest, b">").unwrap_or(rest.len())];
+        if let Some(pos) = find(tag, b"charset=") {
+            let value = &tag[pos + 8..];
+            let value = value
+                .strip_prefix(b"\"")
+                .or_else(|| value.strip_prefix(b"'"))
+                .unwrap_or(value);
+            let end = value
+                .iter()
+                .position(|&b| {
+                    matches!(b, b'"' | b'\'' | b';' | b'/' | b'>') || b.is_ascii_whitespace()
+                })
+                .unwrap_or(value.len());
+            // A document can't declare itself UTF-16, since it was read as ASCII
+            return Encoding::for_label(&value[..end]).map(Encoding::output_encoding);
+        }
+    }
+    None
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifies_unlabelled_bodies() {
+        assert_eq!(sniff("", b"  \n<!doctype html><html>"), "text/html");
+        assert_eq!(sniff("unknown", b"<?xml version=\"1.0\"?>"), "text/xml");
+        assert_eq!(
+            sniff("application/octet-stream", b"%PDF-1.7"),
+            "application/pdf"
+        );
+        assert_eq!(sniff("", b"\x89PNG\r\n\x1a\n\x00\x00"), "image/png");
+        assert_eq!(sniff("", b"RIFF\x10\x00\x00\x00WEBPVP8 "), "image/webp");
+        assert_eq!(sniff("", b"plain words"), "text/plain");
+        assert_eq!(
+            sniff("", b"\x00\x01\x02\x03 binary"),
+            "application/octet-stream"
+        );
+
+        let mut mp4 = b"\x00\x00\x00\x18ftypisom\x00\x00\x02\x00isommp41".to_vec();
+        mp4.extend_from_slice(b"....");
+        assert_eq!(sniff("", &mp4), "video/mp4");
+    }
+
+    #[test]
+    fn short_ftyp_boxes_only_check_the_major_brand() {
+        assert_eq!(sniff("", b"\x00\x00\x00\x0cftypmp42"), "video/mp4");
+        assert_eq!(
+            sniff("video/webm", b"\x00\x00\x00\x0cftypisom"),
+            "video/webm"
+        );
+    }
+
+    #[test]
+    fn corrects_mislabelled_bodies() {
+        assert_eq!(
+            sniff("text/plain; charset=utf-8", b"GIF89a\x01\x00"),
+            "image/gif"
+        );
+        assert_eq!(sniff("text/plain", b"hello"), "text/plain");
+        assert_eq!(sniff("image/png", b"\xff\xd8\xff\xe0"), "image/jpeg");
+        assert_eq!(sniff("image/png", b"<svg>"), "image/png");
+        // Declared text types are trusted
+        assert_eq!(sniff("Application/JSON", b"<html>"), "application/json");
+    }
+
+    #[test]
+    fn detects_charsets() {
+        assert_eq!(
+            detect_charset("text/html; charset=\"ISO-8859-1\"", b"caf\xe9", true),
+            encoding_rs::WINDOWS_1252
+        );
+        assert_eq!(
+            detect_charset("text/html", b"\xff\xfeh\x00i\x00", true),
+            encoding_rs::UTF_16LE
+        );
+        assert_eq!(
+            detect_charset("text/html", b"<meta charset='shift_jis'><p>\x82\xa0", true),
+            encoding_rs::SHIFT_JIS
+        );
+        assert_eq!(
+            detect_charset(
+                "text/html",
+                b"<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=euc-kr\">",
+                true
+            ),
+            encoding_rs::EUC_KR
+        );
+        // Truncated in the middle of a multi-byte character
+        assert_eq!(
+            detect_charset("text/plain", &"日本".as_bytes()[..4], false),
+            UTF_8
+        );
+        assert_ne!(
+            detect_charset("text/plain", b"na\xefve caf\xe9", false),
+            UTF_8
+        );
+    }
+}
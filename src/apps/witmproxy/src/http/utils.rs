@@ -1,5 +1,11 @@
+use anyhow::anyhow;
+use bytes::Bytes;
+use http_body::Frame;
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, BodyStream, StreamBody};
 use salvo::http::response::Parts;
 use wasmtime_wasi_http::p3::Response as WasiResponse;
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
 
 #[derive(PartialEq, Eq)]
 pub enum ContentEncoding {
@@ -65,3 +71,37 @@ impl ContentTyped for WasiResponse {
         }
     }
 }
+
+/// Read all of `body`, unless it's over `max_bytes` (or fails), in which
+/// case the body is rebuilt from what was read and the rest
+pub async fn buffer(
+    mut body: UnsyncBoxBody<Bytes, ErrorCode>,
+    max_bytes: usize,
+) -> Result<Bytes, (UnsyncBoxBody<Bytes, ErrorCode>, anyhow::Error)> {
+    use futures::StreamExt;
+
+    let mut buffered = Vec::new();
+    let error = loop {
+        match body.frame().await {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    buffered.extend_from_slice(data);
+                }
+                if buffered.len() > max_bytes {
+                    break None;
+                }
+            }
+            Some(Err(e)) => break Some(e),
+            None => return Ok(Bytes::from(buffered)),
+        }
+    };
+    let reason = match &error {
+        Some(e) => anyhow!("Failed to read body: {e:?}"),
+        None => anyhow!("Body is larger than {max_bytes} bytes"),
+    };
+    let head = futures::stream::iter(
+        std::iter::once(Ok(Frame::data(Bytes::from(buffered)))).chain(error.map(Err)),
+    );
+    let body = StreamBody::new(head.chain(BodyStream::new(body))).boxed_unsync();
+    Err((body, reason))
+}
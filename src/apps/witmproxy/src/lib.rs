@@ -3,10 +3,12 @@
 // This exposes the internal modules for testing and external use
 
 pub mod acl;
+pub mod builder;
 pub mod cert;
 pub mod cli;
 pub mod config;
 pub mod db;
+pub mod error;
 pub mod events;
 pub mod http;
 pub mod plugins;
@@ -24,10 +26,11 @@ pub mod test_utils;
 mod tests;
 
 // Re-export commonly used types for convenience
+pub use builder::WitmProxyBuilder;
 pub use cert::CertificateAuthority;
 pub use config::{
-    AppConfig, AuthConfig, DbConfig, PluginConfig, ProxyConfig, TlsConfig, TransparentProxyConfig,
-    WebConfig,
+    AppConfig, AuthConfig, DbConfig, PluginConfig, ProxyConfig, ReverseProxyConfig, TlsConfig,
+    TransparentProxyConfig, WebConfig,
 };
 pub use db::Db;
 pub use plugins::registry::PluginRegistry;
@@ -47,10 +50,15 @@ pub struct WitmProxy {
     plugin_registry: Option<Arc<RwLock<PluginRegistry>>>,
     config: AppConfig,
     config_path: Option<std::path::PathBuf>,
+    /// Where running capture sessions are journaled
+    journal_dir: Option<std::path::PathBuf>,
     db_pool: Option<sqlx::SqlitePool>,
     proxy_server: Option<ProxyServer>,
     web_server: Option<WebServer>,
     shutdown_notify: Arc<Notify>,
+    hooks: proxy::hooks::ProxyHooks,
+    /// Holds the CA generated by [WitmProxyBuilder], removed on drop
+    _temp_dir: Option<tempfile::TempDir>,
 }
 
 impl WitmProxy {
@@ -70,13 +78,21 @@ impl WitmProxy {
             plugin_registry,
             config,
             config_path: None,
+            journal_dir: None,
             db_pool: None,
             proxy_server: None,
             web_server: None,
             shutdown_notify: Arc::new(Notify::new()),
+            hooks: proxy::hooks::ProxyHooks::default(),
+            _temp_dir: None,
         }
     }
 
+    /// Start assembling a proxy, with defaults for anything left unset
+    pub fn builder() -> WitmProxyBuilder {
+        WitmProxyBuilder::default()
+    }
+
     /// Get the current configuration
     pub fn config(&self) -> &AppConfig {
         &self.config
@@ -104,6 +120,19 @@ impl WitmProxy {
         self
     }
 
+    /// Journal capture sessions in `dir` while they run, and recover those
+    /// left there by a crash on start
+    pub fn with_journal_dir(mut self, dir: std::path::PathBuf) -> Self {
+        self.journal_dir = Some(dir);
+        self
+    }
+
+    /// Set the callbacks run as flows complete and plugins fail
+    pub fn with_hooks(mut self, hooks: proxy::hooks::ProxyHooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
     /// Get the proxy server listen address (only available after start() is called)
     pub fn proxy_listen_addr(&self) -> Option<SocketAddr> {
         self.proxy_server.as_ref().and_then(|s| s.listen_addr())
@@ -114,17 +143,217 @@ impl WitmProxy {
         self.web_server.as_ref().and_then(|s| s.listen_addr())
     }
 
+    /// Get the live security header rules (only available after start() is called)
+    pub fn security_headers(&self) -> Option<proxy::security_headers::SecurityHeaders> {
+        self.proxy_server.as_ref().map(|s| s.security_headers())
+    }
+
+    /// Get the live flow tag rules (only available after start() is called)
+    pub fn flow_tags(&self) -> Option<proxy::flow_tags::FlowTags> {
+        self.proxy_server.as_ref().map(|s| s.flow_tags())
+    }
+
+    /// Get the capture sessions (only available after start() is called)
+    pub fn sessions(&self) -> Option<proxy::sessions::CaptureSessions> {
+        self.proxy_server.as_ref().map(|s| s.sessions())
+    }
+
+    /// Get the per-host upstream request limits (only available after start() is called)
+    pub fn host_limiter(&self) -> Option<proxy::host_limits::HostLimiter> {
+        self.proxy_server.as_ref().map(|s| s.host_limiter())
+    }
+
+    /// Get the live network condition rules (only available after start() is called)
+    pub fn network_conditions(&self) -> Option<proxy::network_profiles::NetworkConditions> {
+        self.proxy_server.as_ref().map(|s| s.network_conditions())
+    }
+
+    /// Get the live header override rules (only available after start() is called)
+    pub fn header_overrides(&self) -> Option<proxy::header_overrides::HeaderOverrides> {
+        self.proxy_server.as_ref().map(|s| s.header_overrides())
+    }
+
+    /// Get the egress route clients (only available after start() is called)
+    pub fn egress(&self) -> Option<proxy::egress::EgressRoutes> {
+        self.proxy_server.as_ref().map(|s| s.egress())
+    }
+
+    /// Get the block rules (only available after start() is called)
+    pub fn block_rules(&self) -> Option<proxy::block_rules::BlockRules> {
+        self.proxy_server.as_ref().map(|s| s.block_rules())
+    }
+
+    /// Get the TLS policies (only available after start() is called)
+    pub fn tls_policies(&self) -> Option<proxy::tls_policy::TlsPolicies> {
+        self.proxy_server.as_ref().map(|s| s.tls_policies())
+    }
+
+    /// Get the mocked APIs (only available after start() is called)
+    pub fn mocks(&self) -> Option<proxy::mocks::MockApis> {
+        self.proxy_server.as_ref().map(|s| s.mocks())
+    }
+
+    /// Get the injected scripts (only available after start() is called)
+    pub fn user_scripts(&self) -> Option<proxy::user_scripts::UserScripts> {
+        self.proxy_server.as_ref().map(|s| s.user_scripts())
+    }
+
+    /// Get the image transcoding settings (only available after start() is
+    /// called)
+    pub fn images(&self) -> Option<proxy::images::ImageOptimizer> {
+        self.proxy_server.as_ref().map(|s| s.images())
+    }
+
+    /// Get the client-facing compression settings (only available after
+    /// start() is called)
+    pub fn compression(&self) -> Option<proxy::compression::Compression> {
+        self.proxy_server.as_ref().map(|s| s.compression())
+    }
+
+    /// Get the page translation (only available after start() is called)
+    pub fn translation(&self) -> Option<proxy::translation::Translation> {
+        self.proxy_server.as_ref().map(|s| s.translation())
+    }
+
+    /// Get the tracking parameter and third-party cookie stripping (only
+    /// available after start() is called)
+    pub fn privacy(&self) -> Option<proxy::privacy::Privacy> {
+        self.proxy_server.as_ref().map(|s| s.privacy())
+    }
+
+    /// Get the inferred API schemas (only available after start() is called)
+    pub fn api_schemas(&self) -> Option<proxy::api_schemas::ApiSchemas> {
+        self.proxy_server.as_ref().map(|s| s.api_schemas())
+    }
+
+    /// Get the traffic counted by client profile (only available after start() is called)
+    pub fn traffic(&self) -> Option<proxy::traffic::Traffic> {
+        self.proxy_server.as_ref().map(|s| s.traffic())
+    }
+
+    /// Get the protobuf descriptors and mappings (only available after start() is called)
+    pub fn protobuf(&self) -> Option<proxy::protobuf::ProtobufDescriptors> {
+        self.proxy_server.as_ref().map(|s| s.protobuf())
+    }
+
+    /// Get the traces of debugged flows (only available after start() is called)
+    pub fn flow_traces(&self) -> Option<proxy::flow_trace::FlowTraces> {
+        self.proxy_server.as_ref().map(|s| s.flow_traces())
+    }
+
     /// Initialize and start all services
     pub async fn start(&mut self) -> Result<()> {
         let _ = rustls::crypto::ring::default_provider().install_default();
         info!("Hi there! Starting up witmproxy for ya");
 
+        // Created up front so the web server can report on its live stats
+        let mut proxy_server = ProxyServer::new(
+            self.ca.clone(),
+            self.plugin_registry.clone(),
+            self.config.clone(),
+        )?
+        .with_hooks(self.hooks.clone());
+        if let Some(ref dir) = self.journal_dir {
+            proxy_server = proxy_server.with_session_journal(dir.clone());
+            let recovered = proxy_server.sessions().recover().await;
+            if !recovered.is_empty() {
+                info!(
+                    "Recovered {} capture session(s) interrupted by a crash",
+                    recovered.len()
+                );
+            }
+        }
+        if let Some(ref pool) = self.db_pool {
+            proxy_server = proxy_server.with_db_pool(pool.clone());
+            for stored in db::mock_specs::StoredMockSpec::list(pool).await? {
+                let spec = proxy::mocks::parse_document(&stored.document)
+                    .and_then(|document| proxy::mocks::MockSpec::new(stored.name, document));
+                match spec {
+                    Ok(spec) => proxy_server.mocks().insert(spec),
+                    Err(e) => warn!("Skipping mocked API: {}", e),
+                }
+            }
+            for stored in db::user_scripts::StoredUserScript::list(pool).await? {
+                match proxy::user_scripts::UserScript::parse(stored.name, &stored.source) {
+                    Ok(script) => proxy_server.user_scripts().insert(script),
+                    Err(e) => warn!("Skipping user script: {}", e),
+                }
+            }
+            for stored in db::protobuf_descriptors::StoredDescriptorSet::list(pool).await? {
+                match proxy::protobuf::DescriptorSet::new(stored.name, stored.descriptor) {
+                    Ok(set) => proxy_server.protobuf().insert(set),
+                    Err(e) => warn!("Skipping protobuf descriptor set: {}", e),
+                }
+            }
+            db::api_schemas::load(pool, &proxy_server.api_schemas()).await?;
+            if self.config.proxy.infer_schemas {
+                tokio::spawn(db::api_schemas::persist_loop(
+                    pool.clone(),
+                    proxy_server.api_schemas(),
+                    db::api_schemas::PERSIST_INTERVAL,
+                ));
+            }
+            db::traffic::load(pool, &proxy_server.traffic()).await?;
+            if self.config.proxy.account_traffic {
+                tokio::spawn(db::traffic::persist_loop(
+                    pool.clone(),
+                    proxy_server.traffic(),
+                    db::traffic::PERSIST_INTERVAL,
+                ));
+            }
+            db::hsts::load(pool, &proxy_server.https_upgrades()).await?;
+            if self.config.proxy.https_upgrade {
+                tokio::spawn(db::hsts::persist_loop(
+                    pool.clone(),
+                    proxy_server.https_upgrades(),
+                    db::hsts::PERSIST_INTERVAL,
+                ));
+            }
+        }
+        if self.config.proxy.https_upgrade
+            && let Some(ref url) = self.config.proxy.hsts_preload_url
+        {
+            tokio::spawn(
+                proxy_server
+                    .https_upgrades()
+                    .refresh_loop(url.clone(), proxy::https_upgrade::PRELOAD_REFRESH_INTERVAL),
+            );
+        }
+        if proxy_server.privacy().is_enabled()
+            && let Some(ref url) = self.config.proxy.tracking_params_url
+        {
+            tokio::spawn(
+                proxy_server
+                    .privacy()
+                    .refresh_loop(url.clone(), proxy::privacy::PARAMS_REFRESH_INTERVAL),
+            );
+        }
+
+        if let Some(ref registry) = self.plugin_registry {
+            tokio::spawn(plugins::integrity::verify_loop(
+                registry.clone(),
+                plugins::integrity::VERIFY_INTERVAL,
+            ));
+        }
+
         // Start web server for certificate distribution and management API
         let mut web_server = WebServer::new(
             self.ca.clone(),
             self.plugin_registry.clone(),
             self.config.clone(),
-        );
+        )
+        .with_proxy_stats(proxy_server.stats())
+        .with_security_headers(proxy_server.security_headers())
+        .with_flow_tags(proxy_server.flow_tags())
+        .with_sessions(proxy_server.sessions())
+        .with_network_conditions(proxy_server.network_conditions())
+        .with_header_overrides(proxy_server.header_overrides())
+        .with_traffic(proxy_server.traffic())
+        .with_mocks(proxy_server.mocks())
+        .with_user_scripts(proxy_server.user_scripts())
+        .with_api_schemas(proxy_server.api_schemas())
+        .with_protobuf(proxy_server.protobuf())
+        .with_flow_traces(proxy_server.flow_traces());
         if let Some(ref path) = self.config_path {
             web_server = web_server.with_config_path(path.clone());
         }
@@ -139,11 +368,6 @@ impl WitmProxy {
         info!("Visit the web interface to download the root certificate");
 
         // Start proxy server
-        let mut proxy_server = ProxyServer::new(
-            self.ca.clone(),
-            self.plugin_registry.clone(),
-            self.config.clone(),
-        )?;
         // Tell the proxy where its own management server is so it can
         // short-circuit traffic targeting that port back to loopback.
         proxy_server.set_management_addr(web_addr);
@@ -152,6 +376,12 @@ impl WitmProxy {
             .listen_addr()
             .ok_or_else(|| anyhow::anyhow!("Failed to get proxy server listen address"))?;
         info!("Proxy listening on {}", proxy_addr);
+        for listener in proxy_server.stats().listeners().iter().skip(1) {
+            info!(
+                "Proxy listener {} on {} (mitm: {}, auth: {})",
+                listener.name, listener.addr, listener.mitm, listener.require_auth
+            );
+        }
 
         // Store server instances
         self.web_server = Some(web_server);
@@ -184,6 +414,36 @@ impl WitmProxy {
         if let Some(proxy_server) = &self.proxy_server {
             proxy_server.shutdown().await;
         }
+        // Schemas inferred since they were last saved
+        let saved = match (&self.proxy_server, &self.db_pool) {
+            (Some(proxy_server), Some(pool)) => {
+                db::api_schemas::persist(pool, &proxy_server.api_schemas()).await
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = saved {
+            warn!("Failed to save inferred API schemas: {}", e);
+        }
+        // Traffic counted since it was last saved
+        let saved = match (&self.proxy_server, &self.db_pool) {
+            (Some(proxy_server), Some(pool)) => {
+                db::traffic::persist(pool, &proxy_server.traffic()).await
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = saved {
+            warn!("Failed to save traffic counters: {}", e);
+        }
+        // HSTS policies noted since they were last saved
+        let saved = match (&self.proxy_server, &self.db_pool) {
+            (Some(proxy_server), Some(pool)) => {
+                db::hsts::persist(pool, &proxy_server.https_upgrades()).await
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = saved {
+            warn!("Failed to save HSTS policies: {}", e);
+        }
 
         self.shutdown_notify.notify_waiters();
         info!("Thanks for stopping by!");
@@ -0,0 +1,194 @@
+//! Portable bundles of installed plugins, for moving a setup between
+//! machines without copying the rest of the database (`witm db export` and
+//! `witm db import`).
+//!
+//! A bundle holds each plugin's signed component along with the state the
+//! user chose for it: whether it's enabled, which capabilities are granted
+//! and with what scopes and quotas, and its configuration. Components are
+//! verified again when imported, and grants only apply to capabilities the
+//! component's manifest requests.
+
+use anyhow::{Result, bail};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::plugins::WitmPlugin;
+use crate::plugins::capabilities::Capability;
+use crate::wasm::bindgen::UserInput;
+
+/// Version of the bundle format written by [PluginBundle::new]
+pub const BUNDLE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct PluginBundle {
+    pub version: u32,
+    pub plugins: Vec<BundledPlugin>,
+}
+
+/// A plugin as exported, with its component base64-encoded
+#[derive(Serialize, Deserialize)]
+pub struct BundledPlugin {
+    pub id: String,
+    pub component: String,
+    pub enabled: bool,
+    pub capabilities: Vec<Capability>,
+    pub configuration: Vec<UserInput>,
+}
+
+impl PluginBundle {
+    /// Bundle `plugins`, ordered by ID
+    pub fn new<'a>(plugins: impl IntoIterator<Item = &'a WitmPlugin>) -> Self {
+        let mut plugins: Vec<_> = plugins
+            .into_iter()
+            .map(|plugin| BundledPlugin {
+                id: plugin.id(),
+                component: base64::engine::general_purpose::STANDARD
+                    .encode(&plugin.component_bytes),
+                enabled: plugin.enabled,
+                capabilities: plugin.capabilities.clone(),
+                configuration: plugin.configuration.clone(),
+            })
+            .collect();
+        plugins.sort_by(|a, b| a.id.cmp(&b.id));
+        Self {
+            version: BUNDLE_VERSION,
+            plugins,
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        let bundle: Self = serde_json::from_str(json)?;
+        if bundle.version > BUNDLE_VERSION {
+            bail!(
+                "Plugin bundle version {} is newer than this witmproxy supports ({})",
+                bundle.version,
+                BUNDLE_VERSION
+            );
+        }
+        Ok(bundle)
+    }
+}
+
+impl BundledPlugin {
+    pub fn component_bytes(&self) -> Result<Vec<u8>> {
+        Ok(base64::engine::general_purpose::STANDARD.decode(&self.component)?)
+    }
+
+    /// Apply the exported state to `plugin`, freshly loaded from this
+    /// bundled plugin's component. Capability scopes are left uncompiled.
+    pub fn apply_to(&self, plugin: &mut WitmPlugin) -> Result<()> {
+        if plugin.id() != self.id {
+            bail!(
+                "Bundled component for {} contains plugin {}",
+                self.id,
+                plugin.id()
+            );
+        }
+        plugin.enabled = self.enabled;
+        plugin.configuration = self.configuration.clone();
+        for capability in &mut plugin.capabilities {
+            match self
+                .capabilities
+                .iter()
+                .find(|exported| exported.inner.kind == capability.inner.kind)
+            {
+                Some(exported) => {
+                    capability.granted = exported.granted;
+                    capability.inner.scope = exported.inner.scope.clone();
+                    capability.quota = exported.quota.clone();
+                }
+                // Requested by the component but unknown to the exporting
+                // instance, so never granted there
+                None => capability.granted = false,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasm::bindgen::witmproxy::plugin::capabilities::{
+        Capability as WitCapability, CapabilityKind, CapabilityScope, EventKind,
+    };
+
+    fn capability(kind: CapabilityKind, expression: &str, granted: bool) -> Capability {
+        Capability {
+            inner: WitCapability {
+                kind,
+                scope: CapabilityScope {
+                    expression: expression.to_string(),
+                },
+            },
+            granted,
+            quota: Default::default(),
+            cel: None,
+        }
+    }
+
+    fn plugin(capabilities: Vec<Capability>) -> WitmPlugin {
+        WitmPlugin {
+            namespace: "@test".to_string(),
+            name: "bundle".to_string(),
+            version: "0.0.1".to_string(),
+            author: String::new(),
+            description: String::new(),
+            license: String::new(),
+            url: String::new(),
+            publickey: Vec::new(),
+            enabled: true,
+            capabilities,
+            metadata: Default::default(),
+            configuration: Vec::new(),
+            configuration_schema: Vec::new(),
+            component: None,
+            component_bytes: b"\0asm".to_vec(),
+        }
+    }
+
+    #[test]
+    fn exported_state_is_reapplied() {
+        let request = CapabilityKind::HandleEvent(EventKind::Request);
+        let mut exported = plugin(vec![
+            capability(request, "request.host() == 'example.com'", true),
+            capability(CapabilityKind::Logger, "true", false),
+        ]);
+        exported.enabled = false;
+
+        let json = serde_json::to_string(&PluginBundle::new([&exported])).unwrap();
+        let bundle = PluginBundle::from_json(&json).unwrap();
+        assert_eq!(bundle.plugins[0].component_bytes().unwrap(), b"\0asm");
+
+        let mut imported = plugin(vec![
+            capability(request, "true", true),
+            capability(CapabilityKind::Logger, "true", true),
+            capability(CapabilityKind::Clock, "true", true),
+        ]);
+        bundle.plugins[0].apply_to(&mut imported).unwrap();
+        assert!(!imported.enabled);
+        let state: Vec<_> = imported
+            .capabilities
+            .iter()
+            .map(|c| (c.inner.kind, c.inner.scope.expression.as_str(), c.granted))
+            .collect();
+        assert_eq!(
+            state,
+            vec![
+                (request, "request.host() == 'example.com'", true),
+                (CapabilityKind::Logger, "true", false),
+                (CapabilityKind::Clock, "true", false),
+            ]
+        );
+
+        let mut other = plugin(Vec::new());
+        other.name = "other".to_string();
+        assert!(bundle.plugins[0].apply_to(&mut other).is_err());
+    }
+
+    #[test]
+    fn newer_bundles_are_rejected() {
+        let json = serde_json::json!({ "version": BUNDLE_VERSION + 1, "plugins": [] });
+        assert!(PluginBundle::from_json(&json.to_string()).is_err());
+    }
+}
@@ -0,0 +1,283 @@
+//! A message bus plugins granted the `messaging` capability use to publish
+//! messages on named topics for other plugins, ex: a detector plugin
+//! publishing what it finds for a blocker plugin to act on.
+//!
+//! Publishing only queues a message; it's delivered later, to the
+//! `message-handler.on-message` export of each other plugin subscribed to
+//! the topic, by the task draining the queue with [Bus::next]. Each plugin
+//! can publish [BURST] messages at once, refilled at [MESSAGES_PER_SECOND],
+//! and the queue holds at most [QUEUE_CAPACITY] messages, so plugins
+//! publishing in a loop (or to each other) can't starve the proxy.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::{Context, Result, bail};
+use bytes::Bytes;
+use tokio::sync::mpsc;
+use wasmtime::Store;
+use wasmtime::component::{Instance, Resource, TypedFunc};
+
+use crate::wasm::{CapabilityProvider, Host};
+
+/// The interface plugins receiving messages export
+pub const HANDLER_INTERFACE: &str = "witmproxy:plugin/message-handler@0.0.6";
+
+/// Messages queued for delivery before publishing fails
+pub const QUEUE_CAPACITY: usize = 1024;
+
+/// Messages a plugin can publish at once
+pub const BURST: f64 = 20.0;
+
+/// Rate at which a plugin's allowance of messages refills
+pub const MESSAGES_PER_SECOND: f64 = 10.0;
+
+/// Largest payload a message can carry
+pub const MAX_PAYLOAD_BYTES: usize = 64 * 1024;
+
+/// Longest topic name, in bytes
+pub const MAX_TOPIC_BYTES: usize = 256;
+
+type OnMessage = TypedFunc<(String, Vec<u8>, String, Resource<CapabilityProvider>), ()>;
+
+/// The `on-message` export of a plugin instance
+pub fn on_message(instance: &Instance, store: &mut Store<Host>) -> Result<OnMessage> {
+    let interface = instance
+        .get_export_index(&mut *store, None, HANDLER_INTERFACE)
+        .with_context(|| {
+            format!("plugin subscribed to messages but doesn't export {HANDLER_INTERFACE}")
+        })?;
+    let func = instance
+        .get_export_index(&mut *store, Some(&interface), "on-message")
+        .context("plugin's message-handler export has no on-message function")?;
+    Ok(instance.get_typed_func(&mut *store, &func)?)
+}
+
+/// A message published by a plugin
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub topic: String,
+    pub payload: Bytes,
+    /// ID of the plugin which published the message
+    pub publisher: String,
+}
+
+/// A plugin's allowance of messages, refilled over time
+#[derive(Debug)]
+struct Allowance {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Allowance {
+    fn new(now: Instant) -> Self {
+        Self {
+            tokens: BURST,
+            refilled: now,
+        }
+    }
+
+    /// Take one message from the allowance, if any is left at `now`
+    fn take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * MESSAGES_PER_SECOND).min(BURST);
+        self.refilled = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// The message bus shared by the plugins in a registry. Clone is cheap and
+/// all clones share the same subscriptions and queue.
+#[derive(Clone)]
+pub struct Bus {
+    /// IDs of the plugins subscribed to each topic
+    subscriptions: Arc<Mutex<HashMap<String, HashSet<String>>>>,
+    allowances: Arc<Mutex<HashMap<String, Allowance>>>,
+    sender: mpsc::Sender<Message>,
+    receiver: Arc<tokio::sync::Mutex<mpsc::Receiver<Message>>>,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        Self {
+            subscriptions: Arc::new(Mutex::new(HashMap::new())),
+            allowances: Arc::new(Mutex::new(HashMap::new())),
+            sender,
+            receiver: Arc::new(tokio::sync::Mutex::new(receiver)),
+        }
+    }
+
+    fn validate_topic(topic: &str) -> Result<()> {
+        if topic.is_empty() || topic.len() > MAX_TOPIC_BYTES {
+            bail!("Topics must be from 1 to {} bytes long", MAX_TOPIC_BYTES);
+        }
+        Ok(())
+    }
+
+    /// Queue `payload` for delivery to the plugins subscribed to `topic`
+    pub fn publish(&self, publisher: &str, topic: &str, payload: Vec<u8>) -> Result<()> {
+        self.publish_at(publisher, topic, payload, Instant::now())
+    }
+
+    fn publish_at(
+        &self,
+        publisher: &str,
+        topic: &str,
+        payload: Vec<u8>,
+        now: Instant,
+    ) -> Result<()> {
+        Self::validate_topic(topic)?;
+        if payload.len() > MAX_PAYLOAD_BYTES {
+            bail!("Payloads can be at most {} bytes", MAX_PAYLOAD_BYTES);
+        }
+        let allowed = self
+            .allowances
+            .lock()
+            .unwrap()
+            .entry(publisher.to_string())
+            .or_insert_with(|| Allowance::new(now))
+            .take(now);
+        if !allowed {
+            bail!(
+                "Publishing too quickly: at most {} messages a second",
+                MESSAGES_PER_SECOND
+            );
+        }
+        // Nobody would receive it, so it isn't queued
+        if self.subscribers(topic, publisher).is_empty() {
+            return Ok(());
+        }
+        let message = Message {
+            topic: topic.to_string(),
+            payload: Bytes::from(payload),
+            publisher: publisher.to_string(),
+        };
+        self.sender.try_send(message).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => anyhow::anyhow!("The message queue is full"),
+            mpsc::error::TrySendError::Closed(_) => anyhow::anyhow!("The message bus is closed"),
+        })
+    }
+
+    /// Deliver messages published on `topic` to the plugin `plugin_id`
+    pub fn subscribe(&self, plugin_id: &str, topic: &str) -> Result<()> {
+        Self::validate_topic(topic)?;
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .entry(topic.to_string())
+            .or_default()
+            .insert(plugin_id.to_string());
+        Ok(())
+    }
+
+    pub fn unsubscribe(&self, plugin_id: &str, topic: &str) {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        if let Some(plugins) = subscriptions.get_mut(topic) {
+            plugins.remove(plugin_id);
+            if plugins.is_empty() {
+                subscriptions.remove(topic);
+            }
+        }
+    }
+
+    /// Drop the subscriptions of a plugin which has been removed
+    pub fn forget(&self, plugin_id: &str) {
+        self.subscriptions.lock().unwrap().retain(|_, plugins| {
+            plugins.remove(plugin_id);
+            !plugins.is_empty()
+        });
+        self.allowances.lock().unwrap().remove(plugin_id);
+    }
+
+    /// IDs of the plugins a message published on `topic` by `publisher` is
+    /// delivered to, sorted; plugins don't receive their own messages
+    pub fn subscribers(&self, topic: &str, publisher: &str) -> Vec<String> {
+        let mut plugins: Vec<String> = self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .get(topic)
+            .into_iter()
+            .flatten()
+            .filter(|plugin| *plugin != publisher)
+            .cloned()
+            .collect();
+        plugins.sort();
+        plugins
+    }
+
+    /// The next message to deliver, waiting for one to be published
+    pub async fn next(&self) -> Option<Message> {
+        self.receiver.lock().await.recv().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn publishers_are_rate_limited_and_refilled_over_time() {
+        let bus = Bus::new();
+        bus.subscribe("ezco/blocker", "threats").unwrap();
+        let start = Instant::now();
+        for _ in 0..BURST as usize {
+            bus.publish_at("ezco/detector", "threats", b"x".to_vec(), start)
+                .unwrap();
+        }
+        assert!(
+            bus.publish_at("ezco/detector", "threats", b"x".to_vec(), start)
+                .is_err()
+        );
+        // Others have allowances of their own
+        bus.publish_at("ezco/other", "threats", b"x".to_vec(), start)
+            .unwrap();
+        let later = start + Duration::from_secs_f64(1.0 / MESSAGES_PER_SECOND);
+        bus.publish_at("ezco/detector", "threats", b"x".to_vec(), later)
+            .unwrap();
+        assert!(
+            bus.publish("ezco/detector", "threats", vec![0; MAX_PAYLOAD_BYTES + 1])
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn messages_go_to_other_subscribers_of_the_topic() {
+        let bus = Bus::new();
+        bus.subscribe("ezco/detector", "threats").unwrap();
+        bus.subscribe("ezco/blocker", "threats").unwrap();
+        bus.subscribe("ezco/logger", "threats").unwrap();
+        bus.unsubscribe("ezco/logger", "threats");
+        assert!(bus.subscribe("ezco/blocker", "").is_err());
+
+        assert_eq!(
+            bus.subscribers("threats", "ezco/detector"),
+            ["ezco/blocker"]
+        );
+        bus.publish("ezco/detector", "threats", b"evil.com".to_vec())
+            .unwrap();
+        // Not queued, as nobody is subscribed
+        bus.publish("ezco/detector", "elsewhere", b"ignored".to_vec())
+            .unwrap();
+        let message = bus.next().await.unwrap();
+        assert_eq!(message.topic, "threats");
+        assert_eq!(message.publisher, "ezco/detector");
+        assert_eq!(&message.payload[..], b"evil.com");
+
+        bus.forget("ezco/blocker");
+        assert!(bus.subscribers("threats", "ezco/detector").is_empty());
+    }
+}
@@ -1,7 +1,10 @@
 use anyhow::Result;
 use cel_cxx::{Env, Program};
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 
+use crate::plugins::quota::Quota;
+
 use crate::wasm::bindgen::witmproxy::plugin::capabilities::{
     Capability as WitCapability, CapabilityKind,
 };
@@ -10,6 +13,9 @@ use crate::wasm::bindgen::witmproxy::plugin::capabilities::{
 pub struct Capability {
     pub inner: WitCapability,
     pub granted: bool,
+    /// Limits on the use of the capability, set by the user
+    #[serde(default, skip_serializing_if = "Quota::is_unlimited")]
+    pub quota: Quota,
     /// Compiled CEL program
     #[serde(skip)]
     pub cel: Option<Program<'static>>,
@@ -20,6 +26,11 @@ impl Capability {
         self.cel = Some(env.compile(&self.inner.scope.expression)?);
         Ok(())
     }
+
+    /// Whether the capability is granted and, per its quota, usable now
+    pub fn is_active(&self) -> bool {
+        self.granted && self.quota.is_active_at(Utc::now())
+    }
 }
 
 impl std::fmt::Display for CapabilityKind {
@@ -29,6 +40,13 @@ impl std::fmt::Display for CapabilityKind {
             CapabilityKind::Logger => write!(f, "logger"),
             CapabilityKind::LocalStorage => write!(f, "local_storage"),
             CapabilityKind::Clock => write!(f, "clock"),
+            CapabilityKind::FlowReader => write!(f, "flow_reader"),
+            CapabilityKind::Jwt => write!(f, "jwt"),
+            CapabilityKind::Graphql => write!(f, "graphql"),
+            CapabilityKind::Messaging => write!(f, "messaging"),
+            CapabilityKind::Regex => write!(f, "regex"),
+            CapabilityKind::Inference => write!(f, "inference"),
+            CapabilityKind::Html => write!(f, "html"),
             CapabilityKind::HandleEvent(event_kind) => {
                 write!(f, "handle_event_{event_kind}")
             }
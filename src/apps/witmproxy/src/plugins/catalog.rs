@@ -0,0 +1,163 @@
+//! Metadata tags plugins set to be listed in a catalog.
+//!
+//! - `categories`: comma-separated, ex: `privacy, ads`
+//! - `icon`: a `data:image/...` URI or an http(s) URL
+//! - `screenshot.<n>`: screenshots in order of `n`, as data URIs or URLs,
+//!   each optionally described by `screenshot.<n>.caption`
+//!
+//! Tags are stored with the rest of a plugin's metadata. Images which are
+//! neither data URIs nor URLs are left out of the listing.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+/// Metadata key of a plugin's comma-separated categories
+pub const CATEGORIES_KEY: &str = "categories";
+
+/// Metadata key of a plugin's icon
+pub const ICON_KEY: &str = "icon";
+
+/// Prefix of the metadata keys of a plugin's screenshots
+pub const SCREENSHOT_KEY_PREFIX: &str = "screenshot.";
+
+const CAPTION_SUFFIX: &str = ".caption";
+
+/// How a plugin is shown in a catalog
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Listing {
+    /// Lowercase and deduplicated
+    pub categories: Vec<String>,
+    pub icon: Option<String>,
+    pub screenshots: Vec<Screenshot>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Screenshot {
+    pub url: String,
+    pub caption: Option<String>,
+}
+
+impl Listing {
+    /// The listing described by a plugin's metadata tags
+    pub fn from_metadata(metadata: &HashMap<String, String>) -> Self {
+        let mut categories: Vec<String> = Vec::new();
+        for category in metadata
+            .get(CATEGORIES_KEY)
+            .into_iter()
+            .flat_map(|value| value.split(','))
+        {
+            let category = category.trim().to_lowercase();
+            if !category.is_empty() && !categories.contains(&category) {
+                categories.push(category);
+            }
+        }
+
+        let icon = metadata
+            .get(ICON_KEY)
+            .filter(|value| is_image_source(value))
+            .cloned();
+
+        let mut screenshots: Vec<(u32, Screenshot)> = metadata
+            .iter()
+            .filter_map(|(key, value)| {
+                let index = screenshot_index(key)?;
+                if !is_image_source(value) {
+                    return None;
+                }
+                let caption = metadata.get(&format!("{}{}", key, CAPTION_SUFFIX)).cloned();
+                Some((
+                    index,
+                    Screenshot {
+                        url: value.clone(),
+                        caption,
+                    },
+                ))
+            })
+            .collect();
+        screenshots.sort_by_key(|(index, _)| *index);
+
+        Self {
+            categories,
+            icon,
+            screenshots: screenshots
+                .into_iter()
+                .map(|(_, screenshot)| screenshot)
+                .collect(),
+        }
+    }
+
+    /// Whether the plugin is listed under `category`, ignoring case
+    pub fn in_category(&self, category: &str) -> bool {
+        let category = category.trim().to_lowercase();
+        self.categories.contains(&category)
+    }
+}
+
+/// The metadata keys of images which will be left out of the listing
+pub fn invalid_images(metadata: &HashMap<String, String>) -> Vec<String> {
+    let mut keys: Vec<String> = metadata
+        .iter()
+        .filter(|(key, _)| key.as_str() == ICON_KEY || screenshot_index(key).is_some())
+        .filter(|(_, value)| !is_image_source(value))
+        .map(|(key, _)| key.clone())
+        .collect();
+    keys.sort();
+    keys
+}
+
+/// `n` for `screenshot.<n>`, but not for its caption
+fn screenshot_index(key: &str) -> Option<u32> {
+    key.strip_prefix(SCREENSHOT_KEY_PREFIX)?.parse().ok()
+}
+
+fn is_image_source(value: &str) -> bool {
+    value.starts_with("data:image/")
+        || value.starts_with("https://")
+        || value.starts_with("http://")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listing_is_read_from_metadata() {
+        let metadata: HashMap<String, String> = [
+            ("categories", "Privacy, ads,privacy, "),
+            ("icon", "data:image/svg+xml;base64,PHN2Zy8+"),
+            ("screenshot.10", "https://example.com/later.png"),
+            ("screenshot.2", "https://example.com/first.png"),
+            ("screenshot.2.caption", "Blocked requests"),
+            ("screenshot.3", "file:///etc/passwd"),
+            ("runtime-profile", "deterministic"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+        let listing = Listing::from_metadata(&metadata);
+        assert_eq!(listing.categories, ["privacy", "ads"]);
+        assert!(listing.in_category("Ads"));
+        assert_eq!(
+            listing.icon.as_deref(),
+            Some("data:image/svg+xml;base64,PHN2Zy8+")
+        );
+        assert_eq!(
+            listing.screenshots,
+            [
+                Screenshot {
+                    url: "https://example.com/first.png".to_string(),
+                    caption: Some("Blocked requests".to_string()),
+                },
+                Screenshot {
+                    url: "https://example.com/later.png".to_string(),
+                    caption: None,
+                },
+            ]
+        );
+        assert_eq!(invalid_images(&metadata), ["screenshot.3"]);
+
+        assert_eq!(Listing::from_metadata(&HashMap::new()), Listing::default());
+    }
+}
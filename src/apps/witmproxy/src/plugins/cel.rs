@@ -1,5 +1,5 @@
 use bytes::Bytes;
-use cel_cxx::Opaque;
+use cel_cxx::{Activation, Opaque};
 use chrono::Datelike;
 use hyper::{Request, Response};
 use salvo::http::uri::Scheme;
@@ -9,7 +9,16 @@ use std::str::FromStr;
 use wasmtime_wasi_http::p3::{Request as WasiRequest, Response as WasiResponse};
 
 use crate::{
-    events::content::InboundContent, wasm::bindgen::witmproxy::plugin::capabilities::RequestContext,
+    events::content::InboundContent,
+    http::graphql::GraphqlOperation,
+    http::jwt::{self, Jwt},
+    http::sniff::essence,
+    proxy::findings::FindingKind,
+    proxy::flows::FlowRecord,
+    proxy::mqtt,
+    proxy::upstream_cert::UpstreamCert,
+    proxy::vhost::ConnectionInfo,
+    wasm::bindgen::witmproxy::plugin::capabilities::RequestContext,
 };
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Opaque)]
@@ -29,6 +38,169 @@ impl CelConnect {
     }
 }
 
+/// A raw (non-HTTP) TCP stream, as seen by `raw_stream` capability scopes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Opaque)]
+#[cel_cxx(display)]
+pub struct CelStream {
+    pub host: String,
+    pub port: u16,
+    pub protocol: String,
+}
+
+impl CelStream {
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn protocol(&self) -> &str {
+        &self.protocol
+    }
+}
+
+/// An MQTT message, as seen by `mqtt_message` capability scopes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Opaque)]
+#[cel_cxx(display)]
+pub struct CelMqtt {
+    pub host: String,
+    pub port: u16,
+    pub from_client: bool,
+    pub topic: String,
+    pub qos: u8,
+    pub retain: bool,
+}
+
+impl CelMqtt {
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn from_client(&self) -> bool {
+        self.from_client
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    pub fn qos(&self) -> u8 {
+        self.qos
+    }
+
+    pub fn retain(&self) -> bool {
+        self.retain
+    }
+
+    /// Whether the topic matches an MQTT topic filter, ex: "devices/+/telemetry"
+    /// or "devices/#"
+    pub fn topic_matches(&self, filter: &str) -> bool {
+        mqtt::topic_matches(filter, &self.topic)
+    }
+}
+
+/// The intercepted TLS connection a request arrived on
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Opaque)]
+#[cel_cxx(display)]
+pub struct CelConnection {
+    pub authority: String,
+    /// Empty when the client sent no SNI, or the request wasn't tunneled
+    pub sni: String,
+}
+
+impl CelConnection {
+    pub fn authority(&self) -> &str {
+        &self.authority
+    }
+
+    pub fn sni(&self) -> &str {
+        &self.sni
+    }
+}
+
+impl From<&ConnectionInfo> for CelConnection {
+    fn from(info: &ConnectionInfo) -> Self {
+        CelConnection {
+            authority: info.authority_host.clone(),
+            sni: info.sni.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// What the proxy has found in the flow an event belongs to
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Opaque)]
+#[cel_cxx(display)]
+pub struct CelFlow {
+    /// Kinds of sensitive data in the request's URL and headers, such as
+    /// `jwt` or `credit-card`
+    pub findings: Vec<String>,
+}
+
+impl CelFlow {
+    pub fn findings(&self) -> Vec<String> {
+        self.findings.clone()
+    }
+
+    pub fn has_finding(&self, kind: &str) -> bool {
+        self.findings.iter().any(|finding| finding == kind)
+    }
+}
+
+impl From<&[FindingKind]> for CelFlow {
+    fn from(findings: &[FindingKind]) -> Self {
+        CelFlow {
+            findings: findings.iter().map(|kind| kind.to_string()).collect(),
+        }
+    }
+}
+
+/// The GraphQL operation a request runs. Empty for requests that aren't
+/// GraphQL, or whose operation couldn't be parsed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Opaque)]
+#[cel_cxx(display)]
+pub struct CelGraphql {
+    pub operation: String,
+    /// `query`, `mutation` or `subscription`
+    pub operation_type: String,
+    pub fields: Vec<String>,
+}
+
+impl CelGraphql {
+    pub fn operation(&self) -> &str {
+        &self.operation
+    }
+
+    pub fn operation_type(&self) -> &str {
+        &self.operation_type
+    }
+
+    pub fn fields(&self) -> Vec<String> {
+        self.fields.clone()
+    }
+
+    pub fn has_field(&self, name: &str) -> bool {
+        self.fields.iter().any(|field| field == name)
+    }
+}
+
+impl From<Option<&GraphqlOperation>> for CelGraphql {
+    fn from(operation: Option<&GraphqlOperation>) -> Self {
+        operation
+            .map(|operation| CelGraphql {
+                operation: operation.name.clone(),
+                operation_type: operation.operation_type.as_str().to_string(),
+                fields: operation.fields.clone(),
+            })
+            .unwrap_or_default()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Opaque)]
 #[cel_cxx(display)]
 pub struct CelRequest {
@@ -38,6 +210,10 @@ pub struct CelRequest {
     pub query: HashMap<String, Vec<String>>,
     pub method: String,
     pub headers: HashMap<String, Vec<String>>,
+    /// Whether the host disagrees with the SNI or CONNECT authority of the
+    /// intercepted connection the request arrived on
+    #[serde(default)]
+    pub host_mismatch: bool,
 }
 
 impl CelRequest {
@@ -64,6 +240,27 @@ impl CelRequest {
     pub fn headers(&self) -> &HashMap<String, Vec<String>> {
         &self.headers
     }
+
+    pub fn host_mismatch(&self) -> bool {
+        self.host_mismatch
+    }
+
+    /// A claim of the JWT sent as `Authorization: Bearer` or in a cookie,
+    /// without verifying its signature. Strings are returned as they are and
+    /// other values as JSON; empty when there's no such claim.
+    pub fn jwt_claim(&self, name: &str) -> String {
+        let values = |header: &str| {
+            self.headers
+                .get(header)
+                .into_iter()
+                .flatten()
+                .map(String::as_str)
+        };
+        jwt::find_token(values("authorization"), values("cookie"))
+            .and_then(|token| Jwt::decode(token).ok())
+            .and_then(|jwt| jwt.claim(name).map(jwt::claim_text))
+            .unwrap_or_default()
+    }
 }
 
 impl From<CelRequest> for RequestContext {
@@ -110,10 +307,43 @@ impl From<&RequestContext> for CelRequest {
             query,
             method: ctx.method.clone(),
             headers,
+            host_mismatch: false,
         }
     }
 }
 
+/// The request of a logged flow, whose headers aren't kept
+impl From<&FlowRecord> for CelRequest {
+    fn from(flow: &FlowRecord) -> Self {
+        let mut query = HashMap::new();
+        for (key, value) in url::form_urlencoded::parse(flow.query.as_bytes()) {
+            let entry = query.entry(key.to_string()).or_insert_with(Vec::new);
+            entry.push(value.to_string());
+        }
+        CelRequest {
+            scheme: flow.scheme.clone(),
+            host: flow.host.clone(),
+            path: flow.path.clone(),
+            query,
+            method: flow.method.clone(),
+            headers: HashMap::new(),
+            host_mismatch: false,
+        }
+    }
+}
+
+/// Bind the variables of a logged flow, for evaluating plugin scopes and
+/// rules against it. Flows keep nothing of the connection or response.
+pub fn bind_flow<'a>(flow: &FlowRecord, activation: Activation<'a>) -> Option<Activation<'a>> {
+    activation
+        .bind_variable("request", CelRequest::from(flow))
+        .ok()
+        .and_then(|a| a.bind_variable("connection", CelConnection::default()).ok())
+        .and_then(|a| a.bind_variable("flow", CelFlow::default()).ok())
+        .and_then(|a| a.bind_variable("graphql", CelGraphql::default()).ok())
+        .and_then(|a| a.bind_variable("time", CelTime::now()).ok())
+}
+
 impl From<&WasiRequest> for CelRequest {
     fn from(req: &WasiRequest) -> Self {
         let mut headers = HashMap::new();
@@ -155,6 +385,7 @@ impl From<&WasiRequest> for CelRequest {
             query,
             method,
             headers,
+            host_mismatch: false,
         }
     }
 }
@@ -203,6 +434,7 @@ where
             query,
             method,
             headers,
+            host_mismatch: false,
         }
     }
 }
@@ -212,6 +444,8 @@ where
 pub struct CelResponse {
     pub status: u16,
     pub headers: HashMap<String, Vec<String>>,
+    /// `"ipv4"` or `"ipv6"` for the upstream connection, empty when unknown
+    pub address_family: String,
 }
 
 impl CelResponse {
@@ -222,6 +456,10 @@ impl CelResponse {
     pub fn headers(&self) -> &HashMap<String, Vec<String>> {
         &self.headers
     }
+
+    pub fn address_family(&self) -> &str {
+        &self.address_family
+    }
 }
 
 impl<B> From<&Response<B>> for CelResponse
@@ -243,6 +481,7 @@ where
         CelResponse {
             status: res.status().as_u16(),
             headers,
+            address_family: String::new(),
         }
     }
 }
@@ -263,10 +502,47 @@ impl From<&WasiResponse> for CelResponse {
         CelResponse {
             status: res.status.as_u16(),
             headers,
+            address_family: String::new(),
         }
     }
 }
 
+/// The upstream server a response came from. Empty for responses which
+/// weren't received over TLS.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Opaque)]
+#[cel_cxx(display)]
+pub struct CelUpstream {
+    /// SHA-256 fingerprint of the server's certificate, as upper case hex
+    pub cert_fingerprint: String,
+    pub cert_issuer: String,
+    pub cert_sans: Vec<String>,
+}
+
+impl CelUpstream {
+    pub fn cert_fingerprint(&self) -> &str {
+        &self.cert_fingerprint
+    }
+
+    pub fn cert_issuer(&self) -> &str {
+        &self.cert_issuer
+    }
+
+    pub fn cert_sans(&self) -> Vec<String> {
+        self.cert_sans.clone()
+    }
+}
+
+impl From<Option<&UpstreamCert>> for CelUpstream {
+    fn from(cert: Option<&UpstreamCert>) -> Self {
+        cert.map(|cert| CelUpstream {
+            cert_fingerprint: cert.sha256.clone(),
+            cert_issuer: cert.issuer.clone(),
+            cert_sans: cert.sans.clone(),
+        })
+        .unwrap_or_default()
+    }
+}
+
 impl From<&reqwest::Request> for CelRequest {
     fn from(req: &reqwest::Request) -> Self {
         let mut headers = HashMap::new();
@@ -299,6 +575,7 @@ impl From<&reqwest::Request> for CelRequest {
             query,
             method,
             headers,
+            host_mismatch: false,
         }
     }
 }
@@ -307,18 +584,29 @@ impl From<&reqwest::Request> for CelRequest {
 #[cel_cxx(display)]
 pub struct CelContent {
     content_type: String,
+    /// Detected from the body; falls back to the declared type's essence
+    #[serde(default)]
+    sniffed_type: String,
 }
 
 impl CelContent {
     pub fn content_type(&self) -> &str {
         &self.content_type
     }
+
+    pub fn sniffed_type(&self) -> &str {
+        &self.sniffed_type
+    }
 }
 
 impl From<&InboundContent> for CelContent {
     fn from(content: &InboundContent) -> Self {
+        let content_type = content.content_type();
         CelContent {
-            content_type: content.content_type(),
+            sniffed_type: content
+                .sniffed_type()
+                .unwrap_or_else(|| essence(&content_type)),
+            content_type,
         }
     }
 }
@@ -338,7 +626,10 @@ where
             "unknown".to_string()
         };
 
-        CelContent { content_type }
+        CelContent {
+            sniffed_type: essence(&content_type),
+            content_type,
+        }
     }
 }
 
@@ -358,7 +649,12 @@ pub struct CelTime {
 impl CelTime {
     /// Create a new CelTime from the current system time
     pub fn now() -> Self {
-        let now_utc = chrono::Utc::now();
+        Self::at(chrono::Utc::now())
+    }
+
+    /// Create a CelTime for the given instant, ex: to evaluate an expression
+    /// as if at the time a flow was captured
+    pub fn at(now_utc: chrono::DateTime<chrono::Utc>) -> Self {
         let hour = now_utc.hour();
         // chrono: Mon=0 .. Sun=6; we want Sun=0 .. Sat=6
         let day_of_week = match now_utc.weekday() {
@@ -0,0 +1,307 @@
+//! Differential testing of plugin upgrades on live traffic.
+//!
+//! A candidate version of an installed plugin is given a copy of each event
+//! the installed version handles. The installed version's output is applied
+//! to the flow as usual, while the candidate's is only compared to it, so an
+//! upgrade can be checked against real traffic before it replaces anything.
+//! Copying an event buffers its body, so only events with bodies of a known
+//! length up to [MAX_MIRRORED_BODY_BYTES] are mirrored.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Result, bail};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, combinators::UnsyncBoxBody};
+use hyper::{Request, Response};
+use salvo::oapi::ToSchema;
+use serde::Serialize;
+use wasmtime::Store;
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::p3::{Request as WasiRequest, Response as WasiResponse, WasiHttpView};
+
+use crate::events::{Event, request::InterceptedRequest, response::ContextualResponse};
+use crate::plugins::cel::CelRequest;
+use crate::plugins::exercise::{Outcome, collect_bytes};
+use crate::wasm::Host;
+use crate::wasm::bindgen::witmproxy::plugin::capabilities::{
+    ContextualResponse as WasiContextualResponse, Event as WasmEvent, EventKind,
+};
+
+/// Largest body copied for a candidate; events with larger bodies, or bodies
+/// of unknown length, are only passed to the installed version
+pub const MAX_MIRRORED_BODY_BYTES: u64 = 1024 * 1024;
+
+/// Differences kept per plugin; older ones are dropped, though still counted
+const MAX_DIFFERENCES: usize = 100;
+
+/// An event on which the candidate's outcome differed from the installed
+/// version's
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Difference {
+    /// Unix timestamp in milliseconds when the event was handled
+    pub timestamp_millis: u64,
+    /// The kind of event, ex: "request"
+    pub event: String,
+    /// The URL of the flow, when the outcomes carry one
+    pub url: Option<String>,
+    /// How the candidate's outcome differs, ex: "header server: nginx -> caddy"
+    pub changes: Vec<String>,
+}
+
+/// How a candidate compares to the installed version of a plugin
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DifferentialReport {
+    pub plugin_id: String,
+    pub live_version: String,
+    pub candidate_version: String,
+    /// Events passed to both versions
+    pub mirrored: u64,
+    /// Mirrored events on which the outcomes differed
+    pub differing: u64,
+    /// The latest differences, oldest first
+    pub differences: Vec<Difference>,
+}
+
+#[derive(Default)]
+struct Comparison {
+    mirrored: u64,
+    differing: u64,
+    differences: VecDeque<Difference>,
+}
+
+/// The comparisons of candidates to installed plugins, by plugin ID. Cheap to
+/// clone; all clones share the same comparisons.
+#[derive(Clone, Default)]
+pub struct Differences {
+    comparisons: Arc<Mutex<HashMap<String, Comparison>>>,
+}
+
+impl Differences {
+    /// Count an event mirrored to `plugin_id`'s candidate, and how the
+    /// candidate's outcome differed, if it did
+    pub fn record(&self, plugin_id: &str, difference: Option<Difference>) {
+        let mut comparisons = self.comparisons.lock().unwrap();
+        let comparison = comparisons.entry(plugin_id.to_string()).or_default();
+        comparison.mirrored += 1;
+        if let Some(difference) = difference {
+            comparison.differing += 1;
+            if comparison.differences.len() == MAX_DIFFERENCES {
+                comparison.differences.pop_front();
+            }
+            comparison.differences.push_back(difference);
+        }
+    }
+
+    /// Mirrored and differing event counts, and the latest differences
+    pub fn get(&self, plugin_id: &str) -> (u64, u64, Vec<Difference>) {
+        match self.comparisons.lock().unwrap().get(plugin_id) {
+            Some(comparison) => (
+                comparison.mirrored,
+                comparison.differing,
+                comparison.differences.iter().cloned().collect(),
+            ),
+            None => (0, 0, Vec::new()),
+        }
+    }
+
+    /// Forget the comparison of `plugin_id`'s candidate
+    pub fn clear(&self, plugin_id: &str) {
+        self.comparisons.lock().unwrap().remove(plugin_id);
+    }
+}
+
+/// Whether `event` can be copied for a candidate
+pub fn mirrors(event: &dyn Event) -> bool {
+    matches!(
+        event.kind(),
+        EventKind::Request | EventKind::Response | EventKind::InboundContent
+    ) && event
+        .body_size()
+        .is_some_and(|size| size <= MAX_MIRRORED_BODY_BYTES)
+}
+
+/// Buffer the body of `event`, whose body may still be written by the plugin
+/// running in `store`, returning two identical events
+pub(crate) async fn split(
+    event: Box<dyn Event>,
+    store: &mut Store<Host>,
+) -> Result<(Box<dyn Event>, Box<dyn Event>)> {
+    let upstream_addr = event.upstream_addr();
+    let upstream_cert = event.upstream_cert();
+    let connection = event.connection();
+    let findings = event.findings();
+    let graphql = event.graphql();
+
+    match event.into_event_data(store)? {
+        WasmEvent::Request(req) => {
+            let req = store.data_mut().http().table.delete(req)?;
+            let (req, _io) = req.into_http(&mut *store, async { Ok(()) })?;
+            let (parts, body) = req.into_parts();
+            let body = collect_bytes(store, body).await?;
+            let request = |body: Bytes| -> Box<dyn Event> {
+                let req = Request::from_parts(parts.clone(), Full::new(body));
+                let (request, _io) = WasiRequest::from_http(req);
+                match &connection {
+                    Some(connection) => Box::new(InterceptedRequest {
+                        request,
+                        connection: connection.clone(),
+                        findings: findings.clone(),
+                        graphql: graphql.clone(),
+                    }),
+                    None => Box::new(request),
+                }
+            };
+            Ok((request(body.clone()), request(body)))
+        }
+        WasmEvent::Response(WasiContextualResponse { response, request }) => {
+            let response = store.data_mut().http().table.delete(response)?;
+            let response = response.into_http(&mut *store, async { Ok(()) })?;
+            let (parts, body) = response.into_parts();
+            let body = collect_bytes(store, body).await?;
+            let response = |body: Bytes| -> Box<dyn Event> {
+                let (response, _io) =
+                    WasiResponse::from_http(Response::from_parts(parts.clone(), Full::new(body)));
+                Box::new(ContextualResponse {
+                    request: CelRequest::from(&request).into(),
+                    response,
+                    upstream_addr,
+                    upstream_cert: upstream_cert.clone(),
+                    findings: findings.clone(),
+                    graphql: graphql.clone(),
+                })
+            };
+            Ok((response(body.clone()), response(body)))
+        }
+        WasmEvent::InboundContent(content) => {
+            let mut content = store.data_mut().table.delete(content)?;
+            let body = match content.body()? {
+                Some(body) => collect_bytes(store, body).await?,
+                None => Bytes::new(),
+            };
+            let copy = content.with_body(full(body.clone()));
+            content.set_body(full(body));
+            Ok((Box::new(content), Box::new(copy)))
+        }
+        _ => bail!("Only request, response and inbound content events can be mirrored"),
+    }
+}
+
+fn full(body: Bytes) -> UnsyncBoxBody<Bytes, ErrorCode> {
+    Full::new(body).map_err(|e| match e {}).boxed_unsync()
+}
+
+/// How the `candidate` outcome of a `kind` event differs from the `live`
+/// one, if it does
+pub fn compare(kind: EventKind, live: &Outcome, candidate: &Outcome) -> Option<Difference> {
+    if live == candidate {
+        return None;
+    }
+    let changes = match (live, candidate) {
+        (Outcome::Event(live), Outcome::Event(candidate)) => {
+            let mut changes = Vec::new();
+            if live.kind() != candidate.kind() {
+                changes.push(format!(
+                    "returned {} instead of {}",
+                    candidate.kind(),
+                    live.kind()
+                ));
+            }
+            changes.extend(live.changes(candidate));
+            changes
+        }
+        (live, candidate) => vec![format!(
+            "live {}, candidate {}",
+            describe(live),
+            describe(candidate)
+        )],
+    };
+    let url = [live, candidate]
+        .into_iter()
+        .find_map(|outcome| match outcome {
+            Outcome::Event(event) => event.url(),
+            _ => None,
+        });
+    Some(Difference {
+        timestamp_millis: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+        event: kind.to_string(),
+        url: url.map(str::to_string),
+        changes,
+    })
+}
+
+fn describe(outcome: &Outcome) -> String {
+    match outcome {
+        Outcome::Event(event) => format!("returned {}", event.kind()),
+        Outcome::Blocked { .. } => "blocked".to_string(),
+        Outcome::Failed { error } => format!("failed: {}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::exercise::RecordedEvent;
+
+    fn request(url: &str, headers: &[(&str, &str)]) -> RecordedEvent {
+        RecordedEvent::Request {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: String::new(),
+        }
+    }
+
+    #[test]
+    fn describes_how_candidate_outcomes_differ() {
+        let live = Outcome::Event(request("https://example.com/", &[("x-v", "1")]));
+        assert!(compare(EventKind::Request, &live, &live.clone()).is_none());
+
+        let candidate = Outcome::Event(request("https://example.com/", &[("x-v", "2")]));
+        let difference = compare(EventKind::Request, &live, &candidate).unwrap();
+        assert_eq!(difference.event, "request");
+        assert_eq!(difference.url.as_deref(), Some("https://example.com/"));
+        assert_eq!(difference.changes, ["header x-v: 1 -> 2"]);
+
+        let blocked = Outcome::Blocked {
+            plugin_id: "ops/auth".to_string(),
+        };
+        let difference = compare(EventKind::Request, &live, &blocked).unwrap();
+        assert_eq!(
+            difference.changes,
+            ["live returned request, candidate blocked"]
+        );
+        assert_eq!(difference.url.as_deref(), Some("https://example.com/"));
+    }
+
+    #[test]
+    fn keeps_the_latest_differences_and_counts_all() {
+        let differences = Differences::default();
+        let live = Outcome::Event(request("https://example.com/", &[]));
+        for i in 0..MAX_DIFFERENCES + 5 {
+            let candidate = Outcome::Failed {
+                error: i.to_string(),
+            };
+            differences.record("ops/auth", compare(EventKind::Request, &live, &candidate));
+            differences.record("ops/auth", None);
+        }
+
+        let (mirrored, differing, kept) = differences.get("ops/auth");
+        assert_eq!(mirrored, 2 * (MAX_DIFFERENCES as u64 + 5));
+        assert_eq!(differing, MAX_DIFFERENCES as u64 + 5);
+        assert_eq!(kept.len(), MAX_DIFFERENCES);
+        assert_eq!(
+            kept[0].changes,
+            ["live returned request, candidate failed: 5"]
+        );
+
+        differences.clear("ops/auth");
+        assert_eq!(differences.get("ops/auth").0, 0);
+    }
+}
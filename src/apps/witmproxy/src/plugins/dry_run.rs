@@ -0,0 +1,344 @@
+//! Evaluating capability scope expressions outside of a plugin, so authors
+//! can check an expression against an example flow before installing
+//! anything (`witm cel test` and `/api/cel/test`).
+
+use std::collections::BTreeMap;
+
+use bytes::Bytes;
+use cel_cxx::Activation;
+use chrono::{DateTime, Utc};
+use http_body_util::Empty;
+use hyper::{Request, Response};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::http::graphql;
+use crate::plugins::cel::{
+    CelConnect, CelConnection, CelContent, CelFlow, CelGraphql, CelMqtt, CelRequest, CelResponse,
+    CelStream, CelTime, CelUpstream,
+};
+use crate::plugins::lint::event_env;
+use crate::proxy::findings;
+use crate::proxy::flows::FlowRecord;
+use crate::wasm::bindgen::witmproxy::plugin::capabilities::EventKind;
+
+/// An example flow to evaluate expressions against. Only the parts relevant
+/// to the event kind being tested are used.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FlowFixture {
+    /// Request method (default: "GET")
+    #[serde(default = "default_method")]
+    pub method: String,
+    /// Full request URL, ex: "https://example.com/path?query=1"
+    pub url: String,
+    /// Request headers
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Request body, ex: a GraphQL query
+    #[serde(default)]
+    pub body: String,
+    /// Response status (default: 200)
+    #[serde(default = "default_status")]
+    pub status: u16,
+    /// Response headers, including `content-type` for inbound content events
+    #[serde(default)]
+    pub response_headers: BTreeMap<String, String>,
+    /// Response body, for inbound content events
+    #[serde(default)]
+    pub response_body: String,
+    /// Protocol detected on raw streams (default: "unknown")
+    #[serde(default = "default_protocol")]
+    pub protocol: String,
+    /// Topic of MQTT messages, ex: "devices/42/telemetry"
+    #[serde(default)]
+    pub topic: String,
+    /// Time to evaluate the expression at (default: now)
+    #[serde(default)]
+    pub time: Option<DateTime<Utc>>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+fn default_protocol() -> String {
+    "unknown".to_string()
+}
+
+impl From<&FlowRecord> for FlowFixture {
+    fn from(flow: &FlowRecord) -> Self {
+        let query = if flow.query.is_empty() {
+            String::new()
+        } else {
+            format!("?{}", flow.query)
+        };
+        Self {
+            method: flow.method.clone(),
+            url: format!("{}://{}{}{}", flow.scheme, flow.host, flow.path, query),
+            headers: BTreeMap::from([("host".to_string(), flow.host.clone())]),
+            body: String::new(),
+            status: flow.status.unwrap_or_else(default_status),
+            response_headers: BTreeMap::new(),
+            response_body: String::new(),
+            protocol: default_protocol(),
+            topic: String::new(),
+            time: DateTime::from_timestamp_millis(flow.timestamp_millis as i64),
+        }
+    }
+}
+
+/// The outcome of evaluating an expression
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DryRunResult {
+    /// Whether the expression evaluated to `true`, i.e. the plugin would
+    /// handle the event
+    pub matched: bool,
+    /// The value the expression evaluated to, if it evaluated
+    pub value: Option<String>,
+    /// Why the expression failed to compile or evaluate
+    pub error: Option<String>,
+}
+
+impl DryRunResult {
+    fn error(error: impl ToString) -> Self {
+        Self {
+            matched: false,
+            value: None,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// Evaluate a scope `expression` for `kind` events against `fixture`, with
+/// the same variables bound as when the proxy handles such an event
+pub fn evaluate(kind: EventKind, expression: &str, fixture: &FlowFixture) -> DryRunResult {
+    let program = match event_env(kind).compile(expression) {
+        Ok(program) => program,
+        Err(e) => return DryRunResult::error(format!("Failed to compile: {}", e)),
+    };
+    let activation = match bind_fixture(kind, fixture) {
+        Ok(activation) => activation,
+        Err(e) => return DryRunResult::error(format!("Invalid fixture: {}", e)),
+    };
+    match program.evaluate(activation) {
+        Ok(value) => DryRunResult {
+            matched: matches!(value, cel_cxx::Value::Bool(true)),
+            error: match value {
+                cel_cxx::Value::Bool(_) => None,
+                _ => Some("Scope expressions must evaluate to a bool".to_string()),
+            },
+            value: Some(value.to_string()),
+        },
+        Err(e) => DryRunResult::error(format!("Failed to evaluate: {}", e)),
+    }
+}
+
+fn bind_fixture(kind: EventKind, fixture: &FlowFixture) -> anyhow::Result<Activation<'static>> {
+    let mut request = Request::builder()
+        .method(fixture.method.as_str())
+        .uri(&fixture.url);
+    for (name, value) in &fixture.headers {
+        request = request.header(name, value);
+    }
+    let request = request.body(Empty::<Bytes>::new())?;
+    let operation = graphql::is_graphql_post(&request)
+        .then(|| graphql::parse_request(&request, fixture.body.as_bytes()))
+        .flatten();
+    let mut response = Response::builder().status(fixture.status);
+    for (name, value) in &fixture.response_headers {
+        response = response.header(name, value);
+    }
+    let response = response.body(Empty::<Bytes>::new())?;
+    let host = request.uri().host().unwrap_or_default().to_string();
+    let port = request
+        .uri()
+        .port_u16()
+        .unwrap_or(if request.uri().scheme_str() == Some("http") {
+            80
+        } else {
+            443
+        });
+
+    let flow = CelFlow::from(
+        findings::request_findings(&request)
+            .into_iter()
+            .collect::<Vec<_>>()
+            .as_slice(),
+    );
+
+    let activation = Activation::new();
+    let activation = match kind {
+        EventKind::Connect => activation.bind_variable("connect", CelConnect { host, port })?,
+        EventKind::Request => activation
+            .bind_variable("request", CelRequest::from(&request))?
+            .bind_variable("connection", CelConnection::default())?
+            .bind_variable("flow", flow)?
+            .bind_variable("graphql", CelGraphql::from(operation.as_ref()))?,
+        EventKind::Response => activation
+            .bind_variable("request", CelRequest::from(&request))?
+            .bind_variable("response", CelResponse::from(&response))?
+            .bind_variable("upstream", CelUpstream::default())?
+            .bind_variable("flow", flow)?
+            .bind_variable("graphql", CelGraphql::from(operation.as_ref()))?,
+        EventKind::InboundContent => {
+            activation.bind_variable("content", CelContent::from(&response))?
+        }
+        EventKind::Timer => activation,
+        EventKind::RawStream => activation.bind_variable(
+            "stream",
+            CelStream {
+                host,
+                port,
+                protocol: fixture.protocol.clone(),
+            },
+        )?,
+        EventKind::MqttMessage => activation.bind_variable(
+            "mqtt",
+            CelMqtt {
+                host,
+                port,
+                from_client: true,
+                topic: fixture.topic.clone(),
+                qos: 0,
+                retain: false,
+            },
+        )?,
+    };
+    let time = fixture.time.map(CelTime::at).unwrap_or_else(CelTime::now);
+    Ok(activation.bind_variable("time", time)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(url: &str) -> FlowFixture {
+        serde_json::from_value(serde_json::json!({
+            "url": url,
+            "headers": { "user-agent": "curl/8.0" },
+            "status": 404,
+            "response_headers": { "content-type": "text/html; charset=utf-8" },
+            "time": "2026-01-05T10:30:00Z",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn expressions_are_evaluated_against_fixtures() {
+        let example = fixture("https://example.com/login?next=%2F");
+        let result = evaluate(
+            EventKind::Request,
+            "request.host() == 'example.com' && request.path() == '/login'",
+            &example,
+        );
+        assert!(result.matched, "{:?}", result);
+        assert_eq!(result.error, None);
+
+        let result = evaluate(EventKind::Response, "response.status() == 200", &example);
+        assert!(!result.matched);
+        assert_eq!(result.error, None);
+
+        assert!(
+            evaluate(
+                EventKind::InboundContent,
+                "content.content_type().startsWith('text/html')",
+                &example
+            )
+            .matched
+        );
+        assert!(evaluate(EventKind::Connect, "connect.port() == 443", &example).matched);
+        assert!(evaluate(EventKind::Timer, "time.is_day_of_week(1)", &example).matched);
+
+        let mut message = fixture("mqtt://broker.example.com:1883");
+        message.topic = "devices/42/telemetry".to_string();
+        assert!(
+            evaluate(
+                EventKind::MqttMessage,
+                "mqtt.topic_matches('devices/+/telemetry') && mqtt.port() == 1883",
+                &message
+            )
+            .matched
+        );
+    }
+
+    #[test]
+    fn findings_are_bound_to_flow() {
+        let example = fixture("https://example.com/reset?email=dave%40example.com");
+        for kind in [EventKind::Request, EventKind::Response] {
+            assert!(evaluate(kind, "flow.has_finding('email')", &example).matched);
+            assert!(!evaluate(kind, "flow.has_finding('jwt')", &example).matched);
+        }
+    }
+
+    #[test]
+    fn jwt_claims_are_read_from_requests() {
+        // {"alg":"HS256"}.{"sub":"alice","aud":["api"]}
+        let token = "eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiJhbGljZSIsImF1ZCI6WyJhcGkiXX0.c2ln";
+        let mut example = fixture("https://api.example.com/me");
+        example
+            .headers
+            .insert("authorization".to_string(), format!("Bearer {}", token));
+        assert!(
+            evaluate(
+                EventKind::Request,
+                "request.jwt_claim('sub') == 'alice' && request.jwt_claim('aud') == '[\"api\"]'",
+                &example
+            )
+            .matched
+        );
+        assert!(
+            evaluate(
+                EventKind::Request,
+                "request.jwt_claim('iss') == ''",
+                &example
+            )
+            .matched
+        );
+    }
+
+    #[test]
+    fn graphql_operations_are_parsed_from_bodies() {
+        let mut example = fixture("https://api.example.com/graphql");
+        example.method = "POST".to_string();
+        example.body = serde_json::json!({
+            "query": "mutation DeleteUser($id: ID!) { deleteUser(id: $id) { id } }",
+            "operationName": "DeleteUser",
+        })
+        .to_string();
+        for kind in [EventKind::Request, EventKind::Response] {
+            assert!(
+                evaluate(
+                    kind,
+                    "graphql.operation() == 'DeleteUser' && graphql.operation_type() == 'mutation' && graphql.has_field('deleteUser')",
+                    &example
+                )
+                .matched
+            );
+        }
+        assert!(
+            evaluate(
+                EventKind::Request,
+                "graphql.operation() == ''",
+                &fixture("https://api.example.com/graphql")
+            )
+            .matched
+        );
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        let example = fixture("https://example.com/");
+        let result = evaluate(EventKind::Request, "response.status() == 200", &example);
+        assert!(!result.matched);
+        assert!(result.error.unwrap().starts_with("Failed to compile"));
+
+        let result = evaluate(EventKind::Request, "request.host()", &example);
+        assert!(!result.matched);
+        assert_eq!(result.value.as_deref(), Some("\"example.com\""));
+        assert!(result.error.is_some());
+    }
+}
@@ -0,0 +1,536 @@
+//! Passing flows through plugins outside the proxy, reporting what they do
+//! with each event, so plugin authors can try a plugin against captured
+//! flows without routing live traffic through it (`witm plugin exercise`).
+//!
+//! Events are handled as [RecordedEvent]s, plain snapshots which can be
+//! compared and saved, ex: to the cassettes of the plugin test harness.
+
+use std::collections::BTreeMap;
+
+use anyhow::{Result, bail};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, combinators::UnsyncBoxBody};
+use hyper::{HeaderMap, Request, Response, Uri};
+use serde::{Deserialize, Serialize};
+use wasmtime::Store;
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::p3::{Request as WasiRequest, Response as WasiResponse, WasiHttpView};
+
+use crate::events::Event;
+use crate::events::content::InboundContent;
+use crate::events::response::ContextualResponse;
+use crate::plugins::cel::CelRequest;
+use crate::plugins::dry_run::FlowFixture;
+use crate::plugins::registry::{PluginBlocked, PluginRegistry};
+use crate::wasm::Host;
+use crate::wasm::bindgen::witmproxy::plugin::capabilities::{
+    ContextualResponse as WasiContextualResponse, Event as WasmEvent, RequestContext,
+};
+
+/// An event plugins handle. Bodies are kept as text, binary bodies lossily.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RecordedEvent {
+    Request {
+        method: String,
+        url: String,
+        #[serde(default)]
+        headers: Vec<(String, String)>,
+        #[serde(default)]
+        body: String,
+    },
+    /// A response, with the method and URL of the request it answers
+    Response {
+        method: String,
+        url: String,
+        status: u16,
+        #[serde(default)]
+        headers: Vec<(String, String)>,
+        #[serde(default)]
+        body: String,
+    },
+    InboundContent {
+        status: u16,
+        #[serde(default)]
+        headers: Vec<(String, String)>,
+        content_type: String,
+        #[serde(default)]
+        body: String,
+    },
+}
+
+/// How the plugin chain ended
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Outcome {
+    /// The chain returned this event
+    Event(RecordedEvent),
+    /// A plugin returned nothing, blocking the flow
+    Blocked { plugin_id: String },
+    /// A plugin failed
+    Failed { error: String },
+}
+
+/// What the plugins did with an event
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "verdict", rename_all = "snake_case")]
+pub enum Verdict {
+    /// No plugin's scope matched the event
+    Skipped,
+    Unchanged,
+    /// The event was changed, as described by each change
+    Changed {
+        changes: Vec<String>,
+    },
+    /// A plugin answered the request itself, so it wouldn't reach the server
+    Answered {
+        status: u16,
+    },
+    Blocked {
+        plugin_id: String,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+/// What the plugins did with each event the proxy would offer them for a flow
+#[derive(Debug, Clone, Serialize)]
+pub struct FlowReport {
+    /// The flow's method and URL
+    pub flow: String,
+    /// By event kind, in the order the proxy offers them
+    pub events: Vec<(String, Verdict)>,
+}
+
+/// Pass `event` through the plugins of `registry`
+pub async fn handle(registry: &PluginRegistry, event: &RecordedEvent) -> Result<Outcome> {
+    match registry.handle_event(into_event(event)?).await {
+        Ok((event, store)) => Ok(Outcome::Event(from_event(event, store).await?)),
+        Err(e) => Ok(match e.downcast_ref::<PluginBlocked>() {
+            Some(blocked) => Outcome::Blocked {
+                plugin_id: blocked.plugin_id.clone(),
+            },
+            None => Outcome::Failed {
+                error: e.to_string(),
+            },
+        }),
+    }
+}
+
+/// Pass the events of `flow` through the plugins of `registry`: its request,
+/// then unless that was blocked or answered, its response and, if the
+/// response has a content type, its content
+pub async fn exercise(registry: &PluginRegistry, flow: &FlowFixture) -> Result<FlowReport> {
+    let events = RecordedEvent::from_fixture(flow)?;
+    let mut report = FlowReport {
+        flow: format!("{} {}", flow.method, flow.url),
+        events: Vec::with_capacity(events.len()),
+    };
+    for event in events {
+        let verdict = verdict(registry, &event).await?;
+        let ends_flow = matches!(event, RecordedEvent::Request { .. })
+            && matches!(
+                verdict,
+                Verdict::Answered { .. } | Verdict::Blocked { .. } | Verdict::Failed { .. }
+            );
+        report.events.push((event.kind().to_string(), verdict));
+        if ends_flow {
+            break;
+        }
+    }
+    Ok(report)
+}
+
+async fn verdict(registry: &PluginRegistry, event: &RecordedEvent) -> Result<Verdict> {
+    if !registry.can_handle(&*into_event(event)?) {
+        return Ok(Verdict::Skipped);
+    }
+    Ok(match handle(registry, event).await? {
+        Outcome::Event(RecordedEvent::Response { status, .. })
+            if matches!(event, RecordedEvent::Request { .. }) =>
+        {
+            Verdict::Answered { status }
+        }
+        Outcome::Event(after) => {
+            let changes = event.changes(&after);
+            if changes.is_empty() {
+                Verdict::Unchanged
+            } else {
+                Verdict::Changed { changes }
+            }
+        }
+        Outcome::Blocked { plugin_id } => Verdict::Blocked { plugin_id },
+        Outcome::Failed { error } => Verdict::Failed { error },
+    })
+}
+
+impl RecordedEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RecordedEvent::Request { .. } => "request",
+            RecordedEvent::Response { .. } => "response",
+            RecordedEvent::InboundContent { .. } => "inbound_content",
+        }
+    }
+
+    /// The URL of the request, or of the request a response answers
+    pub fn url(&self) -> Option<&str> {
+        self.parts().1
+    }
+
+    /// The events the proxy offers plugins for `flow`, in order
+    pub fn from_fixture(flow: &FlowFixture) -> Result<Vec<Self>> {
+        let uri: Uri = flow.url.parse()?;
+        let lowercase = |headers: &BTreeMap<String, String>| {
+            headers
+                .iter()
+                .map(|(name, value)| (name.to_lowercase(), value.clone()))
+                .collect::<Vec<_>>()
+        };
+        let response_headers = lowercase(&flow.response_headers);
+
+        let mut events = vec![
+            RecordedEvent::Request {
+                method: flow.method.clone(),
+                url: uri.to_string(),
+                headers: lowercase(&flow.headers),
+                body: flow.body.clone(),
+            },
+            RecordedEvent::Response {
+                method: flow.method.clone(),
+                url: format!(
+                    "{}://{}{}",
+                    uri.scheme_str().unwrap_or("https"),
+                    uri.authority().map(|a| a.as_str()).unwrap_or_default(),
+                    uri.path()
+                ),
+                status: flow.status,
+                headers: response_headers.clone(),
+                body: flow.response_body.clone(),
+            },
+        ];
+        if let Some((_, content_type)) = response_headers
+            .iter()
+            .find(|(name, _)| name == "content-type")
+        {
+            events.push(RecordedEvent::InboundContent {
+                status: flow.status,
+                content_type: content_type.clone(),
+                headers: response_headers.clone(),
+                body: flow.response_body.clone(),
+            });
+        }
+        Ok(events)
+    }
+
+    /// How `after` differs from this event. `content-length` is left out, as
+    /// it follows the body.
+    pub fn changes(&self, after: &RecordedEvent) -> Vec<String> {
+        let mut changes = Vec::new();
+        let (before, after) = (self.parts(), after.parts());
+        for (what, before, after) in [("method", before.0, after.0), ("url", before.1, after.1)] {
+            if before != after {
+                changes.push(format!(
+                    "{} {} -> {}",
+                    what,
+                    before.unwrap_or_default(),
+                    after.unwrap_or_default()
+                ));
+            }
+        }
+        if before.2 != after.2 {
+            changes.push(format!(
+                "status {} -> {}",
+                before.2.unwrap_or_default(),
+                after.2.unwrap_or_default()
+            ));
+        }
+
+        let (before_headers, after_headers) = (joined(before.3), joined(after.3));
+        for (name, value) in &before_headers {
+            match after_headers.get(name) {
+                None => changes.push(format!("header {} removed", name)),
+                Some(after) if after != value => {
+                    changes.push(format!("header {}: {} -> {}", name, value, after))
+                }
+                Some(_) => {}
+            }
+        }
+        for (name, value) in &after_headers {
+            if !before_headers.contains_key(name) {
+                changes.push(format!("header {}: {} added", name, value));
+            }
+        }
+
+        if before.4 != after.4 {
+            changes.push(format!(
+                "body changed ({} -> {} bytes)",
+                before.4.len(),
+                after.4.len()
+            ));
+        }
+        changes
+    }
+
+    /// Method, URL, status, headers and body, where the event has them
+    fn parts(
+        &self,
+    ) -> (
+        Option<&str>,
+        Option<&str>,
+        Option<u16>,
+        &[(String, String)],
+        &str,
+    ) {
+        match self {
+            RecordedEvent::Request {
+                method,
+                url,
+                headers,
+                body,
+            } => (Some(method), Some(url), None, headers, body),
+            RecordedEvent::Response {
+                method,
+                url,
+                status,
+                headers,
+                body,
+            } => (Some(method), Some(url), Some(*status), headers, body),
+            RecordedEvent::InboundContent {
+                status,
+                headers,
+                body,
+                ..
+            } => (None, None, Some(*status), headers, body),
+        }
+    }
+}
+
+/// Headers by name, with repeated headers' values joined
+fn joined(headers: &[(String, String)]) -> BTreeMap<String, String> {
+    let mut joined: BTreeMap<String, String> = BTreeMap::new();
+    for (name, value) in headers {
+        if name.eq_ignore_ascii_case("content-length") {
+            continue;
+        }
+        joined
+            .entry(name.to_lowercase())
+            .and_modify(|values| {
+                values.push_str(", ");
+                values.push_str(value);
+            })
+            .or_insert_with(|| value.clone());
+    }
+    joined
+}
+
+fn into_event(event: &RecordedEvent) -> Result<Box<dyn Event>> {
+    Ok(match event {
+        RecordedEvent::Request {
+            method,
+            url,
+            headers,
+            body,
+        } => {
+            let mut req = Request::builder().method(method.as_str()).uri(url);
+            for (name, value) in headers {
+                req = req.header(name, value);
+            }
+            let (req, _io) =
+                WasiRequest::from_http(req.body(Full::new(Bytes::from(body.clone())))?);
+            Box::new(req)
+        }
+        RecordedEvent::Response {
+            method,
+            url,
+            status,
+            headers,
+            body,
+        } => {
+            let req = Request::builder()
+                .method(method.as_str())
+                .uri(url)
+                .body(Full::new(Bytes::new()))?;
+            let (req, _io) = WasiRequest::from_http(req);
+            let (response, _io) = WasiResponse::from_http(response(*status, headers, body)?);
+            Box::new(ContextualResponse {
+                request: CelRequest::from(&req).into(),
+                response,
+                upstream_addr: None,
+                upstream_cert: None,
+                findings: Vec::new(),
+                graphql: None,
+            })
+        }
+        RecordedEvent::InboundContent {
+            status,
+            headers,
+            content_type,
+            body,
+        } => {
+            let (parts, body) = response(*status, headers, body)?.into_parts();
+            let body = body.map_err(|e| match e {}).boxed_unsync();
+            Box::new(InboundContent::new(parts, content_type.clone(), body)?)
+        }
+    })
+}
+
+fn response(
+    status: u16,
+    headers: &[(String, String)],
+    body: &str,
+) -> Result<Response<Full<Bytes>>> {
+    let mut response = Response::builder().status(status);
+    for (name, value) in headers {
+        response = response.header(name, value);
+    }
+    Ok(response.body(Full::new(Bytes::from(body.to_string())))?)
+}
+
+pub(crate) async fn from_event(event: WasmEvent, mut store: Store<Host>) -> Result<RecordedEvent> {
+    match event {
+        WasmEvent::Request(req) => {
+            let req = store.data_mut().http().table.delete(req)?;
+            let (req, _io) = req.into_http(&mut store, async { Ok(()) })?;
+            let (parts, body) = req.into_parts();
+            Ok(RecordedEvent::Request {
+                method: parts.method.to_string(),
+                url: parts.uri.to_string(),
+                headers: headers(&parts.headers),
+                body: collect(&mut store, body).await?,
+            })
+        }
+        WasmEvent::Response(WasiContextualResponse { response, request }) => {
+            let response = store.data_mut().http().table.delete(response)?;
+            let response = response.into_http(&mut store, async { Ok(()) })?;
+            let (parts, body) = response.into_parts();
+            Ok(RecordedEvent::Response {
+                method: request.method.clone(),
+                url: url(&request),
+                status: parts.status.as_u16(),
+                headers: headers(&parts.headers),
+                body: collect(&mut store, body).await?,
+            })
+        }
+        WasmEvent::InboundContent(content) => {
+            let content = store.data_mut().table.delete(content)?;
+            let content_type = content.content_type();
+            let (parts, body) = content.into_response()?.into_parts();
+            Ok(RecordedEvent::InboundContent {
+                status: parts.status.as_u16(),
+                headers: headers(&parts.headers),
+                content_type,
+                body: collect(&mut store, body).await?,
+            })
+        }
+        _ => bail!("Plugins returned an event that can't be recorded"),
+    }
+}
+
+async fn collect(store: &mut Store<Host>, body: UnsyncBoxBody<Bytes, ErrorCode>) -> Result<String> {
+    let body = collect_bytes(store, body).await?;
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Read a body a plugin may still be writing, running the plugin until done
+pub(crate) async fn collect_bytes(
+    store: &mut Store<Host>,
+    body: UnsyncBoxBody<Bytes, ErrorCode>,
+) -> Result<Bytes> {
+    let body = store
+        .run_concurrent(async move |_| body.collect().await)
+        .await??;
+    Ok(body.to_bytes())
+}
+
+fn headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect()
+}
+
+/// The URL of the request a response answers, without its query
+fn url(request: &RequestContext) -> String {
+    format!("{}://{}{}", request.scheme, request.host, request.path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flows_are_offered_as_request_response_and_content() {
+        let flow: FlowFixture = serde_json::from_value(serde_json::json!({
+            "url": "https://example.com",
+            "headers": { "User-Agent": "curl/8.0" },
+            "response_headers": { "Content-Type": "text/html" },
+            "response_body": "<p>hi</p>",
+        }))
+        .unwrap();
+        let events = RecordedEvent::from_fixture(&flow).unwrap();
+        let kinds: Vec<_> = events.iter().map(RecordedEvent::kind).collect();
+        assert_eq!(kinds, ["request", "response", "inbound_content"]);
+        let RecordedEvent::Request { url, headers, .. } = &events[0] else {
+            unreachable!()
+        };
+        assert_eq!(url, "https://example.com/");
+        assert_eq!(headers[0].0, "user-agent");
+
+        let without_content: FlowFixture =
+            serde_json::from_value(serde_json::json!({ "url": "https://example.com/a?b=c" }))
+                .unwrap();
+        assert_eq!(
+            RecordedEvent::from_fixture(&without_content).unwrap().len(),
+            2
+        );
+    }
+
+    #[test]
+    fn changes_are_described() {
+        let before = RecordedEvent::Response {
+            method: "GET".to_string(),
+            url: "https://example.com/".to_string(),
+            status: 200,
+            headers: vec![
+                ("server".to_string(), "nginx".to_string()),
+                ("set-cookie".to_string(), "a=1".to_string()),
+                ("content-length".to_string(), "2".to_string()),
+            ],
+            body: "hi".to_string(),
+        };
+        let mut after = before.clone();
+        assert!(before.changes(&after).is_empty());
+
+        if let RecordedEvent::Response {
+            status,
+            headers,
+            body,
+            ..
+        } = &mut after
+        {
+            *status = 403;
+            *headers = vec![
+                ("server".to_string(), "witmproxy".to_string()),
+                ("x-blocked".to_string(), "1".to_string()),
+            ];
+            *body = "blocked".to_string();
+        }
+        assert_eq!(
+            before.changes(&after),
+            [
+                "status 200 -> 403",
+                "header server: nginx -> witmproxy",
+                "header set-cookie removed",
+                "header x-blocked: 1 added",
+                "body changed (2 -> 7 bytes)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,519 @@
+//! Summaries of HTML documents, parsed by the host for plugins with the
+//! `html` capability: the title, meta tags, links, forms and scripts.
+//!
+//! A document is parsed once however many plugins handle it: its summary is
+//! kept with the content and shared with every plugin summarizing it after,
+//! until one replaces the body. Bodies are buffered whole, up to
+//! [MAX_HTML_BYTES], decoded with their detected charset and scanned on the
+//! blocking thread pool. The scanner is lenient rather than a full HTML5
+//! parser: it reads tags and attributes, skipping comments and the contents
+//! of `script`, `style` and `textarea` elements. Each list holds at most
+//! [MAX_ITEMS] entries, and texts are cut at [MAX_TEXT_CHARS] characters.
+
+use std::sync::Arc;
+
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use http_body_util::combinators::UnsyncBoxBody;
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+
+use crate::http::sniff;
+use crate::http::utils::buffer;
+
+/// Largest document buffered to be summarized
+pub const MAX_HTML_BYTES: usize = 8 * 1024 * 1024;
+
+/// Most entries in each list of a summary
+pub const MAX_ITEMS: usize = 1024;
+
+/// Longest title, link text or meta value kept, in characters
+pub const MAX_TEXT_CHARS: usize = 512;
+
+/// A link, from an `a`, `area` or `link` element
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub element: String,
+    /// The `href`, as written
+    pub href: String,
+    pub rel: Option<String>,
+    /// The text of `a` elements
+    pub text: Option<String>,
+}
+
+/// A field of a form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: Option<String>,
+    /// ex: `password`, `select` or `textarea`
+    pub field_type: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Form {
+    pub action: Option<String>,
+    /// Lowercase, `get` if unset
+    pub method: String,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Script {
+    pub src: Option<String>,
+    /// The `type` attribute, ex: `module`
+    pub script_type: Option<String>,
+    /// Length in bytes of the inline source
+    pub inline_length: usize,
+}
+
+/// What an HTML document holds
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HtmlSummary {
+    pub title: Option<String>,
+    /// The `name`, `property` or `http-equiv` of `meta` elements, and their
+    /// `content`. `charset` declarations are listed as `charset`.
+    pub meta: Vec<(String, String)>,
+    pub links: Vec<Link>,
+    pub forms: Vec<Form>,
+    pub scripts: Vec<Script>,
+}
+
+/// Whether content of `content_type` (an essence) is summarized
+pub fn is_html(content_type: &str) -> bool {
+    matches!(content_type, "text/html" | "application/xhtml+xml")
+}
+
+/// Summarize the HTML document in `body`, returning the body, rebuilt from
+/// what was read, alongside
+pub async fn summarize(
+    body: UnsyncBoxBody<Bytes, ErrorCode>,
+    content_type: &str,
+) -> (UnsyncBoxBody<Bytes, ErrorCode>, Result<Arc<HtmlSummary>>) {
+    let essence = sniff::essence(content_type);
+    if !is_html(&essence) {
+        let reason = anyhow!("Content of type {essence:?} isn't HTML");
+        return (body, Err(reason));
+    }
+    let bytes = match buffer(body, MAX_HTML_BYTES).await {
+        Ok(bytes) => bytes,
+        Err((body, reason)) => return (body, Err(reason)),
+    };
+    let parsed = {
+        let bytes = bytes.clone();
+        let content_type = content_type.to_string();
+        tokio::task::spawn_blocking(move || {
+            let prefix = &bytes[..bytes.len().min(1024)];
+            let (page, _, _) = sniff::detect_charset(&content_type, prefix, true).decode(&bytes);
+            HtmlSummary::parse(&page)
+        })
+        .await
+    };
+    let summary = parsed
+        .map(Arc::new)
+        .map_err(|e| anyhow!("Summarizing failed: {e}"));
+    (full(bytes), summary)
+}
+
+fn full(bytes: Bytes) -> UnsyncBoxBody<Bytes, ErrorCode> {
+    http_body_util::Full::new(bytes)
+        .map_err(|_| ErrorCode::InternalError(Some("conversion error".to_string())))
+        .boxed_unsync()
+}
+
+impl HtmlSummary {
+    pub fn parse(page: &str) -> Self {
+        let mut summary = Self::default();
+        // The `a` element whose text is being read, and the text so far
+        let mut anchor: Option<(usize, String)> = None;
+        let mut in_form = false;
+        let mut pos = 0;
+        while let Some(start) = page[pos..].find('<').map(|i| pos + i) {
+            if let Some((_, text)) = &mut anchor {
+                text.push_str(&page[pos..start]);
+            }
+            if page[start..].starts_with("<!--") {
+                pos = page[start..]
+                    .find("-->")
+                    .map_or(page.len(), |i| start + i + 3);
+                continue;
+            }
+            let end = tag_end(page, start);
+            pos = end;
+            let Ok(tag) = Tag::parse(&page[start..end]) else {
+                continue;
+            };
+            if tag.closing {
+                match tag.name.as_str() {
+                    "a" => {
+                        if let Some((i, text)) = anchor.take() {
+                            summary.links[i].text = Some(clean(&text));
+                        }
+                    }
+                    "form" => in_form = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match tag.name.as_str() {
+                "title" => {
+                    let until = find_end_tag(page, pos, "title");
+                    if summary.title.is_none() {
+                        summary.title = Some(clean(&page[pos..until]));
+                    }
+                    pos = until;
+                }
+                "meta" => {
+                    let key = tag
+                        .attr("name")
+                        .or_else(|| tag.attr("property"))
+                        .or_else(|| tag.attr("http-equiv"));
+                    let entry = match (key, tag.attr("content"), tag.attr("charset")) {
+                        (Some(key), Some(content), _) => Some((key, content)),
+                        (_, _, Some(charset)) => Some(("charset".to_string(), charset)),
+                        _ => None,
+                    };
+                    if let Some((key, content)) = entry {
+                        push(&mut summary.meta, (key, truncate(&content)));
+                    }
+                }
+                "a" | "area" | "link" => {
+                    let Some(href) = tag.attr("href") else {
+                        continue;
+                    };
+                    let link = Link {
+                        element: tag.name.clone(),
+                        href,
+                        rel: tag.attr("rel"),
+                        text: None,
+                    };
+                    if push(&mut summary.links, link) && tag.name == "a" && !tag.self_closing {
+                        anchor = Some((summary.links.len() - 1, String::new()));
+                    }
+                }
+                "form" => {
+                    let form = Form {
+                        action: tag.attr("action"),
+                        method: tag
+                            .attr("method")
+                            .map_or("get".to_string(), |m| m.to_ascii_lowercase()),
+                        fields: Vec::new(),
+                    };
+                    in_form = push(&mut summary.forms, form);
+                }
+                "input" | "select" | "textarea" | "button" if in_form => {
+                    let field_type = match tag.name.as_str() {
+                        "input" => tag.attr("type").unwrap_or_else(|| "text".to_string()),
+                        "button" => tag.attr("type").unwrap_or_else(|| "submit".to_string()),
+                        name => name.to_string(),
+                    };
+                    let field = Field {
+                        name: tag.attr("name"),
+                        field_type: field_type.to_ascii_lowercase(),
+                    };
+                    if let Some(form) = summary.forms.last_mut() {
+                        push(&mut form.fields, field);
+                    }
+                }
+                _ => {}
+            }
+            // Their contents aren't markup, so are skipped whole rather than
+            // scanned for tags
+            if matches!(tag.name.as_str(), "script" | "style" | "textarea") && !tag.self_closing {
+                let until = find_end_tag(page, pos, &tag.name);
+                if tag.name == "script" {
+                    let script = Script {
+                        src: tag.attr("src"),
+                        script_type: tag.attr("type"),
+                        inline_length: until - pos,
+                    };
+                    push(&mut summary.scripts, script);
+                }
+                pos = until;
+            }
+        }
+        if let Some((i, text)) = anchor {
+            summary.links[i].text = Some(clean(&(text + &page[pos..])));
+        }
+        summary
+    }
+}
+
+/// Add `item` to `list` unless it's full, returning whether it was
+fn push<T>(list: &mut Vec<T>, item: T) -> bool {
+    if list.len() >= MAX_ITEMS {
+        return false;
+    }
+    list.push(item);
+    true
+}
+
+/// A start or end tag
+struct Tag<'a> {
+    /// Lowercase
+    name: String,
+    closing: bool,
+    self_closing: bool,
+    /// Names (lowercase) and raw values of the attributes
+    attrs: Vec<(String, &'a str)>,
+}
+
+impl<'a> Tag<'a> {
+    /// Parse `tag`, from `<` to `>`
+    fn parse(tag: &'a str) -> Result<Self> {
+        let inner = tag
+            .strip_prefix('<')
+            .map(|t| t.strip_suffix('>').unwrap_or(t))
+            .unwrap_or_default();
+        let (closing, inner) = match inner.strip_prefix('/') {
+            Some(rest) => (true, rest),
+            None => (false, inner),
+        };
+        let name_end = inner
+            .find(|c: char| c.is_ascii_whitespace() || c == '/')
+            .unwrap_or(inner.len());
+        let name = &inner[..name_end];
+        if name.is_empty() || !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            bail!("Not a tag");
+        }
+        let mut attrs = Vec::new();
+        let mut rest = &inner[name_end..];
+        loop {
+            rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+            if rest.is_empty() {
+                break;
+            }
+            let attr_end = rest
+                .find(|c: char| c.is_ascii_whitespace() || c == '=' || c == '/')
+                .unwrap_or(rest.len());
+            let attr = rest[..attr_end].to_ascii_lowercase();
+            rest = rest[attr_end..].trim_start();
+            let value = match rest.strip_prefix('=') {
+                Some(after) => {
+                    let after = after.trim_start();
+                    let (value, remaining) = match after.chars().next() {
+                        Some(quote @ ('"' | '\'')) => {
+                            let body = &after[1..];
+                            let close = body.find(quote).unwrap_or(body.len());
+                            (&body[..close], body.get(close + 1..).unwrap_or_default())
+                        }
+                        _ => {
+                            let end = after
+                                .find(|c: char| c.is_ascii_whitespace())
+                                .unwrap_or(after.len());
+                            (&after[..end], &after[end..])
+                        }
+                    };
+                    rest = remaining;
+                    value
+                }
+                None => "",
+            };
+            attrs.push((attr, value));
+        }
+        Ok(Self {
+            name: name.to_ascii_lowercase(),
+            closing,
+            self_closing: inner.ends_with('/'),
+            attrs,
+        })
+    }
+
+    /// The decoded value of attribute `name`, if it's set
+    fn attr(&self, name: &str) -> Option<String> {
+        self.attrs
+            .iter()
+            .find(|(attr, _)| attr == name)
+            .map(|(_, value)| decode_entities(value))
+    }
+}
+
+/// The end of the tag starting at `start`, past any `>` in quoted
+/// attribute values
+fn tag_end(page: &str, start: usize) -> usize {
+    let mut quote = None;
+    for (i, c) in page[start..].char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return start + i + 1,
+            _ => {}
+        }
+    }
+    page.len()
+}
+
+/// Where the end tag of the `name` element whose contents start at `pos`
+/// is, ignoring case
+fn find_end_tag(page: &str, pos: usize, name: &str) -> usize {
+    page[pos..]
+        .match_indices("</")
+        .map(|(i, _)| pos + i)
+        .find(|&i| {
+            page.get(i + 2..i + 2 + name.len())
+                .is_some_and(|tag| tag.eq_ignore_ascii_case(name))
+        })
+        .unwrap_or(page.len())
+}
+
+/// `text` with tags removed, entities decoded and whitespace collapsed
+fn clean(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                stripped.push(' ');
+            }
+            c if !in_tag => stripped.push(c),
+            _ => {}
+        }
+    }
+    let collapsed = decode_entities(&stripped)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    truncate(&collapsed)
+}
+
+fn truncate(text: &str) -> String {
+    text.chars().take(MAX_TEXT_CHARS).collect()
+}
+
+/// `text` with character references and the common named entities decoded
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let entity = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .map(|end| &rest[1..end + 1]);
+        let character = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ => match entity.strip_prefix('#') {
+                Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16)
+                    .ok()
+                    .and_then(char::from_u32),
+                Some(decimal) => decimal.parse().ok().and_then(char::from_u32),
+                None => None,
+            },
+        });
+        match (entity, character) {
+            (Some(entity), Some(character)) => {
+                decoded.push(character);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<!doctype html>
+<html><head>
+  <meta charset="utf-8">
+  <title> Sign in &amp; <b>pay</b> </title>
+  <meta name="description" content="Your &quot;bank&quot;">
+  <meta property=og:title content='Bank'>
+  <link rel="stylesheet" href="/main.css">
+  <script src="https://cdn.example.com/app.js" type="module"></script>
+  <!-- <a href="/commented">not a link</a> -->
+</head><body>
+  <script>if (a < b) { document.write("<a href='/x'>") }</script>
+  <a href="/help?a=1&amp;b=2" rel=nofollow>Need <em>help</em>?</a>
+  <form action="/login" method="POST">
+    <input name="user"><input type="password" name="pass">
+    <select name="remember"></select><button>Go</button>
+  </form>
+  <input name="outside">
+  <a href="/last">Last
+"#;
+
+    #[tokio::test]
+    async fn summarizes_title_meta_links_forms_and_scripts() {
+        let summary = HtmlSummary::parse(PAGE);
+        assert_eq!(summary.title.as_deref(), Some("Sign in & pay"));
+        assert_eq!(
+            summary.meta,
+            [
+                ("charset".to_string(), "utf-8".to_string()),
+                ("description".to_string(), "Your \"bank\"".to_string()),
+                ("og:title".to_string(), "Bank".to_string()),
+            ]
+        );
+
+        let links: Vec<_> = summary
+            .links
+            .iter()
+            .map(|link| {
+                (
+                    link.element.as_str(),
+                    link.href.as_str(),
+                    link.text.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            links,
+            [
+                ("link", "/main.css", None),
+                ("a", "/help?a=1&b=2", Some("Need help?")),
+                ("a", "/last", Some("Last")),
+            ]
+        );
+        assert_eq!(summary.links[1].rel.as_deref(), Some("nofollow"));
+
+        assert_eq!(summary.forms.len(), 1);
+        let form = &summary.forms[0];
+        assert_eq!(
+            (form.action.as_deref(), form.method.as_str()),
+            (Some("/login"), "post")
+        );
+        let fields: Vec<_> = form
+            .fields
+            .iter()
+            .map(|f| (f.name.as_deref(), f.field_type.as_str()))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                (Some("user"), "text"),
+                (Some("pass"), "password"),
+                (Some("remember"), "select"),
+                (None, "submit"),
+            ]
+        );
+
+        assert_eq!(summary.scripts.len(), 2);
+        assert_eq!(summary.scripts[0].script_type.as_deref(), Some("module"));
+        assert_eq!(summary.scripts[1].src, None);
+        assert!(summary.scripts[1].inline_length > 0);
+
+        let (body, summarized) = summarize(full(Bytes::from(PAGE)), "text/html").await;
+        assert_eq!(*summarized.unwrap(), summary);
+        assert_eq!(body.collect().await.unwrap().to_bytes(), PAGE);
+        let (_, error) = summarize(full(Bytes::from(PAGE)), "application/json").await;
+        assert!(error.is_err());
+    }
+}
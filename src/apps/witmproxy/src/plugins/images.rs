@@ -0,0 +1,278 @@
+//! Image transcoding in content bodies.
+//!
+//! Unlike replacements, an image can't be transcoded as it streams: it's
+//! buffered whole, up to a size cap past which it's passed through as it
+//! is. It's then decoded, downscaled to fit within a maximum dimension and
+//! encoded again, optionally in another format, on the blocking thread
+//! pool. Only the pixels survive re-encoding, so EXIF and other metadata
+//! (locations, camera serial numbers, ...) are always dropped.
+//!
+//! JPEG, PNG and WebP images are transcoded. Others, notably GIFs, whose
+//! animation would be lost, are left alone.
+
+use std::io::Cursor;
+
+use anyhow::{Context, Result, anyhow};
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use http_body_util::combinators::UnsyncBoxBody;
+use image::DynamicImage;
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use serde::{Deserialize, Serialize};
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+
+use crate::http::sniff;
+use crate::http::utils::buffer;
+use crate::wasm::bindgen::witmproxy::plugin::capabilities::{
+    ImageFormat as WitImageFormat, ImageOptions as WitImageOptions,
+};
+
+/// Quality images are encoded with unless another is given
+pub const DEFAULT_QUALITY: u8 = 80;
+
+/// Largest image buffered for transcoding unless another cap is given
+pub const DEFAULT_MAX_IMAGE_BYTES: usize = 16 * 1024 * 1024;
+
+/// AVIF encoder speed, from 1 (slowest, smallest) to 10, traded towards
+/// speed as images are encoded while the client waits
+const AVIF_SPEED: u8 = 8;
+
+/// A format images are encoded in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImageFormat {
+    /// Lossless WebP
+    Webp,
+    Avif,
+    Jpeg,
+    Png,
+}
+
+impl ImageFormat {
+    /// The format's MIME type, ex: `image/webp`
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ImageFormat::Webp => "image/webp",
+            ImageFormat::Avif => "image/avif",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Png => "image/png",
+        }
+    }
+
+    /// The format of images with `content_type`, if they can be decoded
+    pub fn decodable(content_type: &str) -> Option<Self> {
+        match sniff::essence(content_type).as_str() {
+            "image/webp" => Some(ImageFormat::Webp),
+            "image/jpeg" | "image/jpg" | "image/pjpeg" => Some(ImageFormat::Jpeg),
+            "image/png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+
+    fn encode(self, image: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+        // Encoders only take 8-bit pixels, and JPEG has no alpha channel
+        let image = match (self, image.color().has_alpha()) {
+            (ImageFormat::Jpeg, _) | (_, false) => DynamicImage::ImageRgb8(image.to_rgb8()),
+            (_, true) => DynamicImage::ImageRgba8(image.to_rgba8()),
+        };
+        let mut out = Vec::new();
+        match self {
+            ImageFormat::Webp => image.write_with_encoder(WebPEncoder::new_lossless(&mut out)),
+            ImageFormat::Avif => image.write_with_encoder(AvifEncoder::new_with_speed_quality(
+                &mut out, AVIF_SPEED, quality,
+            )),
+            ImageFormat::Jpeg => {
+                image.write_with_encoder(JpegEncoder::new_with_quality(&mut out, quality))
+            }
+            ImageFormat::Png => image.write_with_encoder(PngEncoder::new(&mut out)),
+        }
+        .with_context(|| format!("Failed to encode {}", self.mime_type()))?;
+        Ok(out)
+    }
+}
+
+impl From<WitImageFormat> for ImageFormat {
+    fn from(format: WitImageFormat) -> Self {
+        match format {
+            WitImageFormat::Webp => ImageFormat::Webp,
+            WitImageFormat::Avif => ImageFormat::Avif,
+            WitImageFormat::Jpeg => ImageFormat::Jpeg,
+            WitImageFormat::Png => ImageFormat::Png,
+        }
+    }
+}
+
+/// How images are transcoded
+#[derive(Debug, Clone, Copy)]
+pub struct Transcode {
+    /// Images wider or taller than this many pixels are scaled down to fit,
+    /// keeping their aspect ratio
+    pub max_dimension: Option<u32>,
+    /// The format images are converted to, rather than their own
+    pub format: Option<ImageFormat>,
+    /// From 1 to 100, for lossy formats
+    pub quality: u8,
+    /// Images larger than this many bytes are passed through untouched
+    pub max_bytes: usize,
+}
+
+impl Default for Transcode {
+    fn default() -> Self {
+        Self {
+            max_dimension: None,
+            format: None,
+            quality: DEFAULT_QUALITY,
+            max_bytes: DEFAULT_MAX_IMAGE_BYTES,
+        }
+    }
+}
+
+impl From<WitImageOptions> for Transcode {
+    fn from(options: WitImageOptions) -> Self {
+        Self {
+            max_dimension: options.max_dimension,
+            format: options.format.map(ImageFormat::from),
+            quality: options.quality.map_or(DEFAULT_QUALITY, |q| q.clamp(1, 100)),
+            max_bytes: DEFAULT_MAX_IMAGE_BYTES,
+        }
+    }
+}
+
+impl Transcode {
+    /// Transcode the image in `bytes`, of format `from`, returning it
+    /// encoded in the format it's now in
+    pub fn transcode(&self, bytes: &[u8], from: ImageFormat) -> Result<(Vec<u8>, ImageFormat)> {
+        let mut image = image::ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()?
+            .decode()
+            .context("Failed to decode image")?;
+        if let Some(max) = self.max_dimension
+            && (image.width() > max || image.height() > max)
+        {
+            image = image.resize(max, max, FilterType::Lanczos3);
+        }
+        let format = self.format.unwrap_or(from);
+        Ok((format.encode(&image, self.quality)?, format))
+    }
+}
+
+/// Transcode the image of `content_type` in `body`, returning the body to
+/// send on along with the format it's now in, or why it's untouched.
+///
+/// The body is buffered whole, so the transcoded body's length is known.
+/// Bodies which aren't images that can be decoded, or are larger than
+/// `options.max_bytes`, are returned as they were.
+pub async fn apply(
+    body: UnsyncBoxBody<Bytes, ErrorCode>,
+    content_type: &str,
+    options: Transcode,
+) -> (UnsyncBoxBody<Bytes, ErrorCode>, Result<ImageFormat>) {
+    let Some(from) = ImageFormat::decodable(content_type) else {
+        let reason = anyhow!("Images of type {content_type:?} can't be transcoded");
+        return (body, Err(reason));
+    };
+    let bytes = match buffer(body, options.max_bytes).await {
+        Ok(bytes) => bytes,
+        Err((body, reason)) => return (body, Err(reason)),
+    };
+    let transcoded = {
+        let bytes = bytes.clone();
+        tokio::task::spawn_blocking(move || options.transcode(&bytes, from)).await
+    };
+    match transcoded {
+        Ok(Ok((image, format))) => (full(Bytes::from(image)), Ok(format)),
+        Ok(Err(e)) => (full(bytes), Err(e)),
+        Err(e) => (full(bytes), Err(anyhow!("Transcoding failed: {e}"))),
+    }
+}
+
+fn full(bytes: Bytes) -> UnsyncBoxBody<Bytes, ErrorCode> {
+    http_body_util::Full::new(bytes)
+        .map_err(|_| ErrorCode::InternalError(Some("conversion error".to_string())))
+        .boxed_unsync()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body::{Body, Frame};
+    use http_body_util::StreamBody;
+    use image::{ImageBuffer, Rgb};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = ImageBuffer::from_fn(width, height, |x, y| Rgb([x as u8, y as u8, 128]));
+        ImageFormat::Png
+            .encode(&DynamicImage::ImageRgb8(image), DEFAULT_QUALITY)
+            .unwrap()
+    }
+
+    fn chunked(chunks: Vec<Vec<u8>>) -> UnsyncBoxBody<Bytes, ErrorCode> {
+        let frames = chunks
+            .into_iter()
+            .map(|chunk| Ok::<_, ErrorCode>(Frame::data(Bytes::from(chunk))));
+        StreamBody::new(futures::stream::iter(frames)).boxed_unsync()
+    }
+
+    #[tokio::test]
+    async fn images_are_downscaled_and_converted() {
+        let image = png(400, 200);
+        let (head, tail) = image.split_at(image.len() / 2);
+        let options = Transcode {
+            max_dimension: Some(100),
+            format: Some(ImageFormat::Jpeg),
+            ..Default::default()
+        };
+        let (body, format) = apply(
+            chunked(vec![head.to_vec(), tail.to_vec()]),
+            "image/png",
+            options,
+        )
+        .await;
+        assert_eq!(format.unwrap(), ImageFormat::Jpeg);
+        // The whole body is known, so its length can be sent up front
+        let length = body.size_hint().exact().unwrap();
+        let bytes = body.collect().await.unwrap().to_bytes();
+        assert_eq!(length, bytes.len() as u64);
+        let decoded = image::load_from_memory(&bytes).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (100, 50));
+        assert_eq!(
+            image::guess_format(&bytes).unwrap(),
+            image::ImageFormat::Jpeg
+        );
+    }
+
+    #[tokio::test]
+    async fn oversized_and_undecodable_bodies_pass_through() {
+        let image = png(64, 64);
+        let options = Transcode {
+            max_bytes: image.len() / 2,
+            ..Default::default()
+        };
+        let chunks = image.chunks(16).map(<[u8]>::to_vec).collect();
+        let (body, format) = apply(chunked(chunks), "image/png", options).await;
+        assert!(format.is_err());
+        assert_eq!(body.collect().await.unwrap().to_bytes(), image);
+
+        let (body, format) = apply(
+            chunked(vec![b"GIF89a".to_vec()]),
+            "image/gif",
+            Transcode::default(),
+        )
+        .await;
+        assert!(format.is_err());
+        assert_eq!(body.collect().await.unwrap().to_bytes(), "GIF89a");
+
+        let (body, format) = apply(
+            chunked(vec![b"not a png".to_vec()]),
+            "image/png",
+            Transcode::default(),
+        )
+        .await;
+        assert!(format.is_err());
+        assert_eq!(body.collect().await.unwrap().to_bytes(), "not a png");
+    }
+}
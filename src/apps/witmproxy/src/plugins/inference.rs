@@ -0,0 +1,379 @@
+//! Machine learning models installed by the operator, which plugins granted
+//! `inference` run on content, ex: a toxicity classifier on page text or an
+//! NSFW classifier on images, without shipping weights in their components.
+//!
+//! Models are ONNX files configured as `[[plugins.models]]` tables, loaded
+//! with tract when the proxy starts and run on the blocking thread pool:
+//!
+//! ```toml
+//! [[plugins.models]]
+//! name = "nsfw"
+//! path = "/var/lib/witmproxy/models/nsfw.onnx"
+//! input = "image"
+//! image_size = 224
+//! ```
+//!
+//! `tensor` models are given the tensors plugins pass as they are, ex: token
+//! IDs from the plugin's own tokenizer. `image` models are given encoded
+//! images, which the host decodes, scales to `image_size` pixels square and
+//! converts to RGB values from 0 to 1 in NCHW order. A run is limited to
+//! the model's `max_batch` in the first dimension of each input and to
+//! [MAX_INPUT_VALUES] values; at most [MAX_CONCURRENT_RUNS] runs at once and
+//! a run taking longer than [RUN_TIMEOUT] fails.
+//!
+//! Running models needs the `inference` cargo feature, so other builds don't
+//! compile tract; without it, configuring a model fails at startup.
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use image::imageops::FilterType;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+#[cfg(feature = "inference")]
+use tract_onnx::prelude::{
+    DatumExt, Framework, InferenceModelExt, Tensor as TractTensor, TypedModel, TypedRunnableModel,
+};
+
+/// Runs of any model which can run at once, so plugins can't take every
+/// blocking thread
+pub const MAX_CONCURRENT_RUNS: usize = 4;
+
+/// Longest a run can take before it fails
+pub const RUN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Most values in the inputs of a run
+pub const MAX_INPUT_VALUES: usize = 4 * 1024 * 1024;
+
+/// Largest encoded image given to an image model
+pub const MAX_IMAGE_BYTES: usize = 16 * 1024 * 1024;
+
+fn default_image_size() -> u32 {
+    224
+}
+
+fn default_max_batch() -> usize {
+    8
+}
+
+/// What a model's plugins give it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelInput {
+    /// Tensors, passed as they are
+    #[default]
+    Tensor,
+    /// Encoded images, decoded and scaled by the host
+    Image,
+}
+
+/// A model the operator installed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelConfig {
+    /// Name plugins run the model by
+    pub name: String,
+    /// Path of the ONNX file
+    pub path: PathBuf,
+    #[serde(default)]
+    pub input: ModelInput,
+    /// Width and height images are scaled to, for image models (default: 224)
+    #[serde(default = "default_image_size")]
+    pub image_size: u32,
+    /// Most inputs in a batch (default: 8)
+    #[serde(default = "default_max_batch")]
+    pub max_batch: usize,
+}
+
+/// The values of a tensor, in row-major order
+#[derive(Debug, Clone, PartialEq)]
+pub enum TensorData {
+    Floats(Vec<f32>),
+    Integers(Vec<i64>),
+}
+
+/// A tensor given to or returned by a model
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tensor {
+    pub shape: Vec<usize>,
+    pub data: TensorData,
+}
+
+impl Tensor {
+    fn len(&self) -> usize {
+        match &self.data {
+            TensorData::Floats(values) => values.len(),
+            TensorData::Integers(values) => values.len(),
+        }
+    }
+
+    #[cfg(feature = "inference")]
+    fn into_tract(self) -> Result<TractTensor> {
+        Ok(match self.data {
+            TensorData::Floats(values) => TractTensor::from_shape(&self.shape, &values)?,
+            TensorData::Integers(values) => TractTensor::from_shape(&self.shape, &values)?,
+        })
+    }
+
+    /// The tensor a model output, with integers widened to `i64` and other
+    /// values converted to `f32`
+    #[cfg(feature = "inference")]
+    fn from_tract(tensor: &TractTensor) -> Result<Self> {
+        let data = if tensor.datum_type().is_integer() {
+            TensorData::Integers(tensor.cast_to::<i64>()?.as_slice::<i64>()?.to_vec())
+        } else {
+            TensorData::Floats(tensor.cast_to::<f32>()?.as_slice::<f32>()?.to_vec())
+        };
+        Ok(Self {
+            shape: tensor.shape().to_vec(),
+            data,
+        })
+    }
+}
+
+/// Check `inputs` fill their shapes and are within the limits of a run
+fn check_inputs(inputs: &[Tensor], max_batch: usize) -> Result<()> {
+    let mut values = 0;
+    for (i, input) in inputs.iter().enumerate() {
+        // Shapes come from plugins, so their product can overflow
+        let filled = input
+            .shape
+            .iter()
+            .try_fold(1usize, |product, dim| product.checked_mul(*dim));
+        if filled != Some(input.len()) {
+            bail!(
+                "Input {} has {} values, which don't fill shape {:?}",
+                i,
+                input.len(),
+                input.shape
+            );
+        }
+        if input.shape.first().is_some_and(|batch| *batch > max_batch) {
+            bail!("Batches can have at most {} inputs", max_batch);
+        }
+        values += input.len();
+    }
+    if values > MAX_INPUT_VALUES {
+        bail!("Inputs can have at most {} values", MAX_INPUT_VALUES);
+    }
+    Ok(())
+}
+
+/// The encoded image in `bytes` as a `[1, 3, size, size]` tensor of RGB
+/// values from 0 to 1
+pub fn image_tensor(bytes: &[u8], size: u32) -> Result<Tensor> {
+    if bytes.len() > MAX_IMAGE_BYTES {
+        bail!("Images can be at most {} bytes", MAX_IMAGE_BYTES);
+    }
+    let image = image::ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .decode()
+        .context("Failed to decode image")?
+        .resize_exact(size, size, FilterType::Triangle)
+        .to_rgb8();
+    let plane = (size * size) as usize;
+    let mut values = vec![0.0; 3 * plane];
+    for (i, pixel) in image.pixels().enumerate() {
+        for (channel, value) in pixel.0.iter().enumerate() {
+            values[channel * plane + i] = f32::from(*value) / 255.0;
+        }
+    }
+    Ok(Tensor {
+        shape: vec![1, 3, size as usize, size as usize],
+        data: TensorData::Floats(values),
+    })
+}
+
+struct Model {
+    config: ModelConfig,
+    #[cfg(feature = "inference")]
+    plan: TypedRunnableModel<TypedModel>,
+}
+
+impl Model {
+    #[cfg(feature = "inference")]
+    fn load(config: ModelConfig) -> Result<Self> {
+        let mut model = tract_onnx::onnx()
+            .model_for_path(&config.path)
+            .with_context(|| format!("Failed to load model {}", config.path.display()))?;
+        if config.input == ModelInput::Image {
+            let size = config.image_size as usize;
+            model = model.with_input_fact(0, f32::fact([1, 3, size, size]).into())?;
+        }
+        let plan = model
+            .into_optimized()?
+            .into_runnable()
+            .with_context(|| format!("Failed to prepare model {}", config.name))?;
+        Ok(Self { config, plan })
+    }
+
+    #[cfg(not(feature = "inference"))]
+    fn load(config: ModelConfig) -> Result<Self> {
+        bail!(
+            "Model {} can't be loaded: witmproxy was built without the `inference` feature",
+            config.name
+        )
+    }
+
+    #[cfg(feature = "inference")]
+    fn run(&self, inputs: Vec<Tensor>) -> Result<Vec<Tensor>> {
+        let inputs = inputs
+            .into_iter()
+            .map(|input| Ok(input.into_tract()?.into()))
+            .collect::<Result<_>>()?;
+        let outputs = self.plan.run(inputs)?;
+        outputs
+            .iter()
+            .map(|output| Tensor::from_tract(output))
+            .collect()
+    }
+
+    #[cfg(not(feature = "inference"))]
+    fn run(&self, _inputs: Vec<Tensor>) -> Result<Vec<Tensor>> {
+        bail!(
+            "Model {} can't run without the `inference` feature",
+            self.config.name
+        )
+    }
+}
+
+/// The installed models. Cheap to clone; all clones share the same models
+/// and limit on concurrent runs.
+#[derive(Clone)]
+pub struct Models {
+    models: Arc<HashMap<String, Arc<Model>>>,
+    permits: Arc<Semaphore>,
+}
+
+impl Default for Models {
+    fn default() -> Self {
+        Self {
+            models: Arc::default(),
+            permits: Arc::new(Semaphore::new(MAX_CONCURRENT_RUNS)),
+        }
+    }
+}
+
+impl Models {
+    /// Load the models in `configs`
+    pub fn load(configs: &[ModelConfig]) -> Result<Self> {
+        let mut models = HashMap::new();
+        for config in configs {
+            if models.contains_key(&config.name) {
+                bail!("Model {} is installed twice", config.name);
+            }
+            let model = Model::load(config.clone())?;
+            models.insert(config.name.clone(), Arc::new(model));
+        }
+        Ok(Self {
+            models: Arc::new(models),
+            ..Self::default()
+        })
+    }
+
+    /// Names of the installed models, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.models.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    fn model(&self, name: &str) -> Result<Arc<Model>> {
+        self.models
+            .get(name)
+            .cloned()
+            .with_context(|| format!("No model named {name:?} is installed"))
+    }
+
+    /// Run model `name` on `inputs`, one for each of its inputs
+    pub async fn run(&self, name: &str, inputs: Vec<Tensor>) -> Result<Vec<Tensor>> {
+        let model = self.model(name)?;
+        if model.config.input != ModelInput::Tensor {
+            bail!("Model {name:?} takes images");
+        }
+        check_inputs(&inputs, model.config.max_batch)?;
+        self.spawn(model, move |model| model.run(inputs)).await
+    }
+
+    /// Run image model `name` on the encoded image in `bytes`
+    pub async fn run_image(&self, name: &str, bytes: Vec<u8>) -> Result<Vec<Tensor>> {
+        let model = self.model(name)?;
+        if model.config.input != ModelInput::Image {
+            bail!("Model {name:?} doesn't take images");
+        }
+        self.spawn(model, move |model| {
+            let input = image_tensor(&bytes, model.config.image_size)?;
+            model.run(vec![input])
+        })
+        .await
+    }
+
+    /// Run `f` on the blocking thread pool, failing after [RUN_TIMEOUT]. A
+    /// run which times out keeps its permit until it's done, so runaway
+    /// runs can't pile up.
+    async fn spawn(
+        &self,
+        model: Arc<Model>,
+        f: impl FnOnce(&Model) -> Result<Vec<Tensor>> + Send + 'static,
+    ) -> Result<Vec<Tensor>> {
+        let permit = self.permits.clone().acquire_owned().await?;
+        let name = model.config.name.clone();
+        let run = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            f(&model)
+        });
+        match tokio::time::timeout(RUN_TIMEOUT, run).await {
+            Ok(outputs) => outputs.context("Model run panicked")?,
+            Err(_) => bail!(
+                "Model {name:?} took longer than {}ms",
+                RUN_TIMEOUT.as_millis()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, Rgb, RgbImage};
+
+    #[tokio::test]
+    async fn inputs_are_checked_and_images_become_nchw_tensors() {
+        let floats = |shape: Vec<usize>, n| Tensor {
+            shape,
+            data: TensorData::Floats(vec![0.5; n]),
+        };
+        assert!(check_inputs(&[floats(vec![2, 3], 6)], 8).is_ok());
+        assert!(check_inputs(&[floats(vec![2, 3], 5)], 8).is_err());
+        assert!(check_inputs(&[floats(vec![9, 1], 9)], 8).is_err());
+        let overflowing = vec![1, usize::MAX, 2];
+        assert!(check_inputs(&[floats(overflowing, 0)], 8).is_err());
+        assert!(
+            check_inputs(
+                &[floats(vec![1, MAX_INPUT_VALUES + 1], MAX_INPUT_VALUES + 1)],
+                8
+            )
+            .is_err()
+        );
+
+        let mut png = Vec::new();
+        RgbImage::from_pixel(4, 2, Rgb([255, 0, 51]))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let tensor = image_tensor(&png, 2).unwrap();
+        assert_eq!(tensor.shape, [1, 3, 2, 2]);
+        let TensorData::Floats(values) = tensor.data else {
+            panic!("image tensors are floats");
+        };
+        assert_eq!(values[..4], [1.0; 4]);
+        assert_eq!(values[4..8], [0.0; 4]);
+        assert_eq!(values[8], 0.2);
+        assert!(image_tensor(b"not an image", 2).is_err());
+
+        let error = Models::default().run("nsfw", Vec::new()).await.unwrap_err();
+        assert!(error.to_string().contains("No model named"));
+    }
+}
@@ -0,0 +1,282 @@
+//! Attestation of the plugins the proxy runs, so a component swapped in the
+//! database, ex: by another user of a shared machine, is refused rather than
+//! run.
+//!
+//! When a plugin is installed or loaded at startup, the registry records an
+//! [Attestation]: the SHA-256 of the component's bytes, whose signature was
+//! verified against the plugin's public key, and of the code compiled from
+//! them. Every [VERIFY_INTERVAL] the registry re-checks both in memory, and
+//! that the database still holds the same bytes and public key with a valid
+//! signature. A plugin failing a check has its compiled component dropped,
+//! so it isn't run again until it's reinstalled, and the failure is logged,
+//! added to the audit log and reported by `/api/status`.
+//!
+//! A plugin's public key is read from the same row as its component, so
+//! whoever can replace one can replace both with a component they signed.
+//! The keys listed in `plugins.trusted_keys` anchor verification outside the
+//! database: when any are configured, plugins signed with another key are
+//! refused as they're installed, loaded or re-verified.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use tokio::sync::RwLock;
+
+use crate::plugins::WitmPlugin;
+use crate::plugins::registry::PluginRegistry;
+
+/// How often the running proxy re-verifies the plugins it runs
+pub const VERIFY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Check `component` is signed by `public_key`
+pub fn verify_signature(public_key: &[u8], component: &[u8]) -> Result<()> {
+    let public_key = wasmsign2::PublicKey::from_bytes(public_key)
+        .map_err(|e| anyhow::anyhow!("Failed to parse public key: {}", e))?;
+    public_key
+        .verify(&mut std::io::Cursor::new(component), None)
+        .map_err(|e| anyhow::anyhow!("Signature verification failed: {}", e))
+}
+
+/// The public keys plugins have to be signed with. Any key is trusted when
+/// none are configured.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedKeys {
+    keys: Vec<Vec<u8>>,
+}
+
+impl TrustedKeys {
+    /// Trust the hex-encoded public `keys`
+    pub fn new(keys: &[String]) -> Result<Self> {
+        let keys = keys
+            .iter()
+            .map(|key| {
+                let bytes = hex::decode(key.trim())
+                    .with_context(|| format!("Invalid trusted key {key:?}: not hex"))?;
+                wasmsign2::PublicKey::from_bytes(&bytes)
+                    .map_err(|e| anyhow::anyhow!("Invalid trusted key {key:?}: {}", e))?;
+                Ok(bytes)
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { keys })
+    }
+
+    /// Check plugins signed with `public_key` may be run
+    pub fn check(&self, public_key: &[u8]) -> Result<()> {
+        if self.keys.is_empty() || self.keys.iter().any(|key| key == public_key) {
+            return Ok(());
+        }
+        bail!(
+            "The plugin's public key {} isn't trusted",
+            hex::encode(public_key)
+        )
+    }
+}
+
+fn digest(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// What a plugin's component was when it was loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attestation {
+    /// SHA-256 of the component's bytes, as hex
+    pub component: String,
+    /// SHA-256 of the code compiled from them, if compiled
+    pub compiled: Option<String>,
+}
+
+impl Attestation {
+    /// Attest `plugin`, whose signature was verified as it was installed
+    pub fn of(plugin: &WitmPlugin) -> Result<Self> {
+        let compiled = match &plugin.component {
+            Some(component) => Some(digest(&component.serialize()?)),
+            None => None,
+        };
+        Ok(Self {
+            component: digest(&plugin.component_bytes),
+            compiled,
+        })
+    }
+
+    /// Attest `plugin` after verifying it's signed with a `trusted` key,
+    /// for plugins loaded from the database
+    pub fn verified(plugin: &WitmPlugin, trusted: &TrustedKeys) -> Result<Self> {
+        trusted.check(&plugin.publickey)?;
+        verify_signature(&plugin.publickey, &plugin.component_bytes)?;
+        Self::of(plugin)
+    }
+
+    /// Check `plugin` and its row in the database still match the
+    /// attestation, signed with a `trusted` key
+    pub async fn verify(
+        &self,
+        plugin: &WitmPlugin,
+        pool: &SqlitePool,
+        trusted: &TrustedKeys,
+    ) -> Result<()> {
+        if digest(&plugin.component_bytes) != self.component {
+            bail!("The component in memory changed");
+        }
+        if let Some(component) = &plugin.component
+            && Some(digest(&component.serialize()?)) != self.compiled
+        {
+            bail!("The code compiled from the component changed");
+        }
+        let (publickey, stored): (Vec<u8>, Vec<u8>) = sqlx::query_as(
+            "SELECT publickey, component FROM plugins WHERE namespace = ? AND name = ?",
+        )
+        .bind(&plugin.namespace)
+        .bind(&plugin.name)
+        .fetch_optional(pool)
+        .await?
+        .context("The plugin is no longer in the database")?;
+        if publickey != plugin.publickey {
+            bail!("The public key in the database changed");
+        }
+        trusted.check(&publickey)?;
+        if digest(&stored) != self.component {
+            bail!("The component in the database changed");
+        }
+        verify_signature(&publickey, &stored)
+            .context("The component in the database isn't signed by the plugin's key")
+    }
+}
+
+/// Re-verify the plugins in `registry` every `interval` until the task is
+/// dropped, refusing those which fail
+pub async fn verify_loop(registry: Arc<RwLock<PluginRegistry>>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    // Plugins were just attested as they were loaded
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let failures = registry.read().await.verify_integrity().await;
+        if failures.is_empty() {
+            continue;
+        }
+        let mut registry = registry.write().await;
+        for (id, reason) in failures {
+            registry.refuse(&id, &reason).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Db, Insert};
+
+    /// An empty module signed with a new key, and the key
+    fn signed() -> (Vec<u8>, wasmsign2::KeyPair) {
+        let key = wasmsign2::KeyPair::generate();
+        let module = wasmsign2::Module::deserialize(&mut &b"\0asm\x01\0\0\0"[..]).unwrap();
+        let mut signed = Vec::new();
+        key.sk
+            .sign(module, None)
+            .unwrap()
+            .serialize(&mut signed)
+            .unwrap();
+        (signed, key)
+    }
+
+    async fn installed(db: &mut Db, component: Vec<u8>, key: &wasmsign2::KeyPair) -> WitmPlugin {
+        let plugin: WitmPlugin = serde_json::from_value(serde_json::json!({
+            "namespace": "@test",
+            "name": "attested",
+            "version": "0.0.1",
+            "author": "",
+            "description": "",
+            "license": "",
+            "url": "",
+            "publickey": key.pk.to_bytes(),
+            "enabled": true,
+            "capabilities": [],
+            "metadata": {},
+            "configuration": [],
+            "component_bytes": component,
+        }))
+        .unwrap();
+        plugin.insert(db).await.unwrap();
+        plugin
+    }
+
+    #[tokio::test]
+    async fn tampering_in_the_database_or_memory_is_detected() {
+        let mut db = Db::in_memory().await.unwrap();
+        db.migrate().await.unwrap();
+        let (signed, key) = signed();
+        let mut plugin = installed(&mut db, signed, &key).await;
+        let trusted = TrustedKeys::default();
+
+        let attestation = Attestation::verified(&plugin, &trusted).unwrap();
+        assert_eq!(attestation.compiled, None);
+        attestation
+            .verify(&plugin, &db.pool, &trusted)
+            .await
+            .unwrap();
+
+        plugin.component_bytes.push(0);
+        assert!(
+            attestation
+                .verify(&plugin, &db.pool, &trusted)
+                .await
+                .is_err()
+        );
+        assert!(Attestation::verified(&plugin, &trusted).is_err());
+
+        sqlx::query("UPDATE plugins SET component = ? WHERE name = 'attested'")
+            .bind(&plugin.component_bytes)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        plugin.component_bytes.pop();
+        let error = attestation
+            .verify(&plugin, &db.pool, &trusted)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "The component in the database changed");
+    }
+
+    #[tokio::test]
+    async fn components_resigned_in_the_database_are_refused_by_trusted_keys() {
+        let mut db = Db::in_memory().await.unwrap();
+        db.migrate().await.unwrap();
+        let (signed, key) = signed();
+        let plugin = installed(&mut db, signed, &key).await;
+        let trusted = TrustedKeys::new(&[hex::encode(key.pk.to_bytes())]).unwrap();
+        let attestation = Attestation::verified(&plugin, &trusted).unwrap();
+
+        // Both the component and the key replaced, by someone with their own
+        let (forged, forger) = signed();
+        sqlx::query("UPDATE plugins SET component = ?, publickey = ? WHERE name = 'attested'")
+            .bind(&forged)
+            .bind(forger.pk.to_bytes())
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert!(
+            attestation
+                .verify(&plugin, &db.pool, &trusted)
+                .await
+                .is_err()
+        );
+
+        // Loaded again, ex: as the proxy restarts, the row is consistent
+        // and only the trusted keys tell it apart
+        let mut loaded = plugin;
+        loaded.component_bytes = forged;
+        loaded.publickey = forger.pk.to_bytes();
+        assert!(Attestation::verified(&loaded, &TrustedKeys::default()).is_ok());
+        let error = Attestation::verified(&loaded, &trusted).unwrap_err();
+        assert!(error.to_string().contains("isn't trusted"), "{error}");
+    }
+
+    #[test]
+    fn trusted_keys_are_checked_to_be_keys() {
+        assert!(TrustedKeys::new(&["not hex".to_string()]).is_err());
+        assert!(TrustedKeys::new(&["00ff".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,261 @@
+//! Static checks run on a plugin's manifest when it's registered.
+//!
+//! Manifests which can never work are rejected: a plugin with no event
+//! capability is never invoked, and a scope expression which doesn't compile
+//! against the variables its event provides never matches. Manifests which
+//! work but are likely mistakes, such as scopes matching every event or
+//! capabilities the component never uses, are reported as warnings.
+
+use std::sync::OnceLock;
+
+use anyhow::{Result, bail};
+use cel_cxx::{Env, EnvBuilder};
+use wasmtime_wasi_http::p3::Request as WasiRequest;
+
+use crate::events::{
+    Event, connect::Connect, content::InboundContent, mqtt::MqttMessage, raw_stream::RawStream,
+    response::ContextualResponse, timer::TimerEvent,
+};
+use crate::plugins::WitmPlugin;
+use crate::plugins::capabilities::Capability;
+use crate::plugins::catalog;
+use crate::plugins::cel::CelTime;
+use crate::wasm::bindgen::witmproxy::plugin::capabilities::{CapabilityKind, EventKind};
+
+/// A likely mistake in a plugin's manifest which doesn't prevent it loading
+#[derive(Debug, Clone, PartialEq)]
+pub enum LintWarning {
+    /// An event scope which matches every event of its kind
+    BroadScope(EventKind),
+    /// A capability the component never requests from its capability provider
+    UnusedCapability(CapabilityKind),
+    /// A catalog image tag which is neither a data URI nor a URL, by key
+    InvalidImage(String),
+}
+
+impl std::fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LintWarning::BroadScope(kind) => {
+                write!(f, "scope for {} events matches every event", kind)
+            }
+            LintWarning::UnusedCapability(kind) => write!(
+                f,
+                "capability {} is requested but never used by the component",
+                kind
+            ),
+            LintWarning::InvalidImage(key) => write!(
+                f,
+                "metadata {} is neither an image data URI nor an http(s) URL, so isn't listed",
+                key
+            ),
+        }
+    }
+}
+
+/// Check `plugin`'s manifest against its component, returning any warnings,
+/// or an error if the manifest can't work
+pub fn lint(plugin: &WitmPlugin, component_bytes: &[u8]) -> Result<Vec<LintWarning>> {
+    let mut warnings = Vec::new();
+    let mut handles_events = false;
+
+    for capability in &plugin.capabilities {
+        match capability.inner.kind {
+            CapabilityKind::HandleEvent(kind) => {
+                handles_events = true;
+                let expression = &capability.inner.scope.expression;
+                if let Err(e) = event_env(kind).compile(expression) {
+                    bail!(
+                        "Plugin {} scope for {} events is invalid: {}\n  expression: {}",
+                        plugin.id(),
+                        kind,
+                        e,
+                        expression
+                    );
+                }
+                if expression.trim() == "true" {
+                    warnings.push(LintWarning::BroadScope(kind));
+                }
+            }
+            kind => {
+                if !is_used(capability, component_bytes) {
+                    warnings.push(LintWarning::UnusedCapability(kind));
+                }
+            }
+        }
+    }
+
+    if !handles_events {
+        bail!(
+            "Plugin {} declares no event capabilities, so would never be invoked",
+            plugin.id()
+        );
+    }
+
+    warnings.extend(
+        catalog::invalid_images(&plugin.metadata)
+            .into_iter()
+            .map(LintWarning::InvalidImage),
+    );
+    Ok(warnings)
+}
+
+/// Whether the component imports the capability provider method returning
+/// `capability`. Components only lower the imports their code calls, so a
+/// missing import means the capability is never requested.
+fn is_used(capability: &Capability, component_bytes: &[u8]) -> bool {
+    let method = match capability.inner.kind {
+        CapabilityKind::Logger => "logger",
+        CapabilityKind::Annotator => "annotator",
+        CapabilityKind::LocalStorage => "local-storage",
+        CapabilityKind::Clock => "clock",
+        CapabilityKind::FlowReader => "flow-reader",
+        CapabilityKind::Jwt => "jwt",
+        CapabilityKind::Graphql => "graphql",
+        CapabilityKind::Messaging => "messaging",
+        CapabilityKind::Regex => "regex",
+        CapabilityKind::Inference => "inference",
+        CapabilityKind::Html => "html",
+        CapabilityKind::HandleEvent(_) => return true,
+    };
+    let import = format!("[method]capability-provider.{}", method);
+    component_bytes
+        .windows(import.len())
+        .any(|window| window == import.as_bytes())
+}
+
+/// A CEL environment declaring only the variables bound for `kind` events,
+/// so scopes referring to another event's variables fail to compile
+pub(crate) fn event_env(kind: EventKind) -> &'static Env<'static> {
+    fn build(register: fn(EnvBuilder<'static>) -> Result<EnvBuilder<'static>>) -> Env<'static> {
+        register(Env::builder().with_standard(true))
+            .and_then(CelTime::register_cel_env)
+            .and_then(|builder| Ok(builder.build()?))
+            .expect("Failed to build the CEL environment")
+    }
+
+    static CONNECT: OnceLock<Env<'static>> = OnceLock::new();
+    static REQUEST: OnceLock<Env<'static>> = OnceLock::new();
+    static RESPONSE: OnceLock<Env<'static>> = OnceLock::new();
+    static INBOUND_CONTENT: OnceLock<Env<'static>> = OnceLock::new();
+    static TIMER: OnceLock<Env<'static>> = OnceLock::new();
+    static RAW_STREAM: OnceLock<Env<'static>> = OnceLock::new();
+    static MQTT_MESSAGE: OnceLock<Env<'static>> = OnceLock::new();
+
+    match kind {
+        EventKind::Connect => CONNECT.get_or_init(|| build(Connect::register_cel_env)),
+        EventKind::Request => REQUEST.get_or_init(|| build(WasiRequest::register_cel_env)),
+        EventKind::Response => RESPONSE.get_or_init(|| {
+            build(|env| {
+                WasiRequest::register_cel_env(env).and_then(ContextualResponse::register_cel_env)
+            })
+        }),
+        EventKind::InboundContent => {
+            INBOUND_CONTENT.get_or_init(|| build(InboundContent::register_cel_env))
+        }
+        EventKind::Timer => TIMER.get_or_init(|| build(TimerEvent::register_cel_env)),
+        EventKind::RawStream => RAW_STREAM.get_or_init(|| build(RawStream::register_cel_env)),
+        EventKind::MqttMessage => MQTT_MESSAGE.get_or_init(|| build(MqttMessage::register_cel_env)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasm::bindgen::witmproxy::plugin::capabilities::{
+        Capability as WitCapability, CapabilityScope,
+    };
+
+    fn plugin(capabilities: &[(CapabilityKind, &str)]) -> WitmPlugin {
+        WitmPlugin {
+            namespace: "@test".to_string(),
+            name: "lint".to_string(),
+            version: "0.0.1".to_string(),
+            author: String::new(),
+            description: String::new(),
+            license: String::new(),
+            url: String::new(),
+            publickey: Vec::new(),
+            enabled: true,
+            capabilities: capabilities
+                .iter()
+                .map(|(kind, expression)| Capability {
+                    inner: WitCapability {
+                        kind: *kind,
+                        scope: CapabilityScope {
+                            expression: expression.to_string(),
+                        },
+                    },
+                    granted: false,
+                    quota: Default::default(),
+                    cel: None,
+                })
+                .collect(),
+            metadata: Default::default(),
+            configuration: Vec::new(),
+            configuration_schema: Vec::new(),
+            component: None,
+            component_bytes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn scopes_are_checked_against_their_event() {
+        let valid = plugin(&[
+            (
+                CapabilityKind::HandleEvent(EventKind::Request),
+                "request.host() == 'example.com'",
+            ),
+            (
+                CapabilityKind::HandleEvent(EventKind::Response),
+                "request.path() == '/' && response.status() == 200",
+            ),
+            (
+                CapabilityKind::HandleEvent(EventKind::Timer),
+                "time.matches_cron('0 * * * *')",
+            ),
+        ]);
+        assert_eq!(lint(&valid, &[]).unwrap(), vec![]);
+
+        let wrong_event = plugin(&[(
+            CapabilityKind::HandleEvent(EventKind::Request),
+            "response.status() == 200",
+        )]);
+        assert!(lint(&wrong_event, &[]).is_err());
+    }
+
+    #[test]
+    fn manifests_must_handle_events() {
+        let no_events = plugin(&[(CapabilityKind::Logger, "true")]);
+        assert!(lint(&no_events, b"[method]capability-provider.logger").is_err());
+    }
+
+    #[test]
+    fn likely_mistakes_are_warnings() {
+        let broad = plugin(&[
+            (CapabilityKind::HandleEvent(EventKind::Request), " true "),
+            (CapabilityKind::Logger, "true"),
+            (CapabilityKind::Clock, "true"),
+        ]);
+        let component = b"\0asm...[async-lower][method]capability-provider.logger...";
+        assert_eq!(
+            lint(&broad, component).unwrap(),
+            vec![
+                LintWarning::BroadScope(EventKind::Request),
+                LintWarning::UnusedCapability(CapabilityKind::Clock),
+            ]
+        );
+
+        let mut unlisted = plugin(&[(
+            CapabilityKind::HandleEvent(EventKind::Request),
+            "request.host() == 'example.com'",
+        )]);
+        unlisted
+            .metadata
+            .insert("icon".to_string(), "./icon.png".to_string());
+        assert_eq!(
+            lint(&unlisted, &[]).unwrap(),
+            vec![LintWarning::InvalidImage("icon".to_string())]
+        );
+    }
+}
@@ -13,18 +13,39 @@ use crate::{
     Runtime,
     db::{Db, Insert},
     plugins::capabilities::Capability,
+    proxy::flow_trace,
     wasm::{
-        Host,
+        Host, Profile,
         bindgen::{
-            Plugin, PluginManifest, UserInput, exports::witmproxy::plugin::witm_plugin::Tag,
+            InputSchema, Plugin, PluginManifest, UserInput,
+            exports::witmproxy::plugin::witm_plugin::Tag,
             witmproxy::plugin::capabilities::Capability as WitCapability,
         },
     },
 };
 
+pub mod bundle;
+pub mod bus;
 pub mod capabilities;
+pub mod catalog;
 pub mod cel;
+pub mod differential;
+pub mod dry_run;
+pub mod exercise;
+pub mod html;
+pub mod images;
+pub mod inference;
+pub mod integrity;
+pub mod lint;
+pub mod pattern;
+pub mod posture;
+pub mod quota;
+pub mod redaction;
 pub mod registry;
+pub mod replace;
+pub mod settings;
+pub mod storage_snapshots;
+pub mod transform;
 
 #[cfg(test)]
 mod tenant_tests;
@@ -46,6 +67,9 @@ pub struct WitmPlugin {
     pub metadata: HashMap<String, String>,
     // User-supplied configuration values passed to plugin on each event
     pub configuration: Vec<UserInput>,
+    // Settings the plugin accepts, as declared in its manifest
+    #[serde(default)]
+    pub configuration_schema: Vec<InputSchema>,
     // Compiled WASM component implementing the Plugin interface
     #[serde(skip)]
     pub component: Option<Component>,
@@ -63,6 +87,16 @@ impl WitmPlugin {
         Self::make_id(&self.namespace, &self.name)
     }
 
+    /// The runtime profile the plugin's metadata opts into
+    pub fn profile(&self) -> Profile {
+        Profile::from_metadata(&self.metadata)
+    }
+
+    /// How the plugin's metadata asks to be shown in a catalog
+    pub fn listing(&self) -> catalog::Listing {
+        catalog::Listing::from_metadata(&self.metadata)
+    }
+
     pub fn with_component(mut self, component: Component, component_bytes: Vec<u8>) -> Self {
         self.component = Some(component);
         self.component_bytes = component_bytes;
@@ -116,7 +150,7 @@ impl WitmPlugin {
         let mut plugin = WitmPlugin::from(guest_result).with_component(component, component_bytes);
         let capabilities = query(
             "
-            SELECT capability, config, granted
+            SELECT capability, config, granted, quota
             FROM plugin_capabilities
             WHERE namespace = ? AND name = ?
             ",
@@ -130,12 +164,23 @@ impl WitmPlugin {
             let config_str: String = row.try_get("config")?;
             let granted_flag: bool = row.try_get("granted")?;
             let config: WitCapability = serde_json::from_str(&config_str)?;
+            let quota_str: String = row.try_get("quota")?;
             let capability = Capability {
                 inner: config,
                 granted: granted_flag,
+                quota: serde_json::from_str(&quota_str)?,
                 cel: None,
             };
-            plugin.capabilities.push(capability);
+            // The stored capability, with the user's choices, replaces the
+            // one the manifest requests
+            match plugin
+                .capabilities
+                .iter_mut()
+                .find(|existing| existing.inner.kind == capability.inner.kind)
+            {
+                Some(existing) => *existing = capability,
+                None => plugin.capabilities.push(capability),
+            }
         }
 
         let config_rows = query(
@@ -211,19 +256,32 @@ impl WitmPlugin {
             .iter()
             // Have we been granted the associated event capability?
             .filter(|cap| cap.inner.kind == event.capability())
-            .filter(|cap| cap.granted)
+            .filter(|cap| cap.is_active())
             .filter_map(|cap| {
                 let program: &cel_cxx::Program<'_> = cap.cel.as_ref()?;
-                Some(program)
+                Some((cap, program))
             })
             // Are we interested in and permitted to handle this event?
-            .any(|program| {
+            .any(|(cap, program)| {
                 let activation = match event.bind_cel_activation(Activation::new()) {
                     Some(a) => a,
                     None => return false,
                 };
 
-                match program.evaluate(activation) {
+                let result = program.evaluate(activation);
+                flow_trace::record("cel", || {
+                    let result = match &result {
+                        Ok(value) => value.to_string(),
+                        Err(e) => format!("error: {e}"),
+                    };
+                    format!(
+                        "{} `{}` = {}",
+                        self.id(),
+                        cap.inner.scope.expression,
+                        result
+                    )
+                });
+                match result {
                     Ok(cel_cxx::Value::Bool(true)) => true,
                     Ok(_) => false,
                     Err(e) => {
@@ -251,6 +309,7 @@ impl From<PluginManifest> for WitmPlugin {
             .map(|c| Capability {
                 inner: c,
                 granted: true,
+                quota: Default::default(),
                 cel: None,
             })
             .collect::<Vec<Capability>>();
@@ -266,6 +325,7 @@ impl From<PluginManifest> for WitmPlugin {
             publickey: manifest.publickey,
             enabled: true,
             configuration: vec![],
+            configuration_schema: manifest.configuration,
             component: None,
             component_bytes: vec![],
             metadata,
@@ -276,7 +336,7 @@ impl From<PluginManifest> for WitmPlugin {
 
 // Schema:
 // `plugins` (namespace, name, version, author, description, license, url, publickey, component)
-// `plugin_capabilities` (namespace, name, capability, granted)
+// `plugin_capabilities` (namespace, name, capability, config, granted, quota)
 // `plugin_metadata` (namespace, name, key, value)
 
 impl Insert for WitmPlugin {
@@ -303,7 +363,7 @@ impl Insert for WitmPlugin {
         .execute(&mut *tx)
         .await?;
 
-        let mut plugin_capabilities: Vec<(String, String, String, String, bool)> = vec![];
+        let mut plugin_capabilities: Vec<(String, String, String, String, bool, String)> = vec![];
 
         self.capabilities.iter().for_each(|cap| {
             let cap_str = cap.inner.kind.to_string();
@@ -317,27 +377,36 @@ impl Insert for WitmPlugin {
                     "".to_string()
                 }
             };
+            let quota_str = serde_json::to_string(&cap.quota).unwrap_or_else(|e| {
+                error!(
+                    "Failed to serialize capability quota for plugin {}/{} capability {}: {}",
+                    self.namespace, self.name, cap_str, e
+                );
+                "{}".to_string()
+            });
             plugin_capabilities.push((
                 self.namespace.clone(),
                 self.name.clone(),
                 cap_str,
                 config_str,
                 cap.granted,
+                quota_str,
             ));
         });
 
         let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
-            "INSERT INTO plugin_capabilities (namespace, name, capability, config, granted) ",
+            "INSERT INTO plugin_capabilities (namespace, name, capability, config, granted, quota) ",
         );
 
         query_builder.push_values(
             &plugin_capabilities,
-            |mut b, (namespace, name, capability, config, granted)| {
+            |mut b, (namespace, name, capability, config, granted, quota)| {
                 b.push_bind(namespace)
                     .push_bind(name)
                     .push_bind(capability)
                     .push_bind(config)
-                    .push_bind(granted);
+                    .push_bind(granted)
+                    .push_bind(quota);
             },
         );
 
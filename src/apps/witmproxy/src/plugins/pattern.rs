@@ -0,0 +1,167 @@
+//! Regular expressions compiled by the host for plugins, so they needn't
+//! build a regex engine into every component.
+//!
+//! Expressions use Rust `regex` syntax, which has no backreferences or
+//! lookaround and matches in time linear in the text searched, so no input
+//! can make a match run away. What's left to bound is the expression
+//! itself: [compile] and [compile_bytes] refuse expressions over
+//! [MAX_EXPRESSION_BYTES], nested deeper than [NEST_LIMIT] or compiling to
+//! more than [SIZE_LIMIT] bytes, ex: `(a{1000}){1000}`.
+
+use anyhow::{Context, Result, bail};
+use regex::{Regex, RegexBuilder};
+
+/// Longest expression compiled, in bytes
+pub const MAX_EXPRESSION_BYTES: usize = 4096;
+
+/// Largest compiled program, in bytes
+pub const SIZE_LIMIT: usize = 1024 * 1024;
+
+/// Memory the lazy DFA may use while matching, in bytes
+pub const DFA_SIZE_LIMIT: usize = 2 * 1024 * 1024;
+
+/// Deepest nesting of groups and repetitions
+pub const NEST_LIMIT: u32 = 64;
+
+/// Most matches returned by [CompiledRegex::find_all]
+pub const MAX_MATCHES: usize = 1000;
+
+fn check_length(expression: &str) -> Result<()> {
+    if expression.len() > MAX_EXPRESSION_BYTES {
+        bail!(
+            "Regular expressions can be at most {} bytes",
+            MAX_EXPRESSION_BYTES
+        );
+    }
+    Ok(())
+}
+
+/// Compile `expression` for matching strings, within the host's limits
+pub fn compile(expression: &str) -> Result<Regex> {
+    check_length(expression)?;
+    RegexBuilder::new(expression)
+        .size_limit(SIZE_LIMIT)
+        .dfa_size_limit(DFA_SIZE_LIMIT)
+        .nest_limit(NEST_LIMIT)
+        .build()
+        .with_context(|| format!("Invalid regular expression {expression:?}"))
+}
+
+/// Compile `expression` for matching bytes, within the host's limits
+pub fn compile_bytes(expression: &str) -> Result<regex::bytes::Regex> {
+    check_length(expression)?;
+    regex::bytes::RegexBuilder::new(expression)
+        .size_limit(SIZE_LIMIT)
+        .dfa_size_limit(DFA_SIZE_LIMIT)
+        .nest_limit(NEST_LIMIT)
+        .build()
+        .with_context(|| format!("Invalid regular expression {expression:?}"))
+}
+
+/// A match, by byte offsets into the text searched
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+impl From<regex::Match<'_>> for Match {
+    fn from(m: regex::Match<'_>) -> Self {
+        Self {
+            start: m.start(),
+            end: m.end(),
+            text: m.as_str().to_string(),
+        }
+    }
+}
+
+/// A regular expression a plugin compiled with its `regex` capability
+#[derive(Debug, Clone)]
+pub struct CompiledRegex {
+    regex: Regex,
+}
+
+impl CompiledRegex {
+    pub fn new(expression: &str) -> Result<Self> {
+        Ok(Self {
+            regex: compile(expression)?,
+        })
+    }
+
+    pub fn expression(&self) -> &str {
+        self.regex.as_str()
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+
+    pub fn find(&self, text: &str) -> Option<Match> {
+        self.regex.find(text).map(Match::from)
+    }
+
+    /// Non-overlapping matches in `text`, at most [MAX_MATCHES]
+    pub fn find_all(&self, text: &str) -> Vec<Match> {
+        self.regex
+            .find_iter(text)
+            .take(MAX_MATCHES)
+            .map(Match::from)
+            .collect()
+    }
+
+    /// The groups of the first match in `text`, group 0 being the match
+    pub fn captures(&self, text: &str) -> Option<Vec<Option<Match>>> {
+        let captures = self.regex.captures(text)?;
+        Some(captures.iter().map(|m| m.map(Match::from)).collect())
+    }
+
+    /// Replace matches in `text` with `replacement`, expanding `$0`, `$1`,
+    /// `$name`, ..., or only the first `limit` matches
+    pub fn replace(&self, text: &str, replacement: &str, limit: Option<usize>) -> String {
+        match limit {
+            Some(0) => text.to_string(),
+            Some(limit) => self.regex.replacen(text, limit, replacement).into_owned(),
+            None => self.regex.replace_all(text, replacement).into_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_and_replacements_use_byte_offsets_and_captures() {
+        let regex = CompiledRegex::new(r"(?<user>\w+)@(?<host>[\w.]+)").unwrap();
+        let text = "mail é a@x.io or bob@example.com";
+        assert!(regex.is_match(text));
+        let first = regex.find(text).unwrap();
+        assert_eq!(
+            (first.start, first.end, first.text.as_str()),
+            (8, 14, "a@x.io")
+        );
+        assert_eq!(regex.find_all(text).len(), 2);
+
+        let groups = regex.captures("bob@example.com").unwrap();
+        let groups: Vec<_> = groups.into_iter().map(|m| m.unwrap().text).collect();
+        assert_eq!(groups, ["bob@example.com", "bob", "example.com"]);
+
+        assert_eq!(
+            regex.replace(text, "$host", Some(1)),
+            "mail é x.io or bob@example.com"
+        );
+        assert_eq!(regex.replace(text, "<$user>", None), "mail é <a> or <bob>");
+        assert_eq!(regex.replace(text, "", Some(0)), text);
+    }
+
+    #[test]
+    fn expressions_over_the_limits_are_refused() {
+        assert!(compile("(").is_err());
+        assert!(compile(&"a".repeat(MAX_EXPRESSION_BYTES + 1)).is_err());
+        assert!(compile(r"(\w{1000}){1000}").is_err());
+        assert!(compile(&format!("{}a{}", "(".repeat(100), ")".repeat(100))).is_err());
+        assert!(compile_bytes(r"(\w{1000}){1000}").is_err());
+        assert!(compile(r"<head[^>]*>").is_ok());
+    }
+}
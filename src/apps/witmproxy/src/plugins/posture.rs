@@ -0,0 +1,309 @@
+//! What the installed plugins can see and do, to reason about the privacy
+//! posture of the plugin set (`witm plugin audit` and
+//! `/api/manage/capability-audit`).
+//!
+//! Per plugin, the audit lists each capability with its scope, quota,
+//! whether it's in effect and how often it was used in the last
+//! [USAGE_WINDOW_MINUTES] minutes. Per event kind, it lists the plugins
+//! which are given such events, optionally only those whose scope matches
+//! a GET request to a given host, ex: which plugins can read response
+//! bodies from `www.bank.com`.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::plugins::WitmPlugin;
+use crate::plugins::dry_run::{self, FlowFixture};
+use crate::plugins::quota::Quota;
+use crate::wasm::bindgen::witmproxy::plugin::capabilities::{CapabilityKind, EventKind};
+
+/// How far back uses of a capability count as recent
+pub const USAGE_WINDOW_MINUTES: i64 = 60;
+
+/// How often plugins used their capabilities recently: events of each kind
+/// they were given, and host capabilities they took from their provider.
+/// Cheap to clone; all clones share the same counts.
+#[derive(Clone, Default)]
+pub struct CapabilityUses {
+    /// Uses by plugin ID and capability, counted by minute, oldest first
+    uses: Arc<Mutex<HashMap<(String, String), VecDeque<(i64, u64)>>>>,
+}
+
+impl CapabilityUses {
+    /// Count a use of `capability` by plugin `plugin_id` at `now`
+    pub fn record(&self, plugin_id: &str, capability: &str, now: DateTime<Utc>) {
+        let minute = now.timestamp().div_euclid(60);
+        let mut uses = self.uses.lock().unwrap();
+        let counts = uses
+            .entry((plugin_id.to_string(), capability.to_string()))
+            .or_default();
+        match counts.back_mut() {
+            Some((last, count)) if *last == minute => *count += 1,
+            _ => counts.push_back((minute, 1)),
+        }
+        while counts
+            .front()
+            .is_some_and(|(first, _)| *first <= minute - USAGE_WINDOW_MINUTES)
+        {
+            counts.pop_front();
+        }
+    }
+
+    /// Uses of `capability` by plugin `plugin_id` in the window ending at
+    /// `now`
+    pub fn recent(&self, plugin_id: &str, capability: &str, now: DateTime<Utc>) -> u64 {
+        let minute = now.timestamp().div_euclid(60);
+        self.uses
+            .lock()
+            .unwrap()
+            .get(&(plugin_id.to_string(), capability.to_string()))
+            .map(|counts| {
+                counts
+                    .iter()
+                    .filter(|(at, _)| *at > minute - USAGE_WINDOW_MINUTES)
+                    .map(|(_, count)| count)
+                    .sum()
+            })
+            .unwrap_or(0)
+    }
+}
+
+/// A capability a plugin requested
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CapabilityPosture {
+    /// ex: `handle_event_response` or `local_storage`
+    pub kind: String,
+    /// CEL expression limiting the events the capability applies to
+    pub scope: String,
+    pub granted: bool,
+    /// Whether the plugin can use the capability now: it's enabled, the
+    /// capability is granted and now is in one of its quota's windows
+    pub effective: bool,
+    #[serde(default, skip_serializing_if = "Quota::is_unlimited")]
+    pub quota: Quota,
+    /// Uses in the last [USAGE_WINDOW_MINUTES] minutes
+    pub recent_uses: u64,
+}
+
+/// The capabilities of an installed plugin
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PluginPosture {
+    pub plugin: String,
+    pub version: String,
+    pub enabled: bool,
+    pub capabilities: Vec<CapabilityPosture>,
+}
+
+impl PluginPosture {
+    pub fn new(plugin: &WitmPlugin, uses: &CapabilityUses, now: DateTime<Utc>) -> Self {
+        let id = plugin.id();
+        let capabilities = plugin
+            .capabilities
+            .iter()
+            .map(|cap| {
+                let kind = cap.inner.kind.to_string();
+                CapabilityPosture {
+                    recent_uses: uses.recent(&id, &kind, now),
+                    kind,
+                    scope: cap.inner.scope.expression.clone(),
+                    granted: cap.granted,
+                    effective: plugin.enabled && cap.granted && cap.quota.is_active_at(now),
+                    quota: cap.quota.clone(),
+                }
+            })
+            .collect();
+        Self {
+            plugin: id,
+            version: plugin.version.clone(),
+            enabled: plugin.enabled,
+            capabilities,
+        }
+    }
+}
+
+/// A plugin given events of some kind
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct EventAccess {
+    pub plugin: String,
+    /// CEL expression limiting the events the plugin is given
+    pub scope: String,
+    /// Whether the plugin is given the events now, rather than only in its
+    /// quota's windows
+    pub active: bool,
+    /// Host capabilities the plugin holds alongside, ex: `local_storage`
+    pub alongside: Vec<String>,
+    /// Why the scope couldn't be evaluated against the host. The plugin is
+    /// listed, as it may be given its events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope_error: Option<String>,
+    /// Events given in the last [USAGE_WINDOW_MINUTES] minutes
+    pub recent_uses: u64,
+}
+
+/// The plugins given events of a kind
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct EventPosture {
+    /// ex: `response`
+    pub event: String,
+    /// The host scopes were evaluated against, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    pub plugins: Vec<EventAccess>,
+}
+
+impl EventPosture {
+    /// Which of the enabled `plugins` are granted `kind` events, only those
+    /// whose scope matches a GET request to `host` if given
+    pub fn new<'a>(
+        plugins: impl IntoIterator<Item = &'a WitmPlugin>,
+        kind: EventKind,
+        host: Option<&str>,
+        uses: &CapabilityUses,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<Self> {
+        let fixture = host
+            .map(|host| -> anyhow::Result<FlowFixture> {
+                Ok(serde_json::from_value(serde_json::json!({
+                    "url": format!("https://{host}/"),
+                    "time": now,
+                }))?)
+            })
+            .transpose()?;
+        let capability = CapabilityKind::HandleEvent(kind).to_string();
+        let mut access: Vec<EventAccess> = plugins
+            .into_iter()
+            .filter(|plugin| plugin.enabled)
+            .filter_map(|plugin| {
+                let cap = plugin.capabilities.iter().find(|cap| {
+                    cap.granted && cap.inner.kind == CapabilityKind::HandleEvent(kind)
+                })?;
+                let scope = &cap.inner.scope.expression;
+                let scope_error = match &fixture {
+                    Some(fixture) => {
+                        let result = dry_run::evaluate(kind, scope, fixture);
+                        if !result.matched && result.error.is_none() {
+                            return None;
+                        }
+                        result.error
+                    }
+                    None => None,
+                };
+                let alongside = plugin
+                    .capabilities
+                    .iter()
+                    .filter(|cap| {
+                        cap.granted && !matches!(cap.inner.kind, CapabilityKind::HandleEvent(_))
+                    })
+                    .map(|cap| cap.inner.kind.to_string())
+                    .collect();
+                Some(EventAccess {
+                    plugin: plugin.id(),
+                    scope: scope.clone(),
+                    active: cap.quota.is_active_at(now),
+                    alongside,
+                    scope_error,
+                    recent_uses: uses.recent(&plugin.id(), &capability, now),
+                })
+            })
+            .collect();
+        access.sort_by(|a, b| a.plugin.cmp(&b.plugin));
+        Ok(Self {
+            event: kind.to_string(),
+            host: host.map(str::to_string),
+            plugins: access,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::capabilities::Capability;
+    use crate::wasm::bindgen::witmproxy::plugin::capabilities::{
+        Capability as WitCapability, CapabilityScope,
+    };
+
+    fn plugin(name: &str, capabilities: &[(CapabilityKind, &str)]) -> WitmPlugin {
+        WitmPlugin {
+            namespace: "@test".to_string(),
+            name: name.to_string(),
+            version: "0.0.1".to_string(),
+            author: String::new(),
+            description: String::new(),
+            license: String::new(),
+            url: String::new(),
+            publickey: Vec::new(),
+            enabled: true,
+            capabilities: capabilities
+                .iter()
+                .map(|(kind, expression)| Capability {
+                    inner: WitCapability {
+                        kind: *kind,
+                        scope: CapabilityScope {
+                            expression: expression.to_string(),
+                        },
+                    },
+                    granted: true,
+                    quota: Default::default(),
+                    cel: None,
+                })
+                .collect(),
+            metadata: Default::default(),
+            configuration: Vec::new(),
+            configuration_schema: Vec::new(),
+            component: None,
+            component_bytes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn lists_who_is_given_each_event_kind() {
+        let now: DateTime<Utc> = "2026-10-15T12:00:00Z".parse().unwrap();
+        let uses = CapabilityUses::default();
+        let earlier = now - chrono::Duration::minutes(USAGE_WINDOW_MINUTES);
+        uses.record("@test/bank", "handle_event_response", earlier);
+        uses.record("@test/bank", "handle_event_response", now);
+        uses.record("@test/bank", "handle_event_response", now);
+
+        let response = CapabilityKind::HandleEvent(EventKind::Response);
+        let bank = plugin(
+            "bank",
+            &[
+                (response, "request.host().endsWith('bank.com')"),
+                (CapabilityKind::LocalStorage, "true"),
+            ],
+        );
+        let news = plugin("news", &[(response, "request.host() == 'news.com'")]);
+        let mut disabled = plugin("disabled", &[(response, "true")]);
+        disabled.enabled = false;
+        let plugins = [bank, news, disabled];
+
+        let posture = PluginPosture::new(&plugins[0], &uses, now);
+        assert_eq!(posture.capabilities[0].recent_uses, 2);
+        assert!(posture.capabilities[0].effective);
+        assert!(!PluginPosture::new(&plugins[2], &uses, now).capabilities[0].effective);
+
+        let all = EventPosture::new(&plugins, EventKind::Response, None, &uses, now).unwrap();
+        let ids: Vec<_> = all.plugins.iter().map(|a| a.plugin.as_str()).collect();
+        assert_eq!(ids, ["@test/bank", "@test/news"]);
+
+        let bank = EventPosture::new(
+            &plugins,
+            EventKind::Response,
+            Some("www.bank.com"),
+            &uses,
+            now,
+        )
+        .unwrap();
+        assert_eq!(bank.plugins.len(), 1);
+        assert_eq!(bank.plugins[0].alongside, ["local_storage"]);
+        assert_eq!(bank.plugins[0].recent_uses, 2);
+
+        let requests = EventPosture::new(&plugins, EventKind::Request, None, &uses, now).unwrap();
+        assert!(requests.plugins.is_empty());
+    }
+}
@@ -0,0 +1,103 @@
+//! Quotas bounding how much a plugin can use a capability it's been granted:
+//! how many events it handles a minute, how many bytes of body it inspects
+//! an hour, and the times it can use the capability at all.
+//!
+//! Limits which aren't set are unlimited. Event and body limits apply to
+//! event capabilities, and are counted in the database by
+//! [CapabilityUsage](crate::db::capability_usage::CapabilityUsage) so they
+//! hold across restarts. Time windows apply to every capability.
+
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Quota {
+    /// Events handled per minute
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_events_per_minute: Option<u32>,
+    /// Bytes of request, response or content body handled per hour. Bodies
+    /// are counted by their `Content-Length`, so events whose body length
+    /// isn't known upfront aren't handled while this is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_body_bytes_per_hour: Option<u64>,
+    /// When the capability can be used, at any time if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub active_windows: Vec<TimeWindow>,
+}
+
+/// A daily window of time, in UTC, ex: `{"start": "09:00", "end": "17:00",
+/// "days": ["Mon", "Tue", "Wed", "Thu", "Fri"]}`. Windows ending before they
+/// start run past midnight.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TimeWindow {
+    #[salvo(schema(value_type = String))]
+    pub start: NaiveTime,
+    #[salvo(schema(value_type = String))]
+    pub end: NaiveTime,
+    /// The days the window starts on, every day if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[salvo(schema(value_type = Vec<String>))]
+    pub days: Vec<Weekday>,
+}
+
+impl Quota {
+    pub fn is_unlimited(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether `now` falls in one of the quota's active windows
+    pub fn is_active_at(&self, now: DateTime<Utc>) -> bool {
+        self.active_windows.is_empty()
+            || self
+                .active_windows
+                .iter()
+                .any(|window| window.contains(now))
+    }
+}
+
+impl TimeWindow {
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let time = now.time();
+        let starts_on = |day: Weekday| self.days.is_empty() || self.days.contains(&day);
+        if self.start <= self.end {
+            starts_on(now.weekday()) && self.start <= time && time < self.end
+        } else {
+            (starts_on(now.weekday()) && self.start <= time)
+                || (starts_on(now.weekday().pred()) && time < self.end)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(timestamp: &str) -> DateTime<Utc> {
+        timestamp.parse().unwrap()
+    }
+
+    #[test]
+    fn windows_are_read_as_times_and_days() {
+        let quota: Quota = serde_json::from_value(serde_json::json!({
+            "active_windows": [
+                {"start": "09:00", "end": "17:00", "days": ["Mon", "Tue", "Wed", "Thu", "Fri"]},
+                {"start": "22:00", "end": "02:00", "days": ["Sat"]},
+            ]
+        }))
+        .unwrap();
+        assert!(!quota.is_unlimited());
+
+        // 2026-10-12 is a Monday
+        assert!(quota.is_active_at(at("2026-10-12T09:00:00Z")));
+        assert!(!quota.is_active_at(at("2026-10-12T17:00:00Z")));
+        assert!(!quota.is_active_at(at("2026-10-11T12:00:00Z")));
+        // Saturday night's window runs into Sunday
+        assert!(quota.is_active_at(at("2026-10-17T23:30:00Z")));
+        assert!(quota.is_active_at(at("2026-10-18T01:59:59Z")));
+        assert!(!quota.is_active_at(at("2026-10-18T22:30:00Z")));
+
+        assert!(Quota::default().is_unlimited());
+        assert!(Quota::default().is_active_at(at("2026-10-18T22:30:00Z")));
+    }
+}
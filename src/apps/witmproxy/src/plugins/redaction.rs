@@ -0,0 +1,905 @@
+//! Redaction of flow data before plugins see it.
+//!
+//! A plugin granted a flow's events sees its headers and body in full, which
+//! isn't always wanted of third-party plugins. Redaction rules remove (or
+//! mask) headers, cookies and JSON body fields from the events they match
+//! before each plugin is given the event, and put them back once the plugin
+//! has handled it, so what's sent on is unaffected unless the plugin set the
+//! data itself. Rules are configured as `[[plugins.redactions]]` tables, or
+//! replaced at runtime through `/api/manage/redactions`:
+//!
+//! ```toml
+//! [[plugins.redactions]]
+//! when = 'request.host().endsWith("example.com")'
+//! headers = ["authorization"]
+//! cookies = ["session"]
+//! body_fields = ["user.password", "cards.number"]
+//! exempt_plugins = ["ops/auth"]
+//! ```
+//!
+//! `when` is a CEL expression as in plugin scopes. One failing to evaluate,
+//! ex: by reading `response` for a request event, counts as a match, so data
+//! isn't leaked by a mistaken rule. Body fields are dot-separated paths into
+//! JSON bodies, looked for in each element of the arrays on the way. As
+//! bodies have to be buffered to redact, a plugin isn't given an event whose
+//! body fields are to be redacted but whose body is encoded, of unknown
+//! length or longer than [MAX_REDACTED_BODY_BYTES].
+//!
+//! Rules also cover what plugins read through capabilities rather than
+//! events:
+//!
+//! - `flow_reader` summaries carry no headers, cookies or bodies, only URLs,
+//!   so the path and query of the flows a rule matches have the sensitive
+//!   data [findings](crate::proxy::findings) detects redacted instead.
+//! - `html` summaries are parsed from the content body as the plugin was
+//!   given it, and dropped whenever the body is replaced, so a plugin is
+//!   never handed a summary of a body redacted differently for it.
+//! - Storage snapshots only hold what plugins stored themselves, from the
+//!   events as redacted for them. They're served to management API admins,
+//!   never to plugins nor viewer tokens.
+
+use std::sync::{Arc, OnceLock, RwLock};
+
+use anyhow::{Context, Result, bail};
+use bytes::Bytes;
+use cel_cxx::{Activation, Env, Program};
+use http_body_util::{BodyExt, Full, combinators::UnsyncBoxBody};
+use hyper::header::{CONTENT_LENGTH, COOKIE, HeaderMap, HeaderName, HeaderValue, SET_COOKIE};
+use hyper::http::{request, response};
+use hyper::{Request, Response};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::debug;
+use wasmtime::Store;
+use wasmtime_wasi_http::FieldMap;
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::p3::{
+    Request as WasiRequest, RequestOptions, Response as WasiResponse, WasiHttpView,
+};
+
+use crate::events::Event;
+use crate::plugins::cel::{self, CelRequest};
+use crate::plugins::exercise::collect_bytes;
+use crate::proxy::flows::FlowRecord;
+use crate::wasm::Host;
+use crate::wasm::bindgen::Event as WasmEvent;
+use crate::wasm::bindgen::witmproxy::plugin::capabilities::{
+    ContextualResponse as WasiContextualResponse, EventKind, RequestContext,
+};
+
+/// Value masked data is replaced with
+pub const MASK: &str = "[REDACTED]";
+
+/// Longest body fields are redacted from
+pub const MAX_REDACTED_BODY_BYTES: u64 = 1024 * 1024;
+
+/// Data to redact from the events matching an expression
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RedactionRule {
+    /// CEL expression selecting the events to redact (default: "true")
+    #[serde(default = "match_all")]
+    pub when: String,
+    /// Names of the headers to redact, ex: "authorization"
+    #[serde(default)]
+    pub headers: Vec<String>,
+    /// Names of the cookies to redact from `Cookie` and `Set-Cookie` headers
+    #[serde(default)]
+    pub cookies: Vec<String>,
+    /// Dot-separated paths of the JSON body fields to redact, ex:
+    /// "user.password"
+    #[serde(default)]
+    pub body_fields: Vec<String>,
+    /// Replace the data with "[REDACTED]" rather than removing it, so
+    /// plugins can tell it's there (default: false)
+    #[serde(default)]
+    pub mask: bool,
+    /// IDs of the plugins trusted to see the data, ex: "ops/auth"
+    #[serde(default)]
+    pub exempt_plugins: Vec<String>,
+}
+
+fn match_all() -> String {
+    "true".to_string()
+}
+
+struct CompiledRule {
+    program: Program<'static>,
+    redaction: Redaction,
+    exempt_plugins: Vec<String>,
+}
+
+impl CompiledRule {
+    fn compile(rule: &RedactionRule) -> Result<Self> {
+        let program = env()
+            .compile(&rule.when)
+            .with_context(|| format!("Invalid redaction expression: {}", rule.when))?;
+        let headers = rule
+            .headers
+            .iter()
+            .map(|name| {
+                let name = HeaderName::try_from(name.as_str())
+                    .with_context(|| format!("Invalid header name: {}", name))?;
+                Ok((name, rule.mask))
+            })
+            .collect::<Result<_>>()?;
+        let body_fields = rule
+            .body_fields
+            .iter()
+            .map(|path| {
+                let segments: Vec<_> = path.split('.').map(str::to_string).collect();
+                if segments.iter().any(String::is_empty) {
+                    bail!("Invalid body field path: {}", path);
+                }
+                Ok((segments, rule.mask))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            program,
+            redaction: Redaction {
+                headers,
+                cookies: rule
+                    .cookies
+                    .iter()
+                    .map(|name| (name.clone(), rule.mask))
+                    .collect(),
+                body_fields,
+            },
+            exempt_plugins: rule.exempt_plugins.clone(),
+        })
+    }
+
+    fn matches(&self, event: &dyn Event) -> bool {
+        self.evaluate(event.bind_cel_activation(Activation::new()))
+    }
+
+    fn evaluate(&self, activation: Option<Activation<'_>>) -> bool {
+        let Some(activation) = activation else {
+            return true;
+        };
+        match self.program.evaluate(activation) {
+            Ok(cel_cxx::Value::Bool(matches)) => matches,
+            Ok(_) => true,
+            Err(e) => {
+                debug!("Redacting on failure to evaluate expression: {}", e);
+                true
+            }
+        }
+    }
+}
+
+/// The CEL environment rules are compiled in, declaring the same variables
+/// and functions as plugin scopes
+fn env() -> &'static Env<'static> {
+    static ENV: OnceLock<Env<'static>> = OnceLock::new();
+    ENV.get_or_init(|| {
+        WasmEvent::register(Env::builder().with_standard(true))
+            .and_then(|builder| Ok(builder.build()?))
+            .expect("Failed to build the CEL environment")
+    })
+}
+
+/// The live set of redaction rules. Cheap to clone; all clones share the
+/// same rules.
+#[derive(Clone, Default)]
+pub struct Redactions {
+    rules: Arc<RwLock<Arc<Rules>>>,
+}
+
+#[derive(Default)]
+struct Rules {
+    config: Vec<RedactionRule>,
+    compiled: Vec<CompiledRule>,
+}
+
+impl std::fmt::Debug for Redactions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Redactions")
+            .field("rules", &self.rules())
+            .finish()
+    }
+}
+
+impl Redactions {
+    pub fn new(rules: Vec<RedactionRule>) -> Result<Self> {
+        let redactions = Self::default();
+        redactions.set_rules(rules)?;
+        Ok(redactions)
+    }
+
+    /// Replace the rules, leaving the current ones in place if any is invalid
+    pub fn set_rules(&self, rules: Vec<RedactionRule>) -> Result<()> {
+        let compiled = rules
+            .iter()
+            .map(CompiledRule::compile)
+            .collect::<Result<_>>()?;
+        *self.rules.write().unwrap() = Arc::new(Rules {
+            config: rules,
+            compiled,
+        });
+        Ok(())
+    }
+
+    pub fn rules(&self) -> Vec<RedactionRule> {
+        self.rules.read().unwrap().config.clone()
+    }
+
+    /// What to redact from `event` before the plugin `plugin_id` handles
+    /// it, merged from every matching rule the plugin isn't exempt from
+    pub fn for_plugin(&self, plugin_id: &str, event: &dyn Event) -> Option<Redaction> {
+        let rules = self.rules.read().unwrap().clone();
+        let mut redaction = Redaction::default();
+        for rule in &rules.compiled {
+            if rule.exempt_plugins.iter().any(|id| id == plugin_id) || !rule.matches(event) {
+                continue;
+            }
+            redaction.merge(&rule.redaction);
+        }
+        (!redaction.is_empty()).then_some(redaction)
+    }
+
+    /// Whether a rule the plugin `plugin_id` isn't exempt from matches the
+    /// logged `flow`, whose URL is then redacted from the plugin's flow reads
+    pub fn matches_flow(&self, plugin_id: &str, flow: &FlowRecord) -> bool {
+        let rules = self.rules.read().unwrap().clone();
+        rules.compiled.iter().any(|rule| {
+            !rule.exempt_plugins.iter().any(|id| id == plugin_id)
+                && rule.evaluate(cel::bind_flow(flow, Activation::new()))
+        })
+    }
+}
+
+/// Data to redact from an event, each item with whether it's masked rather
+/// than removed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Redaction {
+    headers: Vec<(HeaderName, bool)>,
+    cookies: Vec<(String, bool)>,
+    body_fields: Vec<(Vec<String>, bool)>,
+}
+
+impl Redaction {
+    fn merge(&mut self, other: &Redaction) {
+        self.headers.extend(other.headers.iter().cloned());
+        self.cookies.extend(other.cookies.iter().cloned());
+        self.body_fields.extend(other.body_fields.iter().cloned());
+    }
+
+    fn is_empty(&self) -> bool {
+        self.headers.is_empty() && self.cookies.is_empty() && self.body_fields.is_empty()
+    }
+
+    /// Whether `event` can be redacted; bodies have to be buffered and
+    /// parsed to redact fields from
+    pub fn applies_to(&self, event: &dyn Event) -> bool {
+        self.body_fields.is_empty()
+            || (!event.body_encoded()
+                && event
+                    .body_size()
+                    .is_some_and(|size| size <= MAX_REDACTED_BODY_BYTES))
+    }
+
+    /// Redact `headers`, recording what was removed in `redacted`
+    fn redact_headers(&self, headers: &mut HeaderMap, redacted: &mut Redacted) {
+        for (name, mask) in &self.headers {
+            let values: Vec<_> = headers.get_all(name).iter().cloned().collect();
+            if values.is_empty() {
+                continue;
+            }
+            headers.remove(name);
+            if *mask {
+                headers.insert(name.clone(), HeaderValue::from_static(MASK));
+            }
+            redacted.headers.push((name.clone(), values));
+        }
+        if self.cookies.is_empty() {
+            return;
+        }
+
+        if let Some(cookie) = cookie_header(headers) {
+            let pairs = cookie_pairs(&cookie)
+                .into_iter()
+                .filter_map(|(name, value)| match self.cookie(&name) {
+                    Some(mask) => {
+                        redacted.cookies.push((name.clone(), value));
+                        mask.then(|| (name, MASK.to_string()))
+                    }
+                    None => Some((name, value)),
+                })
+                .collect();
+            set_cookie_header(headers, pairs);
+        }
+
+        let set_cookies: Vec<_> = headers.get_all(SET_COOKIE).iter().cloned().collect();
+        if set_cookies.is_empty() {
+            return;
+        }
+        headers.remove(SET_COOKIE);
+        for value in set_cookies {
+            let name = set_cookie_name(&value);
+            match name.as_deref().and_then(|name| self.cookie(name)) {
+                Some(mask) => {
+                    let name = name.unwrap_or_default();
+                    if mask && let Ok(masked) = HeaderValue::try_from(masked_cookie(&name)) {
+                        headers.append(SET_COOKIE, masked);
+                    }
+                    redacted.set_cookies.push((name, value));
+                }
+                None => {
+                    headers.append(SET_COOKIE, value);
+                }
+            }
+        }
+    }
+
+    /// Whether the cookie `name` is masked, if it's redacted
+    fn cookie(&self, name: &str) -> Option<bool> {
+        self.cookies
+            .iter()
+            .find(|(cookie, _)| cookie == name)
+            .map(|(_, mask)| *mask)
+    }
+
+    /// Redact the fields of a JSON `body`, recording what was removed in
+    /// `redacted`
+    fn redact_json(&self, body: &mut Value, redacted: &mut Redacted) {
+        for (path, mask) in &self.body_fields {
+            redact_field(body, path, String::new(), *mask, redacted);
+        }
+    }
+
+    /// Redact a JSON `body`, leaving bodies of other types as they are
+    fn redact_body(&self, body: Bytes, redacted: &mut Redacted) -> Bytes {
+        if self.body_fields.is_empty() {
+            return body;
+        }
+        let Ok(mut json) = serde_json::from_slice::<Value>(&body) else {
+            return body;
+        };
+        self.redact_json(&mut json, redacted);
+        match serde_json::to_vec(&json) {
+            Ok(json) => Bytes::from(json),
+            Err(_) => body,
+        }
+    }
+
+    /// Redact `event`, held in `store`, returning it along with what was
+    /// removed from it
+    pub(crate) async fn apply(
+        &self,
+        event: WasmEvent,
+        store: &mut Store<Host>,
+    ) -> Result<(WasmEvent, Redacted)> {
+        let mut redacted = Redacted {
+            kind: kind_of(&event),
+            ..Default::default()
+        };
+        let event = match event {
+            WasmEvent::Request(req) => {
+                let req = store.data_mut().http().table.delete(req)?;
+                let req = if self.body_fields.is_empty() {
+                    edit_request_headers(req, |headers| self.redact_headers(headers, &mut redacted))
+                } else {
+                    let (mut parts, body, options) = request_parts(req, store).await?;
+                    self.redact_headers(&mut parts.headers, &mut redacted);
+                    let body = self.redact_body(body, &mut redacted);
+                    wasi_request(parts, body, options)
+                };
+                WasmEvent::Request(store.data_mut().http().table.push(req)?)
+            }
+            WasmEvent::Response(WasiContextualResponse {
+                response,
+                mut request,
+            }) => {
+                redacted.context = Some(CelRequest::from(&request));
+                self.redact_context(&mut request);
+                let response = store.data_mut().http().table.delete(response)?;
+                let response = if self.body_fields.is_empty() {
+                    edit_response_headers(response, |headers| {
+                        self.redact_headers(headers, &mut redacted)
+                    })
+                } else {
+                    let (mut parts, body) = response_parts(response, store).await?;
+                    self.redact_headers(&mut parts.headers, &mut redacted);
+                    let body = self.redact_body(body, &mut redacted);
+                    wasi_response(parts, body)
+                };
+                WasmEvent::Response(WasiContextualResponse {
+                    response: store.data_mut().http().table.push(response)?,
+                    request,
+                })
+            }
+            WasmEvent::InboundContent(content) if !self.body_fields.is_empty() => {
+                let body = store.data_mut().table.get_mut(&content)?.body()?;
+                let body = match body {
+                    Some(body) => collect_bytes(store, body).await?,
+                    None => Bytes::new(),
+                };
+                let body = self.redact_body(body, &mut redacted);
+                store
+                    .data_mut()
+                    .table
+                    .get_mut(&content)?
+                    .set_body(full(body));
+                WasmEvent::InboundContent(content)
+            }
+            event => event,
+        };
+        Ok((event, redacted))
+    }
+
+    /// Redact the headers of the request a response event answers
+    fn redact_context(&self, request: &mut RequestContext) {
+        let mut headers = HeaderMap::new();
+        for (name, values) in &request.headers {
+            let Ok(name) = HeaderName::try_from(name.as_str()) else {
+                continue;
+            };
+            for value in values {
+                if let Ok(value) = HeaderValue::try_from(value.as_str()) {
+                    headers.append(name.clone(), value);
+                }
+            }
+        }
+        self.redact_headers(&mut headers, &mut Redacted::default());
+        request.headers = headers
+            .keys()
+            .map(|name| {
+                let values = headers
+                    .get_all(name)
+                    .iter()
+                    .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+                    .collect();
+                (name.to_string(), values)
+            })
+            .collect();
+    }
+}
+
+/// Data redacted from an event, to put back once a plugin has handled it
+#[derive(Debug, Default)]
+pub struct Redacted {
+    /// The kind of event the data was redacted from
+    kind: Option<EventKind>,
+    /// Removed header values, by name
+    headers: Vec<(HeaderName, Vec<HeaderValue>)>,
+    /// Removed `Cookie` header cookies, as names and values
+    cookies: Vec<(String, String)>,
+    /// Removed `Set-Cookie` headers, by cookie name
+    set_cookies: Vec<(String, HeaderValue)>,
+    /// Removed JSON body fields, by JSON pointer
+    fields: Vec<(String, Value)>,
+    /// The request a response event answers, before its headers were
+    /// redacted
+    context: Option<CelRequest>,
+}
+
+impl Redacted {
+    /// Put the redacted data back into `headers`, except where the plugin set
+    /// its own
+    fn restore_headers(&self, headers: &mut HeaderMap) {
+        for (name, values) in &self.headers {
+            if headers.get_all(name).iter().any(|value| value != MASK) {
+                continue;
+            }
+            headers.remove(name);
+            for value in values {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+
+        if !self.cookies.is_empty() {
+            let mut pairs = cookie_header(headers)
+                .map(|cookie| cookie_pairs(&cookie))
+                .unwrap_or_default();
+            for (name, value) in &self.cookies {
+                match pairs.iter_mut().find(|(cookie, _)| cookie == name) {
+                    Some((_, current)) if current == MASK => *current = value.clone(),
+                    Some(_) => {}
+                    None => pairs.push((name.clone(), value.clone())),
+                }
+            }
+            set_cookie_header(headers, pairs);
+        }
+
+        if !self.set_cookies.is_empty() {
+            let set_cookies: Vec<_> = headers.get_all(SET_COOKIE).iter().cloned().collect();
+            headers.remove(SET_COOKIE);
+            for value in set_cookies {
+                let masked = self
+                    .set_cookies
+                    .iter()
+                    .any(|(name, _)| value.as_bytes() == masked_cookie(name).as_bytes());
+                if !masked {
+                    headers.append(SET_COOKIE, value);
+                }
+            }
+            for (name, value) in &self.set_cookies {
+                let set = headers
+                    .get_all(SET_COOKIE)
+                    .iter()
+                    .any(|current| set_cookie_name(current).as_ref() == Some(name));
+                if !set {
+                    headers.append(SET_COOKIE, value.clone());
+                }
+            }
+        }
+    }
+
+    /// Put the redacted fields back into a JSON `body`, except where the
+    /// plugin set its own
+    fn restore_json(&self, body: &mut Value) {
+        for (pointer, original) in &self.fields {
+            if let Some(current) = body.pointer_mut(pointer) {
+                if *current == MASK {
+                    *current = original.clone();
+                }
+                continue;
+            }
+            let Some((parent, key)) = pointer.rsplit_once('/') else {
+                continue;
+            };
+            if let Some(Value::Object(parent)) = body.pointer_mut(parent) {
+                parent.insert(unescape(key), original.clone());
+            }
+        }
+    }
+
+    fn restore_body(&self, body: Bytes) -> Bytes {
+        if self.fields.is_empty() {
+            return body;
+        }
+        let Ok(mut json) = serde_json::from_slice::<Value>(&body) else {
+            return body;
+        };
+        self.restore_json(&mut json);
+        match serde_json::to_vec(&json) {
+            Ok(json) => Bytes::from(json),
+            Err(_) => body,
+        }
+    }
+
+    /// Put the redacted data back into `event`, the event a plugin returned,
+    /// held in `store`
+    pub(crate) async fn restore(
+        self,
+        event: WasmEvent,
+        store: &mut Store<Host>,
+    ) -> Result<WasmEvent> {
+        // A plugin answering a request doesn't get the request's data
+        if kind_of(&event) != self.kind {
+            return Ok(event);
+        }
+        Ok(match event {
+            WasmEvent::Request(req) => {
+                let req = store.data_mut().http().table.delete(req)?;
+                let req = if self.fields.is_empty() {
+                    edit_request_headers(req, |headers| self.restore_headers(headers))
+                } else {
+                    let (mut parts, body, options) = request_parts(req, store).await?;
+                    self.restore_headers(&mut parts.headers);
+                    wasi_request(parts, self.restore_body(body), options)
+                };
+                WasmEvent::Request(store.data_mut().http().table.push(req)?)
+            }
+            WasmEvent::Response(WasiContextualResponse { response, request }) => {
+                let response = store.data_mut().http().table.delete(response)?;
+                let response = if self.fields.is_empty() {
+                    edit_response_headers(response, |headers| self.restore_headers(headers))
+                } else {
+                    let (mut parts, body) = response_parts(response, store).await?;
+                    self.restore_headers(&mut parts.headers);
+                    wasi_response(parts, self.restore_body(body))
+                };
+                WasmEvent::Response(WasiContextualResponse {
+                    response: store.data_mut().http().table.push(response)?,
+                    // The request was already sent, so is put back as it was
+                    request: match &self.context {
+                        Some(context) => context.clone().into(),
+                        None => request,
+                    },
+                })
+            }
+            WasmEvent::InboundContent(content) if !self.fields.is_empty() => {
+                let body = store.data_mut().table.get_mut(&content)?.body()?;
+                let body = match body {
+                    Some(body) => collect_bytes(store, body).await?,
+                    None => Bytes::new(),
+                };
+                let body = self.restore_body(body);
+                store
+                    .data_mut()
+                    .table
+                    .get_mut(&content)?
+                    .set_body(full(body));
+                WasmEvent::InboundContent(content)
+            }
+            event => event,
+        })
+    }
+}
+
+fn kind_of(event: &WasmEvent) -> Option<EventKind> {
+    match event {
+        WasmEvent::Request(_) => Some(EventKind::Request),
+        WasmEvent::Response(_) => Some(EventKind::Response),
+        WasmEvent::InboundContent(_) => Some(EventKind::InboundContent),
+        _ => None,
+    }
+}
+
+/// Redact the field at `path` of `value`, found at `pointer`, from it and
+/// from each element of the arrays on the way
+fn redact_field(
+    value: &mut Value,
+    path: &[String],
+    pointer: String,
+    mask: bool,
+    redacted: &mut Redacted,
+) {
+    match value {
+        Value::Array(elements) => {
+            for (i, element) in elements.iter_mut().enumerate() {
+                redact_field(element, path, format!("{}/{}", pointer, i), mask, redacted);
+            }
+        }
+        Value::Object(fields) => {
+            let Some((key, rest)) = path.split_first() else {
+                return;
+            };
+            let pointer = format!("{}/{}", pointer, escape(key));
+            if !rest.is_empty() {
+                if let Some(field) = fields.get_mut(key) {
+                    redact_field(field, rest, pointer, mask, redacted);
+                }
+                return;
+            }
+            let original = if mask {
+                fields
+                    .get_mut(key)
+                    .map(|field| std::mem::replace(field, Value::String(MASK.to_string())))
+            } else {
+                fields.remove(key)
+            };
+            if let Some(original) = original {
+                redacted.fields.push((pointer, original));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Escape `key` for use in a JSON pointer
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+fn unescape(key: &str) -> String {
+    key.replace("~1", "/").replace("~0", "~")
+}
+
+/// The `Cookie` headers of a request, joined
+fn cookie_header(headers: &HeaderMap) -> Option<String> {
+    let values: Vec<_> = headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    (!values.is_empty()).then(|| values.join("; "))
+}
+
+/// The names and values of the cookies of a `Cookie` header
+fn cookie_pairs(cookie: &str) -> Vec<(String, String)> {
+    cookie
+        .split(';')
+        .filter_map(|pair| {
+            let (name, value) = pair.trim().split_once('=')?;
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+fn set_cookie_header(headers: &mut HeaderMap, pairs: Vec<(String, String)>) {
+    headers.remove(COOKIE);
+    if pairs.is_empty() {
+        return;
+    }
+    let cookie = pairs
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("; ");
+    if let Ok(cookie) = HeaderValue::try_from(cookie) {
+        headers.insert(COOKIE, cookie);
+    }
+}
+
+/// The name of the cookie a `Set-Cookie` header sets
+fn set_cookie_name(value: &HeaderValue) -> Option<String> {
+    let (name, _) = value.to_str().ok()?.split_once('=')?;
+    Some(name.trim().to_string())
+}
+
+fn masked_cookie(name: &str) -> String {
+    format!("{}={}", name, MASK)
+}
+
+fn full(body: Bytes) -> UnsyncBoxBody<Bytes, ErrorCode> {
+    Full::new(body).map_err(|e| match e {}).boxed_unsync()
+}
+
+fn edit_headers(fields: &mut FieldMap, edit: impl FnOnce(&mut HeaderMap)) {
+    let mut headers = HeaderMap::from(std::mem::take(fields));
+    edit(&mut headers);
+    *fields = FieldMap::new_immutable(headers);
+}
+
+fn edit_request_headers(mut req: WasiRequest, edit: impl FnOnce(&mut HeaderMap)) -> WasiRequest {
+    edit_headers(&mut req.headers, edit);
+    req
+}
+
+fn edit_response_headers(
+    mut response: WasiResponse,
+    edit: impl FnOnce(&mut HeaderMap),
+) -> WasiResponse {
+    edit_headers(&mut response.headers, edit);
+    response
+}
+
+type RequestParts = (request::Parts, Bytes, Option<Arc<RequestOptions>>);
+
+async fn request_parts(req: WasiRequest, store: &mut Store<Host>) -> Result<RequestParts> {
+    let (req, options) = req.into_http(&mut *store, async { Ok(()) })?;
+    let (parts, body) = req.into_parts();
+    Ok((parts, collect_bytes(store, body).await?, options))
+}
+
+fn wasi_request(
+    mut parts: request::Parts,
+    body: Bytes,
+    options: Option<Arc<RequestOptions>>,
+) -> WasiRequest {
+    set_length(&mut parts.headers, &body);
+    let (mut req, _io) = WasiRequest::from_http(Request::from_parts(parts, Full::new(body)));
+    req.options = options;
+    req
+}
+
+async fn response_parts(
+    response: WasiResponse,
+    store: &mut Store<Host>,
+) -> Result<(response::Parts, Bytes)> {
+    let response = response.into_http(&mut *store, async { Ok(()) })?;
+    let (parts, body) = response.into_parts();
+    Ok((parts, collect_bytes(store, body).await?))
+}
+
+fn wasi_response(mut parts: response::Parts, body: Bytes) -> WasiResponse {
+    set_length(&mut parts.headers, &body);
+    let (response, _io) = WasiResponse::from_http(Response::from_parts(parts, Full::new(body)));
+    response
+}
+
+/// Keep the `Content-Length` of a message whose body was rewritten right
+fn set_length(headers: &mut HeaderMap, body: &Bytes) {
+    if headers.contains_key(CONTENT_LENGTH) {
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn redaction(headers: &[&str], cookies: &[&str], fields: &[&str], mask: bool) -> Redaction {
+        Redaction {
+            headers: headers
+                .iter()
+                .map(|name| (HeaderName::from_bytes(name.as_bytes()).unwrap(), mask))
+                .collect(),
+            cookies: cookies
+                .iter()
+                .map(|name| (name.to_string(), mask))
+                .collect(),
+            body_fields: fields
+                .iter()
+                .map(|path| (path.split('.').map(str::to_string).collect(), mask))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn headers_and_cookies_are_redacted_and_restored() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer secret"));
+        headers.insert(COOKIE, HeaderValue::from_static("session=abc; theme=dark"));
+        headers.append(SET_COOKIE, HeaderValue::from_static("session=def; Path=/"));
+        headers.append(SET_COOKIE, HeaderValue::from_static("theme=light"));
+        let original = headers.clone();
+
+        let mut redacted = Redacted::default();
+        redaction(&["authorization"], &["session"], &[], false)
+            .redact_headers(&mut headers, &mut redacted);
+        assert!(!headers.contains_key("authorization"));
+        assert_eq!(headers[COOKIE], "theme=dark");
+        assert_eq!(
+            headers.get_all(SET_COOKIE).iter().collect::<Vec<_>>(),
+            ["theme=light"]
+        );
+
+        redacted.restore_headers(&mut headers);
+        assert_eq!(headers["authorization"], "Bearer secret");
+        assert_eq!(headers[COOKIE], "theme=dark; session=abc");
+        assert_eq!(
+            headers.get_all(SET_COOKIE).iter().count(),
+            original.get_all(SET_COOKIE).iter().count()
+        );
+
+        let mut redacted = Redacted::default();
+        redaction(&["authorization"], &["session"], &[], true)
+            .redact_headers(&mut headers, &mut redacted);
+        assert_eq!(headers["authorization"], MASK);
+        assert_eq!(headers[COOKIE], "theme=dark; session=[REDACTED]");
+
+        // Headers the plugin set itself are kept
+        headers.insert("authorization", HeaderValue::from_static("Bearer other"));
+        redacted.restore_headers(&mut headers);
+        assert_eq!(headers["authorization"], "Bearer other");
+        assert_eq!(headers[COOKIE], "theme=dark; session=abc");
+    }
+
+    #[test]
+    fn body_fields_are_redacted_and_restored() {
+        let original = json!({
+            "user": {"name": "ada", "password": "hunter2"},
+            "cards": [{"number": "4111"}, {"number": "5500"}, {"name": "none"}],
+        });
+        let mut body = original.clone();
+        let mut redacted = Redacted::default();
+        redaction(&[], &[], &["user.password", "cards.number"], false)
+            .redact_json(&mut body, &mut redacted);
+        assert_eq!(
+            body,
+            json!({"user": {"name": "ada"}, "cards": [{}, {}, {"name": "none"}]})
+        );
+        redacted.restore_json(&mut body);
+        assert_eq!(body, original);
+
+        let mut redacted = Redacted::default();
+        redaction(&[], &[], &["user.password"], true).redact_json(&mut body, &mut redacted);
+        assert_eq!(body["user"]["password"], MASK);
+        redacted.restore_json(&mut body);
+        assert_eq!(body, original);
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        let rule = |when: &str, headers: &[&str], body_fields: &[&str]| RedactionRule {
+            when: when.to_string(),
+            headers: headers.iter().map(|name| name.to_string()).collect(),
+            cookies: Vec::new(),
+            body_fields: body_fields.iter().map(|path| path.to_string()).collect(),
+            mask: false,
+            exempt_plugins: Vec::new(),
+        };
+        let redactions = Redactions::new(vec![rule("true", &["authorization"], &[])]).unwrap();
+        assert!(
+            redactions
+                .set_rules(vec![rule("request.host() ==", &[], &[])])
+                .is_err()
+        );
+        assert!(
+            redactions
+                .set_rules(vec![rule("true", &["bad header"], &[])])
+                .is_err()
+        );
+        assert!(
+            redactions
+                .set_rules(vec![rule("true", &[], &["user..password"])])
+                .is_err()
+        );
+        assert_eq!(redactions.rules().len(), 1);
+    }
+}
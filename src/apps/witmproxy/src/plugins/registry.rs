@@ -1,35 +1,101 @@
 use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use bytes::Bytes;
 use cel_cxx::Env;
+use chrono::Utc;
 use http_body::Body;
 use http_body_util::{Full, combinators::UnsyncBoxBody};
 use hyper::{Request, Response, body::Incoming};
-use tracing::{debug, info, warn};
+use tracing::{debug, error, info, warn};
 use wasmtime::Store;
 use wasmtime_wasi_http::p3::{
     Request as WasiRequest, WasiHttpView, bindings::http::types::ErrorCode,
 };
 
 use crate::{
-    db::{Db, Insert},
-    events::{Event, connect::Connect, content::InboundContent, response::ContextualResponse},
-    plugins::WitmPlugin,
+    db::{
+        Db, Insert,
+        audit::{AuditAction, AuditEntry},
+        capability_usage::CapabilityUsage,
+    },
+    events::{
+        Event, connect::Connect, content::InboundContent, request::InterceptedRequest,
+        response::ContextualResponse,
+    },
+    http::{graphql::GraphqlOperation, jwt::KeySets},
+    plugins::{
+        WitmPlugin,
+        bundle::BundledPlugin,
+        bus::{self, Bus, Message},
+        differential::{self, Differences, DifferentialReport},
+        exercise::{self, Outcome},
+        inference::Models,
+        integrity::{Attestation, TrustedKeys},
+        lint,
+        posture::{CapabilityUses, EventPosture, PluginPosture},
+        redaction::Redactions,
+        storage_snapshots::StorageSnapshots,
+        transform,
+    },
+    proxy::{
+        flow_trace,
+        flows::FlowLog,
+        timeline::{self, FlowTimelines},
+    },
+    tenant,
     wasm::{
-        CapabilityProvider, Host, Runtime,
+        CapabilityProvider, ClockClient, FlowReader, GraphqlClient, Host, InferenceClient,
+        JwtClient, LocalStorageClient, MessageBus, Profile, Runtime, WitmProxyCtx,
         bindgen::{
             Plugin, UserInput,
-            witmproxy::plugin::capabilities::{Event as WasmEvent, EventKind},
+            witmproxy::plugin::capabilities::{CapabilityKind, Event as WasmEvent, EventKind},
         },
+        tape::Tape,
     },
 };
 
+/// A plugin returned no event data, ending the chain and blocking the flow
+#[derive(Debug, thiserror::Error)]
+#[error("Plugin {plugin_id} returned no event data; cannot continue processing")]
+pub struct PluginBlocked {
+    pub plugin_id: String,
+}
+
 pub struct PluginRegistry {
     plugins: HashMap<String, WitmPlugin>,
     pub db: Db,
     pub runtime: Runtime,
     env: &'static Env<'static>,
+    /// Recently intercepted flows, readable by plugins with `flow_reader`
+    flows: FlowLog,
+    /// Timelines of the flows in the log
+    timelines: FlowTimelines,
+    /// Plugin storage the flows in the log touched, if snapshotted
+    storage_snapshots: StorageSnapshots,
+    /// Key sets plugins with `jwt` verify tokens against
+    key_sets: KeySets,
+    /// Messages plugins with `messaging` exchange
+    bus: Bus,
+    /// Models plugins with `inference` run
+    models: Models,
+    /// How often plugins recently used their capabilities
+    uses: CapabilityUses,
+    /// Records plugins' capability calls, or answers them when replaying
+    tape: Option<Tape>,
+    /// Data removed from events before plugins are given them
+    redactions: Redactions,
+    /// Versions of installed plugins given copies of their events, by ID
+    candidates: HashMap<String, WitmPlugin>,
+    /// How the candidates' outcomes differ from the installed versions'
+    differences: Differences,
+    /// What each plugin's component was when it was loaded, by ID
+    attestations: HashMap<String, Attestation>,
+    /// Keys plugins have to be signed with
+    trusted_keys: TrustedKeys,
+    /// Plugins refused for failing integrity checks, by ID, with the reason
+    refused: HashMap<String, String>,
 }
 
 /// Result of handling a request through the plugin chain.
@@ -61,6 +127,8 @@ impl WasmEvent {
         let env = InboundContent::register_cel_env(env)?;
         let env = Connect::register_cel_env(env)?;
         let env = crate::events::timer::TimerEvent::register_cel_env(env)?;
+        let env = crate::events::raw_stream::RawStream::register_cel_env(env)?;
+        let env = crate::events::mqtt::MqttMessage::register_cel_env(env)?;
         let env = crate::plugins::cel::CelTime::register_cel_env(env)?;
         Ok(env)
     }
@@ -78,9 +146,66 @@ impl PluginRegistry {
             db,
             runtime,
             env,
+            flows: FlowLog::default(),
+            timelines: FlowTimelines::default(),
+            storage_snapshots: StorageSnapshots::default(),
+            key_sets: KeySets::default(),
+            bus: Bus::new(),
+            models: Models::default(),
+            uses: CapabilityUses::default(),
+            tape: None,
+            redactions: Redactions::default(),
+            candidates: HashMap::new(),
+            differences: Differences::default(),
+            attestations: HashMap::new(),
+            trusted_keys: TrustedKeys::default(),
+            refused: HashMap::new(),
         })
     }
 
+    /// Set the key sets plugins with the `jwt` capability verify tokens against
+    pub fn with_key_sets(mut self, key_sets: KeySets) -> Self {
+        self.key_sets = key_sets;
+        self
+    }
+
+    /// Set the models plugins with the `inference` capability run
+    pub fn with_models(mut self, models: Models) -> Self {
+        self.models = models;
+        self
+    }
+
+    /// Send the capability calls plugins make through `tape`, to record them
+    /// or to answer them from an earlier recording
+    pub fn with_tape(mut self, tape: Tape) -> Self {
+        self.tape = Some(tape);
+        self
+    }
+
+    /// Set the rules for data removed from events before plugins see them
+    pub fn with_redactions(mut self, redactions: Redactions) -> Self {
+        self.redactions = redactions;
+        self
+    }
+
+    /// Only run plugins signed with one of `trusted_keys`
+    pub fn with_trusted_keys(mut self, trusted_keys: TrustedKeys) -> Self {
+        self.trusted_keys = trusted_keys;
+        self
+    }
+
+    /// Snapshot the plugin storage each flow touches into `storage_snapshots`
+    pub fn with_storage_snapshots(mut self, storage_snapshots: StorageSnapshots) -> Self {
+        self.storage_snapshots = storage_snapshots;
+        self
+    }
+
+    /// The live redaction rules, shared with the web server so they can be
+    /// replaced without a restart
+    pub fn redactions(&self) -> Redactions {
+        self.redactions.clone()
+    }
+
     pub fn plugins(&self) -> &HashMap<String, WitmPlugin> {
         &self.plugins
     }
@@ -89,14 +214,196 @@ impl PluginRegistry {
         &mut self.plugins
     }
 
+    /// The log of recently intercepted flows, recorded by the proxy
+    pub fn flows(&self) -> &FlowLog {
+        &self.flows
+    }
+
+    /// The timelines of recently intercepted flows, recorded by the proxy
+    pub fn timelines(&self) -> &FlowTimelines {
+        &self.timelines
+    }
+
+    /// The plugin storage recently intercepted flows touched, recorded by
+    /// the proxy when enabled
+    pub fn storage_snapshots(&self) -> &StorageSnapshots {
+        &self.storage_snapshots
+    }
+
+    /// The bus plugins with `messaging` publish messages on, drained by the
+    /// proxy to [deliver](Self::deliver) them
+    pub fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    /// The capabilities granted to `plugin`, backed by this registry's state
+    /// and the event being handled, whose request runs `graphql`
+    fn capability_provider(
+        &self,
+        plugin: &WitmPlugin,
+        graphql: Option<&GraphqlOperation>,
+    ) -> CapabilityProvider {
+        let mut provider = CapabilityProvider::from(&plugin.capabilities);
+        if plugin.profile() == Profile::Deterministic && provider.clock().is_some() {
+            provider = provider.with_clock(ClockClient::frozen(Duration::ZERO));
+        }
+        let granted = |kind: CapabilityKind| {
+            plugin
+                .capabilities
+                .iter()
+                .any(|cap| cap.is_active() && cap.inner.kind == kind)
+        };
+        if granted(CapabilityKind::LocalStorage) {
+            provider = provider.with_local_storage(LocalStorageClient::for_plugin(plugin.id()));
+        }
+        if granted(CapabilityKind::FlowReader) {
+            let scope = plugin
+                .capabilities
+                .iter()
+                .find(|cap| cap.is_active() && cap.inner.kind == CapabilityKind::FlowReader)
+                .and_then(|cap| cap.cel.clone());
+            let reader = FlowReader::new(self.flows.clone(), tenant::current(), scope)
+                .with_redactions(self.redactions.clone(), &plugin.id());
+            provider = provider.with_flow_reader(reader);
+        }
+        if granted(CapabilityKind::Jwt) {
+            provider = provider.with_jwt(JwtClient::new(self.key_sets.clone()));
+        }
+        if granted(CapabilityKind::Graphql) {
+            provider = provider.with_graphql(GraphqlClient::new(graphql.cloned()));
+        }
+        if granted(CapabilityKind::Messaging) {
+            provider = provider.with_messaging(MessageBus::new(self.bus.clone(), plugin.id()));
+        }
+        if granted(CapabilityKind::Inference) {
+            provider = provider.with_inference(InferenceClient::new(self.models.clone()));
+        }
+        provider.with_uses(self.uses.clone(), plugin.id())
+    }
+
+    /// Count `event` against the quota of `plugin`'s capability to handle
+    /// it, returning false if the plugin is over quota and should skip it
+    async fn within_quota(&self, plugin: &WitmPlugin, event: &dyn Event) -> bool {
+        let Some(capability) = plugin
+            .capabilities
+            .iter()
+            .find(|cap| cap.granted && cap.inner.kind == event.capability())
+        else {
+            return true;
+        };
+        let quota = &capability.quota;
+        let body_bytes = match event.body_size() {
+            Some(size) => size,
+            None if quota.max_body_bytes_per_hour.is_some() => {
+                debug!(
+                    "Skipping plugin {}: body of unknown length with a byte quota",
+                    plugin.id()
+                );
+                flow_trace::record("quota", || {
+                    format!("{} skipped, body length unknown", plugin.id())
+                });
+                return false;
+            }
+            None => 0,
+        };
+
+        match CapabilityUsage::consume(
+            &self.db.pool,
+            &plugin.namespace,
+            &plugin.name,
+            &capability.inner.kind.to_string(),
+            quota,
+            body_bytes,
+            Utc::now(),
+        )
+        .await
+        {
+            Ok(true) => true,
+            Ok(false) => {
+                debug!("Skipping plugin {}: over quota", plugin.id());
+                flow_trace::record("quota", || format!("{} skipped, over quota", plugin.id()));
+                false
+            }
+            Err(e) => {
+                // Failing closed, as the quota can't be checked
+                warn!(
+                    "Failed to count quota usage of plugin {}: {}",
+                    plugin.id(),
+                    e
+                );
+                false
+            }
+        }
+    }
+
     pub async fn load_plugins(&mut self) -> Result<()> {
         let plugins = WitmPlugin::all(&mut self.db, &self.runtime.engine, self.env).await?;
         for plugin in plugins.into_iter() {
-            self.plugins.insert(plugin.id(), plugin);
+            let id = plugin.id();
+            let attestation = Attestation::verified(&plugin, &self.trusted_keys);
+            self.plugins.insert(id.clone(), plugin);
+            match attestation {
+                Ok(attestation) => {
+                    self.attestations.insert(id, attestation);
+                }
+                Err(e) => self.refuse(&id, &format!("{:#}", e)).await,
+            }
         }
         Ok(())
     }
 
+    /// Check each plugin still matches its attestation, returning the IDs
+    /// of those which don't with the reason
+    pub async fn verify_integrity(&self) -> Vec<(String, String)> {
+        let mut failures = Vec::new();
+        for (id, attestation) in &self.attestations {
+            let Some(plugin) = self.plugins.get(id) else {
+                continue;
+            };
+            if let Err(e) = attestation
+                .verify(plugin, &self.db.pool, &self.trusted_keys)
+                .await
+            {
+                failures.push((id.clone(), format!("{:#}", e)));
+            }
+        }
+        failures
+    }
+
+    /// Stop running plugin `id`, which failed an integrity check, until it's
+    /// reinstalled: its compiled component is dropped, and the failure
+    /// logged and added to the audit log
+    pub async fn refuse(&mut self, id: &str, reason: &str) {
+        error!(
+            target: "plugins",
+            plugin_id = %id,
+            reason = %reason,
+            "Plugin failed an integrity check; refusing to run it"
+        );
+        if let Some(plugin) = self.plugins.get_mut(id) {
+            plugin.component = None;
+            plugin.enabled = false;
+        }
+        self.attestations.remove(id);
+        self.refused.insert(id.to_string(), reason.to_string());
+        if let Err(e) = AuditEntry::record(
+            &self.db.pool,
+            "witmproxy",
+            AuditAction::PluginIntegrityFailure,
+            Some(id),
+            serde_json::json!({ "reason": reason }),
+        )
+        .await
+        {
+            warn!("Failed to record integrity failure of {}: {}", id, e);
+        }
+    }
+
+    /// Plugins refused for failing integrity checks, by ID, with the reason
+    pub fn refused(&self) -> &HashMap<String, String> {
+        &self.refused
+    }
+
     pub async fn plugin_from_component(&self, component_bytes: Vec<u8>) -> Result<WitmPlugin> {
         self.plugin_from_component_with_key(component_bytes, None)
             .await
@@ -107,6 +414,9 @@ impl PluginRegistry {
     /// If `expected_public_key` is provided, the plugin's embedded public key
     /// must match it exactly — this lets callers pin trust to a known author
     /// rather than accepting any self-signed component.
+    ///
+    /// The manifest is then linted (see [lint]); manifests which can't work
+    /// are rejected and likely mistakes are logged as warnings.
     pub async fn plugin_from_component_with_key(
         &self,
         component_bytes: Vec<u8>,
@@ -114,7 +424,7 @@ impl PluginRegistry {
     ) -> Result<WitmPlugin> {
         let component =
             wasmtime::component::Component::from_binary(&self.runtime.engine, &component_bytes)?;
-        let mut store = wasmtime::Store::new(&self.runtime.engine, Host::default());
+        let mut store = self.runtime.new_store();
         let instance = self
             .runtime
             .linker
@@ -185,17 +495,215 @@ impl PluginRegistry {
         let plugin = WitmPlugin::from(guest_result)
             .with_component(component, component_bytes)
             .compile_capability_scope_expressions(self.env)?;
+        for warning in lint::lint(&plugin, &plugin.component_bytes)? {
+            warn!("Plugin {}: {}", plugin.id(), warning);
+        }
         Ok(plugin)
     }
 
     pub async fn register_plugin(&mut self, plugin: WitmPlugin) -> Result<()> {
+        self.trusted_keys
+            .check(&plugin.publickey)
+            .with_context(|| format!("Refusing to install plugin {}", plugin.id()))?;
         // Upsert the given plugin into the database
         plugin.insert(&mut self.db).await?;
+        // A candidate was compared to the version being replaced
+        self.remove_candidate(&plugin.id());
+        // Its signature was verified as it was loaded from the component
+        self.attestations
+            .insert(plugin.id(), Attestation::of(&plugin)?);
+        self.refused.remove(&plugin.id());
         // Add it to the registry
         self.plugins.insert(plugin.id(), plugin);
         Ok(())
     }
 
+    /// Give `candidate`, another version of an installed plugin, a copy of
+    /// each event the installed version handles, recording where its outcome
+    /// differs (see [differential]). The candidate is granted what the
+    /// installed version is, and configured the same. Replaces any earlier
+    /// candidate.
+    pub fn add_candidate(&mut self, mut candidate: WitmPlugin) -> Result<()> {
+        let id = candidate.id();
+        let Some(live) = self.plugins.get(&id) else {
+            anyhow::bail!("Plugin {} is not installed", id);
+        };
+        for capability in &mut candidate.capabilities {
+            let granted = live
+                .capabilities
+                .iter()
+                .find(|cap| cap.inner.kind == capability.inner.kind);
+            capability.granted = granted.is_some_and(|cap| cap.granted);
+        }
+        candidate.configuration = live.configuration.clone();
+        self.differences.clear(&id);
+        self.candidates.insert(id, candidate);
+        Ok(())
+    }
+
+    /// Stop comparing the candidate of plugin `id`, returning it
+    pub fn remove_candidate(&mut self, id: &str) -> Option<WitmPlugin> {
+        self.differences.clear(id);
+        self.candidates.remove(id)
+    }
+
+    /// The capabilities of each installed plugin, by plugin ID
+    pub fn posture(&self) -> Vec<PluginPosture> {
+        let now = Utc::now();
+        let mut posture: Vec<_> = self
+            .plugins
+            .values()
+            .map(|plugin| PluginPosture::new(plugin, &self.uses, now))
+            .collect();
+        posture.sort_by(|a, b| a.plugin.cmp(&b.plugin));
+        posture
+    }
+
+    /// The installed plugins given `kind` events, for requests to `host` if
+    /// given
+    pub fn event_posture(&self, kind: EventKind, host: Option<&str>) -> Result<EventPosture> {
+        EventPosture::new(self.plugins.values(), kind, host, &self.uses, Utc::now())
+    }
+
+    /// How the candidate of plugin `id` compares to the installed version
+    pub fn differential_report(&self, id: &str) -> Option<DifferentialReport> {
+        let (live, candidate) = (self.plugins.get(id)?, self.candidates.get(id)?);
+        let (mirrored, differing, differences) = self.differences.get(id);
+        Some(DifferentialReport {
+            plugin_id: id.to_string(),
+            live_version: live.version.clone(),
+            candidate_version: candidate.version.clone(),
+            mirrored,
+            differing,
+            differences,
+        })
+    }
+
+    /// Copy `event` for the candidate of `plugin`, if it has one and the
+    /// event can be copied, returning the event to pass on and the copy
+    async fn mirror(
+        &self,
+        plugin: &WitmPlugin,
+        event: Box<dyn Event>,
+        store: &mut Store<Host>,
+    ) -> Result<(Box<dyn Event>, Option<Box<dyn Event>>)> {
+        if !self.candidates.contains_key(&plugin.id()) || !differential::mirrors(&*event) {
+            return Ok((event, None));
+        }
+        let (event, copy) = differential::split(event, store).await?;
+        Ok((event, Some(copy)))
+    }
+
+    /// Pass `input`, a copy of the event `plugin` was given, to its candidate
+    /// and record how the candidate's outcome differs from `live`
+    async fn compare_candidate(&self, plugin: &WitmPlugin, input: Box<dyn Event>, live: Outcome) {
+        let Some(candidate) = self.candidates.get(&plugin.id()) else {
+            return;
+        };
+        let kind = input.kind();
+        let outcome = self
+            .run_candidate(candidate, input)
+            .await
+            .unwrap_or_else(|e| Outcome::Failed {
+                error: e.to_string(),
+            });
+        let difference = differential::compare(kind, &live, &outcome);
+        flow_trace::record("candidate", || match &difference {
+            Some(difference) => format!(
+                "{} {} differs: {}",
+                candidate.id(),
+                candidate.version,
+                difference.changes.join("; ")
+            ),
+            None => format!("{} {} agrees", candidate.id(), candidate.version),
+        });
+        self.differences.record(&plugin.id(), difference);
+    }
+
+    /// The outcome of `event` after the installed version of a plugin
+    /// handled it
+    async fn live_outcome(&self, event: Box<dyn Event>) -> Outcome {
+        let mut store = self.new_store();
+        let recorded = match event.into_event_data(&mut store) {
+            Ok(event) => exercise::from_event(event, store).await,
+            Err(e) => Err(e),
+        };
+        recorded
+            .map(Outcome::Event)
+            .unwrap_or_else(|e| Outcome::Failed {
+                error: e.to_string(),
+            })
+    }
+
+    /// Pass `event` to a candidate alone, without quotas or the tape
+    async fn run_candidate(
+        &self,
+        candidate: &WitmPlugin,
+        event: Box<dyn Event>,
+    ) -> Result<Outcome> {
+        let Some(component) = &candidate.component else {
+            anyhow::bail!("Plugin component missing");
+        };
+        let graphql = event.graphql();
+        // Events are only mirrored once the installed version's redaction
+        // was found to apply, and the candidate shares its ID
+        let redaction = self.redactions.for_plugin(&candidate.id(), &*event);
+        let (plugin_instance, _, mut store) = self
+            .runtime
+            .instantiate_plugin_component(component, candidate.profile())
+            .await?;
+        let event_data = event.into_event_data(&mut store)?;
+        let (event_data, redacted) = match &redaction {
+            Some(redaction) => {
+                let (event_data, redacted) = redaction.apply(event_data, &mut store).await?;
+                (event_data, Some(redacted))
+            }
+            None => (event_data, None),
+        };
+        let provider = self.capability_provider(candidate, graphql.as_ref());
+        let cap_resource = store.data_mut().table.push(provider)?;
+        let config = candidate.configuration.clone();
+
+        let result = store
+            .run_concurrent(async move |store| {
+                let plugin = plugin_instance.witmproxy_plugin_witm_plugin().plugin();
+                let Ok(resource) = plugin.call_create(store, config).await? else {
+                    return Ok(None);
+                };
+                plugin
+                    .call_handle(store, resource, event_data, cap_resource)
+                    .await
+            })
+            .await??;
+        Ok(match result {
+            Some(event) => {
+                let event = match redacted {
+                    Some(redacted) => redacted.restore(event, &mut store).await?,
+                    None => event,
+                };
+                Outcome::Event(exercise::from_event(event, store).await?)
+            }
+            None => Outcome::Blocked {
+                plugin_id: candidate.id(),
+            },
+        })
+    }
+
+    /// Verify and register a plugin from a [PluginBundle], restoring the
+    /// grants, scopes and configuration it was exported with
+    ///
+    /// [PluginBundle]: crate::plugins::bundle::PluginBundle
+    pub async fn import_plugin(&mut self, bundled: &BundledPlugin) -> Result<String> {
+        let mut plugin = self
+            .plugin_from_component(bundled.component_bytes()?)
+            .await?;
+        bundled.apply_to(&mut plugin)?;
+        let plugin = plugin.compile_capability_scope_expressions(self.env)?;
+        let id = plugin.id();
+        self.register_plugin(plugin).await?;
+        Ok(id)
+    }
+
     pub async fn remove_plugin(
         &mut self,
         name: &str,
@@ -223,6 +731,10 @@ impl PluginRegistry {
         let mut removed_plugin_ids = Vec::new();
         for (ns, n) in deleted_plugins {
             let plugin_id = WitmPlugin::make_id(&ns, &n);
+            self.remove_candidate(&plugin_id);
+            self.bus.forget(&plugin_id);
+            self.attestations.remove(&plugin_id);
+            self.refused.remove(&plugin_id);
             if self.plugins.remove(&plugin_id).is_some() {
                 removed_plugin_ids.push(plugin_id);
             }
@@ -264,6 +776,16 @@ impl PluginRegistry {
         self.plugins.values().any(|p| p.can_handle(event))
     }
 
+    /// Whether any plugin has been granted the capability to handle `kind`
+    /// events, whatever its scope
+    pub fn handles_kind(&self, kind: EventKind) -> bool {
+        let kind = CapabilityKind::HandleEvent(kind);
+        self.plugins
+            .values()
+            .flat_map(|p| &p.capabilities)
+            .any(|cap| cap.granted && cap.inner.kind == kind)
+    }
+
     /// Returns the set of plugin IDs that are effective for a given tenant.
     /// Applies per-tenant enable/disable overrides on top of global enabled state.
     pub fn effective_plugins_for_tenant(
@@ -289,6 +811,23 @@ impl PluginRegistry {
         effective
     }
 
+    /// Returns the IDs of enabled plugins accepted by `allowed`, for use as an
+    /// effective set (e.g. a proxy listener's plugin filter).
+    pub fn plugins_matching(&self, allowed: impl Fn(&str) -> bool) -> HashSet<String> {
+        self.plugins
+            .iter()
+            .filter(|(id, plugin)| plugin.enabled && allowed(id))
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// Check if any plugins in an effective set can handle an event
+    pub fn can_handle_in_set(&self, event: &dyn Event, effective_set: &HashSet<String>) -> bool {
+        self.plugins
+            .values()
+            .any(|p| effective_set.contains(&p.id()) && p.can_handle(event))
+    }
+
     /// Resolve configuration for a plugin, merging tenant-specific config over global defaults.
     /// Tenant config values are stored as JSON strings in the database.
     pub fn resolve_config(
@@ -326,6 +865,69 @@ impl PluginRegistry {
         config
     }
 
+    /// Deliver `message` to the `on-message` export of each plugin subscribed
+    /// to its topic which is enabled and still granted `messaging`. Failures
+    /// are logged rather than returned, so one plugin can't keep a message
+    /// from the rest.
+    pub async fn deliver(&self, message: &Message) {
+        for plugin_id in self.bus.subscribers(&message.topic, &message.publisher) {
+            let Some(plugin) = self.plugins.get(&plugin_id) else {
+                continue;
+            };
+            let granted = plugin
+                .capabilities
+                .iter()
+                .any(|cap| cap.is_active() && cap.inner.kind == CapabilityKind::Messaging);
+            if !plugin.enabled || !granted {
+                continue;
+            }
+            let Some(component) = &plugin.component else {
+                continue;
+            };
+            if let Err(e) = self.deliver_to(plugin, component, message).await {
+                warn!(
+                    target: "plugins",
+                    plugin_id = %plugin_id,
+                    topic = %message.topic,
+                    error = %e,
+                    "Failed to deliver message"
+                );
+            }
+        }
+    }
+
+    async fn deliver_to(
+        &self,
+        plugin: &WitmPlugin,
+        component: &wasmtime::component::Component,
+        message: &Message,
+    ) -> Result<()> {
+        let (_, instance, mut store) = self
+            .runtime
+            .instantiate_plugin_component(component, plugin.profile())
+            .await?;
+        if let Some(tape) = &self.tape {
+            store.data_mut().witmproxy_ctx = WitmProxyCtx::builder().tape(tape.clone()).build();
+        }
+        let on_message = bus::on_message(&instance, &mut store)?;
+        let provider = self.capability_provider(plugin, None);
+        let cap_resource = store.data_mut().table.push(provider)?;
+        let args = (
+            message.topic.clone(),
+            message.payload.to_vec(),
+            message.publisher.clone(),
+            cap_resource,
+        );
+        store
+            .run_concurrent(async move |accessor| {
+                on_message.call_concurrent(accessor, args).await?;
+                Ok::<(), wasmtime::Error>(())
+            })
+            .await
+            .and_then(|result| result)?;
+        Ok(())
+    }
+
     /// Handle a generic event, passing it through all registered plugins, and returning the final [Event] (whose inner contents implement [Event]) and [Store] (for resolving any resource handles on the host side)
     /// Validates that the final [Event] matches the expected output type for its event kind, returning an error if not
     #[tracing::instrument(skip(self, event), fields(event_kind = ?event.kind()))]
@@ -347,6 +949,11 @@ impl PluginRegistry {
         );
 
         let mut current_event = event;
+        let upstream_addr = current_event.upstream_addr();
+        let upstream_cert = current_event.upstream_cert();
+        let connection = current_event.connection();
+        let findings = current_event.findings();
+        let graphql = current_event.graphql();
         let mut store = self.new_store();
         let mut executed_plugins = HashSet::new();
 
@@ -363,6 +970,7 @@ impl PluginRegistry {
 
             executed_plugins.insert(plugin.id());
             let kind = current_event.kind();
+            let started = Instant::now();
             let component = if let Some(c) = &plugin.component {
                 c
             } else {
@@ -374,27 +982,70 @@ impl PluginRegistry {
                 );
                 continue;
             };
+            let redaction = self.redactions.for_plugin(&plugin.id(), &*current_event);
+            if redaction
+                .as_ref()
+                .is_some_and(|redaction| !redaction.applies_to(&*current_event))
+            {
+                debug!("Skipping plugin {}: body can't be redacted", plugin.id());
+                flow_trace::record("redaction", || {
+                    format!("{} skipped, body can't be redacted", plugin.id())
+                });
+                continue;
+            }
+            if !self.within_quota(plugin, &*current_event).await {
+                continue;
+            }
+            self.uses.record(
+                &plugin.id(),
+                &current_event.capability().to_string(),
+                Utc::now(),
+            );
+            let (event, mirrored) = self.mirror(plugin, current_event, &mut store).await?;
+            current_event = event;
 
-            let (plugin_instance, component_store) =
-                match self.runtime.instantiate_plugin_component(component).await {
-                    Ok(pi) => pi,
-                    Err(e) => {
-                        warn!(
-                            target: "plugins",
-                            plugin_id = %plugin.id(),
-                            event_kind = kind.to_string(),
-                            error = %e,
-                            "Failed to instantiate plugin component; skipping"
-                        );
-                        continue;
-                    }
-                };
+            let (plugin_instance, instance, component_store) = match self
+                .runtime
+                .instantiate_plugin_component(component, plugin.profile())
+                .await
+            {
+                Ok(pi) => pi,
+                Err(e) => {
+                    warn!(
+                        target: "plugins",
+                        plugin_id = %plugin.id(),
+                        event_kind = kind.to_string(),
+                        error = %e,
+                        "Failed to instantiate plugin component; skipping"
+                    );
+                    flow_trace::record("plugin", || {
+                        format!("{} failed to instantiate, skipped: {}", plugin.id(), e)
+                    });
+                    timeline::record(
+                        "plugin",
+                        || format!("{} {}", plugin.id(), kind),
+                        started,
+                        || "failed to instantiate".to_string(),
+                    );
+                    continue;
+                }
+            };
 
             store = component_store;
+            if let Some(tape) = &self.tape {
+                store.data_mut().witmproxy_ctx = WitmProxyCtx::builder().tape(tape.clone()).build();
+            }
             let event_data = current_event.into_event_data(&mut store)?;
+            let (event_data, redacted) = match &redaction {
+                Some(redaction) => {
+                    let (event_data, redacted) = redaction.apply(event_data, &mut store).await?;
+                    (event_data, Some(redacted))
+                }
+                None => (event_data, None),
+            };
 
             // Build the capability provider based on the plugin's granted capabilities
-            let provider = CapabilityProvider::from(&plugin.capabilities);
+            let provider = self.capability_provider(plugin, graphql.as_ref());
             let cap_resource = store.data_mut().table.push(provider)?;
             let config = plugin.configuration.clone();
 
@@ -452,14 +1103,59 @@ impl PluginRegistry {
                     };
                     Ok(result)
                 })
-                .await??;
+                .await
+                .and_then(|result| result)
+                .inspect_err(|e| {
+                    flow_trace::record("plugin", || format!("{} failed: {}", plugin.id(), e));
+                    timeline::record(
+                        "plugin",
+                        || format!("{} {}", plugin.id(), kind),
+                        started,
+                        || e.to_string(),
+                    );
+                })?;
+            flow_trace::record("plugin", || match &guest_result {
+                Some(_) => format!(
+                    "{} handled {} in {:?}",
+                    plugin.id(),
+                    kind,
+                    started.elapsed()
+                ),
+                None => format!(
+                    "{} returned nothing for {} in {:?}",
+                    plugin.id(),
+                    kind,
+                    started.elapsed()
+                ),
+            });
+            timeline::record(
+                "plugin",
+                || format!("{} {}", plugin.id(), kind),
+                started,
+                || match &guest_result {
+                    Some(_) => "handled".to_string(),
+                    None => "blocked".to_string(),
+                },
+            );
             match guest_result {
                 Some(new_event_data) => {
+                    let new_event_data = match redacted {
+                        Some(redacted) => redacted.restore(new_event_data, &mut store).await?,
+                        None => new_event_data,
+                    };
                     // Create a new event from the returned Event for the next iteration
                     current_event = match new_event_data {
                         WasmEvent::Request(r) => {
                             let req = store.data_mut().http().table.delete(r)?;
-                            Box::new(req)
+                            match &connection {
+                                Some(connection) => Box::new(InterceptedRequest {
+                                    request: req,
+                                    connection: connection.clone(),
+                                    findings: findings.clone(),
+                                    graphql: graphql.clone(),
+                                }),
+                                None => Box::new(req),
+                            }
                         }
                         WasmEvent::Response(r) => {
                             let response = store.data_mut().http().table.delete(r.response)?;
@@ -467,16 +1163,44 @@ impl PluginRegistry {
                             Box::new(ContextualResponse {
                                 request: request_ctx,
                                 response,
+                                upstream_addr,
+                                upstream_cert: upstream_cert.clone(),
+                                findings: findings.clone(),
+                                graphql: graphql.clone(),
                             })
                         }
                         WasmEvent::InboundContent(c) => {
-                            let content = store.data_mut().table.delete(c)?;
+                            let mut content = store.data_mut().table.delete(c)?;
+                            if content.take_transform_request() {
+                                // The plugin's store goes with the transform,
+                                // so later plugins run in one of their own
+                                let plugin_store = std::mem::replace(&mut store, self.new_store());
+                                transform::spawn(
+                                    &mut content,
+                                    &instance,
+                                    plugin_store,
+                                    plugin.id(),
+                                )?;
+                            }
                             Box::new(content)
                         }
                         WasmEvent::Timer(ctx) => Box::new(crate::events::timer::TimerEvent {
                             timestamp: ctx.timestamp,
                         }),
+                        WasmEvent::RawStream(ctx) => {
+                            Box::new(crate::events::raw_stream::RawStream::from(ctx))
+                        }
+                        WasmEvent::MqttMessage(message) => {
+                            Box::new(crate::events::mqtt::MqttMessage::from(message))
+                        }
                     };
+                    if let Some(input) = mirrored {
+                        let (event, output) =
+                            differential::split(current_event, &mut store).await?;
+                        current_event = event;
+                        let live = self.live_outcome(output).await;
+                        self.compare_candidate(plugin, input, live).await;
+                    }
                 }
                 None => {
                     // Timer events may legitimately return None (side-effect only)
@@ -486,7 +1210,16 @@ impl PluginRegistry {
                         let event_data = Box::new(timer_event).into_event_data(&mut store)?;
                         return Ok((event_data, store));
                     }
-                    anyhow::bail!("Plugin returned no event data; cannot continue processing");
+                    if let Some(input) = mirrored {
+                        let live = Outcome::Blocked {
+                            plugin_id: plugin.id(),
+                        };
+                        self.compare_candidate(plugin, input, live).await;
+                    }
+                    return Err(PluginBlocked {
+                        plugin_id: plugin.id(),
+                    }
+                    .into());
                 }
             }
         }
@@ -506,11 +1239,7 @@ impl PluginRegistry {
         effective_set: &HashSet<String>,
         tenant_config: &[crate::db::tenants::TenantPluginConfig],
     ) -> Result<(WasmEvent, Store<Host>)> {
-        let any_plugins = self
-            .plugins
-            .values()
-            .any(|p| effective_set.contains(&p.id()) && p.can_handle(&*event));
-        if !any_plugins {
+        if !self.can_handle_in_set(&*event, effective_set) {
             debug!(
                 "No effective tenant plugins for event of kind: {:?}",
                 event.kind()
@@ -521,6 +1250,11 @@ impl PluginRegistry {
         }
 
         let mut current_event = event;
+        let upstream_addr = current_event.upstream_addr();
+        let upstream_cert = current_event.upstream_cert();
+        let connection = current_event.connection();
+        let findings = current_event.findings();
+        let graphql = current_event.graphql();
         let mut store = self.new_store();
         let mut executed_plugins = HashSet::new();
 
@@ -542,6 +1276,7 @@ impl PluginRegistry {
 
             executed_plugins.insert(plugin.id());
             let kind = current_event.kind();
+            let started = Instant::now();
             let component = if let Some(c) = &plugin.component {
                 c
             } else {
@@ -553,26 +1288,69 @@ impl PluginRegistry {
                 );
                 continue;
             };
+            let redaction = self.redactions.for_plugin(&plugin.id(), &*current_event);
+            if redaction
+                .as_ref()
+                .is_some_and(|redaction| !redaction.applies_to(&*current_event))
+            {
+                debug!("Skipping plugin {}: body can't be redacted", plugin.id());
+                flow_trace::record("redaction", || {
+                    format!("{} skipped, body can't be redacted", plugin.id())
+                });
+                continue;
+            }
+            if !self.within_quota(plugin, &*current_event).await {
+                continue;
+            }
+            self.uses.record(
+                &plugin.id(),
+                &current_event.capability().to_string(),
+                Utc::now(),
+            );
+            let (event, mirrored) = self.mirror(plugin, current_event, &mut store).await?;
+            current_event = event;
 
-            let (plugin_instance, component_store) =
-                match self.runtime.instantiate_plugin_component(component).await {
-                    Ok(pi) => pi,
-                    Err(e) => {
-                        warn!(
-                            target: "plugins",
-                            plugin_id = %plugin.id(),
-                            event_kind = kind.to_string(),
-                            error = %e,
-                            "Failed to instantiate plugin component; skipping"
-                        );
-                        continue;
-                    }
-                };
+            let (plugin_instance, instance, component_store) = match self
+                .runtime
+                .instantiate_plugin_component(component, plugin.profile())
+                .await
+            {
+                Ok(pi) => pi,
+                Err(e) => {
+                    warn!(
+                        target: "plugins",
+                        plugin_id = %plugin.id(),
+                        event_kind = kind.to_string(),
+                        error = %e,
+                        "Failed to instantiate plugin component; skipping"
+                    );
+                    flow_trace::record("plugin", || {
+                        format!("{} failed to instantiate, skipped: {}", plugin.id(), e)
+                    });
+                    timeline::record(
+                        "plugin",
+                        || format!("{} {}", plugin.id(), kind),
+                        started,
+                        || "failed to instantiate".to_string(),
+                    );
+                    continue;
+                }
+            };
 
             store = component_store;
+            if let Some(tape) = &self.tape {
+                store.data_mut().witmproxy_ctx = WitmProxyCtx::builder().tape(tape.clone()).build();
+            }
             let event_data = current_event.into_event_data(&mut store)?;
+            let (event_data, redacted) = match &redaction {
+                Some(redaction) => {
+                    let (event_data, redacted) = redaction.apply(event_data, &mut store).await?;
+                    (event_data, Some(redacted))
+                }
+                None => (event_data, None),
+            };
 
-            let provider = CapabilityProvider::from(&plugin.capabilities);
+            let provider = self.capability_provider(plugin, graphql.as_ref());
             let cap_resource = store.data_mut().table.push(provider)?;
             // Use tenant-resolved config
             let config = self.resolve_config(plugin, tenant_config);
@@ -629,14 +1407,59 @@ impl PluginRegistry {
                     };
                     Ok(result)
                 })
-                .await??;
+                .await
+                .and_then(|result| result)
+                .inspect_err(|e| {
+                    flow_trace::record("plugin", || format!("{} failed: {}", plugin.id(), e));
+                    timeline::record(
+                        "plugin",
+                        || format!("{} {}", plugin.id(), kind),
+                        started,
+                        || e.to_string(),
+                    );
+                })?;
+            flow_trace::record("plugin", || match &guest_result {
+                Some(_) => format!(
+                    "{} handled {} in {:?}",
+                    plugin.id(),
+                    kind,
+                    started.elapsed()
+                ),
+                None => format!(
+                    "{} returned nothing for {} in {:?}",
+                    plugin.id(),
+                    kind,
+                    started.elapsed()
+                ),
+            });
+            timeline::record(
+                "plugin",
+                || format!("{} {}", plugin.id(), kind),
+                started,
+                || match &guest_result {
+                    Some(_) => "handled".to_string(),
+                    None => "blocked".to_string(),
+                },
+            );
 
             match guest_result {
                 Some(new_event_data) => {
+                    let new_event_data = match redacted {
+                        Some(redacted) => redacted.restore(new_event_data, &mut store).await?,
+                        None => new_event_data,
+                    };
                     current_event = match new_event_data {
                         WasmEvent::Request(r) => {
                             let req = store.data_mut().http().table.delete(r)?;
-                            Box::new(req)
+                            match &connection {
+                                Some(connection) => Box::new(InterceptedRequest {
+                                    request: req,
+                                    connection: connection.clone(),
+                                    findings: findings.clone(),
+                                    graphql: graphql.clone(),
+                                }),
+                                None => Box::new(req),
+                            }
                         }
                         WasmEvent::Response(r) => {
                             let response = store.data_mut().http().table.delete(r.response)?;
@@ -644,16 +1467,44 @@ impl PluginRegistry {
                             Box::new(ContextualResponse {
                                 request: request_ctx,
                                 response,
+                                upstream_addr,
+                                upstream_cert: upstream_cert.clone(),
+                                findings: findings.clone(),
+                                graphql: graphql.clone(),
                             })
                         }
                         WasmEvent::InboundContent(c) => {
-                            let content = store.data_mut().table.delete(c)?;
+                            let mut content = store.data_mut().table.delete(c)?;
+                            if content.take_transform_request() {
+                                // The plugin's store goes with the transform,
+                                // so later plugins run in one of their own
+                                let plugin_store = std::mem::replace(&mut store, self.new_store());
+                                transform::spawn(
+                                    &mut content,
+                                    &instance,
+                                    plugin_store,
+                                    plugin.id(),
+                                )?;
+                            }
                             Box::new(content)
                         }
                         WasmEvent::Timer(ctx) => Box::new(crate::events::timer::TimerEvent {
                             timestamp: ctx.timestamp,
                         }),
+                        WasmEvent::RawStream(ctx) => {
+                            Box::new(crate::events::raw_stream::RawStream::from(ctx))
+                        }
+                        WasmEvent::MqttMessage(message) => {
+                            Box::new(crate::events::mqtt::MqttMessage::from(message))
+                        }
                     };
+                    if let Some(input) = mirrored {
+                        let (event, output) =
+                            differential::split(current_event, &mut store).await?;
+                        current_event = event;
+                        let live = self.live_outcome(output).await;
+                        self.compare_candidate(plugin, input, live).await;
+                    }
                 }
                 None => {
                     if kind == EventKind::Timer {
@@ -662,7 +1513,16 @@ impl PluginRegistry {
                         let event_data = Box::new(timer_event).into_event_data(&mut store)?;
                         return Ok((event_data, store));
                     }
-                    anyhow::bail!("Plugin returned no event data; cannot continue processing");
+                    if let Some(input) = mirrored {
+                        let live = Outcome::Blocked {
+                            plugin_id: plugin.id(),
+                        };
+                        self.compare_candidate(plugin, input, live).await;
+                    }
+                    return Err(PluginBlocked {
+                        plugin_id: plugin.id(),
+                    }
+                    .into());
                 }
             }
         }
@@ -712,6 +1572,7 @@ mod tests {
                         expression: cel_expression.into(),
                     },
                 },
+                quota: Default::default(),
                 cel: None,
             },
             Capability {
@@ -722,6 +1583,7 @@ mod tests {
                         expression: cel_expression.into(),
                     },
                 },
+                quota: Default::default(),
                 cel: None,
             },
             Capability {
@@ -732,6 +1594,7 @@ mod tests {
                         expression: cel_expression.into(),
                     },
                 },
+                quota: Default::default(),
                 cel: None,
             },
         ];
@@ -749,6 +1612,7 @@ mod tests {
             publickey: vec![],
             capabilities,
             configuration: vec![],
+            configuration_schema: vec![],
             metadata: std::collections::HashMap::new(),
             component,
         }
@@ -862,6 +1726,47 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_cel_filter_on_connection_names() -> Result<(), anyhow::Error> {
+        let (mut registry, _temp_dir) = create_plugin_registry().await?;
+        let cel_expression = "!request.host_mismatch() && connection.sni() != 'skip.example'";
+        register_test_plugin_with_cel_filter(&mut registry, cel_expression).await?;
+        let executed_plugins = HashSet::new();
+
+        let intercepted = |sni: &str| {
+            let req = Request::builder()
+                .method(Method::GET)
+                .uri("https://example.com/test")
+                .header("host", "example.com")
+                .body(Full::new(Bytes::from("test body")))
+                .unwrap();
+            let (request, _io) = WasiRequest::from_http(req);
+            Box::new(InterceptedRequest {
+                request,
+                connection: crate::proxy::vhost::ConnectionInfo::new("example.com", Some(sni)),
+                findings: Vec::new(),
+                graphql: None,
+            }) as Box<dyn Event>
+        };
+
+        let event = intercepted("example.com");
+        assert!(
+            registry
+                .find_first_unexecuted_plugin(&*event, &executed_plugins)
+                .is_some(),
+            "Request matching its SNI should match"
+        );
+
+        let event = intercepted("skip.example");
+        assert!(
+            registry
+                .find_first_unexecuted_plugin(&*event, &executed_plugins)
+                .is_none(),
+            "Request over a tunnel with a mismatched SNI should not match"
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_find_first_unexecuted_plugin_no_plugins() -> Result<(), anyhow::Error> {
         let (registry, _temp_dir) = create_plugin_registry().await?;
@@ -906,6 +1811,7 @@ mod tests {
                         expression: "true".into(),
                     },
                 },
+                quota: Default::default(),
                 cel: None,
             },
             Capability {
@@ -916,6 +1822,7 @@ mod tests {
                         expression: "true".to_string(),
                     },
                 },
+                quota: Default::default(),
                 cel: None,
             },
         ];
@@ -933,6 +1840,7 @@ mod tests {
             publickey: vec![],
             capabilities,
             configuration: vec![],
+            configuration_schema: vec![],
             metadata: std::collections::HashMap::new(),
             component,
         };
@@ -982,6 +1890,7 @@ mod tests {
                         expression: "true".into(),
                     },
                 },
+                quota: Default::default(),
                 cel: None,
             },
             Capability {
@@ -992,6 +1901,7 @@ mod tests {
                         expression: "true".to_string(),
                     },
                 },
+                quota: Default::default(),
                 cel: None,
             },
             Capability {
@@ -1002,6 +1912,7 @@ mod tests {
                         expression: "true".to_string(),
                     },
                 },
+                quota: Default::default(),
                 cel: None,
             },
         ];
@@ -1019,6 +1930,7 @@ mod tests {
             publickey: vec![],
             capabilities,
             configuration: vec![],
+            configuration_schema: vec![],
             metadata: std::collections::HashMap::new(),
             component,
         }
@@ -1123,6 +2035,7 @@ mod tests {
                             expression: "true".into(),
                         },
                     },
+                    quota: Default::default(),
                     cel: None,
                 },
                 Capability {
@@ -1133,6 +2046,7 @@ mod tests {
                             expression: "true".into(),
                         },
                     },
+                    quota: Default::default(),
                     cel: None,
                 },
             ];
@@ -1150,6 +2064,7 @@ mod tests {
                 publickey: vec![],
                 capabilities,
                 configuration: vec![],
+                configuration_schema: vec![],
                 metadata: std::collections::HashMap::new(),
                 component,
             };
@@ -0,0 +1,250 @@
+//! Streaming pattern replacement in content bodies.
+//!
+//! Plugins asking for `content.replace` hand the host a list of literal or
+//! regular expression replacements rather than reading the body themselves.
+//! Each is applied to the body as it streams by holding back the last few
+//! bytes of every chunk (one less than a literal's length, or a regular
+//! expression's maximum match length) until the next arrives, so a match
+//! straddling two chunks is found just as if the body had arrived whole.
+
+use anyhow::{Result, bail};
+use bytes::Bytes;
+use http_body::Frame;
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, StreamBody};
+use regex::bytes::Regex;
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+
+use crate::plugins::pattern;
+
+use crate::wasm::bindgen::witmproxy::plugin::capabilities::{
+    Pattern as WitPattern, Replacement as WitReplacement,
+};
+
+/// A replacement applied to a body, chunk by chunk
+#[derive(Debug, Clone)]
+pub struct Replacement {
+    regex: Regex,
+    replacement: Vec<u8>,
+    /// Whether `replacement` expands capture groups
+    expand: bool,
+    /// The longest match looked for across chunks
+    max_length: usize,
+    /// Matches left to replace, if limited
+    remaining: Option<usize>,
+    /// Bytes held back from the last chunk
+    carry: Vec<u8>,
+}
+
+impl Replacement {
+    /// Replace every occurrence of `literal` with `replacement`
+    pub fn literal(literal: &str, replacement: &str) -> Result<Self> {
+        if literal.is_empty() {
+            bail!("Literal patterns can't be empty");
+        }
+        Ok(Self {
+            regex: Regex::new(&regex::escape(literal))?,
+            replacement: replacement.as_bytes().to_vec(),
+            expand: false,
+            max_length: literal.len(),
+            remaining: None,
+            carry: Vec::new(),
+        })
+    }
+
+    /// Replace every match of `expression`, assumed to be no longer than
+    /// `max_length` bytes, with `replacement`, expanding capture groups
+    pub fn regex(expression: &str, max_length: usize, replacement: &str) -> Result<Self> {
+        if max_length == 0 {
+            bail!("Regular expressions need a maximum match length");
+        }
+        Ok(Self {
+            regex: pattern::compile_bytes(expression)?,
+            replacement: replacement.as_bytes().to_vec(),
+            expand: true,
+            max_length,
+            remaining: None,
+            carry: Vec::new(),
+        })
+    }
+
+    /// Only replace the first `limit` matches
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.remaining = Some(limit);
+        self
+    }
+
+    /// Pass the next chunk through, returning the bytes which can be sent on.
+    /// `last` flushes anything held back once the body has ended.
+    pub fn push(&mut self, chunk: &[u8], last: bool) -> Vec<u8> {
+        let mut buf = std::mem::take(&mut self.carry);
+        buf.extend_from_slice(chunk);
+        if self.remaining == Some(0) {
+            return buf;
+        }
+
+        // Matches starting from here might be cut short by the end of the
+        // chunk, while any starting earlier would be longer than the longest
+        let safe = if last {
+            buf.len()
+        } else {
+            buf.len().saturating_sub(self.max_length - 1)
+        };
+        let mut out = Vec::with_capacity(buf.len());
+        let mut pos = 0;
+        let mut exhausted = false;
+        for caps in self.regex.captures_iter(&buf) {
+            let m = caps.get(0).expect("group 0 is always set");
+            if m.start() >= safe {
+                break;
+            }
+            out.extend_from_slice(&buf[pos..m.start()]);
+            if self.expand {
+                caps.expand(&self.replacement, &mut out);
+            } else {
+                out.extend_from_slice(&self.replacement);
+            }
+            pos = m.end();
+            if let Some(remaining) = self.remaining.as_mut() {
+                *remaining -= 1;
+                if *remaining == 0 {
+                    exhausted = true;
+                    break;
+                }
+            }
+        }
+
+        let keep_from = if exhausted { buf.len() } else { safe.max(pos) };
+        out.extend_from_slice(&buf[pos..keep_from]);
+        self.carry = buf[keep_from..].to_vec();
+        out
+    }
+}
+
+impl TryFrom<WitReplacement> for Replacement {
+    type Error = anyhow::Error;
+
+    fn try_from(replacement: WitReplacement) -> Result<Self> {
+        let parsed = match replacement.pattern {
+            WitPattern::Literal(literal) => Self::literal(&literal, &replacement.replacement)?,
+            WitPattern::Regex(regex) => Self::regex(
+                &regex.expression,
+                regex.max_length as usize,
+                &replacement.replacement,
+            )?,
+        };
+        Ok(match replacement.limit {
+            Some(limit) => parsed.with_limit(limit as usize),
+            None => parsed,
+        })
+    }
+}
+
+/// Apply `replacements` to `body` in order, each to the output of the last
+pub fn apply(
+    body: UnsyncBoxBody<Bytes, ErrorCode>,
+    replacements: Vec<Replacement>,
+) -> UnsyncBoxBody<Bytes, ErrorCode> {
+    let frames = futures::stream::unfold(Some((body, replacements)), |state| async move {
+        let (mut body, mut replacements) = state?;
+        loop {
+            let (mut chunk, last) = match body.frame().await {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => (data.to_vec(), false),
+                    Err(frame) => return Some((Ok(frame), Some((body, replacements)))),
+                },
+                Some(Err(e)) => return Some((Err(e), None)),
+                None => (Vec::new(), true),
+            };
+            for replacement in replacements.iter_mut() {
+                chunk = replacement.push(&chunk, last);
+            }
+            if last {
+                return (!chunk.is_empty()).then(|| (Ok(Frame::data(Bytes::from(chunk))), None));
+            }
+            if !chunk.is_empty() {
+                let frame = Frame::data(Bytes::from(chunk));
+                return Some((Ok(frame), Some((body, replacements))));
+            }
+        }
+    });
+    StreamBody::new(frames).boxed_unsync()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Push `input` split at every possible point, checking the output
+    /// matches `expected` however it's split
+    fn assert_replaced(replacement: &Replacement, input: &str, expected: &str) {
+        for split in 0..=input.len() {
+            let mut replacement = replacement.clone();
+            let mut out = replacement.push(&input.as_bytes()[..split], false);
+            out.extend(replacement.push(&input.as_bytes()[split..], false));
+            out.extend(replacement.push(&[], true));
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                expected,
+                "split at {split}"
+            );
+        }
+    }
+
+    #[test]
+    fn literals_straddling_chunks_are_replaced() {
+        let replacement = Replacement::literal("<head>", "<head><style></style>").unwrap();
+        assert_replaced(
+            &replacement,
+            "<html><head><title>x</title></head></html>",
+            "<html><head><style></style><title>x</title></head></html>",
+        );
+        assert_replaced(&replacement, "no match here", "no match here");
+        assert!(Replacement::literal("", "x").is_err());
+    }
+
+    #[test]
+    fn regular_expressions_expand_captures() {
+        let replacement = Replacement::regex(r"<head[^>]*>", 64, "$0<script></script>").unwrap();
+        assert_replaced(
+            &replacement,
+            r#"<head lang="en"><title>x</title></head>"#,
+            r#"<head lang="en"><script></script><title>x</title></head>"#,
+        );
+
+        // Greedy matches aren't cut short at the end of a chunk
+        let replacement = Replacement::regex(r"a+", 4, "[$0]").unwrap();
+        assert_replaced(
+            &replacement,
+            "bbbbbbbaaabbbbbbbbbaabbbbbbbbaaaa",
+            "bbbbbbb[aaa]bbbbbbbbb[aa]bbbbbbbb[aaaa]",
+        );
+        assert!(Replacement::regex("(", 16, "").is_err());
+    }
+
+    #[test]
+    fn limits_stop_replacing() {
+        let replacement = Replacement::literal("ab", "X").unwrap().with_limit(2);
+        assert_replaced(&replacement, "ab ab ab ab", "X X ab ab");
+    }
+
+    #[tokio::test]
+    async fn bodies_are_replaced_as_they_stream() {
+        let chunks = ["<html><he", "ad><title>", "x</title></head></html>"]
+            .map(|chunk| Ok::<_, ErrorCode>(Frame::data(Bytes::from(chunk))));
+        let body = StreamBody::new(futures::stream::iter(chunks)).boxed_unsync();
+        let replacements = vec![
+            Replacement::literal("<head>", "<head><style></style>").unwrap(),
+            Replacement::literal("<style>", "<style>a{}").unwrap(),
+        ];
+        let body = apply(body, replacements)
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes();
+        assert_eq!(
+            body,
+            "<html><head><style>a{}</style><title>x</title></head></html>"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! Typed plugin settings. A plugin declares the settings it accepts in its
+//! manifest's `configuration` schema, and receives the values set for it
+//! when it's created for each event. Values set from the CLI or web API
+//! arrive as text, and are parsed into the declared type before they're
+//! stored.
+
+use anyhow::{Result, anyhow, bail};
+use base64::Engine;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+
+use crate::wasm::bindgen::{ActualInput, InputSchema, InputType, UserInput};
+
+/// Parse `raw` into the type `schema` declares
+pub fn parse_input(schema: &InputSchema, raw: &str) -> Result<ActualInput> {
+    let name = &schema.name;
+    Ok(match &schema.input_type {
+        InputType::Str => ActualInput::Str(raw.to_string()),
+        InputType::Boolean => match raw.trim().to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => ActualInput::Boolean(true),
+            "false" | "no" | "off" | "0" => ActualInput::Boolean(false),
+            _ => bail!("{} must be true or false, not '{}'", name, raw),
+        },
+        InputType::Number => match raw.trim().parse::<f64>() {
+            Ok(number) if number.is_finite() => ActualInput::Number(number),
+            _ => bail!("{} must be a number, not '{}'", name, raw),
+        },
+        InputType::Select(options) => {
+            if !options.iter().any(|option| option == raw) {
+                bail!(
+                    "{} must be one of {}, not '{}'",
+                    name,
+                    options.join(", "),
+                    raw
+                );
+            }
+            ActualInput::Select(raw.to_string())
+        }
+        InputType::Datetime => {
+            parse_datetime(name, raw)?;
+            ActualInput::Datetime(raw.trim().to_string())
+        }
+        InputType::Daterange => {
+            let (start, end) = raw
+                .split_once('/')
+                .ok_or_else(|| anyhow!("{} must be a range, as <start>/<end>", name))?;
+            let (start, end) = (start.trim(), end.trim());
+            if parse_datetime(name, start)? > parse_datetime(name, end)? {
+                bail!("{} must not end before it starts", name);
+            }
+            ActualInput::Daterange((start.to_string(), end.to_string()))
+        }
+        InputType::Binary => ActualInput::Binary(
+            base64::engine::general_purpose::STANDARD
+                .decode(raw.trim())
+                .map_err(|_| anyhow!("{} must be base64-encoded", name))?,
+        ),
+        InputType::File => bail!("{} is a file, which can't be set as text", name),
+    })
+}
+
+/// An RFC 3339 date and time, or a date alone (as midnight UTC)
+fn parse_datetime(name: &str, raw: &str) -> Result<NaiveDateTime> {
+    let raw = raw.trim();
+    chrono::DateTime::parse_from_rfc3339(raw)
+        .map(|datetime| datetime.naive_utc())
+        .or_else(|_| {
+            NaiveDate::parse_from_str(raw, "%Y-%m-%d")
+                .map(|date| date.and_time(NaiveTime::default()))
+        })
+        .map_err(|_| {
+            anyhow!(
+                "{} must be a date (2026-01-31) or RFC 3339 date and time, not '{}'",
+                name,
+                raw
+            )
+        })
+}
+
+/// Check the setting `name` is declared in `schema`, and parse `raw` into its
+/// type. Plugins which declare no settings accept any, as text.
+pub fn check_input(schema: &[InputSchema], name: &str, raw: &str) -> Result<ActualInput> {
+    if schema.is_empty() {
+        return Ok(ActualInput::Str(raw.to_string()));
+    }
+    match schema.iter().find(|input| input.name == name) {
+        Some(input) => parse_input(input, raw),
+        None => bail!(
+            "Unknown setting '{}', expected one of: {}",
+            name,
+            schema
+                .iter()
+                .map(|input| input.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Settings which are required and have no default, but haven't been set
+pub fn missing_required<'a>(schema: &'a [InputSchema], config: &[UserInput]) -> Vec<&'a str> {
+    schema
+        .iter()
+        .filter(|input| !input.optional && input.default.is_none())
+        .filter(|input| !config.iter().any(|set| set.name == input.name))
+        .map(|input| input.name.as_str())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(name: &str, input_type: InputType) -> InputSchema {
+        InputSchema {
+            name: name.to_string(),
+            input_type,
+            optional: false,
+            default: None,
+            description: None,
+        }
+    }
+
+    #[test]
+    fn text_is_parsed_into_the_declared_type() {
+        let schema = vec![
+            input("enabled", InputType::Boolean),
+            input("limit", InputType::Number),
+            input(
+                "mode",
+                InputType::Select(vec!["block".to_string(), "log".to_string()]),
+            ),
+            input("since", InputType::Datetime),
+            input("window", InputType::Daterange),
+            input("key", InputType::Binary),
+            input("rules", InputType::File),
+        ];
+
+        assert!(matches!(
+            check_input(&schema, "enabled", "Yes").unwrap(),
+            ActualInput::Boolean(true)
+        ));
+        assert!(check_input(&schema, "enabled", "maybe").is_err());
+        assert!(matches!(
+            check_input(&schema, "limit", "2.5").unwrap(),
+            ActualInput::Number(n) if n == 2.5
+        ));
+        assert!(check_input(&schema, "limit", "NaN").is_err());
+        assert!(matches!(
+            check_input(&schema, "mode", "log").unwrap(),
+            ActualInput::Select(mode) if mode == "log"
+        ));
+        assert!(check_input(&schema, "mode", "LOG").is_err());
+        assert!(check_input(&schema, "since", "2026-01-31").is_ok());
+        assert!(check_input(&schema, "since", "2026-01-31T08:00:00+01:00").is_ok());
+        assert!(check_input(&schema, "since", "yesterday").is_err());
+        assert!(matches!(
+            check_input(&schema, "window", "2026-01-01 / 2026-02-01").unwrap(),
+            ActualInput::Daterange((start, end)) if start == "2026-01-01" && end == "2026-02-01"
+        ));
+        assert!(check_input(&schema, "window", "2026-02-01/2026-01-01").is_err());
+        assert!(matches!(
+            check_input(&schema, "key", "AAEC").unwrap(),
+            ActualInput::Binary(bytes) if bytes == [0, 1, 2]
+        ));
+        assert!(check_input(&schema, "rules", "rules.txt").is_err());
+        assert!(check_input(&schema, "unknown", "value").is_err());
+    }
+
+    #[test]
+    fn undeclared_settings_are_text() {
+        assert!(matches!(
+            check_input(&[], "greeting", "hello").unwrap(),
+            ActualInput::Str(greeting) if greeting == "hello"
+        ));
+    }
+
+    #[test]
+    fn required_settings_without_defaults_must_be_set() {
+        let mut optional = input("optional", InputType::Str);
+        optional.optional = true;
+        let mut defaulted = input("defaulted", InputType::Number);
+        defaulted.default = Some(ActualInput::Number(1.0));
+        let schema = vec![
+            input("token", InputType::Str),
+            input("host", InputType::Str),
+            optional,
+            defaulted,
+        ];
+        let config = vec![UserInput {
+            name: "host".to_string(),
+            value: ActualInput::Str("example.com".to_string()),
+        }];
+        assert_eq!(missing_required(&schema, &config), vec!["token"]);
+    }
+}
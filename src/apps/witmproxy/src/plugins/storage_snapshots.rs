@@ -0,0 +1,212 @@
+//! Snapshots of the plugin storage each flow touched, for finding out later
+//! why a plugin decided what it did, ex: which counter crossed a threshold.
+//!
+//! With `plugins.snapshot_storage` enabled, the `local_storage` calls
+//! plugins make while handling a flow are recorded in order with the values
+//! read or written, and served with the value each key was left with by
+//! `/api/manage/flows/{id}/storage`, which viewer tokens can't read.
+//! Snapshots are kept for as many flows as the flow log keeps by default.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use base64::{Engine as _, engine::general_purpose};
+use salvo::oapi::ToSchema;
+use serde::Serialize;
+
+use crate::proxy::flows::DEFAULT_FLOW_LOG_CAPACITY;
+
+tokio::task_local! {
+    static CURRENT: StorageSnapshot;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageOperation {
+    Get,
+    Set,
+    Delete,
+}
+
+/// A plugin's call to its storage while handling a flow
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct StorageAccess {
+    /// ID of the plugin, ex: `@ops/rate-limit`
+    pub plugin: String,
+    pub operation: StorageOperation,
+    pub key: String,
+    /// The value read or written, as base64; none for missing keys and
+    /// deletes
+    pub value: Option<String>,
+}
+
+/// A key a plugin accessed, and the value it was left with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct StorageKey {
+    pub plugin: String,
+    pub key: String,
+    /// As base64; none if missing or deleted
+    pub value: Option<String>,
+}
+
+/// The plugin storage a flow touched
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct FlowStorage {
+    pub flow_id: String,
+    /// In the order plugins made them
+    pub accesses: Vec<StorageAccess>,
+    /// Sorted by plugin and key
+    pub keys: Vec<StorageKey>,
+}
+
+/// The storage accesses of a flow. Cheap to clone; all clones share the same
+/// accesses.
+#[derive(Clone)]
+pub struct StorageSnapshot {
+    id: String,
+    accesses: Arc<Mutex<Vec<StorageAccess>>>,
+}
+
+impl StorageSnapshot {
+    fn new(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            accesses: Arc::default(),
+        }
+    }
+
+    pub fn record(
+        &self,
+        plugin: &str,
+        operation: StorageOperation,
+        key: &str,
+        value: Option<&[u8]>,
+    ) {
+        self.accesses.lock().unwrap().push(StorageAccess {
+            plugin: plugin.to_string(),
+            operation,
+            key: key.to_string(),
+            value: value.map(|value| general_purpose::STANDARD.encode(value)),
+        });
+    }
+
+    pub fn storage(&self) -> FlowStorage {
+        let accesses = self.accesses.lock().unwrap().clone();
+        let mut keys = BTreeMap::new();
+        for access in &accesses {
+            keys.insert(
+                (access.plugin.clone(), access.key.clone()),
+                access.value.clone(),
+            );
+        }
+        FlowStorage {
+            flow_id: self.id.clone(),
+            accesses,
+            keys: keys
+                .into_iter()
+                .map(|((plugin, key), value)| StorageKey { plugin, key, value })
+                .collect(),
+        }
+    }
+}
+
+/// Run `fut` with `snapshot` as the current snapshot, so [record] adds to it
+pub async fn scoped<F: Future>(snapshot: Option<StorageSnapshot>, fut: F) -> F::Output {
+    match snapshot {
+        Some(snapshot) => CURRENT.scope(snapshot, fut).await,
+        None => fut.await,
+    }
+}
+
+/// Add a storage access to the snapshot of the flow being handled, if any
+pub fn record(plugin: &str, operation: StorageOperation, key: &str, value: Option<&[u8]>) {
+    let _ = CURRENT.try_with(|snapshot| snapshot.record(plugin, operation, key, value));
+}
+
+/// The storage snapshots of the latest flows. Cheap to clone; all clones
+/// share the same snapshots. The default keeps none.
+#[derive(Clone, Default)]
+pub struct StorageSnapshots {
+    snapshots: Arc<Mutex<VecDeque<StorageSnapshot>>>,
+    capacity: usize,
+}
+
+impl StorageSnapshots {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            snapshots: Arc::default(),
+            capacity,
+        }
+    }
+
+    /// Keep the snapshots of as many flows as the flow log keeps by default
+    pub fn enabled() -> Self {
+        Self::new(DEFAULT_FLOW_LOG_CAPACITY)
+    }
+
+    /// Start the snapshot of flow `id`, unless none are kept
+    pub fn start(&self, id: &str) -> Option<StorageSnapshot> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let snapshot = StorageSnapshot::new(id);
+        let mut snapshots = self.snapshots.lock().unwrap();
+        if snapshots.len() == self.capacity {
+            snapshots.pop_front();
+        }
+        snapshots.push_back(snapshot.clone());
+        Some(snapshot)
+    }
+
+    /// The storage flow `id` touched, if its snapshot is still kept
+    pub fn get(&self, id: &str) -> Option<FlowStorage> {
+        self.snapshots
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|snapshot| snapshot.id == id)
+            .map(StorageSnapshot::storage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn accesses_are_recorded_for_the_current_flow() {
+        assert!(StorageSnapshots::default().start("0").is_none());
+
+        let snapshots = StorageSnapshots::new(1);
+        let snapshot = snapshots.start("1");
+        scoped(snapshot, async {
+            record("@ops/limit", StorageOperation::Get, "count", Some(b"9"));
+            record("@ops/limit", StorageOperation::Set, "count", Some(b"10"));
+            record("@ops/limit", StorageOperation::Delete, "seen", None);
+            record("@ops/auth", StorageOperation::Get, "token", None);
+        })
+        .await;
+        record("@ops/limit", StorageOperation::Set, "count", Some(b"11"));
+
+        let storage = snapshots.get("1").unwrap();
+        assert_eq!(storage.accesses.len(), 4);
+        assert_eq!(storage.accesses[0].value.as_deref(), Some("OQ=="));
+        let keys: Vec<_> = storage
+            .keys
+            .iter()
+            .map(|k| (k.plugin.as_str(), k.key.as_str(), k.value.as_deref()))
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                ("@ops/auth", "token", None),
+                ("@ops/limit", "count", Some("MTA=")),
+                ("@ops/limit", "seen", None),
+            ]
+        );
+
+        snapshots.start("2");
+        assert!(snapshots.get("1").is_none());
+    }
+}
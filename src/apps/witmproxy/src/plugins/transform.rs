@@ -0,0 +1,106 @@
+//! Host-mediated body transforms.
+//!
+//! A plugin handling inbound content can call `content.transform-body`
+//! rather than reading the body and replacing it with a stream of its own.
+//! Once it returns, the body is passed through its `transform-chunk` export
+//! one chunk at a time by a task holding the plugin's store. The next chunk
+//! is only read once there's room for the output of the last among the
+//! [MAX_BUFFERED_CHUNKS] waiting for the client, so a slow client slows
+//! reading from the server rather than filling memory, and the plugin needs
+//! no streams or spawned tasks of its own.
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body::Frame;
+use http_body_util::{BodyExt, StreamBody};
+use tokio::sync::mpsc;
+use tracing::warn;
+use wasmtime::Store;
+use wasmtime::component::{Instance, TypedFunc};
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+
+use crate::events::content::InboundContent;
+use crate::wasm::Host;
+
+/// The interface plugins transforming bodies export
+pub const TRANSFORM_INTERFACE: &str = "witmproxy:plugin/body-transform@0.0.6";
+
+/// Transformed chunks waiting for the client before the next is read
+pub const MAX_BUFFERED_CHUNKS: usize = 4;
+
+type TransformChunk = TypedFunc<(Vec<u8>, bool), (Vec<u8>,)>;
+
+/// The `transform-chunk` export of a plugin instance
+fn transform_chunk(instance: &Instance, store: &mut Store<Host>) -> Result<TransformChunk> {
+    let interface = instance
+        .get_export_index(&mut *store, None, TRANSFORM_INTERFACE)
+        .with_context(|| {
+            format!("plugin asked to transform a body but doesn't export {TRANSFORM_INTERFACE}")
+        })?;
+    let func = instance
+        .get_export_index(&mut *store, Some(&interface), "transform-chunk")
+        .context("plugin's body-transform export has no transform-chunk function")?;
+    Ok(instance.get_typed_func(&mut *store, &func)?)
+}
+
+/// Replace the body of `content` with one passed through the
+/// `transform-chunk` export of `instance`, which runs in `store` until the
+/// body has been transformed or the client has gone
+pub fn spawn(
+    content: &mut InboundContent,
+    instance: &Instance,
+    mut store: Store<Host>,
+    plugin_id: String,
+) -> Result<()> {
+    let transform = transform_chunk(instance, &mut store)?;
+    let Some(mut body) = content.body()? else {
+        return Ok(());
+    };
+    let (tx, rx) = mpsc::channel::<Result<Frame<Bytes>, ErrorCode>>(MAX_BUFFERED_CHUNKS);
+    content
+        .set_body(StreamBody::new(tokio_stream::wrappers::ReceiverStream::new(rx)).boxed_unsync());
+
+    tokio::spawn(async move {
+        let result = store
+            .run_concurrent(async move |accessor| {
+                loop {
+                    let (chunk, last) = match body.frame().await {
+                        Some(Ok(frame)) => match frame.into_data() {
+                            Ok(data) => (data.to_vec(), false),
+                            // Trailers aren't passed on to the plugin
+                            Err(_) => continue,
+                        },
+                        Some(Err(e)) => {
+                            let _ = tx.send(Err(e)).await;
+                            return Ok(());
+                        }
+                        None => (Vec::new(), true),
+                    };
+                    let output = match transform.call_concurrent(accessor, (chunk, last)).await {
+                        Ok((output,)) => output,
+                        Err(e) => {
+                            // Cut the body short rather than let it look complete
+                            let message = format!("Body transform failed: {}", e);
+                            let _ = tx.send(Err(ErrorCode::InternalError(Some(message)))).await;
+                            return Err(e);
+                        }
+                    };
+                    if !output.is_empty()
+                        && tx.send(Ok(Frame::data(Bytes::from(output)))).await.is_err()
+                    {
+                        // The client has gone
+                        return Ok(());
+                    }
+                    if last {
+                        return Ok::<(), wasmtime::Error>(());
+                    }
+                }
+            })
+            .await
+            .and_then(|result| result);
+        if let Err(e) = result {
+            warn!("Plugin {} failed transforming a body: {}", plugin_id, e);
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,850 @@
+//! Schema inference from observed traffic, for documenting APIs which come
+//! without documentation.
+//!
+//! With `proxy.infer_schemas` enabled, every intercepted flow is recorded
+//! against its host, method and path, with ID-like path segments (numbers,
+//! UUIDs, long hex strings) replaced by parameters. JSON request and response
+//! bodies are merged into a shape per operation and status code, noting the
+//! types each field has held and which fields are always present. Schemas
+//! are saved to the database periodically, and can be exported as OpenAPI 3
+//! YAML with `witm schema export <host>` or from
+//! `GET /api/manage/schemas/{host}`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use http_body_util::BodyExt;
+use http_body_util::combinators::UnsyncBoxBody;
+use hyper::body::Body;
+use hyper::header::{CONTENT_ENCODING, CONTENT_TYPE, HeaderMap};
+use hyper::{Request, Response};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+
+/// Bodies larger than this many bytes aren't inferred from
+pub const MAX_OBSERVED_BODY: usize = 1024 * 1024;
+
+/// Elements of each array merged into its item shape
+const MAX_SAMPLED_ITEMS: usize = 50;
+
+/// Properties recorded for each object; maps keyed by IDs would otherwise
+/// grow without bound
+const MAX_PROPERTIES: usize = 200;
+
+/// Paths recorded for each host
+const MAX_PATHS: usize = 500;
+
+/// How deeply nested values are followed
+const MAX_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum JsonType {
+    Null,
+    Boolean,
+    Integer,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+/// The shape of every JSON value seen in one place
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Shape {
+    #[serde(default)]
+    types: BTreeSet<JsonType>,
+    /// Formats of the strings seen, with `""` for strings of no format
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    formats: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    properties: BTreeMap<String, Shape>,
+    /// Properties present in every object seen
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    required: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    objects: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    items: Option<Box<Shape>>,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+impl Shape {
+    pub fn from_value(value: &Value) -> Self {
+        let mut shape = Self::default();
+        shape.observe(value);
+        shape
+    }
+
+    /// Merge `value` into the shape
+    pub fn observe(&mut self, value: &Value) {
+        self.observe_at(value, 0);
+    }
+
+    fn observe_at(&mut self, value: &Value, depth: usize) {
+        if depth > MAX_DEPTH {
+            return;
+        }
+        match value {
+            Value::Null => {
+                self.types.insert(JsonType::Null);
+            }
+            Value::Bool(_) => {
+                self.types.insert(JsonType::Boolean);
+            }
+            Value::Number(n) => {
+                self.types.insert(if n.is_f64() {
+                    JsonType::Number
+                } else {
+                    JsonType::Integer
+                });
+            }
+            Value::String(s) => {
+                self.types.insert(JsonType::String);
+                self.formats
+                    .insert(string_format(s).unwrap_or("").to_string());
+            }
+            Value::Array(items) => {
+                self.types.insert(JsonType::Array);
+                let shape = self.items.get_or_insert_with(Default::default);
+                for item in items.iter().take(MAX_SAMPLED_ITEMS) {
+                    shape.observe_at(item, depth + 1);
+                }
+            }
+            Value::Object(object) => {
+                self.types.insert(JsonType::Object);
+                if self.objects == 0 {
+                    self.required = object.keys().cloned().collect();
+                } else {
+                    self.required.retain(|key| object.contains_key(key));
+                }
+                self.objects += 1;
+                for (key, value) in object {
+                    if !self.properties.contains_key(key) && self.properties.len() >= MAX_PROPERTIES
+                    {
+                        continue;
+                    }
+                    self.properties
+                        .entry(key.clone())
+                        .or_default()
+                        .observe_at(value, depth + 1);
+                }
+            }
+        }
+    }
+
+    /// The shape as an OpenAPI 3.0 schema object
+    pub fn to_openapi(&self) -> Value {
+        let mut types: Vec<_> = self
+            .types
+            .iter()
+            .copied()
+            .filter(|t| *t != JsonType::Null)
+            .collect();
+        // Integers are numbers, so a field holding both is a number
+        if types.contains(&JsonType::Number) {
+            types.retain(|t| *t != JsonType::Integer);
+        }
+        let mut schema = match types.as_slice() {
+            [] => Map::new(),
+            [only] => self.typed_schema(*only),
+            several => {
+                let mut schema = Map::new();
+                schema.insert(
+                    "oneOf".to_string(),
+                    several
+                        .iter()
+                        .map(|t| Value::Object(self.typed_schema(*t)))
+                        .collect(),
+                );
+                schema
+            }
+        };
+        if self.types.contains(&JsonType::Null) {
+            schema.insert("nullable".to_string(), Value::Bool(true));
+        }
+        Value::Object(schema)
+    }
+
+    fn typed_schema(&self, json_type: JsonType) -> Map<String, Value> {
+        let mut schema = Map::new();
+        let name = match json_type {
+            JsonType::Null => return schema,
+            JsonType::Boolean => "boolean",
+            JsonType::Integer => "integer",
+            JsonType::Number => "number",
+            JsonType::String => "string",
+            JsonType::Array => "array",
+            JsonType::Object => "object",
+        };
+        schema.insert("type".to_string(), name.into());
+        match json_type {
+            JsonType::String => {
+                let mut formats = self.formats.iter();
+                let only = match (formats.next(), formats.next()) {
+                    (Some(format), None) if !format.is_empty() => Some(format),
+                    _ => None,
+                };
+                if let Some(format) = only {
+                    schema.insert("format".to_string(), format.as_str().into());
+                }
+            }
+            JsonType::Array => {
+                let items = self
+                    .items
+                    .as_ref()
+                    .map(|items| items.to_openapi())
+                    .unwrap_or_else(|| json!({}));
+                schema.insert("items".to_string(), items);
+            }
+            JsonType::Object => {
+                if !self.properties.is_empty() {
+                    schema.insert(
+                        "properties".to_string(),
+                        self.properties
+                            .iter()
+                            .map(|(name, shape)| (name.clone(), shape.to_openapi()))
+                            .collect::<Map<_, _>>()
+                            .into(),
+                    );
+                }
+                if !self.required.is_empty() {
+                    schema.insert(
+                        "required".to_string(),
+                        self.required.iter().cloned().collect::<Vec<_>>().into(),
+                    );
+                }
+            }
+            _ => {}
+        }
+        schema
+    }
+}
+
+/// The OpenAPI format of a string, where it plainly has one
+fn string_format(s: &str) -> Option<&'static str> {
+    if chrono::DateTime::parse_from_rfc3339(s).is_ok() {
+        Some("date-time")
+    } else if chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").is_ok() {
+        Some("date")
+    } else if uuid::Uuid::try_parse(s).is_ok() && s.len() == 36 {
+        Some("uuid")
+    } else if (s.starts_with("https://") || s.starts_with("http://")) && url::Url::parse(s).is_ok()
+    {
+        Some("uri")
+    } else if s.split_once('@').is_some_and(|(user, domain)| {
+        !user.is_empty() && domain.contains('.') && !s.contains(char::is_whitespace)
+    }) {
+        Some("email")
+    } else {
+        None
+    }
+}
+
+/// Whether a path segment looks like an identifier rather than a fixed name
+fn is_identifier(segment: &str) -> bool {
+    let hex = segment.len() >= 16 && segment.chars().all(|c| c.is_ascii_hexdigit());
+    let mixed = segment.len() >= 20
+        && segment.chars().any(|c| c.is_ascii_digit())
+        && segment.chars().any(|c| c.is_ascii_alphabetic())
+        && segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    segment.chars().all(|c| c.is_ascii_digit())
+        || uuid::Uuid::try_parse(segment).is_ok()
+        || hex
+        || mixed
+}
+
+/// `path` with identifier segments replaced by `{id}`, `{id2}` and so on
+pub fn path_template(path: &str) -> String {
+    let mut parameters = 0;
+    let segments: Vec<_> = path
+        .split('/')
+        .map(|segment| {
+            if segment.is_empty() || !is_identifier(segment) {
+                return segment.to_string();
+            }
+            parameters += 1;
+            match parameters {
+                1 => "{id}".to_string(),
+                n => format!("{{id{}}}", n),
+            }
+        })
+        .collect();
+    match segments.join("/") {
+        template if template.is_empty() => "/".to_string(),
+        template => template,
+    }
+}
+
+/// A response seen for an operation
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct ObservedResponse {
+    seen: u64,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    content_types: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body: Option<Shape>,
+}
+
+/// A method seen on a path
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct ObservedOperation {
+    seen: u64,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    query: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request: Option<Shape>,
+    #[serde(default)]
+    responses: BTreeMap<u16, ObservedResponse>,
+}
+
+/// Everything inferred about one host's API
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HostSchema {
+    #[serde(default)]
+    schemes: BTreeSet<String>,
+    /// Operations by path template, then lowercase method
+    #[serde(default)]
+    paths: BTreeMap<String, BTreeMap<String, ObservedOperation>>,
+}
+
+impl HostSchema {
+    fn operation(&mut self, endpoint: &Endpoint) -> Option<&mut ObservedOperation> {
+        if !self.paths.contains_key(&endpoint.path) && self.paths.len() >= MAX_PATHS {
+            return None;
+        }
+        Some(
+            self.paths
+                .entry(endpoint.path.clone())
+                .or_default()
+                .entry(endpoint.method.clone())
+                .or_default(),
+        )
+    }
+
+    pub fn summary(&self, host: &str) -> ApiSchemaSummary {
+        ApiSchemaSummary {
+            host: host.to_string(),
+            paths: self.paths.len(),
+            operations: self.paths.values().map(BTreeMap::len).sum(),
+            observations: self
+                .paths
+                .values()
+                .flat_map(BTreeMap::values)
+                .map(|operation| operation.seen)
+                .sum(),
+        }
+    }
+
+    /// The schema as an OpenAPI 3.0 document
+    pub fn to_openapi(&self, host: &str) -> Value {
+        let servers: Vec<_> = self
+            .schemes
+            .iter()
+            .map(|scheme| json!({ "url": format!("{}://{}", scheme, host) }))
+            .collect();
+        let paths: Map<_, _> = self
+            .paths
+            .iter()
+            .map(|(path, operations)| {
+                let operations: Map<_, _> = operations
+                    .iter()
+                    .map(|(method, operation)| {
+                        (method.clone(), operation_to_openapi(path, operation))
+                    })
+                    .collect();
+                (path.clone(), Value::Object(operations))
+            })
+            .collect();
+        json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": host,
+                "description": "Inferred by witmproxy from observed traffic",
+                "version": "0.0.0",
+            },
+            "servers": servers,
+            "paths": paths,
+        })
+    }
+}
+
+fn operation_to_openapi(path: &str, operation: &ObservedOperation) -> Value {
+    let mut parameters: Vec<_> = path
+        .split('/')
+        .filter_map(|segment| segment.strip_prefix('{')?.strip_suffix('}'))
+        .map(|name| {
+            json!({ "name": name, "in": "path", "required": true, "schema": { "type": "string" } })
+        })
+        .collect();
+    parameters.extend(operation.query.iter().map(|name| {
+        json!({ "name": name, "in": "query", "required": false, "schema": { "type": "string" } })
+    }));
+
+    let responses: Map<_, _> = operation
+        .responses
+        .iter()
+        .map(|(status, response)| {
+            let mut object = json!({ "description": observed(response.seen) });
+            if let Some(body) = &response.body {
+                let content_type = response
+                    .content_types
+                    .iter()
+                    .find(|content_type| content_type.contains("json"))
+                    .map(String::as_str)
+                    .unwrap_or("application/json");
+                object["content"] = json!({ content_type: { "schema": body.to_openapi() } });
+            }
+            (status.to_string(), object)
+        })
+        .collect();
+
+    let mut object = json!({
+        "description": observed(operation.seen),
+        "responses": responses,
+    });
+    if !parameters.is_empty() {
+        object["parameters"] = parameters.into();
+    }
+    if let Some(request) = &operation.request {
+        object["requestBody"] = json!({
+            "content": { "application/json": { "schema": request.to_openapi() } }
+        });
+    }
+    object
+}
+
+fn observed(times: u64) -> String {
+    match times {
+        1 => "Observed once".to_string(),
+        n => format!("Observed {} times", n),
+    }
+}
+
+/// Render an OpenAPI document as YAML
+pub fn to_yaml(document: &Value) -> anyhow::Result<String> {
+    Ok(serde_yaml::to_string(document)?)
+}
+
+/// An inferred schema, as listed by the management API
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ApiSchemaSummary {
+    pub host: String,
+    pub paths: usize,
+    pub operations: usize,
+    /// Requests seen across every operation
+    pub observations: u64,
+}
+
+/// The operation a flow belongs to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    pub host: String,
+    /// Lowercase, as OpenAPI names operations
+    pub method: String,
+    /// Path template, as made by [path_template]
+    pub path: String,
+}
+
+impl Endpoint {
+    pub fn new(host: &str, method: &str, path: &str) -> Self {
+        Self {
+            host: host.to_ascii_lowercase(),
+            method: method.to_ascii_lowercase(),
+            path: path_template(path),
+        }
+    }
+}
+
+/// Whether a body with these headers is JSON which can be read as is
+fn is_plain_json(headers: &HeaderMap) -> bool {
+    let json = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.to_ascii_lowercase().contains("json"));
+    let encoded = headers
+        .get(CONTENT_ENCODING)
+        .is_some_and(|value| !value.as_bytes().eq_ignore_ascii_case(b"identity"));
+    json && !encoded
+}
+
+#[derive(Default)]
+struct Schemas {
+    hosts: BTreeMap<String, HostSchema>,
+    /// Hosts changed since the last [ApiSchemas::take_changed]
+    changed: BTreeSet<String>,
+}
+
+/// The live set of inferred schemas. Cheap to clone; all clones share the
+/// same schemas.
+#[derive(Clone, Default)]
+pub struct ApiSchemas {
+    inner: Arc<Mutex<Schemas>>,
+}
+
+impl std::fmt::Debug for ApiSchemas {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApiSchemas")
+            .field("hosts", &self.inner.lock().unwrap().hosts.len())
+            .finish()
+    }
+}
+
+impl ApiSchemas {
+    /// Add a schema saved earlier, without marking it changed
+    pub fn load(&self, host: &str, schema: HostSchema) {
+        self.inner
+            .lock()
+            .unwrap()
+            .hosts
+            .insert(host.to_ascii_lowercase(), schema);
+    }
+
+    pub fn get(&self, host: &str) -> Option<HostSchema> {
+        self.inner
+            .lock()
+            .unwrap()
+            .hosts
+            .get(&host.to_ascii_lowercase())
+            .cloned()
+    }
+
+    pub fn summaries(&self) -> Vec<ApiSchemaSummary> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .hosts
+            .iter()
+            .map(|(host, schema)| schema.summary(host))
+            .collect()
+    }
+
+    /// Forget everything inferred about `host`, returning whether there was
+    /// anything
+    pub fn remove(&self, host: &str) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let host = host.to_ascii_lowercase();
+        inner.changed.remove(&host);
+        inner.hosts.remove(&host).is_some()
+    }
+
+    /// The schemas changed since the last call, for saving
+    pub fn take_changed(&self) -> Vec<(String, HostSchema)> {
+        let mut inner = self.inner.lock().unwrap();
+        let changed = std::mem::take(&mut inner.changed);
+        changed
+            .into_iter()
+            .filter_map(|host| {
+                let schema = inner.hosts.get(&host)?.clone();
+                Some((host, schema))
+            })
+            .collect()
+    }
+
+    fn update(&self, endpoint: &Endpoint, f: impl FnOnce(&mut ObservedOperation)) {
+        let mut inner = self.inner.lock().unwrap();
+        let host = inner.hosts.entry(endpoint.host.clone()).or_default();
+        if let Some(operation) = host.operation(endpoint) {
+            f(operation);
+            inner.changed.insert(endpoint.host.clone());
+        }
+    }
+
+    /// Record a request, inferring from its body once it has been sent if
+    /// it's JSON. Returns the request to send on, and the endpoint to
+    /// record its response against.
+    pub fn observe_request(
+        &self,
+        req: Request<UnsyncBoxBody<Bytes, ErrorCode>>,
+        default_host: &str,
+    ) -> (Request<UnsyncBoxBody<Bytes, ErrorCode>>, Endpoint) {
+        let uri = req.uri();
+        let endpoint = Endpoint::new(
+            uri.host().unwrap_or(default_host),
+            req.method().as_str(),
+            uri.path(),
+        );
+        let scheme = uri.scheme_str().unwrap_or("https").to_string();
+        let query: Vec<_> = uri
+            .query()
+            .map(|query| {
+                url::form_urlencoded::parse(query.as_bytes())
+                    .map(|(name, _)| name.into_owned())
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.update(&endpoint, |operation| {
+            operation.seen += 1;
+            operation.query.extend(query);
+        });
+        self.inner
+            .lock()
+            .unwrap()
+            .hosts
+            .entry(endpoint.host.clone())
+            .or_default()
+            .schemes
+            .insert(scheme);
+
+        if !is_plain_json(req.headers()) {
+            return (req, endpoint);
+        }
+        let schemas = self.clone();
+        let observed = endpoint.clone();
+        let req = req.map(|body| {
+            ObservedBody::new(body, move |bytes| {
+                if let Ok(value) = serde_json::from_slice::<Value>(bytes) {
+                    schemas.update(&observed, |operation| {
+                        operation
+                            .request
+                            .get_or_insert_with(Default::default)
+                            .observe(&value)
+                    });
+                }
+            })
+            .boxed_unsync()
+        });
+        (req, endpoint)
+    }
+
+    /// Record the response to a request to `endpoint`, inferring from its
+    /// body once it has been sent if it's JSON
+    pub fn observe_response(
+        &self,
+        endpoint: &Endpoint,
+        response: Response<UnsyncBoxBody<Bytes, ErrorCode>>,
+    ) -> Response<UnsyncBoxBody<Bytes, ErrorCode>> {
+        let status = response.status().as_u16();
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| {
+                value
+                    .split(';')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_ascii_lowercase()
+            });
+        self.update(endpoint, |operation| {
+            let observed = operation.responses.entry(status).or_default();
+            observed.seen += 1;
+            observed.content_types.extend(content_type);
+        });
+
+        if !is_plain_json(response.headers()) {
+            return response;
+        }
+        let schemas = self.clone();
+        let endpoint = endpoint.clone();
+        response.map(|body| {
+            ObservedBody::new(body, move |bytes| {
+                if let Ok(value) = serde_json::from_slice::<Value>(bytes) {
+                    schemas.update(&endpoint, |operation| {
+                        operation
+                            .responses
+                            .entry(status)
+                            .or_default()
+                            .body
+                            .get_or_insert_with(Default::default)
+                            .observe(&value)
+                    });
+                }
+            })
+            .boxed_unsync()
+        })
+    }
+}
+
+type OnComplete = Box<dyn FnOnce(&[u8]) + Send>;
+
+/// A body which passes its frames through unchanged, keeping a copy of them
+/// to hand to a callback once the whole body has been sent. Bodies larger
+/// than [MAX_OBSERVED_BODY] are passed through without being kept.
+pub struct ObservedBody {
+    inner: UnsyncBoxBody<Bytes, ErrorCode>,
+    buffer: Vec<u8>,
+    on_complete: Option<OnComplete>,
+}
+
+impl ObservedBody {
+    pub fn new(
+        inner: UnsyncBoxBody<Bytes, ErrorCode>,
+        on_complete: impl FnOnce(&[u8]) + Send + 'static,
+    ) -> Self {
+        let too_large = inner
+            .size_hint()
+            .upper()
+            .is_some_and(|upper| upper > MAX_OBSERVED_BODY as u64);
+        Self {
+            inner,
+            buffer: Vec::new(),
+            on_complete: (!too_large).then(|| Box::new(on_complete) as OnComplete),
+        }
+    }
+
+    fn complete(&mut self) {
+        if let Some(on_complete) = self.on_complete.take() {
+            on_complete(&std::mem::take(&mut self.buffer));
+        }
+    }
+}
+
+impl Body for ObservedBody {
+    type Data = Bytes;
+    type Error = ErrorCode;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
+        let frame = match Pin::new(&mut self.inner).poll_frame(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(frame) => frame,
+        };
+        match &frame {
+            Some(Ok(frame)) => {
+                let data = frame.data_ref().filter(|_| self.on_complete.is_some());
+                if let Some(data) = data {
+                    if self.buffer.len() + data.len() > MAX_OBSERVED_BODY {
+                        self.on_complete = None;
+                        self.buffer = Vec::new();
+                    } else {
+                        self.buffer.extend_from_slice(data);
+                    }
+                }
+                if self.inner.is_end_stream() {
+                    self.complete();
+                }
+            }
+            Some(Err(_)) => {
+                self.on_complete = None;
+                self.buffer = Vec::new();
+            }
+            None => self.complete(),
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::Full;
+
+    fn body(json: &Value) -> UnsyncBoxBody<Bytes, ErrorCode> {
+        Full::new(Bytes::from(json.to_string()))
+            .map_err(|never| match never {})
+            .boxed_unsync()
+    }
+
+    #[test]
+    fn identifiers_become_parameters() {
+        assert_eq!(path_template("/users/42/posts"), "/users/{id}/posts");
+        assert_eq!(
+            path_template("/orgs/6f1c2a0e-5b7d-4c1e-9a57-2d3c4b5a6f70/repos/deadbeefdeadbeef"),
+            "/orgs/{id}/repos/{id2}"
+        );
+        assert_eq!(path_template("/v2/users/me"), "/v2/users/me");
+        assert_eq!(path_template(""), "/");
+    }
+
+    #[test]
+    fn shapes_merge_across_observations() {
+        let mut shape = Shape::from_value(&json!({
+            "id": 1,
+            "email": "a@example.com",
+            "tags": ["x"],
+            "score": 1,
+        }));
+        shape.observe(&json!({
+            "id": 2,
+            "email": "b@example.com",
+            "tags": [],
+            "score": 2.5,
+            "deleted_at": null,
+        }));
+
+        assert_eq!(
+            shape.to_openapi(),
+            json!({
+                "type": "object",
+                "properties": {
+                    "deleted_at": { "nullable": true },
+                    "email": { "type": "string", "format": "email" },
+                    "id": { "type": "integer" },
+                    "score": { "type": "number" },
+                    "tags": { "type": "array", "items": { "type": "string" } },
+                },
+                "required": ["email", "id", "score", "tags"],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn flows_are_inferred_into_openapi() {
+        let schemas = ApiSchemas::default();
+        for (id, name) in [(1, "Rex"), (2, "Fido")] {
+            let req = Request::post(format!("https://api.example.com/pets/{}?verbose=1", id))
+                .header(CONTENT_TYPE, "application/json")
+                .body(body(&json!({ "name": name })))
+                .unwrap();
+            let (req, endpoint) = schemas.observe_request(req, "api.example.com");
+            req.into_body().collect().await.unwrap();
+
+            let response = Response::builder()
+                .status(201)
+                .header(CONTENT_TYPE, "application/json; charset=utf-8")
+                .body(body(&json!({ "id": id, "name": name })))
+                .unwrap();
+            let response = schemas.observe_response(&endpoint, response);
+            response.into_body().collect().await.unwrap();
+        }
+
+        assert_eq!(
+            schemas.summaries(),
+            vec![ApiSchemaSummary {
+                host: "api.example.com".to_string(),
+                paths: 1,
+                operations: 1,
+                observations: 2,
+            }]
+        );
+        let changed = schemas.take_changed();
+        assert_eq!(changed.len(), 1);
+        assert!(schemas.take_changed().is_empty());
+
+        let document = changed[0].1.to_openapi("api.example.com");
+        assert_eq!(document["servers"][0]["url"], "https://api.example.com");
+        let operation = &document["paths"]["/pets/{id}"]["post"];
+        assert_eq!(operation["parameters"][0]["name"], "id");
+        assert_eq!(operation["parameters"][1]["name"], "verbose");
+        assert_eq!(
+            operation["requestBody"]["content"]["application/json"]["schema"]["required"],
+            json!(["name"])
+        );
+        assert_eq!(
+            operation["responses"]["201"]["content"]["application/json"]["schema"]["properties"]["id"],
+            json!({ "type": "integer" })
+        );
+        assert!(to_yaml(&document).unwrap().contains("/pets/{id}:"));
+    }
+}
@@ -0,0 +1,262 @@
+//! Hosts the proxy refuses to reach, and how each refusal looks to clients.
+//!
+//! Apps render a failed CONNECT poorly, so each `[[proxy.block_rules]]`
+//! table picks the way matching hosts are refused:
+//!
+//! ```toml
+//! [[proxy.block_rules]]
+//! host = "*.ads.example"
+//! behavior = "no-content"
+//!
+//! [[proxy.block_rules]]
+//! host = "telemetry.example"
+//! behavior = "reset"
+//!
+//! [[proxy.block_rules]]
+//! host = "social.example"
+//! behavior = "redirect"
+//! location = "https://intranet.example/blocked?host={host}"
+//! ```
+//!
+//! `page`, `no-content` and `redirect` answer HTTP requests, so tunnels to
+//! matching hosts are intercepted. `reset` and `tls-alert` refuse the tunnel
+//! itself, and fall back to the block page for requests which aren't
+//! tunneled, such as plain HTTP ones.
+
+use std::sync::Arc;
+
+use anyhow::{Result, bail};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::upgrade::Upgraded;
+use hyper::{Response, StatusCode, header};
+use hyper_util::rt::TokioIo;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::proxy::host_limits::matches_host;
+use crate::proxy::pages::{ErrorPages, ErrorResponse, FLOW_ID_HEADER, FlowInfo};
+
+/// Shown as the blocking "plugin" on block pages, and noted on flows, for
+/// requests refused by block rules
+pub const BLOCKED_BY_RULE: &str = "block rules";
+
+/// A fatal `access_denied` alert, in a TLS 1.2 record as clients expect
+/// before the version is negotiated
+const ACCESS_DENIED_ALERT: [u8; 7] = [0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x31];
+
+/// How requests to a blocked host are refused
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BlockBehavior {
+    /// The proxy's block page, with a 403 status
+    #[default]
+    Page,
+    /// The connection is reset as soon as the tunnel opens
+    Reset,
+    /// The TLS handshake is refused with an `access_denied` alert
+    TlsAlert,
+    /// An empty 204 response
+    NoContent,
+    /// A redirect to the rule's `location`
+    Redirect,
+}
+
+/// A host refused with the given behavior
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockRule {
+    /// Host name, or `*.` followed by a domain to match its subdomains
+    pub host: String,
+    #[serde(default)]
+    pub behavior: BlockBehavior,
+    /// Where `redirect` sends clients, with `{host}` replaced by the blocked
+    /// host
+    #[serde(default)]
+    pub location: Option<String>,
+}
+
+/// The configured block rules, in order
+#[derive(Debug, Clone, Default)]
+pub struct BlockRules {
+    rules: Arc<Vec<BlockRule>>,
+}
+
+impl BlockRules {
+    pub fn new(rules: Vec<BlockRule>) -> Result<Self> {
+        for rule in &rules {
+            if rule.behavior == BlockBehavior::Redirect && rule.location.is_none() {
+                bail!("Block rule for {} redirects without a location", rule.host);
+            }
+        }
+        Ok(Self {
+            rules: Arc::new(rules),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The first rule matching `host`, if it's blocked
+    pub fn rule_for(&self, host: &str) -> Option<&BlockRule> {
+        self.rules
+            .iter()
+            .find(|rule| matches_host(&rule.host, host))
+    }
+
+    /// Whether tunnels to `authority` have to be intercepted to answer the
+    /// requests sent through them
+    pub fn intercepts(&self, authority: &str) -> bool {
+        crate::proxy::parse_authority_host_port(authority, 443).is_ok_and(|(host, _)| {
+            self.rule_for(&host).is_some_and(|rule| {
+                !matches!(
+                    rule.behavior,
+                    BlockBehavior::Reset | BlockBehavior::TlsAlert
+                )
+            })
+        })
+    }
+
+    /// The response to a request to `flow`'s host, if it's blocked
+    pub fn respond(&self, pages: &ErrorPages, flow: &FlowInfo) -> Option<ErrorResponse> {
+        let rule = self.rule_for(&flow.host)?;
+        let response = match (rule.behavior, &rule.location) {
+            (BlockBehavior::NoContent, _) => empty(StatusCode::NO_CONTENT, flow),
+            (BlockBehavior::Redirect, Some(location)) => {
+                let mut response = empty(StatusCode::FOUND, flow);
+                if let Ok(location) = location.replace("{host}", &flow.host).parse() {
+                    response.headers_mut().insert(header::LOCATION, location);
+                }
+                response
+            }
+            _ => pages.blocked(&format!("{} is blocked", flow.host), BLOCKED_BY_RULE, flow),
+        };
+        Some(response)
+    }
+}
+
+fn empty(status: StatusCode, flow: &FlowInfo) -> ErrorResponse {
+    Response::builder()
+        .status(status)
+        .header(header::CACHE_CONTROL, "no-store")
+        .header(FLOW_ID_HEADER, flow.id.as_str())
+        .body(
+            Empty::<Bytes>::new()
+                .map_err(|never| match never {})
+                .boxed_unsync(),
+        )
+        .expect("Could not construct block Response")
+}
+
+/// Close `stream` with a TCP reset rather than an orderly shutdown
+pub fn reset(stream: TcpStream) {
+    // A zero linger time makes closing the socket send RST
+    let _ = stream.set_linger(Some(std::time::Duration::ZERO));
+    drop(stream);
+}
+
+/// Refuse the TLS handshake a client starts on `stream`, once its
+/// ClientHello arrives
+pub async fn refuse_handshake<IO>(mut stream: IO) -> std::io::Result<()>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut hello = [0u8; 512];
+    if stream.read(&mut hello).await? == 0 {
+        return Ok(());
+    }
+    stream.write_all(&ACCESS_DENIED_ALERT).await?;
+    stream.shutdown().await
+}
+
+/// Refuse a CONNECT tunnel to a host blocked with `behavior`, once it's
+/// opened
+pub async fn refuse_tunnel(upgraded: Upgraded, behavior: BlockBehavior) -> std::io::Result<()> {
+    match behavior {
+        // Tunnels on the proxy's own listeners are plain TCP connections
+        BlockBehavior::Reset => {
+            if let Ok(parts) = upgraded.downcast::<TokioIo<TcpStream>>() {
+                reset(parts.io.into_inner());
+            }
+            Ok(())
+        }
+        _ => refuse_handshake(TokioIo::new(upgraded)).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> BlockRules {
+        BlockRules::new(vec![
+            BlockRule {
+                host: "*.ads.example".to_string(),
+                behavior: BlockBehavior::NoContent,
+                location: None,
+            },
+            BlockRule {
+                host: "social.example".to_string(),
+                behavior: BlockBehavior::Redirect,
+                location: Some("https://intranet.example/blocked?host={host}".to_string()),
+            },
+            BlockRule {
+                host: "telemetry.example".to_string(),
+                behavior: BlockBehavior::Reset,
+                location: None,
+            },
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn blocked_requests_follow_their_rule() {
+        let rules = rules();
+        let pages = ErrorPages::default();
+
+        let response = rules
+            .respond(&pages, &FlowInfo::new("cdn.ads.example"))
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = rules
+            .respond(&pages, &FlowInfo::new("social.example"))
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://intranet.example/blocked?host=social.example"
+        );
+
+        // Resets can't be sent once a request is read, so the page is served
+        let response = rules
+            .respond(&pages, &FlowInfo::new("telemetry.example"))
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        assert!(
+            rules
+                .respond(&pages, &FlowInfo::new("example.com"))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn only_tunnels_answered_over_http_are_intercepted() {
+        let rules = rules();
+        assert!(rules.intercepts("cdn.ads.example:443"));
+        assert!(!rules.intercepts("telemetry.example:443"));
+        assert!(!rules.intercepts("example.com:443"));
+    }
+
+    #[test]
+    fn redirects_need_a_location() {
+        let rule = BlockRule {
+            host: "social.example".to_string(),
+            behavior: BlockBehavior::Redirect,
+            location: None,
+        };
+        assert!(BlockRules::new(vec![rule]).is_err());
+    }
+}
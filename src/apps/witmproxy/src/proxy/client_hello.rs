@@ -0,0 +1,137 @@
+//! Client hello profiles, shaping the TLS handshakes of upstream connections
+//! after a browser's, for services which treat clients differently by TLS
+//! fingerprint (JA3/JA4).
+//!
+//! A profile is selected per host pattern by an egress route:
+//!
+//! ```toml
+//! [[proxy.egress]]
+//! host = "*.example.com"
+//! client_hello = "chrome"
+//! ```
+//!
+//! Profiles offer the cipher suites and key exchange groups the browser
+//! does, in its order, along with its ALPN protocols. rustls doesn't send
+//! GREASE values, legacy CBC suites or the browser's extension order, so
+//! fingerprints which account for those only come closer to the browser's.
+
+use rustls::SupportedCipherSuite;
+use rustls::crypto::ring::{self, cipher_suite, kx_group};
+use rustls::crypto::{CryptoProvider, SupportedKxGroup};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+
+/// A browser whose client hello upstream handshakes mimic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClientHelloProfile {
+    Chrome,
+    Firefox,
+    Safari,
+}
+
+impl ClientHelloProfile {
+    fn cipher_suites(self) -> Vec<SupportedCipherSuite> {
+        use cipher_suite::*;
+        match self {
+            ClientHelloProfile::Chrome => vec![
+                TLS13_AES_128_GCM_SHA256,
+                TLS13_AES_256_GCM_SHA384,
+                TLS13_CHACHA20_POLY1305_SHA256,
+                TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+                TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+                TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+                TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+                TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+                TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+            ],
+            ClientHelloProfile::Firefox => vec![
+                TLS13_AES_128_GCM_SHA256,
+                TLS13_CHACHA20_POLY1305_SHA256,
+                TLS13_AES_256_GCM_SHA384,
+                TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+                TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+                TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+                TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+                TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+                TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+            ],
+            ClientHelloProfile::Safari => vec![
+                TLS13_AES_128_GCM_SHA256,
+                TLS13_AES_256_GCM_SHA384,
+                TLS13_CHACHA20_POLY1305_SHA256,
+                TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+                TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+                TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+                TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+                TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+                TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+            ],
+        }
+    }
+
+    fn kx_groups(self) -> Vec<&'static dyn SupportedKxGroup> {
+        // Every profile prefers X25519; browsers' post-quantum hybrids
+        // aren't offered by the ring provider
+        vec![kx_group::X25519, kx_group::SECP256R1, kx_group::SECP384R1]
+    }
+
+    /// The ALPN protocols advertised, in order
+    pub fn alpn_protocols(self) -> Vec<Vec<u8>> {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    }
+
+    /// A crypto provider offering the profile's cipher suites and key
+    /// exchange groups, in its order
+    pub fn provider(self) -> CryptoProvider {
+        CryptoProvider {
+            cipher_suites: self.cipher_suites(),
+            kx_groups: self.kx_groups(),
+            ..ring::default_provider()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn profiles_order_suites_as_their_browser() {
+        let first_tls12 = |profile: ClientHelloProfile| {
+            profile
+                .provider()
+                .cipher_suites
+                .into_iter()
+                .find(|suite| suite.tls13().is_none())
+                .unwrap()
+                .suite()
+        };
+        assert_eq!(
+            first_tls12(ClientHelloProfile::Chrome),
+            rustls::CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256
+        );
+        assert_eq!(
+            first_tls12(ClientHelloProfile::Safari),
+            rustls::CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384
+        );
+        assert_eq!(
+            ClientHelloProfile::Firefox.provider().cipher_suites[1].suite(),
+            rustls::CipherSuite::TLS13_CHACHA20_POLY1305_SHA256
+        );
+
+        for profile in [
+            ClientHelloProfile::Chrome,
+            ClientHelloProfile::Firefox,
+            ClientHelloProfile::Safari,
+        ] {
+            assert!(
+                rustls::ClientConfig::builder_with_provider(Arc::new(profile.provider()))
+                    .with_safe_default_protocol_versions()
+                    .is_ok()
+            );
+        }
+    }
+}
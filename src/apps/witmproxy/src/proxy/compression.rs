@@ -0,0 +1,370 @@
+//! Compression on the client-facing side, for clients on slow links.
+//!
+//! With `proxy.compress_responses` on, responses upstream sent uncompressed
+//! are compressed with brotli or gzip, whichever the client's
+//! `Accept-Encoding` prefers, when their content type starts with one of
+//! `proxy.compress_content_types` and they're at least
+//! `proxy.compress_min_bytes` long. Responses in an encoding the client
+//! doesn't accept are decompressed for it. Clients which send no
+//! `Accept-Encoding` get responses as upstream sent them, as do responses
+//! marked `Cache-Control: no-transform` and partial content.
+
+use bytes::Bytes;
+use futures::TryStreamExt;
+use http_body::{Body, Frame};
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, BodyStream, StreamBody};
+use hyper::Response;
+use hyper::header::{
+    ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE,
+    ETAG, HeaderMap, HeaderValue, VARY,
+};
+use hyper::http::response::Parts;
+use tokio::io::{AsyncRead, BufReader};
+use tokio_util::io::{ReaderStream, StreamReader};
+use wasmtime_wasi::runtime::with_ambient_tokio_runtime;
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+
+use crate::config::ProxyConfig;
+use crate::http::utils::{ContentEncoding, Encoded};
+
+/// Content types compressed when none are configured
+pub const DEFAULT_CONTENT_TYPES: &[&str] = &[
+    "text/",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/xhtml+xml",
+    "image/svg+xml",
+];
+
+/// Smallest response compressed when no threshold is configured
+pub const DEFAULT_MIN_BYTES: u64 = 1024;
+
+/// Brotli quality responses are compressed with, fast enough to compress
+/// as they're streamed
+const BROTLI_QUALITY: i32 = 4;
+
+#[derive(Debug, Clone)]
+struct Options {
+    /// Prefixes of the content types compressed, lowercase
+    content_types: Vec<String>,
+    min_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Compression {
+    /// What's compressed, if anything is
+    options: Option<Options>,
+}
+
+impl From<&ProxyConfig> for Compression {
+    fn from(config: &ProxyConfig) -> Self {
+        let options = config.compress_responses.then(|| Options {
+            content_types: if config.compress_content_types.is_empty() {
+                DEFAULT_CONTENT_TYPES
+                    .iter()
+                    .map(|t| t.to_string())
+                    .collect()
+            } else {
+                config
+                    .compress_content_types
+                    .iter()
+                    .map(|t| t.trim().to_ascii_lowercase())
+                    .collect()
+            },
+            min_bytes: config.compress_min_bytes.unwrap_or(DEFAULT_MIN_BYTES),
+        });
+        Self { options }
+    }
+}
+
+/// How a response's body is recoded for the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Recode {
+    Brotli,
+    Gzip,
+    /// Decode from the body's encoding
+    Decode,
+}
+
+impl Compression {
+    pub fn is_enabled(&self) -> bool {
+        self.options.is_some()
+    }
+
+    /// The `Accept-Encoding` header of a request, kept to decide how the
+    /// response it's answered with is encoded. `None` if the client sent
+    /// none, as its responses are left as they are.
+    pub fn accepted(&self, headers: &HeaderMap) -> Option<String> {
+        self.options.as_ref()?;
+        headers
+            .get(ACCEPT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    }
+
+    /// Compress or decompress the body of `response` for a client which
+    /// sent `accept_encoding`
+    pub fn apply(
+        &self,
+        accept_encoding: &str,
+        response: Response<UnsyncBoxBody<Bytes, ErrorCode>>,
+    ) -> Response<UnsyncBoxBody<Bytes, ErrorCode>> {
+        let Some(options) = &self.options else {
+            return response;
+        };
+        let (mut parts, body) = response.into_parts();
+        let encoding = parts.encoding();
+        let Some(recode) = options.recode(accept_encoding, &parts, &encoding, &body) else {
+            return Response::from_parts(parts, body);
+        };
+        let body = with_ambient_tokio_runtime(|| recoded(body, recode, encoding));
+        let headers = &mut parts.headers;
+        headers.remove(CONTENT_LENGTH);
+        match recode {
+            Recode::Brotli => {
+                headers.insert(CONTENT_ENCODING, HeaderValue::from_static("br"));
+            }
+            Recode::Gzip => {
+                headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+            }
+            Recode::Decode => {
+                headers.remove(CONTENT_ENCODING);
+            }
+        }
+        // The recoded body isn't byte for byte the one tagged upstream
+        if let Some(etag) = headers.get(ETAG).and_then(|value| value.to_str().ok())
+            && !etag.starts_with("W/")
+            && let Ok(weak) = HeaderValue::from_str(&format!("W/{etag}"))
+        {
+            headers.insert(ETAG, weak);
+        }
+        let varies = headers
+            .get_all(VARY)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|name| {
+                let name = name.trim();
+                name == "*" || name.eq_ignore_ascii_case("accept-encoding")
+            });
+        if !varies {
+            headers.append(VARY, HeaderValue::from_static("Accept-Encoding"));
+        }
+        Response::from_parts(parts, body)
+    }
+}
+
+impl Options {
+    /// How `response` is recoded for a client which sent
+    /// `accept_encoding`, if it is
+    fn recode(
+        &self,
+        accept_encoding: &str,
+        parts: &Parts,
+        encoding: &ContentEncoding,
+        body: &UnsyncBoxBody<Bytes, ErrorCode>,
+    ) -> Option<Recode> {
+        if !matches!(parts.status.as_u16(), 200..=203 | 205 | 207..=299) {
+            return None;
+        }
+        let headers = &parts.headers;
+        let no_transform = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"));
+        if no_transform || headers.contains_key(CONTENT_RANGE) {
+            return None;
+        }
+        let length = body.size_hint().exact().or_else(|| {
+            headers
+                .get(CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+        });
+        if length == Some(0) {
+            return None;
+        }
+
+        match encoding {
+            ContentEncoding::None => {
+                if length.is_some_and(|length| length < self.min_bytes) {
+                    return None;
+                }
+                let content_type = headers
+                    .get(CONTENT_TYPE)
+                    .and_then(|value| value.to_str().ok())?
+                    .to_ascii_lowercase();
+                if !self
+                    .content_types
+                    .iter()
+                    .any(|prefix| content_type.starts_with(prefix.as_str()))
+                {
+                    return None;
+                }
+                let brotli = quality(accept_encoding, "br");
+                let gzip = quality(accept_encoding, "gzip");
+                if brotli > 0.0 && brotli >= gzip {
+                    Some(Recode::Brotli)
+                } else if gzip > 0.0 {
+                    Some(Recode::Gzip)
+                } else {
+                    None
+                }
+            }
+            ContentEncoding::Unknown => None,
+            known => (quality(accept_encoding, name(known)) == 0.0).then_some(Recode::Decode),
+        }
+    }
+}
+
+/// The name of `encoding` in `Accept-Encoding`
+fn name(encoding: &ContentEncoding) -> &'static str {
+    match encoding {
+        ContentEncoding::Gzip => "gzip",
+        ContentEncoding::Deflate => "deflate",
+        ContentEncoding::Br => "br",
+        ContentEncoding::Zstd => "zstd",
+        ContentEncoding::None | ContentEncoding::Unknown => "identity",
+    }
+}
+
+/// The q-value `accept_encoding` gives `coding`, 0 if it isn't accepted
+fn quality(accept_encoding: &str, coding: &str) -> f32 {
+    let mut wildcard = None;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or_default().trim();
+        let q = params
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .and_then(|q| q.trim().parse().ok())
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(coding) {
+            return q;
+        }
+        if name == "*" {
+            wildcard = Some(q);
+        }
+    }
+    wildcard.unwrap_or(0.0)
+}
+
+/// `body`, in `encoding`, recoded as `recode` says while it's streamed
+fn recoded(
+    body: UnsyncBoxBody<Bytes, ErrorCode>,
+    recode: Recode,
+    encoding: ContentEncoding,
+) -> UnsyncBoxBody<Bytes, ErrorCode> {
+    use async_compression::Level;
+    use async_compression::tokio::bufread::{
+        BrotliDecoder, BrotliEncoder, DeflateDecoder, GzipDecoder, GzipEncoder, ZstdDecoder,
+    };
+
+    let reader = BufReader::new(StreamReader::new(
+        BodyStream::new(body)
+            .map_ok(|frame| frame.into_data().unwrap_or_default())
+            .map_err(std::io::Error::other),
+    ));
+    let recoded: Box<dyn AsyncRead + Send + Unpin> = match (recode, encoding) {
+        (Recode::Brotli, _) => Box::new(BrotliEncoder::with_quality(
+            reader,
+            Level::Precise(BROTLI_QUALITY),
+        )),
+        (Recode::Gzip, _) => Box::new(GzipEncoder::new(reader)),
+        (Recode::Decode, ContentEncoding::Gzip) => Box::new(GzipDecoder::new(reader)),
+        (Recode::Decode, ContentEncoding::Deflate) => Box::new(DeflateDecoder::new(reader)),
+        (Recode::Decode, ContentEncoding::Br) => Box::new(BrotliDecoder::new(reader)),
+        (Recode::Decode, ContentEncoding::Zstd) => Box::new(ZstdDecoder::new(reader)),
+        (Recode::Decode, _) => Box::new(reader),
+    };
+    let frames = ReaderStream::new(recoded)
+        .map_ok(Frame::data)
+        .map_err(|e| ErrorCode::InternalError(Some(format!("Compression error: {}", e))));
+    StreamBody::new(frames).boxed_unsync()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_compression::tokio::bufread::BrotliDecoder;
+    use http_body_util::Full;
+
+    fn response(
+        content_type: &str,
+        encoding: Option<&str>,
+        body: Vec<u8>,
+    ) -> Response<UnsyncBoxBody<Bytes, ErrorCode>> {
+        let mut builder = Response::builder().header(CONTENT_TYPE, content_type);
+        if let Some(encoding) = encoding {
+            builder = builder.header(CONTENT_ENCODING, encoding);
+        }
+        builder
+            .body(
+                Full::new(Bytes::from(body))
+                    .map_err(|never| match never {})
+                    .boxed_unsync(),
+            )
+            .unwrap()
+    }
+
+    async fn collect(response: Response<UnsyncBoxBody<Bytes, ErrorCode>>) -> Vec<u8> {
+        response
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn compresses_for_clients_that_accept_it_and_decompresses_for_others() {
+        let compression = Compression {
+            options: Some(Options {
+                content_types: vec!["text/".to_string()],
+                min_bytes: 16,
+            }),
+        };
+        let page = "<p>hello</p>".repeat(100).into_bytes();
+
+        assert_eq!(quality("gzip, br;q=0.5", "br"), 0.5);
+        assert_eq!(quality("*;q=0.2", "zstd"), 0.2);
+        assert_eq!(quality("gzip", "br"), 0.0);
+
+        let compressed =
+            compression.apply("gzip;q=0.8, br", response("text/html", None, page.clone()));
+        assert_eq!(compressed.headers()[CONTENT_ENCODING], "br");
+        assert_eq!(compressed.headers()[VARY], "Accept-Encoding");
+        let brotli = collect(compressed).await;
+        assert!(brotli.len() < page.len());
+        let mut decoded = Vec::new();
+        tokio::io::AsyncReadExt::read_to_end(&mut BrotliDecoder::new(&brotli[..]), &mut decoded)
+            .await
+            .unwrap();
+        assert_eq!(decoded, page);
+
+        let gzipped = compression.apply("gzip, br;q=0", response("text/css", None, page.clone()));
+        assert_eq!(gzipped.headers()[CONTENT_ENCODING], "gzip");
+        let gzip = collect(gzipped).await;
+
+        // Too small, not a configured type, or the client takes neither
+        for (accept, content_type, body) in [
+            ("br", "text/html", b"<p>hi</p>".to_vec()),
+            ("br", "image/png", page.clone()),
+            ("identity", "text/html", page.clone()),
+        ] {
+            let untouched = compression.apply(accept, response(content_type, None, body));
+            assert!(!untouched.headers().contains_key(CONTENT_ENCODING));
+        }
+
+        let decompressed = compression.apply("br", response("text/css", Some("gzip"), gzip));
+        assert!(!decompressed.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(collect(decompressed).await, page);
+
+        let kept = compression.apply("gzip", response("text/css", Some("gzip"), vec![1]));
+        assert_eq!(kept.headers()[CONTENT_ENCODING], "gzip");
+    }
+}
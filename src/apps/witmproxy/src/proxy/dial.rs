@@ -0,0 +1,206 @@
+//! Dual-stack socket helpers: binding listeners that accept both address
+//! families, and dialing upstreams with happy eyeballs (RFC 8305).
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use serde::Serialize;
+use tokio::net::{TcpListener, TcpStream, lookup_host};
+use tracing::debug;
+
+/// Time to wait for a connection attempt before racing the next address
+/// (the "Connection Attempt Delay" recommended by RFC 8305)
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// The address family a connection was made over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressFamily {
+    Ipv4,
+    Ipv6,
+}
+
+impl AddressFamily {
+    /// The family of `ip`, treating IPv4-mapped IPv6 addresses as IPv4
+    pub fn of(ip: IpAddr) -> Self {
+        match ip.to_canonical() {
+            IpAddr::V4(_) => AddressFamily::Ipv4,
+            IpAddr::V6(_) => AddressFamily::Ipv6,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AddressFamily::Ipv4 => "ipv4",
+            AddressFamily::Ipv6 => "ipv6",
+        }
+    }
+}
+
+impl std::fmt::Display for AddressFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Unwrap IPv4-mapped IPv6 addresses (`[::ffff:192.0.2.1]`), as reported for
+/// IPv4 clients of a dual-stack listener, back to plain IPv4.
+pub fn canonical_addr(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+/// Bind a TCP listener. An unspecified IPv6 address (`[::]`) is bound
+/// dual-stack, so it also accepts IPv4 clients regardless of the OS default.
+pub fn bind_listener(addr: SocketAddr) -> io::Result<TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    if let IpAddr::V6(ip) = addr.ip() {
+        socket.set_only_v6(!ip.is_unspecified())?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Order resolved addresses for connection attempts: IPv6 first, then
+/// alternating families so a broken family only costs one attempt delay.
+pub fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let total = addrs.len();
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+
+    let mut ordered = Vec::with_capacity(total);
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+    ordered
+}
+
+/// Resolve `host` and connect to it, racing addresses of both families
+pub async fn connect(host: &str, port: u16) -> io::Result<TcpStream> {
+    let addrs: Vec<SocketAddr> = lookup_host((host, port)).await?.collect();
+    let stream = connect_addrs(interleave_families(addrs), CONNECTION_ATTEMPT_DELAY).await?;
+    if let Ok(addr) = stream.peer_addr() {
+        debug!(
+            "Connected to {}:{} via {} ({})",
+            host,
+            port,
+            addr,
+            AddressFamily::of(addr.ip())
+        );
+    }
+    Ok(stream)
+}
+
+/// Connect to the first address that answers. A new attempt is started each
+/// time `delay` passes without a connection, or as soon as an attempt fails;
+/// earlier attempts keep running until one of them succeeds.
+pub async fn connect_addrs(addrs: Vec<SocketAddr>, delay: Duration) -> io::Result<TcpStream> {
+    let mut pending = addrs.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_err = None;
+
+    loop {
+        if attempts.is_empty() {
+            match pending.next() {
+                Some(addr) => attempts.push(attempt(addr)),
+                None => {
+                    return Err(last_err.unwrap_or_else(|| {
+                        io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to")
+                    }));
+                }
+            }
+        }
+
+        tokio::select! {
+            Some(result) = attempts.next() => match result {
+                Ok(stream) => return Ok(stream),
+                Err(e) => {
+                    last_err = Some(e);
+                    if let Some(addr) = pending.next() {
+                        attempts.push(attempt(addr));
+                    }
+                }
+            },
+            _ = tokio::time::sleep(delay), if !pending.as_slice().is_empty() => {
+                if let Some(addr) = pending.next() {
+                    attempts.push(attempt(addr));
+                }
+            }
+        }
+    }
+}
+
+async fn attempt(addr: SocketAddr) -> io::Result<TcpStream> {
+    TcpStream::connect(addr)
+        .await
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", addr, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn families_alternate_starting_with_ipv6() {
+        let addrs: Vec<SocketAddr> = ["1.1.1.1:443", "1.0.0.1:443", "[2606:4700::1111]:443"]
+            .iter()
+            .map(|a| a.parse().unwrap())
+            .collect();
+        let ordered = interleave_families(addrs);
+        assert_eq!(
+            ordered,
+            vec![
+                "[2606:4700::1111]:443".parse().unwrap(),
+                "1.1.1.1:443".parse().unwrap(),
+                "1.0.0.1:443".parse().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn mapped_addresses_are_ipv4() {
+        let mapped: SocketAddr = "[::ffff:192.0.2.1]:8080".parse().unwrap();
+        assert_eq!(AddressFamily::of(mapped.ip()), AddressFamily::Ipv4);
+        assert_eq!(
+            canonical_addr(mapped),
+            "192.0.2.1:8080".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            AddressFamily::of("::1".parse().unwrap()),
+            AddressFamily::Ipv6
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_past_unreachable_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let good = listener.local_addr().unwrap();
+        // Nothing listens on a freshly released port, so this attempt is refused
+        let refused = {
+            let l = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            l.local_addr().unwrap()
+        };
+
+        let stream = connect_addrs(vec![refused, good], Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), good);
+
+        assert!(
+            connect_addrs(vec![refused], CONNECTION_ATTEMPT_DELAY)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,197 @@
+//! Split-horizon egress: sending the intercepted flows of matching hosts out
+//! through another network interface or a SOCKS proxy, or with a browser's
+//! TLS fingerprint, while everything else goes direct.
+//!
+//! Routes are configured as `[[proxy.egress]]` tables, each naming either an
+//! `interface` or a `socks` proxy, a `client_hello` profile (see
+//! [crate::proxy::client_hello]), or both:
+//!
+//! ```toml
+//! [[proxy.egress]]
+//! host = "*.corp.example.com"
+//! interface = "wg0"
+//!
+//! [[proxy.egress]]
+//! host = "api.example.org"
+//! socks = "socks5h://127.0.0.1:1080"
+//!
+//! [[proxy.egress]]
+//! host = "*.shop.example"
+//! client_hello = "chrome"
+//! ```
+//!
+//! A WireGuard tunnel is used by naming its interface, as created by
+//! `wg-quick` or a userspace implementation such as `boringtun-cli`. The
+//! first matching route applies. Routes only cover flows the proxy
+//! intercepts; tunnels it passes through untouched go direct.
+
+use std::sync::Arc;
+
+use reqwest::ClientBuilder;
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::proxy::client_hello::ClientHelloProfile;
+use crate::proxy::host_limits::matches_host;
+use crate::proxy::{ProxyError, ProxyResult, UpstreamClient};
+
+/// Where the flows to matching hosts are sent out through
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct EgressRoute {
+    /// Host name, or `*.` followed by a domain to match its subdomains
+    pub host: String,
+    /// Network interface to send through, ex: "wg0"
+    #[serde(default)]
+    pub interface: Option<String>,
+    /// SOCKS proxy to send through, ex: "socks5h://127.0.0.1:1080"
+    #[serde(default)]
+    pub socks: Option<String>,
+    /// Browser whose TLS client hello upstream handshakes mimic: chrome,
+    /// firefox or safari
+    #[serde(default)]
+    pub client_hello: Option<ClientHelloProfile>,
+}
+
+impl EgressRoute {
+    /// Configure `builder` to send through the route
+    fn configure(&self, builder: ClientBuilder) -> ProxyResult<ClientBuilder> {
+        match (&self.interface, &self.socks) {
+            (Some(interface), None) => bind_interface(builder, interface),
+            (None, Some(socks)) => {
+                let scheme = socks.split_once("://").map(|(scheme, _)| scheme);
+                if !matches!(scheme, Some("socks4" | "socks4a" | "socks5" | "socks5h")) {
+                    return Err(ProxyError::Generic(format!(
+                        "Egress route for {} needs a socks4, socks4a, socks5 or socks5h URL",
+                        self.host
+                    )));
+                }
+                let proxy = reqwest::Proxy::all(socks).map_err(|e| {
+                    ProxyError::Generic(format!("Invalid SOCKS proxy for {}: {}", self.host, e))
+                })?;
+                Ok(builder.proxy(proxy))
+            }
+            (None, None) if self.client_hello.is_some() => Ok(builder),
+            _ => Err(ProxyError::Generic(format!(
+                "Egress route for {} needs either an interface or a SOCKS proxy, or a client hello profile",
+                self.host
+            ))),
+        }
+    }
+}
+
+#[cfg(any(
+    target_os = "android",
+    target_os = "fuchsia",
+    target_os = "illumos",
+    target_os = "ios",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "solaris",
+))]
+fn bind_interface(builder: ClientBuilder, interface: &str) -> ProxyResult<ClientBuilder> {
+    Ok(builder.interface(interface))
+}
+
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "fuchsia",
+    target_os = "illumos",
+    target_os = "ios",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "solaris",
+)))]
+fn bind_interface(_builder: ClientBuilder, interface: &str) -> ProxyResult<ClientBuilder> {
+    Err(ProxyError::Generic(format!(
+        "Routing through interface {} isn't supported on this platform",
+        interface
+    )))
+}
+
+/// The upstream clients of the egress routes. Cheap to clone; all clones
+/// share the same clients.
+#[derive(Debug, Clone, Default)]
+pub struct EgressRoutes {
+    routes: Arc<Vec<(String, UpstreamClient)>>,
+}
+
+impl EgressRoutes {
+    /// Build a client for each of `routes`, from builders configured as for
+    /// direct flows by `builder`, given the route's client hello profile
+    pub fn new(
+        routes: &[EgressRoute],
+        builder: impl Fn(Option<ClientHelloProfile>) -> ProxyResult<ClientBuilder>,
+    ) -> ProxyResult<Self> {
+        let routes = routes
+            .iter()
+            .map(|route| {
+                let builder = builder(route.client_hello)?;
+                let client = route.configure(builder)?.build().map_err(|e| {
+                    ProxyError::Generic(format!(
+                        "Failed to build client for egress route {}: {}",
+                        route.host, e
+                    ))
+                })?;
+                Ok((route.host.clone(), client))
+            })
+            .collect::<ProxyResult<_>>()?;
+        Ok(Self {
+            routes: Arc::new(routes),
+        })
+    }
+
+    /// The client flows to `host` are sent with, if a route matches it
+    pub fn client_for(&self, host: &str) -> Option<&UpstreamClient> {
+        self.routes
+            .iter()
+            .find(|(pattern, _)| matches_host(pattern, host))
+            .map(|(_, client)| client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(host: &str, interface: Option<&str>, socks: Option<&str>) -> EgressRoute {
+        EgressRoute {
+            host: host.to_string(),
+            interface: interface.map(str::to_string),
+            socks: socks.map(str::to_string),
+            client_hello: None,
+        }
+    }
+
+    #[test]
+    fn first_matching_route_picks_the_client() {
+        let routes = EgressRoutes::new(
+            &[
+                route("api.example.org", None, Some("socks5h://127.0.0.1:1080")),
+                route("*.example.org", None, Some("socks5://127.0.0.1:1081")),
+            ],
+            |_| Ok(reqwest::Client::builder()),
+        )
+        .unwrap();
+        assert!(routes.client_for("api.example.org").is_some());
+        assert!(routes.client_for("www.example.org").is_some());
+        assert!(routes.client_for("example.org").is_none());
+        assert!(EgressRoutes::default().client_for("example.org").is_none());
+    }
+
+    #[test]
+    fn routes_need_one_way_out() {
+        let builder = |_| Ok(reqwest::Client::builder());
+        let fingerprinted = EgressRoute {
+            client_hello: Some(ClientHelloProfile::Firefox),
+            ..route("example.org", None, None)
+        };
+        assert!(EgressRoutes::new(&[fingerprinted], builder).is_ok());
+        for invalid in [
+            route("example.org", None, None),
+            route("example.org", Some("wg0"), Some("socks5://127.0.0.1:1080")),
+            route("example.org", None, Some("http://127.0.0.1:8080")),
+        ] {
+            assert!(EgressRoutes::new(&[invalid], builder).is_err());
+        }
+    }
+}
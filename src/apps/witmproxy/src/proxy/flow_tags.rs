@@ -0,0 +1,205 @@
+//! Tags added to flows by CEL rules as they complete, so that the flows of
+//! interest in a long capture (failed API calls, slow pages, ...) can be
+//! listed with `GET /api/manage/flows?tag=...`.
+//!
+//! Rules are configured as `[[proxy.flow_tags]]` tables, or replaced at
+//! runtime through `/api/manage/flow-tags`:
+//!
+//! ```toml
+//! [[proxy.flow_tags]]
+//! tag = "api-errors"
+//! when = 'request.host().startsWith("api.") && response.status() >= 400'
+//! ```
+//!
+//! `when` is a CEL expression over `request` and `response`, as in plugin
+//! response scopes. A flow is given the tag of every matching rule.
+
+use std::sync::{Arc, OnceLock, RwLock};
+
+use anyhow::{Context, Result, bail};
+use cel_cxx::{Activation, Env, Program};
+use hyper::Response;
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::plugins::cel::{CelRequest, CelResponse, CelTime};
+use crate::proxy::flows::{FlowLog, is_valid_tag};
+use crate::wasm::bindgen::Event as WasmEvent;
+
+/// A tag given to the flows matching an expression
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct FlowTagRule {
+    /// Letters, digits and `-_.:`
+    pub tag: String,
+    /// CEL expression selecting the flows to tag
+    pub when: String,
+}
+
+struct CompiledRule {
+    program: Program<'static>,
+    tag: String,
+}
+
+impl CompiledRule {
+    fn compile(rule: &FlowTagRule) -> Result<Self> {
+        if !is_valid_tag(&rule.tag) {
+            bail!("Invalid flow tag: {:?}", rule.tag);
+        }
+        let program = env()
+            .compile(&rule.when)
+            .with_context(|| format!("Invalid flow tag expression: {}", rule.when))?;
+        Ok(Self {
+            program,
+            tag: rule.tag.clone(),
+        })
+    }
+
+    fn matches(&self, request: &CelRequest, response: &CelResponse) -> bool {
+        let activation = Activation::new()
+            .bind_variable("request", request.clone())
+            .and_then(|a| a.bind_variable("response", response.clone()))
+            .and_then(|a| a.bind_variable("time", CelTime::now()));
+        let Ok(activation) = activation else {
+            return false;
+        };
+        match self.program.evaluate(activation) {
+            Ok(cel_cxx::Value::Bool(matches)) => matches,
+            Ok(_) => false,
+            Err(e) => {
+                error!("Error evaluating flow tag expression: {}", e);
+                false
+            }
+        }
+    }
+}
+
+/// The CEL environment rules are compiled in, declaring the same variables
+/// and functions as plugin scopes
+fn env() -> &'static Env<'static> {
+    static ENV: OnceLock<Env<'static>> = OnceLock::new();
+    ENV.get_or_init(|| {
+        WasmEvent::register(Env::builder().with_standard(true))
+            .and_then(|builder| Ok(builder.build()?))
+            .expect("Failed to build the CEL environment")
+    })
+}
+
+/// The live set of flow tag rules. Cheap to clone; all clones share the
+/// same rules.
+#[derive(Clone, Default)]
+pub struct FlowTags {
+    rules: Arc<RwLock<Arc<Rules>>>,
+}
+
+#[derive(Default)]
+struct Rules {
+    config: Vec<FlowTagRule>,
+    compiled: Vec<CompiledRule>,
+}
+
+impl std::fmt::Debug for FlowTags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FlowTags")
+            .field("rules", &self.rules())
+            .finish()
+    }
+}
+
+impl FlowTags {
+    pub fn new(rules: Vec<FlowTagRule>) -> Result<Self> {
+        let tags = Self::default();
+        tags.set_rules(rules)?;
+        Ok(tags)
+    }
+
+    /// Replace the rules, leaving the current ones in place if any is invalid
+    pub fn set_rules(&self, rules: Vec<FlowTagRule>) -> Result<()> {
+        let compiled = rules
+            .iter()
+            .map(CompiledRule::compile)
+            .collect::<Result<_>>()?;
+        *self.rules.write().unwrap() = Arc::new(Rules {
+            config: rules,
+            compiled,
+        });
+        Ok(())
+    }
+
+    pub fn rules(&self) -> Vec<FlowTagRule> {
+        self.rules.read().unwrap().config.clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.read().unwrap().compiled.is_empty()
+    }
+
+    /// Tag the flow `id` in `flows` with every rule matching `request` and
+    /// `response`
+    pub fn apply<B>(&self, flows: &FlowLog, id: &str, request: &CelRequest, response: &Response<B>)
+    where
+        B: http_body::Body<Data = bytes::Bytes> + Send + 'static,
+    {
+        let rules = self.rules.read().unwrap().clone();
+        if rules.compiled.is_empty() {
+            return;
+        }
+        let cel_response = CelResponse::from(response);
+        for rule in &rules.compiled {
+            if rule.matches(request, &cel_response) {
+                flows.tag(id, &rule.tag);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::flows::FlowRecord;
+    use http_body_util::Empty;
+
+    fn rule(tag: &str, when: &str) -> FlowTagRule {
+        FlowTagRule {
+            tag: tag.to_string(),
+            when: when.to_string(),
+        }
+    }
+
+    #[test]
+    fn matching_rules_tag_flows() {
+        let tags = FlowTags::new(vec![
+            rule(
+                "api-errors",
+                "request.host() == 'api.example' && response.status() >= 400",
+            ),
+            rule("not-found", "response.status() == 404"),
+            rule("never", "false"),
+        ])
+        .unwrap();
+        let flows = FlowLog::default();
+        let req = hyper::Request::get("https://api.example/users/1")
+            .body(Empty::<bytes::Bytes>::new())
+            .unwrap();
+        flows.record(FlowRecord::new("1", &req));
+        let response = Response::builder()
+            .status(404)
+            .body(Empty::<bytes::Bytes>::new())
+            .unwrap();
+
+        tags.apply(&flows, "1", &CelRequest::from(&req), &response);
+        assert_eq!(flows.get("1").unwrap().tags, ["api-errors", "not-found"]);
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        let tags = FlowTags::new(vec![rule("slow", "true")]).unwrap();
+        assert!(tags.set_rules(vec![rule("two words", "true")]).is_err());
+        assert!(
+            tags.set_rules(vec![rule("broken", "response.status() ==")])
+                .is_err()
+        );
+        // The rules in place are kept
+        assert_eq!(tags.rules(), [rule("slow", "true")]);
+    }
+}
@@ -0,0 +1,177 @@
+//! Traces of single flows, for requests sent with `X-Witmproxy-Debug: 1` by
+//! clients allowed to debug them (`proxy.debug_clients`).
+//!
+//! A traced flow records its timeline: the CEL scopes evaluated for it, what
+//! each plugin did with it and how long the upstream took, without raising
+//! the log level for every other flow. Its response carries the flow id in
+//! `x-witmproxy-trace`, for fetching the trace from the management API.
+
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use hyper::Request;
+use salvo::oapi::ToSchema;
+use serde::Serialize;
+
+/// Request header asking for the flow to be traced
+pub const DEBUG_HEADER: &str = "x-witmproxy-debug";
+
+/// Response header carrying the id of a traced flow
+pub const TRACE_HEADER: &str = "x-witmproxy-trace";
+
+/// Traces kept for the management API; older ones are dropped
+const MAX_TRACES: usize = 100;
+
+tokio::task_local! {
+    static CURRENT: FlowTrace;
+}
+
+/// Something that happened while handling a traced flow
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct TraceEntry {
+    /// Microseconds since the request was received
+    pub elapsed_micros: u64,
+    /// What the entry is about, ex: `cel`, `plugin` or `upstream`
+    pub stage: String,
+    pub message: String,
+}
+
+/// The timeline of a traced flow. Cheap to clone; all clones share the same
+/// entries.
+#[derive(Clone)]
+pub struct FlowTrace {
+    id: String,
+    started: Instant,
+    entries: Arc<Mutex<Vec<TraceEntry>>>,
+}
+
+impl FlowTrace {
+    fn new(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            started: Instant::now(),
+            entries: Arc::default(),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn record(&self, stage: &str, message: String) {
+        let elapsed_micros = self.started.elapsed().as_micros() as u64;
+        self.entries.lock().unwrap().push(TraceEntry {
+            elapsed_micros,
+            stage: stage.to_string(),
+            message,
+        });
+    }
+
+    pub fn entries(&self) -> Vec<TraceEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+}
+
+/// Run `fut` with `trace` as the current trace, so [record] adds to it
+pub async fn scoped<F: Future>(trace: Option<FlowTrace>, fut: F) -> F::Output {
+    match trace {
+        Some(trace) => CURRENT.scope(trace, fut).await,
+        None => fut.await,
+    }
+}
+
+/// Add to the trace of the flow being handled, if it's traced. `message` is
+/// only built for traced flows.
+pub fn record(stage: &str, message: impl FnOnce() -> String) {
+    let _ = CURRENT.try_with(|trace| trace.record(stage, message()));
+}
+
+/// The clients allowed to trace flows, and the latest traces. Cheap to
+/// clone; all clones share the same traces.
+#[derive(Clone, Default)]
+pub struct FlowTraces {
+    clients: Arc<Vec<IpAddr>>,
+    traces: Arc<Mutex<VecDeque<FlowTrace>>>,
+}
+
+impl FlowTraces {
+    pub fn new(clients: Vec<IpAddr>) -> Self {
+        Self {
+            clients: Arc::new(clients.into_iter().map(|ip| ip.to_canonical()).collect()),
+            traces: Arc::default(),
+        }
+    }
+
+    /// Start tracing flow `id` if `client` asked for it with [DEBUG_HEADER]
+    /// and is allowed to
+    pub fn start<B>(&self, id: &str, client: IpAddr, req: &Request<B>) -> Option<FlowTrace> {
+        let asked = req
+            .headers()
+            .get(DEBUG_HEADER)
+            .is_some_and(|value| value.as_bytes() == b"1");
+        if !asked || !self.clients.contains(&client.to_canonical()) {
+            return None;
+        }
+
+        let trace = FlowTrace::new(id);
+        trace.record(
+            "request",
+            format!("{} {} from {}", req.method(), req.uri(), client),
+        );
+        let mut traces = self.traces.lock().unwrap();
+        if traces.len() == MAX_TRACES {
+            traces.pop_front();
+        }
+        traces.push_back(trace.clone());
+        Some(trace)
+    }
+
+    /// The entries traced so far for flow `id`
+    pub fn get(&self, id: &str) -> Option<Vec<TraceEntry>> {
+        self.traces
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|trace| trace.id == id)
+            .map(FlowTrace::entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(debug: Option<&str>) -> Request<()> {
+        let mut req = Request::get("https://example.com/");
+        if let Some(debug) = debug {
+            req = req.header(DEBUG_HEADER, debug);
+        }
+        req.body(()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn traces_flows_from_allowed_clients_that_ask() {
+        let allowed: IpAddr = "10.0.0.2".parse().unwrap();
+        let traces = FlowTraces::new(vec![allowed]);
+        assert!(traces.start("1", allowed, &request(None)).is_none());
+        assert!(traces.start("2", allowed, &request(Some("0"))).is_none());
+        let other = "10.0.0.3".parse().unwrap();
+        assert!(traces.start("3", other, &request(Some("1"))).is_none());
+
+        // IPv4 clients of dual-stack listeners arrive IPv4-mapped
+        let mapped = "::ffff:10.0.0.2".parse().unwrap();
+        let trace = traces.start("4", mapped, &request(Some("1")));
+        scoped(trace, async {
+            record("plugin", || "ops/auth returned request".to_string());
+        })
+        .await;
+        record("plugin", || unreachable!("not traced"));
+
+        let entries = traces.get("4").unwrap();
+        let stages: Vec<_> = entries.iter().map(|e| e.stage.as_str()).collect();
+        assert_eq!(stages, ["request", "plugin"]);
+        assert!(traces.get("1").is_none());
+    }
+}
@@ -0,0 +1,468 @@
+//! A bounded, in-memory log of recently intercepted flows.
+//!
+//! Plugins granted the `flow_reader` capability can query it read-only, so
+//! they can correlate requests (e.g. a login followed by a token sent to
+//! another host) without each keeping its own copy of every flow.
+//!
+//! Flows can also be tagged, through the management API or by
+//! `[[proxy.flow_tags]]` rules (see [crate::proxy::flow_tags]), so the ones
+//! of interest can be found again in a long capture.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use hyper::Request;
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::proxy::upstream_cert::{UPSTREAM_CERT_SHA256, UpstreamCert};
+
+/// Number of flows kept by [FlowLog::default]
+pub const DEFAULT_FLOW_LOG_CAPACITY: usize = 1000;
+
+/// Most flows returned by a single query
+pub const MAX_QUERY_RESULTS: usize = 100;
+
+/// Annotation added when a plugin blocks a flow, with the plugin ID as value
+pub const BLOCKED_BY: &str = "blocked-by";
+
+/// Annotation added when a flow's `Host` disagrees with its connection
+pub const HOST_MISMATCH: &str = "host-mismatch";
+
+/// Annotation added for each kind of sensitive data found in a flow, with
+/// the kind as value
+pub const FINDING: &str = "finding";
+
+/// Annotation added for each protobuf body decoded in a flow, with the
+/// message type as value
+pub const PROTOBUF: &str = "protobuf";
+
+/// Annotation added to the flows captured by a session, with the session
+/// ID as value
+pub const SESSION: &str = "session";
+
+/// Annotation added to flows recovered from a session's journal which never
+/// completed, with why as value
+pub const INCOMPLETE: &str = "incomplete";
+
+/// Annotations added to intercepted flows with the TLS version, cipher suite
+/// and ALPN protocol negotiated with the client
+pub const TLS_VERSION: &str = "tls-version";
+pub const TLS_CIPHER_SUITE: &str = "tls-cipher-suite";
+pub const TLS_ALPN: &str = "tls-alpn";
+
+/// Longest tag a flow can be given
+pub const MAX_TAG_LENGTH: usize = 64;
+
+/// What the log remembers of a flow
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct FlowRecord {
+    pub id: String,
+    /// Unix timestamp in milliseconds when the request was received
+    pub timestamp_millis: u64,
+    pub method: String,
+    pub scheme: String,
+    pub host: String,
+    pub path: String,
+    pub query: String,
+    /// The response status, once the flow has completed
+    pub status: Option<u16>,
+    /// Milliseconds between the request being received and its response,
+    /// once the flow has completed
+    #[serde(default)]
+    pub duration_millis: Option<u64>,
+    /// `[key, value]` pairs
+    #[salvo(schema(value_type = Vec<Vec<String>>))]
+    pub annotations: Vec<(String, String)>,
+    /// Tags added through the management API or by flow tag rules
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// The certificate the upstream server presented, for flows sent to
+    /// one over TLS
+    #[serde(default)]
+    pub upstream_cert: Option<UpstreamCert>,
+    /// The tenant the client was identified as, none for anonymous clients
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+impl FlowRecord {
+    pub fn new<B>(id: &str, req: &Request<B>) -> Self {
+        let uri = req.uri();
+        Self {
+            id: id.to_string(),
+            timestamp_millis: now_millis(),
+            method: req.method().to_string(),
+            scheme: uri.scheme_str().unwrap_or("https").to_string(),
+            host: uri.host().unwrap_or_default().to_string(),
+            path: uri.path().to_string(),
+            query: uri.query().unwrap_or_default().to_string(),
+            status: None,
+            duration_millis: None,
+            annotations: Vec::new(),
+            tags: Vec::new(),
+            upstream_cert: None,
+            tenant: None,
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Whether `tag` can be given to a flow: up to [MAX_TAG_LENGTH] letters,
+/// digits and `-_.:`
+pub fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty()
+        && tag.len() <= MAX_TAG_LENGTH
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+/// Filters for [FlowLog::query]; unset fields match every flow. Queries
+/// can be saved by name for the management API's flow list.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct FlowQuery {
+    /// Only flows to this host (case-insensitive)
+    pub host: Option<String>,
+    /// Only flows received at or after this Unix timestamp in milliseconds
+    pub since_millis: Option<u64>,
+    /// Only flows received at or before this Unix timestamp in milliseconds
+    pub until_millis: Option<u64>,
+    /// Only flows carrying an annotation with this key
+    pub annotation: Option<String>,
+    /// Only flows with this tag
+    pub tag: Option<String>,
+    /// Only completed flows with a status of at least this
+    pub min_status: Option<u16>,
+    /// Only completed flows with a status of at most this
+    pub max_status: Option<u16>,
+    /// Only completed flows which took at least this many milliseconds
+    pub min_duration_millis: Option<u64>,
+    /// Return at most this many flows (capped at [MAX_QUERY_RESULTS])
+    pub limit: Option<usize>,
+}
+
+impl FlowQuery {
+    /// This query, with the fields set in `overrides` replacing its own
+    pub fn overridden_by(self, overrides: FlowQuery) -> FlowQuery {
+        FlowQuery {
+            host: overrides.host.or(self.host),
+            since_millis: overrides.since_millis.or(self.since_millis),
+            until_millis: overrides.until_millis.or(self.until_millis),
+            annotation: overrides.annotation.or(self.annotation),
+            tag: overrides.tag.or(self.tag),
+            min_status: overrides.min_status.or(self.min_status),
+            max_status: overrides.max_status.or(self.max_status),
+            min_duration_millis: overrides.min_duration_millis.or(self.min_duration_millis),
+            limit: overrides.limit.or(self.limit),
+        }
+    }
+
+    fn matches(&self, flow: &FlowRecord) -> bool {
+        self.host
+            .as_ref()
+            .is_none_or(|host| host.eq_ignore_ascii_case(&flow.host))
+            && self
+                .since_millis
+                .is_none_or(|since| flow.timestamp_millis >= since)
+            && self
+                .until_millis
+                .is_none_or(|until| flow.timestamp_millis <= until)
+            && self
+                .annotation
+                .as_ref()
+                .is_none_or(|key| flow.annotations.iter().any(|(k, _)| k == key))
+            && self.tag.as_ref().is_none_or(|tag| flow.tags.contains(tag))
+            && self
+                .min_status
+                .is_none_or(|min| flow.status.is_some_and(|status| status >= min))
+            && self
+                .max_status
+                .is_none_or(|max| flow.status.is_some_and(|status| status <= max))
+            && self
+                .min_duration_millis
+                .is_none_or(|min| flow.duration_millis.is_some_and(|duration| duration >= min))
+    }
+}
+
+/// Recently intercepted flows, oldest first. Cheap to clone; all clones
+/// share the same log.
+#[derive(Debug, Clone)]
+pub struct FlowLog {
+    flows: Arc<Mutex<VecDeque<FlowRecord>>>,
+    capacity: usize,
+}
+
+impl Default for FlowLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_FLOW_LOG_CAPACITY)
+    }
+}
+
+impl FlowLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            flows: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Add a flow, evicting the oldest once the log is full
+    pub fn record(&self, flow: FlowRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut flows = self.flows.lock().unwrap();
+        if flows.len() == self.capacity {
+            flows.pop_front();
+        }
+        flows.push_back(flow);
+    }
+
+    /// Set the response status of a flow, and how long it took
+    pub fn complete(&self, id: &str, status: u16) {
+        let now = now_millis();
+        self.update(id, |flow| {
+            flow.status = Some(status);
+            flow.duration_millis = Some(now.saturating_sub(flow.timestamp_millis));
+        });
+    }
+
+    /// Record the certificate the upstream server of a flow presented,
+    /// annotating the flow with its fingerprint
+    pub fn set_upstream_cert(&self, id: &str, cert: &UpstreamCert) {
+        self.update(id, |flow| {
+            flow.annotations
+                .push((UPSTREAM_CERT_SHA256.to_string(), cert.sha256.clone()));
+            flow.upstream_cert = Some(cert.clone());
+        });
+    }
+
+    /// Tag a flow, unless it already has the tag. Returns false if the flow
+    /// isn't in the log.
+    pub fn tag(&self, id: &str, tag: &str) -> bool {
+        self.update(id, |flow| {
+            if !flow.tags.iter().any(|t| t == tag) {
+                flow.tags.push(tag.to_string());
+            }
+        })
+    }
+
+    /// Remove a tag from a flow. Returns false if the flow isn't in the log
+    /// or didn't have the tag.
+    pub fn untag(&self, id: &str, tag: &str) -> bool {
+        let mut removed = false;
+        self.update(id, |flow| {
+            let before = flow.tags.len();
+            flow.tags.retain(|t| t != tag);
+            removed = flow.tags.len() < before;
+        });
+        removed
+    }
+
+    /// Attach a `key`/`value` annotation to a flow
+    pub fn annotate(&self, id: &str, key: &str, value: &str) {
+        self.update(id, |flow| {
+            flow.annotations.push((key.to_string(), value.to_string()))
+        });
+    }
+
+    /// Attach a `key`/`value` annotation to a flow, unless it already has it
+    pub fn annotate_once(&self, id: &str, key: &str, value: &str) {
+        self.update(id, |flow| {
+            if !flow.annotations.iter().any(|(k, v)| k == key && v == value) {
+                flow.annotations.push((key.to_string(), value.to_string()))
+            }
+        });
+    }
+
+    /// Flows matching `query`, newest first
+    pub fn query(&self, query: &FlowQuery) -> Vec<FlowRecord> {
+        self.query_visible(query, |_| true)
+    }
+
+    /// Flows matching `query` for which `visible` holds, newest first
+    pub fn query_visible(
+        &self,
+        query: &FlowQuery,
+        visible: impl Fn(&FlowRecord) -> bool,
+    ) -> Vec<FlowRecord> {
+        let limit = query
+            .limit
+            .unwrap_or(MAX_QUERY_RESULTS)
+            .min(MAX_QUERY_RESULTS);
+        self.flows
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|flow| query.matches(flow) && visible(flow))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// The flow with the given ID, if it's still in the log
+    pub fn get(&self, id: &str) -> Option<FlowRecord> {
+        self.flows
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|flow| flow.id == id)
+            .cloned()
+    }
+
+    /// Update the flow with the given ID. Returns false if it isn't in the
+    /// log.
+    fn update(&self, id: &str, f: impl FnOnce(&mut FlowRecord)) -> bool {
+        // Updates almost always concern one of the latest flows
+        match self
+            .flows
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .rev()
+            .find(|flow| flow.id == id)
+        {
+            Some(flow) => {
+                f(flow);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flow(id: &str, uri: &str, timestamp_millis: u64) -> FlowRecord {
+        let req = Request::get(uri).body(()).unwrap();
+        FlowRecord {
+            timestamp_millis,
+            ..FlowRecord::new(id, &req)
+        }
+    }
+
+    #[test]
+    fn queries_filter_recent_flows() {
+        let log = FlowLog::new(3);
+        log.record(flow("1", "https://login.example/session", 100));
+        log.record(flow("2", "https://login.example/account?token=x", 200));
+        log.record(flow("3", "https://tracker.example/t", 300));
+        log.annotate("3", BLOCKED_BY, "ops/blocker");
+        log.complete("2", 200);
+
+        let ids = |query: FlowQuery| -> Vec<String> {
+            log.query(&query).into_iter().map(|flow| flow.id).collect()
+        };
+        assert_eq!(ids(FlowQuery::default()), ["3", "2", "1"]);
+        assert_eq!(
+            ids(FlowQuery {
+                host: Some("LOGIN.example".to_string()),
+                since_millis: Some(150),
+                ..Default::default()
+            }),
+            ["2"]
+        );
+        assert_eq!(
+            ids(FlowQuery {
+                annotation: Some(BLOCKED_BY.to_string()),
+                ..Default::default()
+            }),
+            ["3"]
+        );
+        assert_eq!(
+            ids(FlowQuery {
+                until_millis: Some(250),
+                limit: Some(1),
+                ..Default::default()
+            }),
+            ["2"]
+        );
+
+        let completed = &log.query(&FlowQuery::default())[1];
+        assert_eq!(completed.status, Some(200));
+        assert_eq!(completed.query, "token=x");
+
+        // The oldest flow is evicted once the log is full
+        log.record(flow("4", "https://login.example/", 400));
+        assert_eq!(ids(FlowQuery::default()), ["4", "3", "2"]);
+    }
+
+    #[test]
+    fn flows_are_found_by_tag_status_and_duration() {
+        let log = FlowLog::default();
+        let now = now_millis();
+        log.record(flow("1", "https://api.example/users", now - 3000));
+        log.record(flow("2", "https://api.example/orders", now - 10));
+        log.record(flow("3", "https://api.example/health", now));
+        log.complete("1", 200);
+        log.complete("2", 502);
+
+        assert!(log.tag("2", "checkout"));
+        assert!(log.tag("2", "checkout"));
+        assert!(log.tag("3", "checkout"));
+        assert!(!log.tag("unknown", "checkout"));
+        assert_eq!(log.get("2").unwrap().tags, ["checkout"]);
+        assert!(log.untag("3", "checkout"));
+        assert!(!log.untag("3", "checkout"));
+
+        let ids = |query: FlowQuery| -> Vec<String> {
+            log.query(&query).into_iter().map(|flow| flow.id).collect()
+        };
+        assert_eq!(
+            ids(FlowQuery {
+                tag: Some("checkout".to_string()),
+                ..Default::default()
+            }),
+            ["2"]
+        );
+        let api_errors = FlowQuery {
+            host: Some("api.example".to_string()),
+            min_status: Some(400),
+            ..Default::default()
+        };
+        assert_eq!(ids(api_errors.clone()), ["2"]);
+        // Flows still in progress have no status, so match no bounds on it
+        assert_eq!(
+            ids(FlowQuery {
+                max_status: Some(299),
+                ..Default::default()
+            }),
+            ["1"]
+        );
+        assert_eq!(
+            ids(FlowQuery {
+                min_duration_millis: Some(2000),
+                ..Default::default()
+            }),
+            ["1"]
+        );
+
+        // A saved query's fields are replaced by those given with it
+        let overridden = api_errors.overridden_by(FlowQuery {
+            min_status: Some(200),
+            ..Default::default()
+        });
+        assert_eq!(ids(overridden), ["2", "1"]);
+    }
+
+    #[test]
+    fn tags_are_restricted() {
+        assert!(is_valid_tag("api-errors"));
+        assert!(is_valid_tag("slow:2s"));
+        assert!(!is_valid_tag(""));
+        assert!(!is_valid_tag("two words"));
+        assert!(!is_valid_tag(&"x".repeat(MAX_TAG_LENGTH + 1)));
+    }
+}
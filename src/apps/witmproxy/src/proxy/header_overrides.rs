@@ -0,0 +1,291 @@
+//! Header override profiles, forcing the `User-Agent`, `Accept-Language` or
+//! other headers of requests sent upstream, to try how a server treats
+//! another locale or device without touching the client.
+//!
+//! A named profile sets headers on each request it applies to, replacing
+//! those the client (or a plugin) sent. Profiles are applied to flows by
+//! rules matching their host, or every host, configured as
+//! `[[proxy.header_overrides]]` tables or replaced at runtime through
+//! `/api/manage/header-overrides` and `witm headers`:
+//!
+//! ```toml
+//! [[proxy.header_profiles]]
+//! name = "german"
+//! accept_language = "de-DE,de;q=0.9"
+//! headers = { "X-Forwarded-For" = "85.214.0.1" }
+//!
+//! [[proxy.header_overrides]]
+//! host = "*.example.de"
+//! profile = "german"
+//!
+//! [[proxy.header_overrides]]
+//! profile = "iphone"
+//! ```
+//!
+//! The first matching rule applies. Besides the built-in profiles
+//! ([builtin_profiles]), others can be defined as `[[proxy.header_profiles]]`
+//! tables, replacing built-in ones of the same name.
+
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{Context, Result, bail};
+use hyper::header::{ACCEPT_LANGUAGE, HeaderMap, HeaderName, HeaderValue, USER_AGENT};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::proxy::host_limits::matches_host;
+
+/// Headers profiles can't set, as they're the proxy's to manage
+const RESERVED_HEADERS: &[&str] = &[
+    "host",
+    "connection",
+    "content-length",
+    "transfer-encoding",
+    "te",
+    "trailer",
+    "upgrade",
+    "keep-alive",
+    "proxy-connection",
+];
+
+/// Headers to force on requests
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct HeaderProfile {
+    /// Name rules refer to the profile by, ex: "iphone"
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accept_language: Option<String>,
+    /// Other headers, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// Profiles available without configuration: "iphone", "android",
+/// "desktop" and "googlebot"
+pub fn builtin_profiles() -> Vec<HeaderProfile> {
+    let user_agent = |name: &str, user_agent: &str| HeaderProfile {
+        name: name.to_string(),
+        user_agent: Some(user_agent.to_string()),
+        ..Default::default()
+    };
+    vec![
+        user_agent(
+            "iphone",
+            "Mozilla/5.0 (iPhone; CPU iPhone OS 18_5 like Mac OS X) AppleWebKit/605.1.15 \
+             (KHTML, like Gecko) Version/18.5 Mobile/15E148 Safari/604.1",
+        ),
+        user_agent(
+            "android",
+            "Mozilla/5.0 (Linux; Android 15; Pixel 9) AppleWebKit/537.36 \
+             (KHTML, like Gecko) Chrome/138.0.0.0 Mobile Safari/537.36",
+        ),
+        user_agent(
+            "desktop",
+            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 \
+             (KHTML, like Gecko) Chrome/138.0.0.0 Safari/537.36",
+        ),
+        user_agent(
+            "googlebot",
+            "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)",
+        ),
+    ]
+}
+
+impl HeaderProfile {
+    /// The headers the profile sets, checked to be valid
+    fn headers(&self) -> Result<Vec<(HeaderName, HeaderValue)>> {
+        let named = [
+            (USER_AGENT, self.user_agent.as_ref()),
+            (ACCEPT_LANGUAGE, self.accept_language.as_ref()),
+        ];
+        let mut headers = Vec::new();
+        for (name, value) in named {
+            if let Some(value) = value {
+                headers.push((name, HeaderValue::from_str(value)?));
+            }
+        }
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Invalid header name {name:?}"))?;
+            if RESERVED_HEADERS.contains(&name.as_str()) {
+                bail!("Header {} can't be overridden", name);
+            }
+            let value = HeaderValue::from_str(value)
+                .with_context(|| format!("Invalid value for header {name}"))?;
+            headers.push((name, value));
+        }
+        Ok(headers)
+    }
+}
+
+/// Applies a profile to the flows matching a host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct HeaderOverrideRule {
+    /// Host name, or `*.` followed by a domain to match its subdomains
+    /// (default: every host)
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Name of the profile to apply
+    pub profile: String,
+}
+
+/// Profiles defined besides the built-in ones, and the rules applying them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct HeaderOverridesConfig {
+    #[serde(default)]
+    pub profiles: Vec<HeaderProfile>,
+    #[serde(default)]
+    pub rules: Vec<HeaderOverrideRule>,
+}
+
+/// The live header override rules. Cheap to clone; all clones share the
+/// same rules.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderOverrides {
+    config: Arc<RwLock<Arc<HeaderOverridesConfig>>>,
+}
+
+impl HeaderOverrides {
+    pub fn new(config: HeaderOverridesConfig) -> Result<Self> {
+        let overrides = Self::default();
+        overrides.set(config)?;
+        Ok(overrides)
+    }
+
+    /// Replace the profiles and rules, leaving the current ones in place if
+    /// any is invalid
+    pub fn set(&self, config: HeaderOverridesConfig) -> Result<()> {
+        let mut names = HashSet::new();
+        for profile in &config.profiles {
+            if !names.insert(profile.name.as_str()) {
+                bail!("Header profile {} is defined twice", profile.name);
+            }
+            profile
+                .headers()
+                .with_context(|| format!("Invalid header profile {}", profile.name))?;
+        }
+        let builtin = builtin_profiles();
+        for rule in &config.rules {
+            if !names.contains(rule.profile.as_str())
+                && !builtin.iter().any(|p| p.name == rule.profile)
+            {
+                bail!("Unknown header profile: {}", rule.profile);
+            }
+        }
+        *self.config.write().unwrap() = Arc::new(config);
+        Ok(())
+    }
+
+    pub fn config(&self) -> HeaderOverridesConfig {
+        (**self.config.read().unwrap()).clone()
+    }
+
+    /// The profiles rules can apply, built-in ones included
+    pub fn profiles(&self) -> Vec<HeaderProfile> {
+        let config = self.config.read().unwrap().clone();
+        let mut profiles = config.profiles.clone();
+        for profile in builtin_profiles() {
+            if !profiles.iter().any(|p| p.name == profile.name) {
+                profiles.push(profile);
+            }
+        }
+        profiles
+    }
+
+    /// The profile to apply to a flow to `host`, if any
+    pub fn profile_for(&self, host: &str) -> Option<HeaderProfile> {
+        let config = self.config.read().unwrap().clone();
+        let rule = config.rules.iter().find(|rule| {
+            rule.host
+                .as_ref()
+                .is_none_or(|pattern| matches_host(pattern, host))
+        })?;
+        config
+            .profiles
+            .iter()
+            .find(|p| p.name == rule.profile)
+            .cloned()
+            .or_else(|| {
+                builtin_profiles()
+                    .into_iter()
+                    .find(|p| p.name == rule.profile)
+            })
+    }
+
+    /// Set the headers of the profile applying to `host` in `headers`,
+    /// returning the profile's name if one applied
+    pub fn apply(&self, host: &str, headers: &mut HeaderMap) -> Option<String> {
+        let profile = self.profile_for(host)?;
+        // Profiles were checked as they were set
+        for (name, value) in profile.headers().unwrap_or_default() {
+            headers.insert(name, value);
+        }
+        Some(profile.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(host: Option<&str>, profile: &str) -> HeaderOverrideRule {
+        HeaderOverrideRule {
+            host: host.map(str::to_string),
+            profile: profile.to_string(),
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_sets_the_profile_headers() {
+        let overrides = HeaderOverrides::new(HeaderOverridesConfig {
+            profiles: vec![HeaderProfile {
+                name: "german".to_string(),
+                user_agent: None,
+                accept_language: Some("de-DE".to_string()),
+                headers: BTreeMap::from([("X-Test".to_string(), "1".to_string())]),
+            }],
+            rules: vec![rule(Some("*.example.de"), "german"), rule(None, "iphone")],
+        })
+        .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("en-US"));
+        headers.insert(USER_AGENT, HeaderValue::from_static("curl/8.0"));
+        let applied = overrides.apply("www.example.de", &mut headers);
+        assert_eq!(applied.as_deref(), Some("german"));
+        assert_eq!(headers[ACCEPT_LANGUAGE], "de-DE");
+        assert_eq!(headers["x-test"], "1");
+        assert_eq!(headers[USER_AGENT], "curl/8.0");
+
+        let applied = overrides.apply("example.org", &mut headers);
+        assert_eq!(applied.as_deref(), Some("iphone"));
+        assert!(headers[USER_AGENT].to_str().unwrap().contains("iPhone"));
+        assert_eq!(overrides.profiles().len(), 5);
+    }
+
+    #[test]
+    fn invalid_overrides_are_rejected() {
+        let overrides = HeaderOverrides::default();
+        let unknown = HeaderOverridesConfig {
+            profiles: Vec::new(),
+            rules: vec![rule(None, "fridge")],
+        };
+        assert!(overrides.set(unknown).is_err());
+
+        for (name, value) in [("Host", "example.com"), ("X-Bad", "line\nbreak")] {
+            let invalid = HeaderOverridesConfig {
+                profiles: vec![HeaderProfile {
+                    name: "invalid".to_string(),
+                    headers: BTreeMap::from([(name.to_string(), value.to_string())]),
+                    ..Default::default()
+                }],
+                rules: Vec::new(),
+            };
+            assert!(overrides.set(invalid).is_err());
+        }
+        assert!(overrides.profile_for("example.com").is_none());
+    }
+}
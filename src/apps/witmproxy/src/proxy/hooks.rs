@@ -0,0 +1,119 @@
+//! Callbacks for programs embedding the proxy, run as flows complete and as
+//! plugins fail.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::error::WitmError;
+use crate::proxy::flows::{FlowLog, FlowRecord};
+use crate::proxy::pages::FlowInfo;
+
+type FlowHook = Arc<dyn Fn(&FlowRecord) + Send + Sync>;
+type PluginErrorHook = Arc<dyn Fn(&FlowInfo, &WitmError) + Send + Sync>;
+
+/// Callbacks run by the proxy. Cheap to clone; all clones share the same
+/// callbacks.
+#[derive(Clone, Default)]
+pub struct ProxyHooks {
+    on_flow: Option<FlowHook>,
+    on_plugin_error: Option<PluginErrorHook>,
+}
+
+impl fmt::Debug for ProxyHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyHooks")
+            .field("on_flow", &self.on_flow.is_some())
+            .field("on_plugin_error", &self.on_plugin_error.is_some())
+            .finish()
+    }
+}
+
+impl ProxyHooks {
+    /// Run `hook` with each intercepted flow once its response status is
+    /// known. Flows are only recorded when the proxy has a plugin registry.
+    pub fn on_flow(mut self, hook: impl Fn(&FlowRecord) + Send + Sync + 'static) -> Self {
+        self.on_flow = Some(Arc::new(hook));
+        self
+    }
+
+    /// Run `hook` when a plugin fails while handling a flow. Flows blocked
+    /// by a plugin aren't failures and don't run it.
+    pub fn on_plugin_error(
+        mut self,
+        hook: impl Fn(&FlowInfo, &WitmError) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_plugin_error = Some(Arc::new(hook));
+        self
+    }
+
+    /// Report the flow `id`, just completed in `flows`
+    pub(crate) fn flow_completed(&self, flows: &FlowLog, id: &str) {
+        if let Some(hook) = &self.on_flow
+            && let Some(record) = flows.get(id)
+        {
+            hook(&record);
+        }
+    }
+
+    /// Report `err` if it's a plugin failure, returning it for the error page
+    pub(crate) fn plugin_failed<'a>(&self, flow: &FlowInfo, err: &'a WitmError) -> &'a WitmError {
+        if let Some(hook) = &self.on_plugin_error
+            && matches!(err, WitmError::Plugin(_) | WitmError::PluginOutput)
+        {
+            hook(flow, err);
+        }
+        err
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[test]
+    fn hooks_see_completed_flows_and_plugin_failures() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let hooks = ProxyHooks::default()
+            .on_flow({
+                let seen = seen.clone();
+                move |flow| {
+                    seen.lock()
+                        .unwrap()
+                        .push(format!("{} {:?}", flow.host, flow.status))
+                }
+            })
+            .on_plugin_error({
+                let seen = seen.clone();
+                move |flow, err| {
+                    seen.lock()
+                        .unwrap()
+                        .push(format!("{} {}", flow.host, err.code()))
+                }
+            });
+
+        let flows = FlowLog::default();
+        let req = hyper::Request::get("https://example.com/")
+            .body(())
+            .unwrap();
+        flows.record(FlowRecord::new("1", &req));
+        flows.complete("1", 204);
+        hooks.flow_completed(&flows, "1");
+        hooks.flow_completed(&flows, "missing");
+
+        let flow = FlowInfo::new("example.com");
+        hooks.plugin_failed(&flow, &WitmError::Plugin("trap".to_string()));
+        hooks.plugin_failed(
+            &flow,
+            &WitmError::PluginBlocked {
+                plugin_id: "ops/blocker".to_string(),
+            },
+        );
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            ["example.com Some(204)", "example.com plugin.failed"]
+        );
+    }
+}
@@ -0,0 +1,322 @@
+//! Limits on the requests in flight to each upstream host. Requests over a
+//! host's limit wait in a queue which takes turns between clients, so neither
+//! one busy site nor one chatty client can hold every upstream connection.
+//!
+//! `proxy.max_requests_per_host` applies to every host, and
+//! `[[proxy.host_limits]]` tables override it for matching hosts:
+//!
+//! ```toml
+//! [[proxy.host_limits]]
+//! host = "*.example.com"
+//! max_concurrent = 4
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+/// The number of requests allowed in flight to matching hosts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct HostLimitRule {
+    /// Host name, or `*.` followed by a domain to match its subdomains
+    pub host: String,
+    /// Requests allowed in flight at once, or 0 for no limit
+    pub max_concurrent: usize,
+}
+
+impl HostLimitRule {
+    fn matches(&self, host: &str) -> bool {
+        matches_host(&self.host, host)
+    }
+}
+
+/// Whether `host` is `pattern`, or a subdomain of the domain in a `*.`
+/// pattern
+pub(crate) fn matches_host(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .len()
+            .checked_sub(domain.len())
+            .filter(|&split| split > 0 && host.is_char_boundary(split))
+            .is_some_and(|split| {
+                host[split..].eq_ignore_ascii_case(domain) && host[..split].ends_with('.')
+            }),
+        None => pattern.eq_ignore_ascii_case(host),
+    }
+}
+
+/// The queue of requests to one host, as reported by the status endpoint
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct HostQueueStatus {
+    pub host: String,
+    pub limit: usize,
+    /// Requests currently sent to the host
+    pub in_flight: usize,
+    /// Requests waiting for one of those to finish
+    pub queued: usize,
+}
+
+/// Hands out permits to send requests upstream, within the configured
+/// per-host limits. Cheap to clone; all clones share the same queues.
+#[derive(Debug, Clone, Default)]
+pub struct HostLimiter {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    default_limit: Option<usize>,
+    rules: Vec<HostLimitRule>,
+    queues: Mutex<HashMap<String, HostQueue>>,
+}
+
+#[derive(Debug)]
+struct HostQueue {
+    limit: usize,
+    in_flight: usize,
+    /// Waiting requests grouped by client, in the order clients take turns
+    waiting: VecDeque<(IpAddr, VecDeque<oneshot::Sender<HostPermit>>)>,
+}
+
+impl HostQueue {
+    fn enqueue(&mut self, client: IpAddr, waiter: oneshot::Sender<HostPermit>) {
+        match self.waiting.iter_mut().find(|(c, _)| *c == client) {
+            Some((_, waiters)) => waiters.push_back(waiter),
+            None => self.waiting.push_back((client, VecDeque::from([waiter]))),
+        }
+    }
+
+    /// The first waiter of the client whose turn it is
+    fn next_waiter(&mut self) -> Option<oneshot::Sender<HostPermit>> {
+        let (client, mut waiters) = self.waiting.pop_front()?;
+        let waiter = waiters.pop_front();
+        if !waiters.is_empty() {
+            self.waiting.push_back((client, waiters));
+        }
+        waiter
+    }
+
+    fn queued(&self) -> usize {
+        self.waiting
+            .iter()
+            .flat_map(|(_, waiters)| waiters)
+            .filter(|waiter| !waiter.is_closed())
+            .count()
+    }
+}
+
+impl HostLimiter {
+    /// Limit every host to `default_limit` requests, except those matching
+    /// one of `rules`, where the first match applies
+    pub fn new(default_limit: Option<usize>, rules: Vec<HostLimitRule>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                default_limit,
+                rules,
+                queues: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    fn limit_for(&self, host: &str) -> Option<usize> {
+        self.inner
+            .rules
+            .iter()
+            .find(|rule| rule.matches(host))
+            .map(|rule| rule.max_concurrent)
+            .or(self.inner.default_limit)
+            .filter(|&limit| limit > 0)
+    }
+
+    fn queues(&self) -> MutexGuard<'_, HashMap<String, HostQueue>> {
+        self.inner
+            .queues
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Wait until `client` may send a request to `host`. The returned permit
+    /// is held until the exchange finishes, and is `None` for unlimited hosts.
+    pub async fn acquire(&self, host: &str, client: IpAddr) -> Option<HostPermit> {
+        let limit = self.limit_for(host)?;
+        let host = host.to_ascii_lowercase();
+        let permit = {
+            let mut queues = self.queues();
+            let queue = queues.entry(host.clone()).or_insert_with(|| HostQueue {
+                limit,
+                in_flight: 0,
+                waiting: VecDeque::new(),
+            });
+            if queue.in_flight < queue.limit && queue.waiting.is_empty() {
+                queue.in_flight += 1;
+                None
+            } else {
+                let (tx, rx) = oneshot::channel();
+                queue.enqueue(client, tx);
+                Some(rx)
+            }
+        };
+        match permit {
+            None => Some(HostPermit::new(self.clone(), host)),
+            // The sender is only dropped with a permit, so this always succeeds
+            Some(rx) => rx.await.ok(),
+        }
+    }
+
+    /// Pass a finished request's slot to the next waiter, or free it
+    fn release(&self, host: &str) {
+        let mut queues = self.queues();
+        let Some(queue) = queues.get_mut(host) else {
+            return;
+        };
+        while let Some(waiter) = queue.next_waiter() {
+            match waiter.send(HostPermit::new(self.clone(), host.to_string())) {
+                Ok(()) => return,
+                // The request was abandoned while waiting
+                Err(mut permit) => permit.host = None,
+            }
+        }
+        queue.in_flight -= 1;
+        if queue.in_flight == 0 {
+            queues.remove(host);
+        }
+    }
+
+    /// The hosts with requests in flight, and how many are waiting for each
+    pub fn status(&self) -> Vec<HostQueueStatus> {
+        let mut status: Vec<_> = self
+            .queues()
+            .iter()
+            .map(|(host, queue)| HostQueueStatus {
+                host: host.clone(),
+                limit: queue.limit,
+                in_flight: queue.in_flight,
+                queued: queue.queued(),
+            })
+            .collect();
+        status.sort_by(|a, b| a.host.cmp(&b.host));
+        status
+    }
+}
+
+/// Allows one request to a host; dropping it lets the next one through
+#[derive(Debug)]
+pub struct HostPermit {
+    limiter: HostLimiter,
+    // Unset when the permit is discarded without having been used
+    host: Option<String>,
+}
+
+impl HostPermit {
+    fn new(limiter: HostLimiter, host: String) -> Self {
+        Self {
+            limiter,
+            host: Some(host),
+        }
+    }
+}
+
+impl Drop for HostPermit {
+    fn drop(&mut self) {
+        if let Some(host) = self.host.take() {
+            self.limiter.release(&host);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn client(last: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, last])
+    }
+
+    #[test]
+    fn rules_override_the_default_limit() {
+        let limiter = HostLimiter::new(
+            Some(8),
+            vec![
+                HostLimitRule {
+                    host: "*.example.com".to_string(),
+                    max_concurrent: 2,
+                },
+                HostLimitRule {
+                    host: "unlimited.test".to_string(),
+                    max_concurrent: 0,
+                },
+            ],
+        );
+        assert_eq!(limiter.limit_for("api.example.com"), Some(2));
+        assert_eq!(limiter.limit_for("API.Example.com"), Some(2));
+        assert_eq!(limiter.limit_for("example.com"), Some(8));
+        assert_eq!(limiter.limit_for("badexample.com"), Some(8));
+        assert_eq!(limiter.limit_for("unlimited.test"), None);
+        assert_eq!(HostLimiter::default().limit_for("example.com"), None);
+    }
+
+    #[tokio::test]
+    async fn waiting_clients_take_turns() {
+        let limiter = HostLimiter::new(Some(1), vec![]);
+        let first = limiter.acquire("example.com", client(1)).await.unwrap();
+
+        // One client queues three requests, then another client queues one
+        let (order_tx, mut order_rx) = tokio::sync::mpsc::unbounded_channel();
+        for (i, last) in [1, 1, 1, 2].into_iter().enumerate() {
+            let limiter = limiter.clone();
+            let order_tx = order_tx.clone();
+            tokio::spawn(async move {
+                let _permit = limiter.acquire("example.com", client(last)).await;
+                order_tx.send(last).unwrap();
+            });
+            // Let the request join the queue before the next one
+            while limiter.status()[0].queued <= i {
+                tokio::task::yield_now().await;
+            }
+        }
+        assert_eq!(
+            limiter.status(),
+            vec![HostQueueStatus {
+                host: "example.com".to_string(),
+                limit: 1,
+                in_flight: 1,
+                queued: 4,
+            }]
+        );
+
+        drop(first);
+        let mut order = Vec::new();
+        for _ in 0..4 {
+            order.push(
+                tokio::time::timeout(Duration::from_secs(5), order_rx.recv())
+                    .await
+                    .unwrap()
+                    .unwrap(),
+            );
+        }
+        assert_eq!(order, vec![1, 2, 1, 1]);
+        assert_eq!(limiter.status(), vec![]);
+    }
+
+    #[tokio::test]
+    async fn abandoned_requests_give_up_their_turn() {
+        let limiter = HostLimiter::new(Some(1), vec![]);
+        let first = limiter.acquire("example.com", client(1)).await.unwrap();
+        let abandoned = tokio::time::timeout(
+            Duration::from_millis(10),
+            limiter.acquire("example.com", client(2)),
+        )
+        .await;
+        assert!(abandoned.is_err());
+        assert_eq!(limiter.status()[0].queued, 0);
+
+        drop(first);
+        assert_eq!(limiter.status(), vec![]);
+        assert!(limiter.acquire("example.com", client(3)).await.is_some());
+    }
+}
@@ -0,0 +1,457 @@
+//! Automatic HTTPS upgrades for plain HTTP requests to hosts known to require
+//! HTTPS.
+//!
+//! With `proxy.https_upgrade` enabled, `http://` requests are sent upstream
+//! over HTTPS when their host is on the HSTS preload list, or sent a
+//! `Strict-Transport-Security` header over an intercepted HTTPS connection
+//! before. A seed of the preload list is bundled, and the full list is
+//! fetched daily from `proxy.hsts_preload_url` if set. Observed headers are
+//! saved to the database so they last across restarts.
+//!
+//! Navigations are answered with a redirect rather than upgraded in place:
+//! the browser would otherwise keep treating the page as an insecure
+//! `http://` origin, dropping the `Secure` cookies it's sent. Every upgrade
+//! is counted in the `witmproxy.https_upgrades` metric.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::{Request, Response, StatusCode, Uri};
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::proxy::flow_trace;
+use crate::proxy::pages::{ErrorResponse, FLOW_ID_HEADER, FlowInfo};
+
+/// How often the preload list is fetched from `proxy.hsts_preload_url`
+pub const PRELOAD_REFRESH_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+const BUNDLED_PRELOAD: &str = include_str!("hsts_preload.json");
+
+/// Why a host is upgraded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpgradeSource {
+    Preload,
+    Observed,
+}
+
+impl UpgradeSource {
+    fn as_str(self) -> &'static str {
+        match self {
+            UpgradeSource::Preload => "preload",
+            UpgradeSource::Observed => "observed",
+        }
+    }
+}
+
+/// A `Strict-Transport-Security` policy a host sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObservedHsts {
+    pub include_subdomains: bool,
+    /// When the policy expires, in seconds since the Unix epoch
+    pub expires_at: i64,
+}
+
+#[derive(Deserialize)]
+struct PreloadList {
+    entries: Vec<PreloadEntry>,
+}
+
+#[derive(Deserialize)]
+struct PreloadEntry {
+    name: String,
+    #[serde(default)]
+    mode: Option<String>,
+    #[serde(default)]
+    include_subdomains: bool,
+}
+
+/// Parse a preload list in the format of Chromium's
+/// `transport_security_state_static.json`, into whether each host's
+/// subdomains are included. Entries which only pin keys are skipped.
+pub fn parse_preload(list: &str) -> Result<HashMap<String, bool>> {
+    // The list is JSON with `//` comment lines
+    let json = list
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("//"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let list: PreloadList = serde_json::from_str(&json)?;
+    Ok(list
+        .entries
+        .into_iter()
+        .filter(|entry| entry.mode.as_deref() == Some("force-https"))
+        .map(|entry| (entry.name.to_ascii_lowercase(), entry.include_subdomains))
+        .collect())
+}
+
+#[derive(Default)]
+struct Observed {
+    hosts: BTreeMap<String, ObservedHsts>,
+    /// Hosts changed since the last [HttpsUpgrades::take_changed]
+    changed: BTreeSet<String>,
+}
+
+/// The hosts plain HTTP requests are upgraded for. Cheap to clone; all
+/// clones share the same lists.
+#[derive(Clone)]
+pub struct HttpsUpgrades {
+    preload: Arc<RwLock<Arc<HashMap<String, bool>>>>,
+    observed: Arc<Mutex<Observed>>,
+    client: reqwest::Client,
+}
+
+impl std::fmt::Debug for HttpsUpgrades {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpsUpgrades")
+            .field("preload", &self.preload.read().unwrap().len())
+            .field("observed", &self.observed.lock().unwrap().hosts.len())
+            .finish()
+    }
+}
+
+impl Default for HttpsUpgrades {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpsUpgrades {
+    /// Upgrades for the hosts on the bundled preload list
+    pub fn new() -> Self {
+        let preload = parse_preload(BUNDLED_PRELOAD).expect("bundled HSTS preload list is valid");
+        Self {
+            preload: Arc::new(RwLock::new(Arc::new(preload))),
+            observed: Arc::default(),
+            client: reqwest::Client::default(),
+        }
+    }
+
+    /// Replace the preload list
+    pub fn set_preload(&self, preload: HashMap<String, bool>) {
+        *self.preload.write().unwrap() = Arc::new(preload);
+    }
+
+    /// Fetch the preload list from `url`, replacing the current one.
+    /// Returns the number of hosts on it.
+    pub async fn refresh_preload(&self, url: &str) -> Result<usize> {
+        let list = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let preload = parse_preload(&list)?;
+        let hosts = preload.len();
+        self.set_preload(preload);
+        Ok(hosts)
+    }
+
+    /// Refresh the preload list from `url` every `interval`, keeping the
+    /// list we have while it's unavailable, until the task is dropped
+    pub async fn refresh_loop(self, url: String, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match self.refresh_preload(&url).await {
+                Ok(hosts) => debug!("Refreshed HSTS preload list: {} hosts", hosts),
+                Err(e) => warn!("Failed to refresh HSTS preload list from {}: {}", url, e),
+            }
+        }
+    }
+
+    /// Add a policy saved earlier, without marking it changed
+    pub fn load(&self, host: &str, policy: ObservedHsts) {
+        self.observed
+            .lock()
+            .unwrap()
+            .hosts
+            .insert(host.to_ascii_lowercase(), policy);
+    }
+
+    /// Policies changed since the last call, with `None` for those removed
+    pub fn take_changed(&self) -> Vec<(String, Option<ObservedHsts>)> {
+        let mut observed = self.observed.lock().unwrap();
+        let changed = std::mem::take(&mut observed.changed);
+        changed
+            .into_iter()
+            .map(|host| {
+                let policy = observed.hosts.get(&host).copied();
+                (host, policy)
+            })
+            .collect()
+    }
+
+    /// Note the `Strict-Transport-Security` policy in headers `host` sent
+    /// over HTTPS, if any
+    pub fn observe(&self, host: &str, headers: &HeaderMap) {
+        self.observe_at(host, headers, unix_now());
+    }
+
+    fn observe_at(&self, host: &str, headers: &HeaderMap, now: i64) {
+        // Only the first header counts, and IP addresses never have policies
+        let Some((max_age, include_subdomains)) = headers
+            .get(header::STRICT_TRANSPORT_SECURITY)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_sts)
+        else {
+            return;
+        };
+        if host.parse::<std::net::IpAddr>().is_ok() {
+            return;
+        }
+        let host = host.to_ascii_lowercase();
+        let mut observed = self.observed.lock().unwrap();
+        let policy = ObservedHsts {
+            include_subdomains,
+            expires_at: now.saturating_add(max_age.min(i64::MAX as u64) as i64),
+        };
+        let changed = if max_age == 0 {
+            observed.hosts.remove(&host).is_some()
+        } else {
+            observed.hosts.insert(host.clone(), policy) != Some(policy)
+        };
+        if changed {
+            observed.changed.insert(host);
+        }
+    }
+
+    /// Why plain HTTP requests to `host` are upgraded, if they are
+    pub fn source_for(&self, host: &str) -> Option<UpgradeSource> {
+        self.source_at(host, unix_now())
+    }
+
+    fn source_at(&self, host: &str, now: i64) -> Option<UpgradeSource> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let preload = self.preload.read().unwrap().clone();
+        let observed = self.observed.lock().unwrap();
+        // The host itself, then each parent domain covering its subdomains
+        let mut domain = host.as_str();
+        let mut exact = true;
+        loop {
+            if preload
+                .get(domain)
+                .is_some_and(|subdomains| exact || *subdomains)
+            {
+                return Some(UpgradeSource::Preload);
+            }
+            if observed.hosts.get(domain).is_some_and(|policy| {
+                policy.expires_at > now && (exact || policy.include_subdomains)
+            }) {
+                return Some(UpgradeSource::Observed);
+            }
+            domain = domain.split_once('.')?.1;
+            exact = false;
+        }
+    }
+
+    /// Upgrade a plain HTTP request to HTTPS if its host requires it.
+    /// Navigations are answered with a redirect to the HTTPS URL instead.
+    pub fn upgrade<B>(&self, mut req: Request<B>, flow: &FlowInfo) -> Upgraded<B> {
+        if req.uri().scheme_str() != Some("http") {
+            return Upgraded::Request(req);
+        }
+        let Some(host) = req.uri().host() else {
+            return Upgraded::Request(req);
+        };
+        let Some(source) = self.source_for(host) else {
+            return Upgraded::Request(req);
+        };
+        let Some(uri) = https_uri(req.uri()) else {
+            return Upgraded::Request(req);
+        };
+
+        if is_navigation(&req) {
+            crate::telemetry::otel::record_https_upgrade(source.as_str(), "redirect");
+            flow_trace::record("https-upgrade", || {
+                format!("redirected to {} ({})", uri, source.as_str())
+            });
+            return Upgraded::Redirect(redirect(&uri, flow));
+        }
+
+        crate::telemetry::otel::record_https_upgrade(source.as_str(), "rewrite");
+        flow_trace::record("https-upgrade", || {
+            format!("upgraded to {} ({})", uri, source.as_str())
+        });
+        if req.headers().contains_key(header::HOST)
+            && let Some(authority) = uri.authority()
+            && let Ok(value) = HeaderValue::from_str(authority.as_str())
+        {
+            req.headers_mut().insert(header::HOST, value);
+        }
+        *req.uri_mut() = uri;
+        Upgraded::Request(req)
+    }
+}
+
+/// A request after [HttpsUpgrades::upgrade]
+pub enum Upgraded<B> {
+    /// Sent on, over HTTPS if its host requires it
+    Request(Request<B>),
+    /// Answered with a redirect to its HTTPS URL
+    Redirect(ErrorResponse),
+}
+
+/// The max-age and whether subdomains are included from a
+/// `Strict-Transport-Security` value (RFC 6797 section 6.1)
+fn parse_sts(value: &str) -> Option<(u64, bool)> {
+    let mut max_age = None;
+    let mut include_subdomains = false;
+    for directive in value.split(';') {
+        let (name, value) = match directive.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+            None => (directive.trim(), None),
+        };
+        if name.eq_ignore_ascii_case("max-age") {
+            max_age = Some(value?.parse().ok()?);
+        } else if name.eq_ignore_ascii_case("includesubdomains") {
+            include_subdomains = true;
+        }
+    }
+    Some((max_age?, include_subdomains))
+}
+
+/// `uri` with the https scheme, moving the default HTTP port to HTTPS's
+fn https_uri(uri: &Uri) -> Option<Uri> {
+    let authority = uri.authority()?;
+    let authority = match authority.port_u16() {
+        Some(80) => authority.host().to_string(),
+        _ => authority.as_str().to_string(),
+    };
+    Uri::builder()
+        .scheme("https")
+        .authority(authority)
+        .path_and_query(uri.path_and_query().map_or("/", |p| p.as_str()))
+        .build()
+        .ok()
+}
+
+/// Whether a browser is loading a page, rather than a resource of one
+fn is_navigation<B>(req: &Request<B>) -> bool {
+    match req.headers().get("sec-fetch-mode") {
+        Some(mode) => mode == "navigate",
+        None => req
+            .headers()
+            .get("upgrade-insecure-requests")
+            .is_some_and(|value| value == "1"),
+    }
+}
+
+/// A temporary redirect keeping the method and body, as browsers send for
+/// HSTS hosts themselves
+fn redirect(uri: &Uri, flow: &FlowInfo) -> ErrorResponse {
+    Response::builder()
+        .status(StatusCode::TEMPORARY_REDIRECT)
+        .header(header::LOCATION, uri.to_string())
+        .header("non-authoritative-reason", "HSTS")
+        .header(header::CACHE_CONTROL, "no-store")
+        .header(FLOW_ID_HEADER, flow.id.as_str())
+        .body(
+            Empty::<Bytes>::new()
+                .map_err(|never| match never {})
+                .boxed_unsync(),
+        )
+        .expect("Could not construct redirect Response")
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sts(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::STRICT_TRANSPORT_SECURITY,
+            HeaderValue::from_str(value).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn preloaded_domains_cover_their_subdomains() {
+        let upgrades = HttpsUpgrades::new();
+        assert_eq!(
+            upgrades.source_for("example.dev"),
+            Some(UpgradeSource::Preload)
+        );
+        assert_eq!(
+            upgrades.source_for("paypal.com"),
+            Some(UpgradeSource::Preload)
+        );
+        assert_eq!(upgrades.source_for("www.paypal.com"), None);
+        assert_eq!(upgrades.source_for("example.com"), None);
+
+        let preload = parse_preload(
+            "// comment\n{\"entries\": [{\"name\": \"Example.com\", \"mode\": \"force-https\", \"include_subdomains\": true}, {\"name\": \"pins.example.org\", \"pins\": \"test\"}]}",
+        )
+        .unwrap();
+        assert_eq!(preload, HashMap::from([("example.com".to_string(), true)]));
+    }
+
+    #[test]
+    fn observed_policies_expire_and_can_be_cleared() {
+        let upgrades = HttpsUpgrades::new();
+        upgrades.observe_at("example.com", &sts("max-age=60; includeSubDomains"), 1000);
+        upgrades.observe_at("127.0.0.1", &sts("max-age=60"), 1000);
+        assert_eq!(
+            upgrades.source_at("api.example.com", 1059),
+            Some(UpgradeSource::Observed)
+        );
+        assert_eq!(upgrades.source_at("api.example.com", 1060), None);
+        assert_eq!(upgrades.source_at("127.0.0.1", 1000), None);
+        assert_eq!(upgrades.take_changed().len(), 1);
+
+        upgrades.observe_at("example.com", &sts("max-age=0"), 1001);
+        assert_eq!(upgrades.source_at("example.com", 1001), None);
+        assert_eq!(
+            upgrades.take_changed(),
+            vec![("example.com".to_string(), None)]
+        );
+    }
+
+    #[test]
+    fn navigations_are_redirected_and_resources_rewritten() {
+        let upgrades = HttpsUpgrades::new();
+        let flow = FlowInfo::new("example.dev");
+
+        let req = Request::get("http://example.dev:80/a?b=c")
+            .header(header::HOST, "example.dev:80")
+            .body(())
+            .unwrap();
+        let Upgraded::Request(req) = upgrades.upgrade(req, &flow) else {
+            panic!("resource requests are rewritten");
+        };
+        assert_eq!(req.uri(), "https://example.dev/a?b=c");
+        assert_eq!(req.headers()[header::HOST], "example.dev");
+
+        let req = Request::get("http://example.dev/")
+            .header("sec-fetch-mode", "navigate")
+            .body(())
+            .unwrap();
+        let Upgraded::Redirect(redirect) = upgrades.upgrade(req, &flow) else {
+            panic!("navigations are redirected");
+        };
+        assert_eq!(redirect.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(redirect.headers()[header::LOCATION], "https://example.dev/");
+
+        let req = Request::get("http://example.com/").body(()).unwrap();
+        let Upgraded::Request(req) = upgrades.upgrade(req, &flow) else {
+            panic!("other hosts are left alone");
+        };
+        assert_eq!(req.uri(), "http://example.com/");
+    }
+}
@@ -0,0 +1,163 @@
+//! Image optimization, transcoding the images in responses without a
+//! plugin, for testing pages over constrained connections and keeping
+//! metadata such as photo locations from clients.
+//!
+//! With `proxy.optimize_images` on, JPEG, PNG and WebP responses are
+//! buffered up to `proxy.max_image_bytes`, scaled down to fit
+//! `proxy.image_max_dimension` and converted to `proxy.image_format` when
+//! the client's `Accept` header allows it (see [crate::plugins::images]).
+
+use bytes::Bytes;
+use http_body::Body;
+use http_body_util::combinators::UnsyncBoxBody;
+use hyper::Response;
+use hyper::header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, HeaderMap, HeaderValue, VARY};
+use tracing::{debug, warn};
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+
+use crate::config::ProxyConfig;
+use crate::events::content::InboundContent;
+use crate::http::utils::{ContentEncoding, Encoded};
+use crate::plugins::images::{
+    self, DEFAULT_MAX_IMAGE_BYTES, DEFAULT_QUALITY, ImageFormat, Transcode,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct ImageOptimizer {
+    /// How images are transcoded, if they are
+    options: Option<Transcode>,
+}
+
+impl From<&ProxyConfig> for ImageOptimizer {
+    fn from(config: &ProxyConfig) -> Self {
+        let options = config.optimize_images.then(|| Transcode {
+            max_dimension: config.image_max_dimension,
+            format: config.image_format,
+            quality: config
+                .image_quality
+                .unwrap_or(DEFAULT_QUALITY)
+                .clamp(1, 100),
+            max_bytes: config.max_image_bytes.unwrap_or(DEFAULT_MAX_IMAGE_BYTES),
+        });
+        Self { options }
+    }
+}
+
+impl ImageOptimizer {
+    pub fn is_enabled(&self) -> bool {
+        self.options.is_some()
+    }
+
+    /// The `Accept` header of a request, kept to decide which format the
+    /// image it's answered with can be converted to
+    pub fn accepted(&self, headers: &HeaderMap) -> Option<String> {
+        self.options?;
+        Some(
+            headers
+                .get(ACCEPT)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string(),
+        )
+    }
+
+    /// Transcode the image in `response`, if it holds one, for a client
+    /// which sent `accept`
+    pub async fn optimize(
+        &self,
+        accept: &str,
+        response: Response<UnsyncBoxBody<Bytes, ErrorCode>>,
+    ) -> Response<UnsyncBoxBody<Bytes, ErrorCode>> {
+        let Some(mut options) = self.options else {
+            return response;
+        };
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let is_image = matches!(response.status().as_u16(), 200..=203 | 205..=299)
+            && ImageFormat::decodable(&content_type).is_some();
+        if !is_image {
+            return response;
+        }
+        let (parts, body) = response.into_parts();
+        if matches!(parts.encoding(), ContentEncoding::Unknown) {
+            return Response::from_parts(parts, body);
+        }
+        // Whether the format depends on `Accept`, even if it isn't converted
+        let converts = options.format.is_some();
+        if let Some(format) = options.format
+            && !accepts(accept, format)
+        {
+            options.format = None;
+        }
+
+        let mut content = match InboundContent::new(parts, content_type.clone(), body) {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("Failed to decode image for optimization: {}", e);
+                return failed();
+            }
+        };
+        let Ok(Some(body)) = content.body() else {
+            return failed();
+        };
+        let (body, transcoded) = images::apply(body, &content_type, options).await;
+        let length = body.size_hint().exact();
+        content.set_body(body);
+        match transcoded {
+            Ok(format) => content.set_content_type(format.mime_type()),
+            Err(e) => debug!("Passing image through unoptimized: {:#}", e),
+        }
+        let mut response = match content.into_response() {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Failed to rebuild optimized image response: {}", e);
+                return failed();
+            }
+        };
+        let headers = response.headers_mut();
+        if let Some(length) = length {
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+        }
+        if converts {
+            headers.append(VARY, HeaderValue::from_static("Accept"));
+        }
+        response
+    }
+}
+
+/// Whether a client which sent `accept` takes images in `format`
+fn accepts(accept: &str, format: ImageFormat) -> bool {
+    match format {
+        // Every client takes these, whatever it says
+        ImageFormat::Jpeg | ImageFormat::Png => true,
+        ImageFormat::Webp | ImageFormat::Avif => accept
+            .split(',')
+            .any(|range| range.split(';').next().unwrap_or_default().trim() == format.mime_type()),
+    }
+}
+
+/// The response sent when an image's body was lost to a failed decoding
+fn failed() -> Response<UnsyncBoxBody<Bytes, ErrorCode>> {
+    let mut response = Response::new(UnsyncBoxBody::default());
+    *response.status_mut() = hyper::StatusCode::BAD_GATEWAY;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_are_only_converted_to_when_accepted() {
+        let browser = "image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8";
+        assert!(accepts(browser, ImageFormat::Avif));
+        assert!(accepts(browser, ImageFormat::Webp));
+        assert!(!accepts("image/png,image/*;q=0.8", ImageFormat::Webp));
+        assert!(!accepts("", ImageFormat::Avif));
+        assert!(accepts("", ImageFormat::Jpeg));
+    }
+}
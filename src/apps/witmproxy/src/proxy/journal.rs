@@ -0,0 +1,240 @@
+//! Write-ahead journals of running capture sessions, so the flows a session
+//! captured survive witmproxy being killed before the session stops and
+//! writes its exports.
+//!
+//! A session's journal is a file of JSON lines in the journal directory: the
+//! session itself, then an entry as each flow it captures begins and another
+//! as it completes. Each entry is written with a single `write` call, so a
+//! kill leaves at most the last line torn, which recovery ignores. The
+//! journal is removed once the session stops.
+//!
+//! On startup, the sessions left with journals are recovered and exported.
+//! Flows which began but never completed are kept with the [INCOMPLETE]
+//! annotation and no status, rather than passed off as complete.
+
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::proxy::flows::{FlowRecord, INCOMPLETE, SESSION};
+use crate::proxy::sessions::{SessionSpec, SessionSummary};
+
+/// Extension of journal files in the journal directory
+const EXTENSION: &str = "jsonl";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "entry", rename_all = "kebab-case")]
+enum Entry {
+    Session {
+        summary: SessionSummary,
+        spec: SessionSpec,
+    },
+    Begin {
+        flow: FlowRecord,
+    },
+    Complete {
+        flow: FlowRecord,
+    },
+}
+
+/// The journal of one running session
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Journal {
+    /// Start the journal of the session `summary` in `dir`
+    pub fn create(dir: &Path, summary: &SessionSummary, spec: &SessionSpec) -> Result<Self> {
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        let path = dir.join(format!("{}.{}", summary.id, EXTENSION));
+        let file = File::create(&path).with_context(|| format!("Failed to create {:?}", path))?;
+        let mut journal = Self { path, file };
+        journal.append(&Entry::Session {
+            summary: summary.clone(),
+            spec: spec.clone(),
+        })?;
+        Ok(journal)
+    }
+
+    /// Continue the journal at `path`, left by a session being recovered
+    fn reopen(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {:?}", path))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    pub fn begin(&mut self, flow: &FlowRecord) -> Result<()> {
+        self.append(&Entry::Begin { flow: flow.clone() })
+    }
+
+    pub fn complete(&mut self, flow: &FlowRecord) -> Result<()> {
+        self.append(&Entry::Complete { flow: flow.clone() })
+    }
+
+    fn append(&mut self, entry: &Entry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.file
+            .write_all(&line)
+            .with_context(|| format!("Failed to write {:?}", self.path))
+    }
+
+    /// Remove the journal of a session which has stopped
+    pub fn remove(self) -> Result<()> {
+        drop(self.file);
+        std::fs::remove_file(&self.path)
+            .with_context(|| format!("Failed to remove {:?}", self.path))
+    }
+}
+
+/// A session read back from the journal it left behind
+#[derive(Debug)]
+pub struct Recovered {
+    pub summary: SessionSummary,
+    pub spec: SessionSpec,
+    /// Completed flows in the order they completed, then those which never
+    /// did, marked [INCOMPLETE]
+    pub flows: Vec<FlowRecord>,
+    /// How many of the flows are incomplete
+    pub incomplete: usize,
+    /// The journal, to remove once the session has been exported
+    pub journal: Journal,
+}
+
+/// Journals left in `dir` by sessions which never stopped
+pub fn leftovers(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", dir)),
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+/// Read back the session journaled at `path`, stopping at the first entry
+/// which can't be read, as a torn last write leaves
+pub fn recover(path: &Path) -> Result<Recovered> {
+    let content = std::fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let mut entries = content
+        .split(|b| *b == b'\n')
+        .map_while(|line| serde_json::from_slice::<Entry>(line).ok());
+    let Some(Entry::Session { summary, spec }) = entries.next() else {
+        anyhow::bail!("{:?} doesn't start with a capture session", path);
+    };
+
+    let mut begun: Vec<FlowRecord> = Vec::new();
+    let mut completed: Vec<FlowRecord> = Vec::new();
+    for entry in entries {
+        match entry {
+            Entry::Begin { flow } => begun.push(flow),
+            Entry::Complete { flow } => completed.push(flow),
+            Entry::Session { .. } => {}
+        }
+    }
+    let done: HashSet<&str> = completed.iter().map(|f| f.id.as_str()).collect();
+    let mut interrupted: Vec<FlowRecord> = begun
+        .into_iter()
+        .filter(|flow| !done.contains(flow.id.as_str()))
+        .collect();
+    // Flows are annotated with their sessions as they complete
+    for flow in &mut interrupted {
+        flow.annotations
+            .push((SESSION.to_string(), summary.id.clone()));
+        flow.annotations
+            .push((INCOMPLETE.to_string(), "interrupted".to_string()));
+    }
+
+    let incomplete = interrupted.len();
+    let mut flows = completed;
+    flows.extend(interrupted);
+    Ok(Recovered {
+        summary,
+        spec,
+        flows,
+        incomplete,
+        journal: Journal::reopen(path)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flow(id: &str) -> FlowRecord {
+        let req = hyper::Request::get("https://api.example.com/")
+            .body(())
+            .unwrap();
+        FlowRecord::new(id, &req)
+    }
+
+    #[test]
+    fn interrupted_flows_are_recovered_as_incomplete() {
+        let dir = tempfile::tempdir().unwrap();
+        let summary = SessionSummary {
+            id: "s1".to_string(),
+            name: "checkout".to_string(),
+            started_millis: 0,
+            ends_millis: None,
+            stopped_millis: None,
+            flows: 0,
+            export: Vec::new(),
+            export_errors: Vec::new(),
+        };
+        let spec = SessionSpec {
+            name: "checkout".to_string(),
+            duration_secs: None,
+            export: Vec::new(),
+            hosts: Vec::new(),
+            max_flows: None,
+        };
+        let mut journal = Journal::create(dir.path(), &summary, &spec).unwrap();
+        journal.begin(&flow("1")).unwrap();
+        journal.begin(&flow("2")).unwrap();
+        journal
+            .complete(&FlowRecord {
+                status: Some(200),
+                ..flow("2")
+            })
+            .unwrap();
+        drop(journal);
+        // A write torn by the kill
+        let path = dir.path().join("s1.jsonl");
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"entry":"complete","flow":{"id":"1""#)
+            .unwrap();
+
+        assert_eq!(leftovers(dir.path()).unwrap(), std::slice::from_ref(&path));
+        let recovered = recover(&path).unwrap();
+        assert_eq!(recovered.summary.name, "checkout");
+        assert_eq!(recovered.incomplete, 1);
+        let ids: Vec<_> = recovered.flows.iter().map(|f| f.id.as_str()).collect();
+        assert_eq!(ids, ["2", "1"]);
+        assert_eq!(recovered.flows[0].status, Some(200));
+        assert_eq!(recovered.flows[1].status, None);
+        assert!(
+            recovered.flows[1]
+                .annotations
+                .contains(&(INCOMPLETE.to_string(), "interrupted".to_string()))
+        );
+
+        recovered.journal.remove().unwrap();
+        assert!(leftovers(dir.path()).unwrap().is_empty());
+    }
+}
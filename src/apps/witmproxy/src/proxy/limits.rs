@@ -0,0 +1,126 @@
+//! Size and time limits applied to proxied flows. Violations are reported
+//! with the pages in [super::pages].
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, Limited};
+use hyper::body::Incoming;
+use hyper::{HeaderMap, header};
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+
+use crate::config::ProxyConfig;
+
+pub const DEFAULT_UPSTREAM_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_UPSTREAM_READ_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_FLOW_DEADLINE: Duration = Duration::from_secs(60);
+
+/// The limits enforced on each request/response exchange
+#[derive(Debug, Clone, Copy)]
+pub struct FlowLimits {
+    /// Requests with larger bodies are rejected with 413
+    pub max_request_body_bytes: Option<u64>,
+    /// Responses declaring a larger `Content-Length` skip content plugins
+    pub max_buffered_response_bytes: Option<u64>,
+    pub upstream_connect_timeout: Duration,
+    pub upstream_read_timeout: Duration,
+    /// Time allowed from receiving a request until the full response is sent
+    pub flow_deadline: Duration,
+}
+
+impl Default for FlowLimits {
+    fn default() -> Self {
+        Self {
+            max_request_body_bytes: None,
+            max_buffered_response_bytes: None,
+            upstream_connect_timeout: DEFAULT_UPSTREAM_CONNECT_TIMEOUT,
+            upstream_read_timeout: DEFAULT_UPSTREAM_READ_TIMEOUT,
+            flow_deadline: DEFAULT_FLOW_DEADLINE,
+        }
+    }
+}
+
+impl From<&ProxyConfig> for FlowLimits {
+    fn from(config: &ProxyConfig) -> Self {
+        let secs = |value: Option<u64>, default| value.map(Duration::from_secs).unwrap_or(default);
+        Self {
+            max_request_body_bytes: config.max_request_body_bytes,
+            max_buffered_response_bytes: config.max_buffered_response_bytes,
+            upstream_connect_timeout: secs(
+                config.upstream_connect_timeout_secs,
+                DEFAULT_UPSTREAM_CONNECT_TIMEOUT,
+            ),
+            upstream_read_timeout: secs(
+                config.upstream_read_timeout_secs,
+                DEFAULT_UPSTREAM_READ_TIMEOUT,
+            ),
+            flow_deadline: secs(config.flow_deadline_secs, DEFAULT_FLOW_DEADLINE),
+        }
+    }
+}
+
+impl FlowLimits {
+    /// The exceeded limit, if the request declares a body over it
+    pub fn check_request(&self, headers: &HeaderMap) -> Option<u64> {
+        let limit = self.max_request_body_bytes?;
+        let length = content_length(headers)?;
+        (length > limit).then_some(limit)
+    }
+
+    /// Wrap a request body so that it fails once it exceeds the limit, for
+    /// bodies whose size isn't declared up front
+    pub fn limit_request_body(&self, body: Incoming) -> UnsyncBoxBody<Bytes, ErrorCode> {
+        let body = body.map_err(ErrorCode::from_hyper_request_error);
+        match self.max_request_body_bytes {
+            None => body.boxed_unsync(),
+            Some(limit) => Limited::new(body, usize::try_from(limit).unwrap_or(usize::MAX))
+                .map_err(move |e| match e.downcast::<ErrorCode>() {
+                    Ok(code) => *code,
+                    Err(_) => ErrorCode::HttpRequestBodySize(Some(limit)),
+                })
+                .boxed_unsync(),
+        }
+    }
+
+    /// Whether a response is small enough to hand to content plugins
+    pub fn allows_buffering(&self, headers: &HeaderMap) -> bool {
+        match (self.max_buffered_response_bytes, content_length(headers)) {
+            (Some(limit), Some(length)) => length <= limit,
+            _ => true,
+        }
+    }
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn declared_request_size_is_checked() {
+        let limits = FlowLimits {
+            max_request_body_bytes: Some(10),
+            max_buffered_response_bytes: Some(100),
+            ..Default::default()
+        };
+        let mut headers = HeaderMap::new();
+        assert!(limits.check_request(&headers).is_none());
+        assert!(limits.allows_buffering(&headers));
+
+        headers.insert(header::CONTENT_LENGTH, "11".parse().unwrap());
+        assert_eq!(limits.check_request(&headers), Some(10));
+
+        headers.insert(header::CONTENT_LENGTH, "101".parse().unwrap());
+        assert!(!limits.allows_buffering(&headers));
+        assert!(FlowLimits::default().allows_buffering(&headers));
+    }
+}
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+
+/// Whether CONNECT tunnels accepted on a listener are intercepted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum MitmPolicy {
+    /// Intercept a tunnel only when a plugin wants to handle the connection
+    #[default]
+    Auto,
+    /// Intercept every tunnel, even when no plugin matches
+    Always,
+    /// Never intercept; tunnels are forwarded as-is
+    Passthrough,
+}
+
+impl std::fmt::Display for MitmPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            MitmPolicy::Auto => "auto",
+            MitmPolicy::Always => "always",
+            MitmPolicy::Passthrough => "passthrough",
+        })
+    }
+}
+
+/// An additional proxy listener with its own policy, declared in the config file:
+///
+/// ```toml
+/// [[proxy.listeners]]
+/// name = "guests"
+/// bind_addr = "0.0.0.0:8081"
+/// mitm = "passthrough"
+/// require_auth = true
+/// tls_hostname = "proxy.lan"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListenerConfig {
+    /// Name used in logs and status output (defaults to the bind address)
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// The address to bind to, e.g. `0.0.0.0:8081`
+    pub bind_addr: String,
+
+    #[serde(default)]
+    pub mitm: MitmPolicy,
+
+    /// Plugins allowed to handle traffic on this listener, as `namespace/name`
+    /// or `namespace/*` patterns. All plugins are allowed when empty.
+    #[serde(default)]
+    pub plugins: Vec<String>,
+
+    /// Require clients to present an API token in `Proxy-Authorization`
+    /// (as a bearer token, or as the basic auth password)
+    #[serde(default)]
+    pub require_auth: bool,
+
+    /// Terminate TLS on the listener itself with a certificate minted for
+    /// this hostname, making it an HTTPS proxy: CONNECT targets and proxy
+    /// credentials are then encrypted on the local network
+    #[serde(default)]
+    pub tls_hostname: Option<String>,
+}
+
+impl ListenerConfig {
+    /// The listener bound to `proxy_bind_addr`, which intercepts with
+    /// `proxy.mitm` and admits every plugin and client.
+    pub fn primary(bind_addr: String, mitm: MitmPolicy) -> Self {
+        Self {
+            name: Some("default".to_string()),
+            bind_addr,
+            mitm,
+            plugins: Vec::new(),
+            require_auth: false,
+            tls_hostname: None,
+        }
+    }
+
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.bind_addr)
+    }
+
+    /// Whether the plugin set filter restricts which plugins may run
+    pub fn filters_plugins(&self) -> bool {
+        !self.plugins.is_empty()
+    }
+
+    /// Whether the plugin with the given `namespace/name` ID may run on this listener
+    pub fn allows_plugin(&self, plugin_id: &str) -> bool {
+        if self.plugins.is_empty() {
+            return true;
+        }
+        self.plugins.iter().any(|pattern| {
+            pattern == "*"
+                || pattern == plugin_id
+                || pattern.strip_suffix("/*").is_some_and(|namespace| {
+                    plugin_id
+                        .split_once('/')
+                        .is_some_and(|(ns, _)| ns == namespace)
+                })
+        })
+    }
+}
+
+/// A listener that has been bound, as reported in status output
+#[derive(Debug, Clone)]
+pub struct BoundListener {
+    pub name: String,
+    pub addr: std::net::SocketAddr,
+    pub mitm: MitmPolicy,
+    pub require_auth: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plugin_filter_patterns() {
+        let mut listener = ListenerConfig::primary("127.0.0.1:0".to_string(), MitmPolicy::Auto);
+        assert!(!listener.filters_plugins());
+        assert!(listener.allows_plugin("any/plugin"));
+
+        listener.plugins = vec!["dev/*".to_string(), "ops/logger".to_string()];
+        assert!(listener.allows_plugin("dev/rewriter"));
+        assert!(listener.allows_plugin("ops/logger"));
+        assert!(!listener.allows_plugin("ops/blocker"));
+        assert!(!listener.allows_plugin("development/rewriter"));
+    }
+}
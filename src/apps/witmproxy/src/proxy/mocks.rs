@@ -0,0 +1,796 @@
+//! Mock mode, for working against APIs which don't exist yet. Requests to
+//! an API described by an uploaded OpenAPI 3 document are answered with
+//! example responses from the document instead of being sent upstream.
+//!
+//! Documents are uploaded as JSON or YAML through
+//! `PUT /api/manage/mocks/{name}`. A request to one of a document's `servers`
+//! which matches an operation gets the operation's first success response,
+//! or its `default` one. The body is the media type's `example`, the first of
+//! its `examples`, or an example generated from its schema. Clients can ask
+//! for another response with `Prefer: code=404`, or for a named example with
+//! `Prefer: example=empty`. Requests matching no operation go upstream as
+//! usual, so an API can be mocked one endpoint at a time.
+//!
+//! Mocked responses pass through response plugins like any other. They
+//! carry an `x-witm-mock` header naming the document they came from.
+
+use std::sync::{Arc, RwLock};
+
+use anyhow::{Context, Result, bail};
+use bytes::Bytes;
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, Full};
+use hyper::header::{CONTENT_TYPE, HeaderMap, HeaderValue};
+use hyper::{Method, Response, StatusCode};
+use salvo::oapi::ToSchema;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tracing::debug;
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+
+use crate::proxy::parse_authority_host_port;
+
+/// Header naming the document a mocked response came from
+pub const MOCK_HEADER: &str = "x-witm-mock";
+
+/// How deeply `$ref`s and nested schemas are followed, which stops
+/// recursive schemas from generating endless examples
+const MAX_DEPTH: usize = 8;
+
+const METHODS: &[&str] = &[
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// Parse an uploaded document, as JSON or YAML
+pub fn parse_document(text: &str) -> Result<Value> {
+    if let Ok(document) = serde_json::from_str(text) {
+        return Ok(document);
+    }
+    let document: serde_yaml::Value =
+        serde_yaml::from_str(text).context("Document is neither JSON nor YAML")?;
+    Ok(yaml_to_json(document))
+}
+
+/// Convert YAML to JSON, where unquoted keys such as response codes are
+/// numbers rather than strings
+fn yaml_to_json(value: serde_yaml::Value) -> Value {
+    match value {
+        serde_yaml::Value::Null => Value::Null,
+        serde_yaml::Value::Bool(b) => Value::Bool(b),
+        serde_yaml::Value::Number(n) => serde_json::to_value(n).unwrap_or(Value::Null),
+        serde_yaml::Value::String(s) => Value::String(s),
+        serde_yaml::Value::Sequence(items) => {
+            Value::Array(items.into_iter().map(yaml_to_json).collect())
+        }
+        serde_yaml::Value::Mapping(mapping) => Value::Object(
+            mapping
+                .into_iter()
+                .map(|(key, value)| {
+                    let key = match yaml_to_json(key) {
+                        Value::String(s) => s,
+                        other => other.to_string(),
+                    };
+                    (key, yaml_to_json(value))
+                })
+                .collect(),
+        ),
+        serde_yaml::Value::Tagged(tagged) => yaml_to_json(tagged.value),
+    }
+}
+
+/// A server the operations of a document are served from
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Server {
+    scheme: String,
+    host: String,
+    port: u16,
+    /// Path prefix of every operation, without a trailing `/`
+    base_path: String,
+}
+
+impl Server {
+    /// Parse a server object's URL, filling in its variables' defaults.
+    /// Relative URLs can't be matched against requests, so are skipped.
+    fn parse(server: &Value) -> Option<Self> {
+        let mut url = server.get("url")?.as_str()?.to_string();
+        if let Some(variables) = server.get("variables").and_then(Value::as_object) {
+            for (name, variable) in variables {
+                if let Some(default) = variable.get("default").and_then(Value::as_str) {
+                    url = url.replace(&format!("{{{}}}", name), default);
+                }
+            }
+        }
+        let url = reqwest::Url::parse(&url).ok()?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return None;
+        }
+        Some(Self {
+            scheme: url.scheme().to_string(),
+            host: url.host_str()?.to_ascii_lowercase(),
+            port: url.port_or_known_default()?,
+            base_path: url.path().trim_end_matches('/').to_string(),
+        })
+    }
+
+    fn url(&self) -> String {
+        format!(
+            "{}://{}:{}{}",
+            self.scheme, self.host, self.port, self.base_path
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Operation {
+    method: Method,
+    /// The path's segments, `None` where templated
+    segments: Vec<Option<String>>,
+    responses: Value,
+}
+
+impl Operation {
+    fn matches(&self, method: &Method, segments: &[&str]) -> bool {
+        self.method == method
+            && self.segments.len() == segments.len()
+            && self
+                .segments
+                .iter()
+                .zip(segments)
+                .all(|(template, segment)| template.as_deref().is_none_or(|t| t == *segment))
+    }
+
+    fn literal_segments(&self) -> usize {
+        self.segments.iter().filter(|s| s.is_some()).count()
+    }
+}
+
+/// A mocked document, as listed by the management API
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct MockSpecSummary {
+    pub name: String,
+    /// The document's `info.title`
+    pub title: Option<String>,
+    pub servers: Vec<String>,
+    pub operations: usize,
+}
+
+/// An OpenAPI 3 document, ready to answer requests
+#[derive(Debug, Clone)]
+pub struct MockSpec {
+    name: String,
+    document: Value,
+    servers: Vec<Server>,
+    operations: Vec<Operation>,
+}
+
+impl MockSpec {
+    pub fn new(name: impl Into<String>, document: Value) -> Result<Self> {
+        let name = name.into();
+        if !document
+            .get("openapi")
+            .and_then(Value::as_str)
+            .is_some_and(|version| version.starts_with("3."))
+        {
+            bail!("{} is not an OpenAPI 3 document", name);
+        }
+
+        let servers: Vec<_> = document
+            .get("servers")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Server::parse)
+            .collect();
+        if servers.is_empty() {
+            bail!("{} declares no absolute http(s) server URLs to mock", name);
+        }
+
+        let mut operations = Vec::new();
+        for (path, item) in document
+            .get("paths")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flatten()
+        {
+            let segments: Vec<_> = path
+                .split('/')
+                .filter(|segment| !segment.is_empty())
+                .map(|segment| {
+                    (!(segment.starts_with('{') && segment.ends_with('}')))
+                        .then(|| segment.to_string())
+                })
+                .collect();
+            for method in METHODS {
+                if let Some(operation) = item.get(*method) {
+                    operations.push(Operation {
+                        method: Method::from_bytes(method.to_ascii_uppercase().as_bytes())?,
+                        segments: segments.clone(),
+                        responses: operation.get("responses").cloned().unwrap_or_default(),
+                    });
+                }
+            }
+        }
+
+        Ok(Self {
+            name,
+            document,
+            servers,
+            operations,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn document(&self) -> &Value {
+        &self.document
+    }
+
+    pub fn summary(&self) -> MockSpecSummary {
+        MockSpecSummary {
+            name: self.name.clone(),
+            title: self
+                .document
+                .pointer("/info/title")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            servers: self.servers.iter().map(Server::url).collect(),
+            operations: self.operations.len(),
+        }
+    }
+
+    fn serves(&self, host: &str, port: u16) -> bool {
+        self.servers
+            .iter()
+            .any(|server| server.port == port && server.host.eq_ignore_ascii_case(host))
+    }
+
+    /// The operation `method` and `url` match, preferring the most specific
+    /// path where several do
+    fn find(&self, method: &Method, url: &reqwest::Url) -> Option<&Operation> {
+        let host = url.host_str()?;
+        let port = url.port_or_known_default()?;
+        let path = url.path();
+        self.servers
+            .iter()
+            .filter(|server| {
+                server.scheme == url.scheme()
+                    && server.port == port
+                    && server.host.eq_ignore_ascii_case(host)
+            })
+            .filter_map(|server| {
+                let rest = path.strip_prefix(server.base_path.as_str())?;
+                (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+            })
+            .flat_map(|rest| {
+                let segments: Vec<_> = rest.split('/').filter(|s| !s.is_empty()).collect();
+                self.operations
+                    .iter()
+                    .filter(move |operation| operation.matches(method, &segments))
+            })
+            .max_by_key(|operation| operation.literal_segments())
+    }
+
+    /// Follow `value`'s `$ref`s within the document
+    fn resolve<'a>(&'a self, mut value: &'a Value) -> &'a Value {
+        for _ in 0..MAX_DEPTH {
+            let Some(pointer) = value
+                .get("$ref")
+                .and_then(Value::as_str)
+                .and_then(|reference| reference.strip_prefix('#'))
+            else {
+                break;
+            };
+            match self.document.pointer(pointer) {
+                Some(target) => value = target,
+                None => break,
+            }
+        }
+        value
+    }
+
+    fn respond(
+        &self,
+        operation: &Operation,
+        headers: &HeaderMap,
+    ) -> Response<UnsyncBoxBody<Bytes, ErrorCode>> {
+        let prefer = Prefer::from_headers(headers);
+        let responses = operation.responses.as_object();
+        let (status, response) = select_response(responses, prefer.code);
+        let response = response.map(|response| self.resolve(response));
+
+        let content = response
+            .and_then(|response| response.get("content"))
+            .and_then(Value::as_object)
+            .and_then(|content| {
+                content
+                    .get_key_value("application/json")
+                    .or_else(|| content.iter().find(|(media, _)| media.contains("json")))
+                    .or_else(|| content.iter().next())
+            });
+        let mut builder = Response::builder().status(status).header(
+            MOCK_HEADER,
+            HeaderValue::from_str(&self.name).unwrap_or(HeaderValue::from_static("mock")),
+        );
+        let body = match content {
+            Some((media_type, media)) => {
+                if !media_type.contains('*') {
+                    builder = builder.header(CONTENT_TYPE, media_type.as_str());
+                }
+                match self.media_example(media, prefer.example.as_deref()) {
+                    Value::String(text) if !media_type.contains("json") => Bytes::from(text),
+                    example => Bytes::from(serde_json::to_vec_pretty(&example).unwrap_or_default()),
+                }
+            }
+            None => Bytes::new(),
+        };
+        builder
+            .body(
+                Full::new(body)
+                    .map_err(|_| ErrorCode::InternalError(Some("conversion error".to_string())))
+                    .boxed_unsync(),
+            )
+            .unwrap_or_else(|_| {
+                let mut response = Response::new(
+                    Full::new(Bytes::new())
+                        .map_err(|_| ErrorCode::InternalError(Some("conversion error".to_string())))
+                        .boxed_unsync(),
+                );
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                response
+            })
+    }
+
+    /// The example for a media type object: its own, the named or first of
+    /// its examples, or one generated from its schema
+    fn media_example(&self, media: &Value, name: Option<&str>) -> Value {
+        let media = self.resolve(media);
+        if let Some(example) = media.get("example") {
+            return example.clone();
+        }
+        if let Some(examples) = media.get("examples").and_then(Value::as_object) {
+            let example = name
+                .and_then(|name| examples.get(name))
+                .or_else(|| examples.values().next())
+                .map(|example| self.resolve(example));
+            if let Some(value) = example.and_then(|example| example.get("value")) {
+                return value.clone();
+            }
+        }
+        media
+            .get("schema")
+            .map(|schema| self.schema_example(schema, 0))
+            .unwrap_or_default()
+    }
+
+    /// Generate an example matching `schema`
+    fn schema_example(&self, schema: &Value, depth: usize) -> Value {
+        if depth > MAX_DEPTH {
+            return Value::Null;
+        }
+        let schema = self.resolve(schema);
+        for key in ["example", "default", "const"] {
+            if let Some(value) = schema.get(key) {
+                return value.clone();
+            }
+        }
+        for key in ["examples", "enum"] {
+            if let Some(value) = schema
+                .get(key)
+                .and_then(Value::as_array)
+                .and_then(|v| v.first())
+            {
+                return value.clone();
+            }
+        }
+        if let Some(parts) = schema.get("allOf").and_then(Value::as_array) {
+            let mut merged = Map::new();
+            for part in parts {
+                match self.schema_example(part, depth + 1) {
+                    Value::Object(object) => merged.extend(object),
+                    other if merged.is_empty() => return other,
+                    _ => {}
+                }
+            }
+            return Value::Object(merged);
+        }
+        for key in ["oneOf", "anyOf"] {
+            if let Some(first) = schema
+                .get(key)
+                .and_then(Value::as_array)
+                .and_then(|v| v.first())
+            {
+                return self.schema_example(first, depth + 1);
+            }
+        }
+
+        let schema_type = match schema.get("type") {
+            Some(Value::String(schema_type)) => schema_type.as_str(),
+            // OpenAPI 3.1 allows a list, such as ["string", "null"]
+            Some(Value::Array(types)) => types
+                .iter()
+                .filter_map(Value::as_str)
+                .find(|t| *t != "null")
+                .unwrap_or("null"),
+            _ if schema.get("properties").is_some() => "object",
+            _ if schema.get("items").is_some() => "array",
+            _ => "null",
+        };
+        match schema_type {
+            "object" => Value::Object(
+                schema
+                    .get("properties")
+                    .and_then(Value::as_object)
+                    .into_iter()
+                    .flatten()
+                    .map(|(name, property)| {
+                        (name.clone(), self.schema_example(property, depth + 1))
+                    })
+                    .collect(),
+            ),
+            "array" => Value::Array(
+                schema
+                    .get("items")
+                    .map(|items| vec![self.schema_example(items, depth + 1)])
+                    .unwrap_or_default(),
+            ),
+            "string" => Value::String(
+                match schema.get("format").and_then(Value::as_str) {
+                    Some("date-time") => "2026-01-01T00:00:00Z",
+                    Some("date") => "2026-01-01",
+                    Some("time") => "00:00:00Z",
+                    Some("email") => "user@example.com",
+                    Some("uuid") => "00000000-0000-4000-8000-000000000000",
+                    Some("uri" | "url") => "https://example.com/",
+                    Some("hostname") => "example.com",
+                    Some("ipv4") => "192.0.2.1",
+                    Some("ipv6") => "2001:db8::1",
+                    _ => "string",
+                }
+                .to_string(),
+            ),
+            "integer" => schema
+                .get("minimum")
+                .and_then(Value::as_i64)
+                .unwrap_or(0)
+                .into(),
+            "number" => schema
+                .get("minimum")
+                .and_then(Value::as_f64)
+                .unwrap_or(0.0)
+                .into(),
+            "boolean" => Value::Bool(true),
+            _ => Value::Null,
+        }
+    }
+}
+
+/// The response a client asked for with a `Prefer` header
+#[derive(Debug, Default)]
+struct Prefer {
+    code: Option<u16>,
+    example: Option<String>,
+}
+
+impl Prefer {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let mut prefer = Self::default();
+        let preferences = headers
+            .get_all("prefer")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split([',', ';']))
+            .filter_map(|preference| preference.split_once('='));
+        for (key, value) in preferences {
+            let value = value.trim().trim_matches('"');
+            match key.trim() {
+                "code" => prefer.code = value.parse().ok(),
+                "example" => prefer.example = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        prefer
+    }
+}
+
+/// The status and response object to answer with: the preferred code if the
+/// operation has it, else its first success response, else `default`
+fn select_response(
+    responses: Option<&Map<String, Value>>,
+    preferred: Option<u16>,
+) -> (StatusCode, Option<&Value>) {
+    let Some(responses) = responses else {
+        return (StatusCode::OK, None);
+    };
+    let by_code = |code: u16| {
+        responses
+            .get(&code.to_string())
+            .or_else(|| responses.get(&format!("{}XX", code / 100)))
+    };
+    let preferred_response = preferred.and_then(|code| {
+        let status = StatusCode::from_u16(code).ok()?;
+        Some((status, by_code(code)?))
+    });
+    if let Some((status, response)) = preferred_response {
+        return (status, Some(response));
+    }
+    let first_success = responses
+        .iter()
+        .filter_map(|(code, response)| {
+            let code = code.replace(['X', 'x'], "0").parse::<u16>().ok()?;
+            (200..300).contains(&code).then_some((code, response))
+        })
+        .min_by_key(|(code, _)| *code);
+    match first_success {
+        Some((code, response)) => (
+            StatusCode::from_u16(code).unwrap_or(StatusCode::OK),
+            Some(response),
+        ),
+        None => (
+            preferred
+                .and_then(|code| StatusCode::from_u16(code).ok())
+                .unwrap_or(StatusCode::OK),
+            responses.get("default"),
+        ),
+    }
+}
+
+/// The live set of mocked documents. Cheap to clone; all clones share the
+/// same documents.
+#[derive(Clone, Default)]
+pub struct MockApis {
+    specs: Arc<RwLock<Arc<Vec<MockSpec>>>>,
+}
+
+impl std::fmt::Debug for MockApis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockApis")
+            .field(
+                "specs",
+                &self.specs().iter().map(MockSpec::name).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl MockApis {
+    pub fn new(specs: Vec<MockSpec>) -> Self {
+        Self {
+            specs: Arc::new(RwLock::new(Arc::new(specs))),
+        }
+    }
+
+    pub fn specs(&self) -> Arc<Vec<MockSpec>> {
+        self.specs.read().unwrap().clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.specs.read().unwrap().is_empty()
+    }
+
+    /// Add `spec`, replacing any document with the same name
+    pub fn insert(&self, spec: MockSpec) {
+        let mut specs = self.specs.write().unwrap();
+        let mut updated: Vec<_> = specs
+            .iter()
+            .filter(|existing| existing.name != spec.name)
+            .cloned()
+            .collect();
+        updated.push(spec);
+        *specs = Arc::new(updated);
+    }
+
+    /// Remove the document called `name`, returning whether there was one
+    pub fn remove(&self, name: &str) -> bool {
+        let mut specs = self.specs.write().unwrap();
+        let updated: Vec<_> = specs
+            .iter()
+            .filter(|spec| spec.name != name)
+            .cloned()
+            .collect();
+        let removed = updated.len() != specs.len();
+        *specs = Arc::new(updated);
+        removed
+    }
+
+    /// Whether requests through a tunnel to `authority` may be mocked, so
+    /// it has to be intercepted
+    pub fn intercepts(&self, authority: &str) -> bool {
+        let specs = self.specs();
+        if specs.is_empty() {
+            return false;
+        }
+        let Ok((host, port)) = parse_authority_host_port(authority, 443) else {
+            return false;
+        };
+        specs.iter().any(|spec| spec.serves(&host, port))
+    }
+
+    /// A mocked response to the request, if a document describes it
+    pub fn respond(
+        &self,
+        method: &Method,
+        url: &reqwest::Url,
+        headers: &HeaderMap,
+    ) -> Option<Response<UnsyncBoxBody<Bytes, ErrorCode>>> {
+        let specs = self.specs();
+        specs.iter().find_map(|spec| {
+            let operation = spec.find(method, url)?;
+            debug!("Mocking {} {} from {}", method, url, spec.name);
+            Some(spec.respond(operation, headers))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PETSTORE: &str = r##"
+openapi: 3.0.3
+info:
+  title: Petstore
+  version: 1.0.0
+servers:
+  - url: "{scheme}://api.example.com/v1"
+    variables:
+      scheme:
+        default: https
+paths:
+  /pets:
+    get:
+      responses:
+        200:
+          description: All pets
+          content:
+            application/json:
+              schema:
+                type: array
+                items:
+                  $ref: "#/components/schemas/Pet"
+  /pets/{id}:
+    get:
+      responses:
+        "200":
+          description: A pet
+          content:
+            application/json:
+              examples:
+                rex:
+                  value: { id: 1, name: Rex }
+                empty:
+                  value: {}
+        "404":
+          description: No such pet
+          content:
+            application/json:
+              example: { error: not found }
+  /pets/mine:
+    get:
+      responses:
+        "204":
+          description: No pets
+components:
+  schemas:
+    Pet:
+      type: object
+      properties:
+        id: { type: integer, minimum: 1 }
+        name: { type: string }
+        born: { type: string, format: date }
+        owner: { $ref: "#/components/schemas/Pet" }
+"##;
+
+    fn mocks() -> MockApis {
+        let document = parse_document(PETSTORE).unwrap();
+        MockApis::new(vec![MockSpec::new("petstore", document).unwrap()])
+    }
+
+    async fn respond(mocks: &MockApis, url: &str, prefer: Option<&str>) -> Option<(u16, Value)> {
+        let mut headers = HeaderMap::new();
+        if let Some(prefer) = prefer {
+            headers.insert("prefer", HeaderValue::from_str(prefer).unwrap());
+        }
+        let response = mocks.respond(&Method::GET, &reqwest::Url::parse(url).unwrap(), &headers)?;
+        assert_eq!(response.headers()[MOCK_HEADER], "petstore");
+        let status = response.status().as_u16();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        Some((status, serde_json::from_slice(&body).unwrap_or_default()))
+    }
+
+    #[tokio::test]
+    async fn operations_are_answered_with_examples() {
+        let mocks = mocks();
+        assert!(mocks.intercepts("api.example.com:443"));
+        assert!(!mocks.intercepts("api.example.com:8443"));
+        assert_eq!(
+            mocks.specs()[0].summary().servers,
+            vec!["https://api.example.com:443/v1"]
+        );
+
+        let (status, body) = respond(&mocks, "https://api.example.com/v1/pets/7", None)
+            .await
+            .unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(body, serde_json::json!({ "id": 1, "name": "Rex" }));
+
+        let (status, body) = respond(
+            &mocks,
+            "https://api.example.com/v1/pets/7",
+            Some("code=404"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, 404);
+        assert_eq!(body["error"], "not found");
+        let (_, body) = respond(
+            &mocks,
+            "https://api.example.com/v1/pets/7",
+            Some("example=empty"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(body, serde_json::json!({}));
+
+        // The literal path wins over the templated one
+        let (status, _) = respond(&mocks, "https://api.example.com/v1/pets/mine", None)
+            .await
+            .unwrap();
+        assert_eq!(status, 204);
+
+        assert!(
+            respond(&mocks, "https://api.example.com/v2/pets", None)
+                .await
+                .is_none()
+        );
+        assert!(
+            respond(&mocks, "http://api.example.com/v1/pets", None)
+                .await
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn examples_are_generated_from_schemas() {
+        let (_, body) = respond(&mocks(), "https://api.example.com/v1/pets", None)
+            .await
+            .unwrap();
+        let pet = &body[0];
+        assert_eq!(pet["id"], 1);
+        assert_eq!(pet["name"], "string");
+        assert_eq!(pet["born"], "2026-01-01");
+        // Recursive schemas end in null
+        let mut owner = &pet["owner"];
+        let mut depth = 0;
+        while owner.is_object() {
+            owner = &owner["owner"];
+            depth += 1;
+        }
+        assert!(owner.is_null());
+        assert!(depth < MAX_DEPTH);
+    }
+
+    #[test]
+    fn documents_must_be_openapi_3_with_servers() {
+        let swagger = serde_json::json!({ "swagger": "2.0", "paths": {} });
+        assert!(MockSpec::new("old", swagger).is_err());
+        let relative = serde_json::json!({
+            "openapi": "3.1.0",
+            "servers": [{ "url": "/v1" }],
+            "paths": {}
+        });
+        assert!(MockSpec::new("relative", relative).is_err());
+    }
+
+    #[test]
+    fn documents_are_replaced_by_name() {
+        let mocks = mocks();
+        let document = parse_document(PETSTORE).unwrap();
+        mocks.insert(MockSpec::new("petstore", document).unwrap());
+        assert_eq!(mocks.specs().len(), 1);
+        assert!(mocks.remove("petstore"));
+        assert!(!mocks.remove("petstore"));
+        assert!(!mocks.intercepts("api.example.com"));
+    }
+}
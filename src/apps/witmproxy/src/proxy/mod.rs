@@ -1,14 +1,50 @@
 use crate::cert::CertificateAuthority;
 use crate::config::AppConfig;
+use crate::db::api_tokens::{API_TOKEN_PREFIX, ApiToken};
+use crate::error::WitmError;
 use crate::events::Event;
 use crate::events::connect::Connect;
 use crate::events::content::InboundContent;
+use crate::events::request::InterceptedRequest;
 use crate::events::response::ContextualResponse;
+use crate::http::graphql;
 use crate::http::utils::ContentTyped;
 use crate::plugins::cel::CelRequest;
-use crate::plugins::registry::PluginRegistry;
+use crate::plugins::registry::{PluginBlocked, PluginRegistry};
+use crate::plugins::storage_snapshots;
+use crate::proxy::api_schemas::ApiSchemas;
+use crate::proxy::block_rules::{BLOCKED_BY_RULE, BlockBehavior, BlockRules};
+use crate::proxy::compression::Compression;
+use crate::proxy::egress::EgressRoutes;
+use crate::proxy::findings::SensitiveData;
+use crate::proxy::flow_tags::FlowTags;
+use crate::proxy::flow_trace::{DEBUG_HEADER, FlowTraces, TRACE_HEADER};
+use crate::proxy::flows::{BLOCKED_BY, HOST_MISMATCH, PROTOBUF};
+use crate::proxy::header_overrides::{HeaderOverrides, HeaderOverridesConfig};
+use crate::proxy::hooks::ProxyHooks;
+use crate::proxy::host_limits::HostLimiter;
+use crate::proxy::https_upgrade::{HttpsUpgrades, Upgraded};
+use crate::proxy::images::ImageOptimizer;
+use crate::proxy::limits::FlowLimits;
+use crate::proxy::listener::{BoundListener, ListenerConfig, MitmPolicy};
+use crate::proxy::mocks::MockApis;
+use crate::proxy::network_profiles::{NetworkConditions, NetworkConditionsConfig};
+use crate::proxy::pages::{ErrorPages, ErrorResponse, FlowInfo};
+use crate::proxy::privacy::{Privacy, Stripped};
+use crate::proxy::protobuf::ProtobufDescriptors;
+use crate::proxy::security_headers::SecurityHeaders;
+use crate::proxy::sessions::CaptureSessions;
+use crate::proxy::stream::{PrefixedIo, StreamProtocol};
+use crate::proxy::timeline::Handshake;
+use crate::proxy::tls_policy::{TlsPolicies, TlsPolicy};
+use crate::proxy::traffic::{QuotaCheck, Traffic, is_page_load};
+use crate::proxy::translation::Translation;
+use crate::proxy::upstream_cert::UpstreamCert;
+use crate::proxy::user_scripts::UserScripts;
 use crate::proxy::utils::convert_hyper_boxed_body_to_reqwest_request;
-use crate::tenant::TenantContext;
+use crate::proxy::vhost::{ConnectionInfo, HostMismatchPolicy};
+use crate::tenant::{self, TenantContext};
+use crate::wasm::Host;
 use crate::wasm::bindgen::Event as WasmEvent;
 use crate::wasm::bindgen::witmproxy::plugin::capabilities::ContextualResponse as WasiContextualResponse;
 
@@ -17,38 +53,135 @@ use http_body_util::BodyExt;
 use http_body_util::Full;
 use http_body_util::combinators::UnsyncBoxBody;
 use hyper::body::Incoming;
+use hyper::header::HeaderValue;
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
 use hyper::{Method, Request, StatusCode};
 use hyper::{Response, upgrade};
+use sqlx::SqlitePool;
 use tokio::sync::{Notify, RwLock};
+use wasmtime::Store;
 use wasmtime_wasi_http::p3::WasiHttpView;
 use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
 use wasmtime_wasi_http::p3::{Request as WasiRequest, Response as WasiResponse};
 
 use std::sync::OnceLock;
-use std::{net::SocketAddr, sync::Arc};
-use tokio::net::{TcpListener, TcpStream};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+use tokio::net::TcpListener;
 use tokio_rustls::TlsAcceptor;
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info, warn};
 
 use hyper_util::server::conn::auto::Builder as AutoServer;
 use hyper_util::{rt::TokioExecutor, rt::TokioIo};
 
+pub mod api_schemas;
+pub mod block_rules;
+pub mod client_hello;
+pub mod compression;
+pub mod dial;
+pub mod egress;
+pub mod findings;
+pub mod flow_tags;
+pub mod flow_trace;
+pub mod flows;
+pub mod header_overrides;
+pub mod hooks;
+pub mod host_limits;
+pub mod https_upgrade;
+pub mod images;
+pub mod journal;
+pub mod limits;
+pub mod listener;
+pub mod mocks;
+pub mod mqtt;
 pub mod netfilter;
+pub mod network_profiles;
+pub mod normalize;
+pub mod pages;
+pub mod privacy;
+pub mod protobuf;
+pub mod reverse;
+pub mod security_headers;
+pub mod sessions;
+pub mod stream;
 pub mod tenant_resolver;
+pub mod timeline;
+pub mod tls_policy;
+pub mod traffic;
+pub mod translation;
 pub mod transparent;
+pub mod upstream_cert;
+pub mod upstream_tls;
+pub mod user_scripts;
+pub mod vhost;
 
+mod stats;
 mod utils;
+pub use stats::ProxyStats;
 pub use utils::{
-    ProxyError, ProxyResult, UpstreamClient, build_server_tls_for_host, client,
-    convert_boxbody_to_full_response, convert_hyper_incoming_to_reqwest_request,
-    convert_reqwest_to_hyper_response, is_closed, parse_authority_host_port, strip_proxy_headers,
+    ProxyError, ProxyResult, UpstreamAddr, UpstreamClient, build_server_tls_for_host, client,
+    client_builder, convert_hyper_incoming_to_reqwest_request, convert_reqwest_to_hyper_response,
+    is_closed, parse_authority_host_port, strip_proxy_headers,
 };
 
 #[cfg(test)]
 mod tests;
 
+/// Settings applied to each flow of an intercepted connection
+#[derive(Debug, Clone, Default)]
+pub struct FlowSettings {
+    pub limits: FlowLimits,
+    pub pages: ErrorPages,
+    pub host_mismatch: HostMismatchPolicy,
+    pub security_headers: SecurityHeaders,
+    /// Tags added to flows matching CEL rules as they complete
+    pub flow_tags: FlowTags,
+    /// Capture sessions flows are offered to as they complete
+    pub sessions: CaptureSessions,
+    pub host_limiter: HostLimiter,
+    pub network_conditions: NetworkConditions,
+    /// Headers forced on the requests sent to matching hosts
+    pub header_overrides: HeaderOverrides,
+    /// Hosts refused, and how
+    pub block_rules: BlockRules,
+    /// Clients the flows of matching hosts are sent with instead
+    pub egress: EgressRoutes,
+    /// TLS versions, cipher suites and ALPN protocols negotiated, by host
+    pub tls_policies: TlsPolicies,
+    pub mocks: MockApis,
+    /// Scripts injected into the HTML pages they match
+    pub user_scripts: UserScripts,
+    /// How images in responses are transcoded, if they are
+    pub images: ImageOptimizer,
+    /// How responses are compressed for the client, if they are
+    pub compression: Compression,
+    /// Pages translated before they're delivered
+    pub translation: Translation,
+    /// Where inferred API schemas are recorded, if inference is enabled
+    pub schemas: Option<ApiSchemas>,
+    /// Where HSTS policies are noted, if HTTPS upgrades are enabled
+    pub https_upgrades: Option<HttpsUpgrades>,
+    /// Where traffic is counted by client profile, if it's accounted
+    pub traffic: Option<Traffic>,
+    pub sensitive_data: SensitiveData,
+    /// Tracking parameters and third-party cookies stripped from requests
+    pub privacy: Privacy,
+    /// Descriptors protobuf bodies are decoded to JSON with for plugins
+    pub protobuf: ProtobufDescriptors,
+    /// Origin requests are sent to in place of the host they name, as for
+    /// reverse proxy routes
+    pub origin: Option<reqwest::Url>,
+    /// Listener the flows came in on, whose plugin filter applies
+    pub listener: Option<Arc<ListenerConfig>>,
+    /// Tenant the client was identified as, none for anonymous clients
+    pub tenant: Option<String>,
+    pub hooks: ProxyHooks,
+    pub traces: FlowTraces,
+}
+
 #[derive(Clone)]
 pub struct ProxyServer {
     listen_addr: Option<SocketAddr>,
@@ -62,6 +195,32 @@ pub struct ProxyServer {
     /// loopback connection so the management UI keeps working when the
     /// system proxy is enabled and the user opens it via a public hostname.
     management_addr: Arc<OnceLock<SocketAddr>>,
+    stats: ProxyStats,
+    /// Used to check API tokens on listeners that require authentication
+    db_pool: Option<SqlitePool>,
+    limits: FlowLimits,
+    pages: ErrorPages,
+    security_headers: SecurityHeaders,
+    flow_tags: FlowTags,
+    sessions: CaptureSessions,
+    host_limiter: HostLimiter,
+    network_conditions: NetworkConditions,
+    header_overrides: HeaderOverrides,
+    block_rules: BlockRules,
+    egress: EgressRoutes,
+    tls_policies: TlsPolicies,
+    mocks: MockApis,
+    user_scripts: UserScripts,
+    images: ImageOptimizer,
+    compression: Compression,
+    translation: Translation,
+    schemas: ApiSchemas,
+    https_upgrades: HttpsUpgrades,
+    traffic: Traffic,
+    privacy: Privacy,
+    protobuf: ProtobufDescriptors,
+    hooks: ProxyHooks,
+    traces: FlowTraces,
 }
 
 impl ProxyServer {
@@ -70,7 +229,54 @@ impl ProxyServer {
         plugin_registry: Option<Arc<RwLock<PluginRegistry>>>,
         config: AppConfig,
     ) -> ProxyResult<Self> {
-        let upstream = client(ca.clone())?;
+        let ca = ca.with_minting(&config.tls);
+        let limits = FlowLimits::from(&config.proxy);
+        let upstream = client(ca.clone(), &limits, &config.tls)?;
+        let upstream_policy = TlsPolicy::upstream(&config.tls);
+        let egress = EgressRoutes::new(&config.proxy.egress, |client_hello| {
+            client_builder(
+                ca.clone(),
+                &limits,
+                &config.tls,
+                client_hello,
+                &upstream_policy,
+            )
+        })?;
+        let tls_policies = TlsPolicies::new(&config.tls, |policy| {
+            client_builder(ca.clone(), &limits, &config.tls, None, policy)
+        })?;
+        let pages = ErrorPages::from_config(&config.proxy)
+            .map_err(|e| ProxyError::Generic(e.to_string()))?;
+        let security_headers = SecurityHeaders::new(config.proxy.security_headers.clone())
+            .map_err(|e| ProxyError::Generic(e.to_string()))?;
+        let flow_tags = FlowTags::new(config.proxy.flow_tags.clone())
+            .map_err(|e| ProxyError::Generic(e.to_string()))?;
+        let block_rules = BlockRules::new(config.proxy.block_rules.clone())
+            .map_err(|e| ProxyError::Generic(e.to_string()))?;
+        let host_limiter = HostLimiter::new(
+            config.proxy.max_requests_per_host,
+            config.proxy.host_limits.clone(),
+        );
+        let network_conditions = NetworkConditions::new(NetworkConditionsConfig {
+            profiles: config.proxy.network_profiles.clone(),
+            rules: config.proxy.network_conditions.clone(),
+        })
+        .map_err(|e| ProxyError::Generic(e.to_string()))?;
+        let header_overrides = HeaderOverrides::new(HeaderOverridesConfig {
+            profiles: config.proxy.header_profiles.clone(),
+            rules: config.proxy.header_overrides.clone(),
+        })
+        .map_err(|e| ProxyError::Generic(e.to_string()))?;
+        let protobuf = ProtobufDescriptors::default();
+        protobuf
+            .set_mappings(config.proxy.protobuf.clone())
+            .map_err(|e| ProxyError::Generic(e.to_string()))?;
+        let traces = FlowTraces::new(config.proxy.debug_clients.clone());
+        let traffic = Traffic::new(config.proxy.client_profiles.clone())
+            .map_err(|e| ProxyError::Generic(e.to_string()))?;
+        let privacy = Privacy::from(&config.proxy);
+        let stats = ProxyStats::new();
+        stats.set_host_limiter(host_limiter.clone());
         Ok(Self {
             listen_addr: None,
             ca: Arc::new(ca),
@@ -79,14 +285,204 @@ impl ProxyServer {
             upstream,
             shutdown_notify: Arc::new(Notify::new()),
             management_addr: Arc::new(OnceLock::new()),
+            stats,
+            db_pool: None,
+            limits,
+            pages,
+            security_headers,
+            flow_tags,
+            sessions: CaptureSessions::default(),
+            host_limiter,
+            network_conditions,
+            header_overrides,
+            block_rules,
+            egress,
+            tls_policies,
+            mocks: MockApis::default(),
+            user_scripts: UserScripts::default(),
+            images: ImageOptimizer::from(&config.proxy),
+            compression: Compression::from(&config.proxy),
+            translation: Translation::from(&config.proxy),
+            schemas: ApiSchemas::default(),
+            https_upgrades: HttpsUpgrades::new(),
+            traffic,
+            privacy,
+            protobuf,
+            hooks: ProxyHooks::default(),
+            traces,
         })
     }
 
-    /// Returns the actual bound listen address, if the server has been started
+    /// Set the database pool used to check API tokens on listeners with
+    /// `require_auth`. Without it such listeners reject every client.
+    pub fn with_db_pool(mut self, pool: SqlitePool) -> Self {
+        self.db_pool = Some(pool);
+        self
+    }
+
+    /// Journal capture sessions in `dir` while they run, so they can be
+    /// recovered after a crash
+    pub fn with_session_journal(mut self, dir: std::path::PathBuf) -> Self {
+        self.sessions = CaptureSessions::journaled(dir);
+        self
+    }
+
+    /// Set the callbacks run as flows complete and plugins fail
+    pub fn with_hooks(mut self, hooks: ProxyHooks) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Returns the actual bound address of the primary listener, if the server has been started
     pub fn listen_addr(&self) -> Option<SocketAddr> {
         self.listen_addr
     }
 
+    /// Live listener and connection state, shared with the web server
+    pub fn stats(&self) -> ProxyStats {
+        self.stats.clone()
+    }
+
+    /// Live security header rules, shared with the web server so they can be
+    /// replaced without a restart
+    pub fn security_headers(&self) -> SecurityHeaders {
+        self.security_headers.clone()
+    }
+
+    /// Live flow tag rules, shared with the web server so they can be
+    /// replaced without a restart
+    pub fn flow_tags(&self) -> FlowTags {
+        self.flow_tags.clone()
+    }
+
+    /// Capture sessions, shared with the web server so they can be started
+    /// and stopped
+    pub fn sessions(&self) -> CaptureSessions {
+        self.sessions.clone()
+    }
+
+    /// Mocked APIs, shared with the web server so documents can be uploaded
+    /// without a restart
+    pub fn mocks(&self) -> MockApis {
+        self.mocks.clone()
+    }
+
+    /// Injected scripts, shared with the web server so scripts can be
+    /// registered without a restart
+    pub fn user_scripts(&self) -> UserScripts {
+        self.user_scripts.clone()
+    }
+
+    /// Protobuf descriptors and mappings, shared with the web server so
+    /// they can be uploaded and replaced without a restart
+    pub fn protobuf(&self) -> ProtobufDescriptors {
+        self.protobuf.clone()
+    }
+
+    /// Inferred API schemas, shared with the web server for export
+    pub fn api_schemas(&self) -> ApiSchemas {
+        self.schemas.clone()
+    }
+
+    /// Traces of flows debugged with `X-Witmproxy-Debug`, shared with the web
+    /// server so they can be fetched by flow id
+    pub fn flow_traces(&self) -> FlowTraces {
+        self.traces.clone()
+    }
+
+    /// The schemas flows are recorded to, if inference is enabled
+    fn recorded_schemas(&self) -> Option<ApiSchemas> {
+        self.config
+            .proxy
+            .infer_schemas
+            .then(|| self.schemas.clone())
+    }
+
+    /// How images in responses are transcoded, if they are
+    pub fn images(&self) -> ImageOptimizer {
+        self.images.clone()
+    }
+
+    /// How responses are compressed for the client, if they are
+    pub fn compression(&self) -> Compression {
+        self.compression.clone()
+    }
+
+    /// Page translation, shared so translated fragments are cached across
+    /// listeners
+    pub fn translation(&self) -> Translation {
+        self.translation.clone()
+    }
+
+    /// Tracking parameter and third-party cookie stripping, shared so the
+    /// parameter list can be refreshed
+    pub fn privacy(&self) -> Privacy {
+        self.privacy.clone()
+    }
+
+    /// Hosts plain HTTP requests are upgraded to HTTPS for, shared so the
+    /// policies they send can be saved and the preload list refreshed
+    pub fn https_upgrades(&self) -> HttpsUpgrades {
+        self.https_upgrades.clone()
+    }
+
+    /// The upgrades applied to flows, if they're enabled
+    fn enabled_upgrades(&self) -> Option<HttpsUpgrades> {
+        self.config
+            .proxy
+            .https_upgrade
+            .then(|| self.https_upgrades.clone())
+    }
+
+    /// Traffic counted by client profile and host, shared with the web server
+    /// so it can be reported and saved
+    pub fn traffic(&self) -> Traffic {
+        self.traffic.clone()
+    }
+
+    /// The traffic flows are counted in, if it's accounted
+    fn accounted_traffic(&self) -> Option<Traffic> {
+        self.config
+            .proxy
+            .account_traffic
+            .then(|| self.traffic.clone())
+    }
+
+    /// Per-host upstream request limits, shared with the transparent proxy
+    /// so both draw from the same queues
+    pub fn host_limiter(&self) -> HostLimiter {
+        self.host_limiter.clone()
+    }
+
+    /// Live network condition rules, shared with the web server so devices
+    /// can be switched between profiles without a restart
+    pub fn network_conditions(&self) -> NetworkConditions {
+        self.network_conditions.clone()
+    }
+
+    /// Live header override rules, shared with the web server so profiles
+    /// can be switched without a restart
+    pub fn header_overrides(&self) -> HeaderOverrides {
+        self.header_overrides.clone()
+    }
+
+    /// Clients for the egress routes, shared with the reverse and transparent
+    /// proxies so their flows are routed alike
+    pub fn egress(&self) -> EgressRoutes {
+        self.egress.clone()
+    }
+
+    /// Block rules, shared with the reverse and transparent proxies
+    pub fn block_rules(&self) -> BlockRules {
+        self.block_rules.clone()
+    }
+
+    /// TLS policies and their clients, shared with the reverse and
+    /// transparent proxies so their handshakes are negotiated alike
+    pub fn tls_policies(&self) -> TlsPolicies {
+        self.tls_policies.clone()
+    }
+
     /// Tell the proxy where its own management web server is listening.
     /// Connections to that port are then routed directly to loopback
     /// instead of being treated as ordinary upstream traffic — without
@@ -108,24 +504,69 @@ impl ProxyServer {
         }
     }
 
-    /// Starts the server: binds the listener and spawns the accept loop.
-    /// Returns immediately once the listener is bound.
+    /// Starts the server: binds the primary listener (`proxy_bind_addr`) and
+    /// any additional `listeners`, then spawns an accept loop for each.
+    /// Returns immediately once every listener is bound.
     pub async fn start(&mut self) -> ProxyResult<()> {
         // Determine the bind address: use configured address or default to OS-assigned port
-        let bind_addr: SocketAddr = if let Some(ref addr_str) = self.config.proxy.proxy_bind_addr {
-            addr_str.parse().map_err(|e| {
-                ProxyError::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
-            })?
-        } else {
-            "127.0.0.1:0".parse().unwrap()
+        let primary = ListenerConfig {
+            tls_hostname: self.config.proxy.proxy_tls_hostname.clone(),
+            ..ListenerConfig::primary(
+                self.config
+                    .proxy
+                    .proxy_bind_addr
+                    .clone()
+                    .unwrap_or_else(|| "127.0.0.1:0".to_string()),
+                self.config.proxy.mitm,
+            )
         };
-
-        let listener = TcpListener::bind(bind_addr).await?;
+        let configs = std::iter::once(primary).chain(self.config.proxy.listeners.iter().cloned());
+
+        // Bind everything before serving anything, so a bad listener fails startup
+        let mut listeners = Vec::new();
+        let mut bound = Vec::new();
+        for config in configs {
+            let bind_addr: SocketAddr = config.bind_addr.parse().map_err(|e| {
+                ProxyError::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "Invalid bind address for listener {}: {}",
+                        config.display_name(),
+                        e
+                    ),
+                ))
+            })?;
+            let listener = dial::bind_listener(bind_addr)?;
+            let addr = listener.local_addr()?;
+            let acceptor = match &config.tls_hostname {
+                Some(hostname) => Some(self.proxy_acceptor(hostname).await?),
+                None => None,
+            };
+            if config.require_auth && self.db_pool.is_none() {
+                warn!(
+                    "Listener {} requires auth but no database is available; all clients will be rejected",
+                    config.display_name()
+                );
+            }
+            bound.push(BoundListener {
+                name: config.display_name().to_string(),
+                addr,
+                mitm: config.mitm,
+                require_auth: config.require_auth,
+            });
+            listeners.push((listener, Arc::new(config), acceptor));
+        }
 
         // Store the actual bound address
-        self.listen_addr = Some(listener.local_addr()?);
-        let shutdown = self.shutdown_notify.clone();
-        let server = self.clone();
+        self.listen_addr = Some(bound[0].addr);
+        self.stats.set_listen_addr(bound[0].addr);
+        for listener in &bound[1..] {
+            debug!(
+                "Proxy listener {} bound to {} (mitm: {})",
+                listener.name, listener.addr, listener.mitm
+            );
+        }
+        self.stats.set_listeners(bound);
 
         // Spawn the timer scheduler (checks every 30 seconds for timer-capable plugins)
         if let Some(ref registry) = self.plugin_registry {
@@ -150,9 +591,87 @@ impl ProxyServer {
                     }
                 }
             });
+
+            // Deliver the messages plugins publish to those subscribed
+            let bus_registry = Arc::clone(registry);
+            let bus_shutdown = self.shutdown_notify.clone();
+            let bus = registry.read().await.bus().clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::select! {
+                        _ = bus_shutdown.notified() => break,
+                        message = bus.next() => {
+                            let Some(message) = message else { break };
+                            bus_registry.read().await.deliver(&message).await;
+                        }
+                    }
+                }
+            });
+        }
+
+        for (listener, config, acceptor) in listeners {
+            self.spawn_accept_loop(listener, config, acceptor);
         }
 
-        // Spawn the accept loop
+        Ok(())
+    }
+
+    /// The settings applied to each flow of a connection accepted on
+    /// `listener`
+    fn flow_settings(&self, listener: Option<Arc<ListenerConfig>>) -> FlowSettings {
+        FlowSettings {
+            limits: self.limits,
+            pages: self.pages.clone(),
+            host_mismatch: self.config.proxy.host_mismatch,
+            security_headers: self.security_headers.clone(),
+            flow_tags: self.flow_tags.clone(),
+            sessions: self.sessions.clone(),
+            host_limiter: self.host_limiter.clone(),
+            network_conditions: self.network_conditions.clone(),
+            header_overrides: self.header_overrides.clone(),
+            block_rules: self.block_rules.clone(),
+            egress: self.egress.clone(),
+            tls_policies: self.tls_policies.clone(),
+            mocks: self.mocks.clone(),
+            user_scripts: self.user_scripts.clone(),
+            images: self.images.clone(),
+            compression: self.compression.clone(),
+            translation: self.translation.clone(),
+            schemas: self.recorded_schemas(),
+            https_upgrades: self.enabled_upgrades(),
+            traffic: self.accounted_traffic(),
+            sensitive_data: SensitiveData::from(&self.config.proxy),
+            privacy: self.privacy.clone(),
+            protobuf: self.protobuf.clone(),
+            origin: None,
+            listener,
+            // Clients of the proxy listeners aren't identified yet
+            tenant: None,
+            hooks: self.hooks.clone(),
+            traces: self.traces.clone(),
+        }
+    }
+
+    /// Accepts TLS on a listener serving as an HTTPS proxy, with a
+    /// certificate minted for `hostname`. Only HTTP/1.1 is offered, as
+    /// CONNECT tunnels are upgraded from it.
+    async fn proxy_acceptor(&self, hostname: &str) -> ProxyResult<TlsAcceptor> {
+        let policy = self.tls_policies.client_policy(hostname);
+        let mut server_tls = build_server_tls_for_host(&self.ca, hostname, policy).await?;
+        server_tls.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(server_tls)))
+    }
+
+    /// Serve connections from `listener` under its listener policy until
+    /// shutdown, first terminating TLS with `acceptor` if it's an HTTPS proxy.
+    fn spawn_accept_loop(
+        &self,
+        listener: TcpListener,
+        policy: Arc<ListenerConfig>,
+        acceptor: Option<TlsAcceptor>,
+    ) {
+        let shutdown = self.shutdown_notify.clone();
+        let server = self.clone();
         tokio::spawn(async move {
             loop {
                 tokio::select! {
@@ -160,32 +679,20 @@ impl ProxyServer {
                     accept_result = listener.accept() => {
                         match accept_result {
                             Ok((io, peer)) => {
-                                debug!("Accepted connection from {}", peer);
+                                let peer = dial::canonical_addr(peer);
+                                debug!("Accepted connection from {} on {}", peer, policy.display_name());
                                 let shared = server.clone();
-                                // Resolve tenant from peer address (anonymous for now,
-                                // will be replaced by TenantResolver in Phase 4)
-                                let tenant_ctx = TenantContext::anonymous();
+                                let policy = policy.clone();
+                                let acceptor = acceptor.clone();
+                                let connection = shared.stats.connection_opened();
                                 tokio::spawn(async move {
-                                    let svc = service_fn(move |req: Request<Incoming>| {
-                                        let shared = shared.clone();
-                                        let tenant_ctx = tenant_ctx.clone();
-                                        async move {
-                                            shared.handle_plain_http(req, &tenant_ctx).await.map_err(|e| std::io::Error::other(e.to_string()))
-                                        }
-                                    });
-
-                                    if let Err(e) = http1::Builder::new()
-                                        .preserve_header_case(true)
-                                        .title_case_headers(true)
-                                        .serve_connection(TokioIo::new(io), svc)
-                                        .with_upgrades()
-                                        .await
-                                    {
-                                        if is_closed(&e) {
-                                            debug!("client closed: {}", e);
-                                        } else {
-                                            error!("conn error: {}", e);
-                                        }
+                                    let _connection = connection;
+                                    match acceptor {
+                                        Some(acceptor) => match acceptor.accept(io).await {
+                                            Ok(tls) => shared.serve_proxy_connection(tls, peer, policy).await,
+                                            Err(e) => debug!("TLS handshake with proxy client {} failed: {}", peer, e),
+                                        },
+                                        None => shared.serve_proxy_connection(io, peer, policy).await,
                                     }
                                 });
                             }
@@ -195,8 +702,42 @@ impl ProxyServer {
                 }
             }
         });
+    }
 
-        Ok(())
+    /// Serve the proxy requests a client sends on `io`, CONNECT or
+    /// absolute-form, under the listener's `policy`
+    async fn serve_proxy_connection<IO>(self, io: IO, peer: SocketAddr, policy: Arc<ListenerConfig>)
+    where
+        IO: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+    {
+        // Resolve tenant from peer address (anonymous for now,
+        // will be replaced by TenantResolver in Phase 4)
+        let tenant_ctx = TenantContext::anonymous();
+        let svc = service_fn(move |req: Request<Incoming>| {
+            let shared = self.clone();
+            let tenant_ctx = tenant_ctx.clone();
+            let policy = policy.clone();
+            async move {
+                shared
+                    .handle_plain_http(req, peer, &tenant_ctx, policy)
+                    .await
+                    .map_err(|e| std::io::Error::other(e.to_string()))
+            }
+        });
+
+        if let Err(e) = http1::Builder::new()
+            .preserve_header_case(true)
+            .title_case_headers(true)
+            .serve_connection(TokioIo::new(io), svc)
+            .with_upgrades()
+            .await
+        {
+            if is_closed(&e) {
+                debug!("client closed: {}", e);
+            } else {
+                error!("conn error: {}", e);
+            }
+        }
     }
 
     /// Returns a future that resolves when the server stops.
@@ -209,10 +750,33 @@ impl ProxyServer {
         self.shutdown_notify.notify_waiters();
     }
 
+    /// Check the request's `Proxy-Authorization` for a valid API token, given
+    /// either as a bearer token or as the password (or username) of basic auth.
+    async fn is_authorized(&self, req: &Request<Incoming>) -> bool {
+        let Some(pool) = &self.db_pool else {
+            return false;
+        };
+        let Some(token) = req
+            .headers()
+            .get(hyper::header::PROXY_AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(proxy_authorization_token)
+        else {
+            return false;
+        };
+        match ApiToken::authenticate(pool, &token).await {
+            Ok(token) => token.is_some(),
+            Err(e) => {
+                warn!("Failed to check proxy API token: {}", e);
+                false
+            }
+        }
+    }
+
     /// Determine whether any plugins want to handle this connection
     /// Returns true if MITM should be performed, false if connection should be forwarded transparently
-    #[tracing::instrument(skip(self), fields(authority = %authority))]
-    async fn handle_connect(&self, authority: &str) -> bool {
+    #[tracing::instrument(skip(self, policy), fields(authority = %authority))]
+    async fn handle_connect(&self, authority: &str, policy: &ListenerConfig) -> bool {
         let Some(plugin_registry) = &self.plugin_registry else {
             debug!("No plugin registry, skipping MITM for {}", authority);
             return false;
@@ -229,7 +793,12 @@ impl ProxyServer {
         let connect_event: Box<dyn Event> = Box::new(Connect::new(host, port));
         let has_matching_plugin = {
             let registry = plugin_registry.read().await;
-            registry.can_handle(&*connect_event)
+            if policy.filters_plugins() {
+                let allowed = registry.plugins_matching(|id| policy.allows_plugin(id));
+                registry.can_handle_in_set(&*connect_event, &allowed)
+            } else {
+                registry.can_handle(&*connect_event)
+            }
         };
 
         if has_matching_plugin {
@@ -251,6 +820,7 @@ impl ProxyServer {
     async fn forward_connection_transparently(
         &self,
         upgraded: upgrade::Upgraded,
+        client: IpAddr,
         authority: String,
     ) -> ProxyResult<()> {
         debug!("Forwarding connection transparently to {}", authority);
@@ -258,8 +828,8 @@ impl ProxyServer {
         // Parse host and port
         let (host, port) = parse_authority_host_port(&authority, 443)?;
 
-        // Connect to the upstream server
-        let upstream = TcpStream::connect(format!("{}:{}", host, port)).await?;
+        // Connect to the upstream server, racing IPv6 and IPv4 addresses
+        let upstream = dial::connect(&host, port).await?;
         debug!("Connected to upstream {}:{}", host, port);
 
         // Wrap the upgraded connection with TokioIo for compatibility
@@ -273,6 +843,14 @@ impl ProxyServer {
                     "Transparent forwarding completed for {}: {} bytes client->upstream, {} bytes upstream->client",
                     authority, client_to_upstream_bytes, upstream_to_client_bytes
                 );
+                if let Some(traffic) = self.accounted_traffic() {
+                    traffic.count(
+                        traffic.profile_for(client),
+                        &host,
+                        client_to_upstream_bytes + upstream_to_client_bytes,
+                        chrono::Utc::now(),
+                    );
+                }
             }
             Err(e) => {
                 debug!("Transparent forwarding error for {}: {}", authority, e);
@@ -291,8 +869,28 @@ impl ProxyServer {
     async fn handle_plain_http(
         &self,
         mut req: Request<Incoming>,
+        peer: SocketAddr,
         _tenant_ctx: &TenantContext,
+        policy: Arc<ListenerConfig>,
     ) -> Result<Response<UnsyncBoxBody<Bytes, ErrorCode>>, ProxyError> {
+        if policy.require_auth && !self.is_authorized(&req).await {
+            debug!(
+                "Rejecting unauthenticated request on {}",
+                policy.display_name()
+            );
+            return Ok(Response::builder()
+                .status(StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+                .header(
+                    hyper::header::PROXY_AUTHENTICATE,
+                    "Basic realm=\"witmproxy\"",
+                )
+                .body(
+                    Full::new(Bytes::from("Proxy authentication required"))
+                        .map_err(|_| ErrorCode::InternalError(Some("conversion error".to_string())))
+                        .boxed_unsync(),
+                )?);
+        }
+
         if req.method() == Method::CONNECT {
             debug!("Handling CONNECT request");
 
@@ -326,40 +924,124 @@ impl ProxyServer {
                 is_management_loopback = true;
             }
 
+            // Blocked hosts refused at the tunnel never reach MITM
+            let refused = parse_authority_host_port(&authority, 443)
+                .ok()
+                .filter(|_| !is_management_loopback)
+                .and_then(|(host, _)| self.block_rules.rule_for(&host).map(|rule| rule.behavior))
+                .filter(|behavior| {
+                    matches!(behavior, BlockBehavior::Reset | BlockBehavior::TlsAlert)
+                });
+
             // Check if any plugins want to handle this connection. Skip the
             // plugin path entirely for management-loopback so the UI bytes
             // aren't fed through MITM.
             let should_mitm = if is_management_loopback {
                 false
             } else {
-                self.handle_connect(&authority).await
+                match policy.mitm {
+                    MitmPolicy::Auto => {
+                        self.block_rules.intercepts(&authority)
+                            || self.mocks.intercepts(&authority)
+                            || self.user_scripts.intercepts(&authority)
+                            || self.translation.intercepts(&authority)
+                            || self.handle_connect(&authority, &policy).await
+                    }
+                    MitmPolicy::Always => true,
+                    MitmPolicy::Passthrough => false,
+                }
             };
 
+            // Flows through tunnels that aren't intercepted can't be checked
+            // one by one, so the tunnels themselves are refused past the quota
+            if !should_mitm
+                && !is_management_loopback
+                && let Some(traffic) = self.accounted_traffic()
+                && let Some(page) = quota_page(
+                    &traffic,
+                    peer.ip(),
+                    false,
+                    &self.pages,
+                    &FlowInfo::new(authority.as_str()),
+                )
+            {
+                return Ok(page);
+            }
+
             let on_upgrade = upgrade::on(&mut req);
 
-            if should_mitm {
-                // Perform MITM - existing behavior
+            if let Some(behavior) = refused {
+                info!(
+                    "Refusing tunnel to blocked host {} ({:?})",
+                    authority, behavior
+                );
+                tokio::spawn(async move {
+                    match on_upgrade.await {
+                        Ok(upgraded) => {
+                            if let Err(e) = block_rules::refuse_tunnel(upgraded, behavior).await
+                                && !is_closed(&e)
+                            {
+                                debug!("Error refusing tunnel to {}: {}", authority, e);
+                            }
+                        }
+                        Err(e) => warn!("upgrade error (CONNECT): {}", e),
+                    }
+                });
+            } else if should_mitm {
+                // Perform MITM on TLS; other protocols are offered to raw stream
+                // plugins and forwarded, since TLS interception would break them
                 let ca = self.ca.clone();
                 let upstream = self.upstream.clone();
                 let plugin_registry = self.plugin_registry.clone();
+                let settings = self.flow_settings(Some(policy.clone()));
 
                 tokio::spawn(async move {
                     match on_upgrade.await {
                         Ok(upgraded) => {
-                            if let Err(e) = run_tls_mitm(
-                                upstream,
-                                TokioIo::new(upgraded),
-                                authority.clone(),
-                                ca,
-                                plugin_registry,
-                            )
-                            .await
-                            {
+                            let mut client = TokioIo::new(upgraded);
+                            let result = match stream::read_preface(&mut client).await {
+                                Ok(None) => Ok(()),
+                                Ok(Some(preface)) => match StreamProtocol::classify(&preface) {
+                                    StreamProtocol::Tls => {
+                                        run_tls_mitm(
+                                            upstream,
+                                            PrefixedIo::new(preface, client),
+                                            peer.ip(),
+                                            authority.clone(),
+                                            ca,
+                                            plugin_registry,
+                                            settings,
+                                        )
+                                        .await
+                                    }
+                                    // Cleartext HTTP, e.g. a websocket tunneled with CONNECT
+                                    StreamProtocol::Http => {
+                                        stream::forward(client, &preface, &authority).await
+                                    }
+                                    protocol => {
+                                        debug!(
+                                            "Tunnel to {} carries {} rather than TLS, not intercepting",
+                                            authority, protocol
+                                        );
+                                        stream::handle_raw_stream(
+                                            client,
+                                            preface,
+                                            protocol,
+                                            &authority,
+                                            plugin_registry,
+                                            Some(policy),
+                                        )
+                                        .await
+                                    }
+                                },
+                                Err(e) => Err(e.into()),
+                            };
+                            if let Err(e) = result {
                                 match &e {
                                     ProxyError::Io(ioe) if is_closed(ioe) => {
-                                        debug!("tls tunnel closed")
+                                        debug!("tunnel closed")
                                     }
-                                    _ => warn!("tls mitm error for upstream {}: {}", authority, e),
+                                    _ => warn!("tunnel error for upstream {}: {}", authority, e),
                                 }
                             }
                         }
@@ -373,7 +1055,11 @@ impl ProxyServer {
                     match on_upgrade.await {
                         Ok(upgraded) => {
                             if let Err(e) = server
-                                .forward_connection_transparently(upgraded, authority.clone())
+                                .forward_connection_transparently(
+                                    upgraded,
+                                    peer.ip(),
+                                    authority.clone(),
+                                )
                                 .await
                             {
                                 match &e {
@@ -436,104 +1122,278 @@ impl ProxyServer {
             }
         }
 
-        // Convert hyper request to reqwest request
-        let reqwest_req = convert_hyper_incoming_to_reqwest_request(req, &self.upstream)?;
-        let resp = self.upstream.execute(reqwest_req).await?;
-
-        // Convert reqwest response back to hyper response
-        let mut response = convert_reqwest_to_hyper_response(resp).await?;
+        let req = normalize::normalize_request(req, false);
+        let flow = FlowInfo::new(req.uri().host().unwrap_or_default());
+        if let Some(response) = self.block_rules.respond(&self.pages, &flow) {
+            info!("Refusing request {} to blocked host {}", flow.id, flow.host);
+            return Ok(response);
+        }
+        let req = match self.enabled_upgrades() {
+            Some(upgrades) => match upgrades.upgrade(req, &flow) {
+                Upgraded::Request(req) => req,
+                Upgraded::Redirect(redirect) => return Ok(redirect),
+            },
+            None => req,
+        };
+        if let Some(limit) = self.limits.check_request(req.headers()) {
+            return Ok(self
+                .pages
+                .failure(&WitmError::RequestTooLarge(limit), &flow));
+        }
 
-        // Strip hop-by-hop headers from the response
-        strip_proxy_headers(response.headers_mut());
+        let traffic = self.accounted_traffic();
+        if let Some(page) = traffic.as_ref().and_then(|traffic| {
+            let page_load = is_page_load(req.method(), req.headers());
+            quota_page(traffic, peer.ip(), page_load, &self.pages, &flow)
+        }) {
+            return Ok(page);
+        }
 
+        // Convert hyper request to reqwest request
+        let mut req = req.map(|body| self.limits.limit_request_body(body));
+        if let Some(traffic) = &traffic {
+            let profile = traffic.profile_for(peer.ip());
+            req = req.map(|body| traffic.counted(profile, &flow.host, body));
+        }
+        let security_request = (!self.security_headers.is_empty()).then(|| CelRequest::from(&req));
+        let page_url = (!self.user_scripts.is_empty()).then(|| req.uri().to_string());
+        let image_accept = self.images.accepted(req.headers());
+        let accept_encoding = self.compression.accepted(req.headers());
+        let stripped = if self.privacy.is_enabled() {
+            self.privacy.strip_request(&mut req, &flow.host)
+        } else {
+            Stripped::default()
+        };
+        let schemas = self.recorded_schemas();
+        let (req, endpoint) = match &schemas {
+            Some(schemas) => {
+                let (req, endpoint) = schemas.observe_request(req, &flow.host);
+                (req, Some(endpoint))
+            }
+            None => (req, None),
+        };
+        let upstream_client = self
+            .egress
+            .client_for(&flow.host)
+            .or_else(|| self.tls_policies.client_for(&flow.host))
+            .unwrap_or(&self.upstream);
+        let reqwest_req = convert_hyper_boxed_body_to_reqwest_request(req, upstream_client)?;
+        let deadline = self.limits.flow_deadline;
+        let settings = self.flow_settings(None);
+        let upstream = perform_upstream(upstream_client, reqwest_req, peer.ip(), &flow, &settings);
+        let mut response = match tokio::time::timeout(deadline, upstream).await {
+            Ok(response) => response,
+            Err(_) => self
+                .pages
+                .failure(&WitmError::DeadlineExceeded(deadline), &flow),
+        };
+        if let Some(request) = &security_request {
+            self.security_headers.apply(request, &mut response);
+        }
+        if self.translation.applies(&flow.host) {
+            response = self.translation.translate(&flow.host, response).await;
+        }
+        if let Some(url) = &page_url {
+            response = self.user_scripts.inject(url, response);
+        }
+        if let Some(accept) = &image_accept {
+            response = self.images.optimize(accept, response).await;
+        }
+        self.privacy
+            .strip_response(stripped, response.headers_mut());
+        if let (Some(schemas), Some(endpoint)) = (&schemas, &endpoint) {
+            response = schemas.observe_response(endpoint, response);
+        }
+        if let Some(accept_encoding) = &accept_encoding {
+            response = self.compression.apply(accept_encoding, response);
+        }
+        // Compressed first, so traffic is counted as it's sent
+        if let Some(traffic) = &traffic {
+            let profile = traffic.profile_for(peer.ip());
+            response = response.map(|body| traffic.counted(profile, &flow.host, body));
+        }
         Ok(response)
     }
 }
 
 // --- Extracted helpers from run_tls_mitm ---
 
+/// Pass an event through the plugin chain, restricted to the plugins the
+/// listener allows when it has a plugin filter.
+async fn dispatch_event(
+    registry: &PluginRegistry,
+    event: Box<dyn Event>,
+    listener: Option<&ListenerConfig>,
+) -> anyhow::Result<(WasmEvent, Store<Host>)> {
+    match listener.filter(|l| l.filters_plugins()) {
+        Some(listener) => {
+            let allowed = registry.plugins_matching(|id| listener.allows_plugin(id));
+            registry.handle_event_for_tenant(event, &allowed, &[]).await
+        }
+        None => registry.handle_event(event).await,
+    }
+}
+
+/// The page answering a flow of `client` in place of its request when its
+/// profile's quota doesn't allow it
+fn quota_page(
+    traffic: &Traffic,
+    client: IpAddr,
+    page_load: bool,
+    pages: &ErrorPages,
+    flow: &FlowInfo,
+) -> Option<ErrorResponse> {
+    match traffic.check(client, page_load, chrono::Utc::now()) {
+        QuotaCheck::Allowed => None,
+        QuotaCheck::Warn(reached) => {
+            info!(
+                "Warning {} of profile {}'s {} quota",
+                client,
+                reached.profile,
+                reached.period.adjective()
+            );
+            let message = format!(
+                "Clients in profile {} have transferred {} bytes, past their {} warning limit of {} bytes. Reload the page to continue.",
+                reached.profile,
+                reached.used,
+                reached.period.adjective(),
+                reached.limit
+            );
+            Some(pages.error(StatusCode::OK, "Data quota nearly used", &message, flow))
+        }
+        QuotaCheck::Exceeded(reached) => {
+            let err = WitmError::QuotaExceeded {
+                profile: reached.profile,
+                period: reached.period.adjective(),
+                limit: reached.limit,
+            };
+            Some(pages.failure(&err, flow))
+        }
+    }
+}
+
+/// Extract the API token from a `Proxy-Authorization` header value
+fn proxy_authorization_token(value: &str) -> Option<String> {
+    use base64::Engine;
+
+    let (scheme, credentials) = value.trim().split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("bearer") {
+        return Some(credentials.trim().to_string());
+    }
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(credentials.trim())
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':').unwrap_or((&decoded, ""));
+    [password, username]
+        .into_iter()
+        .find(|part| part.starts_with(API_TOKEN_PREFIX))
+        .map(str::to_string)
+}
+
+/// Send `req` upstream, or answer it from `mocks`, with the headers of its
+/// host's override profile and under the network conditions simulated for
+/// the client
 pub(crate) async fn perform_upstream(
+    upstream: &reqwest::Client,
+    mut req: reqwest::Request,
+    client: IpAddr,
+    flow: &FlowInfo,
+    settings: &FlowSettings,
+) -> Response<UnsyncBoxBody<Bytes, ErrorCode>> {
+    if let Some(host) = req.url().host_str().map(str::to_string)
+        && let Some(name) = settings.header_overrides.apply(&host, req.headers_mut())
+    {
+        flow_trace::record("headers", || format!("{name} header profile"));
+    }
+    let profile = req
+        .url()
+        .host_str()
+        .and_then(|host| settings.network_conditions.profile_for(host, client));
+    let Some(profile) = profile else {
+        return exchange(upstream, req, client, flow, settings).await;
+    };
+    let delay = profile.delay();
+    flow_trace::record("network", || {
+        format!("{} profile, {:?} added latency", profile.name, delay)
+    });
+    tokio::time::sleep(delay).await;
+    exchange(upstream, req, client, flow, settings)
+        .await
+        .map(|body| profile.shape(body))
+}
+
+async fn exchange(
     upstream: &reqwest::Client,
     req: reqwest::Request,
+    client: IpAddr,
+    flow: &FlowInfo,
+    settings: &FlowSettings,
 ) -> Response<UnsyncBoxBody<Bytes, ErrorCode>> {
+    if let Some(response) = settings
+        .mocks
+        .respond(req.method(), req.url(), req.headers())
+    {
+        flow_trace::record("upstream", || {
+            format!("{} mocked {}", response.status(), req.url())
+        });
+        timeline::record(
+            "upstream",
+            || format!("{} {}", req.method(), req.url()),
+            std::time::Instant::now(),
+            || format!("{} mocked", response.status()),
+        );
+        return response;
+    }
+    let permit = match req.url().host_str() {
+        Some(host) => settings.host_limiter.acquire(host, client).await,
+        None => None,
+    };
+    let start = std::time::Instant::now();
+    let label = format!("{} {}", req.method(), req.url());
     match upstream.execute(req).await {
         Ok(resp) => {
             debug!("Upstream response status: {}", resp.status());
-            match convert_reqwest_to_hyper_response(resp).await {
-                Ok(mut response) => {
-                    strip_proxy_headers(response.headers_mut());
-                    response
-                }
-                Err(err) => {
-                    error!("Failed to convert response: {}", err);
-                    Response::builder()
-                        .status(StatusCode::BAD_GATEWAY)
-                        .body(
-                            Full::new(Bytes::from("Failed to convert upstream response"))
-                                .map_err(|_| {
-                                    ErrorCode::InternalError(Some("conversion error".to_string()))
-                                })
-                                .boxed_unsync(),
-                        )
-                        .unwrap()
-                }
+            flow_trace::record("upstream", || {
+                format!(
+                    "{} from {} in {:?}",
+                    resp.status(),
+                    resp.url(),
+                    start.elapsed()
+                )
+            });
+            timeline::record(
+                "upstream",
+                || label.clone(),
+                start,
+                || resp.status().to_string(),
+            );
+            let mut response = convert_reqwest_to_hyper_response(resp);
+            strip_proxy_headers(response.headers_mut());
+            // The connection stays busy, and the upstream's span open, until
+            // the body has been streamed
+            match (permit, timeline::open("upstream-body", || label)) {
+                (None, None) => response,
+                held => response.map(|body| utils::BodyHolding::new(body, held).boxed_unsync()),
             }
         }
         Err(err) => {
-            error!("Upstream request failed with detailed error: {:?}", err);
-            Response::builder()
-                .status(StatusCode::BAD_GATEWAY)
-                .body(
-                    Full::new(Bytes::from(err.to_string()))
-                        .map_err(|_| ErrorCode::InternalError(Some("conversion error".to_string())))
-                        .boxed_unsync(),
-                )
-                .unwrap()
-        }
-    }
-}
-
-/// Fix origin-form requests by adding authority from Host header to URI
-fn fix_origin_form_request(mut req: Request<Incoming>) -> Request<Incoming> {
-    // Check if URI has no authority but has a Host header (origin-form request)
-    if req.uri().authority().is_none()
-        && let Some(host_header) = req.headers().get(hyper::header::HOST)
-        && let Ok(host_str) = host_header.to_str()
-    {
-        // Clone the host string to avoid borrowing conflicts
-        let host_string = host_str.to_string();
-
-        // Reconstruct URI with authority from Host header
-        let original_uri = req.uri();
-        let mut uri_builder = hyper::Uri::builder();
-
-        // Preserve scheme (default to https for TLS connections)
-        if let Some(scheme) = original_uri.scheme() {
-            uri_builder = uri_builder.scheme(scheme.clone());
-        } else {
-            uri_builder = uri_builder.scheme("https");
-        }
-
-        // Add authority from Host header
-        uri_builder = uri_builder.authority(host_string.as_str());
-
-        // Preserve path and query
-        if let Some(path_and_query) = original_uri.path_and_query() {
-            uri_builder = uri_builder.path_and_query(path_and_query.clone());
-        } else {
-            uri_builder = uri_builder.path_and_query("/");
-        }
-
-        // Build new URI and update request
-        if let Ok(new_uri) = uri_builder.build() {
-            *req.uri_mut() = new_uri;
-            debug!(
-                "Fixed origin-form request: added authority '{}' to URI",
-                host_string
+            error!(
+                "Upstream request failed for flow {} with detailed error: {:?}",
+                flow.id, err
             );
+            flow_trace::record("upstream", || {
+                format!("Failed after {:?}: {}", start.elapsed(), err)
+            });
+            timeline::record("upstream", || label, start, || err.to_string());
+            settings
+                .pages
+                .failure(&WitmError::from_upstream(&err), flow)
         }
     }
-    req
 }
 
 /// Performs TLS MITM on a connection, then serves the *client-facing* side
@@ -541,13 +1401,15 @@ fn fix_origin_form_request(mut req: Request<Incoming>) -> Request<Incoming> {
 ///
 /// Generic over the IO type so it can be used from both the standard proxy
 /// (with `TokioIo<Upgraded>`) and the transparent proxy (with `TcpStream`).
-#[tracing::instrument(skip(upstream, stream, ca, plugin_registry), fields(authority = %authority))]
+#[tracing::instrument(skip(upstream, stream, ca, plugin_registry, settings), fields(authority = %authority))]
 pub(crate) async fn run_tls_mitm<IO>(
     upstream: reqwest::Client,
     stream: IO,
+    client: IpAddr,
     authority: String,
     ca: Arc<CertificateAuthority>,
     plugin_registry: Option<Arc<RwLock<PluginRegistry>>>,
+    settings: FlowSettings,
 ) -> ProxyResult<()>
 where
     IO: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
@@ -558,309 +1420,640 @@ where
     let (host, _port) = parse_authority_host_port(&authority, 443)?;
 
     // --- Build a server TLS config for the client side (fake cert for `host`) ---
-    let server_tls = build_server_tls_for_host(&ca, &host).await?;
+    let policy = settings.tls_policies.client_policy(&host);
+    let server_tls = build_server_tls_for_host(&ca, &host, policy).await?;
     let acceptor = TlsAcceptor::from(Arc::new(server_tls));
 
+    serve_tls_flows(
+        upstream,
+        stream,
+        client,
+        host,
+        acceptor,
+        plugin_registry,
+        settings,
+    )
+    .await
+}
+
+/// Accepts TLS for `host` with `acceptor`, then runs each request on the
+/// connection through the plugin pipeline and on to `upstream`.
+pub(crate) async fn serve_tls_flows<IO>(
+    upstream: reqwest::Client,
+    stream: IO,
+    client: IpAddr,
+    host: String,
+    acceptor: TlsAcceptor,
+    plugin_registry: Option<Arc<RwLock<PluginRegistry>>>,
+    settings: FlowSettings,
+) -> ProxyResult<()>
+where
+    IO: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let connected = std::time::Instant::now();
+    // Reverse proxy routes are matched by the origin they're sent to
+    let egress_host = settings
+        .origin
+        .as_ref()
+        .and_then(|origin| origin.host_str())
+        .unwrap_or(&host);
+    let upstream = match settings.egress.client_for(egress_host) {
+        Some(routed) => {
+            debug!("Routing flows for {} through egress route", egress_host);
+            routed.clone()
+        }
+        None => match settings.tls_policies.client_for(egress_host) {
+            Some(policed) => policed.clone(),
+            None => upstream,
+        },
+    };
+    let settings = Arc::new(settings);
+
+    let tls_started = std::time::Instant::now();
     let tls = acceptor.accept(stream).await?;
     debug!("TLS established with client for {}", host);
+    // Only the first flow on the connection waited for the handshake
+    let handshake = Arc::new(std::sync::Mutex::new(Some(Handshake {
+        connected,
+        tls_started,
+        tls_finished: std::time::Instant::now(),
+    })));
+    let connection = ConnectionInfo::new(&host, tls.get_ref().1.server_name());
+    let negotiated = tls_policy::negotiated(tls.get_ref().1);
 
     // Auto (h1/h2) Hyper server over the client TLS stream
     let executor = TokioExecutor::new();
     let auto: AutoServer<TokioExecutor> = AutoServer::new(executor);
 
     // Service that proxies each decrypted request to the real upstream host
+    // Flows are recorded for plugins to read, so without plugins there's no log
+    let (flows, timelines, snapshots) = match &plugin_registry {
+        Some(registry) => {
+            let registry = registry.read().await;
+            (
+                Some(registry.flows().clone()),
+                Some(registry.timelines().clone()),
+                Some(registry.storage_snapshots().clone()),
+            )
+        }
+        None => (None, None, None),
+    };
     let svc = {
         service_fn(move |req: Request<Incoming>| {
             let upstream = upstream.clone();
+            let flows = flows.clone();
             let plugin_registry = plugin_registry.clone();
+            let settings = settings.clone();
+            let connection = connection.clone();
+            let negotiated = negotiated.clone();
+            let flow = FlowInfo::new(host.as_str());
+            let timeline = timelines
+                .as_ref()
+                .map(|timelines| timelines.start(&flow.id, handshake.lock().unwrap().take()));
+            let storage_snapshot = snapshots
+                .as_ref()
+                .and_then(|snapshots| snapshots.start(&flow.id));
 
             async move {
-                let service_fn_start = std::time::Instant::now();
-                let method = req.method().clone();
-                let uri = req.uri().clone();
-                let req = fix_origin_form_request(req);
-                debug!("Handling TLS request: {} {}", method, uri);
-                debug!("🕐 SERVICE_FN START: {} {}", method, uri);
-                let mut request_ctx = CelRequest::from(&req);
-
-                let request_event_result = if let Some(registry) = &plugin_registry {
-                    let registry = registry.read().await;
-                    let (parts, body) = req.into_parts();
-                    let mapped_body = body.map_err(ErrorCode::from_hyper_request_error);
-                    let req = Request::from_parts(parts, mapped_body);
-                    let (request, _io) = WasiRequest::from_http(req);
-                    let event: Box<dyn Event> = Box::new(request);
-
-                    registry.handle_event(event).await
+                let settings = &*settings;
+                let FlowSettings {
+                    limits,
+                    pages,
+                    host_mismatch,
+                    security_headers,
+                    flow_tags,
+                    sessions,
+                    block_rules,
+                    user_scripts,
+                    images,
+                    compression,
+                    translation,
+                    schemas,
+                    https_upgrades,
+                    traffic,
+                    sensitive_data,
+                    privacy,
+                    protobuf,
+                    origin,
+                    listener,
+                    tenant,
+                    hooks,
+                    traces,
+                    ..
+                } = settings;
+                if let Some(limit) = limits.check_request(req.headers()) {
+                    return Ok::<_, std::convert::Infallible>(
+                        pages.failure(&WitmError::RequestTooLarge(limit), &flow),
+                    );
+                }
+                if let Some(page) = traffic.as_ref().and_then(|traffic| {
+                    let page_load = is_page_load(req.method(), req.headers());
+                    quota_page(traffic, client, page_load, pages, &flow)
+                }) {
+                    return Ok(page);
+                }
+                let req = req.map(|body| limits.limit_request_body(body));
+                let req = match &traffic {
+                    Some(traffic) => req
+                        .map(|body| traffic.counted(traffic.profile_for(client), &flow.host, body)),
+                    None => req,
+                };
+                let mut req = normalize::normalize_request(req, true);
+                // Security headers and flow tags match the request as the
+                // client sent it
+                let cel_request = (!security_headers.is_empty() || !flow_tags.is_empty())
+                    .then(|| CelRequest::from(&req));
+                let page_url = (!user_scripts.is_empty()).then(|| {
+                    let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+                    format!("https://{}{}", flow.host, path)
+                });
+                let image_accept = images.accepted(req.headers());
+                let accept_encoding = compression.accepted(req.headers());
+                let stripped = if privacy.is_enabled() {
+                    privacy.strip_request(&mut req, &flow.host)
                 } else {
-                    let request_result = convert_hyper_incoming_to_reqwest_request(req, &upstream);
-                    match request_result {
-                        Ok(rq) => return Ok(perform_upstream(&upstream, rq).await),
-                        Err(err) => {
-                            return Response::builder().status(StatusCode::BAD_REQUEST).body(
-                                Full::new(Bytes::from(format!(
-                                    "Failed to convert request: {}",
-                                    err
-                                )))
-                                .map_err(|_| {
-                                    ErrorCode::InternalError(Some("conversion error".to_string()))
-                                })
-                                .boxed_unsync(),
-                            );
-                        }
+                    Stripped::default()
+                };
+                let (req, endpoint) = match &schemas {
+                    Some(schemas) => {
+                        let (req, endpoint) = schemas.observe_request(req, &flow.host);
+                        (req, Some(endpoint))
                     }
+                    None => (req, None),
                 };
+                let mut req = req;
+                let trace = traces.start(&flow.id, client, &req);
+                if trace.is_some() {
+                    req.headers_mut().remove(DEBUG_HEADER);
+                }
 
-                let request_event_elapsed = service_fn_start.elapsed();
-                debug!(
-                    "🕐 REQUEST_EVENT handled in {:?}, checking result and performing upstream call if needed",
-                    request_event_elapsed
-                );
-
-                let upstream_start = std::time::Instant::now();
-                let initial_response = match request_event_result {
-                    Err(e) => Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(
-                            Full::new(Bytes::from(format!("Plugin event handling error: {}", e)))
+                let timeout_pages = pages.clone();
+                let timeout_flow = flow.clone();
+                let timeout_flows = flows.clone();
+                let timeout_hooks = hooks.clone();
+                let timeout_sessions = sessions.clone();
+                let handle = async move {
+                    let service_fn_start = std::time::Instant::now();
+                    let method = req.method().clone();
+                    let uri = req.uri().clone();
+                    debug!("Handling TLS request {}: {} {}", flow.id, method, uri);
+                    let (req, findings) = match &flows {
+                        Some(flows) => {
+                            sensitive_data.record(flows, &flow.id, &req, tenant.as_deref());
+                            for (key, value) in &negotiated {
+                                flows.annotate(&flow.id, key, value);
+                            }
+                            if sessions.is_active() {
+                                sessions.begin(flows, &flow.id);
+                            }
+                            sensitive_data.observe_request(flows, &flow.id, req)
+                        }
+                        None => (req, Vec::new()),
+                    };
+                    if let Some(mismatch) = req
+                        .uri()
+                        .authority()
+                        .and_then(|authority| connection.mismatch(authority.as_str()))
+                    {
+                        if let Some(flows) = &flows {
+                            flows.annotate(&flow.id, HOST_MISMATCH, &mismatch);
+                        }
+                        match host_mismatch {
+                            HostMismatchPolicy::Allow => {}
+                            HostMismatchPolicy::Log => {
+                                warn!("Host mismatch on flow {}: {}", flow.id, mismatch)
+                            }
+                            HostMismatchPolicy::Block => {
+                                warn!("Blocking flow {}: {}", flow.id, mismatch);
+                                return Ok(pages.failure(&WitmError::HostMismatch(mismatch), &flow));
+                            }
+                        }
+                    }
+                    if let Some(response) = block_rules.respond(&pages, &flow) {
+                        info!("Refusing flow {} to blocked host {}", flow.id, flow.host);
+                        if let Some(flows) = &flows {
+                            flows.annotate(&flow.id, BLOCKED_BY, BLOCKED_BY_RULE);
+                        }
+                        return Ok(response);
+                    }
+                    // GraphQL operations are only parsed for plugins and their
+                    // scopes, so without plugins the body streams through
+                    let (req, graphql) = if plugin_registry.is_some()
+                        && graphql::is_graphql_post(&req)
+                        && graphql::fits_buffer(&req)
+                    {
+                        let (parts, body) = req.into_parts();
+                        let body = match body.collect().await {
+                            Ok(body) => body.to_bytes(),
+                            Err(e) => {
+                                return Ok(
+                                    pages.failure(&WitmError::RequestBody(e.to_string()), &flow)
+                                );
+                            }
+                        };
+                        let req = Request::from_parts(parts, body);
+                        let graphql = graphql::parse_request(&req, req.body());
+                        let req = req.map(|body| {
+                            Full::new(body)
                                 .map_err(|_| {
                                     ErrorCode::InternalError(Some("conversion error".to_string()))
                                 })
-                                .boxed_unsync(),
-                        )
-                        .expect("Could not construct error Response"),
-                    Ok((event_data, mut store)) => match event_data {
-                        WasmEvent::Request(rq) => {
-                            // TODO: no unwraps
-                            let rq = store.data_mut().http().table.delete(rq).unwrap();
-                            request_ctx = CelRequest::from(&rq);
-                            let (rq, _io) = rq.into_http(store, async { Ok(()) }).unwrap();
-
-                            let rq: Result<reqwest::Request, ProxyError> =
-                                convert_hyper_boxed_body_to_reqwest_request(rq, &upstream);
-                            match rq {
-                                Ok(rq) => perform_upstream(&upstream, rq).await,
-                                Err(err) => Response::builder()
-                                    .status(StatusCode::BAD_REQUEST)
-                                    .body(
-                                        Full::new(Bytes::from(format!(
-                                            "Failed to convert request: {}",
-                                            err
-                                        )))
-                                        .map_err(|_| {
-                                            ErrorCode::InternalError(Some(
-                                                "conversion error".to_string(),
-                                            ))
-                                        })
-                                        .boxed_unsync(),
-                                    )
-                                    .unwrap(),
+                                .boxed_unsync()
+                        });
+                        (req, graphql)
+                    } else {
+                        (req, None)
+                    };
+                    // Protobuf bodies are decoded to JSON for plugins, and
+                    // encoded back once they've handled the request
+                    let message = protobuf
+                        .request_message(req.uri().host().unwrap_or(&flow.host), req.uri().path())
+                        .filter(|_| plugin_registry.is_some() && protobuf::decodes_request(&req));
+                    let (req, protobuf_request) = match message {
+                        Some(message) => {
+                            let content_type = req.headers()[hyper::header::CONTENT_TYPE].clone();
+                            match protobuf::decode_request(req, &message).await {
+                                Ok((req, true)) => {
+                                    if let Some(flows) = &flows {
+                                        flows.annotate(&flow.id, PROTOBUF, message.full_name());
+                                    }
+                                    (req, Some((message, content_type)))
+                                }
+                                Ok((req, false)) => (req, None),
+                                Err(e) => {
+                                    return Ok(pages
+                                        .failure(&WitmError::RequestBody(e.to_string()), &flow));
+                                }
                             }
                         }
-                        WasmEvent::Response(WasiContextualResponse { response, .. }) => {
-                            let response = store.data_mut().http().table.delete(response).unwrap();
-                            response.into_http(store, async { Ok(()) }).unwrap()
+                        None => (req, None),
+                    };
+                    debug!("🕐 SERVICE_FN START: {} {}", method, uri);
+                    let mut request_ctx = CelRequest::from(&req);
+
+                    let request_event_result = if let Some(registry) = &plugin_registry {
+                        let registry = registry.read().await;
+                        let (request, _io) = WasiRequest::from_http(req);
+                        let event: Box<dyn Event> = Box::new(InterceptedRequest {
+                            request,
+                            connection,
+                            findings: findings.clone(),
+                            graphql: graphql.clone(),
+                        });
+
+                        dispatch_event(&registry, event, listener.as_deref()).await
+                    } else {
+                        let request_result =
+                            convert_hyper_boxed_body_to_reqwest_request(req, &upstream);
+                        match request_result {
+                            Ok(mut rq) => {
+                                if let Some(origin) = &origin {
+                                    reverse::route_to_origin(&mut rq, origin, client);
+                                }
+                                return Ok(perform_upstream(
+                                    &upstream, rq, client, &flow, settings,
+                                )
+                                .await);
+                            }
+                            Err(err) => {
+                                return Ok(pages
+                                    .failure(&WitmError::InvalidRequest(err.to_string()), &flow));
+                            }
                         }
-                        _ => Response::builder()
-                            .status(StatusCode::INTERNAL_SERVER_ERROR)
-                            .body(
-                                Full::new(Bytes::from("Unexpected event data type from plugin"))
-                                    .map_err(|_| {
-                                        ErrorCode::InternalError(Some(
-                                            "conversion error".to_string(),
-                                        ))
-                                    })
-                                    .boxed_unsync(),
-                            )
-                            .expect("Could not construct error Response"),
-                    },
-                };
-
-                let upstream_elapsed = upstream_start.elapsed();
-                debug!(
-                    "🕐 INITIAL_RESPONSE obtained in {:?}, proceeding to response event handling",
-                    upstream_elapsed
-                );
-
-                let response_event_start = std::time::Instant::now();
-                let handled_response = if let Some(registry) = &plugin_registry {
-                    let registry = registry.read().await;
-                    let (response, _io) = WasiResponse::from_http(initial_response);
-                    let contextual_response = ContextualResponse {
-                        request: request_ctx.into(),
-                        response,
                     };
-                    registry.handle_event(Box::new(contextual_response)).await
-                } else {
-                    // No plugin registry, just return the initial response
-                    return Ok(initial_response);
-                };
 
-                let response_event_elapsed = response_event_start.elapsed();
-                debug!(
-                    "🕐 RESPONSE_EVENT handled in {:?}, checking for specific content-type handling",
-                    response_event_elapsed
-                );
+                    let request_event_elapsed = service_fn_start.elapsed();
+                    debug!(
+                        "🕐 REQUEST_EVENT handled in {:?}, checking result and performing upstream call if needed",
+                        request_event_elapsed
+                    );
 
-                // Check response content-type for content-specific handling
-                let (content, plugin_store) = if let Some(registry) = &plugin_registry {
-                    let (response, mut store) = match handled_response {
-                        Ok((event_data, store)) => match event_data {
-                            WasmEvent::Response(WasiContextualResponse { response, .. }) => {
-                                (response, store)
+                    let upstream_start = std::time::Instant::now();
+                    let initial_response = match request_event_result {
+                        Err(e) => {
+                            debug!("Request event handling failed for flow {}: {}", flow.id, e);
+                            if let (Some(flows), Some(blocked)) =
+                                (&flows, e.downcast_ref::<PluginBlocked>())
+                            {
+                                flows.annotate(&flow.id, BLOCKED_BY, &blocked.plugin_id);
                             }
-                            _ => {
-                                return Response::builder()
-                                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                                    .body(
-                                        Full::new(Bytes::from(
-                                            "Unexpected event data type from plugin",
-                                        ))
-                                        .map_err(|_| {
-                                            ErrorCode::InternalError(Some(
-                                                "conversion error".to_string(),
-                                            ))
-                                        })
-                                        .boxed_unsync(),
-                                    );
+                            pages.failure(
+                                hooks.plugin_failed(&flow, &WitmError::from_plugin(&e)),
+                                &flow,
+                            )
+                        }
+                        Ok((event_data, mut store)) => match event_data {
+                            WasmEvent::Request(rq) => {
+                                // TODO: no unwraps
+                                let rq = store.data_mut().http().table.delete(rq).unwrap();
+                                request_ctx = CelRequest::from(&rq);
+                                let (rq, _io) = rq.into_http(store, async { Ok(()) }).unwrap();
+                                let rq = match &protobuf_request {
+                                    Some((message, content_type)) => protobuf::encode_request(
+                                        rq,
+                                        message.clone(),
+                                        content_type.clone(),
+                                    ),
+                                    None => rq,
+                                };
+
+                                let rq: Result<reqwest::Request, ProxyError> =
+                                    convert_hyper_boxed_body_to_reqwest_request(rq, &upstream);
+                                match rq {
+                                    Ok(mut rq) => {
+                                        if let Some(origin) = &origin {
+                                            reverse::route_to_origin(&mut rq, origin, client);
+                                        }
+                                        perform_upstream(&upstream, rq, client, &flow, settings)
+                                            .await
+                                    }
+                                    Err(err) => pages.failure(
+                                        &WitmError::InvalidRequest(err.to_string()),
+                                        &flow,
+                                    ),
+                                }
+                            }
+                            WasmEvent::Response(WasiContextualResponse { response, .. }) => {
+                                let response =
+                                    store.data_mut().http().table.delete(response).unwrap();
+                                response.into_http(store, async { Ok(()) }).unwrap()
                             }
+                            _ => pages.failure(
+                                hooks.plugin_failed(&flow, &WitmError::PluginOutput),
+                                &flow,
+                            ),
                         },
-                        Err(e) => {
-                            error!("Response event handling error: {}", e);
-                            return Response::builder()
-                                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                                .body(
-                                    Full::new(Bytes::from(format!(
-                                        "Plugin response event handling error: {}",
-                                        e
-                                    )))
-                                    .map_err(|_| {
-                                        ErrorCode::InternalError(Some(
-                                            "conversion error".to_string(),
-                                        ))
-                                    })
-                                    .boxed_unsync(),
-                                );
-                        }
                     };
-                    let registry = registry.read().await;
-                    let response = store.data_mut().http().table.delete(response).unwrap();
-                    let content_type = response.content_type();
-
-                    // Check if this response should have content that plugins should process
-                    // Only process 2xx success responses (except 204 No Content)
-                    // Skip: 1xx informational, 204 No Content, 3xx redirects, 4xx client errors, 5xx server errors
-                    let should_process_content = matches!(
-                        response.status.as_u16(),
-                        // Only 2xx success responses (except 204 No Content)
-                        200..=203 | 205..=299
+
+                    let upstream_elapsed = upstream_start.elapsed();
+                    debug!(
+                        "🕐 INITIAL_RESPONSE obtained in {:?}, proceeding to response event handling",
+                        upstream_elapsed
                     );
+                    let upstream_cert =
+                        initial_response.extensions().get::<UpstreamCert>().cloned();
+                    if let (Some(flows), Some(cert)) = (&flows, &upstream_cert) {
+                        flows.set_upstream_cert(&flow.id, cert);
+                    }
 
-                    debug!("Content type for InboundContent: {}", content_type);
-                    let response = response.into_http(&mut store, async { Ok(()) }).unwrap();
-                    let (parts, body) = response.into_parts();
-                    let content = InboundContent::new(parts, content_type.clone(), body).unwrap();
-                    // Skip content event processing if:
-                    // 1. Content-type is unknown (no Content-Type header)
-                    // 2. Response status indicates content should not be processed by plugins
-                    //    (only 2xx success responses are processed, excluding 204 No Content)
-                    if content_type.eq("unknown") || !should_process_content {
-                        debug!(
-                            "Skipping InboundContent event processing (content_type={}, should_process_content={})",
-                            content_type, should_process_content
-                        );
-                        (content, None)
+                    let response_message =
+                        protobuf.response_message(&request_ctx.host, &request_ctx.path);
+                    let response_event_start = std::time::Instant::now();
+                    let handled_response = if let Some(registry) = &plugin_registry {
+                        let registry = registry.read().await;
+                        let upstream_addr = initial_response
+                            .extensions()
+                            .get::<UpstreamAddr>()
+                            .map(|addr| addr.0);
+                        let (response, _io) = WasiResponse::from_http(initial_response);
+                        let contextual_response = ContextualResponse {
+                            request: request_ctx.into(),
+                            response,
+                            upstream_addr,
+                            upstream_cert,
+                            findings,
+                            graphql,
+                        };
+                        dispatch_event(
+                            &registry,
+                            Box::new(contextual_response),
+                            listener.as_deref(),
+                        )
+                        .await
                     } else {
-                        let content = Box::new(content) as Box<dyn Event>;
-                        debug!(
-                            "Created InboundContent event with content-type: {}",
-                            content_type
-                        );
-                        let start_handle = std::time::Instant::now();
-                        let (event, mut store) = registry.handle_event(content).await.unwrap();
-                        debug!(
-                            "InboundContent event handled in {:?}",
-                            start_handle.elapsed()
-                        );
-
-                        match event {
-                            WasmEvent::InboundContent(content_resource) => {
-                                let content =
-                                    store.data_mut().table.delete(content_resource).unwrap();
-                                (content, Some(store))
+                        // No plugin registry, just return the initial response
+                        return Ok(initial_response);
+                    };
+
+                    let response_event_elapsed = response_event_start.elapsed();
+                    debug!(
+                        "🕐 RESPONSE_EVENT handled in {:?}, checking for specific content-type handling",
+                        response_event_elapsed
+                    );
+
+                    // Check response content-type for content-specific handling
+                    let (content, plugin_store) = if let Some(registry) = &plugin_registry {
+                        let (response, mut store) = match handled_response {
+                            Ok((event_data, store)) => match event_data {
+                                WasmEvent::Response(WasiContextualResponse {
+                                    response, ..
+                                }) => (response, store),
+                                _ => {
+                                    return Ok(pages.failure(
+                                        hooks.plugin_failed(&flow, &WitmError::PluginOutput),
+                                        &flow,
+                                    ));
+                                }
+                            },
+                            Err(e) => {
+                                error!("Response event handling error for flow {}: {}", flow.id, e);
+                                return Ok(pages.failure(
+                                    hooks.plugin_failed(&flow, &WitmError::from_plugin(&e)),
+                                    &flow,
+                                ));
+                            }
+                        };
+                        let registry = registry.read().await;
+                        let response = store.data_mut().http().table.delete(response).unwrap();
+                        let content_type = response.content_type();
+
+                        // Check if this response should have content that plugins should process
+                        // Only process 2xx success responses (except 204 No Content)
+                        // Skip: 1xx informational, 204 No Content, 3xx redirects, 4xx client errors, 5xx server errors
+                        let should_process_content = matches!(
+                            response.status.as_u16(),
+                            // Only 2xx success responses (except 204 No Content)
+                            200..=203 | 205..=299
+                        ) && limits
+                            .allows_buffering(&response.headers);
+
+                        debug!("Content type for InboundContent: {}", content_type);
+                        let response = response.into_http(&mut store, async { Ok(()) }).unwrap();
+                        // Skip content event processing if:
+                        // 1. Content-type is unknown (no Content-Type header)
+                        // 2. Response status indicates content should not be processed by plugins
+                        //    (only 2xx success responses are processed, excluding 204 No Content),
+                        //    or the response is larger than `max_buffered_response_bytes`
+                        // The body is then streamed to the client as received, still encoded.
+                        if content_type.eq("unknown") || !should_process_content {
+                            debug!(
+                                "Skipping InboundContent event processing (content_type={}, should_process_content={})",
+                                content_type, should_process_content
+                            );
+                            return Ok(response);
+                        } else {
+                            let (parts, body) = response.into_parts();
+                            let mut content =
+                                InboundContent::new(parts, content_type.clone(), body).unwrap();
+                            if let Some(message) =
+                                response_message.filter(|_| protobuf::is_protobuf(&content_type))
+                            {
+                                content.decode_protobuf(message).await;
+                                if let (Some(flows), Some(message)) =
+                                    (&flows, content.protobuf_message())
+                                {
+                                    flows.annotate(&flow.id, PROTOBUF, message.full_name());
+                                }
+                            }
+                            // Sniff before dispatching, so scopes can match `content.sniffed_type()`
+                            // and plugins receive text as UTF-8
+                            content.sniff().await;
+                            let content = Box::new(content) as Box<dyn Event>;
+                            debug!(
+                                "Created InboundContent event with content-type: {}",
+                                content_type
+                            );
+                            let start_handle = std::time::Instant::now();
+                            let (event, mut store) =
+                                dispatch_event(&registry, content, listener.as_deref())
+                                    .await
+                                    .unwrap();
+                            debug!(
+                                "InboundContent event handled in {:?}",
+                                start_handle.elapsed()
+                            );
+
+                            match event {
+                                WasmEvent::InboundContent(content_resource) => {
+                                    let content =
+                                        store.data_mut().table.delete(content_resource).unwrap();
+                                    (content, Some(store))
+                                }
+                                _ => {
+                                    return Ok(pages.failure(
+                                        hooks.plugin_failed(&flow, &WitmError::PluginOutput),
+                                        &flow,
+                                    ));
+                                }
                             }
-                            _ => {
-                                return Response::builder()
-                                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                                    .body(
-                                        Full::new(Bytes::from(
-                                            "Unexpected event data type from plugin",
-                                        ))
-                                        .map_err(|_| {
-                                            ErrorCode::InternalError(Some(
-                                                "conversion error".to_string(),
-                                            ))
+                        }
+                    } else {
+                        unreachable!()
+                    };
+
+                    let content_handling_elapsed = service_fn_start.elapsed()
+                        - request_event_elapsed
+                        - upstream_elapsed
+                        - response_event_elapsed;
+                    debug!(
+                        "🕐 CONTENT_HANDLING completed in {:?}",
+                        content_handling_elapsed
+                    );
+                    debug!("Converting final InboundContent to HTTP response");
+
+                    match content.into_response() {
+                        Ok(response) => {
+                            // If plugins processed content, their WASM subtasks may still
+                            // be streaming body data. Keep the store alive in a background
+                            // run_concurrent context so subtasks can make progress until
+                            // the response body is fully consumed.
+                            if let Some(mut store) = plugin_store {
+                                let (body_done_tx, body_done_rx) =
+                                    tokio::sync::oneshot::channel::<()>();
+                                let (parts, body) = response.into_parts();
+                                let wrapped_body =
+                                    crate::proxy::utils::BodyWithSignal::new(body, body_done_tx);
+                                let response =
+                                    Response::from_parts(parts, wrapped_body.boxed_unsync());
+                                tokio::spawn(async move {
+                                    let _ = store
+                                        .run_concurrent(async move |_| {
+                                            let _ = body_done_rx.await;
                                         })
-                                        .boxed_unsync(),
-                                    );
+                                        .await;
+                                });
+                                Ok(response)
+                            } else {
+                                Ok(response)
                             }
                         }
+                        Err(err) => {
+                            error!("Error getting streaming response: {}", err);
+                            Ok(pages.failure(&WitmError::Response(err.to_string()), &flow))
+                        }
                     }
-                } else {
-                    unreachable!()
                 };
-
-                let content_handling_elapsed = service_fn_start.elapsed()
-                    - request_event_elapsed
-                    - upstream_elapsed
-                    - response_event_elapsed;
-                debug!(
-                    "🕐 CONTENT_HANDLING completed in {:?}",
-                    content_handling_elapsed
-                );
-                debug!("Converting final InboundContent to HTTP response");
-
-                match content.into_response() {
-                    Ok(response) => {
-                        // If plugins processed content, their WASM subtasks may still
-                        // be streaming body data. Keep the store alive in a background
-                        // run_concurrent context so subtasks can make progress until
-                        // the response body is fully consumed.
-                        if let Some(mut store) = plugin_store {
-                            let (body_done_tx, body_done_rx) =
-                                tokio::sync::oneshot::channel::<()>();
-                            let (parts, body) = response.into_parts();
-                            let wrapped_body =
-                                crate::proxy::utils::BodyWithSignal::new(body, body_done_tx);
-                            let response = Response::from_parts(parts, wrapped_body.boxed_unsync());
-                            tokio::spawn(async move {
-                                let _ = store
-                                    .run_concurrent(async move |_| {
-                                        let _ = body_done_rx.await;
-                                    })
-                                    .await;
-                            });
-                            Ok(response)
-                        } else {
-                            Ok(response)
+                let handle = flow_trace::scoped(trace.clone(), handle);
+                let handle = timeline::scoped(timeline.clone(), handle);
+                let handle = storage_snapshots::scoped(storage_snapshot, handle);
+                let handle = tenant::scoped(tenant.clone(), handle);
+                let mut response = match tokio::time::timeout(limits.flow_deadline, handle).await {
+                    Ok(response) => response,
+                    Err(_) => Ok(timeout_pages.failure(
+                        &WitmError::DeadlineExceeded(limits.flow_deadline),
+                        &timeout_flow,
+                    )),
+                };
+                // The policy is the origin's own, before any we add
+                if let (Some(upgrades), Ok(response)) = (&https_upgrades, &response) {
+                    upgrades.observe(&timeout_flow.host, response.headers());
+                }
+                if let (Some(request), Ok(response)) = (&cel_request, &mut response) {
+                    security_headers.apply(request, response);
+                }
+                if let Ok(response) = &mut response {
+                    privacy.strip_response(stripped, response.headers_mut());
+                }
+                if translation.applies(&timeout_flow.host) {
+                    response = match response {
+                        Ok(response) => {
+                            Ok(translation.translate(&timeout_flow.host, response).await)
                         }
+                        Err(e) => Err(e),
+                    };
+                }
+                if let Some(url) = &page_url {
+                    response = response.map(|response| user_scripts.inject(url, response));
+                }
+                if let Some(accept) = &image_accept {
+                    response = match response {
+                        Ok(response) => Ok(images.optimize(accept, response).await),
+                        Err(e) => Err(e),
+                    };
+                }
+                if let (Some(schemas), Some(endpoint)) = (&schemas, &endpoint) {
+                    response =
+                        response.map(|response| schemas.observe_response(endpoint, response));
+                }
+                if let Some(flows) = &timeout_flows {
+                    response = response.map(|response| {
+                        flows.complete(&timeout_flow.id, response.status().as_u16());
+                        if let Some(request) = &cel_request {
+                            flow_tags.apply(flows, &timeout_flow.id, request, &response);
+                        }
+                        if timeout_sessions.is_active() {
+                            timeout_sessions.record(flows, &timeout_flow.id);
+                        }
+                        timeout_hooks.flow_completed(flows, &timeout_flow.id);
+                        sensitive_data.observe_response(flows, &timeout_flow.id, response)
+                    });
+                }
+                if let (Some(trace), Ok(response)) = (&trace, &mut response) {
+                    trace.record("response", response.status().to_string());
+                    if let Ok(id) = HeaderValue::from_str(trace.id()) {
+                        response.headers_mut().insert(TRACE_HEADER, id);
                     }
-                    Err(err) => {
-                        error!("Error getting streaming response: {}", err);
-                        Response::builder()
-                            .status(StatusCode::INTERNAL_SERVER_ERROR)
-                            .body(
-                                http_body_util::Full::new(Bytes::from(format!(
-                                    "Failed to get streaming response: {}",
-                                    err
-                                )))
-                                .map_err(|_| {
-                                    ErrorCode::InternalError(Some("conversion error".to_string()))
-                                })
-                                .boxed_unsync(),
-                            )
-                    }
                 }
+                // Compressed last, so traffic is counted as it's sent
+                if let Some(accept_encoding) = &accept_encoding {
+                    response =
+                        response.map(|response| compression.apply(accept_encoding, response));
+                }
+                if let Some(traffic) = &traffic {
+                    let profile = traffic.profile_for(client);
+                    response = response.map(|response| {
+                        response.map(|body| traffic.counted(profile, &timeout_flow.host, body))
+                    });
+                }
+                // Written to the client once hyper has streamed the body
+                if let Some(timeline) = &timeline {
+                    response = response.map(|response| {
+                        let span =
+                            timeline.open("client", format!("{} response", response.status()));
+                        response.map(|body| utils::BodyHolding::new(body, span).boxed_unsync())
+                    });
+                }
+                response
             }
         })
     };
@@ -0,0 +1,559 @@
+//! Decoding MQTT streams tunneled through the proxy, so plugins holding the
+//! `mqtt_message` capability can log, rewrite or drop the messages devices
+//! and brokers publish to each other.
+//!
+//! Packets are forwarded as they're decoded; a direction which stops
+//! decoding as MQTT is passed through untouched from then on.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::{Result, bail, ensure};
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, info};
+
+use super::listener::ListenerConfig;
+use super::{ProxyResult, dial, dispatch_event, parse_authority_host_port};
+use crate::events::mqtt::MqttMessage;
+use crate::plugins::registry::PluginRegistry;
+use crate::wasm::bindgen::Event as WasmEvent;
+
+/// Largest packet decoded; larger ones switch the stream to passthrough
+pub const MAX_PACKET: usize = 1024 * 1024;
+
+const CONNECT: u8 = 1;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const PUBREC: u8 = 5;
+const PUBREL: u8 = 6;
+const PUBCOMP: u8 = 7;
+
+/// The protocol level of MQTT 3.1.1, assumed when a stream doesn't start
+/// with a CONNECT packet naming one
+const DEFAULT_LEVEL: u8 = 4;
+
+/// The protocol level of MQTT 5, whose packets carry properties
+const LEVEL_5: u8 = 5;
+
+/// Decode a variable byte integer (as used for remaining lengths and MQTT 5
+/// property lengths). Returns the value and the number of bytes it took, or
+/// `None` if `buf` ends before it does.
+fn variable_int(buf: &[u8]) -> Result<Option<(usize, usize)>> {
+    let mut value = 0usize;
+    for (i, byte) in buf.iter().take(4).enumerate() {
+        value |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some((value, i + 1)));
+        }
+    }
+    ensure!(
+        buf.len() < 4,
+        "Variable byte integer is longer than 4 bytes"
+    );
+    Ok(None)
+}
+
+fn put_variable_int(buf: &mut BytesMut, mut value: usize) {
+    loop {
+        let mut byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value > 0 {
+            byte |= 0x80;
+        }
+        buf.put_u8(byte);
+        if value == 0 {
+            break;
+        }
+    }
+}
+
+/// The length of the complete packet at the start of `buf`, or `None` if
+/// more bytes are needed
+pub fn packet_len(buf: &[u8]) -> Result<Option<usize>> {
+    let Some(first) = buf.first() else {
+        return Ok(None);
+    };
+    ensure!(first >> 4 != 0, "Packet type 0 is reserved");
+    let Some((remaining, len_bytes)) = variable_int(&buf[1..])? else {
+        return Ok(None);
+    };
+    ensure!(
+        remaining <= MAX_PACKET,
+        "Packet of {} bytes is larger than {} bytes",
+        remaining,
+        MAX_PACKET
+    );
+    let len = 1 + len_bytes + remaining;
+    Ok((buf.len() >= len).then_some(len))
+}
+
+/// The protocol level a stream starting with `preface` speaks, if it starts
+/// with a CONNECT packet: 3 for MQTT 3.1, 4 for 3.1.1 and 5 for 5.0
+pub fn connect_level(preface: &[u8]) -> Option<u8> {
+    let (&first, rest) = preface.split_first()?;
+    if first != CONNECT << 4 {
+        return None;
+    }
+    let (_, len_bytes) = variable_int(rest).ok()??;
+    let variable_header = &rest[len_bytes..];
+    [&b"\0\x04MQTT"[..], &b"\0\x06MQIsdp"[..]]
+        .iter()
+        .find_map(|name| variable_header.strip_prefix(*name))
+        .and_then(|rest| rest.first().copied())
+}
+
+/// Whether `topic` matches the topic filter `filter`, where `+` matches a
+/// single level and a trailing `#` any number of them. As with brokers,
+/// wildcards at the first level don't match topics starting with `$`.
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    if topic.starts_with('$') && filter.starts_with(['+', '#']) {
+        return false;
+    }
+    let mut topic_levels = topic.split('/');
+    for level in filter.split('/') {
+        match (level, topic_levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (level, Some(topic_level)) if level == topic_level => {}
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
+
+/// A PUBLISH packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Publish {
+    pub dup: bool,
+    pub qos: u8,
+    pub retain: bool,
+    /// Empty when an MQTT 5 client names the topic with a topic alias
+    pub topic: String,
+    /// Present for QoS 1 and 2
+    pub packet_id: Option<u16>,
+    /// MQTT 5 properties, passed on as they are
+    pub properties: Bytes,
+    pub payload: Bytes,
+}
+
+impl Publish {
+    /// Decode the complete PUBLISH packet `packet`, sent on a stream speaking
+    /// protocol `level`
+    pub fn decode(packet: &[u8], level: u8) -> Result<Self> {
+        let first = packet[0];
+        ensure!(first >> 4 == PUBLISH, "Not a PUBLISH packet");
+        let qos = (first >> 1) & 0b11;
+        ensure!(qos < 3, "Invalid QoS 3");
+        let Some((remaining, len_bytes)) = variable_int(&packet[1..])? else {
+            bail!("Truncated packet");
+        };
+        let mut body = &packet[1 + len_bytes..];
+        ensure!(body.len() == remaining, "Truncated packet");
+
+        let topic_len = take(&mut body, 2)?;
+        let topic_len = u16::from_be_bytes([topic_len[0], topic_len[1]]) as usize;
+        let topic = String::from_utf8(take(&mut body, topic_len)?.to_vec())?;
+        let packet_id = if qos > 0 {
+            let id = take(&mut body, 2)?;
+            Some(u16::from_be_bytes([id[0], id[1]]))
+        } else {
+            None
+        };
+        let properties = if level >= LEVEL_5 {
+            let Some((properties_len, len_bytes)) = variable_int(body)? else {
+                bail!("Truncated properties");
+            };
+            take(&mut body, len_bytes)?;
+            Bytes::copy_from_slice(take(&mut body, properties_len)?)
+        } else {
+            Bytes::new()
+        };
+
+        Ok(Self {
+            dup: first & 0b1000 != 0,
+            qos,
+            retain: first & 0b1 != 0,
+            topic,
+            packet_id,
+            properties,
+            payload: Bytes::copy_from_slice(body),
+        })
+    }
+
+    /// Encode the packet for a stream speaking protocol `level`
+    pub fn encode(&self, level: u8) -> Bytes {
+        let mut body = BytesMut::new();
+        body.put_u16(self.topic.len() as u16);
+        body.put_slice(self.topic.as_bytes());
+        if self.qos > 0 {
+            body.put_u16(self.packet_id.unwrap_or_default());
+        }
+        if level >= LEVEL_5 {
+            put_variable_int(&mut body, self.properties.len());
+            body.put_slice(&self.properties);
+        }
+        body.put_slice(&self.payload);
+
+        let mut packet = BytesMut::with_capacity(body.len() + 5);
+        packet.put_u8(
+            (PUBLISH << 4) | ((self.dup as u8) << 3) | ((self.qos & 0b11) << 1) | self.retain as u8,
+        );
+        put_variable_int(&mut packet, body.len());
+        packet.put_slice(&body);
+        packet.freeze()
+    }
+
+    /// The packet acknowledging this one to its sender, for a message the
+    /// proxy drops: PUBACK for QoS 1, PUBREC for QoS 2
+    fn acknowledgement(&self) -> Option<[u8; 4]> {
+        let [hi, lo] = self.packet_id?.to_be_bytes();
+        match self.qos {
+            1 => Some([PUBACK << 4, 2, hi, lo]),
+            2 => Some([PUBREC << 4, 2, hi, lo]),
+            _ => None,
+        }
+    }
+}
+
+/// Packet identifiers of the QoS 2 messages dropped from a sender. The
+/// receiver never saw them, so the sender's PUBREL for each is answered with
+/// PUBCOMP by the proxy rather than forwarded.
+#[derive(Debug, Default)]
+struct DroppedQos2(HashSet<u16>);
+
+impl DroppedQos2 {
+    /// Note whether a message published with `qos` and `packet_id` was
+    /// forwarded or dropped
+    fn record(&mut self, qos: u8, packet_id: Option<u16>, forwarded: bool) {
+        if let (2, Some(id)) = (qos, packet_id) {
+            if forwarded {
+                self.0.remove(&id);
+            } else {
+                self.0.insert(id);
+            }
+        }
+    }
+
+    /// The PUBCOMP completing `pubrel`, if it releases a dropped message
+    fn complete(&mut self, pubrel: &[u8]) -> Option<[u8; 4]> {
+        let (_, header) = variable_int(pubrel.get(1..)?).ok()??;
+        let &[hi, lo] = pubrel.get(1 + header..3 + header)? else {
+            return None;
+        };
+        self.0
+            .remove(&u16::from_be_bytes([hi, lo]))
+            .then_some([PUBCOMP << 4, 2, hi, lo])
+    }
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    ensure!(buf.len() >= len, "Truncated packet");
+    let (taken, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(taken)
+}
+
+/// Where messages on a stream are going, and the plugins they're offered to
+struct Relay {
+    host: String,
+    port: u16,
+    level: u8,
+    registry: Arc<RwLock<PluginRegistry>>,
+    listener: Option<Arc<ListenerConfig>>,
+}
+
+impl Relay {
+    /// Offer a message to plugins, returning the packet to forward in its
+    /// place, or `None` if a plugin dropped it
+    async fn handle(&self, publish: Publish, packet: Bytes, from_client: bool) -> Option<Bytes> {
+        let registry = self.registry.read().await;
+        let message = MqttMessage {
+            host: self.host.clone(),
+            port: self.port,
+            from_client,
+            topic: publish.topic.clone(),
+            payload: publish.payload.to_vec(),
+            qos: publish.qos,
+            retain: publish.retain,
+        };
+        let wanted = match self.listener.as_deref().filter(|l| l.filters_plugins()) {
+            Some(listener) => {
+                let allowed = registry.plugins_matching(|id| listener.allows_plugin(id));
+                registry.can_handle_in_set(&message, &allowed)
+            }
+            None => registry.can_handle(&message),
+        };
+        if !wanted {
+            return Some(packet);
+        }
+
+        match dispatch_event(&registry, Box::new(message), self.listener.as_deref()).await {
+            Ok((WasmEvent::MqttMessage(message), _)) => {
+                let message = MqttMessage::from(message);
+                if message.topic == publish.topic
+                    && message.payload == publish.payload
+                    && message.retain == publish.retain
+                {
+                    return Some(packet);
+                }
+                // The QoS is kept, since the receiver acknowledges by it
+                Some(
+                    Publish {
+                        topic: message.topic,
+                        payload: message.payload.into(),
+                        retain: message.retain,
+                        ..publish
+                    }
+                    .encode(self.level),
+                )
+            }
+            Ok(_) => Some(packet),
+            Err(e) => {
+                info!(
+                    "Dropping MQTT message to {} on {}:{}, rejected by plugins: {}",
+                    publish.topic, self.host, self.port, e
+                );
+                None
+            }
+        }
+    }
+
+    /// Relay packets read from `from` to `to`, offering messages to plugins
+    /// and acknowledging dropped ones on `reply`, along with the PUBREL of
+    /// dropped QoS 2 messages. Falls back to copying bytes as-is once they
+    /// don't decode as MQTT.
+    async fn pump<R, W, A>(
+        &self,
+        mut from: R,
+        to: Arc<Mutex<W>>,
+        reply: Arc<Mutex<A>>,
+        prefix: &[u8],
+        from_client: bool,
+    ) -> ProxyResult<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+        A: AsyncWrite + Unpin,
+    {
+        let mut buf = BytesMut::from(prefix);
+        let mut dropped = DroppedQos2::default();
+        loop {
+            loop {
+                let len = match packet_len(&buf) {
+                    Ok(Some(len)) => len,
+                    Ok(None) => break,
+                    Err(e) => {
+                        debug!(
+                            "Passing MQTT stream to {}:{} through undecoded: {}",
+                            self.host, self.port, e
+                        );
+                        to.lock().await.write_all(&buf).await?;
+                        return passthrough(from, to).await;
+                    }
+                };
+                let packet = buf.split_to(len).freeze();
+                let forwarded = match packet[0] >> 4 {
+                    PUBLISH => match Publish::decode(&packet, self.level) {
+                        Ok(publish) => {
+                            let ack = publish.acknowledgement();
+                            let (qos, packet_id) = (publish.qos, publish.packet_id);
+                            let forwarded = self.handle(publish, packet, from_client).await;
+                            dropped.record(qos, packet_id, forwarded.is_some());
+                            if forwarded.is_none()
+                                && let Some(ack) = ack
+                            {
+                                reply.lock().await.write_all(&ack).await?;
+                            }
+                            forwarded
+                        }
+                        Err(e) => {
+                            debug!("Forwarding undecodable MQTT PUBLISH: {}", e);
+                            Some(packet)
+                        }
+                    },
+                    PUBREL => match dropped.complete(&packet) {
+                        Some(completion) => {
+                            reply.lock().await.write_all(&completion).await?;
+                            None
+                        }
+                        None => Some(packet),
+                    },
+                    _ => Some(packet),
+                };
+                if let Some(packet) = forwarded {
+                    to.lock().await.write_all(&packet).await?;
+                }
+            }
+            if from.read_buf(&mut buf).await? == 0 {
+                to.lock().await.shutdown().await?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Copy bytes from `from` to `to` until `from` closes, without holding the
+/// lock on `to` between writes
+async fn passthrough<R, W>(mut from: R, to: Arc<Mutex<W>>) -> ProxyResult<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; 16 * 1024];
+    loop {
+        let n = from.read(&mut buf).await?;
+        if n == 0 {
+            to.lock().await.shutdown().await?;
+            return Ok(());
+        }
+        to.lock().await.write_all(&buf[..n]).await?;
+    }
+}
+
+/// Forward an MQTT stream to its upstream, offering each message published
+/// in either direction to `mqtt_message` plugins
+pub(crate) async fn relay<IO>(
+    client: IO,
+    preface: &[u8],
+    authority: &str,
+    registry: Arc<RwLock<PluginRegistry>>,
+    listener: Option<Arc<ListenerConfig>>,
+) -> ProxyResult<()>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    let (host, port) = parse_authority_host_port(authority, 443)?;
+    let upstream = dial::connect(&host, port).await?;
+    let relay = Relay {
+        level: connect_level(preface).unwrap_or(DEFAULT_LEVEL),
+        host,
+        port,
+        registry,
+        listener,
+    };
+
+    let (client_read, client_write) = tokio::io::split(client);
+    let (upstream_read, upstream_write) = upstream.into_split();
+    let client_write = Arc::new(Mutex::new(client_write));
+    let upstream_write = Arc::new(Mutex::new(upstream_write));
+    tokio::try_join!(
+        relay.pump(
+            client_read,
+            upstream_write.clone(),
+            client_write.clone(),
+            preface,
+            true
+        ),
+        relay.pump(upstream_read, client_write, upstream_write, &[], false),
+    )?;
+    debug!("MQTT stream to {} finished", authority);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // CONNECT, MQTT 3.1.1, clean session, keep alive 60, client ID "dev1"
+    const CONNECT_311: &[u8] = b"\x10\x10\x00\x04MQTT\x04\x02\x00\x3c\x00\x04dev1";
+
+    #[test]
+    fn connect_packets_are_recognized() {
+        assert_eq!(connect_level(CONNECT_311), Some(4));
+        assert_eq!(
+            connect_level(b"\x10\x12\x00\x06MQIsdp\x03\x02\x00\x3c\x00\x04dev1"),
+            Some(3)
+        );
+        assert_eq!(connect_level(b"GET / HTTP/1.1\r\n"), None);
+        assert_eq!(connect_level(b"\x10\x05\x00\x04XXXX"), None);
+        assert_eq!(packet_len(CONNECT_311).unwrap(), Some(CONNECT_311.len()));
+        assert_eq!(packet_len(&CONNECT_311[..8]).unwrap(), None);
+        assert!(packet_len(b"\x30\xff\xff\xff\xff\x01").is_err());
+    }
+
+    #[test]
+    fn topic_filters_match() {
+        assert!(topic_matches("devices/+/telemetry", "devices/42/telemetry"));
+        assert!(!topic_matches("devices/+/telemetry", "devices/42/status"));
+        assert!(topic_matches("devices/#", "devices/42/telemetry"));
+        assert!(topic_matches("devices/#", "devices"));
+        assert!(topic_matches("#", "devices/42"));
+        assert!(!topic_matches("devices/42", "devices/42/telemetry"));
+        assert!(!topic_matches("#", "$SYS/broker/uptime"));
+        assert!(topic_matches("$SYS/#", "$SYS/broker/uptime"));
+    }
+
+    #[test]
+    fn publish_packets_round_trip() {
+        let publish = Publish {
+            dup: false,
+            qos: 1,
+            retain: true,
+            topic: "devices/42/telemetry".to_string(),
+            packet_id: Some(7),
+            properties: Bytes::new(),
+            payload: Bytes::from_static(br#"{"temp":21.5}"#),
+        };
+        let packet = publish.encode(4);
+        assert_eq!(packet[0], 0x33);
+        assert_eq!(packet_len(&packet).unwrap(), Some(packet.len()));
+        assert_eq!(Publish::decode(&packet, 4).unwrap(), publish);
+        assert_eq!(publish.acknowledgement(), Some([0x40, 2, 0, 7]));
+
+        // MQTT 5: a payload format indicator property precedes the payload
+        let publish = Publish {
+            qos: 0,
+            packet_id: None,
+            properties: Bytes::from_static(b"\x01\x01"),
+            ..publish
+        };
+        let packet = publish.encode(5);
+        assert_eq!(Publish::decode(&packet, 5).unwrap(), publish);
+        assert_eq!(publish.acknowledgement(), None);
+
+        // A payload over 127 bytes takes a two-byte remaining length
+        let publish = Publish {
+            payload: Bytes::from(vec![b'x'; 300]),
+            ..publish
+        };
+        let packet = publish.encode(4);
+        assert_eq!(&packet[1..3], &[0xc2, 0x02]);
+        assert_eq!(Publish::decode(&packet, 4).unwrap().payload.len(), 300);
+        assert!(Publish::decode(&packet[..packet.len() - 1], 4).is_err());
+    }
+
+    #[test]
+    fn dropped_qos2_messages_are_completed_locally() {
+        let publish = Publish {
+            dup: false,
+            qos: 2,
+            retain: false,
+            topic: "devices/42/telemetry".to_string(),
+            packet_id: Some(7),
+            properties: Bytes::new(),
+            payload: Bytes::from_static(b"21.5"),
+        };
+        assert_eq!(publish.acknowledgement(), Some([0x50, 2, 0, 7]));
+
+        let mut dropped = DroppedQos2::default();
+        dropped.record(publish.qos, publish.packet_id, false);
+        dropped.record(2, Some(8), true);
+        // PUBREL for a message the broker received is forwarded
+        assert_eq!(dropped.complete(b"\x62\x02\x00\x08"), None);
+        // MQTT 5 adds a reason code, and properties, after the packet ID
+        assert_eq!(
+            dropped.complete(b"\x62\x03\x00\x07\x00"),
+            Some([0x70, 2, 0, 7])
+        );
+        // Only once
+        assert_eq!(dropped.complete(b"\x62\x02\x00\x07"), None);
+
+        // A retry the plugins let through is released by the broker
+        dropped.record(2, Some(9), false);
+        dropped.record(2, Some(9), true);
+        assert_eq!(dropped.complete(b"\x62\x02\x00\x09"), None);
+        assert_eq!(dropped.complete(b"\x62"), None);
+    }
+}
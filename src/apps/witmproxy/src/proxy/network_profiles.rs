@@ -0,0 +1,373 @@
+//! Simulated network conditions, so a device can be tried on a slow or
+//! unreliable network without changing the network it's on.
+//!
+//! A named profile adds latency before requests go upstream, limits the rate
+//! response bodies are delivered at, and resets a share of connections
+//! partway through their response. Profiles are applied to flows by rules
+//! matching their host and/or client, configured as
+//! `[[proxy.network_conditions]]` tables or replaced at runtime through
+//! `/api/manage/network-conditions`:
+//!
+//! ```toml
+//! [[proxy.network_conditions]]
+//! host = "*.example.com"
+//! profile = "3g"
+//!
+//! [[proxy.network_conditions]]
+//! client = "192.168.1.23"
+//! profile = "flaky-wifi"
+//! ```
+//!
+//! The first matching rule applies. Besides the built-in profiles
+//! ([builtin_profiles]), others can be defined as `[[proxy.network_profiles]]`
+//! tables, replacing built-in ones of the same name.
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::BodyExt;
+use http_body_util::combinators::UnsyncBoxBody;
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use tokio::time::Sleep;
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+
+use crate::proxy::host_limits::matches_host;
+
+/// Bytes a response is assumed to have, to pick where a reset cuts it,
+/// when its length isn't known
+const UNKNOWN_BODY_BYTES: u64 = 64 * 1024;
+
+/// Network conditions to simulate
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct NetworkProfile {
+    /// Name rules refer to the profile by, ex: "3g"
+    pub name: String,
+    /// Milliseconds added before each request is sent upstream
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// Up to this many milliseconds added to the latency at random
+    #[serde(default)]
+    pub jitter_ms: u64,
+    /// Kilobits per second response bodies are delivered at (default:
+    /// unlimited)
+    #[serde(default)]
+    pub bandwidth_kbps: Option<u64>,
+    /// Share of responses whose connection is reset partway through the
+    /// body, from 0 to 1
+    #[serde(default)]
+    pub reset_rate: f64,
+}
+
+/// Profiles available without configuration: "3g", "flaky-wifi" and
+/// "satellite"
+pub fn builtin_profiles() -> Vec<NetworkProfile> {
+    vec![
+        NetworkProfile {
+            name: "3g".to_string(),
+            latency_ms: 300,
+            jitter_ms: 100,
+            bandwidth_kbps: Some(750),
+            reset_rate: 0.0,
+        },
+        NetworkProfile {
+            name: "flaky-wifi".to_string(),
+            latency_ms: 30,
+            jitter_ms: 250,
+            bandwidth_kbps: Some(5_000),
+            reset_rate: 0.05,
+        },
+        NetworkProfile {
+            name: "satellite".to_string(),
+            latency_ms: 600,
+            jitter_ms: 50,
+            bandwidth_kbps: Some(10_000),
+            reset_rate: 0.0,
+        },
+    ]
+}
+
+impl NetworkProfile {
+    /// The latency to add to a request, jitter included
+    pub fn delay(&self) -> Duration {
+        let jitter = (self.jitter_ms as f64 * random_fraction()) as u64;
+        Duration::from_millis(self.latency_ms + jitter)
+    }
+
+    /// `body`, delivered at the profile's bandwidth and possibly reset
+    pub fn shape(&self, body: UnsyncBoxBody<Bytes, ErrorCode>) -> UnsyncBoxBody<Bytes, ErrorCode> {
+        let reset_after = (random_fraction() < self.reset_rate).then(|| {
+            let size = body.size_hint();
+            let size = size.upper().unwrap_or(size.lower().max(UNKNOWN_BODY_BYTES));
+            (size as f64 * random_fraction()) as u64
+        });
+        if self.bandwidth_kbps.is_none() && reset_after.is_none() {
+            return body;
+        }
+        ShapedBody {
+            inner: body,
+            bytes_per_sec: self.bandwidth_kbps.map(|kbps| (kbps * 1000 / 8).max(1)),
+            reset_after,
+            sent: 0,
+            pause: None,
+        }
+        .boxed_unsync()
+    }
+}
+
+/// A random number in [0, 1)
+fn random_fraction() -> f64 {
+    let (random, _) = uuid::Uuid::new_v4().as_u64_pair();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Applies a profile to the flows matching a host and/or client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct NetworkConditionRule {
+    /// Host name, or `*.` followed by a domain to match its subdomains
+    /// (default: every host)
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Client address (default: every client)
+    #[serde(default)]
+    #[salvo(schema(value_type = Option<String>))]
+    pub client: Option<IpAddr>,
+    /// Name of the profile to apply
+    pub profile: String,
+}
+
+impl NetworkConditionRule {
+    fn matches(&self, host: &str, client: IpAddr) -> bool {
+        self.host
+            .as_ref()
+            .is_none_or(|pattern| matches_host(pattern, host))
+            && self
+                .client
+                .is_none_or(|ip| ip.to_canonical() == client.to_canonical())
+    }
+}
+
+/// Profiles defined besides the built-in ones, and the rules applying them
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct NetworkConditionsConfig {
+    #[serde(default)]
+    pub profiles: Vec<NetworkProfile>,
+    #[serde(default)]
+    pub rules: Vec<NetworkConditionRule>,
+}
+
+/// The live network condition rules. Cheap to clone; all clones share the
+/// same rules.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkConditions {
+    config: Arc<RwLock<Arc<NetworkConditionsConfig>>>,
+}
+
+impl NetworkConditions {
+    pub fn new(config: NetworkConditionsConfig) -> Result<Self> {
+        let conditions = Self::default();
+        conditions.set(config)?;
+        Ok(conditions)
+    }
+
+    /// Replace the profiles and rules, leaving the current ones in place if
+    /// any is invalid
+    pub fn set(&self, config: NetworkConditionsConfig) -> Result<()> {
+        let mut names = HashSet::new();
+        for profile in &config.profiles {
+            if !names.insert(profile.name.as_str()) {
+                bail!("Network profile {} is defined twice", profile.name);
+            }
+            if !(0.0..=1.0).contains(&profile.reset_rate) {
+                bail!(
+                    "Reset rate of network profile {} must be from 0 to 1",
+                    profile.name
+                );
+            }
+        }
+        let builtin = builtin_profiles();
+        for rule in &config.rules {
+            if !names.contains(rule.profile.as_str())
+                && !builtin.iter().any(|p| p.name == rule.profile)
+            {
+                bail!("Unknown network profile: {}", rule.profile);
+            }
+        }
+        *self.config.write().unwrap() = Arc::new(config);
+        Ok(())
+    }
+
+    pub fn config(&self) -> NetworkConditionsConfig {
+        (**self.config.read().unwrap()).clone()
+    }
+
+    /// The profile to apply to a flow from `client` to `host`, if any
+    pub fn profile_for(&self, host: &str, client: IpAddr) -> Option<NetworkProfile> {
+        let config = self.config.read().unwrap().clone();
+        let rule = config
+            .rules
+            .iter()
+            .find(|rule| rule.matches(host, client))?;
+        config
+            .profiles
+            .iter()
+            .find(|p| p.name == rule.profile)
+            .cloned()
+            .or_else(|| {
+                builtin_profiles()
+                    .into_iter()
+                    .find(|p| p.name == rule.profile)
+            })
+    }
+}
+
+/// A body delivered at a limited rate, and cut off by a simulated reset
+struct ShapedBody {
+    inner: UnsyncBoxBody<Bytes, ErrorCode>,
+    bytes_per_sec: Option<u64>,
+    /// Bytes sent before the connection is reset
+    reset_after: Option<u64>,
+    sent: u64,
+    /// Time left to wait before the next frame, to keep to the rate
+    pause: Option<Pin<Box<Sleep>>>,
+}
+
+impl Body for ShapedBody {
+    type Data = Bytes;
+    type Error = ErrorCode;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        if let Some(pause) = self.pause.as_mut() {
+            if pause.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.pause = None;
+        }
+        if self.reset_after.is_some_and(|after| self.sent >= after) {
+            return Poll::Ready(Some(Err(ErrorCode::ConnectionTerminated)));
+        }
+
+        let frame = match Pin::new(&mut self.inner).poll_frame(cx) {
+            Poll::Ready(Some(Ok(frame))) => frame,
+            other => return other,
+        };
+        let Some(data) = frame.data_ref() else {
+            return Poll::Ready(Some(Ok(frame)));
+        };
+        let data = match self.reset_after {
+            // Send what gets through before the reset
+            Some(after) if self.sent + data.len() as u64 > after => {
+                data.slice(..(after - self.sent) as usize)
+            }
+            _ => data.clone(),
+        };
+        self.sent += data.len() as u64;
+        if let Some(rate) = self.bytes_per_sec {
+            let wait = Duration::from_secs_f64(data.len() as f64 / rate as f64);
+            self.pause = Some(Box::pin(tokio::time::sleep(wait)));
+        }
+        Poll::Ready(Some(Ok(Frame::data(data))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.pause.is_none() && self.reset_after.is_none() && self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::Full;
+
+    fn rule(host: Option<&str>, client: Option<&str>, profile: &str) -> NetworkConditionRule {
+        NetworkConditionRule {
+            host: host.map(str::to_string),
+            client: client.map(|ip| ip.parse().unwrap()),
+            profile: profile.to_string(),
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_picks_the_profile() {
+        let conditions = NetworkConditions::new(NetworkConditionsConfig {
+            profiles: vec![NetworkProfile {
+                name: "3g".to_string(),
+                latency_ms: 1,
+                jitter_ms: 0,
+                bandwidth_kbps: None,
+                reset_rate: 0.0,
+            }],
+            rules: vec![
+                rule(None, Some("10.0.0.2"), "satellite"),
+                rule(Some("*.example.com"), None, "3g"),
+            ],
+        })
+        .unwrap();
+
+        let client: IpAddr = "10.0.0.3".parse().unwrap();
+        let profile = conditions.profile_for("api.example.com", client).unwrap();
+        assert_eq!((profile.name.as_str(), profile.latency_ms), ("3g", 1));
+        assert!(conditions.profile_for("example.org", client).is_none());
+
+        let mapped: IpAddr = "::ffff:10.0.0.2".parse().unwrap();
+        let profile = conditions.profile_for("api.example.com", mapped).unwrap();
+        assert_eq!(profile.name, "satellite");
+    }
+
+    #[test]
+    fn invalid_conditions_are_rejected() {
+        let conditions = NetworkConditions::default();
+        let unknown = NetworkConditionsConfig {
+            profiles: Vec::new(),
+            rules: vec![rule(None, None, "dial-up")],
+        };
+        assert!(conditions.set(unknown).is_err());
+
+        let mut profile = builtin_profiles().remove(0);
+        profile.reset_rate = 2.0;
+        let out_of_range = NetworkConditionsConfig {
+            profiles: vec![profile],
+            rules: Vec::new(),
+        };
+        assert!(conditions.set(out_of_range).is_err());
+    }
+
+    #[tokio::test]
+    async fn reset_bodies_are_cut_off() {
+        let profile = NetworkProfile {
+            name: "lossy".to_string(),
+            latency_ms: 0,
+            jitter_ms: 0,
+            bandwidth_kbps: None,
+            reset_rate: 1.0,
+        };
+        let body = Full::new(Bytes::from(vec![0u8; 1024]))
+            .map_err(|e| match e {})
+            .boxed_unsync();
+        let mut body = profile.shape(body);
+        let mut received = 0;
+        let error = loop {
+            match body.frame().await {
+                Some(Ok(frame)) => received += frame.into_data().unwrap().len(),
+                Some(Err(e)) => break e,
+                None => panic!("Body ended without a reset"),
+            }
+        };
+        assert!(received < 1024);
+        assert!(matches!(error, ErrorCode::ConnectionTerminated));
+    }
+}
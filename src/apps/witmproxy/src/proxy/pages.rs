@@ -0,0 +1,301 @@
+//! Pages served in place of an upstream response, when a plugin blocks a
+//! request or the proxy fails to complete it.
+//!
+//! The built-in pages are askama templates (`web-ui/templates/error.html`
+//! and `block.html`). Either can be replaced by a file of the same name in
+//! `proxy.error_template_dir`, in which `{{ name }}` placeholders are
+//! substituted with HTML-escaped values:
+//!
+//! - `error.html`: `status`, `reason`, `title`, `message`, `code`, `host`,
+//!   `flow_id`
+//! - `block.html`: `reason`, `plugin`, `host`, `flow_id`
+
+use anyhow::Context;
+use askama::Template;
+use bytes::Bytes;
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, Full};
+use hyper::{Response, StatusCode, header};
+use std::path::Path;
+use std::sync::Arc;
+use tracing::{info, warn};
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+
+use crate::config::ProxyConfig;
+use crate::error::{ERROR_CODE_HEADER, WitmError};
+use crate::web::templates::{BlockPageTemplate, ErrorPageTemplate};
+
+pub type ErrorResponse = Response<UnsyncBoxBody<Bytes, ErrorCode>>;
+
+/// Response header carrying the flow ID shown on error and block pages
+pub const FLOW_ID_HEADER: &str = "x-witmproxy-flow-id";
+
+/// The flow a page is served for
+#[derive(Debug, Clone)]
+pub struct FlowInfo {
+    pub id: String,
+    pub host: String,
+}
+
+impl FlowInfo {
+    pub fn new(host: impl Into<String>) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            host: host.into(),
+        }
+    }
+}
+
+/// Renders error and block pages, using user templates where provided
+#[derive(Debug, Clone, Default)]
+pub struct ErrorPages {
+    error_template: Option<Arc<str>>,
+    block_template: Option<Arc<str>>,
+}
+
+impl ErrorPages {
+    /// The pages configured by `proxy.error_template_dir`
+    pub fn from_config(config: &ProxyConfig) -> anyhow::Result<Self> {
+        match &config.error_template_dir {
+            Some(dir) => Self::load(dir),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Load `error.html` and `block.html` overrides from `dir`. Missing files
+    /// fall back to the built-in pages.
+    pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        let read = |name: &str| -> anyhow::Result<Option<Arc<str>>> {
+            let path = dir.join(name);
+            if !path.exists() {
+                return Ok(None);
+            }
+            let template = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read template {}", path.display()))?;
+            info!("Using custom page template {}", path.display());
+            Ok(Some(template.into()))
+        };
+        Ok(Self {
+            error_template: read("error.html")?,
+            block_template: read("block.html")?,
+        })
+    }
+
+    /// Render an error page, falling back to plain text
+    pub fn error(
+        &self,
+        status: StatusCode,
+        title: &str,
+        message: &str,
+        flow: &FlowInfo,
+    ) -> ErrorResponse {
+        self.render_error(status, title, message, "", flow)
+    }
+
+    fn render_error(
+        &self,
+        status: StatusCode,
+        title: &str,
+        message: &str,
+        code: &str,
+        flow: &FlowInfo,
+    ) -> ErrorResponse {
+        let page = ErrorPageTemplate::new(status, title, message, code, &flow.host, &flow.id);
+        let html = match &self.error_template {
+            Some(template) => Ok(substitute(
+                template,
+                &[
+                    ("status", &page.status.to_string()),
+                    ("reason", &page.reason),
+                    ("title", &page.title),
+                    ("message", &page.message),
+                    ("code", &page.code),
+                    ("host", &page.host),
+                    ("flow_id", &page.flow_id),
+                ],
+            )),
+            None => page.render(),
+        };
+        page_response(status, html, flow, || format!("{}: {}", title, message))
+    }
+
+    /// Render the page served when a plugin blocks a request
+    pub fn blocked(&self, reason: &str, plugin: &str, flow: &FlowInfo) -> ErrorResponse {
+        let page = BlockPageTemplate::new(reason, plugin, &flow.host, &flow.id);
+        let html = match &self.block_template {
+            Some(template) => Ok(substitute(
+                template,
+                &[
+                    ("reason", &page.reason),
+                    ("plugin", &page.plugin),
+                    ("host", &page.host),
+                    ("flow_id", &page.flow_id),
+                ],
+            )),
+            None => page.render(),
+        };
+        page_response(StatusCode::FORBIDDEN, html, flow, || {
+            format!("Blocked by {}: {}", plugin, reason)
+        })
+    }
+
+    /// Page for a request the proxy couldn't complete: a block page if a
+    /// plugin blocked it, otherwise an error page showing the error's code.
+    /// The code is also logged, counted and sent in [ERROR_CODE_HEADER].
+    pub fn failure(&self, err: &WitmError, flow: &FlowInfo) -> ErrorResponse {
+        let code = err.code();
+        info!(
+            "Flow {} to {} failed ({}): {}",
+            flow.id, flow.host, code, err
+        );
+        crate::telemetry::otel::record_error(code);
+
+        let mut response = match err {
+            WitmError::PluginBlocked { plugin_id } => {
+                self.blocked(&err.to_string(), plugin_id, flow)
+            }
+            _ => self.render_error(err.status(), err.title(), &err.to_string(), code, flow),
+        };
+        response
+            .headers_mut()
+            .insert(ERROR_CODE_HEADER, header::HeaderValue::from_static(code));
+        response
+    }
+}
+
+fn page_response(
+    status: StatusCode,
+    html: askama::Result<String>,
+    flow: &FlowInfo,
+    plain: impl FnOnce() -> String,
+) -> ErrorResponse {
+    let (content_type, body) = match html {
+        Ok(html) => ("text/html; charset=utf-8", html),
+        Err(e) => {
+            warn!("Failed to render page for flow {}: {}", flow.id, e);
+            ("text/plain; charset=utf-8", plain())
+        }
+    };
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CACHE_CONTROL, "no-store")
+        .header(FLOW_ID_HEADER, flow.id.as_str())
+        .body(
+            Full::new(Bytes::from(body))
+                .map_err(|_| ErrorCode::InternalError(Some("conversion error".to_string())))
+                .boxed_unsync(),
+        )
+        .expect("Could not construct error Response")
+}
+
+/// Replace `{{ name }}` (or `{{name}}`) placeholders with escaped values
+fn substitute(template: &str, values: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+        let name = after[..end].trim();
+        match values.iter().find(|(key, _)| *key == name) {
+            Some((_, value)) => out.push_str(&escape_html(value)),
+            // Unknown placeholders are left as written
+            None => out.push_str(&rest[start..start + 2 + end + 2]),
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn body_text(resp: ErrorResponse) -> String {
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8_lossy(&body).into_owned()
+    }
+
+    #[tokio::test]
+    async fn error_pages_are_html() {
+        let flow = FlowInfo::new("example.com");
+        let resp = ErrorPages::default().error(
+            StatusCode::BAD_GATEWAY,
+            "Could not reach upstream",
+            "connection refused",
+            &flow,
+        );
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        assert!(
+            resp.headers()[header::CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .starts_with("text/html")
+        );
+        assert_eq!(resp.headers()[FLOW_ID_HEADER], flow.id.as_str());
+        let body = body_text(resp).await;
+        assert!(body.contains("502 Bad Gateway"));
+        assert!(body.contains("example.com"));
+        assert!(body.contains(&flow.id));
+    }
+
+    #[tokio::test]
+    async fn plugin_blocks_render_block_page() {
+        let flow = FlowInfo::new("example.com");
+        let err = anyhow::Error::new(crate::plugins::registry::PluginBlocked {
+            plugin_id: "ops/blocker".to_string(),
+        });
+        let resp = ErrorPages::default().failure(&WitmError::from_plugin(&err), &flow);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(resp.headers()[ERROR_CODE_HEADER], "plugin.blocked");
+        assert!(body_text(resp).await.contains("ops/blocker"));
+
+        let resp =
+            ErrorPages::default().failure(&WitmError::from_plugin(&anyhow::anyhow!("trap")), &flow);
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(resp.headers()[ERROR_CODE_HEADER], "plugin.failed");
+        assert!(body_text(resp).await.contains("plugin.failed"));
+    }
+
+    #[tokio::test]
+    async fn user_templates_override_builtin_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("block.html"),
+            "<p>{{ plugin }} blocked {{host}} ({{ flow_id }}) {{ unknown }}</p>",
+        )
+        .unwrap();
+        let pages = ErrorPages::load(dir.path()).unwrap();
+        assert!(pages.error_template.is_none());
+
+        let flow = FlowInfo::new("<b>example.com</b>");
+        let body = body_text(pages.blocked("no", "ops/blocker", &flow)).await;
+        assert_eq!(
+            body,
+            format!(
+                "<p>ops/blocker blocked &lt;b&gt;example.com&lt;/b&gt; ({}) {{{{ unknown }}}}</p>",
+                flow.id
+            )
+        );
+    }
+}
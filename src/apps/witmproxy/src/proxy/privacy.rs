@@ -0,0 +1,471 @@
+//! Stripping of tracking query parameters and third-party cookies.
+//!
+//! With `proxy.strip_tracking_params` enabled, parameters such as `utm_*`,
+//! `fbclid` and `gclid` are removed from request URLs before plugins or the
+//! origin see them. The built-in list is extended by `proxy.tracking_params`
+//! and replaced daily by the one at `proxy.tracking_params_url`, if set.
+//!
+//! With `proxy.strip_third_party_cookies` enabled, cross-site requests have
+//! their `Cookie` header removed, and their responses their `Set-Cookie`
+//! headers. A request is cross-site when its `Sec-Fetch-Site` says so, or,
+//! from clients not sending that, when its `Origin` or `Referer` is on
+//! another site. Sites are told apart by their last two labels, or three
+//! under country domains' generic second levels such as `co.uk`, rather
+//! than by the public suffix list.
+//!
+//! Both can be turned on or off for some hosts with `[[proxy.privacy_overrides]]`.
+//! Removals are counted in the `witmproxy.privacy_removals` metric.
+
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use hyper::header::{COOKIE, HeaderMap, ORIGIN, REFERER, SET_COOKIE};
+use hyper::{Request, Uri};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::config::ProxyConfig;
+use crate::proxy::flow_trace;
+use crate::proxy::host_limits::matches_host;
+
+/// How often the parameter list is fetched from `proxy.tracking_params_url`
+pub const PARAMS_REFRESH_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Parameters stripped by default, where a trailing `*` matches any suffix
+pub const DEFAULT_TRACKING_PARAMS: &[&str] = &[
+    "utm_*",
+    "fbclid",
+    "gclid",
+    "gclsrc",
+    "dclid",
+    "gbraid",
+    "wbraid",
+    "msclkid",
+    "yclid",
+    "twclid",
+    "ttclid",
+    "igshid",
+    "li_fat_id",
+    "mc_cid",
+    "mc_eid",
+    "_hsenc",
+    "_hsmi",
+    "mkt_tok",
+    "oly_anon_id",
+    "oly_enc_id",
+    "vero_id",
+    "_openstat",
+];
+
+/// Privacy settings for the hosts matching `host`, in place of the global ones
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct PrivacyOverride {
+    /// Host name, or `*.` followed by a domain to match its subdomains
+    pub host: String,
+    /// Whether to strip tracking parameters from requests to these hosts
+    #[serde(default)]
+    pub strip_tracking_params: Option<bool>,
+    /// Whether to strip cookies from cross-site requests to these hosts
+    #[serde(default)]
+    pub strip_third_party_cookies: Option<bool>,
+}
+
+/// The tracking parameters to strip
+#[derive(Debug, Default)]
+struct TrackingParams {
+    names: HashSet<String>,
+    prefixes: Vec<String>,
+}
+
+impl TrackingParams {
+    fn new<'a>(params: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut list = Self::default();
+        for param in params {
+            let param = param.trim().to_ascii_lowercase();
+            match param.strip_suffix('*') {
+                Some(prefix) if !prefix.is_empty() => list.prefixes.push(prefix.to_string()),
+                Some(_) => {}
+                None if !param.is_empty() => {
+                    list.names.insert(param);
+                }
+                None => {}
+            }
+        }
+        list
+    }
+
+    fn len(&self) -> usize {
+        self.names.len() + self.prefixes.len()
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        self.names.contains(&name) || self.prefixes.iter().any(|p| name.starts_with(p.as_str()))
+    }
+}
+
+/// Parse a parameter list fetched from `proxy.tracking_params_url`: one
+/// parameter per line, with `#` comments
+pub fn parse_params(list: &str) -> Result<Vec<String>> {
+    let params: Vec<_> = list
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+    if params.is_empty() {
+        bail!("Tracking parameter list is empty");
+    }
+    Ok(params)
+}
+
+/// What [Privacy::strip_request] removed, for the response to follow
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stripped {
+    /// Whether the request was cross-site with cookie stripping on, so
+    /// cookies the response sets should be dropped too
+    pub third_party: bool,
+}
+
+/// Tracking parameter and third-party cookie stripping. Cheap to clone; all
+/// clones share the parameter list.
+#[derive(Clone)]
+pub struct Privacy {
+    strip_tracking_params: bool,
+    strip_third_party_cookies: bool,
+    overrides: Arc<Vec<PrivacyOverride>>,
+    /// Parameters configured besides those in the list
+    extra_params: Arc<Vec<String>>,
+    params: Arc<RwLock<Arc<TrackingParams>>>,
+    client: reqwest::Client,
+}
+
+impl std::fmt::Debug for Privacy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Privacy")
+            .field("strip_tracking_params", &self.strip_tracking_params)
+            .field("strip_third_party_cookies", &self.strip_third_party_cookies)
+            .field("overrides", &self.overrides)
+            .field("params", &self.params.read().unwrap().len())
+            .finish()
+    }
+}
+
+impl Default for Privacy {
+    fn default() -> Self {
+        Self::new(false, false, Vec::new(), Vec::new())
+    }
+}
+
+impl From<&ProxyConfig> for Privacy {
+    fn from(config: &ProxyConfig) -> Self {
+        Self::new(
+            config.strip_tracking_params,
+            config.strip_third_party_cookies,
+            config.tracking_params.clone(),
+            config.privacy_overrides.clone(),
+        )
+    }
+}
+
+impl Privacy {
+    pub fn new(
+        strip_tracking_params: bool,
+        strip_third_party_cookies: bool,
+        extra_params: Vec<String>,
+        overrides: Vec<PrivacyOverride>,
+    ) -> Self {
+        let privacy = Self {
+            strip_tracking_params,
+            strip_third_party_cookies,
+            overrides: Arc::new(overrides),
+            extra_params: Arc::new(extra_params),
+            params: Arc::default(),
+            client: reqwest::Client::default(),
+        };
+        privacy.set_params(
+            DEFAULT_TRACKING_PARAMS
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+        );
+        privacy
+    }
+
+    /// Whether anything may be stripped from some host's flows
+    pub fn is_enabled(&self) -> bool {
+        self.strip_tracking_params
+            || self.strip_third_party_cookies
+            || self.overrides.iter().any(|o| {
+                o.strip_tracking_params == Some(true) || o.strip_third_party_cookies == Some(true)
+            })
+    }
+
+    /// Replace the parameter list, keeping those configured besides it
+    pub fn set_params(&self, params: Vec<String>) {
+        let params = TrackingParams::new(
+            params
+                .iter()
+                .chain(self.extra_params.iter())
+                .map(String::as_str),
+        );
+        *self.params.write().unwrap() = Arc::new(params);
+    }
+
+    /// Fetch the parameter list from `url`, replacing the current one.
+    /// Returns the number of parameters on it.
+    pub async fn refresh_params(&self, url: &str) -> Result<usize> {
+        let list = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let params = parse_params(&list)?;
+        let count = params.len();
+        self.set_params(params);
+        Ok(count)
+    }
+
+    /// Refresh the parameter list from `url` every `interval`, keeping the
+    /// list we have while it's unavailable, until the task is dropped
+    pub async fn refresh_loop(self, url: String, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match self.refresh_params(&url).await {
+                Ok(count) => debug!("Refreshed tracking parameter list: {} parameters", count),
+                Err(e) => warn!("Failed to refresh tracking parameters from {}: {}", url, e),
+            }
+        }
+    }
+
+    /// Whether tracking parameters and third-party cookies are stripped
+    /// from the flows of `host`
+    fn policy(&self, host: &str) -> (bool, bool) {
+        match self.overrides.iter().find(|o| matches_host(&o.host, host)) {
+            Some(o) => (
+                o.strip_tracking_params
+                    .unwrap_or(self.strip_tracking_params),
+                o.strip_third_party_cookies
+                    .unwrap_or(self.strip_third_party_cookies),
+            ),
+            None => (self.strip_tracking_params, self.strip_third_party_cookies),
+        }
+    }
+
+    /// Strip tracking parameters and, for cross-site requests, cookies from
+    /// a request to `host`
+    pub fn strip_request<B>(&self, req: &mut Request<B>, host: &str) -> Stripped {
+        let (params, cookies) = self.policy(host);
+        if params && let Some(uri) = self.strip_params(req.uri()) {
+            *req.uri_mut() = uri;
+        }
+        let third_party = cookies && is_cross_site(req.headers(), host);
+        if third_party && let Some(cookie) = req.headers_mut().remove(COOKIE) {
+            let count = cookie
+                .to_str()
+                .map_or(1, |c| c.split(';').filter(|c| !c.trim().is_empty()).count());
+            crate::telemetry::otel::record_privacy_removal("cookie", count as u64);
+            flow_trace::record("privacy", || {
+                format!("removed {} third-party cookies", count)
+            });
+        }
+        Stripped { third_party }
+    }
+
+    /// Drop the cookies a response to a cross-site request sets
+    pub fn strip_response(&self, stripped: Stripped, headers: &mut HeaderMap) {
+        if !stripped.third_party {
+            return;
+        }
+        let count = headers.get_all(SET_COOKIE).iter().count();
+        if count > 0 {
+            headers.remove(SET_COOKIE);
+            crate::telemetry::otel::record_privacy_removal("set_cookie", count as u64);
+            flow_trace::record("privacy", || {
+                format!("dropped {} third-party Set-Cookie headers", count)
+            });
+        }
+    }
+
+    /// `uri` without its tracking parameters, if it had any
+    fn strip_params(&self, uri: &Uri) -> Option<Uri> {
+        let query = uri.query()?;
+        let params = self.params.read().unwrap().clone();
+        let kept: Vec<_> = query
+            .split('&')
+            .filter(|pair| {
+                let name = pair.split('=').next().unwrap_or_default();
+                !params.matches(name)
+            })
+            .collect();
+        let removed = query.split('&').count() - kept.len();
+        if removed == 0 {
+            return None;
+        }
+
+        let path_and_query = if kept.is_empty() {
+            uri.path().to_string()
+        } else {
+            format!("{}?{}", uri.path(), kept.join("&"))
+        };
+        let mut parts = uri.clone().into_parts();
+        parts.path_and_query = Some(path_and_query.parse().ok()?);
+        let stripped = Uri::from_parts(parts).ok()?;
+        crate::telemetry::otel::record_privacy_removal("tracking_param", removed as u64);
+        flow_trace::record("privacy", || {
+            format!("removed {} tracking parameters", removed)
+        });
+        Some(stripped)
+    }
+}
+
+/// Whether a request to `host` with `headers` comes from a page on another
+/// site
+fn is_cross_site(headers: &HeaderMap, host: &str) -> bool {
+    if let Some(site) = headers.get("sec-fetch-site") {
+        return site.as_bytes().eq_ignore_ascii_case(b"cross-site");
+    }
+    let initiator = [ORIGIN, REFERER]
+        .into_iter()
+        .filter_map(|name| headers.get(name)?.to_str().ok())
+        .find_map(|url| {
+            reqwest::Url::parse(url)
+                .ok()?
+                .host_str()
+                .map(str::to_string)
+        });
+    match initiator {
+        Some(initiator) => site(&initiator) != site(host),
+        // Navigations typed or bookmarked have no initiator
+        None => false,
+    }
+}
+
+/// Second-level labels country domains register names under, as in `co.uk`
+const GENERIC_SECOND_LEVELS: &[&str] = &[
+    "ac", "co", "com", "edu", "go", "gob", "gov", "ltd", "ne", "net", "or", "org", "plc",
+];
+
+/// The registrable domain `host` is under, approximately
+fn site(host: &str) -> String {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    if host.parse::<std::net::IpAddr>().is_ok() {
+        return host;
+    }
+    let labels: Vec<_> = host.split('.').collect();
+    let keep = match labels.as_slice() {
+        // ex: example.co.uk, example.com.au
+        [.., second, tld]
+            if tld.len() == 2 && GENERIC_SECOND_LEVELS.contains(second) && labels.len() > 2 =>
+        {
+            3
+        }
+        _ => 2,
+    };
+    labels[labels.len().saturating_sub(keep)..].join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn request(uri: &str, headers: &[(&str, &str)]) -> Request<()> {
+        let mut builder = Request::builder().uri(uri);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn tracking_params_are_stripped() {
+        let privacy = Privacy::new(true, false, vec!["ref_id".to_string()], Vec::new());
+        let mut req = request(
+            "https://example.com/a?id=1&utm_source=x&UTM_Medium=y&fbclid=z&ref_id=2",
+            &[],
+        );
+        privacy.strip_request(&mut req, "example.com");
+        assert_eq!(req.uri(), "https://example.com/a?id=1");
+
+        let mut req = request("/a?gclid=1", &[]);
+        privacy.strip_request(&mut req, "example.com");
+        assert_eq!(req.uri(), "/a");
+
+        privacy.set_params(parse_params("# list\nid\n").unwrap());
+        let mut req = request("/a?id=1&utm_source=x&ref_id=2", &[]);
+        privacy.strip_request(&mut req, "example.com");
+        assert_eq!(req.uri(), "/a?utm_source=x");
+    }
+
+    #[test]
+    fn third_party_cookies_are_stripped() {
+        let privacy = Privacy::new(false, true, Vec::new(), Vec::new());
+        let cookie = ("cookie", "a=1; b=2");
+
+        let mut req = request("/", &[cookie, ("sec-fetch-site", "cross-site")]);
+        let stripped = privacy.strip_request(&mut req, "ads.tracker.net");
+        assert!(stripped.third_party);
+        assert!(!req.headers().contains_key(COOKIE));
+        let mut headers = HeaderMap::new();
+        headers.append(SET_COOKIE, HeaderValue::from_static("id=1"));
+        privacy.strip_response(stripped, &mut headers);
+        assert!(headers.is_empty());
+
+        let mut req = request("/", &[cookie, ("sec-fetch-site", "same-site")]);
+        assert!(
+            !privacy
+                .strip_request(&mut req, "ads.tracker.net")
+                .third_party
+        );
+        assert!(req.headers().contains_key(COOKIE));
+
+        let mut req = request("/", &[cookie, ("referer", "https://www.example.co.uk/")]);
+        assert!(
+            !privacy
+                .strip_request(&mut req, "static.example.co.uk")
+                .third_party
+        );
+        let mut req = request("/", &[cookie, ("referer", "https://other.co.uk/")]);
+        assert!(
+            privacy
+                .strip_request(&mut req, "static.example.co.uk")
+                .third_party
+        );
+        let mut req = request("/", &[cookie]);
+        assert!(!privacy.strip_request(&mut req, "example.com").third_party);
+    }
+
+    #[test]
+    fn overrides_apply_by_host() {
+        let privacy = Privacy::new(
+            true,
+            true,
+            Vec::new(),
+            vec![PrivacyOverride {
+                host: "*.example.com".to_string(),
+                strip_tracking_params: Some(false),
+                strip_third_party_cookies: None,
+            }],
+        );
+        let headers = [("cookie", "a=1"), ("sec-fetch-site", "cross-site")];
+        let mut req = request("/?utm_source=x", &headers);
+        assert!(
+            privacy
+                .strip_request(&mut req, "www.example.com")
+                .third_party
+        );
+        assert_eq!(req.uri(), "/?utm_source=x");
+
+        let mut req = request("/?utm_source=x", &headers);
+        privacy.strip_request(&mut req, "example.org");
+        assert_eq!(req.uri(), "/");
+    }
+}
@@ -0,0 +1,459 @@
+//! Decoding protobuf bodies with uploaded descriptor sets, so plugins and
+//! the flow log see JSON rather than opaque bytes.
+//!
+//! Descriptor sets (as written by `protoc --descriptor_set_out`) are
+//! uploaded through `PUT /api/manage/protobuf/descriptors/{name}` or
+//! `witm protobuf add`. Mappings from hosts and paths to the message types
+//! their bodies hold are configured as `[[proxy.protobuf]]` tables, or
+//! replaced at runtime through `/api/manage/protobuf/mappings`:
+//!
+//! ```toml
+//! [[proxy.protobuf]]
+//! host = "*.example.com"
+//! path = "/v1/users/*"
+//! request = "example.v1.UpdateUserRequest"
+//! response = "example.v1.User"
+//! ```
+//!
+//! Matching bodies are handed to plugins as JSON, in the proto3 JSON
+//! mapping, with an `x-witm-protobuf` header naming their message type.
+//! They're encoded back into protobuf before being sent on, so plugins can
+//! modify them as JSON. Bodies which fail to decode pass through untouched.
+//! Unknown fields don't survive the round trip.
+
+use std::sync::{Arc, RwLock};
+
+use anyhow::{Context, Result, bail};
+use bytes::Bytes;
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::Request;
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE, HeaderValue};
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+
+use crate::http::sniff::essence;
+use crate::proxy::host_limits::matches_host;
+
+/// Header naming the message type of a body decoded to JSON
+pub const PROTOBUF_HEADER: &str = "x-witm-protobuf";
+
+/// Largest request body decoded for plugins
+pub const MAX_PROTOBUF_BODY: usize = 4 * 1024 * 1024;
+
+/// Content types of protobuf bodies. Many APIs send them as
+/// `application/octet-stream`, which is decoded when a mapping names a type.
+const CONTENT_TYPES: &[&str] = &[
+    "application/x-protobuf",
+    "application/protobuf",
+    "application/x-google-protobuf",
+    "application/vnd.google.protobuf",
+    "application/octet-stream",
+];
+
+/// Whether a body with `content_type` may hold a protobuf message
+pub fn is_protobuf(content_type: &str) -> bool {
+    CONTENT_TYPES.contains(&essence(content_type).as_str())
+}
+
+/// The message types of the bodies sent to matching hosts and paths
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ProtobufMapping {
+    /// Host name, or `*.` followed by a domain to match its subdomains
+    pub host: String,
+    /// Path, where `*` matches any run of characters (default: "*")
+    #[serde(default = "any_path")]
+    pub path: String,
+    /// Fully qualified message type of request bodies, ex: "example.v1.GetUserRequest"
+    #[serde(default)]
+    pub request: Option<String>,
+    /// Fully qualified message type of response bodies
+    #[serde(default)]
+    pub response: Option<String>,
+}
+
+fn any_path() -> String {
+    "*".to_string()
+}
+
+impl ProtobufMapping {
+    fn matches(&self, host: &str, path: &str) -> bool {
+        matches_host(&self.host, host) && matches_path(&self.path, path)
+    }
+}
+
+/// Whether `path` matches `pattern`, where `*` matches any run of characters
+fn matches_path(pattern: &str, path: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(first) = parts.next() else {
+        return true;
+    };
+    let Some(mut rest) = path.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<_> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*`, so the pattern must match the whole path
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// An uploaded descriptor set
+#[derive(Debug, Clone)]
+pub struct DescriptorSet {
+    name: String,
+    pool: DescriptorPool,
+    bytes: Bytes,
+}
+
+/// A descriptor set, as listed by the management API
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct DescriptorSetSummary {
+    pub name: String,
+    /// The `.proto` files the set describes
+    pub files: Vec<String>,
+    /// Fully qualified names of the messages it defines
+    pub messages: Vec<String>,
+}
+
+impl DescriptorSet {
+    /// Parse a serialized `FileDescriptorSet`
+    pub fn new(name: impl Into<String>, bytes: impl Into<Bytes>) -> Result<Self> {
+        let bytes = bytes.into();
+        let pool = DescriptorPool::decode(bytes.clone())
+            .context("Not a valid FileDescriptorSet (build one with protoc --descriptor_set_out --include_imports)")?;
+        if pool.files().next().is_none() {
+            bail!("The descriptor set describes no files");
+        }
+        Ok(Self {
+            name: name.into(),
+            pool,
+            bytes,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The serialized set, as uploaded
+    pub fn bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    pub fn summary(&self) -> DescriptorSetSummary {
+        DescriptorSetSummary {
+            name: self.name.clone(),
+            files: self
+                .pool
+                .files()
+                .map(|file| file.name().to_string())
+                .collect(),
+            messages: self
+                .pool
+                .all_messages()
+                .map(|message| message.full_name().to_string())
+                .collect(),
+        }
+    }
+}
+
+/// Uploaded descriptor sets and the mappings to their message types.
+/// Cheap to clone; all clones share the same sets and mappings.
+#[derive(Clone, Default)]
+pub struct ProtobufDescriptors {
+    sets: Arc<RwLock<Arc<Vec<DescriptorSet>>>>,
+    mappings: Arc<RwLock<Arc<Vec<ProtobufMapping>>>>,
+}
+
+impl std::fmt::Debug for ProtobufDescriptors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProtobufDescriptors")
+            .field(
+                "sets",
+                &self
+                    .sets()
+                    .iter()
+                    .map(DescriptorSet::name)
+                    .collect::<Vec<_>>(),
+            )
+            .field("mappings", &self.mappings())
+            .finish()
+    }
+}
+
+impl ProtobufDescriptors {
+    pub fn sets(&self) -> Arc<Vec<DescriptorSet>> {
+        self.sets.read().unwrap().clone()
+    }
+
+    /// Add `set`, replacing any set with the same name
+    pub fn insert(&self, set: DescriptorSet) {
+        let mut sets = self.sets.write().unwrap();
+        let mut updated: Vec<_> = sets
+            .iter()
+            .filter(|existing| existing.name != set.name)
+            .cloned()
+            .collect();
+        updated.push(set);
+        *sets = Arc::new(updated);
+    }
+
+    /// Remove the set called `name`, returning whether there was one
+    pub fn remove(&self, name: &str) -> bool {
+        let mut sets = self.sets.write().unwrap();
+        let updated: Vec<_> = sets
+            .iter()
+            .filter(|existing| existing.name != name)
+            .cloned()
+            .collect();
+        let removed = updated.len() != sets.len();
+        *sets = Arc::new(updated);
+        removed
+    }
+
+    pub fn mappings(&self) -> Vec<ProtobufMapping> {
+        self.mappings.read().unwrap().to_vec()
+    }
+
+    /// Replace the mappings. Message types needn't be uploaded yet; bodies
+    /// mapped to types no set defines pass through untouched.
+    pub fn set_mappings(&self, mappings: Vec<ProtobufMapping>) -> Result<()> {
+        for mapping in &mappings {
+            if mapping.host.is_empty() {
+                bail!("Protobuf mappings need a host");
+            }
+            if mapping.request.is_none() && mapping.response.is_none() {
+                bail!(
+                    "The protobuf mapping for {}{} names no request or response type",
+                    mapping.host,
+                    mapping.path
+                );
+            }
+        }
+        *self.mappings.write().unwrap() = Arc::new(mappings);
+        Ok(())
+    }
+
+    /// The message type called `name`, from the first set defining it
+    pub fn message(&self, name: &str) -> Option<MessageDescriptor> {
+        self.sets()
+            .iter()
+            .find_map(|set| set.pool.get_message_by_name(name))
+    }
+
+    /// The message type of request bodies sent to `host` and `path`
+    pub fn request_message(&self, host: &str, path: &str) -> Option<MessageDescriptor> {
+        self.mapped_message(host, path, |mapping| mapping.request.as_deref())
+    }
+
+    /// The message type of response bodies received from `host` and `path`
+    pub fn response_message(&self, host: &str, path: &str) -> Option<MessageDescriptor> {
+        self.mapped_message(host, path, |mapping| mapping.response.as_deref())
+    }
+
+    fn mapped_message(
+        &self,
+        host: &str,
+        path: &str,
+        message_type: impl Fn(&ProtobufMapping) -> Option<&str>,
+    ) -> Option<MessageDescriptor> {
+        let mappings = self.mappings.read().unwrap().clone();
+        mappings
+            .iter()
+            .filter(|mapping| mapping.matches(host, path))
+            .find_map(|mapping| message_type(mapping))
+            .and_then(|name| self.message(name))
+    }
+}
+
+/// Whether `req` has a protobuf body small enough to be decoded
+pub fn decodes_request<B>(req: &Request<B>) -> bool {
+    let header = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+    };
+    header(CONTENT_TYPE).is_some_and(is_protobuf)
+        && header(CONTENT_LENGTH)
+            .and_then(|length| length.parse::<usize>().ok())
+            .is_some_and(|length| length <= MAX_PROTOBUF_BODY)
+}
+
+/// Decode the body of `req` as a `message` for request plugins. Returns
+/// whether it was decoded; if not, the body is left as it was.
+pub async fn decode_request(
+    req: Request<UnsyncBoxBody<Bytes, ErrorCode>>,
+    message: &MessageDescriptor,
+) -> Result<(Request<UnsyncBoxBody<Bytes, ErrorCode>>, bool), ErrorCode> {
+    let (mut parts, body) = req.into_parts();
+    let body = body.collect().await?.to_bytes();
+    let (body, decoded) = match decode(message, &body) {
+        Ok(json) => {
+            set_json_headers(&mut parts.headers, message);
+            (Bytes::from(json), true)
+        }
+        Err(_) => (body, false),
+    };
+    let body = Full::new(body)
+        .map_err(|_| ErrorCode::InternalError(Some("conversion error".to_string())))
+        .boxed_unsync();
+    Ok((Request::from_parts(parts, body), decoded))
+}
+
+/// Encode the body of a request decoded by [decode_request] back
+/// into a `message`, with its original `content_type`. Requests whose
+/// body plugins replaced with something else are left as they are.
+pub fn encode_request(
+    req: Request<UnsyncBoxBody<Bytes, ErrorCode>>,
+    message: MessageDescriptor,
+    content_type: HeaderValue,
+) -> Request<UnsyncBoxBody<Bytes, ErrorCode>> {
+    if req
+        .headers()
+        .get(PROTOBUF_HEADER)
+        .map(HeaderValue::as_bytes)
+        != Some(message.full_name().as_bytes())
+    {
+        return req;
+    }
+    let (mut parts, body) = req.into_parts();
+    restore_headers(&mut parts.headers, content_type);
+    Request::from_parts(parts, encode_body(body, message))
+}
+
+/// Decode a `message` from protobuf `bytes` into JSON
+pub fn decode(message: &MessageDescriptor, bytes: &[u8]) -> Result<Vec<u8>> {
+    let decoded = DynamicMessage::decode(message.clone(), bytes)
+        .with_context(|| format!("Not a valid {} message", message.full_name()))?;
+    Ok(serde_json::to_vec(&decoded)?)
+}
+
+/// Encode a `message` from JSON `bytes` into protobuf
+pub fn encode(message: &MessageDescriptor, json: &[u8]) -> Result<Bytes> {
+    let mut deserializer = serde_json::Deserializer::from_slice(json);
+    let decoded = DynamicMessage::deserialize(message.clone(), &mut deserializer)
+        .with_context(|| format!("Not a valid {} message in JSON", message.full_name()))?;
+    deserializer.end()?;
+    Ok(Bytes::from(decoded.encode_to_vec()))
+}
+
+/// Mark headers as those of a body decoded from a `message` into JSON
+pub fn set_json_headers(headers: &mut hyper::HeaderMap, message: &MessageDescriptor) {
+    headers.remove(CONTENT_LENGTH);
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    if let Ok(value) = HeaderValue::from_str(message.full_name()) {
+        headers.insert(PROTOBUF_HEADER, value);
+    }
+}
+
+/// Undo [set_json_headers] for a body encoded back into protobuf
+pub fn restore_headers(headers: &mut hyper::HeaderMap, content_type: HeaderValue) {
+    headers.remove(CONTENT_LENGTH);
+    headers.remove(PROTOBUF_HEADER);
+    headers.insert(CONTENT_TYPE, content_type);
+}
+
+/// Encode a JSON body back into a `message` once it has been read in full,
+/// failing the body if plugins left it invalid
+pub fn encode_body(
+    body: UnsyncBoxBody<Bytes, ErrorCode>,
+    message: MessageDescriptor,
+) -> UnsyncBoxBody<Bytes, ErrorCode> {
+    let encoded = futures::stream::once(async move {
+        let json = body.collect().await?.to_bytes();
+        encode(&message, &json)
+            .map(http_body::Frame::data)
+            .map_err(|e| ErrorCode::InternalError(Some(format!("{:#}", e))))
+    });
+    StreamBody::new(encoded).boxed_unsync()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A descriptor set for `example.v1.User { string name = 1; int32 id = 2;
+    /// repeated string roles = 3; }`
+    const USER_DESCRIPTOR: &[u8] = b"\x0ab\x0a\x0auser.proto\x12\x0aexample.v1\x22@\x0a\x04User\x12\x12\x0a\x04name\x18\x01 \x01(\x09R\x04name\x12\x0e\x0a\x02id\x18\x02 \x01(\x05R\x02id\x12\x14\x0a\x05roles\x18\x03 \x03(\x09R\x05rolesb\x06proto3";
+
+    /// `User { name: "Ada", id: 7, roles: ["admin"] }`
+    const USER: &[u8] = b"\x0a\x03Ada\x10\x07\x1a\x05admin";
+
+    fn descriptors() -> ProtobufDescriptors {
+        let descriptors = ProtobufDescriptors::default();
+        descriptors
+            .set_mappings(vec![ProtobufMapping {
+                host: "*.example.com".to_string(),
+                path: "/v1/users/*".to_string(),
+                request: None,
+                response: Some("example.v1.User".to_string()),
+            }])
+            .unwrap();
+        descriptors.insert(DescriptorSet::new("users", USER_DESCRIPTOR).unwrap());
+        descriptors
+    }
+
+    #[test]
+    fn paths_match_patterns() {
+        assert!(matches_path("*", "/anything"));
+        assert!(matches_path("/v1/users/*", "/v1/users/7"));
+        assert!(matches_path("/v1/*/roles", "/v1/users/7/roles"));
+        assert!(!matches_path("/v1/*/roles", "/v1/users/7"));
+        assert!(matches_path("/v1/users", "/v1/users"));
+        assert!(!matches_path("/v1/users", "/v1/users/7"));
+    }
+
+    #[test]
+    fn messages_are_found_by_mapping() {
+        let descriptors = descriptors();
+        assert_eq!(
+            descriptors.sets()[0].summary().messages,
+            vec!["example.v1.User"]
+        );
+        let message = descriptors
+            .response_message("api.example.com", "/v1/users/7")
+            .unwrap();
+        assert_eq!(message.full_name(), "example.v1.User");
+        assert!(
+            descriptors
+                .request_message("api.example.com", "/v1/users/7")
+                .is_none()
+        );
+        assert!(
+            descriptors
+                .response_message("example.org", "/v1/users/7")
+                .is_none()
+        );
+        assert!(DescriptorSet::new("junk", &b"not a descriptor"[..]).is_err());
+    }
+
+    #[test]
+    fn messages_round_trip_through_json() {
+        let message = descriptors().message("example.v1.User").unwrap();
+        let json: serde_json::Value =
+            serde_json::from_slice(&decode(&message, USER).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "name": "Ada", "id": 7, "roles": ["admin"] })
+        );
+        let modified = serde_json::json!({ "name": "Ada", "id": 7, "roles": ["admin", "owner"] });
+        let encoded = encode(&message, modified.to_string().as_bytes()).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_slice(&decode(&message, &encoded).unwrap()).unwrap();
+        assert_eq!(json, modified);
+        assert!(encode(&message, br#"{"name": 7}"#).is_err());
+        assert!(is_protobuf("application/x-protobuf; charset=binary"));
+        assert!(!is_protobuf("application/json"));
+    }
+}
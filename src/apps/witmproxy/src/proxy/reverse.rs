@@ -0,0 +1,514 @@
+//! Reverse proxy mode, for putting plugins in front of services hosted on
+//! the local network. witmproxy terminates TLS for each configured host name,
+//! with a certificate issued by its CA or one provided for the route, and
+//! forwards requests to the route's origin through the same plugin pipeline
+//! as intercepted traffic:
+//!
+//! ```toml
+//! [reverse]
+//! enabled = true
+//! listen_addr = "0.0.0.0:443"
+//!
+//! [[reverse.routes]]
+//! host = "grafana.home.arpa"
+//! upstream = "http://127.0.0.1:3000"
+//! tls_cert_path = "~/.witmproxy/grafana.crt"
+//! tls_key_path = "~/.witmproxy/grafana.key"
+//! ```
+
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, bail};
+use hyper::header::{HOST, HeaderValue};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tokio::sync::{Notify, RwLock};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info, warn};
+
+use crate::cert::CertificateAuthority;
+use crate::config::ReverseProxyConfig;
+use crate::plugins::registry::PluginRegistry;
+use crate::proxy::api_schemas::ApiSchemas;
+use crate::proxy::block_rules::BlockRules;
+use crate::proxy::compression::Compression;
+use crate::proxy::egress::EgressRoutes;
+use crate::proxy::findings::SensitiveData;
+use crate::proxy::flow_tags::FlowTags;
+use crate::proxy::flow_trace::FlowTraces;
+use crate::proxy::header_overrides::HeaderOverrides;
+use crate::proxy::host_limits::HostLimiter;
+use crate::proxy::images::ImageOptimizer;
+use crate::proxy::limits::FlowLimits;
+use crate::proxy::mocks::MockApis;
+use crate::proxy::network_profiles::NetworkConditions;
+use crate::proxy::pages::ErrorPages;
+use crate::proxy::privacy::Privacy;
+use crate::proxy::protobuf::ProtobufDescriptors;
+use crate::proxy::security_headers::SecurityHeaders;
+use crate::proxy::sessions::CaptureSessions;
+use crate::proxy::tls_policy::TlsPolicies;
+use crate::proxy::traffic::Traffic;
+use crate::proxy::translation::Translation;
+use crate::proxy::transparent::extract_sni_from_client_hello;
+use crate::proxy::user_scripts::UserScripts;
+use crate::proxy::vhost::HostMismatchPolicy;
+use crate::proxy::{
+    FlowSettings, ProxyResult, UpstreamClient, build_server_tls_for_host, is_closed,
+    serve_tls_flows,
+};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+
+/// A host name served by the reverse proxy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReverseRoute {
+    /// Host name clients connect to, matched against the TLS SNI
+    pub host: String,
+    /// Origin requests are forwarded to, ex: `http://127.0.0.1:3000`
+    pub upstream: String,
+    /// Certificate chain (PEM) presented for `host`, instead of one issued
+    /// by the witmproxy CA
+    #[serde(default)]
+    pub tls_cert_path: Option<PathBuf>,
+    /// Private key (PEM) for `tls_cert_path`
+    #[serde(default)]
+    pub tls_key_path: Option<PathBuf>,
+}
+
+/// A route ready to serve, with its origin parsed and certificate loaded
+#[derive(Debug)]
+struct Route {
+    host: String,
+    origin: reqwest::Url,
+    tls: Option<Arc<rustls::ServerConfig>>,
+}
+
+impl Route {
+    fn load(route: &ReverseRoute) -> anyhow::Result<Self> {
+        let origin = reqwest::Url::parse(&route.upstream)
+            .with_context(|| format!("Invalid upstream for {}: {}", route.host, route.upstream))?;
+        if !matches!(origin.scheme(), "http" | "https") || origin.host_str().is_none() {
+            bail!(
+                "Upstream for {} must be an http or https URL: {}",
+                route.host,
+                route.upstream
+            );
+        }
+        let tls = match (&route.tls_cert_path, &route.tls_key_path) {
+            (Some(cert), Some(key)) => Some(Arc::new(load_server_tls(cert, key)?)),
+            (None, None) => None,
+            _ => bail!(
+                "tls_cert_path and tls_key_path must be set together, for {}",
+                route.host
+            ),
+        };
+        Ok(Self {
+            host: route.host.to_ascii_lowercase(),
+            origin,
+            tls,
+        })
+    }
+}
+
+fn load_server_tls(cert_path: &Path, key_path: &Path) -> anyhow::Result<rustls::ServerConfig> {
+    let cert_pem = std::fs::read(cert_path)
+        .with_context(|| format!("Failed to read TLS cert {}", cert_path.display()))?;
+    let key_pem = std::fs::read(key_path)
+        .with_context(|| format!("Failed to read TLS key {}", key_path.display()))?;
+    let certs = rustls_pemfile::certs(&mut cert_pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid TLS cert {}", cert_path.display()))?;
+    if certs.is_empty() {
+        bail!("No certificates found in {}", cert_path.display());
+    }
+    let key = rustls_pemfile::private_key(&mut key_pem.as_slice())
+        .with_context(|| format!("Invalid TLS key {}", key_path.display()))?
+        .with_context(|| format!("No private key found in {}", key_path.display()))?;
+
+    let mut cfg = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    cfg.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(cfg)
+}
+
+/// Send `req` to `origin` instead of the host it names, keeping its path and
+/// query, and tell the origin who the request came from in `X-Forwarded-*`
+/// headers
+pub(crate) fn route_to_origin(req: &mut reqwest::Request, origin: &reqwest::Url, client: IpAddr) {
+    let url = req.url_mut();
+    let requested_host = url.host_str().map(|host| match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    });
+    // Both URLs are http(s) with a host, so these can't fail
+    let _ = url.set_scheme(origin.scheme());
+    let _ = url.set_host(origin.host_str());
+    let _ = url.set_port(origin.port());
+
+    let headers = req.headers_mut();
+    // reqwest sets the Host header from the new URL
+    headers.remove(HOST);
+    let forwarded_for = match headers
+        .get(X_FORWARDED_FOR)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(previous) => format!("{}, {}", previous, client),
+        None => client.to_string(),
+    };
+    if let Ok(value) = HeaderValue::from_str(&forwarded_for) {
+        headers.insert(X_FORWARDED_FOR, value);
+    }
+    if let Some(value) = requested_host.and_then(|host| HeaderValue::from_str(&host).ok()) {
+        headers.insert(X_FORWARDED_HOST, value);
+    }
+    headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static("https"));
+}
+
+/// Reverse proxy server, terminating TLS for the configured routes.
+pub struct ReverseProxy {
+    listen_addr: Option<SocketAddr>,
+    ca: Arc<CertificateAuthority>,
+    plugin_registry: Option<Arc<RwLock<PluginRegistry>>>,
+    upstream: UpstreamClient,
+    config: ReverseProxyConfig,
+    shutdown_notify: Arc<Notify>,
+    settings: FlowSettings,
+}
+
+impl ReverseProxy {
+    pub fn new(
+        ca: Arc<CertificateAuthority>,
+        plugin_registry: Option<Arc<RwLock<PluginRegistry>>>,
+        upstream: UpstreamClient,
+        config: ReverseProxyConfig,
+        shutdown_notify: Arc<Notify>,
+    ) -> Self {
+        Self {
+            listen_addr: None,
+            ca,
+            plugin_registry,
+            upstream,
+            config,
+            shutdown_notify,
+            settings: FlowSettings::default(),
+        }
+    }
+
+    /// Set the size and time limits applied to proxied flows
+    pub fn with_limits(mut self, limits: FlowLimits) -> Self {
+        self.settings.limits = limits;
+        self
+    }
+
+    /// Set the pages served when a plugin blocks a flow or it fails
+    pub fn with_error_pages(mut self, pages: ErrorPages) -> Self {
+        self.settings.pages = pages;
+        self
+    }
+
+    /// Set how requests whose `Host` differs from the connection's SNI are handled
+    pub fn with_host_mismatch_policy(mut self, policy: HostMismatchPolicy) -> Self {
+        self.settings.host_mismatch = policy;
+        self
+    }
+
+    /// Set the security headers injected into proxied responses
+    pub fn with_security_headers(mut self, security_headers: SecurityHeaders) -> Self {
+        self.settings.security_headers = security_headers;
+        self
+    }
+
+    /// Set the rules tagging flows as they complete
+    pub fn with_flow_tags(mut self, flow_tags: FlowTags) -> Self {
+        self.settings.flow_tags = flow_tags;
+        self
+    }
+
+    /// Set the capture sessions flows are offered to
+    pub fn with_sessions(mut self, sessions: CaptureSessions) -> Self {
+        self.settings.sessions = sessions;
+        self
+    }
+
+    /// Set the mocked APIs, answered in place of origins
+    pub fn with_mocks(mut self, mocks: MockApis) -> Self {
+        self.settings.mocks = mocks;
+        self
+    }
+
+    /// Set the scripts injected into matching HTML pages
+    pub fn with_user_scripts(mut self, user_scripts: UserScripts) -> Self {
+        self.settings.user_scripts = user_scripts;
+        self
+    }
+
+    /// Set how images in responses are transcoded
+    pub fn with_images(mut self, images: ImageOptimizer) -> Self {
+        self.settings.images = images;
+        self
+    }
+
+    /// Set how responses are compressed for the client
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.settings.compression = compression;
+        self
+    }
+
+    /// Set the page translation
+    pub fn with_translation(mut self, translation: Translation) -> Self {
+        self.settings.translation = translation;
+        self
+    }
+
+    /// Set the tracking parameter and third-party cookie stripping
+    pub fn with_privacy(mut self, privacy: Privacy) -> Self {
+        self.settings.privacy = privacy;
+        self
+    }
+
+    /// Record flows to `schemas`, inferring API schemas from them
+    pub fn with_api_schemas(mut self, schemas: ApiSchemas) -> Self {
+        self.settings.schemas = Some(schemas);
+        self
+    }
+
+    /// Set what's done about sensitive data in proxied flows
+    pub fn with_sensitive_data(mut self, sensitive_data: SensitiveData) -> Self {
+        self.settings.sensitive_data = sensitive_data;
+        self
+    }
+
+    /// Set the descriptors protobuf bodies of proxied flows are decoded with
+    pub fn with_protobuf(mut self, protobuf: ProtobufDescriptors) -> Self {
+        self.settings.protobuf = protobuf;
+        self
+    }
+
+    /// Set the clients allowed to trace flows, and where their traces are kept
+    pub fn with_flow_traces(mut self, traces: FlowTraces) -> Self {
+        self.settings.traces = traces;
+        self
+    }
+
+    /// Set the per-host limits on requests sent to origins
+    pub fn with_host_limiter(mut self, host_limiter: HostLimiter) -> Self {
+        self.settings.host_limiter = host_limiter;
+        self
+    }
+
+    /// Set the network conditions simulated for clients
+    pub fn with_network_conditions(mut self, network_conditions: NetworkConditions) -> Self {
+        self.settings.network_conditions = network_conditions;
+        self
+    }
+
+    /// Set the headers forced on the requests sent to matching hosts
+    pub fn with_header_overrides(mut self, header_overrides: HeaderOverrides) -> Self {
+        self.settings.header_overrides = header_overrides;
+        self
+    }
+
+    /// Set the routes flows to matching origins are sent out through
+    pub fn with_egress(mut self, egress: EgressRoutes) -> Self {
+        self.settings.egress = egress;
+        self
+    }
+
+    /// Set the hosts refused, and how
+    pub fn with_block_rules(mut self, block_rules: BlockRules) -> Self {
+        self.settings.block_rules = block_rules;
+        self
+    }
+
+    /// Count flows' traffic by client profile, enforcing the profiles' quotas
+    pub fn with_traffic(mut self, traffic: Traffic) -> Self {
+        self.settings.traffic = Some(traffic);
+        self
+    }
+
+    /// Set the TLS policies handshakes are negotiated with
+    pub fn with_tls_policies(mut self, tls_policies: TlsPolicies) -> Self {
+        self.settings.tls_policies = tls_policies;
+        self
+    }
+
+    pub fn listen_addr(&self) -> Option<SocketAddr> {
+        self.listen_addr
+    }
+
+    pub async fn start(&mut self) -> anyhow::Result<()> {
+        let routes = self
+            .config
+            .routes
+            .iter()
+            .map(Route::load)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if routes.is_empty() {
+            warn!("Reverse proxy is enabled without any [[reverse.routes]]");
+        }
+        let routes = Arc::new(routes);
+
+        let bind_addr: SocketAddr = self
+            .config
+            .listen_addr
+            .as_deref()
+            .unwrap_or("0.0.0.0:8443")
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid reverse proxy bind address: {}", e))?;
+
+        let listener = super::dial::bind_listener(bind_addr)?;
+        self.listen_addr = Some(listener.local_addr()?);
+        info!("Reverse proxy listening on {}", self.listen_addr.unwrap());
+
+        let shutdown = self.shutdown_notify.clone();
+        let ca = self.ca.clone();
+        let plugin_registry = self.plugin_registry.clone();
+        let upstream = self.upstream.clone();
+        let settings = self.settings.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = shutdown.notified() => break,
+                    accept_result = listener.accept() => {
+                        match accept_result {
+                            Ok((stream, peer)) => {
+                                let peer = super::dial::canonical_addr(peer);
+                                debug!("Reverse: accepted connection from {}", peer);
+                                let routes = routes.clone();
+                                let ca = ca.clone();
+                                let plugin_registry = plugin_registry.clone();
+                                let upstream = upstream.clone();
+                                let settings = settings.clone();
+
+                                tokio::spawn(async move {
+                                    if let Err(e) = handle_reverse_connection(
+                                        stream,
+                                        peer,
+                                        &routes,
+                                        ca,
+                                        plugin_registry,
+                                        upstream,
+                                        settings,
+                                    ).await
+                                        && !is_closed(&e) {
+                                            debug!("Reverse connection error from {}: {}", peer, e);
+                                        }
+                                });
+                            }
+                            Err(e) => error!("Reverse accept error: {}", e),
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// Find the route for a connection from its SNI, then serve it with the
+/// route's certificate and origin
+async fn handle_reverse_connection(
+    stream: TcpStream,
+    peer: SocketAddr,
+    routes: &[Route],
+    ca: Arc<CertificateAuthority>,
+    plugin_registry: Option<Arc<RwLock<PluginRegistry>>>,
+    upstream: UpstreamClient,
+    settings: FlowSettings,
+) -> ProxyResult<()> {
+    let mut hello_buf = vec![0u8; 4096];
+    let n = stream.peek(&mut hello_buf).await?;
+    let Some(sni) = extract_sni_from_client_hello(&hello_buf[..n]) else {
+        debug!("Reverse: no SNI in ClientHello from {}", peer);
+        return Ok(());
+    };
+    let Some(route) = routes
+        .iter()
+        .find(|route| route.host.eq_ignore_ascii_case(&sni))
+    else {
+        debug!("Reverse: no route for {} from {}", sni, peer);
+        return Ok(());
+    };
+
+    let server_tls = match &route.tls {
+        Some(tls) => tls.clone(),
+        None => Arc::new(
+            build_server_tls_for_host(
+                &ca,
+                &route.host,
+                settings.tls_policies.client_policy(&route.host),
+            )
+            .await?,
+        ),
+    };
+    let settings = FlowSettings {
+        origin: Some(route.origin.clone()),
+        ..settings
+    };
+    serve_tls_flows(
+        upstream,
+        stream,
+        peer.ip(),
+        route.host.clone(),
+        TlsAcceptor::from(server_tls),
+        plugin_registry,
+        settings,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(upstream: &str) -> ReverseRoute {
+        ReverseRoute {
+            host: "App.home.arpa".to_string(),
+            upstream: upstream.to_string(),
+            tls_cert_path: None,
+            tls_key_path: None,
+        }
+    }
+
+    #[test]
+    fn routes_need_an_http_origin() {
+        let loaded = Route::load(&route("http://127.0.0.1:3000")).unwrap();
+        assert_eq!(loaded.host, "app.home.arpa");
+        assert_eq!(loaded.origin.as_str(), "http://127.0.0.1:3000/");
+        assert!(loaded.tls.is_none());
+
+        assert!(Route::load(&route("127.0.0.1:3000")).is_err());
+        assert!(Route::load(&route("ftp://127.0.0.1")).is_err());
+        let mut without_key = route("http://127.0.0.1:3000");
+        without_key.tls_cert_path = Some(PathBuf::from("app.crt"));
+        assert!(Route::load(&without_key).is_err());
+    }
+
+    #[test]
+    fn requests_are_sent_to_the_origin() {
+        let client = reqwest::Client::new();
+        let mut req = client
+            .get("https://app.home.arpa/dashboard?tab=1")
+            .header(HOST, "app.home.arpa")
+            .header(X_FORWARDED_FOR, "198.51.100.7")
+            .build()
+            .unwrap();
+        let origin = reqwest::Url::parse("http://127.0.0.1:3000").unwrap();
+        route_to_origin(&mut req, &origin, IpAddr::from([192, 0, 2, 1]));
+
+        assert_eq!(req.url().as_str(), "http://127.0.0.1:3000/dashboard?tab=1");
+        let headers = req.headers();
+        assert!(headers.get(HOST).is_none());
+        assert_eq!(headers[X_FORWARDED_FOR], "198.51.100.7, 192.0.2.1");
+        assert_eq!(headers[X_FORWARDED_HOST], "app.home.arpa");
+        assert_eq!(headers[X_FORWARDED_PROTO], "https");
+    }
+}
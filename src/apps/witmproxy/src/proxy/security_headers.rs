@@ -0,0 +1,263 @@
+//! A built-in module which injects or overrides security headers (HSTS, CSP,
+//! X-Frame-Options, Referrer-Policy, ...) on intercepted responses, e.g. to
+//! see how a site behaves under a stricter policy.
+//!
+//! Rules are configured as `[[proxy.security_headers]]` tables, or replaced
+//! at runtime through `/api/manage/security-headers`:
+//!
+//! ```toml
+//! [[proxy.security_headers]]
+//! when = 'request.host().endsWith("example.com") && response.status() == 200'
+//! [proxy.security_headers.headers]
+//! strict-transport-security = "max-age=63072000; includeSubDomains"
+//! content-security-policy = "default-src 'self'"
+//! ```
+//!
+//! `when` is a CEL expression over `request` and `response`, as in plugin
+//! response scopes. Every matching rule is applied, in order.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use anyhow::{Context, Result};
+use cel_cxx::{Activation, Env, Program};
+use hyper::Response;
+use hyper::header::{HeaderName, HeaderValue};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+
+use crate::plugins::cel::{CelRequest, CelResponse, CelTime};
+use crate::wasm::bindgen::Event as WasmEvent;
+
+/// Headers to set on the responses matching an expression
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SecurityHeaderRule {
+    /// CEL expression selecting the responses to modify (default: "true")
+    #[serde(default = "match_all")]
+    pub when: String,
+    /// Header values by name
+    pub headers: BTreeMap<String, String>,
+    /// Keep a header the upstream already sent rather than replacing it
+    /// (default: false)
+    #[serde(default)]
+    pub keep_existing: bool,
+}
+
+fn match_all() -> String {
+    "true".to_string()
+}
+
+struct CompiledRule {
+    program: Program<'static>,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    keep_existing: bool,
+}
+
+impl CompiledRule {
+    fn compile(rule: &SecurityHeaderRule) -> Result<Self> {
+        let program = env()
+            .compile(&rule.when)
+            .with_context(|| format!("Invalid security header expression: {}", rule.when))?;
+        let headers = rule
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                Ok((
+                    HeaderName::try_from(name.as_str())
+                        .with_context(|| format!("Invalid header name: {}", name))?,
+                    HeaderValue::try_from(value.as_str())
+                        .with_context(|| format!("Invalid value for header {}", name))?,
+                ))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            program,
+            headers,
+            keep_existing: rule.keep_existing,
+        })
+    }
+
+    fn matches(&self, request: &CelRequest, response: &CelResponse) -> bool {
+        let activation = Activation::new()
+            .bind_variable("request", request.clone())
+            .and_then(|a| a.bind_variable("response", response.clone()))
+            .and_then(|a| a.bind_variable("time", CelTime::now()));
+        let Ok(activation) = activation else {
+            return false;
+        };
+        match self.program.evaluate(activation) {
+            Ok(cel_cxx::Value::Bool(matches)) => matches,
+            Ok(_) => false,
+            Err(e) => {
+                error!("Error evaluating security header expression: {}", e);
+                false
+            }
+        }
+    }
+}
+
+/// The CEL environment rules are compiled in, declaring the same variables
+/// and functions as plugin scopes
+fn env() -> &'static Env<'static> {
+    static ENV: OnceLock<Env<'static>> = OnceLock::new();
+    ENV.get_or_init(|| {
+        WasmEvent::register(Env::builder().with_standard(true))
+            .and_then(|builder| Ok(builder.build()?))
+            .expect("Failed to build the CEL environment")
+    })
+}
+
+/// The live set of security header rules. Cheap to clone; all clones share
+/// the same rules.
+#[derive(Clone, Default)]
+pub struct SecurityHeaders {
+    rules: Arc<RwLock<Arc<Rules>>>,
+}
+
+#[derive(Default)]
+struct Rules {
+    config: Vec<SecurityHeaderRule>,
+    compiled: Vec<CompiledRule>,
+}
+
+impl std::fmt::Debug for SecurityHeaders {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecurityHeaders")
+            .field("rules", &self.rules())
+            .finish()
+    }
+}
+
+impl SecurityHeaders {
+    pub fn new(rules: Vec<SecurityHeaderRule>) -> Result<Self> {
+        let headers = Self::default();
+        headers.set_rules(rules)?;
+        Ok(headers)
+    }
+
+    /// Replace the rules, leaving the current ones in place if any is invalid
+    pub fn set_rules(&self, rules: Vec<SecurityHeaderRule>) -> Result<()> {
+        let compiled = rules
+            .iter()
+            .map(CompiledRule::compile)
+            .collect::<Result<_>>()?;
+        *self.rules.write().unwrap() = Arc::new(Rules {
+            config: rules,
+            compiled,
+        });
+        Ok(())
+    }
+
+    pub fn rules(&self) -> Vec<SecurityHeaderRule> {
+        self.rules.read().unwrap().config.clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.read().unwrap().compiled.is_empty()
+    }
+
+    /// Set the headers of every rule matching `request` and `response`
+    pub fn apply<B>(&self, request: &CelRequest, response: &mut Response<B>)
+    where
+        B: http_body::Body<Data = bytes::Bytes> + Send + 'static,
+    {
+        let rules = self.rules.read().unwrap().clone();
+        if rules.compiled.is_empty() {
+            return;
+        }
+        let cel_response = CelResponse::from(&*response);
+        for rule in &rules.compiled {
+            if !rule.matches(request, &cel_response) {
+                continue;
+            }
+            for (name, value) in &rule.headers {
+                if rule.keep_existing && response.headers().contains_key(name) {
+                    continue;
+                }
+                debug!("Setting security header {} on {}", name, request.host());
+                response.headers_mut().insert(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::Empty;
+
+    fn rule(when: &str, headers: &[(&str, &str)], keep_existing: bool) -> SecurityHeaderRule {
+        SecurityHeaderRule {
+            when: when.to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            keep_existing,
+        }
+    }
+
+    fn apply(headers: &SecurityHeaders, uri: &str) -> Response<Empty<bytes::Bytes>> {
+        let request = CelRequest::from(
+            &hyper::Request::get(uri)
+                .body(Empty::<bytes::Bytes>::new())
+                .unwrap(),
+        );
+        let mut response = Response::builder()
+            .header("x-frame-options", "ALLOWALL")
+            .body(Empty::new())
+            .unwrap();
+        headers.apply(&request, &mut response);
+        response
+    }
+
+    #[test]
+    fn matching_rules_set_headers() {
+        let headers = SecurityHeaders::new(vec![
+            rule(
+                "request.host() == 'example.com'",
+                &[("strict-transport-security", "max-age=60")],
+                false,
+            ),
+            rule("true", &[("x-frame-options", "DENY")], false),
+            rule(
+                "response.status() == 200",
+                &[("x-frame-options", "SAMEORIGIN")],
+                true,
+            ),
+        ])
+        .unwrap();
+
+        let response = apply(&headers, "https://example.com/");
+        assert_eq!(
+            response.headers()["strict-transport-security"],
+            "max-age=60"
+        );
+        assert_eq!(response.headers()["x-frame-options"], "DENY");
+
+        let response = apply(&headers, "https://other.example/");
+        assert!(!response.headers().contains_key("strict-transport-security"));
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        let headers = SecurityHeaders::new(vec![rule(
+            "true",
+            &[("referrer-policy", "no-referrer")],
+            false,
+        )])
+        .unwrap();
+        assert!(
+            headers
+                .set_rules(vec![rule("request.host() ==", &[], false)])
+                .is_err()
+        );
+        assert!(
+            headers
+                .set_rules(vec![rule("true", &[("bad header", "x")], false)])
+                .is_err()
+        );
+        assert_eq!(headers.rules().len(), 1);
+    }
+}
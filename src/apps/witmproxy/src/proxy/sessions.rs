@@ -0,0 +1,645 @@
+//! Capture sessions, grouping the flows captured over a period so they can
+//! be exported together, ex: for the length of a test run.
+//!
+//! A session is started through `/api/manage/sessions` (or `witm session
+//! start`), optionally for a set duration after which it stops itself.
+//! While it runs, the flows it captures are annotated with its ID and kept
+//! by the session, independently of the flow log's capacity. When it stops,
+//! its flows are written to each of its export targets:
+//!
+//! - `har://path`, a HAR 1.2 archive, which browsers' developer tools and
+//!   most HTTP tools import
+//! - `json://path`, the flow records as the management API returns them
+//!
+//! A session can capture the flows of some hosts only, and keep more or
+//! fewer flows than the default.
+//!
+//! With a journal directory (see [crate::proxy::journal]), running sessions
+//! are journaled so they can be recovered and exported after a crash.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, SecondsFormat};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tracing::{info, warn};
+
+use crate::proxy::flows::{FlowLog, FlowRecord, SESSION};
+use crate::proxy::host_limits::matches_host;
+use crate::proxy::journal::{self, Journal};
+
+/// Most flows kept by a session unless it sets another cap
+pub const DEFAULT_MAX_SESSION_FLOWS: usize = 10_000;
+
+/// Stopped sessions kept for listing, the oldest being forgotten first
+const MAX_STOPPED_SESSIONS: usize = 20;
+
+/// How a session is run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SessionSpec {
+    pub name: String,
+    /// Seconds after which the session stops itself; it runs until stopped
+    /// when unset
+    #[serde(default)]
+    pub duration_secs: Option<u64>,
+    /// Where the session's flows are written when it stops, ex:
+    /// `har:///tmp/checkout.har` or `json://flows.json`
+    #[serde(default)]
+    pub export: Vec<String>,
+    /// Only capture the flows of hosts matching these patterns, ex:
+    /// `*.example.com` (default: every host)
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Keep at most this many flows, dropping the oldest (default:
+    /// [DEFAULT_MAX_SESSION_FLOWS])
+    #[serde(default)]
+    pub max_flows: Option<usize>,
+}
+
+/// Where a session's flows are written
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Export {
+    Har(PathBuf),
+    Json(PathBuf),
+}
+
+impl FromStr for Export {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (scheme, path) = s
+            .split_once("://")
+            .with_context(|| format!("Invalid export target {s:?}, expected har://path"))?;
+        if path.is_empty() {
+            bail!("Export target {s:?} has no path");
+        }
+        match scheme {
+            "har" => Ok(Export::Har(PathBuf::from(path))),
+            "json" => Ok(Export::Json(PathBuf::from(path))),
+            _ => bail!("Unknown export format {scheme:?}, expected har or json"),
+        }
+    }
+}
+
+impl Export {
+    async fn write(&self, session: &SessionSummary, flows: &[FlowRecord]) -> Result<PathBuf> {
+        let (path, document) = match self {
+            Export::Har(path) => (path, har(session, flows)),
+            Export::Json(path) => (path, serde_json::to_value(flows)?),
+        };
+        let text = serde_json::to_string_pretty(&document)?;
+        // Written aside and renamed into place, so an export is never left
+        // half written
+        let mut partial = path.clone().into_os_string();
+        partial.push(".partial");
+        tokio::fs::write(&partial, text)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        tokio::fs::rename(&partial, path)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path.clone())
+    }
+}
+
+/// A session as listed by the management API
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SessionSummary {
+    pub id: String,
+    pub name: String,
+    /// Unix timestamp in milliseconds when the session started
+    pub started_millis: u64,
+    /// When the session stops itself, if it has a duration
+    pub ends_millis: Option<u64>,
+    /// When the session stopped, once it has
+    pub stopped_millis: Option<u64>,
+    /// Flows kept by the session
+    pub flows: usize,
+    pub export: Vec<String>,
+    /// Why exports failed, once the session has stopped
+    pub export_errors: Vec<String>,
+}
+
+struct Session {
+    summary: SessionSummary,
+    spec: SessionSpec,
+    flows: VecDeque<FlowRecord>,
+    /// Where the session is journaled while it runs
+    journal: Option<Journal>,
+}
+
+impl Session {
+    fn captures(&self, flow: &FlowRecord) -> bool {
+        self.summary.stopped_millis.is_none()
+            && (self.spec.hosts.is_empty()
+                || self
+                    .spec
+                    .hosts
+                    .iter()
+                    .any(|pattern| matches_host(pattern, &flow.host)))
+    }
+
+    fn summary(&self) -> SessionSummary {
+        SessionSummary {
+            flows: self.flows.len(),
+            ..self.summary.clone()
+        }
+    }
+
+    /// Write to the session's journal, if it has one, giving up on the
+    /// journal if that fails
+    fn write_journal(&mut self, write: impl FnOnce(&mut Journal) -> Result<()>) {
+        if let Some(journal) = &mut self.journal
+            && let Err(e) = write(journal)
+        {
+            warn!(
+                "Stopped journaling capture session {}: {:#}",
+                self.summary.name, e
+            );
+            self.journal = None;
+        }
+    }
+}
+
+/// Running and recently stopped capture sessions. Cheap to clone; all
+/// clones share the same sessions.
+#[derive(Clone, Default)]
+pub struct CaptureSessions {
+    sessions: Arc<Mutex<Vec<Session>>>,
+    /// Where running sessions are journaled, if anywhere
+    journal_dir: Option<Arc<PathBuf>>,
+}
+
+impl std::fmt::Debug for CaptureSessions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CaptureSessions")
+            .field("sessions", &self.list())
+            .finish()
+    }
+}
+
+impl CaptureSessions {
+    /// Sessions journaled in `dir` while they run
+    pub fn journaled(dir: PathBuf) -> Self {
+        Self {
+            sessions: Arc::default(),
+            journal_dir: Some(Arc::new(dir)),
+        }
+    }
+
+    /// Recover and export the sessions left running by a previous process,
+    /// returning them
+    pub async fn recover(&self) -> Vec<SessionSummary> {
+        let Some(dir) = self.journal_dir.as_deref() else {
+            return Vec::new();
+        };
+        let paths = match journal::leftovers(dir) {
+            Ok(paths) => paths,
+            Err(e) => {
+                warn!("Failed to look for interrupted capture sessions: {:#}", e);
+                return Vec::new();
+            }
+        };
+
+        let mut recovered = Vec::new();
+        for path in paths {
+            match self.recover_one(&path).await {
+                Ok(summary) => recovered.push(summary),
+                Err(e) => warn!("Failed to recover capture session {:?}: {:#}", path, e),
+            }
+        }
+        recovered
+    }
+
+    async fn recover_one(&self, path: &Path) -> Result<SessionSummary> {
+        let journal::Recovered {
+            summary,
+            spec,
+            flows,
+            incomplete,
+            journal,
+        } = journal::recover(path)?;
+        info!(
+            "Recovering capture session {} ({}) with {} flows, {} of them incomplete",
+            summary.name,
+            summary.id,
+            flows.len(),
+            incomplete
+        );
+        let id = summary.id.clone();
+        let max = spec.max_flows.unwrap_or(DEFAULT_MAX_SESSION_FLOWS);
+        let skip = flows.len().saturating_sub(max);
+        self.sessions.lock().unwrap().push(Session {
+            summary,
+            spec,
+            flows: flows.into_iter().skip(skip).collect(),
+            journal: Some(journal),
+        });
+        self.stop(&id)
+            .await
+            .context("The recovered session disappeared")
+    }
+
+    /// Start a session, which stops itself after its duration if it has one
+    pub fn start(&self, spec: SessionSpec) -> Result<SessionSummary> {
+        if spec.name.trim().is_empty() {
+            bail!("Session name is empty");
+        }
+        for target in &spec.export {
+            target.parse::<Export>()?;
+        }
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions
+            .iter()
+            .any(|s| s.summary.stopped_millis.is_none() && s.spec.name == spec.name)
+        {
+            bail!("A session named {:?} is already running", spec.name);
+        }
+
+        let started_millis = now_millis();
+        let summary = SessionSummary {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: spec.name.clone(),
+            started_millis,
+            ends_millis: spec
+                .duration_secs
+                .map(|secs| started_millis + secs.saturating_mul(1000)),
+            stopped_millis: None,
+            flows: 0,
+            export: spec.export.clone(),
+            export_errors: Vec::new(),
+        };
+        if let Some(secs) = spec.duration_secs {
+            let sessions = self.clone();
+            let id = summary.id.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_secs(secs)).await;
+                sessions.stop(&id).await;
+            });
+        }
+        info!("Started capture session {} ({})", summary.name, summary.id);
+        let journal = self.journal_dir.as_deref().and_then(|dir| {
+            Journal::create(dir, &summary, &spec)
+                .inspect_err(|e| {
+                    warn!(
+                        "Capture session {} won't survive a crash: {:#}",
+                        summary.name, e
+                    )
+                })
+                .ok()
+        });
+        sessions.push(Session {
+            summary: summary.clone(),
+            spec,
+            flows: VecDeque::new(),
+            journal,
+        });
+        Ok(summary)
+    }
+
+    /// Stop a running session and write its flows to its export targets.
+    /// Returns None if there's no such session.
+    pub async fn stop(&self, id: &str) -> Option<SessionSummary> {
+        let (summary, exports, flows, journal) = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let session = sessions.iter_mut().find(|s| s.summary.id == id)?;
+            if session.summary.stopped_millis.is_some() {
+                return Some(session.summary());
+            }
+            session.summary.stopped_millis = Some(now_millis());
+            (
+                session.summary(),
+                session.spec.export.clone(),
+                Vec::from(session.flows.clone()),
+                session.journal.take(),
+            )
+        };
+        info!(
+            "Stopped capture session {} ({}) with {} flows",
+            summary.name, summary.id, summary.flows
+        );
+
+        let mut errors = Vec::new();
+        for target in &exports {
+            let result = match target.parse::<Export>() {
+                Ok(export) => export.write(&summary, &flows).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(path) => info!("Exported session {} to {}", summary.name, path.display()),
+                Err(e) => {
+                    warn!("Failed to export session {}: {:#}", summary.name, e);
+                    errors.push(format!("{target}: {e:#}"));
+                }
+            }
+        }
+        // The exports have been written, or failed with errors to report
+        if let Some(journal) = journal
+            && let Err(e) = journal.remove()
+        {
+            warn!("{:#}", e);
+        }
+
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.iter_mut().find(|s| s.summary.id == id)?;
+        session.summary.export_errors = errors;
+        let summary = session.summary();
+        // Forget the oldest stopped sessions past the limit
+        let stopped = sessions
+            .iter()
+            .filter(|s| s.summary.stopped_millis.is_some())
+            .count();
+        let mut excess = stopped.saturating_sub(MAX_STOPPED_SESSIONS);
+        sessions.retain(|s| {
+            let forget = excess > 0 && s.summary.stopped_millis.is_some();
+            if forget {
+                excess -= 1;
+            }
+            !forget
+        });
+        Some(summary)
+    }
+
+    /// Whether any session is running, so flows have to be offered to them
+    pub fn is_active(&self) -> bool {
+        self.sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|s| s.summary.stopped_millis.is_none())
+    }
+
+    /// Journal the flow `id`, just begun in `flows`, for the running sessions
+    /// capturing it, so it's recovered as incomplete if it never completes
+    pub fn begin(&self, flows: &FlowLog, id: &str) {
+        if self.journal_dir.is_none() {
+            return;
+        }
+        let Some(flow) = flows.get(id) else {
+            return;
+        };
+        let mut sessions = self.sessions.lock().unwrap();
+        for session in sessions.iter_mut().filter(|s| s.captures(&flow)) {
+            session.write_journal(|journal| journal.begin(&flow));
+        }
+    }
+
+    /// Add the flow `id`, just completed in `flows`, to the running sessions
+    /// capturing it, annotating it with their IDs
+    pub fn record(&self, flows: &FlowLog, id: &str) {
+        let Some(flow) = flows.get(id) else {
+            return;
+        };
+        let mut sessions = self.sessions.lock().unwrap();
+        let capturing: Vec<_> = sessions.iter_mut().filter(|s| s.captures(&flow)).collect();
+        if capturing.is_empty() {
+            return;
+        }
+        for session in &capturing {
+            flows.annotate(id, SESSION, &session.summary.id);
+        }
+        let Some(flow) = flows.get(id) else {
+            return;
+        };
+        for session in capturing {
+            session.write_journal(|journal| journal.complete(&flow));
+            let max = session.spec.max_flows.unwrap_or(DEFAULT_MAX_SESSION_FLOWS);
+            if max == 0 {
+                continue;
+            }
+            if session.flows.len() >= max {
+                session.flows.pop_front();
+            }
+            session.flows.push_back(flow.clone());
+        }
+    }
+
+    /// Sessions, oldest first
+    pub fn list(&self) -> Vec<SessionSummary> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(Session::summary)
+            .collect()
+    }
+
+    /// The flows kept by a session, oldest first
+    pub fn flows(&self, id: &str) -> Option<Vec<FlowRecord>> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|s| s.summary.id == id)
+            .map(|s| Vec::from(s.flows.clone()))
+    }
+}
+
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn timestamp(millis: u64) -> String {
+    DateTime::from_timestamp_millis(millis as i64)
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// A HAR 1.2 archive of `flows`. Flow records don't keep headers or bodies,
+/// so entries only have each request's line and response status, along
+/// with the flow's ID, tags and annotations as custom fields.
+fn har(session: &SessionSummary, flows: &[FlowRecord]) -> Value {
+    let entries: Vec<_> = flows
+        .iter()
+        .map(|flow| {
+            let mut url = format!("{}://{}{}", flow.scheme, flow.host, flow.path);
+            if !flow.query.is_empty() {
+                url = format!("{url}?{}", flow.query);
+            }
+            let query_string: Vec<_> = url::form_urlencoded::parse(flow.query.as_bytes())
+                .map(|(name, value)| json!({ "name": name, "value": value }))
+                .collect();
+            let time = flow.duration_millis.unwrap_or_default();
+            json!({
+                "startedDateTime": timestamp(flow.timestamp_millis),
+                "time": time,
+                "request": {
+                    "method": flow.method,
+                    "url": url,
+                    "httpVersion": "HTTP/1.1",
+                    "cookies": [],
+                    "headers": [],
+                    "queryString": query_string,
+                    "headersSize": -1,
+                    "bodySize": -1,
+                },
+                "response": {
+                    // Flows without a response are given the status 0, as
+                    // browsers do for requests which failed
+                    "status": flow.status.unwrap_or_default(),
+                    "statusText": "",
+                    "httpVersion": "HTTP/1.1",
+                    "cookies": [],
+                    "headers": [],
+                    "content": { "size": -1, "mimeType": "" },
+                    "redirectURL": "",
+                    "headersSize": -1,
+                    "bodySize": -1,
+                },
+                "cache": {},
+                "timings": { "send": 0, "wait": time, "receive": 0 },
+                "_id": flow.id,
+                "_tags": flow.tags,
+                "_annotations": flow.annotations,
+            })
+        })
+        .collect();
+    json!({
+        "log": {
+            "version": "1.2",
+            "creator": { "name": "witmproxy", "version": env!("CARGO_PKG_VERSION") },
+            "pages": [],
+            "entries": entries,
+            "comment": format!("Capture session {} ({})", session.name, session.id),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::flows::INCOMPLETE;
+
+    fn spec(name: &str) -> SessionSpec {
+        SessionSpec {
+            name: name.to_string(),
+            duration_secs: None,
+            export: Vec::new(),
+            hosts: Vec::new(),
+            max_flows: None,
+        }
+    }
+
+    fn complete(flows: &FlowLog, sessions: &CaptureSessions, id: &str, uri: &str) {
+        let req = hyper::Request::get(uri).body(()).unwrap();
+        flows.record(FlowRecord::new(id, &req));
+        flows.complete(id, 200);
+        sessions.record(flows, id);
+    }
+
+    #[tokio::test]
+    async fn sessions_capture_and_export_matching_flows() {
+        let dir = tempfile::tempdir().unwrap();
+        let har_path = dir.path().join("api.har");
+        let flows = FlowLog::default();
+        let sessions = CaptureSessions::default();
+        let api = sessions
+            .start(SessionSpec {
+                export: vec![format!("har://{}", har_path.display())],
+                hosts: vec!["*.example.com".to_string()],
+                max_flows: Some(2),
+                ..spec("api")
+            })
+            .unwrap();
+        let all = sessions.start(spec("all")).unwrap();
+        assert!(sessions.start(spec("all")).is_err());
+        assert!(sessions.is_active());
+
+        complete(&flows, &sessions, "1", "https://api.example.com/a?x=1");
+        complete(&flows, &sessions, "2", "https://other.example/");
+        complete(&flows, &sessions, "3", "https://api.example.com/b");
+        complete(&flows, &sessions, "4", "https://api.example.com/c");
+
+        // The oldest flow is dropped past the session's own cap
+        let ids = |id: &str| -> Vec<String> {
+            sessions
+                .flows(id)
+                .unwrap()
+                .into_iter()
+                .map(|flow| flow.id)
+                .collect()
+        };
+        assert_eq!(ids(&api.id), ["3", "4"]);
+        assert_eq!(ids(&all.id), ["1", "2", "3", "4"]);
+        let annotations = flows.get("3").unwrap().annotations;
+        assert!(annotations.contains(&(SESSION.to_string(), api.id.clone())));
+        assert!(annotations.contains(&(SESSION.to_string(), all.id.clone())));
+
+        let stopped = sessions.stop(&api.id).await.unwrap();
+        assert!(stopped.stopped_millis.is_some());
+        assert!(stopped.export_errors.is_empty());
+        // Stopped sessions capture nothing more
+        complete(&flows, &sessions, "5", "https://api.example.com/d");
+        assert_eq!(ids(&api.id), ["3", "4"]);
+
+        let har: Value = serde_json::from_slice(&std::fs::read(&har_path).unwrap()).unwrap();
+        let entries = har["log"]["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["request"]["url"], "https://api.example.com/b");
+        assert_eq!(entries[0]["response"]["status"], 200);
+    }
+
+    #[test]
+    fn export_targets_are_validated() {
+        let sessions = CaptureSessions::default();
+        for export in ["har:/tmp/x.har", "har://", "pcap:///tmp/x.pcap"] {
+            let spec = SessionSpec {
+                export: vec![export.to_string()],
+                ..spec("bad")
+            };
+            assert!(sessions.start(spec).is_err(), "{export}");
+        }
+        assert_eq!(
+            "json://flows.json".parse::<Export>().unwrap(),
+            Export::Json(PathBuf::from("flows.json"))
+        );
+    }
+
+    #[tokio::test]
+    async fn sessions_left_running_are_recovered_from_their_journals() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal_dir = dir.path().join("journal");
+        let json_path = dir.path().join("flows.json");
+        let flows = FlowLog::default();
+        let crashed = CaptureSessions::journaled(journal_dir.clone());
+        crashed
+            .start(SessionSpec {
+                export: vec![format!("json://{}", json_path.display())],
+                ..spec("checkout")
+            })
+            .unwrap();
+        complete(&flows, &crashed, "1", "https://shop.example/cart");
+        let req = hyper::Request::get("https://shop.example/pay")
+            .body(())
+            .unwrap();
+        flows.record(FlowRecord::new("2", &req));
+        crashed.begin(&flows, "2");
+        drop(crashed);
+
+        let sessions = CaptureSessions::journaled(journal_dir.clone());
+        let recovered = sessions.recover().await;
+        assert_eq!(recovered.len(), 1);
+        assert!(recovered[0].stopped_millis.is_some());
+        assert!(journal::leftovers(&journal_dir).unwrap().is_empty());
+
+        let exported: Vec<FlowRecord> =
+            serde_json::from_slice(&std::fs::read(&json_path).unwrap()).unwrap();
+        assert_eq!(exported.len(), 2);
+        assert_eq!(exported[0].status, Some(200));
+        assert_eq!(exported[1].status, None);
+        assert!(
+            exported[1]
+                .annotations
+                .contains(&(INCOMPLETE.to_string(), "interrupted".to_string()))
+        );
+    }
+}
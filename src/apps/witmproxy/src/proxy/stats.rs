@@ -0,0 +1,73 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use super::host_limits::{HostLimiter, HostQueueStatus};
+use super::listener::BoundListener;
+
+/// Live proxy state shared with the web server's health and status endpoints.
+///
+/// Cheap to clone; all clones observe the same counters.
+#[derive(Clone, Debug, Default)]
+pub struct ProxyStats {
+    listen_addr: Arc<OnceLock<SocketAddr>>,
+    listeners: Arc<OnceLock<Vec<BoundListener>>>,
+    active_connections: Arc<AtomicUsize>,
+    host_limiter: Arc<OnceLock<HostLimiter>>,
+}
+
+impl ProxyStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The address the primary proxy listener is bound to, once it has started
+    pub fn listen_addr(&self) -> Option<SocketAddr> {
+        self.listen_addr.get().copied()
+    }
+
+    pub(crate) fn set_listen_addr(&self, addr: SocketAddr) {
+        let _ = self.listen_addr.set(addr);
+    }
+
+    /// Every bound listener, starting with the primary one
+    pub fn listeners(&self) -> &[BoundListener] {
+        self.listeners.get().map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub(crate) fn set_listeners(&self, listeners: Vec<BoundListener>) {
+        let _ = self.listeners.set(listeners);
+    }
+
+    /// Number of client connections currently being served
+    pub fn active_connections(&self) -> usize {
+        self.active_connections.load(Ordering::Relaxed)
+    }
+
+    /// Upstream hosts with requests in flight under a per-host limit
+    pub fn host_queues(&self) -> Vec<HostQueueStatus> {
+        self.host_limiter
+            .get()
+            .map(HostLimiter::status)
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn set_host_limiter(&self, host_limiter: HostLimiter) {
+        let _ = self.host_limiter.set(host_limiter);
+    }
+
+    /// Track a new client connection until the returned guard is dropped
+    pub(crate) fn connection_opened(&self) -> ConnectionGuard {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(self.active_connections.clone())
+    }
+}
+
+/// Decrements the active connection count when dropped
+pub(crate) struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,276 @@
+//! Handling of CONNECT tunnels by protocol: the first bytes sent by the
+//! client decide whether a tunnel is intercepted as TLS, or forwarded as a
+//! raw stream (after offering it to `raw_stream` plugins). MQTT streams are
+//! decoded on the way, for `mqtt_message` plugins.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::{Buf, Bytes};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::sync::RwLock;
+use tracing::{debug, info};
+
+use super::listener::ListenerConfig;
+use super::{ProxyResult, dial, dispatch_event, mqtt, parse_authority_host_port};
+use crate::events::raw_stream::RawStream;
+use crate::plugins::registry::PluginRegistry;
+use crate::wasm::bindgen::witmproxy::plugin::capabilities::EventKind;
+
+/// How long to wait for the client's first bytes before assuming a protocol
+/// where the server speaks first (SMTP, FTP, IMAP, ...)
+pub const PREFACE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Maximum number of bytes read from the client for classification
+const PREFACE_LEN: usize = 1024;
+
+/// The protocol spoken in a tunnel, as classified from its first bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamProtocol {
+    Tls,
+    Http,
+    Ssh,
+    Mqtt,
+    /// Unrecognized, or the client sent nothing and waits for the server
+    Unknown,
+}
+
+impl StreamProtocol {
+    pub fn classify(preface: &[u8]) -> Self {
+        const HTTP_PREFIXES: &[&[u8]] = &[
+            b"GET ",
+            b"POST ",
+            b"PUT ",
+            b"HEAD ",
+            b"DELETE ",
+            b"OPTIONS ",
+            b"PATCH ",
+            b"TRACE ",
+            b"CONNECT ",
+            b"PRI * HTTP/2.0",
+        ];
+
+        match preface {
+            // TLS handshake record, protocol version 3.x
+            [0x16, 0x03, ..] => StreamProtocol::Tls,
+            p if p.starts_with(b"SSH-") => StreamProtocol::Ssh,
+            p if mqtt::connect_level(p).is_some() => StreamProtocol::Mqtt,
+            p if HTTP_PREFIXES.iter().any(|prefix| p.starts_with(prefix)) => StreamProtocol::Http,
+            _ => StreamProtocol::Unknown,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StreamProtocol::Tls => "tls",
+            StreamProtocol::Http => "http",
+            StreamProtocol::Ssh => "ssh",
+            StreamProtocol::Mqtt => "mqtt",
+            StreamProtocol::Unknown => "unknown",
+        }
+    }
+}
+
+impl std::fmt::Display for StreamProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Read the first bytes the client sends. Returns `None` if the client closed
+/// the tunnel, or an empty preface if it sent nothing within [PREFACE_TIMEOUT].
+pub async fn read_preface<IO>(io: &mut IO) -> io::Result<Option<Vec<u8>>>
+where
+    IO: AsyncRead + Unpin,
+{
+    let mut buf = vec![0u8; PREFACE_LEN];
+    match tokio::time::timeout(PREFACE_TIMEOUT, io.read(&mut buf)).await {
+        Ok(Ok(0)) => Ok(None),
+        Ok(Ok(n)) => {
+            buf.truncate(n);
+            Ok(Some(buf))
+        }
+        Ok(Err(e)) => Err(e),
+        Err(_) => Ok(Some(Vec::new())),
+    }
+}
+
+/// An IO stream which replays bytes already read from it before reading more
+pub struct PrefixedIo<IO> {
+    prefix: Bytes,
+    inner: IO,
+}
+
+impl<IO> PrefixedIo<IO> {
+    pub fn new(prefix: impl Into<Bytes>, inner: IO) -> Self {
+        Self {
+            prefix: prefix.into(),
+            inner,
+        }
+    }
+}
+
+impl<IO: AsyncRead + Unpin> AsyncRead for PrefixedIo<IO> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.prefix.has_remaining() {
+            let n = self.prefix.len().min(buf.remaining());
+            let chunk = self.prefix.split_to(n);
+            buf.put_slice(&chunk);
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<IO: AsyncWrite + Unpin> AsyncWrite for PrefixedIo<IO> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Offer a raw stream to plugins with a matching `raw_stream` capability,
+/// then forward it unless one of them closed it. MQTT streams are relayed
+/// packet by packet when plugins hold the `mqtt_message` capability.
+pub(crate) async fn handle_raw_stream<IO>(
+    client: IO,
+    preface: Vec<u8>,
+    protocol: StreamProtocol,
+    authority: &str,
+    plugin_registry: Option<Arc<RwLock<PluginRegistry>>>,
+    listener: Option<Arc<ListenerConfig>>,
+) -> ProxyResult<()>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    let (host, port) = parse_authority_host_port(authority, 443)?;
+
+    if let Some(registry) = &plugin_registry {
+        let registry = registry.read().await;
+        let event = RawStream {
+            host: host.clone(),
+            port,
+            protocol: protocol.to_string(),
+            preface: preface.clone(),
+        };
+        let wanted = match listener.as_deref().filter(|l| l.filters_plugins()) {
+            Some(listener) => {
+                let allowed = registry.plugins_matching(|id| listener.allows_plugin(id));
+                registry.can_handle_in_set(&event, &allowed)
+            }
+            None => registry.can_handle(&event),
+        };
+        if wanted
+            && let Err(e) = dispatch_event(&registry, Box::new(event), listener.as_deref()).await
+        {
+            info!(
+                "Closing {} stream to {}, rejected by plugins: {}",
+                protocol, authority, e
+            );
+            return Ok(());
+        }
+    }
+
+    if protocol == StreamProtocol::Mqtt
+        && let Some(registry) = plugin_registry
+        && registry.read().await.handles_kind(EventKind::MqttMessage)
+    {
+        return mqtt::relay(client, &preface, authority, registry, listener).await;
+    }
+
+    forward(client, &preface, authority).await
+}
+
+/// Forward a tunnel to its upstream as-is, replaying any bytes already read
+pub(crate) async fn forward<IO>(mut client: IO, preface: &[u8], authority: &str) -> ProxyResult<()>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    let (host, port) = parse_authority_host_port(authority, 443)?;
+    let mut upstream = dial::connect(&host, port).await?;
+    upstream.write_all(preface).await?;
+    let (sent, received) = tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+    debug!(
+        "Tunnel to {} finished: {} bytes client->upstream, {} bytes upstream->client",
+        authority,
+        sent + preface.len() as u64,
+        received
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_common_prefaces() {
+        assert_eq!(
+            StreamProtocol::classify(&[0x16, 0x03, 0x01, 0x02, 0x00]),
+            StreamProtocol::Tls
+        );
+        assert_eq!(
+            StreamProtocol::classify(b"GET / HTTP/1.1\r\n"),
+            StreamProtocol::Http
+        );
+        assert_eq!(
+            StreamProtocol::classify(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n"),
+            StreamProtocol::Http
+        );
+        assert_eq!(
+            StreamProtocol::classify(b"SSH-2.0-OpenSSH_9.6\r\n"),
+            StreamProtocol::Ssh
+        );
+        assert_eq!(
+            StreamProtocol::classify(b"\x10\x10\x00\x04MQTT\x04\x02\x00\x3c\x00\x04dev1"),
+            StreamProtocol::Mqtt
+        );
+        assert_eq!(StreamProtocol::classify(b""), StreamProtocol::Unknown);
+        assert_eq!(
+            StreamProtocol::classify(b"\x00\x01binary"),
+            StreamProtocol::Unknown
+        );
+    }
+
+    #[tokio::test]
+    async fn prefixed_io_replays_prefix() {
+        let (client, mut server) = tokio::io::duplex(64);
+        server.write_all(b" world").await.unwrap();
+        drop(server);
+
+        let mut io = PrefixedIo::new(b"hello".to_vec(), client);
+        let mut out = String::new();
+        io.read_to_string(&mut out).await.unwrap();
+        assert_eq!(out, "hello world");
+    }
+}
@@ -90,3 +90,253 @@ async fn test_http1_to_http2() {
     })
     .await;
 }
+
+#[tokio::test]
+async fn test_additional_listener_requires_auth() {
+    use crate::db::api_tokens::{ApiToken, Role};
+    use crate::proxy::listener::{ListenerConfig, MitmPolicy};
+
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let (ca, mut config) = create_ca_and_config().await;
+    let server_handle = create_hello_server("127.0.0.1", 1238, ca.clone(), Protocol::Http1).await;
+    let (mut registry, _temp_dir) = create_plugin_registry().await.unwrap();
+    register_noop_plugin(&mut registry).await.unwrap();
+    let pool = registry.db.pool.clone();
+    let (_, secret) = ApiToken::create(&pool, "guest", Role::Viewer, None)
+        .await
+        .unwrap();
+
+    config.proxy.proxy_bind_addr = Some("127.0.0.1:0".to_string());
+    config.proxy.listeners = vec![ListenerConfig {
+        name: Some("guests".to_string()),
+        bind_addr: "127.0.0.1:0".to_string(),
+        mitm: MitmPolicy::Passthrough,
+        plugins: Vec::new(),
+        require_auth: true,
+        tls_hostname: None,
+    }];
+
+    let mut proxy = ProxyServer::new(ca.clone(), Some(Arc::new(RwLock::new(registry))), config)
+        .unwrap()
+        .with_db_pool(pool);
+    proxy.start().await.unwrap();
+    let listeners = proxy.stats().listeners().to_vec();
+    assert_eq!(listeners.len(), 2);
+    assert_eq!(listeners[1].name, "guests");
+    let guest_addr = listeners[1].addr;
+
+    // Without credentials the tunnel is refused
+    let client = create_client(
+        ca.clone(),
+        &format!("http://{}", guest_addr),
+        Protocol::Http1,
+    )
+    .await;
+    assert!(client.get("https://127.0.0.1:1238").send().await.is_err());
+
+    // With an API token as the basic auth password the tunnel is passed through
+    let client = create_client(
+        ca,
+        &format!("http://guest:{}@{}", secret, guest_addr),
+        Protocol::Http1,
+    )
+    .await;
+    let text = client
+        .get("https://127.0.0.1:1238")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(text, "hello world");
+
+    proxy.shutdown().await;
+    server_handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_tls_listener_serves_connect_over_https() {
+    use crate::proxy::listener::{ListenerConfig, MitmPolicy};
+
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let (ca, mut config) = create_ca_and_config().await;
+    let server_handle = create_hello_server("127.0.0.1", 1239, ca.clone(), Protocol::Http1).await;
+    let (mut registry, _temp_dir) = create_plugin_registry().await.unwrap();
+    register_noop_plugin(&mut registry).await.unwrap();
+
+    config.proxy.proxy_bind_addr = Some("127.0.0.1:0".to_string());
+    config.proxy.listeners = vec![ListenerConfig {
+        name: Some("https".to_string()),
+        bind_addr: "127.0.0.1:0".to_string(),
+        mitm: MitmPolicy::Always,
+        plugins: Vec::new(),
+        require_auth: false,
+        tls_hostname: Some("localhost".to_string()),
+    }];
+    let mut proxy =
+        ProxyServer::new(ca.clone(), Some(Arc::new(RwLock::new(registry))), config).unwrap();
+    proxy.start().await.unwrap();
+    let port = proxy.stats().listeners()[1].addr.port();
+
+    // The client reaches the proxy itself over TLS, trusting the same CA
+    let client = create_client(ca, &format!("https://localhost:{}", port), Protocol::Http1).await;
+    let text = client
+        .get("https://127.0.0.1:1239")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(text, "hello world");
+
+    // Plain HTTP to the listener fails the handshake
+    let plain = create_client(
+        create_ca_and_config().await.0,
+        &format!("http://127.0.0.1:{}", port),
+        Protocol::Http1,
+    )
+    .await;
+    assert!(plain.get("https://127.0.0.1:1239").send().await.is_err());
+
+    proxy.shutdown().await;
+    server_handle.shutdown().await;
+}
+
+/// Open a CONNECT tunnel through the proxy and return the established stream
+async fn connect_tunnel(proxy: std::net::SocketAddr, target: &str) -> tokio::net::TcpStream {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = tokio::net::TcpStream::connect(proxy).await.unwrap();
+    stream
+        .write_all(format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n\r\n").as_bytes())
+        .await
+        .unwrap();
+    let mut buf = [0u8; 1024];
+    let n = stream.read(&mut buf).await.unwrap();
+    assert!(String::from_utf8_lossy(&buf[..n]).starts_with("HTTP/1.1 200"));
+    stream
+}
+
+#[tokio::test]
+async fn test_raw_streams_are_forwarded_when_intercepting() {
+    use crate::proxy::listener::MitmPolicy;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let (ca, mut config) = create_ca_and_config().await;
+    config.proxy.proxy_bind_addr = Some("127.0.0.1:0".to_string());
+    config.proxy.listeners = vec![crate::proxy::listener::ListenerConfig {
+        name: Some("intercept-all".to_string()),
+        bind_addr: "127.0.0.1:0".to_string(),
+        mitm: MitmPolicy::Always,
+        plugins: Vec::new(),
+        require_auth: false,
+        tls_hostname: None,
+    }];
+    let mut proxy = ProxyServer::new(ca, None, config).unwrap();
+    proxy.start().await.unwrap();
+    let proxy_addr = proxy.stats().listeners()[1].addr;
+
+    // Upstream greets first (like SMTP), then echoes a line back
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut conn, _)) = upstream.accept().await {
+            tokio::spawn(async move {
+                conn.write_all(b"220 ready\r\n").await.unwrap();
+                let mut buf = [0u8; 64];
+                let n = conn.read(&mut buf).await.unwrap();
+                conn.write_all(&buf[..n]).await.unwrap();
+            });
+        }
+    });
+
+    // Server-speaks-first protocol: the client waits for the banner
+    let mut tunnel = connect_tunnel(proxy_addr, &upstream_addr.to_string()).await;
+    let mut buf = [0u8; 64];
+    let n = tunnel.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"220 ready\r\n");
+    tunnel.write_all(b"EHLO witm\r\n").await.unwrap();
+    let n = tunnel.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"EHLO witm\r\n");
+
+    // Client-speaks-first, non-TLS bytes are replayed to the upstream rather
+    // than fed to the TLS acceptor
+    let mut tunnel = connect_tunnel(proxy_addr, &upstream_addr.to_string()).await;
+    tunnel.write_all(b"\x00\x01hello").await.unwrap();
+    let mut received = Vec::new();
+    while received.len() < b"220 ready\r\n\x00\x01hello".len() {
+        let n = tunnel.read(&mut buf).await.unwrap();
+        assert!(n > 0);
+        received.extend_from_slice(&buf[..n]);
+    }
+    assert_eq!(received, b"220 ready\r\n\x00\x01hello");
+
+    proxy.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_plain_http_counts_towards_quotas() {
+    use crate::proxy::traffic::{ClientProfile, Period};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (ca, mut config) = create_ca_and_config().await;
+    config.proxy.proxy_bind_addr = Some("127.0.0.1:0".to_string());
+    config.proxy.account_traffic = true;
+    config.proxy.client_profiles = vec![ClientProfile {
+        name: "lab".to_string(),
+        clients: vec!["127.0.0.1".parse().unwrap()],
+        daily_soft_limit: None,
+        daily_hard_limit: Some(10),
+        monthly_soft_limit: None,
+        monthly_hard_limit: None,
+    }];
+    let mut proxy = ProxyServer::new(ca.clone(), None, config).unwrap();
+    proxy.start().await.unwrap();
+    let proxy_addr = proxy.listen_addr().unwrap();
+
+    let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut conn, _)) = upstream.accept().await {
+            tokio::spawn(async move {
+                let mut buf = [0u8; 1024];
+                let _ = conn.read(&mut buf).await;
+                let _ = conn
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\nConnection: close\r\n\r\nhello world",
+                    )
+                    .await;
+            });
+        }
+    });
+
+    let client = create_client(ca, &format!("http://{}", proxy_addr), Protocol::Http1).await;
+    let url = format!("http://{}", upstream_addr);
+    let text = client.get(&url).send().await.unwrap().text().await.unwrap();
+    assert_eq!(text, "hello world");
+
+    // Bodies are counted once the proxy has dropped them
+    let traffic = proxy.traffic();
+    for _ in 0..50 {
+        if traffic.used("lab", Period::Day, chrono::Utc::now()) >= 10 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert!(traffic.used("lab", Period::Day, chrono::Utc::now()) >= 10);
+
+    let resp = client.get(&url).send().await.unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+
+    // Tunnels that aren't intercepted are refused too
+    let resp = client
+        .get(format!("https://{}", upstream_addr))
+        .send()
+        .await;
+    assert!(resp.is_err());
+
+    proxy.shutdown().await;
+}
@@ -0,0 +1,303 @@
+//! Timelines of intercepted flows, answering "why was this request slow".
+//!
+//! Each flow in the flow log gets a timeline of spans: the client's
+//! connection and TLS handshake (on the first flow of a connection), each
+//! plugin which handled it and with what outcome, the upstream until its
+//! first byte and until its body completed, and writing the response to the
+//! client. Unlike [crate::proxy::flow_trace], every flow is timed, so the
+//! slow one needn't be reproduced with a debug header.
+//!
+//! Timelines are served as waterfalls by `/api/manage/flows/{id}/timeline`:
+//! spans sorted by start, offset from the start of the flow.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use salvo::oapi::ToSchema;
+use serde::Serialize;
+
+use crate::proxy::flows::DEFAULT_FLOW_LOG_CAPACITY;
+
+tokio::task_local! {
+    static CURRENT: FlowTimeline;
+}
+
+/// A stage of handling a flow, or one still in progress
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Span {
+    /// What the span is about, ex: `tls`, `plugin` or `upstream`
+    pub phase: String,
+    pub label: String,
+    /// Microseconds since the start of the flow
+    pub start_micros: u64,
+    /// How long the span took, once it has ended
+    pub duration_micros: Option<u64>,
+    /// How the span ended, ex: the status of the upstream's response
+    pub outcome: Option<String>,
+}
+
+/// A flow's spans, sorted by start, for rendering as a waterfall
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Waterfall {
+    pub flow_id: String,
+    /// Microseconds from the start of the flow to the end of its last span
+    pub duration_micros: u64,
+    pub spans: Vec<Span>,
+}
+
+/// When a client's connection was handed to the proxy, and its TLS
+/// handshake started and finished
+#[derive(Debug, Clone, Copy)]
+pub struct Handshake {
+    pub connected: Instant,
+    pub tls_started: Instant,
+    pub tls_finished: Instant,
+}
+
+/// The timeline of a flow. Cheap to clone; all clones share the same spans.
+#[derive(Clone)]
+pub struct FlowTimeline {
+    id: String,
+    started: Instant,
+    spans: Arc<Mutex<Vec<Span>>>,
+}
+
+impl FlowTimeline {
+    fn new(id: &str, started: Instant) -> Self {
+        Self {
+            id: id.to_string(),
+            started,
+            spans: Arc::default(),
+        }
+    }
+
+    fn micros(&self, at: Instant) -> u64 {
+        at.saturating_duration_since(self.started).as_micros() as u64
+    }
+
+    /// Add a span which ran from `start` to `end`
+    pub fn record(
+        &self,
+        phase: &str,
+        label: String,
+        start: Instant,
+        end: Instant,
+        outcome: Option<String>,
+    ) {
+        let span = Span {
+            phase: phase.to_string(),
+            label,
+            start_micros: self.micros(start),
+            duration_micros: Some(end.saturating_duration_since(start).as_micros() as u64),
+            outcome,
+        };
+        self.spans.lock().unwrap().push(span);
+    }
+
+    /// Add a span starting now, which ends when the returned [OpenSpan] is
+    /// dropped
+    pub fn open(&self, phase: &str, label: String) -> OpenSpan {
+        let now = Instant::now();
+        let mut spans = self.spans.lock().unwrap();
+        spans.push(Span {
+            phase: phase.to_string(),
+            label,
+            start_micros: self.micros(now),
+            duration_micros: None,
+            outcome: None,
+        });
+        OpenSpan {
+            timeline: self.clone(),
+            index: spans.len() - 1,
+            started: now,
+        }
+    }
+
+    pub fn waterfall(&self) -> Waterfall {
+        let mut spans = self.spans.lock().unwrap().clone();
+        spans.sort_by_key(|span| span.start_micros);
+        let duration_micros = spans
+            .iter()
+            .map(|span| span.start_micros + span.duration_micros.unwrap_or_default())
+            .max()
+            .unwrap_or_default();
+        Waterfall {
+            flow_id: self.id.clone(),
+            duration_micros,
+            spans,
+        }
+    }
+}
+
+/// A span still in progress, ending when dropped, ex: held by a response
+/// body until it has been streamed
+pub struct OpenSpan {
+    timeline: FlowTimeline,
+    index: usize,
+    started: Instant,
+}
+
+impl Drop for OpenSpan {
+    fn drop(&mut self) {
+        let duration_micros = self.started.elapsed().as_micros() as u64;
+        if let Some(span) = self.timeline.spans.lock().unwrap().get_mut(self.index) {
+            span.duration_micros = Some(duration_micros);
+        }
+    }
+}
+
+/// Run `fut` with `timeline` as the current timeline, so [record] and
+/// [open] add to it
+pub async fn scoped<F: Future>(timeline: Option<FlowTimeline>, fut: F) -> F::Output {
+    match timeline {
+        Some(timeline) => CURRENT.scope(timeline, fut).await,
+        None => fut.await,
+    }
+}
+
+/// Add a span from `start` to now to the timeline of the flow being
+/// handled, if any. `label` and `outcome` are only built for timed flows.
+pub fn record(
+    phase: &str,
+    label: impl FnOnce() -> String,
+    start: Instant,
+    outcome: impl FnOnce() -> String,
+) {
+    let _ = CURRENT.try_with(|timeline| {
+        timeline.record(phase, label(), start, Instant::now(), Some(outcome()))
+    });
+}
+
+/// Open a span on the timeline of the flow being handled, if any
+pub fn open(phase: &str, label: impl FnOnce() -> String) -> Option<OpenSpan> {
+    CURRENT
+        .try_with(|timeline| timeline.open(phase, label()))
+        .ok()
+}
+
+/// The timelines of the latest flows, as many as the flow log keeps by
+/// default. Cheap to clone; all clones share the same timelines.
+#[derive(Clone)]
+pub struct FlowTimelines {
+    timelines: Arc<Mutex<VecDeque<FlowTimeline>>>,
+    capacity: usize,
+}
+
+impl Default for FlowTimelines {
+    fn default() -> Self {
+        Self::new(DEFAULT_FLOW_LOG_CAPACITY)
+    }
+}
+
+impl FlowTimelines {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            timelines: Arc::default(),
+            capacity,
+        }
+    }
+
+    /// Start the timeline of flow `id`, from the `handshake` of its
+    /// connection if it's the first flow on it, otherwise from now
+    pub fn start(&self, id: &str, handshake: Option<Handshake>) -> FlowTimeline {
+        let timeline = match handshake {
+            Some(handshake) => {
+                let timeline = FlowTimeline::new(id, handshake.connected);
+                timeline.record(
+                    "connect",
+                    "client connection".to_string(),
+                    handshake.connected,
+                    handshake.tls_started,
+                    None,
+                );
+                timeline.record(
+                    "tls",
+                    "client handshake".to_string(),
+                    handshake.tls_started,
+                    handshake.tls_finished,
+                    None,
+                );
+                timeline
+            }
+            None => FlowTimeline::new(id, Instant::now()),
+        };
+        if self.capacity == 0 {
+            return timeline;
+        }
+        let mut timelines = self.timelines.lock().unwrap();
+        if timelines.len() == self.capacity {
+            timelines.pop_front();
+        }
+        timelines.push_back(timeline.clone());
+        timeline
+    }
+
+    /// The waterfall of flow `id`, if its timeline is still kept
+    pub fn get(&self, id: &str) -> Option<Waterfall> {
+        self.timelines
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|timeline| timeline.id == id)
+            .map(FlowTimeline::waterfall)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn timelines_become_waterfalls_sorted_by_start() {
+        let timelines = FlowTimelines::new(2);
+        let connected = Instant::now() - Duration::from_millis(10);
+        let handshake = Handshake {
+            connected,
+            tls_started: connected + Duration::from_millis(1),
+            tls_finished: connected + Duration::from_millis(5),
+        };
+        let timeline = timelines.start("1", Some(handshake));
+        let body = scoped(Some(timeline.clone()), async {
+            let entered = Instant::now();
+            record(
+                "plugin",
+                || "ops/auth".to_string(),
+                entered,
+                || "handled".to_string(),
+            );
+            open("upstream-body", || "https://example.com/".to_string())
+        })
+        .await;
+        record(
+            "plugin",
+            || unreachable!("not timed"),
+            Instant::now(),
+            || unreachable!("not timed"),
+        );
+
+        let waterfall = timelines.get("1").unwrap();
+        let phases: Vec<_> = waterfall.spans.iter().map(|s| s.phase.as_str()).collect();
+        assert_eq!(phases, ["connect", "tls", "plugin", "upstream-body"]);
+        assert_eq!(waterfall.spans[1].start_micros, 1000);
+        assert_eq!(waterfall.spans[1].duration_micros, Some(4000));
+        assert_eq!(waterfall.spans[2].outcome.as_deref(), Some("handled"));
+        // Still streaming
+        assert_eq!(waterfall.spans[3].duration_micros, None);
+        drop(body);
+        assert!(
+            timelines.get("1").unwrap().spans[3]
+                .duration_micros
+                .is_some()
+        );
+        assert!(timelines.get("1").unwrap().duration_micros >= 5000);
+
+        // Later flows on the connection start when they're received
+        let later = timelines.start("2", None);
+        assert!(later.waterfall().spans.is_empty());
+        timelines.start("3", None);
+        assert!(timelines.get("1").is_none());
+    }
+}
@@ -0,0 +1,355 @@
+//! TLS version, cipher suite and ALPN policies for the handshakes the proxy
+//! makes, both with clients (as the intercepted server) and with upstream
+//! servers, to reproduce issues which only show up against, say, a TLS
+//! 1.2-only server.
+//!
+//! Policies for every host are set with `tls.client_*` and `tls.upstream_*`,
+//! and overridden for some hosts with `[[tls.host_policies]]` tables:
+//!
+//! ```toml
+//! [tls]
+//! upstream_min_version = "1.2"
+//!
+//! [[tls.host_policies]]
+//! host = "*.legacy.example"
+//! client = { max_version = "1.2", alpn = ["http/1.1"] }
+//! upstream = { max_version = "1.2", cipher_suites = ["TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256"] }
+//! ```
+//!
+//! Cipher suites are named as in the TLS registry, ex:
+//! `TLS13_AES_256_GCM_SHA384`, and offered in the given order. The first
+//! matching host policy applies. Flows to hosts with an egress route are
+//! sent with the route's client, which follows the `tls.upstream_*`
+//! policy only.
+//!
+//! The version, cipher suite and ALPN protocol negotiated with the client
+//! are added to each intercepted flow as annotations.
+
+use std::sync::Arc;
+
+use reqwest::ClientBuilder;
+use rustls::crypto::CryptoProvider;
+use rustls::version::{TLS12, TLS13};
+use rustls::{ProtocolVersion, SupportedProtocolVersion};
+use serde::{Deserialize, Serialize};
+
+use crate::config::TlsConfig;
+use crate::proxy::flows::{TLS_ALPN, TLS_CIPHER_SUITE, TLS_VERSION};
+use crate::proxy::host_limits::matches_host;
+use crate::proxy::{ProxyError, ProxyResult, UpstreamClient};
+
+/// A TLS protocol version
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, clap::ValueEnum,
+)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    #[value(name = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    #[value(name = "1.3")]
+    Tls13,
+}
+
+impl TlsVersion {
+    fn supported(self) -> &'static SupportedProtocolVersion {
+        match self {
+            TlsVersion::Tls12 => &TLS12,
+            TlsVersion::Tls13 => &TLS13,
+        }
+    }
+}
+
+/// What one side of a connection may negotiate. Unset fields leave rustls'
+/// defaults in place.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsPolicy {
+    #[serde(default)]
+    pub min_version: Option<TlsVersion>,
+    #[serde(default)]
+    pub max_version: Option<TlsVersion>,
+    /// Cipher suites offered, in order
+    #[serde(default)]
+    pub cipher_suites: Vec<String>,
+    /// ALPN protocols advertised, in order, ex: `["http/1.1"]`
+    #[serde(default)]
+    pub alpn: Vec<String>,
+}
+
+impl TlsPolicy {
+    /// The policy for handshakes with clients, for every host
+    pub fn client(config: &TlsConfig) -> Self {
+        Self {
+            min_version: config.client_min_version,
+            max_version: config.client_max_version,
+            cipher_suites: config.client_cipher_suites.clone(),
+            alpn: config.client_alpn.clone(),
+        }
+    }
+
+    /// The policy for handshakes with upstream servers, for every host
+    pub fn upstream(config: &TlsConfig) -> Self {
+        Self {
+            min_version: config.upstream_min_version,
+            max_version: config.upstream_max_version,
+            cipher_suites: config.upstream_cipher_suites.clone(),
+            alpn: config.upstream_alpn.clone(),
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// This policy, with the fields it leaves unset taken from `fallback`
+    pub fn or(&self, fallback: &TlsPolicy) -> Self {
+        let or_vec = |set: &Vec<String>, fallback: &Vec<String>| {
+            if set.is_empty() { fallback } else { set }.clone()
+        };
+        Self {
+            min_version: self.min_version.or(fallback.min_version),
+            max_version: self.max_version.or(fallback.max_version),
+            cipher_suites: or_vec(&self.cipher_suites, &fallback.cipher_suites),
+            alpn: or_vec(&self.alpn, &fallback.alpn),
+        }
+    }
+
+    /// The protocol versions allowed, newest first
+    pub fn versions(&self) -> ProxyResult<Vec<&'static SupportedProtocolVersion>> {
+        let min = self.min_version.unwrap_or(TlsVersion::Tls12);
+        let max = self.max_version.unwrap_or(TlsVersion::Tls13);
+        let versions: Vec<_> = [TlsVersion::Tls13, TlsVersion::Tls12]
+            .into_iter()
+            .filter(|version| (min..=max).contains(version))
+            .map(TlsVersion::supported)
+            .collect();
+        if versions.is_empty() {
+            return Err(ProxyError::Generic(format!(
+                "TLS minimum version {:?} is above the maximum {:?}",
+                min, max
+            )));
+        }
+        Ok(versions)
+    }
+
+    /// `base`, offering only the policy's cipher suites in its order
+    pub fn provider(&self, base: CryptoProvider) -> ProxyResult<CryptoProvider> {
+        if self.cipher_suites.is_empty() {
+            return Ok(base);
+        }
+        let cipher_suites = self
+            .cipher_suites
+            .iter()
+            .map(|name| {
+                base.cipher_suites
+                    .iter()
+                    .find(|suite| suite.suite().as_str() == Some(name.as_str()))
+                    .copied()
+                    .ok_or_else(|| {
+                        ProxyError::Generic(format!("Unsupported cipher suite {}", name))
+                    })
+            })
+            .collect::<ProxyResult<_>>()?;
+        Ok(CryptoProvider {
+            cipher_suites,
+            ..base
+        })
+    }
+
+    /// The ALPN protocols to advertise, or `default` if the policy sets none
+    pub fn alpn_protocols(&self, default: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+        if self.alpn.is_empty() {
+            return default;
+        }
+        self.alpn.iter().map(|p| p.as_bytes().to_vec()).collect()
+    }
+
+    /// Check that a config can be built with the policy
+    fn validate(&self) -> ProxyResult<()> {
+        let provider = self.provider(rustls::crypto::ring::default_provider())?;
+        rustls::ServerConfig::builder_with_provider(Arc::new(provider))
+            .with_protocol_versions(&self.versions()?)
+            .map_err(ProxyError::Tls)?;
+        Ok(())
+    }
+}
+
+/// Policies overriding the `tls.client_*` and `tls.upstream_*` ones for
+/// matching hosts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsHostPolicy {
+    /// Host name, or `*.` followed by a domain to match its subdomains
+    pub host: String,
+    #[serde(default)]
+    pub client: TlsPolicy,
+    #[serde(default)]
+    pub upstream: TlsPolicy,
+}
+
+#[derive(Debug)]
+struct HostPolicy {
+    host: String,
+    client: TlsPolicy,
+    upstream: Option<UpstreamClient>,
+}
+
+/// The TLS policies in effect, with a client for each host overriding the
+/// upstream policy. Cheap to clone; all clones share the same clients.
+#[derive(Debug, Clone, Default)]
+pub struct TlsPolicies {
+    client: TlsPolicy,
+    hosts: Arc<Vec<HostPolicy>>,
+}
+
+impl TlsPolicies {
+    /// Resolve the policies of `config`, building upstream clients for host
+    /// overrides from builders configured by `builder` with the given policy
+    pub fn new(
+        config: &TlsConfig,
+        builder: impl Fn(&TlsPolicy) -> ProxyResult<ClientBuilder>,
+    ) -> ProxyResult<Self> {
+        let client = TlsPolicy::client(config);
+        let upstream = TlsPolicy::upstream(config);
+        client.validate()?;
+        upstream.validate()?;
+        let hosts = config
+            .host_policies
+            .iter()
+            .map(|policy| {
+                let client = policy.client.or(&client);
+                client.validate()?;
+                let upstream = if policy.upstream.is_default() {
+                    None
+                } else {
+                    let upstream = policy.upstream.or(&upstream);
+                    upstream.validate()?;
+                    Some(builder(&upstream)?.build().map_err(|e| {
+                        ProxyError::Generic(format!(
+                            "Failed to build client for TLS policy of {}: {}",
+                            policy.host, e
+                        ))
+                    })?)
+                };
+                Ok(HostPolicy {
+                    host: policy.host.clone(),
+                    client,
+                    upstream,
+                })
+            })
+            .collect::<ProxyResult<_>>()?;
+        Ok(Self {
+            client,
+            hosts: Arc::new(hosts),
+        })
+    }
+
+    fn host_policy(&self, host: &str) -> Option<&HostPolicy> {
+        self.hosts
+            .iter()
+            .find(|policy| matches_host(&policy.host, host))
+    }
+
+    /// The policy for handshakes with clients connecting to `host`
+    pub fn client_policy(&self, host: &str) -> &TlsPolicy {
+        self.host_policy(host)
+            .map_or(&self.client, |policy| &policy.client)
+    }
+
+    /// The client flows to `host` are sent with, if a host policy overrides
+    /// the upstream one
+    pub fn client_for(&self, host: &str) -> Option<&UpstreamClient> {
+        self.host_policy(host)
+            .and_then(|policy| policy.upstream.as_ref())
+    }
+}
+
+/// The flow annotations describing what `connection` negotiated
+pub fn negotiated(connection: &rustls::ServerConnection) -> Vec<(&'static str, String)> {
+    let mut annotations = Vec::new();
+    if let Some(version) = connection.protocol_version() {
+        let version = match version {
+            ProtocolVersion::TLSv1_2 => "1.2".to_string(),
+            ProtocolVersion::TLSv1_3 => "1.3".to_string(),
+            other => format!("{:?}", other),
+        };
+        annotations.push((TLS_VERSION, version));
+    }
+    if let Some(suite) = connection.negotiated_cipher_suite() {
+        annotations.push((TLS_CIPHER_SUITE, format!("{:?}", suite.suite())));
+    }
+    if let Some(alpn) = connection.alpn_protocol() {
+        annotations.push((TLS_ALPN, String::from_utf8_lossy(alpn).into_owned()));
+    }
+    annotations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(min: Option<TlsVersion>, max: Option<TlsVersion>, suites: &[&str]) -> TlsPolicy {
+        TlsPolicy {
+            min_version: min,
+            max_version: max,
+            cipher_suites: suites.iter().map(|s| s.to_string()).collect(),
+            alpn: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn policies_restrict_versions_and_suites() {
+        let tls12 = policy(None, Some(TlsVersion::Tls12), &[]);
+        assert_eq!(tls12.versions().unwrap(), [&TLS12]);
+        assert_eq!(TlsPolicy::default().versions().unwrap(), [&TLS13, &TLS12]);
+        assert!(
+            policy(Some(TlsVersion::Tls13), Some(TlsVersion::Tls12), &[])
+                .versions()
+                .is_err()
+        );
+
+        let suites = policy(
+            None,
+            None,
+            &["TLS13_AES_256_GCM_SHA384", "TLS13_AES_128_GCM_SHA256"],
+        );
+        let provider = suites
+            .provider(rustls::crypto::ring::default_provider())
+            .unwrap();
+        assert_eq!(
+            provider
+                .cipher_suites
+                .iter()
+                .map(|suite| suite.suite())
+                .collect::<Vec<_>>(),
+            [
+                rustls::CipherSuite::TLS13_AES_256_GCM_SHA384,
+                rustls::CipherSuite::TLS13_AES_128_GCM_SHA256
+            ]
+        );
+        assert!(suites.validate().is_ok());
+        assert!(
+            policy(None, None, &["TLS_RSA_WITH_RC4_128_MD5"])
+                .validate()
+                .is_err()
+        );
+        // TLS 1.2 can't be negotiated with only TLS 1.3 suites
+        assert!(suites.or(&tls12).validate().is_err());
+    }
+
+    #[test]
+    fn host_policies_override_the_global_one() {
+        let global = policy(Some(TlsVersion::Tls12), None, &[]);
+        let host = TlsPolicy {
+            alpn: vec!["http/1.1".to_string()],
+            ..policy(None, Some(TlsVersion::Tls12), &[])
+        };
+        let merged = host.or(&global);
+        assert_eq!(merged.min_version, Some(TlsVersion::Tls12));
+        assert_eq!(merged.max_version, Some(TlsVersion::Tls12));
+        assert_eq!(merged.alpn_protocols(Vec::new()), [b"http/1.1".to_vec()]);
+        assert_eq!(
+            global.alpn_protocols(vec![b"h2".to_vec()]),
+            [b"h2".to_vec()]
+        );
+    }
+}
@@ -0,0 +1,536 @@
+//! Bytes clients transfer through the proxy, by client profile and host,
+//! with optional quotas, ex: for a lab network or a household's devices.
+//!
+//! Clients are grouped into profiles configured as
+//! `[[proxy.client_profiles]]` tables; clients in none are counted under
+//! [DEFAULT_PROFILE]:
+//!
+//! ```toml
+//! [[proxy.client_profiles]]
+//! name = "kids"
+//! clients = ["192.168.1.40", "192.168.1.41"]
+//! daily_soft_limit = 500_000_000
+//! monthly_hard_limit = 20_000_000_000
+//! ```
+//!
+//! With `proxy.account_traffic`, the request and response bodies of
+//! intercepted and plain HTTP flows are counted as they stream, and the
+//! bytes of tunnels passed through once they close, into daily and monthly
+//! counters (UTC days and months) saved by [crate::db::traffic] and served
+//! by `/api/manage/traffic`. Once a profile has transferred its soft limit
+//! for the period, the next page each of its clients loads is replaced by a
+//! warning, once a period; once it has transferred its hard limit, its flows
+//! and new tunnels are refused with `proxy.quota_exceeded` until the period
+//! ends.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use anyhow::{Result, bail};
+use bytes::Bytes;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::BodyExt;
+use http_body_util::combinators::UnsyncBoxBody;
+use hyper::Method;
+use hyper::header::{ACCEPT, HeaderMap};
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+
+/// Profile of the clients in no configured profile
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Clients whose traffic is counted, and limited, together
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ClientProfile {
+    pub name: String,
+    /// Addresses of the clients in the profile
+    #[serde(default)]
+    #[salvo(schema(value_type = Vec<String>))]
+    pub clients: Vec<IpAddr>,
+    /// Bytes a day after which clients are warned
+    #[serde(default)]
+    pub daily_soft_limit: Option<u64>,
+    /// Bytes a day after which flows are refused
+    #[serde(default)]
+    pub daily_hard_limit: Option<u64>,
+    /// Bytes a month after which clients are warned
+    #[serde(default)]
+    pub monthly_soft_limit: Option<u64>,
+    /// Bytes a month after which flows are refused
+    #[serde(default)]
+    pub monthly_hard_limit: Option<u64>,
+}
+
+impl ClientProfile {
+    fn unlimited(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            clients: Vec::new(),
+            daily_soft_limit: None,
+            daily_hard_limit: None,
+            monthly_soft_limit: None,
+            monthly_hard_limit: None,
+        }
+    }
+
+    /// The soft and hard limits for `period`
+    fn limits(&self, period: Period) -> (Option<u64>, Option<u64>) {
+        match period {
+            Period::Day => (self.daily_soft_limit, self.daily_hard_limit),
+            Period::Month => (self.monthly_soft_limit, self.monthly_hard_limit),
+        }
+    }
+}
+
+/// A period traffic is counted over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    Month,
+}
+
+impl Period {
+    pub const ALL: [Period; 2] = [Period::Day, Period::Month];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Period::Day => "day",
+            Period::Month => "month",
+        }
+    }
+
+    /// "daily" or "monthly"
+    pub fn adjective(self) -> &'static str {
+        match self {
+            Period::Day => "daily",
+            Period::Month => "monthly",
+        }
+    }
+
+    /// Unix timestamp of the start of the period `now` is in
+    pub fn start(self, now: DateTime<Utc>) -> i64 {
+        let date = match self {
+            Period::Day => now.date_naive(),
+            Period::Month => NaiveDate::from_ymd_opt(now.year(), now.month(), 1)
+                .expect("the first of the month exists"),
+        };
+        date.and_hms_opt(0, 0, 0)
+            .expect("midnight exists")
+            .and_utc()
+            .timestamp()
+    }
+}
+
+/// A limit a profile has reached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaReached {
+    pub profile: String,
+    pub period: Period,
+    /// Bytes transferred in the period
+    pub used: u64,
+    pub limit: u64,
+}
+
+/// What a profile's traffic allows of a client's next flow
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuotaCheck {
+    Allowed,
+    /// The soft limit was reached, and the client hasn't been warned yet
+    Warn(QuotaReached),
+    /// The hard limit was reached
+    Exceeded(QuotaReached),
+}
+
+/// Whether a request loads a page, which a warning can replace without
+/// breaking the page
+pub fn is_page_load(method: &Method, headers: &HeaderMap) -> bool {
+    method == Method::GET
+        && headers
+            .get(ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html"))
+}
+
+/// Traffic of a profile and host in a period, as saved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Counted {
+    pub profile: String,
+    pub host: String,
+    pub period: Period,
+    pub period_start: i64,
+    pub bytes: u64,
+}
+
+/// Bytes a host has been sent and received for a profile in a period
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct HostTraffic {
+    pub host: String,
+    pub bytes: u64,
+}
+
+/// A profile's traffic in a period, and its limits
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ProfileTraffic {
+    pub profile: String,
+    pub bytes: u64,
+    pub soft_limit: Option<u64>,
+    pub hard_limit: Option<u64>,
+    /// Hosts by bytes, most first
+    pub hosts: Vec<HostTraffic>,
+}
+
+/// The traffic of every profile in a period
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct TrafficReport {
+    pub period: Period,
+    /// Unix timestamp of the start of the period
+    pub period_start: i64,
+    pub profiles: Vec<ProfileTraffic>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Bytes of each profile in the current period, by period, with its start
+    totals: HashMap<(String, Period), (i64, u64)>,
+    /// Bytes not saved yet, by profile, host, period and period start
+    pending: HashMap<(String, String, Period, i64), u64>,
+    /// Clients warned of a soft limit, by period and period start
+    warned: HashSet<(IpAddr, Period, i64)>,
+}
+
+/// Traffic counted by client profile and host. Cheap to clone; all clones
+/// share the same counters.
+#[derive(Clone, Default)]
+pub struct Traffic {
+    profiles: Arc<Vec<ClientProfile>>,
+    state: Arc<Mutex<State>>,
+}
+
+impl Traffic {
+    pub fn new(profiles: Vec<ClientProfile>) -> Result<Self> {
+        let mut names = HashSet::new();
+        let mut clients = HashSet::new();
+        for profile in &profiles {
+            if !names.insert(profile.name.as_str()) {
+                bail!("Client profile {} is defined twice", profile.name);
+            }
+            for client in &profile.clients {
+                if !clients.insert(client.to_canonical()) {
+                    bail!("Client {} is in more than one profile", client);
+                }
+            }
+        }
+        Ok(Self {
+            profiles: Arc::new(profiles),
+            state: Arc::default(),
+        })
+    }
+
+    /// Name of the profile `client` is in
+    pub fn profile_for(&self, client: IpAddr) -> &str {
+        let client = client.to_canonical();
+        self.profiles
+            .iter()
+            .find(|profile| profile.clients.iter().any(|c| c.to_canonical() == client))
+            .map_or(DEFAULT_PROFILE, |profile| profile.name.as_str())
+    }
+
+    fn profile(&self, name: &str) -> ClientProfile {
+        self.profiles
+            .iter()
+            .find(|profile| profile.name == name)
+            .cloned()
+            .unwrap_or_else(|| ClientProfile::unlimited(name))
+    }
+
+    /// Count `bytes` transferred with `host` for `profile` at `now`
+    pub fn count(&self, profile: &str, host: &str, bytes: u64, now: DateTime<Utc>) {
+        if bytes == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        for period in Period::ALL {
+            let start = period.start(now);
+            let total = state
+                .totals
+                .entry((profile.to_string(), period))
+                .or_insert((start, 0));
+            if total.0 != start {
+                *total = (start, 0);
+            }
+            total.1 += bytes;
+            *state
+                .pending
+                .entry((profile.to_string(), host.to_string(), period, start))
+                .or_default() += bytes;
+        }
+    }
+
+    /// Add `bytes` saved before to `profile`'s total for the period starting
+    /// at `period_start`
+    pub fn load(&self, profile: &str, period: Period, period_start: i64, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        let total = state
+            .totals
+            .entry((profile.to_string(), period))
+            .or_insert((period_start, 0));
+        if total.0 == period_start {
+            total.1 += bytes;
+        }
+    }
+
+    /// Bytes `profile` has transferred in the period `now` is in
+    pub fn used(&self, profile: &str, period: Period, now: DateTime<Utc>) -> u64 {
+        match self
+            .state
+            .lock()
+            .unwrap()
+            .totals
+            .get(&(profile.to_string(), period))
+        {
+            Some((start, bytes)) if *start == period.start(now) => *bytes,
+            _ => 0,
+        }
+    }
+
+    /// Check `client`'s profile against its limits. Soft limits only warn
+    /// clients loading a page, once a period.
+    pub fn check(&self, client: IpAddr, page_load: bool, now: DateTime<Utc>) -> QuotaCheck {
+        let profile = self.profile(self.profile_for(client));
+        let mut warning = None;
+        for period in Period::ALL {
+            let (soft, hard) = profile.limits(period);
+            if soft.is_none() && hard.is_none() {
+                continue;
+            }
+            let used = self.used(&profile.name, period, now);
+            let reached = |limit| QuotaReached {
+                profile: profile.name.clone(),
+                period,
+                used,
+                limit,
+            };
+            if let Some(limit) = hard.filter(|limit| used >= *limit) {
+                return QuotaCheck::Exceeded(reached(limit));
+            }
+            if let Some(limit) = soft.filter(|limit| page_load && used >= *limit) {
+                warning.get_or_insert((reached(limit), period));
+            }
+        }
+        let Some((reached, period)) = warning else {
+            return QuotaCheck::Allowed;
+        };
+        let warned = (client.to_canonical(), period, period.start(now));
+        if self.state.lock().unwrap().warned.insert(warned) {
+            QuotaCheck::Warn(reached)
+        } else {
+            QuotaCheck::Allowed
+        }
+    }
+
+    /// The traffic counted since it was last taken, to be saved
+    pub fn take_pending(&self) -> Vec<Counted> {
+        let mut state = self.state.lock().unwrap();
+        let now = Utc::now();
+        // Warnings of past periods can't be repeated
+        state
+            .warned
+            .retain(|(_, period, start)| *start == period.start(now));
+        state
+            .pending
+            .drain()
+            .map(|((profile, host, period, period_start), bytes)| Counted {
+                profile,
+                host,
+                period,
+                period_start,
+                bytes,
+            })
+            .collect()
+    }
+
+    /// Report the traffic `counted` in the period starting at
+    /// `period_start`, by profile, with each profile's limits
+    pub fn report(
+        &self,
+        period: Period,
+        period_start: i64,
+        counted: Vec<Counted>,
+    ) -> TrafficReport {
+        let mut hosts: BTreeMap<String, Vec<HostTraffic>> = BTreeMap::new();
+        for profile in self.profiles.iter() {
+            hosts.entry(profile.name.clone()).or_default();
+        }
+        for counted in counted {
+            if counted.period == period && counted.period_start == period_start {
+                hosts.entry(counted.profile).or_default().push(HostTraffic {
+                    host: counted.host,
+                    bytes: counted.bytes,
+                });
+            }
+        }
+        let profiles = hosts
+            .into_iter()
+            .map(|(name, mut hosts)| {
+                hosts.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.host.cmp(&b.host)));
+                let (soft_limit, hard_limit) = self.profile(&name).limits(period);
+                ProfileTraffic {
+                    bytes: hosts.iter().map(|host| host.bytes).sum(),
+                    profile: name,
+                    soft_limit,
+                    hard_limit,
+                    hosts,
+                }
+            })
+            .collect();
+        TrafficReport {
+            period,
+            period_start,
+            profiles,
+        }
+    }
+
+    /// `body`, counted for `profile` and `host` once it's dropped
+    pub fn counted(
+        &self,
+        profile: &str,
+        host: &str,
+        body: UnsyncBoxBody<Bytes, ErrorCode>,
+    ) -> UnsyncBoxBody<Bytes, ErrorCode> {
+        CountedBody {
+            inner: body,
+            bytes: 0,
+            traffic: self.clone(),
+            profile: profile.to_string(),
+            host: host.to_string(),
+        }
+        .boxed_unsync()
+    }
+}
+
+/// A body counting the bytes streamed through it, which are added to the
+/// traffic when it's dropped, so bodies cut off are counted as far as they
+/// got
+struct CountedBody {
+    inner: UnsyncBoxBody<Bytes, ErrorCode>,
+    bytes: u64,
+    traffic: Traffic,
+    profile: String,
+    host: String,
+}
+
+impl Body for CountedBody {
+    type Data = Bytes;
+    type Error = ErrorCode;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let polled = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &polled
+            && let Some(data) = frame.data_ref()
+        {
+            self.bytes += data.len() as u64;
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for CountedBody {
+    fn drop(&mut self) {
+        self.traffic
+            .count(&self.profile, &self.host, self.bytes, Utc::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::Full;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn profiles_are_warned_then_refused_until_the_period_ends() {
+        let kid: IpAddr = "192.168.1.40".parse().unwrap();
+        let sibling: IpAddr = "::ffff:192.168.1.41".parse().unwrap();
+        let traffic = Traffic::new(vec![ClientProfile {
+            daily_soft_limit: Some(100),
+            daily_hard_limit: Some(200),
+            clients: vec![kid, "192.168.1.41".parse().unwrap()],
+            ..ClientProfile::unlimited("kids")
+        }])
+        .unwrap();
+        assert_eq!(traffic.profile_for(sibling), "kids");
+        assert_eq!(
+            traffic.profile_for("10.0.0.1".parse().unwrap()),
+            DEFAULT_PROFILE
+        );
+
+        let body = Full::new(Bytes::from(vec![0; 120]))
+            .map_err(|never| match never {})
+            .boxed_unsync();
+        let body = traffic.counted("kids", "video.example", body);
+        assert_eq!(traffic.used("kids", Period::Day, Utc::now()), 0);
+        assert_eq!(body.collect().await.unwrap().to_bytes().len(), 120);
+        let now = Utc::now();
+        assert_eq!(traffic.used("kids", Period::Month, now), 120);
+
+        // Only pages are replaced by the warning, once for each client
+        assert_eq!(traffic.check(kid, false, now), QuotaCheck::Allowed);
+        assert!(matches!(traffic.check(kid, true, now), QuotaCheck::Warn(_)));
+        assert_eq!(traffic.check(kid, true, now), QuotaCheck::Allowed);
+        assert!(matches!(
+            traffic.check(sibling, true, now),
+            QuotaCheck::Warn(_)
+        ));
+
+        traffic.count("kids", "games.example", 80, now);
+        assert_eq!(
+            traffic.check(kid, false, now),
+            QuotaCheck::Exceeded(QuotaReached {
+                profile: "kids".to_string(),
+                period: Period::Day,
+                used: 200,
+                limit: 200,
+            })
+        );
+        let tomorrow = now + chrono::Duration::days(1);
+        assert_eq!(traffic.check(kid, true, tomorrow), QuotaCheck::Allowed);
+
+        let start = Period::Day.start(now);
+        let report = traffic.report(Period::Day, start, traffic.take_pending());
+        assert!(traffic.take_pending().is_empty());
+        assert_eq!(report.profiles.len(), 1);
+        let kids = &report.profiles[0];
+        assert_eq!((kids.bytes, kids.hard_limit), (200, Some(200)));
+        assert_eq!(kids.hosts[0].host, "video.example");
+
+        assert_eq!(Period::Month.start(at("2026-10-15T12:30:00Z")), 1790812800);
+        assert_eq!(Period::Day.start(at("2026-10-15T12:30:00Z")), 1792022400);
+        assert!(
+            Traffic::new(vec![
+                ClientProfile::unlimited("a"),
+                ClientProfile::unlimited("a")
+            ])
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,684 @@
+//! Translation of pages into another language before they're delivered.
+//!
+//! With `proxy.translate_to` and `proxy.translation_url` set, HTML and plain
+//! text responses from the hosts in `proxy.translate_hosts` are buffered (up
+//! to [MAX_TRANSLATED_BYTES], past which they pass through untranslated)
+//! and their text split into fragments between tags. Text in `<script>`,
+//! `<style>`, `<pre>`, `<code>` and the like, or in elements marked
+//! `translate="no"`, is left alone. Fragments are sent to the backend in
+//! batches and their translations cached, so the navigation and footers a
+//! site's pages share are only translated once.
+//!
+//! The backend is either a LibreTranslate server or an OpenAI-compatible
+//! chat completions API, asked to translate a JSON array of fragments.
+//!
+//! Translated pages get a banner at the top of `<body>`, inlined like
+//! injected scripts, with a link switching the page between the translation
+//! and the original text.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, LazyLock, Mutex};
+
+use anyhow::{Context, Result, bail};
+use bytes::Bytes;
+use http_body_util::combinators::UnsyncBoxBody;
+use hyper::Response;
+use hyper::header::{CONTENT_LANGUAGE, CONTENT_LENGTH, CONTENT_TYPE, HeaderValue};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{debug, warn};
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+
+use crate::config::ProxyConfig;
+use crate::events::content::InboundContent;
+use crate::http::sniff;
+use crate::http::utils::{ContentEncoding, Encoded, buffer};
+use crate::proxy::host_limits::matches_host;
+
+/// Largest page buffered for translation
+pub const MAX_TRANSLATED_BYTES: usize = 2 * 1024 * 1024;
+
+/// Translated fragments kept, past which the cache is emptied
+const MAX_CACHED_FRAGMENTS: usize = 20_000;
+
+/// Fragments sent to the backend at once
+const BATCH_SIZE: usize = 50;
+
+/// The start tag of a page's body
+static BODY: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)<body(?:\s[^>]*)?>").unwrap());
+
+/// Elements whose text isn't translated, being code or not shown
+const UNTRANSLATED_ELEMENTS: &[&str] = &[
+    "script", "style", "noscript", "template", "textarea", "pre", "code", "kbd", "samp", "var",
+    "svg", "math",
+];
+
+/// Elements without end tags
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// The service pages are translated with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum TranslationBackend {
+    /// A LibreTranslate server's `/translate` endpoint
+    #[default]
+    LibreTranslate,
+    /// An OpenAI-compatible `/chat/completions` endpoint, prompted to
+    /// translate
+    Openai,
+}
+
+struct Translator {
+    /// The language pages are translated into, ex: `en`
+    target: String,
+    hosts: Vec<String>,
+    backend: TranslationBackend,
+    url: String,
+    api_key: Option<String>,
+    model: Option<String>,
+    client: reqwest::Client,
+    /// Translations of the fragments seen before
+    cache: Mutex<HashMap<String, String>>,
+}
+
+/// Page translation. Cheap to clone; all clones share the fragment cache.
+#[derive(Clone, Default)]
+pub struct Translation {
+    translator: Option<Arc<Translator>>,
+}
+
+impl std::fmt::Debug for Translation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("Translation");
+        if let Some(translator) = &self.translator {
+            debug
+                .field("target", &translator.target)
+                .field("hosts", &translator.hosts)
+                .field("backend", &translator.backend)
+                .field("cached", &translator.cache.lock().unwrap().len());
+        }
+        debug.finish()
+    }
+}
+
+impl From<&ProxyConfig> for Translation {
+    fn from(config: &ProxyConfig) -> Self {
+        let (Some(target), Some(url)) = (&config.translate_to, &config.translation_url) else {
+            if config.translate_to.is_some() {
+                warn!("Not translating pages: proxy.translation_url isn't set");
+            }
+            return Self::default();
+        };
+        Self {
+            translator: Some(Arc::new(Translator {
+                target: target.clone(),
+                hosts: config.translate_hosts.clone(),
+                backend: config.translation_backend,
+                url: url.trim_end_matches('/').to_string(),
+                api_key: config.translation_api_key.clone(),
+                model: config.translation_model.clone(),
+                client: reqwest::Client::default(),
+                cache: Mutex::default(),
+            })),
+        }
+    }
+}
+
+impl Translation {
+    pub fn is_enabled(&self) -> bool {
+        self.translator
+            .as_ref()
+            .is_some_and(|translator| !translator.hosts.is_empty())
+    }
+
+    /// Whether the pages of `host` are translated
+    pub fn applies(&self, host: &str) -> bool {
+        self.translator.as_ref().is_some_and(|translator| {
+            translator
+                .hosts
+                .iter()
+                .any(|pattern| matches_host(pattern, host))
+        })
+    }
+
+    /// Whether pages through a tunnel to `authority` are translated, so it
+    /// has to be intercepted
+    pub fn intercepts(&self, authority: &str) -> bool {
+        self.is_enabled()
+            && crate::proxy::parse_authority_host_port(authority, 443)
+                .is_ok_and(|(host, _)| self.applies(&host))
+    }
+
+    /// Translate `response`, if it's an HTML or text page from `host`
+    pub async fn translate(
+        &self,
+        host: &str,
+        response: Response<UnsyncBoxBody<Bytes, ErrorCode>>,
+    ) -> Response<UnsyncBoxBody<Bytes, ErrorCode>> {
+        let Some(translator) = self.translator.as_ref().filter(|_| self.applies(host)) else {
+            return response;
+        };
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let is_html = match sniff::essence(&content_type).as_str() {
+            "text/html" => true,
+            "text/plain" => false,
+            _ => return response,
+        };
+        if !matches!(response.status().as_u16(), 200..=203 | 205..=299) {
+            return response;
+        }
+        let (parts, body) = response.into_parts();
+        if matches!(parts.encoding(), ContentEncoding::Unknown) {
+            return Response::from_parts(parts, body);
+        }
+        let mut content = match InboundContent::new(parts, content_type, body) {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("Failed to decode page for translation: {}", e);
+                return failed();
+            }
+        };
+        // Pages in other charsets are transcoded to UTF-8
+        content.sniff().await;
+        let Ok(Some(body)) = content.body() else {
+            return failed();
+        };
+        let page = match buffer(body, MAX_TRANSLATED_BYTES).await {
+            Ok(page) => page,
+            Err((body, e)) => {
+                debug!("Passing page through untranslated: {:#}", e);
+                content.set_body(body);
+                return rebuild(content);
+            }
+        };
+        let page = String::from_utf8_lossy(&page);
+
+        let pieces = if is_html {
+            split_html(&page)
+        } else {
+            split_text(&page)
+        };
+        let fragments: Vec<&str> = pieces
+            .iter()
+            .filter_map(|piece| match piece {
+                Piece::Text(text) => Some(*text),
+                Piece::Markup(_) => None,
+            })
+            .collect();
+        let (translated, language) = match translator.translate(&fragments).await {
+            Ok(translated) => {
+                let (mut translated, pairs) = render(&pieces, &translated);
+                if is_html && !pairs.is_empty() {
+                    translated = add_banner(&translated, &translator.target, &pairs);
+                }
+                (translated, HeaderValue::from_str(&translator.target).ok())
+            }
+            Err(e) => {
+                warn!("Failed to translate page from {}: {:#}", host, e);
+                (page.into_owned(), None)
+            }
+        };
+        let length = translated.len();
+        content.set_body(full(Bytes::from(translated)));
+        let mut response = rebuild(content);
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+        if let Some(language) = language {
+            headers.insert(CONTENT_LANGUAGE, language);
+        }
+        response
+    }
+}
+
+impl Translator {
+    /// Translate `fragments`, returning the translation of each distinct
+    /// fragment
+    async fn translate(&self, fragments: &[&str]) -> Result<HashMap<String, String>> {
+        let mut translated = HashMap::new();
+        let mut missing = Vec::new();
+        {
+            let cache = self.cache.lock().unwrap();
+            let mut seen = HashSet::new();
+            for &fragment in fragments {
+                if !seen.insert(fragment) {
+                    continue;
+                }
+                match cache.get(fragment) {
+                    Some(translation) => {
+                        translated.insert(fragment.to_string(), translation.clone());
+                    }
+                    None => missing.push(fragment.to_string()),
+                }
+            }
+        }
+        for batch in missing.chunks(BATCH_SIZE) {
+            let translations = match self.backend {
+                TranslationBackend::LibreTranslate => self.libre_translate(batch).await,
+                TranslationBackend::Openai => self.chat_completion(batch).await,
+            }?;
+            if translations.len() != batch.len() {
+                bail!(
+                    "Backend returned {} translations for {} fragments",
+                    translations.len(),
+                    batch.len()
+                );
+            }
+            let mut cache = self.cache.lock().unwrap();
+            if cache.len() + batch.len() > MAX_CACHED_FRAGMENTS {
+                cache.clear();
+            }
+            for (fragment, translation) in batch.iter().zip(translations) {
+                cache.insert(fragment.clone(), translation.clone());
+                translated.insert(fragment.clone(), translation);
+            }
+        }
+        Ok(translated)
+    }
+
+    async fn libre_translate(&self, batch: &[String]) -> Result<Vec<String>> {
+        #[derive(Deserialize)]
+        struct Translated {
+            #[serde(rename = "translatedText")]
+            translated_text: Vec<String>,
+        }
+
+        let mut request = json!({
+            "q": batch,
+            "source": "auto",
+            "target": self.target,
+            "format": "html",
+        });
+        if let Some(api_key) = &self.api_key {
+            request["api_key"] = json!(api_key);
+        }
+        let response: Translated = self
+            .client
+            .post(format!("{}/translate", self.url))
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Unexpected LibreTranslate response")?;
+        Ok(response.translated_text)
+    }
+
+    async fn chat_completion(&self, batch: &[String]) -> Result<Vec<String>> {
+        #[derive(Deserialize)]
+        struct Completion {
+            choices: Vec<Choice>,
+        }
+        #[derive(Deserialize)]
+        struct Choice {
+            message: Message,
+        }
+        #[derive(Deserialize)]
+        struct Message {
+            content: String,
+        }
+
+        let prompt = format!(
+            "Translate each string in the JSON array the user sends into the language with code \
+             {:?}. Keep HTML entities and markup as they are, and strings already in that \
+             language unchanged. Reply with only a JSON array of the translations, in the same \
+             order.",
+            self.target
+        );
+        let request = json!({
+            "model": self.model.as_deref().unwrap_or("gpt-4o-mini"),
+            "messages": [
+                { "role": "system", "content": prompt },
+                { "role": "user", "content": serde_json::to_string(batch)? },
+            ],
+        });
+        let mut builder = self
+            .client
+            .post(format!("{}/chat/completions", self.url))
+            .json(&request);
+        if let Some(api_key) = &self.api_key {
+            builder = builder.bearer_auth(api_key);
+        }
+        let completion: Completion = builder
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Unexpected chat completion response")?;
+        let content = completion
+            .choices
+            .into_iter()
+            .next()
+            .context("Chat completion has no choices")?
+            .message
+            .content;
+        // Models tend to wrap JSON in a code block, asked not to or not
+        let json = content
+            .trim()
+            .trim_start_matches("```json")
+            .trim_start_matches("```")
+            .trim_end_matches("```");
+        serde_json::from_str(json).context("Chat completion isn't a JSON array of strings")
+    }
+}
+
+/// Part of a page
+#[derive(Debug, PartialEq, Eq)]
+enum Piece<'a> {
+    /// Tags, whitespace and text which isn't translated, sent as it is
+    Markup(&'a str),
+    /// Text to translate, without the whitespace around it
+    Text(&'a str),
+}
+
+/// Split an HTML page into its markup and the text between tags
+fn split_html(page: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    // The element whose text is being skipped, and how deeply it's nested
+    let mut skipping: Option<(String, usize)> = None;
+    let mut pos = 0;
+    while pos < page.len() {
+        let Some(start) = page[pos..].find('<').map(|i| pos + i) else {
+            push_text(&mut pieces, &page[pos..], skipping.is_none());
+            break;
+        };
+        push_text(&mut pieces, &page[pos..start], skipping.is_none());
+
+        let end = if page[start..].starts_with("<!--") {
+            page[start..]
+                .find("-->")
+                .map_or(page.len(), |i| start + i + 3)
+        } else {
+            tag_end(page, start)
+        };
+        let tag = &page[start..end];
+        pieces.push(Piece::Markup(tag));
+        pos = end;
+
+        let Some((name, closing)) = tag_name(tag) else {
+            continue;
+        };
+        match &mut skipping {
+            Some((skipped, depth)) if *skipped == name => {
+                if closing {
+                    *depth -= 1;
+                    if *depth == 0 {
+                        skipping = None;
+                    }
+                } else if !tag.ends_with("/>") {
+                    *depth += 1;
+                }
+            }
+            Some(_) => {}
+            None if closing || tag.ends_with("/>") || VOID_ELEMENTS.contains(&name.as_str()) => {}
+            None => {
+                let untranslated = UNTRANSLATED_ELEMENTS.contains(&name.as_str())
+                    || tag.contains("translate=\"no\"")
+                    || tag.contains("translate='no'")
+                    || tag.contains("notranslate");
+                if untranslated {
+                    // Script and style contents aren't markup, so are
+                    // skipped whole rather than scanned for tags
+                    if matches!(name.as_str(), "script" | "style" | "textarea") {
+                        let until = find_end_tag(page, pos, &name);
+                        pieces.push(Piece::Markup(&page[pos..until]));
+                        pos = until;
+                    } else {
+                        skipping = Some((name, 1));
+                    }
+                }
+            }
+        }
+    }
+    pieces
+}
+
+/// The end of the tag starting at `start`, past any `>` in quoted
+/// attribute values
+fn tag_end(page: &str, start: usize) -> usize {
+    let mut quote = None;
+    for (i, c) in page[start..].char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return start + i + 1,
+            _ => {}
+        }
+    }
+    page.len()
+}
+
+/// Where the end tag of the `name` element whose contents start at `pos`
+/// is, ignoring case
+fn find_end_tag(page: &str, pos: usize, name: &str) -> usize {
+    page[pos..]
+        .match_indices("</")
+        .map(|(i, _)| pos + i)
+        .find(|&i| {
+            page.get(i + 2..i + 2 + name.len())
+                .is_some_and(|tag| tag.eq_ignore_ascii_case(name))
+        })
+        .unwrap_or(page.len())
+}
+
+/// The lowercased name of a start or end tag, and whether it's an end tag
+fn tag_name(tag: &str) -> Option<(String, bool)> {
+    let tag = tag.strip_prefix('<')?;
+    let (tag, closing) = match tag.strip_prefix('/') {
+        Some(tag) => (tag, true),
+        None => (tag, false),
+    };
+    let name: String = tag
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect();
+    (!name.is_empty()).then(|| (name.to_ascii_lowercase(), closing))
+}
+
+/// Split a plain text page into paragraphs
+fn split_text(page: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut pos = 0;
+    for (i, _) in page.match_indices("\n\n") {
+        push_text(&mut pieces, &page[pos..i], true);
+        pieces.push(Piece::Markup("\n\n"));
+        pos = i + 2;
+    }
+    push_text(&mut pieces, &page[pos..], true);
+    pieces
+}
+
+/// Add `text` to `pieces`, as text to translate if it's to be and has any
+/// letters, keeping the whitespace around it as it is
+fn push_text<'a>(pieces: &mut Vec<Piece<'a>>, text: &'a str, translate: bool) {
+    if text.is_empty() {
+        return;
+    }
+    let trimmed = text.trim();
+    if !translate || !trimmed.chars().any(char::is_alphabetic) {
+        pieces.push(Piece::Markup(text));
+        return;
+    }
+    let leading = text.len() - text.trim_start().len();
+    let (before, rest) = text.split_at(leading);
+    let (core, after) = rest.split_at(trimmed.len());
+    for piece in [
+        Piece::Markup(before),
+        Piece::Text(core),
+        Piece::Markup(after),
+    ] {
+        if !matches!(piece, Piece::Markup("")) {
+            pieces.push(piece);
+        }
+    }
+}
+
+/// Put `pieces` back together with their text translated, returning the
+/// page along with each original fragment and the translation it was
+/// replaced by
+fn render(
+    pieces: &[Piece<'_>],
+    translated: &HashMap<String, String>,
+) -> (String, Vec<(String, String)>) {
+    let mut page = String::new();
+    let mut pairs = Vec::new();
+    let mut paired = HashSet::new();
+    for piece in pieces {
+        match piece {
+            Piece::Markup(markup) => page.push_str(markup),
+            Piece::Text(text) => match translated.get(*text) {
+                Some(translation) => {
+                    page.push_str(translation);
+                    if translation != text && paired.insert(*text) {
+                        pairs.push((text.to_string(), translation.clone()));
+                    }
+                }
+                None => page.push_str(text),
+            },
+        }
+    }
+    (page, pairs)
+}
+
+/// Styles of the banner added to translated pages, reset from the page's
+const BANNER_STYLE: &str = "all:initial;display:block;padding:6px 12px;background:#f2f2f2;\
+    border-bottom:1px solid #ccc;font:13px sans-serif;color:#333";
+
+/// Called with the original and translated fragments of a page, switching
+/// the page's text nodes between them when the banner's link is clicked
+const TOGGLE_SCRIPT: &str = r#"(pairs) => {
+  const parser = new DOMParser();
+  const text = (html) => parser.parseFromString(html, "text/html").documentElement.textContent.trim();
+  const originals = new Map(pairs.map(([o, t]) => [text(t), text(o)]));
+  const translations = new Map(pairs.map(([o, t]) => [text(o), text(t)]));
+  const banner = document.getElementById("witm-translation");
+  const link = banner.querySelector("a");
+  let original = false;
+  link.addEventListener("click", (event) => {
+    event.preventDefault();
+    original = !original;
+    const swaps = original ? originals : translations;
+    const walker = document.createTreeWalker(document.documentElement, NodeFilter.SHOW_TEXT);
+    for (let node; (node = walker.nextNode()); ) {
+      if (banner.contains(node)) continue;
+      const key = node.data.trim();
+      const swap = swaps.get(key);
+      if (swap !== undefined) node.data = node.data.replace(key, swap);
+    }
+    link.textContent = original ? "Show translation" : "Show original";
+  });
+}"#;
+
+/// Add the banner switching between the translation and the original text
+/// at the top of `<body>`, or the end of pages without one
+fn add_banner(page: &str, target: &str, pairs: &[(String, String)]) -> String {
+    // Escaped so the pairs can't close the script they're in
+    let pairs = serde_json::to_string(pairs)
+        .unwrap_or_else(|_| "[]".to_string())
+        .replace('<', "\\u003c");
+    let target = target.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "");
+    let banner = format!(
+        r##"<div id="witm-translation" translate="no" style="{BANNER_STYLE}">Translated to {target} by witmproxy &middot; <a href="#">Show original</a></div><script>({TOGGLE_SCRIPT})({pairs});</script>"##
+    );
+    match BODY.find(page) {
+        Some(tag) => format!("{}{}{}", &page[..tag.end()], banner, &page[tag.end()..]),
+        None => format!("{page}{banner}"),
+    }
+}
+
+fn full(bytes: Bytes) -> UnsyncBoxBody<Bytes, ErrorCode> {
+    use http_body_util::BodyExt;
+    http_body_util::Full::new(bytes)
+        .map_err(|_| ErrorCode::InternalError(Some("conversion error".to_string())))
+        .boxed_unsync()
+}
+
+fn rebuild(content: InboundContent) -> Response<UnsyncBoxBody<Bytes, ErrorCode>> {
+    match content.into_response() {
+        Ok(response) => response,
+        Err(e) => {
+            warn!("Failed to rebuild page after translation: {}", e);
+            failed()
+        }
+    }
+}
+
+/// The response sent when a page's body was lost to a failed translation
+fn failed() -> Response<UnsyncBoxBody<Bytes, ErrorCode>> {
+    let mut response = Response::new(UnsyncBoxBody::default());
+    *response.status_mut() = hyper::StatusCode::BAD_GATEWAY;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts<'a>(pieces: &[Piece<'a>]) -> Vec<&'a str> {
+        pieces
+            .iter()
+            .filter_map(|piece| match piece {
+                Piece::Text(text) => Some(*text),
+                Piece::Markup(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn only_visible_prose_is_translated() {
+        let page = r#"<html><head><title>Bonjour</title><style>p > a { color: red }</style>
+<script>if (a < b) { document.write("<p>non</p>") }</script></head>
+<body><p class="x" data-y="a>b">Le  chat <b>noir</b>.</p><!-- pas ça -->
+<pre>ne <i>pas</i> traduire</pre><div translate="no">Marque <div>imbriquée</div></div>
+<img alt="image"><p>Fin</p> 42 </body></html>"#;
+        let pieces = split_html(page);
+        assert_eq!(texts(&pieces), ["Bonjour", "Le  chat", "noir", "Fin"]);
+
+        // Everything else is sent as it was
+        let (rendered, pairs) = render(&pieces, &HashMap::new());
+        assert_eq!(rendered, page);
+        assert!(pairs.is_empty());
+    }
+
+    #[test]
+    fn pages_are_rebuilt_with_translations() {
+        let page = "<body>\n  <p>Le chat</p><p>Le chat</p><p>OK</p>\n</body>";
+        let pieces = split_html(page);
+        let translated = HashMap::from([
+            ("Le chat".to_string(), "The cat".to_string()),
+            ("OK".to_string(), "OK".to_string()),
+        ]);
+        let (rendered, pairs) = render(&pieces, &translated);
+        assert_eq!(
+            rendered,
+            "<body>\n  <p>The cat</p><p>The cat</p><p>OK</p>\n</body>"
+        );
+        // Untouched and repeated fragments aren't switched twice
+        assert_eq!(pairs, [("Le chat".to_string(), "The cat".to_string())]);
+
+        let with_banner = add_banner(&rendered, "en", &pairs);
+        assert!(with_banner.starts_with("<body><div id=\"witm-translation\""));
+        assert!(with_banner.ends_with("<p>The cat</p><p>The cat</p><p>OK</p>\n</body>"));
+
+        let pieces = split_text("Bonjour\n\n  Au revoir\n");
+        assert_eq!(texts(&pieces), ["Bonjour", "Au revoir"]);
+    }
+
+    #[test]
+    fn pairs_cant_close_the_banner_script() {
+        let pairs = [("</script><b>".to_string(), "x".to_string())];
+        let page = add_banner("<p>x</p>", "en", &pairs);
+        assert_eq!(page.matches("</script>").count(), 1);
+    }
+}
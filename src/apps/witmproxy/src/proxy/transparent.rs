@@ -1,7 +1,7 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
 
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpStream;
 use tokio::sync::{Notify, RwLock};
 use tracing::{debug, error, info, warn};
 
@@ -10,8 +10,33 @@ use crate::config::TransparentProxyConfig;
 use crate::events::Event;
 use crate::events::connect::Connect;
 use crate::plugins::registry::PluginRegistry;
+use crate::proxy::api_schemas::ApiSchemas;
+use crate::proxy::block_rules::{self, BlockBehavior, BlockRules};
+use crate::proxy::compression::Compression;
+use crate::proxy::egress::EgressRoutes;
+use crate::proxy::findings::SensitiveData;
+use crate::proxy::flow_tags::FlowTags;
+use crate::proxy::flow_trace::FlowTraces;
+use crate::proxy::header_overrides::HeaderOverrides;
+use crate::proxy::host_limits::HostLimiter;
+use crate::proxy::images::ImageOptimizer;
+use crate::proxy::limits::FlowLimits;
+use crate::proxy::mocks::MockApis;
+use crate::proxy::network_profiles::NetworkConditions;
+use crate::proxy::pages::ErrorPages;
+use crate::proxy::privacy::Privacy;
+use crate::proxy::protobuf::ProtobufDescriptors;
+use crate::proxy::security_headers::SecurityHeaders;
+use crate::proxy::sessions::CaptureSessions;
 use crate::proxy::tenant_resolver::TenantResolver;
-use crate::proxy::{UpstreamClient, is_closed, parse_authority_host_port, run_tls_mitm};
+use crate::proxy::tls_policy::TlsPolicies;
+use crate::proxy::traffic::Traffic;
+use crate::proxy::translation::Translation;
+use crate::proxy::user_scripts::UserScripts;
+use crate::proxy::vhost::HostMismatchPolicy;
+use crate::proxy::{
+    FlowSettings, UpstreamClient, is_closed, parse_authority_host_port, run_tls_mitm,
+};
 use crate::tenant::TenantContext;
 
 use super::netfilter::NetfilterManager;
@@ -26,6 +51,7 @@ pub struct TransparentProxy {
     config: TransparentProxyConfig,
     shutdown_notify: Arc<Notify>,
     netfilter: Option<NetfilterManager>,
+    settings: FlowSettings,
 }
 
 impl TransparentProxy {
@@ -46,9 +72,149 @@ impl TransparentProxy {
             config,
             shutdown_notify,
             netfilter: None,
+            settings: FlowSettings::default(),
         }
     }
 
+    /// Set the size and time limits applied to intercepted flows
+    pub fn with_limits(mut self, limits: FlowLimits) -> Self {
+        self.settings.limits = limits;
+        self
+    }
+
+    /// Set the pages served when a plugin blocks a flow or it fails
+    pub fn with_error_pages(mut self, pages: ErrorPages) -> Self {
+        self.settings.pages = pages;
+        self
+    }
+
+    /// Set how requests whose `Host` differs from the connection's SNI are handled
+    pub fn with_host_mismatch_policy(mut self, policy: HostMismatchPolicy) -> Self {
+        self.settings.host_mismatch = policy;
+        self
+    }
+
+    /// Set the security headers injected into intercepted responses
+    pub fn with_security_headers(mut self, security_headers: SecurityHeaders) -> Self {
+        self.settings.security_headers = security_headers;
+        self
+    }
+
+    /// Set the rules tagging flows as they complete
+    pub fn with_flow_tags(mut self, flow_tags: FlowTags) -> Self {
+        self.settings.flow_tags = flow_tags;
+        self
+    }
+
+    /// Set the capture sessions flows are offered to
+    pub fn with_sessions(mut self, sessions: CaptureSessions) -> Self {
+        self.settings.sessions = sessions;
+        self
+    }
+
+    /// Set the mocked APIs, whose hosts are intercepted even when no plugin
+    /// wants them
+    pub fn with_mocks(mut self, mocks: MockApis) -> Self {
+        self.settings.mocks = mocks;
+        self
+    }
+
+    /// Set the scripts injected into matching HTML pages
+    pub fn with_user_scripts(mut self, user_scripts: UserScripts) -> Self {
+        self.settings.user_scripts = user_scripts;
+        self
+    }
+
+    /// Set how images in responses are transcoded
+    pub fn with_images(mut self, images: ImageOptimizer) -> Self {
+        self.settings.images = images;
+        self
+    }
+
+    /// Set how responses are compressed for the client
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.settings.compression = compression;
+        self
+    }
+
+    /// Set the page translation
+    pub fn with_translation(mut self, translation: Translation) -> Self {
+        self.settings.translation = translation;
+        self
+    }
+
+    /// Set the tracking parameter and third-party cookie stripping
+    pub fn with_privacy(mut self, privacy: Privacy) -> Self {
+        self.settings.privacy = privacy;
+        self
+    }
+
+    /// Record flows to `schemas`, inferring API schemas from them
+    pub fn with_api_schemas(mut self, schemas: ApiSchemas) -> Self {
+        self.settings.schemas = Some(schemas);
+        self
+    }
+
+    /// Set what's done about sensitive data in intercepted flows
+    pub fn with_sensitive_data(mut self, sensitive_data: SensitiveData) -> Self {
+        self.settings.sensitive_data = sensitive_data;
+        self
+    }
+
+    /// Set the descriptors protobuf bodies of intercepted flows are decoded with
+    pub fn with_protobuf(mut self, protobuf: ProtobufDescriptors) -> Self {
+        self.settings.protobuf = protobuf;
+        self
+    }
+
+    /// Set the clients allowed to trace flows, and where their traces are kept
+    pub fn with_flow_traces(mut self, traces: FlowTraces) -> Self {
+        self.settings.traces = traces;
+        self
+    }
+
+    /// Set the per-host limits on requests sent upstream
+    pub fn with_host_limiter(mut self, host_limiter: HostLimiter) -> Self {
+        self.settings.host_limiter = host_limiter;
+        self
+    }
+
+    /// Set the network conditions simulated for clients
+    pub fn with_network_conditions(mut self, network_conditions: NetworkConditions) -> Self {
+        self.settings.network_conditions = network_conditions;
+        self
+    }
+
+    /// Set the headers forced on the requests sent to matching hosts
+    pub fn with_header_overrides(mut self, header_overrides: HeaderOverrides) -> Self {
+        self.settings.header_overrides = header_overrides;
+        self
+    }
+
+    /// Set the routes flows to matching hosts are sent out through
+    pub fn with_egress(mut self, egress: EgressRoutes) -> Self {
+        self.settings.egress = egress;
+        self
+    }
+
+    /// Set the hosts refused, and how
+    pub fn with_block_rules(mut self, block_rules: BlockRules) -> Self {
+        self.settings.block_rules = block_rules;
+        self
+    }
+
+    /// Count flows' traffic by client profile, enforcing the profiles' quotas
+    pub fn with_traffic(mut self, traffic: Traffic) -> Self {
+        self.settings.traffic = Some(traffic);
+        self
+    }
+
+    /// Set the TLS policies handshakes are negotiated with
+    pub fn with_tls_policies(mut self, tls_policies: TlsPolicies) -> Self {
+        self.settings.tls_policies = tls_policies;
+        self
+    }
+
     pub fn listen_addr(&self) -> Option<SocketAddr> {
         self.listen_addr
     }
@@ -62,7 +228,7 @@ impl TransparentProxy {
             .parse()
             .map_err(|e| anyhow::anyhow!("Invalid transparent proxy bind address: {}", e))?;
 
-        let listener = TcpListener::bind(bind_addr).await?;
+        let listener = super::dial::bind_listener(bind_addr)?;
         self.listen_addr = Some(listener.local_addr()?);
         info!(
             "Transparent proxy listening on {}",
@@ -89,6 +255,7 @@ impl TransparentProxy {
         let plugin_registry = self.plugin_registry.clone();
         let tenant_resolver = self.tenant_resolver.clone();
         let upstream = self.upstream.clone();
+        let settings = self.settings.clone();
 
         tokio::spawn(async move {
             loop {
@@ -97,11 +264,14 @@ impl TransparentProxy {
                     accept_result = listener.accept() => {
                         match accept_result {
                             Ok((stream, peer)) => {
+                                // Tenants are keyed by plain IPv4 addresses, even on a dual-stack listener
+                                let peer = super::dial::canonical_addr(peer);
                                 info!("Transparent: accepted connection from {}", peer);
                                 let ca = ca.clone();
                                 let plugin_registry = plugin_registry.clone();
                                 let tenant_resolver = tenant_resolver.clone();
                                 let upstream = upstream.clone();
+                                let settings = settings.clone();
 
                                 tokio::spawn(async move {
                                     let tenant_ctx = tenant_resolver.resolve(&peer).await;
@@ -111,6 +281,7 @@ impl TransparentProxy {
                                         ca,
                                         plugin_registry,
                                         upstream,
+                                        settings,
                                         tenant_ctx,
                                     ).await
                                         && !is_closed(&e) {
@@ -256,8 +427,13 @@ async fn handle_transparent_connection(
     ca: Arc<CertificateAuthority>,
     plugin_registry: Option<Arc<RwLock<PluginRegistry>>>,
     upstream: UpstreamClient,
-    _tenant_ctx: TenantContext,
+    settings: FlowSettings,
+    tenant_ctx: TenantContext,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let settings = FlowSettings {
+        tenant: tenant_ctx.tenant_id,
+        ..settings
+    };
     // Peek at the first bytes to determine protocol
     let mut peek_buf = [0u8; 5];
     let n = stream.peek(&mut peek_buf).await?;
@@ -278,11 +454,49 @@ async fn handle_transparent_connection(
 
         info!("Transparent TLS: SNI={} from {}", hostname, peer);
 
-        if should_intercept(&plugin_registry, &hostname).await {
+        match settings
+            .block_rules
+            .rule_for(&hostname)
+            .map(|rule| rule.behavior)
+        {
+            Some(BlockBehavior::Reset) => {
+                info!(
+                    "Transparent: resetting connection to blocked host {}",
+                    hostname
+                );
+                block_rules::reset(stream);
+                return Ok(());
+            }
+            Some(BlockBehavior::TlsAlert) => {
+                info!(
+                    "Transparent: refusing handshake with blocked host {}",
+                    hostname
+                );
+                block_rules::refuse_handshake(stream).await?;
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        if settings.block_rules.intercepts(&hostname)
+            || settings.mocks.intercepts(&hostname)
+            || settings.user_scripts.intercepts(&hostname)
+            || settings.translation.intercepts(&hostname)
+            || should_intercept(&plugin_registry, &hostname).await
+        {
             // Plugin(s) want this connection — run the full MITM pipeline
             info!("Transparent: intercepting {} (plugins matched)", hostname);
             let authority = format!("{}:443", hostname);
-            if let Err(e) = run_tls_mitm(upstream, stream, authority, ca, plugin_registry).await
+            if let Err(e) = run_tls_mitm(
+                upstream,
+                stream,
+                peer.ip(),
+                authority,
+                ca,
+                plugin_registry,
+                settings,
+            )
+            .await
                 && !is_closed(&e)
             {
                 debug!("Transparent MITM error for {}: {}", hostname, e);
@@ -293,7 +507,7 @@ async fn handle_transparent_connection(
                 "Transparent: forwarding {} directly (no plugins matched)",
                 hostname
             );
-            let mut upstream_stream = TcpStream::connect(format!("{}:443", hostname)).await?;
+            let mut upstream_stream = super::dial::connect(&hostname, 443).await?;
             match tokio::io::copy_bidirectional(&mut stream, &mut upstream_stream).await {
                 Ok(_) => {}
                 Err(e) if is_closed(&e) => {}
@@ -318,7 +532,8 @@ async fn handle_transparent_connection(
             return Ok(());
         }
 
-        let mut upstream_stream = TcpStream::connect(format!("{}:80", host)).await?;
+        let (host, port) = parse_authority_host_port(&host, 80)?;
+        let mut upstream_stream = super::dial::connect(&host, port).await?;
         match tokio::io::copy_bidirectional(&mut stream, &mut upstream_stream).await {
             Ok(_) => {}
             Err(e) if is_closed(&e) => {}
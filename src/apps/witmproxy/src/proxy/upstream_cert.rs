@@ -0,0 +1,103 @@
+//! The certificates upstream servers present to the proxy, recorded on the
+//! flows they served.
+//!
+//! Upstream clients are built with `tls_info`, so responses carry the DER
+//! of the server's leaf certificate; [UpstreamCert::parse] summarizes it.
+//! Only the leaf is recorded, as it's all the HTTP client exposes of the
+//! chain. Flows get the summary in their `upstream_cert` field and its
+//! SHA-256 fingerprint as the [UPSTREAM_CERT_SHA256] annotation, which
+//! plugins with `flow_reader` can compare across flows to notice a host's
+//! certificate changing. Scopes read it with `upstream.cert_fingerprint()`.
+
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use x509_parser::extensions::GeneralName;
+
+/// Annotation added to flows with the SHA-256 fingerprint of the upstream's
+/// certificate, as upper case hex
+pub const UPSTREAM_CERT_SHA256: &str = "upstream-cert-sha256";
+
+/// What a flow records of its upstream server's leaf certificate
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct UpstreamCert {
+    pub subject: String,
+    pub issuer: String,
+    /// DNS names and IP addresses the certificate is valid for
+    pub sans: Vec<String>,
+    /// Serial number, as upper case hex
+    pub serial: String,
+    /// Unix timestamp in seconds from which the certificate is valid
+    pub not_before: i64,
+    /// Unix timestamp in seconds after which the certificate has expired
+    pub not_after: i64,
+    /// Fingerprints of the DER encoded certificate, as upper case hex
+    pub sha256: String,
+    pub sha1: String,
+}
+
+impl UpstreamCert {
+    /// Summarize the DER encoded certificate `der`, if it can be parsed
+    pub fn parse(der: &[u8]) -> Option<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+        let sans = cert
+            .subject_alternative_name()
+            .ok()
+            .flatten()
+            .map(|san| {
+                san.value
+                    .general_names
+                    .iter()
+                    .filter_map(|name| match name {
+                        GeneralName::DNSName(name) => Some(name.to_string()),
+                        GeneralName::IPAddress(bytes) => ip_address(bytes),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let sha1 = ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, der);
+        Some(Self {
+            subject: cert.subject().to_string(),
+            issuer: cert.issuer().to_string(),
+            sans,
+            serial: hex::encode_upper(cert.raw_serial()),
+            not_before: cert.validity().not_before.timestamp(),
+            not_after: cert.validity().not_after.timestamp(),
+            sha256: hex::encode_upper(Sha256::digest(der)),
+            sha1: hex::encode_upper(sha1.as_ref()),
+        })
+    }
+}
+
+fn ip_address(bytes: &[u8]) -> Option<String> {
+    match bytes.len() {
+        4 => Some(std::net::Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?).to_string()),
+        16 => Some(std::net::Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?).to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, KeyPair, SanType};
+
+    #[test]
+    fn summarizes_the_leaf_certificate() {
+        let mut params = CertificateParams::new(vec!["api.example.com".to_string()]).unwrap();
+        params
+            .subject_alt_names
+            .push(SanType::IpAddress("10.0.0.1".parse().unwrap()));
+        let key = KeyPair::generate().unwrap();
+        let der = params.self_signed(&key).unwrap().der().to_vec();
+
+        let cert = UpstreamCert::parse(&der).unwrap();
+        assert_eq!(cert.sans, ["api.example.com", "10.0.0.1"]);
+        assert_eq!(cert.subject, cert.issuer);
+        assert!(cert.not_before < cert.not_after);
+        assert_eq!(cert.sha256, hex::encode_upper(Sha256::digest(&der)));
+        assert_eq!((cert.sha256.len(), cert.sha1.len()), (64, 40));
+        assert!(UpstreamCert::parse(b"not a certificate").is_none());
+    }
+}
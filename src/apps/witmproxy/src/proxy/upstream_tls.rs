@@ -0,0 +1,288 @@
+//! Verification of upstream server certificates.
+//!
+//! Upstream certificates are checked against the platform trust store, the
+//! proxy's own CA, and an optional extra CA bundle (`tls.upstream_ca_bundle`),
+//! e.g. a corporate root for an environment that already intercepts TLS.
+//! Selected hosts can additionally be pinned to certificate fingerprints, or
+//! exempted from verification when `tls.allow_insecure_upstream` is set.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::config::TlsConfig;
+use crate::proxy::client_hello::ClientHelloProfile;
+use crate::proxy::tls_policy::TlsPolicy;
+use crate::proxy::{ProxyError, ProxyResult};
+
+/// Pin an upstream host to the SHA-256 fingerprints of the leaf certificates
+/// it may present:
+///
+/// ```toml
+/// [[tls.upstream_pins]]
+/// host = "api.internal.example"
+/// sha256 = ["3B:9A:...:0F"]
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpstreamPin {
+    pub host: String,
+    /// Hex fingerprints, with or without `:` separators
+    pub sha256: Vec<String>,
+}
+
+/// Load the certificates of `tls.upstream_ca_bundle`, if configured
+pub fn extra_roots(config: &TlsConfig) -> ProxyResult<Vec<CertificateDer<'static>>> {
+    let Some(path) = &config.upstream_ca_bundle else {
+        return Ok(Vec::new());
+    };
+    let pem = std::fs::read(path).map_err(|e| {
+        ProxyError::Generic(format!(
+            "Failed to read upstream CA bundle {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| ProxyError::Cert(e.into()))?;
+    if certs.is_empty() {
+        return Err(ProxyError::Generic(format!(
+            "No certificates found in upstream CA bundle {}",
+            path.display()
+        )));
+    }
+    Ok(certs)
+}
+
+/// Whether upstream verification needs per-host rules, rather than the
+/// client's default verifier
+pub fn has_host_rules(config: &TlsConfig) -> bool {
+    !config.upstream_pins.is_empty() || !config.upstream_insecure_hosts.is_empty()
+}
+
+/// A verifier which applies per-host pins and exemptions on top of `inner`
+#[derive(Debug)]
+pub struct UpstreamVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    pins: HashMap<String, Vec<[u8; 32]>>,
+    insecure_hosts: HashSet<String>,
+}
+
+impl UpstreamVerifier {
+    pub fn new(inner: Arc<dyn ServerCertVerifier>, config: &TlsConfig) -> ProxyResult<Self> {
+        if !config.upstream_insecure_hosts.is_empty() && !config.allow_insecure_upstream {
+            return Err(ProxyError::Generic(
+                "tls.upstream_insecure_hosts is set but tls.allow_insecure_upstream is not enabled"
+                    .to_string(),
+            ));
+        }
+        for host in &config.upstream_insecure_hosts {
+            warn!("Upstream certificate verification is DISABLED for {}", host);
+        }
+
+        let mut pins: HashMap<String, Vec<[u8; 32]>> = HashMap::new();
+        for pin in &config.upstream_pins {
+            let entry = pins.entry(pin.host.to_ascii_lowercase()).or_default();
+            for fingerprint in &pin.sha256 {
+                entry.push(parse_fingerprint(fingerprint).ok_or_else(|| {
+                    ProxyError::Generic(format!(
+                        "Invalid SHA-256 fingerprint for {}: {}",
+                        pin.host, fingerprint
+                    ))
+                })?);
+            }
+        }
+
+        Ok(Self {
+            inner,
+            pins,
+            insecure_hosts: config
+                .upstream_insecure_hosts
+                .iter()
+                .map(|host| host.to_ascii_lowercase())
+                .collect(),
+        })
+    }
+}
+
+impl ServerCertVerifier for UpstreamVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let host = server_name.to_str().to_ascii_lowercase();
+        if let Some(pins) = self.pins.get(&host) {
+            let fingerprint: [u8; 32] = Sha256::digest(end_entity.as_ref()).into();
+            if !pins.contains(&fingerprint) {
+                return Err(rustls::Error::General(format!(
+                    "certificate for {} does not match its pinned fingerprints (got {})",
+                    host,
+                    hex::encode_upper(fingerprint)
+                )));
+            }
+        }
+        if self.insecure_hosts.contains(&host) {
+            return Ok(ServerCertVerified::assertion());
+        }
+        self.inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Build the rustls config for upstream connections with per-host rules,
+/// trusting the platform store plus `extra_roots`, shaping handshakes after
+/// `client_hello` if given and restricting them to `policy`
+pub fn client_config(
+    config: &TlsConfig,
+    extra_roots: Vec<CertificateDer<'static>>,
+    client_hello: Option<ClientHelloProfile>,
+    policy: &TlsPolicy,
+) -> ProxyResult<rustls::ClientConfig> {
+    let provider = Arc::new(policy.provider(match client_hello {
+        Some(profile) => profile.provider(),
+        None => rustls::crypto::ring::default_provider(),
+    })?);
+    let platform =
+        rustls_platform_verifier::Verifier::new_with_extra_roots(extra_roots, provider.clone())
+            .map_err(|e| ProxyError::Cert(e.into()))?;
+    let verifier = UpstreamVerifier::new(Arc::new(platform), config)?;
+
+    let mut tls = rustls::ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(&policy.versions()?)
+        .map_err(|e| ProxyError::Cert(e.into()))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    tls.alpn_protocols = policy.alpn_protocols(match client_hello {
+        Some(profile) => profile.alpn_protocols(),
+        None => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+    });
+    Ok(tls)
+}
+
+fn parse_fingerprint(fingerprint: &str) -> Option<[u8; 32]> {
+    let hex_digits: String = fingerprint.chars().filter(|c| *c != ':').collect();
+    hex::decode(hex_digits).ok()?.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Accepts everything, to observe which checks the wrapper adds
+    #[derive(Debug)]
+    struct AcceptAll;
+
+    impl ServerCertVerifier for AcceptAll {
+        fn verify_server_cert(
+            &self,
+            _: &CertificateDer<'_>,
+            _: &[CertificateDer<'_>],
+            _: &ServerName<'_>,
+            _: &[u8],
+            _: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            Ok(ServerCertVerified::assertion())
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            _: &[u8],
+            _: &CertificateDer<'_>,
+            _: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            Ok(HandshakeSignatureValid::assertion())
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            _: &[u8],
+            _: &CertificateDer<'_>,
+            _: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            Ok(HandshakeSignatureValid::assertion())
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            Vec::new()
+        }
+    }
+
+    fn verify(verifier: &UpstreamVerifier, cert: &[u8], host: &str) -> bool {
+        verifier
+            .verify_server_cert(
+                &CertificateDer::from(cert.to_vec()),
+                &[],
+                &ServerName::try_from(host.to_string()).unwrap(),
+                &[],
+                UnixTime::now(),
+            )
+            .is_ok()
+    }
+
+    #[test]
+    fn pinned_hosts_require_matching_fingerprint() {
+        let cert = b"not really a certificate";
+        let fingerprint = hex::encode_upper(Sha256::digest(cert))
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| std::str::from_utf8(pair).unwrap())
+            .collect::<Vec<_>>()
+            .join(":");
+        let config = TlsConfig {
+            upstream_pins: vec![UpstreamPin {
+                host: "Pinned.example".to_string(),
+                sha256: vec![fingerprint],
+            }],
+            ..Default::default()
+        };
+        let verifier = UpstreamVerifier::new(Arc::new(AcceptAll), &config).unwrap();
+        assert!(verify(&verifier, cert, "pinned.example"));
+        assert!(!verify(&verifier, b"another certificate", "pinned.example"));
+        assert!(verify(&verifier, b"another certificate", "other.example"));
+    }
+
+    #[test]
+    fn insecure_hosts_must_be_allowed_explicitly() {
+        let mut config = TlsConfig {
+            upstream_insecure_hosts: vec!["dev.internal".to_string()],
+            ..Default::default()
+        };
+        assert!(UpstreamVerifier::new(Arc::new(AcceptAll), &config).is_err());
+        config.allow_insecure_upstream = true;
+        assert!(UpstreamVerifier::new(Arc::new(AcceptAll), &config).is_ok());
+        assert!(parse_fingerprint("AB:CD").is_none());
+    }
+}
@@ -0,0 +1,592 @@
+//! Script injection, for the most common plugin use case without writing a
+//! plugin. Scripts registered through `PUT /api/manage/scripts/{name}` are
+//! inlined into the HTML pages they match as the pages stream through.
+//!
+//! A script is either a Greasemonkey-style userscript, whose
+//! `// ==UserScript==` block gives the pages it runs on with `@match`,
+//! `@include`, `@exclude` and `@exclude-match` and when with `@run-at`, or a
+//! JSON snippet:
+//!
+//! ```json
+//! { "matches": ["https://*.example.com/*"], "source": "console.log(1)", "run_at": "document-start" }
+//! ```
+//!
+//! `document-start` scripts are added at the top of `<head>` and the rest
+//! before `</body>`, so pages without those tags are left alone. Scripts
+//! run in the page itself: no `GM_*` APIs are provided, and pages whose
+//! `Content-Security-Policy` forbids inline scripts will block them.
+
+use std::sync::{Arc, RwLock};
+
+use anyhow::{Context, Result, bail};
+use bytes::Bytes;
+use http_body_util::combinators::UnsyncBoxBody;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Response, StatusCode};
+use regex::Regex;
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+
+use crate::events::content::InboundContent;
+use crate::http::utils::{ContentEncoding, Encoded};
+use crate::plugins::replace::{self, Replacement};
+
+/// Longest `<head>` start tag looked for, attributes included
+const MAX_HEAD_TAG: usize = 1024;
+
+/// Where in a page a script is added
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum RunAt {
+    /// At the top of `<head>`, before the page's own scripts
+    DocumentStart,
+    /// Before `</body>`, once the page's content has been parsed
+    #[default]
+    DocumentEnd,
+}
+
+impl RunAt {
+    /// The `@run-at` value of a userscript, where `document-idle` and
+    /// `document-body` are treated as `document-end`
+    fn parse(value: &str) -> Result<Self> {
+        match value {
+            "document-start" => Ok(RunAt::DocumentStart),
+            "document-end" | "document-idle" | "document-body" => Ok(RunAt::DocumentEnd),
+            other => bail!("Unknown @run-at {:?}", other),
+        }
+    }
+}
+
+/// A set of URLs, from a match pattern, an `@include` glob or a `/regex/`
+#[derive(Debug, Clone)]
+struct UrlPattern {
+    source: String,
+    url: Regex,
+    /// Hosts the pattern may match URLs of, where that can be told
+    host: Option<Regex>,
+}
+
+impl UrlPattern {
+    /// A match pattern, ex: `https://*.example.com/path/*` or `<all_urls>`
+    fn from_match(pattern: &str) -> Result<Self> {
+        if pattern == "<all_urls>" {
+            return Self::new(pattern, "https?://.*", None);
+        }
+        let (scheme, rest) = pattern
+            .split_once("://")
+            .with_context(|| format!("Match pattern {:?} has no scheme", pattern))?;
+        let scheme = match scheme {
+            "*" => "https?",
+            "http" | "https" => scheme,
+            other => bail!("Match pattern scheme {:?} can't be proxied", other),
+        };
+        let (host, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => bail!("Match pattern {:?} has no path", pattern),
+        };
+        let host = match host {
+            "*" => "[^/:]+".to_string(),
+            host => match host.strip_prefix("*.") {
+                Some(domain) => format!(r"(?:[^/:]+\.)?{}", regex::escape(domain)),
+                None if host.contains('*') => {
+                    bail!("Match pattern host {:?} may only start with *.", host)
+                }
+                None => regex::escape(host),
+            },
+        };
+        Self::new(
+            pattern,
+            &format!(r"{}://{}(?::\d+)?{}", scheme, host, glob(path)),
+            Some(&host),
+        )
+    }
+
+    /// An `@include` or `@exclude` glob over the whole URL, or a regular
+    /// expression between slashes
+    fn from_include(pattern: &str) -> Result<Self> {
+        if let Some(expression) = pattern
+            .strip_prefix('/')
+            .and_then(|p| p.strip_suffix('/'))
+            .filter(|p| !p.is_empty())
+        {
+            return Self::new(pattern, &format!(".*(?:{}).*", expression), None);
+        }
+        // The host is whatever's between the scheme and the path, unless
+        // that's where a wildcard is
+        let host = pattern
+            .split_once("://")
+            .map(|(_, rest)| rest.split('/').next().unwrap_or(rest))
+            .filter(|host| {
+                let domain = host.strip_prefix("*.").unwrap_or(host);
+                !domain.is_empty() && !domain.contains('*')
+            })
+            .map(|host| match host.strip_prefix("*.") {
+                Some(domain) => format!(r"(?:[^/:]+\.)?{}", regex::escape(domain)),
+                None => regex::escape(host),
+            });
+        Self::new(pattern, &glob(pattern), host.as_deref())
+    }
+
+    fn new(source: &str, url: &str, host: Option<&str>) -> Result<Self> {
+        let anchored = |expression: &str| {
+            Regex::new(&format!("(?i)^(?:{})$", expression))
+                .with_context(|| format!("Invalid pattern {:?}", source))
+        };
+        Ok(Self {
+            source: source.to_string(),
+            url: anchored(url)?,
+            host: host.map(anchored).transpose()?,
+        })
+    }
+
+    fn matches(&self, url: &str) -> bool {
+        self.url.is_match(url)
+    }
+
+    /// Whether the pattern may match URLs on `host`
+    fn may_match_host(&self, host: &str) -> bool {
+        self.host
+            .as_ref()
+            .is_none_or(|pattern| pattern.is_match(host))
+    }
+}
+
+/// A regular expression matching what `pattern` does, where `*` matches
+/// anything
+fn glob(pattern: &str) -> String {
+    pattern
+        .split('*')
+        .map(regex::escape)
+        .collect::<Vec<_>>()
+        .join(".*")
+}
+
+/// A script's code and the pages it runs on, as registered as JSON
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct ScriptSnippet {
+    /// Match patterns of the pages to run on
+    pub matches: Vec<String>,
+    /// Match patterns of pages not to run on, even if they match
+    #[serde(default)]
+    pub excludes: Vec<String>,
+    #[serde(default)]
+    pub run_at: RunAt,
+    /// The JavaScript to run
+    pub source: String,
+}
+
+/// A registered script, as listed by the management API
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct UserScriptSummary {
+    pub name: String,
+    /// Whether the script was registered as a userscript, rather than JSON
+    pub userscript: bool,
+    pub matches: Vec<String>,
+    pub excludes: Vec<String>,
+    pub run_at: RunAt,
+    /// Length of the script's code, in bytes
+    pub size: usize,
+}
+
+/// A script to inject into the pages it matches
+#[derive(Debug, Clone)]
+pub struct UserScript {
+    name: String,
+    /// The script as it was registered, which is what's stored
+    source: String,
+    userscript: bool,
+    code: String,
+    matches: Vec<UrlPattern>,
+    excludes: Vec<UrlPattern>,
+    run_at: RunAt,
+}
+
+impl UserScript {
+    /// Parse a script registered as `name`, either a JSON [ScriptSnippet] or
+    /// a userscript with a metadata block
+    pub fn parse(name: impl Into<String>, source: &str) -> Result<Self> {
+        let name = name.into();
+        let script = match serde_json::from_str::<ScriptSnippet>(source) {
+            Ok(snippet) => Self {
+                name,
+                source: source.to_string(),
+                userscript: false,
+                matches: snippet
+                    .matches
+                    .iter()
+                    .map(|pattern| UrlPattern::from_match(pattern))
+                    .collect::<Result<_>>()?,
+                excludes: snippet
+                    .excludes
+                    .iter()
+                    .map(|pattern| UrlPattern::from_match(pattern))
+                    .collect::<Result<_>>()?,
+                run_at: snippet.run_at,
+                code: snippet.source,
+            },
+            Err(_) => Self::parse_userscript(name, source)?,
+        };
+        if script.matches.is_empty() {
+            bail!("Script {:?} doesn't match any pages", script.name);
+        }
+        Ok(script)
+    }
+
+    fn parse_userscript(name: String, source: &str) -> Result<Self> {
+        let mut lines = source.lines().map(str::trim);
+        if !lines.any(|line| line == "// ==UserScript==") {
+            bail!("Script is neither JSON nor a userscript with a // ==UserScript== block");
+        }
+        let mut script = Self {
+            name,
+            source: source.to_string(),
+            userscript: true,
+            code: source.to_string(),
+            matches: Vec::new(),
+            excludes: Vec::new(),
+            run_at: RunAt::default(),
+        };
+        for line in lines {
+            if line == "// ==/UserScript==" {
+                return Ok(script);
+            }
+            let Some(entry) = line.strip_prefix("//").map(str::trim) else {
+                continue;
+            };
+            let Some((key, value)) = entry.split_once(char::is_whitespace) else {
+                continue;
+            };
+            let value = value.trim();
+            match key {
+                "@match" => script.matches.push(UrlPattern::from_match(value)?),
+                "@include" => script.matches.push(UrlPattern::from_include(value)?),
+                "@exclude-match" => script.excludes.push(UrlPattern::from_match(value)?),
+                "@exclude" => script.excludes.push(UrlPattern::from_include(value)?),
+                "@run-at" => script.run_at = RunAt::parse(value)?,
+                _ => {}
+            }
+        }
+        bail!("Userscript's // ==UserScript== block isn't closed")
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The script as it was registered
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn summary(&self) -> UserScriptSummary {
+        let sources = |patterns: &[UrlPattern]| {
+            patterns
+                .iter()
+                .map(|pattern| pattern.source.clone())
+                .collect()
+        };
+        UserScriptSummary {
+            name: self.name.clone(),
+            userscript: self.userscript,
+            matches: sources(&self.matches),
+            excludes: sources(&self.excludes),
+            run_at: self.run_at,
+            size: self.code.len(),
+        }
+    }
+
+    /// Whether the script runs on the page at `url`
+    pub fn applies_to(&self, url: &str) -> bool {
+        self.matches.iter().any(|pattern| pattern.matches(url))
+            && !self.excludes.iter().any(|pattern| pattern.matches(url))
+    }
+
+    /// The script inlined in a `<script>` element
+    fn element(&self) -> String {
+        let name = self
+            .name
+            .replace('&', "&amp;")
+            .replace('"', "&quot;")
+            .replace('<', "&lt;");
+        format!(
+            "<script data-witm-script=\"{}\">\n{}\n</script>",
+            name,
+            self.code.replace("</script", "<\\/script")
+        )
+    }
+}
+
+/// The registered scripts, shared between the proxy and the web server
+#[derive(Clone, Default)]
+pub struct UserScripts {
+    scripts: Arc<RwLock<Arc<Vec<UserScript>>>>,
+}
+
+impl std::fmt::Debug for UserScripts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UserScripts")
+            .field(
+                "scripts",
+                &self
+                    .scripts()
+                    .iter()
+                    .map(UserScript::name)
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl UserScripts {
+    pub fn new(scripts: Vec<UserScript>) -> Self {
+        Self {
+            scripts: Arc::new(RwLock::new(Arc::new(scripts))),
+        }
+    }
+
+    pub fn scripts(&self) -> Arc<Vec<UserScript>> {
+        self.scripts.read().unwrap().clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.read().unwrap().is_empty()
+    }
+
+    /// Add `script`, replacing any script with the same name
+    pub fn insert(&self, script: UserScript) {
+        let mut scripts = self.scripts.write().unwrap();
+        let mut updated: Vec<_> = scripts
+            .iter()
+            .filter(|existing| existing.name != script.name)
+            .cloned()
+            .collect();
+        updated.push(script);
+        *scripts = Arc::new(updated);
+    }
+
+    /// Remove the script called `name`, returning whether there was one
+    pub fn remove(&self, name: &str) -> bool {
+        let mut scripts = self.scripts.write().unwrap();
+        let updated: Vec<_> = scripts
+            .iter()
+            .filter(|script| script.name != name)
+            .cloned()
+            .collect();
+        let removed = updated.len() != scripts.len();
+        *scripts = Arc::new(updated);
+        removed
+    }
+
+    /// Whether pages through a tunnel to `authority` may have scripts
+    /// injected, so it has to be intercepted
+    pub fn intercepts(&self, authority: &str) -> bool {
+        let scripts = self.scripts();
+        if scripts.is_empty() {
+            return false;
+        }
+        let host = match crate::proxy::parse_authority_host_port(authority, 443) {
+            Ok((host, _)) => host,
+            Err(_) => return false,
+        };
+        scripts.iter().any(|script| {
+            script
+                .matches
+                .iter()
+                .any(|pattern| pattern.may_match_host(&host))
+        })
+    }
+
+    /// Add the scripts matching `url` to `response`, if it's an HTML page
+    pub fn inject(
+        &self,
+        url: &str,
+        response: Response<UnsyncBoxBody<Bytes, ErrorCode>>,
+    ) -> Response<UnsyncBoxBody<Bytes, ErrorCode>> {
+        let is_page = matches!(response.status().as_u16(), 200..=203 | 205..=299)
+            && response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| {
+                    value
+                        .trim_start()
+                        .to_ascii_lowercase()
+                        .starts_with("text/html")
+                });
+        if !is_page {
+            return response;
+        }
+        let scripts = self.scripts();
+        let (start, end): (Vec<_>, Vec<_>) = scripts
+            .iter()
+            .filter(|script| script.applies_to(url))
+            .partition(|script| script.run_at == RunAt::DocumentStart);
+        if start.is_empty() && end.is_empty() {
+            return response;
+        }
+
+        let (parts, body) = response.into_parts();
+        if matches!(parts.encoding(), ContentEncoding::Unknown) {
+            return Response::from_parts(parts, body);
+        }
+        let content_type = parts.headers[CONTENT_TYPE]
+            .to_str()
+            .unwrap_or_default()
+            .to_string();
+        let mut content = match InboundContent::new(parts, content_type, body) {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("Failed to decode page for script injection: {}", e);
+                return failed();
+            }
+        };
+        let mut replacements = Vec::new();
+        // `$` would otherwise expand capture groups
+        let elements = |scripts: &[&UserScript]| {
+            scripts
+                .iter()
+                .map(|script| script.element().replace('$', "$$"))
+                .collect::<String>()
+        };
+        if !start.is_empty() {
+            let head = Replacement::regex(
+                r"(?i)<head(?:\s[^>]*)?>",
+                MAX_HEAD_TAG,
+                &format!("$0{}", elements(&start)),
+            )
+            .expect("valid head pattern");
+            replacements.push(head.with_limit(1));
+        }
+        if !end.is_empty() {
+            let body_end =
+                Replacement::regex(r"(?i)</body\s*>", 16, &format!("{}$0", elements(&end)))
+                    .expect("valid body pattern");
+            replacements.push(body_end.with_limit(1));
+        }
+        if let Ok(Some(body)) = content.body() {
+            content.set_body(replace::apply(body, replacements));
+        }
+        match content.into_response() {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Failed to rebuild page after script injection: {}", e);
+                failed()
+            }
+        }
+    }
+}
+
+/// The response sent when a page's body was lost to a failed injection
+fn failed() -> Response<UnsyncBoxBody<Bytes, ErrorCode>> {
+    let mut response = Response::new(UnsyncBoxBody::default());
+    *response.status_mut() = StatusCode::BAD_GATEWAY;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{BodyExt, Full};
+
+    const USERSCRIPT: &str = r#"// ==UserScript==
+// @name        Hide shorts
+// @match       https://*.youtube.com/*
+// @exclude     https://www.youtube.com/embed/*
+// @run-at      document-start
+// @grant       none
+// ==/UserScript==
+document.title = "$1 </script>";
+"#;
+
+    const PAGE: &[u8] = b"<html><head lang=\"en\"><title>x</title></head><body></body></html>";
+
+    #[test]
+    fn userscript_metadata_is_parsed() {
+        let script = UserScript::parse("shorts", USERSCRIPT).unwrap();
+        let summary = script.summary();
+        assert!(summary.userscript);
+        assert_eq!(summary.matches, ["https://*.youtube.com/*"]);
+        assert_eq!(summary.run_at, RunAt::DocumentStart);
+
+        assert!(script.applies_to("https://www.youtube.com/watch?v=1"));
+        assert!(script.applies_to("https://youtube.com/"));
+        assert!(!script.applies_to("https://www.youtube.com/embed/1"));
+        assert!(!script.applies_to("http://www.youtube.com/"));
+        assert!(!script.applies_to("https://notyoutube.com/"));
+
+        assert!(UserScript::parse("none", "alert(1)").is_err());
+        assert!(UserScript::parse("bad", "// ==UserScript==\n// @match ftp://x/*\n").is_err());
+    }
+
+    #[test]
+    fn snippets_and_includes_are_matched() {
+        let snippet = UserScript::parse(
+            "snippet",
+            r#"{"matches": ["<all_urls>"], "excludes": ["*://*.bank.com/*"], "source": "1"}"#,
+        )
+        .unwrap();
+        assert!(snippet.applies_to("http://example.com:8080/a"));
+        assert!(!snippet.applies_to("https://www.bank.com/login"));
+
+        let include = UrlPattern::from_include("http*://example.com/a*").unwrap();
+        assert!(include.matches("https://example.com/abc"));
+        assert!(!include.matches("https://example.com/b"));
+        assert!(include.may_match_host("example.com"));
+        assert!(!include.may_match_host("example.org"));
+        let regex = UrlPattern::from_include(r"/example\.(com|org)/").unwrap();
+        assert!(regex.matches("https://example.org/"));
+        assert!(regex.may_match_host("anything"));
+    }
+
+    #[test]
+    fn intercepts_matching_hosts() {
+        let scripts = UserScripts::default();
+        assert!(!scripts.intercepts("www.youtube.com:443"));
+        scripts.insert(UserScript::parse("shorts", USERSCRIPT).unwrap());
+        assert!(scripts.intercepts("www.youtube.com:443"));
+        assert!(!scripts.intercepts("example.com:443"));
+        assert!(scripts.remove("shorts"));
+        assert!(scripts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn scripts_are_injected_into_matching_pages() {
+        let scripts = UserScripts::default();
+        scripts.insert(UserScript::parse("shorts", USERSCRIPT).unwrap());
+        scripts.insert(
+            UserScript::parse(
+                "footer",
+                r#"{"matches": ["https://*/*"], "source": "footer()"}"#,
+            )
+            .unwrap(),
+        );
+
+        let page = |content_type: &str| {
+            Response::builder()
+                .header(CONTENT_TYPE, content_type)
+                .header("content-length", "1")
+                .body(
+                    Full::new(Bytes::from_static(PAGE))
+                        .map_err(|_| ErrorCode::InternalError(None))
+                        .boxed_unsync(),
+                )
+                .unwrap()
+        };
+        let response = scripts.inject("https://www.youtube.com/", page("text/html; charset=utf-8"));
+        assert!(!response.headers().contains_key("content-length"));
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            std::str::from_utf8(&body).unwrap(),
+            "<html><head lang=\"en\"><script data-witm-script=\"shorts\">\n".to_string()
+                + USERSCRIPT.replace("</script", "<\\/script").as_str()
+                + "\n</script><title>x</title></head><body>"
+                + "<script data-witm-script=\"footer\">\nfooter()\n</script></body></html>"
+        );
+
+        let response = scripts.inject("https://www.youtube.com/", page("application/json"));
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, PAGE);
+    }
+}
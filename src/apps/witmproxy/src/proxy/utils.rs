@@ -1,9 +1,15 @@
 use crate::cert::{CertError, CertificateAuthority};
+use crate::config::TlsConfig;
+use crate::proxy::client_hello::ClientHelloProfile;
+use crate::proxy::limits::FlowLimits;
+use crate::proxy::tls_policy::TlsPolicy;
+use crate::proxy::upstream_cert::UpstreamCert;
+use crate::proxy::upstream_tls;
 
 use bytes::Bytes;
 use futures::TryStreamExt;
+use http_body_util::BodyExt;
 use http_body_util::combinators::UnsyncBoxBody;
-use http_body_util::{BodyExt, Full};
 use hyper::body::{Body, Incoming};
 use hyper::{Method, Request, Response, header};
 use reqwest::Certificate;
@@ -61,6 +67,44 @@ impl Body for BodyWithSignal {
     }
 }
 
+/// A body which holds on to a value until it's consumed or dropped, such as
+/// a permit which should last as long as the response is streaming
+pub struct BodyHolding<T> {
+    inner: UnsyncBoxBody<Bytes, ErrorCode>,
+    _held: T,
+}
+
+impl<T> BodyHolding<T> {
+    pub fn new(inner: UnsyncBoxBody<Bytes, ErrorCode>, held: T) -> Self {
+        Self { inner, _held: held }
+    }
+}
+
+impl<T: Unpin> Body for BodyHolding<T> {
+    type Data = Bytes;
+    type Error = ErrorCode;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// The upstream server a response was received from, attached to converted
+/// responses as an extension
+#[derive(Debug, Clone, Copy)]
+pub struct UpstreamAddr(pub std::net::SocketAddr);
+
 /// Custom error type for proxy operations
 #[derive(Debug)]
 pub enum ProxyError {
@@ -298,75 +342,82 @@ pub fn convert_hyper_incoming_to_reqwest_request(
         .map_err(|e| ProxyError::Generic(format!("Failed to build reqwest request: {}", e)))
 }
 
-/// Convert a reqwest Response to a hyper Response with streaming body
-pub async fn convert_reqwest_to_hyper_response(
+/// Convert a reqwest Response to a hyper Response. The body is streamed
+/// frame by frame as it arrives, without being collected or copied.
+pub fn convert_reqwest_to_hyper_response(
     reqwest_resp: reqwest::Response,
-) -> ProxyResult<Response<UnsyncBoxBody<Bytes, ErrorCode>>> {
-    let status = reqwest_resp.status();
-    let headers = reqwest_resp.headers().clone();
-
-    // Convert reqwest bytes stream to a body stream
-    let stream = reqwest_resp
-        .bytes_stream()
-        .map_err(|e| ErrorCode::InternalError(Some(format!("Stream error: {}", e))));
-
-    // Create a streaming body from the stream
-    let body = http_body_util::StreamBody::new(stream.map_ok(hyper::body::Frame::data));
-    let boxed = body.boxed_unsync();
-
-    let mut response = Response::builder().status(status);
-
-    // Copy headers
-    for (name, value) in headers.iter() {
-        response = response.header(name, value);
+) -> Response<UnsyncBoxBody<Bytes, ErrorCode>> {
+    let remote_addr = reqwest_resp.remote_addr();
+    let cert = reqwest_resp
+        .extensions()
+        .get::<reqwest::tls::TlsInfo>()
+        .and_then(|info| info.peer_certificate())
+        .and_then(UpstreamCert::parse);
+    let (mut parts, body) = Response::<reqwest::Body>::from(reqwest_resp).into_parts();
+    if let Some(addr) = remote_addr {
+        parts.extensions.insert(UpstreamAddr(addr));
     }
-
-    response
-        .body(boxed)
-        .map_err(|e| ProxyError::Generic(format!("Failed to build hyper response: {}", e)))
-}
-
-/// Convert a Response<BoxBody<Bytes, ErrorCode>> to a Response<Full<Bytes>>
-pub async fn convert_boxbody_to_full_response(
-    response: Response<UnsyncBoxBody<Bytes, ErrorCode>>,
-) -> ProxyResult<Response<Full<Bytes>>> {
-    let (parts, body) = response.into_parts();
-
-    // Collect all body data into bytes
-    let body_bytes = body
-        .collect()
-        .await
-        .map_err(|e| ProxyError::Generic(format!("Failed to collect body data: {}", e)))?
-        .to_bytes();
-
-    // Build new response with Full<Bytes> body
-    let mut response_builder = Response::builder()
-        .status(parts.status)
-        .version(parts.version);
-
-    // Copy all headers
-    for (name, value) in parts.headers.iter() {
-        response_builder = response_builder.header(name, value);
+    if let Some(cert) = cert {
+        parts.extensions.insert(cert);
     }
-
-    response_builder
-        .body(Full::new(body_bytes))
-        .map_err(|e| ProxyError::Generic(format!("Failed to build response: {}", e)))
+    let body = body
+        .map_err(|e| ErrorCode::InternalError(Some(format!("Stream error: {}", e))))
+        .boxed_unsync();
+    Response::from_parts(parts, body)
 }
 
 /// Create a configured reqwest client for upstream requests
-pub fn client(ca: CertificateAuthority) -> ProxyResult<UpstreamClient> {
-    let ca_cert = Certificate::from_der(&ca.get_root_certificate_der()?)
-        .map_err(|e| ProxyError::Cert(e.to_string().into()))?;
+pub fn client(
+    ca: CertificateAuthority,
+    limits: &FlowLimits,
+    tls: &TlsConfig,
+) -> ProxyResult<UpstreamClient> {
+    client_builder(ca, limits, tls, None, &TlsPolicy::upstream(tls))?
+        .build()
+        .map_err(|e| ProxyError::Generic(format!("Failed to build reqwest client: {}", e)))
+}
 
-    let client = reqwest::Client::builder()
+/// The builder [`client`] is built from, for clients sending through other
+/// routes with the same TLS settings and limits, optionally shaping their
+/// handshakes after a browser's, and restricted to `policy`
+pub fn client_builder(
+    ca: CertificateAuthority,
+    limits: &FlowLimits,
+    tls: &TlsConfig,
+    client_hello: Option<ClientHelloProfile>,
+    policy: &TlsPolicy,
+) -> ProxyResult<reqwest::ClientBuilder> {
+    let mut roots = upstream_tls::extra_roots(tls)?;
+    roots.push(ca.get_root_certificate_der()?.into());
+
+    let builder = reqwest::Client::builder();
+    // Pins, exemptions, client hello profiles and TLS policies need our own
+    // rustls config; otherwise the extra roots are merged into reqwest's
+    // platform verifier
+    let builder =
+        if upstream_tls::has_host_rules(tls) || client_hello.is_some() || !policy.is_default() {
+            builder.tls_backend_preconfigured(upstream_tls::client_config(
+                tls,
+                roots,
+                client_hello,
+                policy,
+            )?)
+        } else {
+            let certs = roots
+                .iter()
+                .map(|der| Certificate::from_der(der))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| ProxyError::Cert(e.to_string().into()))?;
+            builder.tls_certs_merge(certs)
+        };
+
+    Ok(builder
         // HTTP/2 compatible connection pooling
         .pool_idle_timeout(std::time::Duration::from_secs(90))
         .pool_max_idle_per_host(10) // Allow more connections for HTTP/2 multiplexing
-        .connect_timeout(std::time::Duration::from_secs(30))
-        .timeout(std::time::Duration::from_secs(60))
-        // Certificate setup
-        .add_root_certificate(ca_cert)
+        .connect_timeout(limits.upstream_connect_timeout)
+        .read_timeout(limits.upstream_read_timeout)
+        .timeout(limits.flow_deadline)
         // HTTP/2 specific configuration to avoid protocol errors
         .http2_initial_stream_window_size(Some(1024 * 1024)) // 1MB stream window
         .http2_initial_connection_window_size(Some(4 * 1024 * 1024)) // 4MB connection window
@@ -375,9 +426,8 @@ pub fn client(ca: CertificateAuthority) -> ProxyResult<UpstreamClient> {
         .http2_keep_alive_interval(Some(std::time::Duration::from_secs(60)))
         .http2_keep_alive_timeout(std::time::Duration::from_secs(20))
         .http2_keep_alive_while_idle(true)
-        .build()
-        .map_err(|e| ProxyError::Generic(format!("Failed to build reqwest client: {}", e)))?;
-    Ok(client)
+        // Responses carry the upstream's certificate, see [UpstreamCert]
+        .tls_info(true))
 }
 
 /// Strip hop-by-hop headers from HTTP requests/responses
@@ -397,8 +447,28 @@ pub fn strip_proxy_headers(h: &mut hyper::HeaderMap) {
     }
 }
 
-/// Parse authority string into host and port components
+/// Parse authority string into host and port components. IPv6 literals may be
+/// bracketed (`[::1]:443`); the returned host never includes the brackets.
 pub fn parse_authority_host_port(authority: &str, default_port: u16) -> ProxyResult<(String, u16)> {
+    if let Some(rest) = authority.strip_prefix('[') {
+        let (host, after) = rest.split_once(']').ok_or_else(|| {
+            ProxyError::Generic(format!(
+                "Unterminated IPv6 literal in authority: {}",
+                authority
+            ))
+        })?;
+        let port = match after.strip_prefix(':') {
+            Some(p) => p.parse().map_err(|_| {
+                ProxyError::Generic(format!("Invalid port in authority: {}", authority))
+            })?,
+            None => default_port,
+        };
+        return Ok((host.to_string(), port));
+    }
+    // A bare IPv6 literal has no room for a port
+    if authority.matches(':').count() > 1 {
+        return Ok((authority.to_string(), default_port));
+    }
     match authority.rsplit_once(':') {
         Some((h, p)) if !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()) => {
             Ok((h.to_string(), p.parse().unwrap_or(default_port)))
@@ -407,10 +477,12 @@ pub fn parse_authority_host_port(authority: &str, default_port: u16) -> ProxyRes
     }
 }
 
-/// Build a TLS server configuration for the given host using the CA
+/// Build a TLS server configuration for the given host using the CA,
+/// negotiating what `policy` allows
 pub async fn build_server_tls_for_host(
     ca: &CertificateAuthority,
     host: &str,
+    policy: &TlsPolicy,
 ) -> ProxyResult<rustls::ServerConfig> {
     // Use your CA to mint a leaf cert for `host`
     let cert = ca
@@ -422,13 +494,16 @@ pub async fn build_server_tls_for_host(
         .get_root_certificate_der()
         .map_err(|e| ProxyError::Cert(e.into()))?;
     let cert_chain = vec![cert.cert_der.clone(), root_cert_der.into()];
+    let ocsp = ca.ocsp_staple(&cert).unwrap_or_default();
 
     // Minimal rustls server config
-    let mut cfg = rustls::ServerConfig::builder()
+    let provider = policy.provider(rustls::crypto::ring::default_provider())?;
+    let mut cfg = rustls::ServerConfig::builder_with_provider(std::sync::Arc::new(provider))
+        .with_protocol_versions(&policy.versions()?)?
         .with_no_client_auth()
-        .with_single_cert(cert_chain, cert.key_der)
+        .with_single_cert_with_ocsp(cert_chain, cert.key_der, ocsp)
         .map_err(|e| ProxyError::Tls(rustls::Error::General(e.to_string())))?;
-    cfg.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    cfg.alpn_protocols = policy.alpn_protocols(vec![b"h2".to_vec(), b"http/1.1".to_vec()]);
     Ok(cfg)
 }
 
@@ -460,3 +535,36 @@ pub fn is_closed<E: std::fmt::Display>(e: &E) -> bool {
         || s.contains("unexpected eof")
         || s.contains("close_notify")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ipv4_ipv6_and_named_authorities() {
+        let parse = |a| parse_authority_host_port(a, 443).unwrap();
+        assert_eq!(parse("example.com"), ("example.com".to_string(), 443));
+        assert_eq!(parse("example.com:8443"), ("example.com".to_string(), 8443));
+        assert_eq!(parse("127.0.0.1:80"), ("127.0.0.1".to_string(), 80));
+        assert_eq!(parse("[::1]:8443"), ("::1".to_string(), 8443));
+        assert_eq!(parse("[2001:db8::1]"), ("2001:db8::1".to_string(), 443));
+        assert_eq!(parse("2001:db8::1"), ("2001:db8::1".to_string(), 443));
+        assert!(parse_authority_host_port("[::1:443", 443).is_err());
+    }
+
+    #[tokio::test]
+    async fn held_values_outlive_the_body() {
+        let held = std::sync::Arc::new(());
+        let body = http_body_util::Full::new(Bytes::from("hello"))
+            .map_err(|never| match never {})
+            .boxed_unsync();
+        let mut body = BodyHolding::new(body, held.clone());
+        assert_eq!(std::sync::Arc::strong_count(&held), 2);
+
+        let frame = body.frame().await.unwrap().unwrap();
+        assert_eq!(frame.into_data().unwrap(), "hello");
+        assert_eq!(std::sync::Arc::strong_count(&held), 2);
+        drop(body);
+        assert_eq!(std::sync::Arc::strong_count(&held), 1);
+    }
+}
@@ -0,0 +1,115 @@
+//! Detection of intercepted requests whose `Host` disagrees with the TLS SNI
+//! or the CONNECT authority of the tunnel they arrived on, e.g. from domain
+//! fronting or clients reusing a connection across virtual hosts.
+
+/// What to do with a request whose host doesn't match its connection
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostMismatchPolicy {
+    /// Forward the request to its `Host` without logging
+    Allow,
+    /// Forward the request to its `Host`, logging a warning
+    #[default]
+    Log,
+    /// Reject the request with 421 Misdirected Request
+    Block,
+}
+
+impl std::fmt::Display for HostMismatchPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HostMismatchPolicy::Allow => write!(f, "allow"),
+            HostMismatchPolicy::Log => write!(f, "log"),
+            HostMismatchPolicy::Block => write!(f, "block"),
+        }
+    }
+}
+
+/// The names a client gave for an intercepted TLS connection
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// Host from the CONNECT request (or the SNI, for transparent connections)
+    pub authority_host: String,
+    /// Server name from the TLS ClientHello, if the client sent one
+    pub sni: Option<String>,
+}
+
+impl ConnectionInfo {
+    pub fn new(authority_host: &str, sni: Option<&str>) -> Self {
+        Self {
+            authority_host: host_of(authority_host).to_string(),
+            sni: sni.map(str::to_string),
+        }
+    }
+
+    /// Describe how `request_host` (a `host[:port]` authority) disagrees with
+    /// this connection, if it does
+    pub fn mismatch(&self, request_host: &str) -> Option<String> {
+        if let Some(sni) = &self.sni
+            && !same_host(sni, &self.authority_host)
+        {
+            return Some(format!(
+                "SNI {} does not match CONNECT authority {}",
+                sni, self.authority_host
+            ));
+        }
+        let request_host = host_of(request_host);
+        if request_host.is_empty() {
+            return None;
+        }
+        let expected = self.sni.as_deref().unwrap_or(&self.authority_host);
+        (!same_host(request_host, expected)).then(|| {
+            format!(
+                "Host {} does not match connection host {}",
+                request_host, expected
+            )
+        })
+    }
+}
+
+/// Strip the port (and IPv6 brackets) from an authority
+fn host_of(authority: &str) -> &str {
+    if let Some(rest) = authority.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest);
+    }
+    match authority.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => authority,
+    }
+}
+
+fn same_host(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.')
+        .eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_mismatches() {
+        let conn = ConnectionInfo::new("example.com", Some("example.com"));
+        assert_eq!(conn.mismatch("example.com"), None);
+        assert_eq!(conn.mismatch("EXAMPLE.com.:443"), None);
+        assert_eq!(conn.mismatch(""), None);
+        assert!(conn.mismatch("other.example").is_some());
+
+        let fronted = ConnectionInfo::new("example.com", Some("cdn.example"));
+        assert!(fronted.mismatch("example.com").unwrap().contains("SNI"));
+
+        let no_sni = ConnectionInfo::new("[::1]", None);
+        assert_eq!(no_sni.mismatch("[::1]:8443"), None);
+        assert!(no_sni.mismatch("localhost").is_some());
+    }
+}
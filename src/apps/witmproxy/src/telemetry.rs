@@ -74,6 +74,8 @@ pub mod otel {
                         opentelemetry_semantic_conventions::attribute::SERVICE_VERSION,
                         version,
                     ),
+                    // Still experimental in the semantic conventions crate
+                    KeyValue::new("service.instance.id", config.instance_id()),
                 ])
                 .build();
 
@@ -182,6 +184,57 @@ pub mod otel {
         }
     }
 
+    /// Count an error reported to a client in the `witmproxy.errors` metric,
+    /// by its code
+    pub fn record_error(code: &'static str) {
+        static ERRORS: std::sync::OnceLock<opentelemetry::metrics::Counter<u64>> =
+            std::sync::OnceLock::new();
+        ERRORS
+            .get_or_init(|| {
+                global::meter("witmproxy.proxy")
+                    .u64_counter("witmproxy.errors")
+                    .with_description("Errors reported to clients in place of a response")
+                    .build()
+            })
+            .add(1, &[KeyValue::new("code", code)]);
+    }
+
+    /// Count a plain HTTP request upgraded to HTTPS in the
+    /// `witmproxy.https_upgrades` metric, by why and how it was upgraded
+    pub fn record_https_upgrade(source: &'static str, action: &'static str) {
+        static UPGRADES: std::sync::OnceLock<opentelemetry::metrics::Counter<u64>> =
+            std::sync::OnceLock::new();
+        UPGRADES
+            .get_or_init(|| {
+                global::meter("witmproxy.proxy")
+                    .u64_counter("witmproxy.https_upgrades")
+                    .with_description("Plain HTTP requests upgraded to HTTPS")
+                    .build()
+            })
+            .add(
+                1,
+                &[
+                    KeyValue::new("source", source),
+                    KeyValue::new("action", action),
+                ],
+            );
+    }
+
+    /// Count tracking parameters and third-party cookies stripped from flows
+    /// in the `witmproxy.privacy_removals` metric, by what was removed
+    pub fn record_privacy_removal(kind: &'static str, count: u64) {
+        static REMOVALS: std::sync::OnceLock<opentelemetry::metrics::Counter<u64>> =
+            std::sync::OnceLock::new();
+        REMOVALS
+            .get_or_init(|| {
+                global::meter("witmproxy.proxy")
+                    .u64_counter("witmproxy.privacy_removals")
+                    .with_description("Tracking parameters and third-party cookies stripped")
+                    .build()
+            })
+            .add(count, &[KeyValue::new("kind", kind)]);
+    }
+
     /// Spawns a background task that periodically emits system resource metrics
     /// (CPU, memory) via the OpenTelemetry meter.
     pub fn spawn_resource_metrics(interval_secs: u64) -> tokio::task::JoinHandle<()> {
@@ -261,4 +314,10 @@ pub mod otel {
             _worker_guard: worker_guard,
         }
     }
+
+    pub fn record_error(_code: &'static str) {}
+
+    pub fn record_https_upgrade(_source: &'static str, _action: &'static str) {}
+
+    pub fn record_privacy_removal(_kind: &'static str, _count: u64) {}
 }
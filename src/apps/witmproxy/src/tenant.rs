@@ -1,3 +1,7 @@
+tokio::task_local! {
+    static CURRENT: Option<String>;
+}
+
 /// Context identifying the tenant for a given connection/request.
 #[derive(Debug, Clone)]
 pub struct TenantContext {
@@ -23,3 +27,14 @@ impl TenantContext {
         self.tenant_id.is_none()
     }
 }
+
+/// Run `fut` on behalf of the tenant `tenant_id`, so [current] returns it
+pub async fn scoped<F: Future>(tenant_id: Option<String>, fut: F) -> F::Output {
+    CURRENT.scope(tenant_id, fut).await
+}
+
+/// The tenant whose flow is being handled; none for anonymous clients and
+/// outside of flows
+pub fn current() -> Option<String> {
+    CURRENT.try_with(Clone::clone).ok().flatten()
+}
@@ -24,6 +24,8 @@ use crate::Runtime;
 use crate::WitmProxy;
 use crate::{AppConfig, CertificateAuthority};
 
+pub mod replay;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Protocol {
     Http1,
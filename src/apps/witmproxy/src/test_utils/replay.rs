@@ -0,0 +1,85 @@
+//! Record/replay harness for plugin integration tests.
+//!
+//! [record] passes events through a registry's plugins, saving each event,
+//! the capability calls the plugins made while handling it and the outcome
+//! to a [Cassette]. [replay] passes the same events through again, answering
+//! the capability calls from the cassette rather than the live clock,
+//! storage, flow log or key sets, so a test asserting on the replayed
+//! cassette is deterministic:
+//!
+//! ```ignore
+//! let cassette = Cassette::load("tests/cassettes/auth.json")?;
+//! assert_eq!(replay(registry, &cassette).await?, cassette);
+//! ```
+
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::PluginRegistry;
+use crate::plugins::exercise::handle;
+pub use crate::plugins::exercise::{Outcome, RecordedEvent};
+use crate::wasm::tape::{CapabilityCall, Tape};
+
+/// Events passed through plugins, with what the plugins did with them
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Cassette {
+    pub exchanges: Vec<Exchange>,
+}
+
+impl Cassette {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// An event and what the plugins did with it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Exchange {
+    pub event: RecordedEvent,
+    /// The capability calls made while handling the event, in order
+    #[serde(default)]
+    pub calls: Vec<CapabilityCall>,
+    pub outcome: Outcome,
+}
+
+/// Pass `events` through the plugins of `registry`, recording what they do
+pub async fn record(registry: PluginRegistry, events: Vec<RecordedEvent>) -> Result<Cassette> {
+    let tape = Tape::default();
+    let registry = registry.with_tape(tape.clone());
+    let mut exchanges = Vec::with_capacity(events.len());
+    for event in events {
+        let outcome = handle(&registry, &event).await?;
+        exchanges.push(Exchange {
+            event,
+            calls: tape.take(),
+            outcome,
+        });
+    }
+    Ok(Cassette { exchanges })
+}
+
+/// Pass the events of `cassette` through the plugins of `registry` again,
+/// answering their capability calls from it. The plugins behave as recorded
+/// when the returned cassette equals `cassette`.
+pub async fn replay(registry: PluginRegistry, cassette: &Cassette) -> Result<Cassette> {
+    let tape = Tape::default();
+    let registry = registry.with_tape(tape.clone());
+    let mut exchanges = Vec::with_capacity(cassette.exchanges.len());
+    for exchange in &cassette.exchanges {
+        tape.replay(exchange.calls.clone());
+        let outcome = handle(&registry, &exchange.event).await?;
+        exchanges.push(Exchange {
+            event: exchange.event.clone(),
+            calls: tape.take(),
+            outcome,
+        });
+    }
+    Ok(Cassette { exchanges })
+}
@@ -1,9 +1,14 @@
+use std::sync::{Arc, Mutex};
+
 use anyhow::Result;
 use tracing::debug;
 
+use crate::WitmProxy;
+use crate::test_utils::replay::{Cassette, Outcome, RecordedEvent, record, replay};
 use crate::test_utils::{
     EchoResponse, Protocol, create_client, create_html_server, create_json_echo_server,
-    create_witmproxy, register_noshorts_plugin, register_test_component,
+    create_plugin_registry, create_witmproxy, register_noshorts_plugin, register_test_component,
+    test_component_path,
 };
 
 #[tokio::test]
@@ -206,3 +211,84 @@ async fn e2e_plugin_body_streaming_subtask() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn e2e_builder_reports_flows() -> Result<()> {
+    let flows = Arc::new(Mutex::new(Vec::new()));
+    let mut proxy = WitmProxy::builder()
+        .plugin(std::fs::read(test_component_path()?)?)
+        .on_flow({
+            let flows = flows.clone();
+            move |flow| flows.lock().unwrap().push(flow.clone())
+        })
+        .build()
+        .await?;
+    proxy.start().await?;
+
+    let ca = proxy.certificate_authority().clone();
+    let target = create_json_echo_server("127.0.0.1", None, ca.clone(), Protocol::Http1).await;
+    let client = create_client(
+        ca,
+        &format!("http://{}", proxy.proxy_listen_addr().unwrap()),
+        Protocol::Http1,
+    )
+    .await;
+    let resp = client
+        .get(format!(
+            "https://127.0.0.1:{}/example",
+            target.listen_addr().port()
+        ))
+        .send()
+        .await?;
+    assert!(resp.status().is_success());
+
+    {
+        let flows = flows.lock().unwrap();
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].path, "/example");
+        assert_eq!(flows[0].status, Some(200));
+    }
+    proxy.shutdown().await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn e2e_replayed_cassette_matches_recording() -> Result<()> {
+    let (mut registry, _temp_dir) = create_plugin_registry().await?;
+    register_test_component(&mut registry).await?;
+    let events = vec![
+        RecordedEvent::Request {
+            method: "POST".to_string(),
+            url: "https://example.com/submit".to_string(),
+            headers: vec![("content-type".to_string(), "text/plain".to_string())],
+            body: "hello".to_string(),
+        },
+        RecordedEvent::Response {
+            method: "GET".to_string(),
+            url: "https://example.com/".to_string(),
+            status: 200,
+            headers: Vec::new(),
+            body: "world".to_string(),
+        },
+    ];
+    let cassette = record(registry, events).await?;
+    let Outcome::Event(RecordedEvent::Request { headers, .. }) = &cassette.exchanges[0].outcome
+    else {
+        panic!(
+            "Expected a request, got {:?}",
+            cassette.exchanges[0].outcome
+        );
+    };
+    assert!(headers.contains(&("witmproxy".to_string(), "req".to_string())));
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("cassette.json");
+    cassette.save(&path)?;
+    let loaded = Cassette::load(&path)?;
+    assert_eq!(loaded, cassette);
+
+    let (mut registry, _temp_dir) = create_plugin_registry().await?;
+    register_test_component(&mut registry).await?;
+    assert_eq!(replay(registry, &loaded).await?, cassette);
+    Ok(())
+}
@@ -1,9 +1,11 @@
 pub use crate::events::content::InboundContent;
+pub use crate::plugins::pattern::CompiledRegex;
 pub use crate::wasm::bindgen::exports::witmproxy::plugin::witm_plugin::{
     ActualInput, ConfigureError, Event, InputSchema, InputType, PluginManifest, UserInput,
 };
 pub use crate::wasm::{
-    AnnotatorClient, CapabilityProvider, ClockClient, LocalStorageClient, Logger,
+    AnnotatorClient, CapabilityProvider, ClockClient, FlowReader, GraphqlClient, HtmlClient,
+    InferenceClient, JwtClient, LocalStorageClient, Logger, MessageBus, RegexClient,
 };
 
 wasmtime::component::bindgen!({
@@ -19,6 +21,14 @@ wasmtime::component::bindgen!({
         "witmproxy:plugin/capabilities.local-storage-client": LocalStorageClient,
         "witmproxy:plugin/capabilities.logger": Logger,
         "witmproxy:plugin/capabilities.clock-client": ClockClient,
+        "witmproxy:plugin/capabilities.flow-reader": FlowReader,
+        "witmproxy:plugin/capabilities.jwt-client": JwtClient,
+        "witmproxy:plugin/capabilities.graphql-client": GraphqlClient,
+        "witmproxy:plugin/capabilities.message-bus": MessageBus,
+        "witmproxy:plugin/capabilities.regex-client": RegexClient,
+        "witmproxy:plugin/capabilities.regex": CompiledRegex,
+        "witmproxy:plugin/capabilities.inference-client": InferenceClient,
+        "witmproxy:plugin/capabilities.html-client": HtmlClient,
         "witmproxy:plugin/capabilities.content": InboundContent,
         "wasi:http/types@0.3.0-rc-2026-03-15": wasmtime_wasi_http::p3::bindings::http::types,
     },
@@ -203,6 +213,25 @@ impl Serialize for witmproxy::plugin::capabilities::CapabilityKind {
             witmproxy::plugin::capabilities::CapabilityKind::Clock => {
                 serializer.serialize_str("clock")
             }
+            witmproxy::plugin::capabilities::CapabilityKind::FlowReader => {
+                serializer.serialize_str("flow_reader")
+            }
+            witmproxy::plugin::capabilities::CapabilityKind::Jwt => serializer.serialize_str("jwt"),
+            witmproxy::plugin::capabilities::CapabilityKind::Graphql => {
+                serializer.serialize_str("graphql")
+            }
+            witmproxy::plugin::capabilities::CapabilityKind::Messaging => {
+                serializer.serialize_str("messaging")
+            }
+            witmproxy::plugin::capabilities::CapabilityKind::Regex => {
+                serializer.serialize_str("regex")
+            }
+            witmproxy::plugin::capabilities::CapabilityKind::Inference => {
+                serializer.serialize_str("inference")
+            }
+            witmproxy::plugin::capabilities::CapabilityKind::Html => {
+                serializer.serialize_str("html")
+            }
         }
     }
 }
@@ -235,6 +264,15 @@ impl<'de> Deserialize<'de> for witmproxy::plugin::capabilities::CapabilityKind {
                         Ok(witmproxy::plugin::capabilities::CapabilityKind::LocalStorage)
                     }
                     "clock" => Ok(witmproxy::plugin::capabilities::CapabilityKind::Clock),
+                    "flow_reader" => {
+                        Ok(witmproxy::plugin::capabilities::CapabilityKind::FlowReader)
+                    }
+                    "jwt" => Ok(witmproxy::plugin::capabilities::CapabilityKind::Jwt),
+                    "graphql" => Ok(witmproxy::plugin::capabilities::CapabilityKind::Graphql),
+                    "messaging" => Ok(witmproxy::plugin::capabilities::CapabilityKind::Messaging),
+                    "regex" => Ok(witmproxy::plugin::capabilities::CapabilityKind::Regex),
+                    "inference" => Ok(witmproxy::plugin::capabilities::CapabilityKind::Inference),
+                    "html" => Ok(witmproxy::plugin::capabilities::CapabilityKind::Html),
 
                     // New flat snake_case event handlers
                     "handle_event_connect" => Ok(
@@ -262,6 +300,16 @@ impl<'de> Deserialize<'de> for witmproxy::plugin::capabilities::CapabilityKind {
                             witmproxy::plugin::capabilities::EventKind::Timer,
                         ),
                     ),
+                    "handle_event_raw_stream" => Ok(
+                        witmproxy::plugin::capabilities::CapabilityKind::HandleEvent(
+                            witmproxy::plugin::capabilities::EventKind::RawStream,
+                        ),
+                    ),
+                    "handle_event_mqtt_message" => Ok(
+                        witmproxy::plugin::capabilities::CapabilityKind::HandleEvent(
+                            witmproxy::plugin::capabilities::EventKind::MqttMessage,
+                        ),
+                    ),
 
                     _ => Err(de::Error::unknown_variant(
                         value,
@@ -270,11 +318,20 @@ impl<'de> Deserialize<'de> for witmproxy::plugin::capabilities::CapabilityKind {
                             "annotator",
                             "local_storage",
                             "clock",
+                            "flow_reader",
+                            "jwt",
+                            "graphql",
+                            "messaging",
+                            "regex",
+                            "inference",
+                            "html",
                             "handle_event_connect",
                             "handle_event_request",
                             "handle_event_response",
                             "handle_event_inbound_content",
                             "handle_event_timer",
+                            "handle_event_raw_stream",
+                            "handle_event_mqtt_message",
                         ],
                     )),
                 }
@@ -292,11 +349,20 @@ impl<'de> Deserialize<'de> for witmproxy::plugin::capabilities::CapabilityKind {
                         "annotator",
                         "local_storage",
                         "clock",
+                        "flow_reader",
+                        "jwt",
+                        "graphql",
+                        "messaging",
+                        "regex",
+                        "inference",
+                        "html",
                         "handle_event_connect",
                         "handle_event_request",
                         "handle_event_response",
                         "handle_event_inbound_content",
                         "handle_event_timer",
+                        "handle_event_raw_stream",
+                        "handle_event_mqtt_message",
                     ],
                 ))
             }
@@ -325,6 +391,12 @@ impl Serialize for witmproxy::plugin::capabilities::EventKind {
                 serializer.serialize_str("inbound_content")
             }
             witmproxy::plugin::capabilities::EventKind::Timer => serializer.serialize_str("timer"),
+            witmproxy::plugin::capabilities::EventKind::RawStream => {
+                serializer.serialize_str("raw_stream")
+            }
+            witmproxy::plugin::capabilities::EventKind::MqttMessage => {
+                serializer.serialize_str("mqtt_message")
+            }
         }
     }
 }
@@ -358,9 +430,19 @@ impl<'de> Deserialize<'de> for witmproxy::plugin::capabilities::EventKind {
                         Ok(witmproxy::plugin::capabilities::EventKind::InboundContent)
                     }
                     "timer" => Ok(witmproxy::plugin::capabilities::EventKind::Timer),
+                    "raw_stream" => Ok(witmproxy::plugin::capabilities::EventKind::RawStream),
+                    "mqtt_message" => Ok(witmproxy::plugin::capabilities::EventKind::MqttMessage),
                     _ => Err(de::Error::unknown_variant(
                         value,
-                        &["connect", "request", "response", "inbound_content", "timer"],
+                        &[
+                            "connect",
+                            "request",
+                            "response",
+                            "inbound_content",
+                            "timer",
+                            "raw_stream",
+                            "mqtt_message",
+                        ],
                     )),
                 }
             }
@@ -379,6 +461,8 @@ impl witmproxy::plugin::capabilities::EventKind {
             witmproxy::plugin::capabilities::EventKind::Response => "response",
             witmproxy::plugin::capabilities::EventKind::InboundContent => "inbound_content",
             witmproxy::plugin::capabilities::EventKind::Timer => "timer",
+            witmproxy::plugin::capabilities::EventKind::RawStream => "raw_stream",
+            witmproxy::plugin::capabilities::EventKind::MqttMessage => "mqtt_message",
         }
     }
 }
@@ -402,6 +486,12 @@ impl PartialEq for witmproxy::plugin::capabilities::EventKind {
             ) | (
                 witmproxy::plugin::capabilities::EventKind::Timer,
                 witmproxy::plugin::capabilities::EventKind::Timer,
+            ) | (
+                witmproxy::plugin::capabilities::EventKind::RawStream,
+                witmproxy::plugin::capabilities::EventKind::RawStream,
+            ) | (
+                witmproxy::plugin::capabilities::EventKind::MqttMessage,
+                witmproxy::plugin::capabilities::EventKind::MqttMessage,
             )
         )
     }
@@ -430,6 +520,34 @@ impl PartialEq for witmproxy::plugin::capabilities::CapabilityKind {
                 witmproxy::plugin::capabilities::CapabilityKind::Clock,
                 witmproxy::plugin::capabilities::CapabilityKind::Clock,
             ) => true,
+            (
+                witmproxy::plugin::capabilities::CapabilityKind::FlowReader,
+                witmproxy::plugin::capabilities::CapabilityKind::FlowReader,
+            ) => true,
+            (
+                witmproxy::plugin::capabilities::CapabilityKind::Jwt,
+                witmproxy::plugin::capabilities::CapabilityKind::Jwt,
+            ) => true,
+            (
+                witmproxy::plugin::capabilities::CapabilityKind::Graphql,
+                witmproxy::plugin::capabilities::CapabilityKind::Graphql,
+            ) => true,
+            (
+                witmproxy::plugin::capabilities::CapabilityKind::Messaging,
+                witmproxy::plugin::capabilities::CapabilityKind::Messaging,
+            ) => true,
+            (
+                witmproxy::plugin::capabilities::CapabilityKind::Regex,
+                witmproxy::plugin::capabilities::CapabilityKind::Regex,
+            ) => true,
+            (
+                witmproxy::plugin::capabilities::CapabilityKind::Inference,
+                witmproxy::plugin::capabilities::CapabilityKind::Inference,
+            ) => true,
+            (
+                witmproxy::plugin::capabilities::CapabilityKind::Html,
+                witmproxy::plugin::capabilities::CapabilityKind::Html,
+            ) => true,
             _ => false,
         }
     }
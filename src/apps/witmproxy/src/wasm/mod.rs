@@ -6,6 +6,8 @@ use std::task::{Context, Poll};
 
 use anyhow::Result;
 use bytes::Bytes;
+use cel_cxx::{Activation, Program, Value};
+use chrono::Utc;
 use http_body::Body as _;
 use http_body_util::BodyExt;
 use http_body_util::combinators::UnsyncBoxBody;
@@ -17,21 +19,48 @@ use wasmtime::component::{
     Accessor, Destination, HasData, Resource, ResourceTable, Source, StreamProducer, StreamReader,
     StreamResult,
 };
-use wasmtime_wasi::{WasiCtx, WasiCtxBuilder, WasiCtxView, WasiView};
+use wasmtime_wasi::{
+    Deterministic, HostMonotonicClock, HostWallClock, TrappableError, WasiCtx, WasiCtxBuilder,
+    WasiCtxView, WasiView,
+};
 use wasmtime_wasi_http::WasiHttpCtx;
 use wasmtime_wasi_http::p3::bindings::http::types::ErrorCode;
+use wasmtime_wasi_http::p3::{RequestOptions, WasiHttpHooks};
 
 mod runtime;
+pub mod tape;
 
 use crate::events::content::InboundContent;
+use crate::http::graphql::{self, GraphqlOperation};
+use crate::http::jwt::{self, Jwt, KeySets};
+use crate::http::sniff;
+use crate::plugins::bus::Bus;
 use crate::plugins::capabilities::Capability;
+use crate::plugins::cel;
+use crate::plugins::inference::{self, Models};
+use crate::plugins::pattern::{self, CompiledRegex};
+use crate::plugins::posture::CapabilityUses;
+use crate::plugins::redaction::Redactions;
+use crate::plugins::storage_snapshots::{self, StorageOperation};
+use crate::plugins::{html, images, replace};
+use crate::proxy::findings;
+use crate::proxy::flows::{FlowLog, FlowQuery, FlowRecord};
 use crate::wasm::bindgen::witmproxy::plugin::capabilities::{
-    CapabilityKind, HostAnnotatorClient, HostAnnotatorClientWithStore, HostCapabilityProvider,
-    HostCapabilityProviderWithStore, HostClockClient, HostClockClientWithStore, HostContent,
-    HostContentWithStore, HostLocalStorageClient, HostLocalStorageClientWithStore, HostLogger,
-    HostLoggerWithStore,
+    CapabilityKind, FlowQuery as WitFlowQuery, FlowSummary,
+    GraphqlOperation as WitGraphqlOperation, HostAnnotatorClient, HostAnnotatorClientWithStore,
+    HostCapabilityProvider, HostCapabilityProviderWithStore, HostClockClient,
+    HostClockClientWithStore, HostContent, HostContentWithStore, HostFlowReader,
+    HostFlowReaderWithStore, HostGraphqlClient, HostGraphqlClientWithStore, HostHtmlClient,
+    HostHtmlClientWithStore, HostInferenceClient, HostInferenceClientWithStore, HostJwtClient,
+    HostJwtClientWithStore, HostLocalStorageClient, HostLocalStorageClientWithStore, HostLogger,
+    HostLoggerWithStore, HostMessageBus, HostMessageBusWithStore, HostRegex, HostRegexClient,
+    HostRegexClientWithStore, HostRegexWithStore, HtmlField as WitHtmlField,
+    HtmlForm as WitHtmlForm, HtmlLink as WitHtmlLink, HtmlScript as WitHtmlScript,
+    HtmlSummary as WitHtmlSummary, ImageOptions as WitImageOptions, Jwt as WitJwt,
+    Replacement as WitReplacement, Tensor as WitTensor, TensorData as WitTensorData,
 };
-pub use runtime::Runtime;
+pub use runtime::{Profile, Runtime};
+use tape::Tape;
 
 pub mod bindgen;
 
@@ -44,6 +73,15 @@ pub struct CapabilityProvider {
     annotator: Option<AnnotatorClient>,
     local_storage: Option<LocalStorageClient>,
     clock: Option<ClockClient>,
+    flow_reader: Option<FlowReader>,
+    jwt: Option<JwtClient>,
+    graphql: Option<GraphqlClient>,
+    messaging: Option<MessageBus>,
+    regex: Option<RegexClient>,
+    inference: Option<InferenceClient>,
+    html: Option<HtmlClient>,
+    /// Where to count the capabilities the plugin takes, and its ID
+    uses: Option<(CapabilityUses, String)>,
 }
 
 impl CapabilityProvider {
@@ -76,6 +114,62 @@ impl CapabilityProvider {
         self
     }
 
+    /// Set the flow reader capability
+    pub fn with_flow_reader(mut self, flow_reader: FlowReader) -> Self {
+        self.flow_reader = Some(flow_reader);
+        self
+    }
+
+    /// Set the JWT capability
+    pub fn with_jwt(mut self, jwt: JwtClient) -> Self {
+        self.jwt = Some(jwt);
+        self
+    }
+
+    /// Set the GraphQL capability
+    pub fn with_graphql(mut self, graphql: GraphqlClient) -> Self {
+        self.graphql = Some(graphql);
+        self
+    }
+
+    /// Set the messaging capability
+    pub fn with_messaging(mut self, messaging: MessageBus) -> Self {
+        self.messaging = Some(messaging);
+        self
+    }
+
+    /// Set the regex capability
+    pub fn with_regex(mut self, regex: RegexClient) -> Self {
+        self.regex = Some(regex);
+        self
+    }
+
+    /// Set the inference capability
+    pub fn with_inference(mut self, inference: InferenceClient) -> Self {
+        self.inference = Some(inference);
+        self
+    }
+
+    /// Set the HTML capability
+    pub fn with_html(mut self, html: HtmlClient) -> Self {
+        self.html = Some(html);
+        self
+    }
+
+    /// Count the capabilities plugin `plugin_id` takes from the provider in
+    /// `uses`
+    pub fn with_uses(mut self, uses: CapabilityUses, plugin_id: String) -> Self {
+        self.uses = Some((uses, plugin_id));
+        self
+    }
+
+    /// Count a use of the `kind` capability
+    pub fn used(&self, kind: CapabilityKind) {
+        if let Some((uses, plugin_id)) = &self.uses {
+            uses.record(plugin_id, &kind.to_string(), Utc::now());
+        }
+    }
+
     /// Returns a clone of the logger if granted
     pub fn logger(&self) -> Option<Logger> {
         self.logger.clone()
@@ -95,13 +189,48 @@ impl CapabilityProvider {
     pub fn clock(&self) -> Option<ClockClient> {
         self.clock.clone()
     }
+
+    /// Returns a clone of the flow reader if granted
+    pub fn flow_reader(&self) -> Option<FlowReader> {
+        self.flow_reader.clone()
+    }
+
+    /// Returns a clone of the JWT client if granted
+    pub fn jwt(&self) -> Option<JwtClient> {
+        self.jwt.clone()
+    }
+
+    /// Returns a clone of the GraphQL client if granted
+    pub fn graphql(&self) -> Option<GraphqlClient> {
+        self.graphql.clone()
+    }
+
+    /// Returns a clone of the message bus if granted
+    pub fn messaging(&self) -> Option<MessageBus> {
+        self.messaging.clone()
+    }
+
+    /// Returns a clone of the regex client if granted
+    pub fn regex(&self) -> Option<RegexClient> {
+        self.regex.clone()
+    }
+
+    /// Returns a clone of the inference client if granted
+    pub fn inference(&self) -> Option<InferenceClient> {
+        self.inference.clone()
+    }
+
+    /// Returns a clone of the HTML client if granted
+    pub fn html(&self) -> Option<HtmlClient> {
+        self.html.clone()
+    }
 }
 
 impl From<&Vec<Capability>> for CapabilityProvider {
     fn from(capabilities: &Vec<Capability>) -> Self {
         let mut provider = CapabilityProvider::new();
         for cap in capabilities {
-            if cap.granted {
+            if cap.is_active() {
                 match &cap.inner.kind {
                     CapabilityKind::Logger => {
                         provider = provider.with_logger(Logger::new());
@@ -115,6 +244,27 @@ impl From<&Vec<Capability>> for CapabilityProvider {
                     CapabilityKind::Clock => {
                         provider = provider.with_clock(ClockClient::new());
                     }
+                    CapabilityKind::FlowReader => {
+                        // Granted by the plugin registry, which owns the flow log
+                    }
+                    CapabilityKind::Jwt => {
+                        // Granted by the plugin registry, which owns the key sets
+                    }
+                    CapabilityKind::Graphql => {
+                        // Granted by the plugin registry, which knows the current event
+                    }
+                    CapabilityKind::Messaging => {
+                        // Granted by the plugin registry, which owns the bus
+                    }
+                    CapabilityKind::Regex => {
+                        provider = provider.with_regex(RegexClient::new());
+                    }
+                    CapabilityKind::Inference => {
+                        // Granted by the plugin registry, which owns the models
+                    }
+                    CapabilityKind::Html => {
+                        provider = provider.with_html(HtmlClient::new());
+                    }
                     CapabilityKind::HandleEvent(_) => {
                         // Event handling capabilities are managed separately
                     }
@@ -274,6 +424,8 @@ impl Default for Logger {
 #[derive(Clone)]
 pub struct LocalStorageClient {
     store: Arc<RwLock<HashMap<String, Bytes>>>,
+    /// ID of the plugin the storage is for, to snapshot its accesses
+    plugin: Option<String>,
 }
 
 impl Default for LocalStorageClient {
@@ -286,48 +438,78 @@ impl LocalStorageClient {
     pub fn new() -> Self {
         Self {
             store: Arc::new(RwLock::new(HashMap::new())),
+            plugin: None,
+        }
+    }
+
+    /// Storage for `plugin_id`, whose accesses are added to the storage
+    /// snapshot of the flow being handled
+    pub fn for_plugin(plugin_id: String) -> Self {
+        Self {
+            plugin: Some(plugin_id),
+            ..Self::new()
+        }
+    }
+
+    fn snapshot(&self, operation: StorageOperation, key: &str, value: Option<&[u8]>) {
+        if let Some(plugin) = &self.plugin {
+            storage_snapshots::record(plugin, operation, key, value);
         }
     }
 
     /// Set a key-value pair in the store (async)
     pub async fn set(&self, key: String, value: Vec<u8>) {
+        self.snapshot(StorageOperation::Set, &key, Some(&value));
         self.store.write().await.insert(key, Bytes::from(value));
     }
 
     /// Get a value by key (async). Returns cloned Bytes which is cheap.
     pub async fn get(&self, key: &str) -> Option<Bytes> {
-        self.store.read().await.get(key).cloned()
+        let value = self.store.read().await.get(key).cloned();
+        self.snapshot(StorageOperation::Get, key, value.as_deref());
+        value
     }
 
     /// Delete a key from the store (async)
     pub async fn delete(&self, key: &str) {
+        self.snapshot(StorageOperation::Delete, key, None);
         self.store.write().await.remove(key);
     }
 }
 
 /// A clock client providing access to the current system time.
 #[derive(Clone)]
-pub struct ClockClient {}
+pub struct ClockClient {
+    // Time since the UNIX epoch reported instead of the system time
+    frozen: Option<std::time::Duration>,
+}
 
 impl ClockClient {
     pub fn new() -> Self {
-        Self {}
+        Self { frozen: None }
+    }
+
+    /// A clock which always reports `at`, for deterministic plugins
+    pub fn frozen(at: std::time::Duration) -> Self {
+        Self { frozen: Some(at) }
+    }
+
+    fn since_epoch(&self) -> std::time::Duration {
+        self.frozen.unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+        })
     }
 
     /// Returns the current time as a Unix timestamp in seconds
     pub fn now_seconds(&self) -> u64 {
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs()
+        self.since_epoch().as_secs()
     }
 
     /// Returns the current time as a Unix timestamp in milliseconds
     pub fn now_millis(&self) -> u64 {
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_millis() as u64
+        self.since_epoch().as_millis() as u64
     }
 }
 
@@ -337,10 +519,323 @@ impl Default for ClockClient {
     }
 }
 
+/// Read-only access to the flows recently intercepted by the proxy, limited
+/// to those of the plugin's tenant within its `flow_reader` scope. Clone is
+/// cheap and all clones read the same [FlowLog].
+#[derive(Clone)]
+pub struct FlowReader {
+    flows: FlowLog,
+    /// Only flows of this tenant are read; none for anonymous clients
+    tenant: Option<String>,
+    /// The capability's scope, evaluated against each flow's request
+    scope: Option<Program<'static>>,
+    /// Rules redacting the URLs of the flows they match, and the ID of the
+    /// plugin reading, which may be exempt from them
+    redactions: Redactions,
+    plugin_id: String,
+}
+
+impl FlowReader {
+    /// Read the flows of `tenant` matching `scope`. Without a compiled
+    /// scope, no flows are read.
+    pub fn new(flows: FlowLog, tenant: Option<String>, scope: Option<Program<'static>>) -> Self {
+        Self {
+            flows,
+            tenant,
+            scope,
+            redactions: Redactions::default(),
+            plugin_id: String::new(),
+        }
+    }
+
+    /// Redact the URLs of the flows `redactions` match for `plugin_id`
+    pub fn with_redactions(mut self, redactions: Redactions, plugin_id: &str) -> Self {
+        self.redactions = redactions;
+        self.plugin_id = plugin_id.to_string();
+        self
+    }
+
+    /// Returns the flows matching `query` the plugin may read, newest first
+    pub fn query(&self, query: &FlowQuery) -> Vec<FlowRecord> {
+        let mut flows = self.flows.query_visible(query, |flow| self.visible(flow));
+        for flow in &mut flows {
+            if self.redactions.matches_flow(&self.plugin_id, flow) {
+                (flow.path, flow.query) = findings::redact_url(&flow.path, &flow.query);
+            }
+        }
+        flows
+    }
+
+    fn visible(&self, flow: &FlowRecord) -> bool {
+        if flow.tenant != self.tenant {
+            return false;
+        }
+        let Some(scope) = &self.scope else {
+            return false;
+        };
+        // Scopes reading what flows don't keep, ex: `response`, fail to
+        // evaluate and hide the flow
+        match cel::bind_flow(flow, Activation::new()).map(|a| scope.evaluate(a)) {
+            Some(Ok(Value::Bool(visible))) => visible,
+            _ => false,
+        }
+    }
+}
+
+impl From<WitFlowQuery> for FlowQuery {
+    fn from(query: WitFlowQuery) -> Self {
+        Self {
+            host: query.host,
+            since_millis: query.since_millis,
+            until_millis: query.until_millis,
+            annotation: query.annotation,
+            limit: query.limit.map(|limit| limit as usize),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<FlowRecord> for FlowSummary {
+    fn from(flow: FlowRecord) -> Self {
+        Self {
+            id: flow.id,
+            timestamp_millis: flow.timestamp_millis,
+            method: flow.method,
+            scheme: flow.scheme,
+            host: flow.host,
+            path: flow.path,
+            query: flow.query,
+            status: flow.status,
+            annotations: flow.annotations,
+        }
+    }
+}
+
+/// Decoding and verification of JWTs for plugins. Clone is cheap and all
+/// clones share the same cache of [KeySets].
+#[derive(Clone)]
+pub struct JwtClient {
+    key_sets: KeySets,
+}
+
+impl JwtClient {
+    pub fn new(key_sets: KeySets) -> Self {
+        Self { key_sets }
+    }
+
+    /// Decode `token` without verifying its signature
+    pub fn decode(&self, token: &str) -> Result<Jwt> {
+        Jwt::decode(token)
+    }
+
+    /// Decode the token carried by request `headers`, without verifying it
+    pub fn find(&self, headers: &[(String, Vec<u8>)]) -> Option<Jwt> {
+        let values = |name: &'static str| {
+            headers
+                .iter()
+                .filter(move |(header, _)| header.eq_ignore_ascii_case(name))
+                .filter_map(|(_, value)| std::str::from_utf8(value).ok())
+        };
+        jwt::find_token(values("authorization"), values("cookie"))
+            .and_then(|token| Jwt::decode(token).ok())
+    }
+
+    /// Decode `token`, verifying it against the configured key sets
+    pub async fn verify(&self, token: &str) -> Result<Jwt> {
+        self.key_sets.verify(token).await
+    }
+}
+
+impl From<Jwt> for WitJwt {
+    fn from(jwt: Jwt) -> Self {
+        Self {
+            algorithm: jwt.algorithm().to_string(),
+            key_id: jwt.key_id().map(str::to_string),
+            claims: serde_json::Value::Object(jwt.claims).to_string(),
+            verified: jwt.verified,
+        }
+    }
+}
+
+/// The GraphQL operation of the event a plugin is handling, for plugins
+#[derive(Clone, Default)]
+pub struct GraphqlClient {
+    operation: Option<GraphqlOperation>,
+}
+
+impl GraphqlClient {
+    pub fn new(operation: Option<GraphqlOperation>) -> Self {
+        Self { operation }
+    }
+
+    /// The operation run by the request of the current event
+    pub fn operation(&self) -> Option<GraphqlOperation> {
+        self.operation.clone()
+    }
+
+    /// Parse the operation run by a request `body`, JSON or a bare document
+    pub fn parse(&self, body: &str) -> Result<GraphqlOperation> {
+        graphql::parse_json(body.as_bytes())
+            .or_else(|| graphql::parse_document(body, None))
+            .ok_or_else(|| anyhow::anyhow!("The body has no GraphQL operation"))
+    }
+}
+
+impl From<GraphqlOperation> for WitGraphqlOperation {
+    fn from(operation: GraphqlOperation) -> Self {
+        Self {
+            name: Some(operation.name).filter(|name| !name.is_empty()),
+            operation_type: operation.operation_type.as_str().to_string(),
+            fields: operation.fields,
+        }
+    }
+}
+
+/// A plugin's access to the [Bus] it exchanges messages with other plugins
+/// on. Clone is cheap and all clones share the same bus.
+#[derive(Clone)]
+pub struct MessageBus {
+    bus: Bus,
+    /// ID of the plugin publishing and subscribing
+    plugin_id: String,
+}
+
+impl MessageBus {
+    pub fn new(bus: Bus, plugin_id: String) -> Self {
+        Self { bus, plugin_id }
+    }
+
+    pub fn publish(&self, topic: &str, payload: Vec<u8>) -> Result<()> {
+        self.bus.publish(&self.plugin_id, topic, payload)
+    }
+
+    pub fn subscribe(&self, topic: &str) -> Result<()> {
+        self.bus.subscribe(&self.plugin_id, topic)
+    }
+
+    pub fn unsubscribe(&self, topic: &str) {
+        self.bus.unsubscribe(&self.plugin_id, topic)
+    }
+}
+
+/// Compiles regular expressions for plugins, within the limits of [pattern]
+#[derive(Clone, Default)]
+pub struct RegexClient {}
+
+impl RegexClient {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn compile(&self, expression: &str) -> Result<CompiledRegex> {
+        CompiledRegex::new(expression)
+    }
+}
+
+/// Summarizes HTML content for plugins, within the limits of [html]
+#[derive(Clone, Default)]
+pub struct HtmlClient {}
+
+impl HtmlClient {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl From<&html::HtmlSummary> for WitHtmlSummary {
+    fn from(summary: &html::HtmlSummary) -> Self {
+        Self {
+            title: summary.title.clone(),
+            meta: summary.meta.clone(),
+            links: summary
+                .links
+                .iter()
+                .map(|link| WitHtmlLink {
+                    element: link.element.clone(),
+                    href: link.href.clone(),
+                    rel: link.rel.clone(),
+                    text: link.text.clone(),
+                })
+                .collect(),
+            forms: summary
+                .forms
+                .iter()
+                .map(|form| WitHtmlForm {
+                    action: form.action.clone(),
+                    method: form.method.clone(),
+                    fields: form
+                        .fields
+                        .iter()
+                        .map(|field| WitHtmlField {
+                            name: field.name.clone(),
+                            field_type: field.field_type.clone(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+            scripts: summary
+                .scripts
+                .iter()
+                .map(|script| WitHtmlScript {
+                    src: script.src.clone(),
+                    script_type: script.script_type.clone(),
+                    inline_length: script.inline_length.try_into().unwrap_or(u32::MAX),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Runs the models the operator installed, within the limits of [inference]
+#[derive(Clone)]
+pub struct InferenceClient {
+    models: Models,
+}
+
+impl InferenceClient {
+    pub fn new(models: Models) -> Self {
+        Self { models }
+    }
+}
+
+impl From<WitTensor> for inference::Tensor {
+    fn from(tensor: WitTensor) -> Self {
+        Self {
+            shape: tensor.shape.into_iter().map(|d| d as usize).collect(),
+            data: match tensor.data {
+                WitTensorData::Floats(values) => inference::TensorData::Floats(values),
+                WitTensorData::Integers(values) => inference::TensorData::Integers(values),
+            },
+        }
+    }
+}
+
+impl From<inference::Tensor> for WitTensor {
+    fn from(tensor: inference::Tensor) -> Self {
+        Self {
+            shape: tensor.shape.into_iter().map(|d| d as u32).collect(),
+            data: match tensor.data {
+                inference::TensorData::Floats(values) => WitTensorData::Floats(values),
+                inference::TensorData::Integers(values) => WitTensorData::Integers(values),
+            },
+        }
+    }
+}
+
+impl From<pattern::Match> for RegexMatch {
+    fn from(m: pattern::Match) -> Self {
+        Self {
+            start: m.start as u32,
+            end: m.end as u32,
+            text: m.text,
+        }
+    }
+}
+
 /// Builder-style structure used to create a [`WitmProxyCtx`].
 #[derive(Default)]
 pub struct WitmProxyCtxBuilder {
-    // Add any initial configuration here
+    tape: Option<Tape>,
 }
 
 impl WitmProxyCtxBuilder {
@@ -349,17 +844,22 @@ impl WitmProxyCtxBuilder {
         Default::default()
     }
 
+    /// Record the plugin's capability calls to `tape`, or answer them from it
+    /// when it's replaying
+    pub fn tape(mut self, tape: Tape) -> Self {
+        self.tape = Some(tape);
+        self
+    }
+
     /// Uses the configured context so far to construct the final [`WitmProxyCtx`].
     pub fn build(self) -> WitmProxyCtx {
-        WitmProxyCtx {
-            // Initialize context state
-        }
+        WitmProxyCtx { tape: self.tape }
     }
 }
 
 /// Capture the state necessary for use in the `witmproxy:plugin` API implementation.
 pub struct WitmProxyCtx {
-    // Add context state here
+    tape: Option<Tape>,
 }
 
 impl WitmProxyCtx {
@@ -371,14 +871,36 @@ impl WitmProxyCtx {
 
 /// A wrapper capturing the needed internal `witmproxy:plugin` state.
 pub struct WitmProxyCtxView<'a> {
-    _ctx: &'a WitmProxyCtx,
+    ctx: &'a WitmProxyCtx,
     pub table: &'a mut ResourceTable,
 }
 
 impl<'a> WitmProxyCtxView<'a> {
     /// Create a new view into the `witmproxy:plugin` state.
     pub fn new(ctx: &'a WitmProxyCtx, table: &'a mut ResourceTable) -> Self {
-        Self { _ctx: ctx, table }
+        Self { ctx, table }
+    }
+
+    /// The tape the plugin's capability calls go through, if any
+    pub fn tape(&self) -> Option<Tape> {
+        self.ctx.tape.clone()
+    }
+}
+
+/// Answer a capability call with `live`, or through `tape` when there is one
+async fn taped<R>(
+    tape: Option<Tape>,
+    capability: &str,
+    method: &str,
+    args: serde_json::Value,
+    live: impl Future<Output = R>,
+) -> wasmtime::Result<R>
+where
+    R: serde::Serialize + serde::de::DeserializeOwned,
+{
+    match tape {
+        Some(tape) => tape.call(capability, method, args, live).await,
+        None => Ok(live.await),
     }
 }
 
@@ -388,6 +910,8 @@ pub struct Host {
     pub wasi: WasiCtx,
     pub http: WasiHttpCtx,
     pub witmproxy_ctx: WitmProxyCtx,
+    // Set to refuse the guest's outbound HTTP requests
+    deny_http: Option<DenyOutboundHttp>,
 }
 
 impl Default for Host {
@@ -397,10 +921,81 @@ impl Default for Host {
             wasi: WasiCtxBuilder::new().build(),
             http: WasiHttpCtx::new(),
             witmproxy_ctx: WitmProxyCtxBuilder::new().build(),
+            deny_http: None,
+        }
+    }
+}
+
+impl Host {
+    /// Host state for [Profile::Deterministic](runtime::Profile::Deterministic)
+    /// stores: clocks never advance from the UNIX epoch, random bytes repeat a
+    /// fixed sequence, and sockets and outbound HTTP are refused.
+    pub fn deterministic() -> Self {
+        // Sockets are already denied unless the builder inherits the network
+        let wasi = WasiCtxBuilder::new()
+            .wall_clock(FrozenClock)
+            .monotonic_clock(FrozenClock)
+            .secure_random(Deterministic::new(DETERMINISTIC_RANDOM_BYTES.to_vec()))
+            .insecure_random(Deterministic::new(DETERMINISTIC_RANDOM_BYTES.to_vec()))
+            .insecure_random_seed(0)
+            .build();
+        Self {
+            wasi,
+            deny_http: Some(DenyOutboundHttp),
+            ..Self::default()
         }
     }
 }
 
+const DETERMINISTIC_RANDOM_BYTES: [u8; 8] = [0x2a, 0x91, 0x5c, 0xe3, 0x07, 0xb8, 0x4f, 0xd6];
+
+/// A wall and monotonic clock stopped at zero
+struct FrozenClock;
+
+impl HostWallClock for FrozenClock {
+    fn resolution(&self) -> std::time::Duration {
+        std::time::Duration::from_nanos(1)
+    }
+
+    fn now(&self) -> std::time::Duration {
+        std::time::Duration::ZERO
+    }
+}
+
+impl HostMonotonicClock for FrozenClock {
+    fn resolution(&self) -> u64 {
+        1
+    }
+
+    fn now(&self) -> u64 {
+        0
+    }
+}
+
+/// HTTP hooks refusing every outbound request the guest sends
+struct DenyOutboundHttp;
+
+impl WasiHttpHooks for DenyOutboundHttp {
+    fn send_request(
+        &mut self,
+        _request: hyper::Request<UnsyncBoxBody<Bytes, ErrorCode>>,
+        _options: Option<RequestOptions>,
+        _fut: Box<dyn Future<Output = Result<(), ErrorCode>> + Send>,
+    ) -> Box<
+        dyn Future<
+                Output = Result<
+                    (
+                        hyper::Response<UnsyncBoxBody<Bytes, ErrorCode>>,
+                        Box<dyn Future<Output = Result<(), ErrorCode>> + Send>,
+                    ),
+                    TrappableError<ErrorCode>,
+                >,
+            > + Send,
+    > {
+        Box::new(async { Err(ErrorCode::HttpRequestDenied.into()) })
+    }
+}
+
 impl HostContentWithStore for WitmProxy {
     async fn drop<T>(
         accessor: &Accessor<T, Self>,
@@ -539,6 +1134,76 @@ impl HostContentWithStore for WitmProxy {
 
         Ok(())
     }
+
+    async fn transform_body<T>(
+        accessor: &wasmtime::component::Accessor<T, Self>,
+        self_: wasmtime::component::Resource<InboundContent>,
+    ) -> wasmtime::Result<()> {
+        // The registry streams the body through the plugin once it returns
+        accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            state.table.get_mut(&self_)?.request_transform();
+            Ok::<(), wasmtime::component::ResourceTableError>(())
+        })?;
+        Ok(())
+    }
+
+    async fn replace<T>(
+        accessor: &wasmtime::component::Accessor<T, Self>,
+        self_: wasmtime::component::Resource<InboundContent>,
+        replacements: Vec<WitReplacement>,
+    ) -> wasmtime::Result<Result<(), String>> {
+        let replacements = match replacements
+            .into_iter()
+            .map(replace::Replacement::try_from)
+            .collect::<anyhow::Result<Vec<_>>>()
+        {
+            Ok(replacements) => replacements,
+            Err(e) => return Ok(Err(format!("{:#}", e))),
+        };
+        accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            let content = state.table.get_mut(&self_)?;
+            Ok(match content.body().unwrap_or(None) {
+                Some(body) => {
+                    content.set_body(replace::apply(body, replacements));
+                    Ok(())
+                }
+                None => Err("Content body has already been consumed".to_string()),
+            })
+        })
+    }
+
+    async fn transcode_image<T>(
+        accessor: &wasmtime::component::Accessor<T, Self>,
+        self_: wasmtime::component::Resource<InboundContent>,
+        options: WitImageOptions,
+    ) -> wasmtime::Result<Result<(), String>> {
+        let taken = accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            let content = state.table.get_mut(&self_)?;
+            let body = content.body().unwrap_or(None);
+            Ok::<_, wasmtime::component::ResourceTableError>(
+                body.map(|body| (body, content.content_type())),
+            )
+        })?;
+        let Some((body, content_type)) = taken else {
+            return Ok(Err("Content body has already been consumed".to_string()));
+        };
+        let (body, transcoded) = images::apply(body, &content_type, options.into()).await;
+        accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            let content = state.table.get_mut(&self_)?;
+            content.set_body(body);
+            Ok(match transcoded {
+                Ok(format) => {
+                    content.set_content_type(format.mime_type());
+                    Ok(())
+                }
+                Err(e) => Err(format!("{:#}", e)),
+            })
+        })
+    }
 }
 
 // Implement the Host traits using the accessor pattern
@@ -550,14 +1215,14 @@ impl HostLocalStorageClientWithStore for WitmProxy {
         value: Vec<u8>,
     ) -> wasmtime::Result<()> {
         // Clone the client (cheap Arc clone) to use outside the accessor closure
-        let client = accessor.with(|mut access| {
+        let (client, tape) = accessor.with(|mut access| {
             let state: &mut WitmProxyCtxView = &mut access.get();
-            let client = state.table.get(&self_)?;
-            Ok::<LocalStorageClient, wasmtime::component::ResourceTableError>(client.clone())
+            let client = state.table.get(&self_)?.clone();
+            Ok::<_, wasmtime::component::ResourceTableError>((client, state.tape()))
         })?;
         // Call async method on the cloned client (shares Arc storage)
-        client.set(key, value).await;
-        Ok(())
+        let args = serde_json::json!([key, value]);
+        taped(tape, "local-storage", "set", args, client.set(key, value)).await
     }
 
     async fn get<T>(
@@ -566,13 +1231,14 @@ impl HostLocalStorageClientWithStore for WitmProxy {
         key: String,
     ) -> wasmtime::Result<Option<Vec<u8>>> {
         // Clone the client (cheap Arc clone) to use outside the accessor closure
-        let client = accessor.with(|mut access| {
+        let (client, tape) = accessor.with(|mut access| {
             let state: &mut WitmProxyCtxView = &mut access.get();
-            let client = state.table.get(&self_)?;
-            Ok::<LocalStorageClient, wasmtime::component::ResourceTableError>(client.clone())
+            let client = state.table.get(&self_)?.clone();
+            Ok::<_, wasmtime::component::ResourceTableError>((client, state.tape()))
         })?;
         // Call async method and convert Bytes to Vec<u8> for WIT interface
-        Ok(client.get(&key).await.map(|bytes| bytes.to_vec()))
+        let live = async { client.get(&key).await.map(|bytes| bytes.to_vec()) };
+        taped(tape, "local-storage", "get", serde_json::json!([key]), live).await
     }
 
     async fn delete<T>(
@@ -581,14 +1247,21 @@ impl HostLocalStorageClientWithStore for WitmProxy {
         key: String,
     ) -> wasmtime::Result<()> {
         // Clone the client (cheap Arc clone) to use outside the accessor closure
-        let client = accessor.with(|mut access| {
+        let (client, tape) = accessor.with(|mut access| {
             let state: &mut WitmProxyCtxView = &mut access.get();
-            let client = state.table.get(&self_)?;
-            Ok::<LocalStorageClient, wasmtime::component::ResourceTableError>(client.clone())
+            let client = state.table.get(&self_)?.clone();
+            Ok::<_, wasmtime::component::ResourceTableError>((client, state.tape()))
         })?;
         // Call async method on the cloned client (shares Arc storage)
-        client.delete(&key).await;
-        Ok(())
+        let live = client.delete(&key);
+        taped(
+            tape,
+            "local-storage",
+            "delete",
+            serde_json::json!([key]),
+            live,
+        )
+        .await
     }
 
     async fn drop<T>(
@@ -702,24 +1375,32 @@ impl HostClockClientWithStore for WitmProxy {
         accessor: &Accessor<T, Self>,
         self_: Resource<ClockClient>,
     ) -> wasmtime::Result<u64> {
-        let result = accessor.with(|mut access| {
+        let (result, tape) = accessor.with(|mut access| {
             let state: &mut WitmProxyCtxView = &mut access.get();
             let client = state.table.get(&self_)?;
-            Ok::<u64, wasmtime::component::ResourceTableError>(client.now_seconds())
+            Ok::<_, wasmtime::component::ResourceTableError>((client.now_seconds(), state.tape()))
         })?;
-        Ok(result)
+        let method = "now-seconds";
+        taped(tape, "clock", method, serde_json::Value::Null, async {
+            result
+        })
+        .await
     }
 
     async fn now_millis<T>(
         accessor: &Accessor<T, Self>,
         self_: Resource<ClockClient>,
     ) -> wasmtime::Result<u64> {
-        let result = accessor.with(|mut access| {
+        let (result, tape) = accessor.with(|mut access| {
             let state: &mut WitmProxyCtxView = &mut access.get();
             let client = state.table.get(&self_)?;
-            Ok::<u64, wasmtime::component::ResourceTableError>(client.now_millis())
+            Ok::<_, wasmtime::component::ResourceTableError>((client.now_millis(), state.tape()))
         })?;
-        Ok(result)
+        let method = "now-millis";
+        taped(tape, "clock", method, serde_json::Value::Null, async {
+            result
+        })
+        .await
     }
 
     async fn drop<T>(
@@ -734,21 +1415,473 @@ impl HostClockClientWithStore for WitmProxy {
     }
 }
 
-impl HostCapabilityProviderWithStore for WitmProxy {
-    async fn logger<T>(
+impl HostFlowReaderWithStore for WitmProxy {
+    async fn query<T>(
         accessor: &Accessor<T, Self>,
-        cap: Resource<CapabilityProvider>,
-    ) -> wasmtime::Result<Option<Resource<Logger>>> {
-        Ok(accessor
-            .with(|mut access| {
+        self_: Resource<FlowReader>,
+        query: WitFlowQuery,
+    ) -> wasmtime::Result<Vec<FlowSummary>> {
+        let (reader, tape) = accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            let reader = state.table.get(&self_)?.clone();
+            Ok::<_, wasmtime::component::ResourceTableError>((reader, state.tape()))
+        })?;
+        let query = FlowQuery::from(query);
+        let args = serde_json::to_value(&query)?;
+        let flows = taped(tape, "flow-reader", "query", args, async {
+            reader.query(&query)
+        })
+        .await?;
+        Ok(flows.into_iter().map(FlowSummary::from).collect())
+    }
+
+    async fn drop<T>(
+        accessor: &Accessor<T, Self>,
+        rep: Resource<FlowReader>,
+    ) -> wasmtime::Result<()> {
+        accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            state.table.delete(rep)
+        })?;
+        Ok(())
+    }
+}
+
+impl HostJwtClientWithStore for WitmProxy {
+    async fn decode<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<JwtClient>,
+        token: String,
+    ) -> wasmtime::Result<Result<WitJwt, String>> {
+        let client = accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            let client = state.table.get(&self_)?;
+            Ok::<JwtClient, wasmtime::component::ResourceTableError>(client.clone())
+        })?;
+        Ok(client
+            .decode(&token)
+            .map(WitJwt::from)
+            .map_err(|e| e.to_string()))
+    }
+
+    async fn find<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<JwtClient>,
+        headers: Vec<(String, Vec<u8>)>,
+    ) -> wasmtime::Result<Option<WitJwt>> {
+        let client = accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            let client = state.table.get(&self_)?;
+            Ok::<JwtClient, wasmtime::component::ResourceTableError>(client.clone())
+        })?;
+        Ok(client.find(&headers).map(WitJwt::from))
+    }
+
+    async fn verify<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<JwtClient>,
+        token: String,
+    ) -> wasmtime::Result<Result<WitJwt, String>> {
+        let (client, tape) = accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            let client = state.table.get(&self_)?.clone();
+            Ok::<_, wasmtime::component::ResourceTableError>((client, state.tape()))
+        })?;
+        // Verification may fetch key sets, so it's recorded like the clock
+        let live = async { client.verify(&token).await.map_err(|e| e.to_string()) };
+        let args = serde_json::json!([token]);
+        let jwt = taped(tape, "jwt", "verify", args, live).await?;
+        Ok(jwt.map(WitJwt::from))
+    }
+
+    async fn drop<T>(
+        accessor: &Accessor<T, Self>,
+        rep: Resource<JwtClient>,
+    ) -> wasmtime::Result<()> {
+        accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            state.table.delete(rep)
+        })?;
+        Ok(())
+    }
+}
+
+impl HostGraphqlClientWithStore for WitmProxy {
+    async fn operation<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<GraphqlClient>,
+    ) -> wasmtime::Result<Option<WitGraphqlOperation>> {
+        let client = accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            let client = state.table.get(&self_)?;
+            Ok::<GraphqlClient, wasmtime::component::ResourceTableError>(client.clone())
+        })?;
+        Ok(client.operation().map(WitGraphqlOperation::from))
+    }
+
+    async fn parse<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<GraphqlClient>,
+        body: String,
+    ) -> wasmtime::Result<Result<WitGraphqlOperation, String>> {
+        let client = accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            let client = state.table.get(&self_)?;
+            Ok::<GraphqlClient, wasmtime::component::ResourceTableError>(client.clone())
+        })?;
+        Ok(client
+            .parse(&body)
+            .map(WitGraphqlOperation::from)
+            .map_err(|e| e.to_string()))
+    }
+
+    async fn drop<T>(
+        accessor: &Accessor<T, Self>,
+        rep: Resource<GraphqlClient>,
+    ) -> wasmtime::Result<()> {
+        accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            state.table.delete(rep)
+        })?;
+        Ok(())
+    }
+}
+
+impl HostMessageBusWithStore for WitmProxy {
+    async fn publish<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<MessageBus>,
+        topic: String,
+        payload: Vec<u8>,
+    ) -> wasmtime::Result<Result<(), String>> {
+        let (client, tape) = accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            let client = state.table.get(&self_)?.clone();
+            Ok::<_, wasmtime::component::ResourceTableError>((client, state.tape()))
+        })?;
+        // Whether publishing succeeds depends on the rate limit and queue
+        let args = serde_json::json!([topic, payload]);
+        let live = async { client.publish(&topic, payload).map_err(|e| e.to_string()) };
+        taped(tape, "messaging", "publish", args, live).await
+    }
+
+    async fn subscribe<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<MessageBus>,
+        topic: String,
+    ) -> wasmtime::Result<Result<(), String>> {
+        let client = accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            let client = state.table.get(&self_)?;
+            Ok::<MessageBus, wasmtime::component::ResourceTableError>(client.clone())
+        })?;
+        Ok(client.subscribe(&topic).map_err(|e| e.to_string()))
+    }
+
+    async fn unsubscribe<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<MessageBus>,
+        topic: String,
+    ) -> wasmtime::Result<()> {
+        accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            state.table.get(&self_)?.unsubscribe(&topic);
+            Ok::<(), wasmtime::component::ResourceTableError>(())
+        })?;
+        Ok(())
+    }
+
+    async fn drop<T>(
+        accessor: &Accessor<T, Self>,
+        rep: Resource<MessageBus>,
+    ) -> wasmtime::Result<()> {
+        accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            state.table.delete(rep)
+        })?;
+        Ok(())
+    }
+}
+
+impl HostRegexClientWithStore for WitmProxy {
+    async fn compile<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<RegexClient>,
+        expression: String,
+    ) -> wasmtime::Result<Result<Resource<CompiledRegex>, String>> {
+        accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            let client = state.table.get(&self_)?;
+            Ok(match client.compile(&expression) {
+                Ok(regex) => Ok(state.table.push(regex)?),
+                Err(e) => Err(format!("{:#}", e)),
+            })
+        })
+    }
+
+    async fn drop<T>(
+        accessor: &Accessor<T, Self>,
+        rep: Resource<RegexClient>,
+    ) -> wasmtime::Result<()> {
+        accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            state.table.delete(rep)
+        })?;
+        Ok(())
+    }
+}
+
+/// The inference client `self_` refers to, cloned out of the table
+fn inference_client<T>(
+    accessor: &Accessor<T, WitmProxy>,
+    self_: &Resource<InferenceClient>,
+) -> wasmtime::Result<InferenceClient> {
+    Ok(accessor.with(|mut access| {
+        let state: &mut WitmProxyCtxView = &mut access.get();
+        let client = state.table.get(self_)?;
+        Ok::<InferenceClient, wasmtime::component::ResourceTableError>(client.clone())
+    })?)
+}
+
+fn wit_tensors(outputs: Result<Vec<inference::Tensor>>) -> Result<Vec<WitTensor>, String> {
+    outputs
+        .map(|outputs| outputs.into_iter().map(WitTensor::from).collect())
+        .map_err(|e| format!("{:#}", e))
+}
+
+impl HostInferenceClientWithStore for WitmProxy {
+    async fn models<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<InferenceClient>,
+    ) -> wasmtime::Result<Vec<String>> {
+        Ok(inference_client(accessor, &self_)?.models.names())
+    }
+
+    async fn run<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<InferenceClient>,
+        model: String,
+        inputs: Vec<WitTensor>,
+    ) -> wasmtime::Result<Result<Vec<WitTensor>, String>> {
+        let client = inference_client(accessor, &self_)?;
+        let inputs = inputs.into_iter().map(inference::Tensor::from).collect();
+        Ok(wit_tensors(client.models.run(&model, inputs).await))
+    }
+
+    async fn run_image<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<InferenceClient>,
+        model: String,
+        image: Vec<u8>,
+    ) -> wasmtime::Result<Result<Vec<WitTensor>, String>> {
+        let client = inference_client(accessor, &self_)?;
+        Ok(wit_tensors(client.models.run_image(&model, image).await))
+    }
+
+    async fn drop<T>(
+        accessor: &Accessor<T, Self>,
+        rep: Resource<InferenceClient>,
+    ) -> wasmtime::Result<()> {
+        accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            state.table.delete(rep)
+        })?;
+        Ok(())
+    }
+}
+
+impl HostHtmlClientWithStore for WitmProxy {
+    async fn summarize<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<HtmlClient>,
+        content: Resource<InboundContent>,
+    ) -> wasmtime::Result<Result<WitHtmlSummary, String>> {
+        let cached = accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            state.table.get(&self_)?;
+            let content = state.table.get(&content)?;
+            Ok::<_, wasmtime::component::ResourceTableError>(content.html_summary())
+        })?;
+        // Parsed already, for this plugin or one before it
+        if let Some(summary) = cached {
+            return Ok(Ok(WitHtmlSummary::from(&*summary)));
+        }
+        let taken = accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            let content = state.table.get_mut(&content)?;
+            // Generic declared types are replaced by the type sniffed, whose
+            // charset the body was transcoded from if needed
+            let declared = content.content_type();
+            let content_type = match content.sniffed_type() {
+                Some(sniffed) if sniff::essence(&declared) != sniffed => sniffed,
+                _ => declared,
+            };
+            let body = content.body().unwrap_or(None);
+            Ok::<_, wasmtime::component::ResourceTableError>(body.map(|body| (body, content_type)))
+        })?;
+        let Some((body, content_type)) = taken else {
+            return Ok(Err("Content body has already been consumed".to_string()));
+        };
+        let (body, summary) = html::summarize(body, &content_type).await;
+        accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            let content = state.table.get_mut(&content)?;
+            content.set_body(body);
+            Ok(match summary {
+                Ok(summary) => {
+                    let summarized = WitHtmlSummary::from(&*summary);
+                    content.set_html_summary(summary);
+                    Ok(summarized)
+                }
+                Err(e) => Err(format!("{:#}", e)),
+            })
+        })
+    }
+
+    async fn drop<T>(
+        accessor: &Accessor<T, Self>,
+        rep: Resource<HtmlClient>,
+    ) -> wasmtime::Result<()> {
+        accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            state.table.delete(rep)
+        })?;
+        Ok(())
+    }
+}
+
+/// The regex `self_` refers to, cloned out of the table
+fn compiled_regex<T>(
+    accessor: &Accessor<T, WitmProxy>,
+    self_: &Resource<CompiledRegex>,
+) -> wasmtime::Result<CompiledRegex> {
+    Ok(accessor.with(|mut access| {
+        let state: &mut WitmProxyCtxView = &mut access.get();
+        let regex = state.table.get(self_)?;
+        Ok::<CompiledRegex, wasmtime::component::ResourceTableError>(regex.clone())
+    })?)
+}
+
+impl HostRegexWithStore for WitmProxy {
+    async fn is_match<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<CompiledRegex>,
+        text: String,
+    ) -> wasmtime::Result<bool> {
+        Ok(compiled_regex(accessor, &self_)?.is_match(&text))
+    }
+
+    async fn find<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<CompiledRegex>,
+        text: String,
+    ) -> wasmtime::Result<Option<RegexMatch>> {
+        Ok(compiled_regex(accessor, &self_)?
+            .find(&text)
+            .map(RegexMatch::from))
+    }
+
+    async fn find_all<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<CompiledRegex>,
+        text: String,
+    ) -> wasmtime::Result<Vec<RegexMatch>> {
+        Ok(compiled_regex(accessor, &self_)?
+            .find_all(&text)
+            .into_iter()
+            .map(RegexMatch::from)
+            .collect())
+    }
+
+    async fn captures<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<CompiledRegex>,
+        text: String,
+    ) -> wasmtime::Result<Option<Vec<Option<RegexMatch>>>> {
+        Ok(compiled_regex(accessor, &self_)?
+            .captures(&text)
+            .map(|groups| {
+                groups
+                    .into_iter()
+                    .map(|m| m.map(RegexMatch::from))
+                    .collect()
+            }))
+    }
+
+    async fn replace<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<CompiledRegex>,
+        text: String,
+        replacement: String,
+        limit: Option<u32>,
+    ) -> wasmtime::Result<String> {
+        let limit = limit.map(|limit| limit as usize);
+        Ok(compiled_regex(accessor, &self_)?.replace(&text, &replacement, limit))
+    }
+
+    async fn replace_body<T>(
+        accessor: &Accessor<T, Self>,
+        self_: Resource<CompiledRegex>,
+        content: Resource<InboundContent>,
+        replacement: String,
+        max_length: u32,
+        limit: Option<u32>,
+    ) -> wasmtime::Result<Result<(), String>> {
+        let regex = compiled_regex(accessor, &self_)?;
+        let parsed = match replace::Replacement::regex(
+            regex.expression(),
+            max_length as usize,
+            &replacement,
+        ) {
+            Ok(parsed) => parsed,
+            Err(e) => return Ok(Err(format!("{:#}", e))),
+        };
+        let parsed = match limit {
+            Some(limit) => parsed.with_limit(limit as usize),
+            None => parsed,
+        };
+        accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            let content = state.table.get_mut(&content)?;
+            Ok(match content.body().unwrap_or(None) {
+                Some(body) => {
+                    content.set_body(replace::apply(body, vec![parsed]));
+                    Ok(())
+                }
+                None => Err("Content body has already been consumed".to_string()),
+            })
+        })
+    }
+
+    async fn drop<T>(
+        accessor: &Accessor<T, Self>,
+        rep: Resource<CompiledRegex>,
+    ) -> wasmtime::Result<()> {
+        accessor.with(|mut access| {
+            let state: &mut WitmProxyCtxView = &mut access.get();
+            state.table.delete(rep)
+        })?;
+        Ok(())
+    }
+}
+
+impl HostCapabilityProviderWithStore for WitmProxy {
+    async fn logger<T>(
+        accessor: &Accessor<T, Self>,
+        cap: Resource<CapabilityProvider>,
+    ) -> wasmtime::Result<Option<Resource<Logger>>> {
+        Ok(accessor
+            .with(|mut access| {
                 let state: &mut WitmProxyCtxView = &mut access.get();
                 let provider = state.table.get(&cap)?;
                 // Get a clone of the logger if granted (cheap clone)
                 match provider.logger() {
-                    Some(logger) => Ok::<
-                        Option<Resource<Logger>>,
-                        wasmtime::component::ResourceTableError,
-                    >(Some(state.table.push(logger)?)),
+                    Some(logger) => {
+                        provider.used(CapabilityKind::Logger);
+                        Ok::<Option<Resource<Logger>>, wasmtime::component::ResourceTableError>(
+                            Some(state.table.push(logger)?),
+                        )
+                    }
                     None => Ok(None),
                 }
             })
@@ -765,10 +1898,13 @@ impl HostCapabilityProviderWithStore for WitmProxy {
                 let provider = state.table.get(&cap)?;
                 // Get a clone of the local storage client if granted (cheap Arc clone)
                 match provider.local_storage() {
-                    Some(client) => Ok::<
-                        Option<Resource<LocalStorageClient>>,
-                        wasmtime::component::ResourceTableError,
-                    >(Some(state.table.push(client)?)),
+                    Some(client) => {
+                        provider.used(CapabilityKind::LocalStorage);
+                        Ok::<
+                            Option<Resource<LocalStorageClient>>,
+                            wasmtime::component::ResourceTableError,
+                        >(Some(state.table.push(client)?))
+                    }
                     None => Ok(None),
                 }
             })
@@ -785,10 +1921,13 @@ impl HostCapabilityProviderWithStore for WitmProxy {
                 let provider = state.table.get(&cap)?;
                 // Get a clone of the annotator client if granted (cheap clone)
                 match provider.annotator() {
-                    Some(client) => Ok::<
-                        Option<Resource<AnnotatorClient>>,
-                        wasmtime::component::ResourceTableError,
-                    >(Some(state.table.push(client)?)),
+                    Some(client) => {
+                        provider.used(CapabilityKind::Annotator);
+                        Ok::<
+                            Option<Resource<AnnotatorClient>>,
+                            wasmtime::component::ResourceTableError,
+                        >(Some(state.table.push(client)?))
+                    }
                     None => Ok(None),
                 }
             })
@@ -799,15 +1938,174 @@ impl HostCapabilityProviderWithStore for WitmProxy {
         accessor: &Accessor<T, Self>,
         cap: Resource<CapabilityProvider>,
     ) -> wasmtime::Result<Option<Resource<ClockClient>>> {
+        Ok(
+            accessor
+                .with(|mut access| {
+                    let state: &mut WitmProxyCtxView = &mut access.get();
+                    let provider = state.table.get(&cap)?;
+                    match provider.clock() {
+                        Some(client) => {
+                            provider.used(CapabilityKind::Clock);
+                            Ok::<
+                                Option<Resource<ClockClient>>,
+                                wasmtime::component::ResourceTableError,
+                            >(Some(state.table.push(client)?))
+                        }
+                        None => Ok(None),
+                    }
+                })
+                .unwrap_or(None),
+        )
+    }
+
+    async fn flow_reader<T>(
+        accessor: &Accessor<T, Self>,
+        cap: Resource<CapabilityProvider>,
+    ) -> wasmtime::Result<Option<Resource<FlowReader>>> {
         Ok(accessor
             .with(|mut access| {
                 let state: &mut WitmProxyCtxView = &mut access.get();
                 let provider = state.table.get(&cap)?;
-                match provider.clock() {
-                    Some(client) => Ok::<
-                        Option<Resource<ClockClient>>,
-                        wasmtime::component::ResourceTableError,
-                    >(Some(state.table.push(client)?)),
+                match provider.flow_reader() {
+                    Some(reader) => {
+                        provider.used(CapabilityKind::FlowReader);
+                        Ok::<Option<Resource<FlowReader>>, wasmtime::component::ResourceTableError>(
+                            Some(state.table.push(reader)?),
+                        )
+                    }
+                    None => Ok(None),
+                }
+            })
+            .unwrap_or(None))
+    }
+
+    async fn jwt<T>(
+        accessor: &Accessor<T, Self>,
+        cap: Resource<CapabilityProvider>,
+    ) -> wasmtime::Result<Option<Resource<JwtClient>>> {
+        Ok(accessor
+            .with(|mut access| {
+                let state: &mut WitmProxyCtxView = &mut access.get();
+                let provider = state.table.get(&cap)?;
+                match provider.jwt() {
+                    Some(client) => {
+                        provider.used(CapabilityKind::Jwt);
+                        Ok::<Option<Resource<JwtClient>>, wasmtime::component::ResourceTableError>(
+                            Some(state.table.push(client)?),
+                        )
+                    }
+                    None => Ok(None),
+                }
+            })
+            .unwrap_or(None))
+    }
+
+    async fn graphql<T>(
+        accessor: &Accessor<T, Self>,
+        cap: Resource<CapabilityProvider>,
+    ) -> wasmtime::Result<Option<Resource<GraphqlClient>>> {
+        Ok(
+            accessor
+                .with(|mut access| {
+                    let state: &mut WitmProxyCtxView = &mut access.get();
+                    let provider = state.table.get(&cap)?;
+                    match provider.graphql() {
+                        Some(client) => {
+                            provider.used(CapabilityKind::Graphql);
+                            Ok::<
+                                Option<Resource<GraphqlClient>>,
+                                wasmtime::component::ResourceTableError,
+                            >(Some(state.table.push(client)?))
+                        }
+                        None => Ok(None),
+                    }
+                })
+                .unwrap_or(None),
+        )
+    }
+
+    async fn messaging<T>(
+        accessor: &Accessor<T, Self>,
+        cap: Resource<CapabilityProvider>,
+    ) -> wasmtime::Result<Option<Resource<MessageBus>>> {
+        Ok(accessor
+            .with(|mut access| {
+                let state: &mut WitmProxyCtxView = &mut access.get();
+                let provider = state.table.get(&cap)?;
+                match provider.messaging() {
+                    Some(bus) => {
+                        provider.used(CapabilityKind::Messaging);
+                        Ok::<Option<Resource<MessageBus>>, wasmtime::component::ResourceTableError>(
+                            Some(state.table.push(bus)?),
+                        )
+                    }
+                    None => Ok(None),
+                }
+            })
+            .unwrap_or(None))
+    }
+
+    async fn regex<T>(
+        accessor: &Accessor<T, Self>,
+        cap: Resource<CapabilityProvider>,
+    ) -> wasmtime::Result<Option<Resource<RegexClient>>> {
+        Ok(
+            accessor
+                .with(|mut access| {
+                    let state: &mut WitmProxyCtxView = &mut access.get();
+                    let provider = state.table.get(&cap)?;
+                    match provider.regex() {
+                        Some(client) => {
+                            provider.used(CapabilityKind::Regex);
+                            Ok::<
+                                Option<Resource<RegexClient>>,
+                                wasmtime::component::ResourceTableError,
+                            >(Some(state.table.push(client)?))
+                        }
+                        None => Ok(None),
+                    }
+                })
+                .unwrap_or(None),
+        )
+    }
+
+    async fn inference<T>(
+        accessor: &Accessor<T, Self>,
+        cap: Resource<CapabilityProvider>,
+    ) -> wasmtime::Result<Option<Resource<InferenceClient>>> {
+        Ok(accessor
+            .with(|mut access| {
+                let state: &mut WitmProxyCtxView = &mut access.get();
+                let provider = state.table.get(&cap)?;
+                match provider.inference() {
+                    Some(client) => {
+                        provider.used(CapabilityKind::Inference);
+                        Ok::<
+                            Option<Resource<InferenceClient>>,
+                            wasmtime::component::ResourceTableError,
+                        >(Some(state.table.push(client)?))
+                    }
+                    None => Ok(None),
+                }
+            })
+            .unwrap_or(None))
+    }
+
+    async fn html<T>(
+        accessor: &Accessor<T, Self>,
+        cap: Resource<CapabilityProvider>,
+    ) -> wasmtime::Result<Option<Resource<HtmlClient>>> {
+        Ok(accessor
+            .with(|mut access| {
+                let state: &mut WitmProxyCtxView = &mut access.get();
+                let provider = state.table.get(&cap)?;
+                match provider.html() {
+                    Some(client) => {
+                        provider.used(CapabilityKind::Html);
+                        Ok::<Option<Resource<HtmlClient>>, wasmtime::component::ResourceTableError>(
+                            Some(state.table.push(client)?),
+                        )
+                    }
                     None => Ok(None),
                 }
             })
@@ -836,6 +2134,14 @@ impl HostLocalStorageClient for WitmProxyCtxView<'_> {}
 impl HostAnnotatorClient for WitmProxyCtxView<'_> {}
 impl HostLogger for WitmProxyCtxView<'_> {}
 impl HostClockClient for WitmProxyCtxView<'_> {}
+impl HostFlowReader for WitmProxyCtxView<'_> {}
+impl HostJwtClient for WitmProxyCtxView<'_> {}
+impl HostGraphqlClient for WitmProxyCtxView<'_> {}
+impl HostMessageBus for WitmProxyCtxView<'_> {}
+impl HostRegexClient for WitmProxyCtxView<'_> {}
+impl HostRegex for WitmProxyCtxView<'_> {}
+impl HostInferenceClient for WitmProxyCtxView<'_> {}
+impl HostHtmlClient for WitmProxyCtxView<'_> {}
 
 impl WasiView for Host {
     fn ctx(&mut self) -> WasiCtxView<'_> {
@@ -851,7 +2157,10 @@ impl wasmtime_wasi_http::p3::WasiHttpView for Host {
         wasmtime_wasi_http::p3::WasiHttpCtxView {
             table: &mut self.table,
             ctx: &mut self.http,
-            hooks: Default::default(),
+            hooks: match &mut self.deny_http {
+                Some(hooks) => hooks,
+                None => Default::default(),
+            },
         }
     }
 }
@@ -870,3 +2179,95 @@ struct WitmProxy;
 impl HasData for WitmProxy {
     type Data<'a> = WitmProxyCtxView<'a>;
 }
+
+#[cfg(test)]
+mod tests {
+    use cel_cxx::Env;
+    use hyper::Request;
+
+    use super::*;
+    use crate::plugins::redaction::RedactionRule;
+
+    fn scope(expression: &str) -> Program<'static> {
+        let env: &'static Env<'static> = Box::leak(Box::new(
+            bindgen::Event::register(Env::builder().with_standard(true))
+                .unwrap()
+                .build()
+                .unwrap(),
+        ));
+        env.compile(expression).unwrap()
+    }
+
+    fn flow(id: &str, uri: &str, tenant: Option<&str>) -> FlowRecord {
+        let req = Request::get(uri).body(()).unwrap();
+        FlowRecord {
+            tenant: tenant.map(str::to_string),
+            ..FlowRecord::new(id, &req)
+        }
+    }
+
+    #[test]
+    fn flows_outside_the_tenant_or_scope_are_hidden() {
+        let flows = FlowLog::new(8);
+        flows.record(flow("1", "https://a.example/?q=1", Some("acme")));
+        flows.record(flow("2", "https://b.example/", Some("acme")));
+        flows.record(flow("3", "https://a.example/", Some("globex")));
+        flows.record(flow("4", "https://a.example/", None));
+
+        let ids = |reader: &FlowReader| -> Vec<String> {
+            let flows = reader.query(&FlowQuery::default());
+            flows.into_iter().map(|flow| flow.id).collect()
+        };
+        let in_scope = scope("request.host() == 'a.example'");
+        let reader = FlowReader::new(flows.clone(), Some("acme".into()), Some(in_scope.clone()));
+        assert_eq!(ids(&reader), ["1"]);
+        let reader = FlowReader::new(flows.clone(), None, Some(in_scope));
+        assert_eq!(ids(&reader), ["4"]);
+
+        // Scopes reading what flows don't keep hide every flow
+        let response = scope("response.status() == 200");
+        let reader = FlowReader::new(flows.clone(), Some("acme".into()), Some(response));
+        assert!(ids(&reader).is_empty());
+        let reader = FlowReader::new(flows, Some("acme".into()), None);
+        assert!(ids(&reader).is_empty());
+    }
+
+    #[test]
+    fn urls_of_flows_matched_by_redaction_rules_are_redacted() {
+        let flows = FlowLog::new(8);
+        flows.record(flow(
+            "1",
+            "https://a.example/reset?email=bob@example.com",
+            None,
+        ));
+        flows.record(flow(
+            "2",
+            "https://b.example/reset?email=bob@example.com",
+            None,
+        ));
+        let redactions = Redactions::new(vec![RedactionRule {
+            when: "request.host() == 'a.example'".to_string(),
+            headers: vec!["authorization".to_string()],
+            cookies: Vec::new(),
+            body_fields: Vec::new(),
+            mask: false,
+            exempt_plugins: vec!["ops/auth".to_string()],
+        }])
+        .unwrap();
+
+        let queries = |plugin_id: &str| -> Vec<String> {
+            let reader = FlowReader::new(flows.clone(), None, Some(scope("true")))
+                .with_redactions(redactions.clone(), plugin_id);
+            let flows = reader.query(&FlowQuery::default());
+            flows.into_iter().map(|flow| flow.query).collect()
+        };
+        assert_eq!(
+            queries("acme/analytics"),
+            ["email=bob@example.com", "email=[redacted:email]"]
+        );
+        assert_eq!(
+            queries("ops/auth"),
+            ["email=bob@example.com", "email=bob@example.com"]
+        );
+    }
+}
@@ -1,15 +1,43 @@
-use crate::wasm::{Host, WitmProxyCtxView, bindgen::Plugin};
+use crate::wasm::{DenyOutboundHttp, Host, WitmProxyCtxView, bindgen::Plugin};
 use anyhow::Result;
+use std::collections::HashMap;
 use wasmtime::{
     Config, Engine, Store,
-    component::{Component, Linker},
+    component::{Component, Instance, Linker},
 };
 use wasmtime_wasi::p3::bindings::LinkOptions;
 
+/// How much of the host's nondeterminism a plugin's store can observe
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Profile {
+    /// The system clocks and random sources, with outbound HTTP allowed
+    #[default]
+    Standard,
+    /// Clocks frozen at the UNIX epoch, random sources replaced by a fixed
+    /// byte sequence, and no network or outbound HTTP, so the same input
+    /// always produces the same output
+    Deterministic,
+}
+
+impl Profile {
+    /// Plugin metadata key plugins set to `deterministic` to opt in
+    pub const METADATA_KEY: &'static str = "runtime-profile";
+
+    /// The profile requested by a plugin's metadata tags
+    pub fn from_metadata(metadata: &HashMap<String, String>) -> Self {
+        match metadata.get(Self::METADATA_KEY).map(String::as_str) {
+            Some("deterministic") => Profile::Deterministic,
+            _ => Profile::Standard,
+        }
+    }
+}
+
 pub struct Runtime {
     pub engine: Engine,
     pub config: Config,
     pub linker: Linker<Host>,
+    /// Whether stores refuse outbound HTTP whatever the plugin's profile
+    deny_egress: bool,
 }
 
 impl Runtime {
@@ -42,20 +70,72 @@ impl Runtime {
             engine,
             config,
             linker,
+            deny_egress: false,
         })
     }
 
+    /// Refuse the outbound HTTP requests of plugins in every store created,
+    /// ex: to try out untrusted plugins. Sockets are refused regardless.
+    pub fn deny_egress(mut self) -> Self {
+        self.deny_egress = true;
+        self
+    }
+
     pub fn new_store(&self) -> Store<Host> {
-        Store::new(&self.engine, Host::default())
+        self.new_store_with_profile(Profile::Standard)
+    }
+
+    pub fn new_store_with_profile(&self, profile: Profile) -> Store<Host> {
+        let mut host = match profile {
+            Profile::Standard => Host::default(),
+            Profile::Deterministic => Host::deterministic(),
+        };
+        if self.deny_egress {
+            host.deny_http = Some(DenyOutboundHttp);
+        }
+        Store::new(&self.engine, host)
     }
 
     pub async fn instantiate_plugin_component(
         &self,
         component: &Component,
-    ) -> Result<(Plugin, Store<Host>)> {
-        let mut store = self.new_store();
+        profile: Profile,
+    ) -> Result<(Plugin, Instance, Store<Host>)> {
+        let mut store = self.new_store_with_profile(profile);
         let instance = self.linker.instantiate_async(&mut store, component).await?;
         let plugin = Plugin::new(&mut store, &instance)?;
-        Ok((plugin, store))
+        Ok((plugin, instance, store))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plugins_opt_into_the_deterministic_profile() {
+        let mut metadata = HashMap::new();
+        assert_eq!(Profile::from_metadata(&metadata), Profile::Standard);
+
+        metadata.insert(Profile::METADATA_KEY.to_string(), "standard".to_string());
+        assert_eq!(Profile::from_metadata(&metadata), Profile::Standard);
+
+        metadata.insert(
+            Profile::METADATA_KEY.to_string(),
+            "deterministic".to_string(),
+        );
+        assert_eq!(Profile::from_metadata(&metadata), Profile::Deterministic);
+    }
+
+    #[test]
+    fn denying_egress_applies_to_every_profile() {
+        let runtime = Runtime::try_default().unwrap();
+        assert!(runtime.new_store().data().deny_http.is_none());
+
+        let runtime = runtime.deny_egress();
+        for profile in [Profile::Standard, Profile::Deterministic] {
+            let store = runtime.new_store_with_profile(profile);
+            assert!(store.data().deny_http.is_some());
+        }
     }
 }
@@ -0,0 +1,138 @@
+//! Recording of the capability calls plugins make, and their replay in place
+//! of the live capabilities, so a plugin can be run again without the proxy,
+//! network or clock it was recorded against.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A capability call made by a plugin, with its arguments and result
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapabilityCall {
+    /// The capability called, ex: "clock"
+    pub capability: String,
+    /// The function called on it, ex: "now-millis"
+    pub method: String,
+    #[serde(default)]
+    pub args: Value,
+    #[serde(default)]
+    pub result: Value,
+}
+
+#[derive(Default)]
+struct TapeState {
+    calls: Vec<CapabilityCall>,
+    /// Calls still to be answered, when replaying
+    replay: Option<VecDeque<CapabilityCall>>,
+}
+
+/// Capability calls made by plugins. Calls are answered by the live
+/// capabilities and recorded, or after [Tape::replay] answered with the
+/// results of an earlier recording. Cheap to clone; all clones share the
+/// same calls.
+#[derive(Clone, Default)]
+pub struct Tape {
+    state: Arc<Mutex<TapeState>>,
+}
+
+impl Tape {
+    /// Answer the calls that follow with the results recorded in `calls`,
+    /// which must be made in the same order with the same arguments
+    pub fn replay(&self, calls: Vec<CapabilityCall>) {
+        self.state.lock().unwrap().replay = Some(calls.into());
+    }
+
+    /// The calls made since last taken
+    pub fn take(&self) -> Vec<CapabilityCall> {
+        std::mem::take(&mut self.state.lock().unwrap().calls)
+    }
+
+    /// Answer a call, from the recording when replaying and otherwise with
+    /// `live`, recording it either way
+    pub(crate) async fn call<R>(
+        &self,
+        capability: &str,
+        method: &str,
+        args: Value,
+        live: impl Future<Output = R>,
+    ) -> wasmtime::Result<R>
+    where
+        R: Serialize + DeserializeOwned,
+    {
+        let replayed = match self.state.lock().unwrap().replay.as_mut() {
+            Some(replay) => Some(next_call(replay, capability, method, &args)?),
+            None => None,
+        };
+        let result = match replayed {
+            Some(result) => serde_json::from_value(result).map_err(wasmtime::Error::msg)?,
+            None => live.await,
+        };
+
+        let call = CapabilityCall {
+            capability: capability.to_string(),
+            method: method.to_string(),
+            args,
+            result: serde_json::to_value(&result).map_err(wasmtime::Error::msg)?,
+        };
+        self.state.lock().unwrap().calls.push(call);
+        Ok(result)
+    }
+}
+
+/// The recorded result of the next call, which must match the one made
+fn next_call(
+    replay: &mut VecDeque<CapabilityCall>,
+    capability: &str,
+    method: &str,
+    args: &Value,
+) -> wasmtime::Result<Value> {
+    match replay.pop_front() {
+        Some(call)
+            if call.capability == capability && call.method == method && call.args == *args =>
+        {
+            Ok(call.result)
+        }
+        Some(call) => Err(wasmtime::Error::msg(format!(
+            "Plugin called {capability}.{method}({args}) where the recording has {}.{}({})",
+            call.capability, call.method, call.args
+        ))),
+        None => Err(wasmtime::Error::msg(format!(
+            "Plugin called {capability}.{method}({args}) after the last recorded call"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn replays_recorded_results_in_order() {
+        let tape = Tape::default();
+        let now = tape.call("clock", "now-millis", Value::Null, async { 42u64 });
+        assert_eq!(now.await.unwrap(), 42);
+        let get = tape.call("local-storage", "get", json!("k"), async {
+            Some(b"v".to_vec())
+        });
+        assert_eq!(get.await.unwrap(), Some(b"v".to_vec()));
+        let recorded = tape.take();
+        assert_eq!(recorded.len(), 2);
+
+        let tape = Tape::default();
+        tape.replay(recorded.clone());
+        let now = tape.call("clock", "now-millis", Value::Null, async { 7u64 });
+        assert_eq!(now.await.unwrap(), 42);
+        let get = tape.call("local-storage", "get", json!("other"), async {
+            None::<Vec<u8>>
+        });
+        assert!(get.await.is_err());
+        let extra = tape.call("clock", "now-millis", Value::Null, async { 7u64 });
+        assert!(extra.await.is_err());
+        assert_eq!(tape.take(), recorded[..1]);
+    }
+}
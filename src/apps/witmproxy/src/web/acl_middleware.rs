@@ -5,6 +5,7 @@ use sqlx::SqlitePool;
 use tracing::{debug, warn};
 
 use crate::acl;
+use crate::db::api_tokens::ApiToken;
 use crate::db::tenants::Tenant;
 
 /// ACL middleware that checks if the authenticated tenant has permission
@@ -14,6 +15,8 @@ use crate::db::tenants::Tenant;
 ///
 /// Usage: Add this handler after `jwt_auth` in the middleware chain.
 /// The `tenant_id` must already be in the depot (set by jwt_auth).
+/// Requests authenticated with an API token are checked against the
+/// token's role instead.
 #[handler]
 pub async fn acl_check(
     req: &mut Request,
@@ -32,6 +35,23 @@ pub async fn acl_check(
         _ => return,
     };
 
+    // API tokens are scoped by role rather than by tenant permissions
+    if let Ok(api_token) = depot.get::<ApiToken>("api_token") {
+        let permitted = api_token
+            .role()
+            .map(|role| role.permits(req.method().as_str(), req.uri().path()))
+            .unwrap_or(false);
+        if permitted {
+            debug!("ACL granted: token {} -> {}", api_token.name, req.uri());
+        } else {
+            debug!("ACL denied: token {} -> {}", api_token.name, req.uri());
+            res.status_code(StatusCode::FORBIDDEN);
+            res.render(Text::Plain("Insufficient permissions"));
+            ctrl.skip_rest();
+        }
+        return;
+    }
+
     let tenant_id = match depot.get::<String>("tenant_id") {
         Ok(id) => id.clone(),
         Err(_) => {
@@ -131,6 +151,8 @@ fn derive_resource_from_request(req: &Request) -> String {
     // /api/manage/groups/:id/members -> groups:<id>:manage
     // /api/manage/groups/:id/permissions -> groups:<id>:manage
     // /api/manage/tenants/:id/plugins/:ns/:name/... -> plugins:<ns>/<name>:configure
+    // /api/manage/audit -> audit:*:read
+    // /api/status -> status:*:read
 
     let segments: Vec<&str> = path
         .trim_start_matches("/api/manage/")
@@ -147,6 +169,9 @@ fn derive_resource_from_request(req: &Request) -> String {
         ["groups", id] => format!("groups:{}:{}", id, action),
         ["groups", id, "members"] => format!("groups:{}:manage", id),
         ["groups", id, "permissions"] => format!("groups:{}:manage", id),
+        ["audit", ..] => format!("audit:*:{}", action),
+        // Not under /api/manage, so left untrimmed
+        ["api", "status"] => format!("status:*:{}", action),
         _ => format!("unknown:*:{}", action),
     }
 }
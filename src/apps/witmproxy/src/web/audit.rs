@@ -0,0 +1,96 @@
+use salvo::http::StatusError;
+use salvo::oapi::extract::QueryParam;
+use salvo::oapi::{ToSchema, endpoint};
+use salvo::prelude::*;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use tracing::{debug, warn};
+
+use crate::db::api_tokens::ApiToken;
+use crate::db::audit::{AuditAction, AuditEntry, AuditFilter};
+
+/// The actor recorded for requests to the web API: the authenticated tenant
+/// or API token, or `anonymous` when authentication is disabled.
+pub fn actor(depot: &Depot) -> String {
+    if let Ok(api_token) = depot.get::<ApiToken>("api_token") {
+        return format!("token:{}", api_token.name);
+    }
+    match depot.get::<String>("tenant_id") {
+        Ok(tenant_id) => format!("tenant:{}", tenant_id),
+        Err(_) => "anonymous".to_string(),
+    }
+}
+
+/// Append an entry to the audit log on behalf of the current request.
+///
+/// Failing to audit is logged rather than failing the (already applied) action.
+pub async fn record(
+    depot: &Depot,
+    action: AuditAction,
+    target: Option<&str>,
+    details: serde_json::Value,
+) {
+    let Ok(pool) = depot.obtain::<SqlitePool>() else {
+        debug!("No database available, skipping audit of {}", action);
+        return;
+    };
+    if let Err(e) = AuditEntry::record(pool, &actor(depot), action, target, details).await {
+        warn!("Failed to record audit entry for {}: {}", action, e);
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AuditEntryResponse {
+    id: i64,
+    timestamp: String,
+    actor: String,
+    action: String,
+    target: Option<String>,
+    details: serde_json::Value,
+    instance: Option<String>,
+}
+
+impl From<AuditEntry> for AuditEntryResponse {
+    fn from(e: AuditEntry) -> Self {
+        Self {
+            id: e.id,
+            timestamp: e.timestamp,
+            actor: e.actor,
+            action: e.action,
+            target: e.target,
+            details: serde_json::from_str(&e.details).unwrap_or(serde_json::Value::Null),
+            instance: e.instance,
+        }
+    }
+}
+
+/// GET /api/manage/audit -- query the audit log, newest first.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 500))]
+pub async fn list_audit_log(
+    actor: QueryParam<String, false>,
+    action: QueryParam<String, false>,
+    target: QueryParam<String, false>,
+    instance: QueryParam<String, false>,
+    since: QueryParam<String, false>,
+    limit: QueryParam<i64, false>,
+    depot: &mut Depot,
+) -> Result<Json<Vec<AuditEntryResponse>>, StatusError> {
+    let pool = depot
+        .obtain::<SqlitePool>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("Database not available"))?;
+
+    let filter = AuditFilter {
+        actor: actor.into_inner(),
+        action: action.into_inner(),
+        target: target.into_inner(),
+        instance: instance.into_inner(),
+        since: since.into_inner(),
+        limit: limit.into_inner(),
+    };
+    let entries = AuditEntry::query(&pool, &filter).await.map_err(|e| {
+        warn!("Failed to query audit log: {}", e);
+        StatusError::internal_server_error().brief("Internal error")
+    })?;
+    Ok(Json(entries.into_iter().map(Into::into).collect()))
+}
@@ -3,9 +3,11 @@ use salvo::handler;
 use salvo::http::StatusCode;
 use salvo::prelude::*;
 use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
 use tracing::{debug, warn};
 
 use crate::config::AuthConfig;
+use crate::db::api_tokens::{API_TOKEN_PREFIX, ApiToken};
 
 /// JWT claims for witmproxy management tokens.
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -70,6 +72,9 @@ pub fn decode_token(
 
 /// Salvo handler/middleware that extracts and validates JWT from Authorization header.
 /// On success, injects `tenant_id` (String) and `Claims` into the depot.
+///
+/// Bearer tokens starting with `wpt_` are API tokens instead; on success the
+/// matching [`ApiToken`] is injected as `api_token`.
 #[handler]
 pub async fn jwt_auth(
     req: &mut Request,
@@ -108,6 +113,33 @@ pub async fn jwt_auth(
         }
     };
 
+    // API tokens are opaque and looked up in the database
+    if token.starts_with(API_TOKEN_PREFIX) {
+        let api_token = match depot.obtain::<SqlitePool>() {
+            Ok(pool) => ApiToken::authenticate(pool, &token).await,
+            Err(_) => Ok(None),
+        };
+        match api_token {
+            Ok(Some(api_token)) => {
+                debug!("API token validated: {}", api_token.name);
+                depot.insert("api_token", api_token);
+                return;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!("Failed to look up API token: {}", e);
+                res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+                res.render(Text::Plain("Internal error"));
+                ctrl.skip_rest();
+                return;
+            }
+        }
+        res.status_code(StatusCode::UNAUTHORIZED);
+        res.render(Text::Plain("Invalid, revoked or expired API token"));
+        ctrl.skip_rest();
+        return;
+    }
+
     // Try local secret first
     if let Some(ref secret) = auth_config.jwt_secret {
         match decode_token(
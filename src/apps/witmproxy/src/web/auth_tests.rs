@@ -1,9 +1,12 @@
 use crate::db::Db;
+use crate::db::api_tokens::{ApiToken, Role, VIEWER_ROUTES};
+use crate::db::audit::{AuditEntry, AuditFilter};
 use crate::db::tenants::{Group, Tenant};
 use crate::test_utils::create_ca_and_config;
 use crate::wasm::Runtime;
 use crate::web::WebServer;
 use crate::web::auth::{Claims, create_token};
+use crate::web::server::management_router;
 use std::sync::Arc;
 use tempfile::tempdir;
 use tokio::sync::RwLock;
@@ -537,6 +540,226 @@ async fn management_group_crud_flow() {
     assert_eq!(resp.status(), 200);
 }
 
+// ---------------------------------------------------------------------------
+// Audit log
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn management_actions_are_audited() {
+    let (client, base_url, pool, _dir) = setup_auth_server().await;
+
+    Tenant::create(&pool, "t-aud", "Auditor", None, None, None, None)
+        .await
+        .unwrap();
+    Group::create(&pool, "g-aud", "auditors", "").await.unwrap();
+    Group::add_member(&pool, "g-aud", "t-aud").await.unwrap();
+    Group::add_permission(&pool, "p1", "g-aud", "grant", "groups:*:write")
+        .await
+        .unwrap();
+    Group::add_permission(&pool, "p2", "g-aud", "grant", "audit:*:read")
+        .await
+        .unwrap();
+
+    let token = make_token("t-aud", "test-secret-key");
+
+    let resp = client
+        .post(format!("{}/api/manage/groups", base_url))
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&serde_json::json!({"name": "audited"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 201);
+    let group_id = resp.json::<serde_json::Value>().await.unwrap()["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let resp = client
+        .get(format!("{}/api/manage/audit?action=group.create", base_url))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let entries: Vec<serde_json::Value> = resp.json().await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["actor"], "tenant:t-aud");
+    assert_eq!(entries[0]["target"], group_id);
+    assert_eq!(entries[0]["details"]["name"], "audited");
+}
+
+#[tokio::test]
+async fn audit_log_requires_permission() {
+    let (client, base_url, pool, _dir) = setup_auth_server().await;
+
+    Tenant::create(&pool, "t-none", "Nobody", None, None, None, None)
+        .await
+        .unwrap();
+    let token = make_token("t-none", "test-secret-key");
+
+    let resp = client
+        .get(format!("{}/api/manage/audit", base_url))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 403);
+}
+
+// ---------------------------------------------------------------------------
+// API tokens
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn viewer_token_is_read_only() {
+    let (client, base_url, pool, _dir) = setup_auth_server().await;
+
+    let (_, secret) = ApiToken::create(&pool, "dashboard", Role::Viewer, None)
+        .await
+        .unwrap();
+
+    let resp = client
+        .get(format!("{}/api/plugins", base_url))
+        .header("Authorization", format!("Bearer {}", secret))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let resp = client
+        .delete(format!("{}/api/plugins/ns/name", base_url))
+        .header("Authorization", format!("Bearer {}", secret))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 403);
+
+    for path in [
+        "/api/plugins/ns/name/config",
+        "/api/manage/flows/1/trace",
+        "/api/manage/flows/1/storage",
+        "/api/manage/schemas",
+        "/api/manage/traffic",
+        "/api/manage/sessions/1/flows",
+    ] {
+        let resp = client
+            .get(format!("{}{}", base_url, path))
+            .header("Authorization", format!("Bearer {}", secret))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 403, "{} should be denied", path);
+    }
+}
+
+/// Routes with a GET handler which viewers are deliberately not allowed to
+/// read, see [VIEWER_ROUTES]
+const ADMIN_READ_ROUTES: &[&str] = &[
+    "/api/manage/tenants/*/ip-mappings",
+    "/api/manage/config",
+    "/api/manage/redactions",
+    "/api/manage/header-overrides",
+    "/api/manage/schemas",
+    "/api/manage/schemas/*",
+    "/api/manage/flows/*/trace",
+    "/api/manage/flows/*/storage",
+    "/api/manage/sessions/*/flows",
+    "/api/manage/traffic",
+    "/api/plugins/*/*/config",
+];
+
+#[test]
+fn every_readable_route_is_classified_for_viewers() {
+    fn readable_routes(router: &salvo::Router, prefix: &str, routes: &mut Vec<String>) {
+        let mut path = prefix.to_string();
+        let mut readable = false;
+        for filter in router.filters() {
+            let filter = format!("{filter:?}");
+            if let Some(segment) = filter.strip_prefix("path:") {
+                path.push_str(segment);
+            }
+            readable |= filter == "method:GET";
+        }
+        if readable {
+            let route = path
+                .split('/')
+                .map(|segment| {
+                    if segment.starts_with('{') {
+                        "*"
+                    } else {
+                        segment
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            routes.push(route);
+        }
+        for child in router.routers() {
+            readable_routes(child, &path, routes);
+        }
+    }
+
+    let mut routes = Vec::new();
+    readable_routes(&management_router(), "", &mut routes);
+    assert!(routes.contains(&"/api/plugins".to_string()));
+    for route in &routes {
+        let for_viewers = VIEWER_ROUTES.contains(&route.as_str());
+        let for_admins = ADMIN_READ_ROUTES.contains(&route.as_str());
+        assert!(
+            for_viewers != for_admins,
+            "{route} must be in exactly one of VIEWER_ROUTES and ADMIN_READ_ROUTES"
+        );
+    }
+    for route in VIEWER_ROUTES.iter().chain(ADMIN_READ_ROUTES) {
+        assert!(
+            routes.iter().any(|r| r == route),
+            "{route} isn't a readable route"
+        );
+    }
+}
+
+#[tokio::test]
+async fn admin_token_can_manage_and_is_audited() {
+    let (client, base_url, pool, _dir) = setup_auth_server().await;
+
+    let (_, secret) = ApiToken::create(&pool, "ci", Role::Admin, None)
+        .await
+        .unwrap();
+
+    let resp = client
+        .post(format!("{}/api/manage/groups", base_url))
+        .header("Authorization", format!("Bearer {}", secret))
+        .json(&serde_json::json!({ "name": "ops" }))
+        .send()
+        .await
+        .unwrap();
+    assert!(resp.status().is_success(), "got {}", resp.status());
+
+    let entries = AuditEntry::query(&pool, &AuditFilter::default())
+        .await
+        .unwrap();
+    assert_eq!(entries[0].actor, "token:ci");
+}
+
+#[tokio::test]
+async fn revoked_token_rejected() {
+    let (client, base_url, pool, _dir) = setup_auth_server().await;
+
+    let (token, secret) = ApiToken::create(&pool, "old", Role::Admin, None)
+        .await
+        .unwrap();
+    ApiToken::revoke(&pool, &token.id).await.unwrap();
+
+    let resp = client
+        .get(format!("{}/api/plugins", base_url))
+        .header("Authorization", format!("Bearer {}", secret))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
+}
+
 // ---------------------------------------------------------------------------
 // Health check (unauthenticated)
 // ---------------------------------------------------------------------------
@@ -553,3 +776,51 @@ async fn health_check_no_auth_required() {
 
     assert_eq!(resp.status(), 200);
 }
+
+#[tokio::test]
+async fn probes_no_auth_required() {
+    let (client, base_url, _pool, _dir) = setup_auth_server().await;
+
+    for path in ["/healthz", "/readyz"] {
+        let resp = client
+            .get(format!("{}{}", base_url, path))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200, "{} should succeed", path);
+    }
+}
+
+#[tokio::test]
+async fn status_reports_db_plugins_and_ca() {
+    let (client, base_url, pool, _dir) = setup_auth_server().await;
+
+    let resp = client
+        .get(format!("{}/api/status", base_url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 401);
+
+    let (_, secret) = ApiToken::create(&pool, "dashboard", Role::Viewer, None)
+        .await
+        .unwrap();
+    let resp = client
+        .get(format!("{}/api/status", base_url))
+        .header("Authorization", format!("Bearer {}", secret))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let body: serde_json::Value = resp.json().await.unwrap();
+    assert_eq!(body["ready"], true);
+    assert_eq!(body["db"]["configured"], true);
+    assert_eq!(body["db"]["healthy"], true);
+    assert_eq!(body["plugins"]["enabled"], true);
+    assert_eq!(body["plugins"]["loaded"], 0);
+    assert_eq!(body["active_connections"], 0);
+    assert_eq!(body["upstream_queues"], serde_json::json!([]));
+    assert!(body["listeners"]["web"].is_string());
+    assert!(body["ca"]["days_remaining"].as_i64().unwrap() > 0);
+}
@@ -1,7 +1,199 @@
+use askama::Template;
+use rand::Rng;
+use salvo::oapi::endpoint;
+use salvo::writing::{Redirect, Text};
+use salvo::{Depot, Request, Response};
 use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::web::AppState;
+use crate::web::templates::QrTemplate;
+
+/// How long a short link stays valid if it isn't used
+pub const SHORT_LINK_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Most short links live at once. `/qr` needs no authentication, so while
+/// this many are live new ones are refused until some expire or are used,
+/// rather than growing without bound or dropping links being typed.
+pub const MAX_SHORT_LINKS: usize = 64;
+
+const SHORT_LINK_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+const SHORT_LINK_LEN: usize = 8;
 
 #[derive(Debug, Deserialize)]
 pub struct CertQuery {
     pub format: Option<String>,
     pub download: Option<bool>,
 }
+
+/// One-time links to the certificate download, short enough to type on a phone
+#[derive(Clone, Default)]
+pub struct ShortLinks {
+    links: Arc<Mutex<Vec<ShortLink>>>,
+}
+
+struct ShortLink {
+    token: String,
+    format: Option<String>,
+    expires_at: Instant,
+}
+
+impl ShortLinks {
+    /// Create a link to the certificate in `format`, or in the format
+    /// recommended for whichever device opens it. None while
+    /// [MAX_SHORT_LINKS] are live.
+    pub fn create(&self, format: Option<String>) -> Option<String> {
+        let mut links = self.links.lock().unwrap();
+        let now = Instant::now();
+        links.retain(|link| link.expires_at > now);
+        if links.len() >= MAX_SHORT_LINKS {
+            return None;
+        }
+
+        let mut rng = rand::rng();
+        let token = loop {
+            let token: String = (0..SHORT_LINK_LEN)
+                .map(|_| {
+                    SHORT_LINK_ALPHABET[rng.random_range(0..SHORT_LINK_ALPHABET.len())] as char
+                })
+                .collect();
+            if !links.iter().any(|link| link.token == token) {
+                break token;
+            }
+        };
+        links.push(ShortLink {
+            token: token.clone(),
+            format,
+            expires_at: now + SHORT_LINK_TTL,
+        });
+        Some(token)
+    }
+
+    /// Consume a link, returning the format it was created for
+    pub fn redeem(&self, token: &str) -> Option<Option<String>> {
+        let mut links = self.links.lock().unwrap();
+        let index = links.iter().position(|link| link.token == token)?;
+        let link = links.remove(index);
+        (link.expires_at > Instant::now()).then_some(link.format)
+    }
+}
+
+/// The certificate download URL a short link redirects to
+fn download_path(format: Option<&str>) -> String {
+    match format {
+        Some(format) => format!("/cert?format={}&download=true", format),
+        None => "/cert?download=true".to_string(),
+    }
+}
+
+/// Page with a QR code for installing the certificate from another device.
+/// The code encodes a one-time short link, which downloads the certificate
+/// in `format` or, if unset, in the format recommended for the device that
+/// opens it.
+#[endpoint(status_codes(200, 429, 500))]
+pub async fn qr_page(
+    req: &mut Request,
+    res: &mut Response,
+    depot: &mut Depot,
+) -> Result<(), salvo::http::StatusError> {
+    let state = depot
+        .obtain::<AppState>()
+        .map_err(|_| salvo::http::StatusError::internal_server_error().brief("Internal error"))?;
+
+    let format = req.query::<String>("format").filter(|format| {
+        matches!(
+            format.as_str(),
+            "pem" | "der" | "crt" | "p12" | "mobileconfig"
+        )
+    });
+    let host = req
+        .headers()
+        .get(salvo::http::header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| req.uri().authority().map(|a| a.to_string()))
+        .unwrap_or_default();
+
+    let token = state.short_links.create(format.clone()).ok_or_else(|| {
+        salvo::http::StatusError::too_many_requests()
+            .brief("Too many certificate links are open, try again in a few minutes")
+    })?;
+    let short_link = format!("https://{}/c/{}", host, token);
+    let svg = qrcode::QrCode::new(&short_link)
+        .map_err(|_| salvo::http::StatusError::internal_server_error().brief("QR code error"))?
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(240, 240)
+        .build();
+
+    let html = QrTemplate {
+        svg,
+        short_link,
+        format: format.unwrap_or_else(|| "recommended for your device".to_string()),
+        ttl_minutes: SHORT_LINK_TTL.as_secs() / 60,
+    }
+    .render()
+    .map_err(|_| salvo::http::StatusError::internal_server_error().brief("Template error"))?;
+    res.add_header(salvo::http::header::CACHE_CONTROL, "no-store", true)
+        .unwrap();
+    res.render(Text::Html(html));
+    Ok(())
+}
+
+/// Redeem a short link from [qr_page], redirecting to the certificate download
+#[endpoint(status_codes(303, 404, 500))]
+pub async fn redeem_short_link(
+    req: &mut Request,
+    res: &mut Response,
+    depot: &mut Depot,
+) -> Result<(), salvo::http::StatusError> {
+    let state = depot
+        .obtain::<AppState>()
+        .map_err(|_| salvo::http::StatusError::internal_server_error().brief("Internal error"))?;
+    let token = req.param::<String>("token").unwrap_or_default();
+    let format = state.short_links.redeem(&token).ok_or_else(|| {
+        salvo::http::StatusError::not_found().brief("This link has expired or was already used")
+    })?;
+    res.render(Redirect::see_other(download_path(format.as_deref())));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_links_are_single_use() {
+        let links = ShortLinks::default();
+        let token = links.create(Some("pem".to_string())).unwrap();
+        assert_eq!(token.len(), SHORT_LINK_LEN);
+        assert_eq!(links.redeem(&token), Some(Some("pem".to_string())));
+        assert_eq!(links.redeem(&token), None);
+
+        let token = links.create(None).unwrap();
+        assert_eq!(links.redeem(&token), Some(None));
+        assert_eq!(download_path(None), "/cert?download=true");
+        assert_eq!(download_path(Some("p12")), "/cert?format=p12&download=true");
+    }
+
+    #[test]
+    fn live_short_links_are_capped() {
+        let links = ShortLinks::default();
+        let tokens: Vec<_> = (0..MAX_SHORT_LINKS)
+            .map(|_| links.create(None).unwrap())
+            .collect();
+        // Live links are kept rather than dropped for new ones
+        assert_eq!(links.create(None), None);
+        assert_eq!(links.redeem(&tokens[0]), Some(None));
+        let token = links.create(None).unwrap();
+        assert_eq!(links.create(None), None);
+
+        // Expired links make room
+        links.links.lock().unwrap()[0].expires_at = Instant::now();
+        assert!(links.create(None).is_some());
+        assert_eq!(links.redeem(&token), Some(None));
+        assert!(tokens.iter().all(|token| {
+            token.len() == SHORT_LINK_LEN && token.bytes().all(|b| SHORT_LINK_ALPHABET.contains(&b))
+        }));
+    }
+}
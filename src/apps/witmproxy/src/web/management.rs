@@ -1,12 +1,43 @@
 use salvo::http::{StatusCode, StatusError};
-use salvo::oapi::extract::{JsonBody, PathParam};
+use salvo::oapi::extract::{JsonBody, PathParam, QueryParam};
 use salvo::oapi::{ToSchema, endpoint};
 use salvo::prelude::*;
 use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 use tracing::warn;
 
+use crate::db::api_schemas::StoredApiSchema;
+use crate::db::audit::AuditAction;
+use crate::db::flow_filters::StoredFlowFilter;
+use crate::db::mock_specs::StoredMockSpec;
+use crate::db::protobuf_descriptors::StoredDescriptorSet;
 use crate::db::tenants::{self, Group, Tenant};
+use crate::db::traffic::StoredTraffic;
+use crate::db::user_scripts::StoredUserScript;
+use crate::plugins::posture::{EventPosture, PluginPosture};
+use crate::plugins::redaction::{RedactionRule, Redactions};
+use crate::plugins::storage_snapshots::FlowStorage;
+use crate::proxy::api_schemas::{self, ApiSchemaSummary, ApiSchemas};
+use crate::proxy::flow_tags::{FlowTagRule, FlowTags};
+use crate::proxy::flow_trace::{FlowTraces, TraceEntry};
+use crate::proxy::flows::{FlowLog, FlowQuery, FlowRecord, is_valid_tag};
+use crate::proxy::header_overrides::{
+    HeaderOverrideRule, HeaderOverrides, HeaderOverridesConfig, HeaderProfile,
+};
+use crate::proxy::mocks::{self, MockApis, MockSpec, MockSpecSummary};
+use crate::proxy::network_profiles::{
+    NetworkConditionRule, NetworkConditions, NetworkConditionsConfig,
+};
+use crate::proxy::protobuf::{
+    DescriptorSet, DescriptorSetSummary, ProtobufDescriptors, ProtobufMapping,
+};
+use crate::proxy::security_headers::{SecurityHeaderRule, SecurityHeaders};
+use crate::proxy::sessions::{CaptureSessions, SessionSpec, SessionSummary};
+use crate::proxy::timeline::Waterfall;
+use crate::proxy::traffic::{Period, Traffic, TrafficReport};
+use crate::proxy::user_scripts::{UserScript, UserScriptSummary, UserScripts};
+use crate::wasm::bindgen::witmproxy::plugin::capabilities::EventKind;
+use crate::web::{AppState, audit};
 
 // ---------------------------------------------------------------------------
 // Helpers
@@ -107,6 +138,14 @@ pub async fn update_tenant(
         return Err(StatusError::internal_server_error().brief("Internal error"));
     }
 
+    audit::record(
+        depot,
+        AuditAction::TenantUpdate,
+        Some(&tenant_id),
+        serde_json::json!({ "display_name": body.display_name, "enabled": body.enabled }),
+    )
+    .await;
+
     match Tenant::by_id(&pool, &tenant_id).await {
         Ok(Some(tenant)) => Ok(Json(TenantResponse::from(tenant))),
         Ok(None) => Err(StatusError::not_found().brief("Tenant not found")),
@@ -125,8 +164,16 @@ pub async fn delete_tenant(
     res: &mut Response,
 ) -> Result<&'static str, StatusError> {
     let pool = db(depot)?;
-    match Tenant::delete(&pool, &id.into_inner()).await {
+    let tenant_id = id.into_inner();
+    match Tenant::delete(&pool, &tenant_id).await {
         Ok(true) => {
+            audit::record(
+                depot,
+                AuditAction::TenantDelete,
+                Some(&tenant_id),
+                serde_json::json!({}),
+            )
+            .await;
             res.status_code(StatusCode::OK);
             Ok("Tenant deleted")
         }
@@ -190,6 +237,13 @@ pub async fn create_group(
 
     match Group::create(&pool, &group_id, &body.name, description).await {
         Ok(group) => {
+            audit::record(
+                depot,
+                AuditAction::GroupCreate,
+                Some(&group.id),
+                serde_json::json!({ "name": group.name }),
+            )
+            .await;
             res.status_code(StatusCode::CREATED);
             Ok(Json(GroupResponse::from(group)))
         }
@@ -208,8 +262,18 @@ pub async fn delete_group(
     depot: &mut Depot,
 ) -> Result<&'static str, StatusError> {
     let pool = db(depot)?;
-    match Group::delete(&pool, &id.into_inner()).await {
-        Ok(true) => Ok("Group deleted"),
+    let group_id = id.into_inner();
+    match Group::delete(&pool, &group_id).await {
+        Ok(true) => {
+            audit::record(
+                depot,
+                AuditAction::GroupDelete,
+                Some(&group_id),
+                serde_json::json!({}),
+            )
+            .await;
+            Ok("Group deleted")
+        }
         Ok(false) => Err(StatusError::not_found().brief("Group not found")),
         Err(e) => {
             warn!("Failed to delete group: {}", e);
@@ -275,12 +339,20 @@ pub async fn add_group_member(
 ) -> Result<&'static str, StatusError> {
     let pool = db(depot)?;
     let body = body.into_inner();
-    Group::add_member(&pool, &id.into_inner(), &body.tenant_id)
+    let group_id = id.into_inner();
+    Group::add_member(&pool, &group_id, &body.tenant_id)
         .await
         .map_err(|e| {
             warn!("Failed to add group member: {}", e);
             StatusError::internal_server_error().brief(format!("Failed: {}", e))
         })?;
+    audit::record(
+        depot,
+        AuditAction::GroupMemberAdd,
+        Some(&group_id),
+        serde_json::json!({ "tenant_id": body.tenant_id }),
+    )
+    .await;
     Ok("Member added")
 }
 
@@ -293,12 +365,20 @@ pub async fn remove_group_member(
 ) -> Result<&'static str, StatusError> {
     let pool = db(depot)?;
     let body = body.into_inner();
-    Group::remove_member(&pool, &id.into_inner(), &body.tenant_id)
+    let group_id = id.into_inner();
+    Group::remove_member(&pool, &group_id, &body.tenant_id)
         .await
         .map_err(|e| {
             warn!("Failed to remove group member: {}", e);
             StatusError::internal_server_error().brief(format!("Failed: {}", e))
         })?;
+    audit::record(
+        depot,
+        AuditAction::GroupMemberRemove,
+        Some(&group_id),
+        serde_json::json!({ "tenant_id": body.tenant_id }),
+    )
+    .await;
     Ok("Member removed")
 }
 
@@ -335,11 +415,12 @@ pub async fn add_group_permission(
     }
 
     let permission_id = uuid::Uuid::new_v4().to_string();
+    let group_id = id.into_inner();
 
     Group::add_permission(
         &pool,
         &permission_id,
-        &id.into_inner(),
+        &group_id,
         &body.effect,
         &body.resource,
     )
@@ -349,6 +430,18 @@ pub async fn add_group_permission(
         StatusError::internal_server_error().brief(format!("Failed: {}", e))
     })?;
 
+    audit::record(
+        depot,
+        AuditAction::PermissionGrant,
+        Some(&group_id),
+        serde_json::json!({
+            "permission_id": permission_id,
+            "effect": body.effect,
+            "resource": body.resource,
+        }),
+    )
+    .await;
+
     res.status_code(StatusCode::CREATED);
     Ok(Json(PermissionResponse {
         id: permission_id,
@@ -365,10 +458,20 @@ pub async fn remove_group_permission(
     depot: &mut Depot,
 ) -> Result<&'static str, StatusError> {
     let pool = db(depot)?;
-    let _ = id.into_inner(); // group_id for ACL context (already checked by middleware)
+    let group_id = id.into_inner();
+    let permission_id = permission_id.into_inner();
 
-    match Group::remove_permission(&pool, &permission_id.into_inner()).await {
-        Ok(true) => Ok("Permission removed"),
+    match Group::remove_permission(&pool, &permission_id).await {
+        Ok(true) => {
+            audit::record(
+                depot,
+                AuditAction::PermissionRevoke,
+                Some(&group_id),
+                serde_json::json!({ "permission_id": permission_id }),
+            )
+            .await;
+            Ok("Permission removed")
+        }
         Ok(false) => Err(StatusError::not_found().brief("Permission not found")),
         Err(e) => {
             warn!("Failed to remove permission: {}", e);
@@ -398,11 +501,15 @@ pub async fn set_tenant_plugin_enabled(
     let pool = db(depot)?;
     let body = body.into_inner();
 
+    let tenant_id = id.into_inner();
+    let namespace = ns.into_inner();
+    let plugin_name = name.into_inner();
+
     tenants::set_plugin_override(
         &pool,
-        &id.into_inner(),
-        &ns.into_inner(),
-        &name.into_inner(),
+        &tenant_id,
+        &namespace,
+        &plugin_name,
         Some(body.enabled),
     )
     .await
@@ -411,6 +518,14 @@ pub async fn set_tenant_plugin_enabled(
         StatusError::internal_server_error().brief(format!("Failed: {}", e))
     })?;
 
+    audit::record(
+        depot,
+        AuditAction::PluginEnable,
+        Some(&format!("{}/{}", namespace, plugin_name)),
+        serde_json::json!({ "tenant_id": tenant_id, "enabled": body.enabled }),
+    )
+    .await;
+
     Ok("Plugin override set")
 }
 
@@ -450,6 +565,17 @@ pub async fn set_tenant_plugin_config(
         })?;
     }
 
+    audit::record(
+        depot,
+        AuditAction::PluginConfigure,
+        Some(&format!("{}/{}", namespace, plugin_name)),
+        serde_json::json!({
+            "tenant_id": tenant_id,
+            "inputs": body.config.keys().collect::<Vec<_>>(),
+        }),
+    )
+    .await;
+
     Ok("Plugin config updated")
 }
 
@@ -500,12 +626,20 @@ pub async fn add_ip_mapping(
     let pool = db(depot)?;
     let body = body.into_inner();
 
-    tenants::add_ip_mapping(&pool, &id.into_inner(), &body.ip_address)
+    let tenant_id = id.into_inner();
+    tenants::add_ip_mapping(&pool, &tenant_id, &body.ip_address)
         .await
         .map_err(|e| {
             warn!("Failed to add IP mapping: {}", e);
             StatusError::internal_server_error().brief(format!("Failed: {}", e))
         })?;
+    audit::record(
+        depot,
+        AuditAction::IpMappingAdd,
+        Some(&tenant_id),
+        serde_json::json!({ "ip_address": body.ip_address }),
+    )
+    .await;
 
     res.status_code(StatusCode::CREATED);
     Ok("IP mapping added")
@@ -521,12 +655,20 @@ pub async fn remove_ip_mapping(
     let pool = db(depot)?;
     let body = body.into_inner();
 
-    tenants::remove_ip_mapping(&pool, &id.into_inner(), &body.ip_address)
+    let tenant_id = id.into_inner();
+    tenants::remove_ip_mapping(&pool, &tenant_id, &body.ip_address)
         .await
         .map_err(|e| {
             warn!("Failed to remove IP mapping: {}", e);
             StatusError::internal_server_error().brief(format!("Failed: {}", e))
         })?;
+    audit::record(
+        depot,
+        AuditAction::IpMappingRemove,
+        Some(&tenant_id),
+        serde_json::json!({ "ip_address": body.ip_address }),
+    )
+    .await;
 
     Ok("IP mapping removed")
 }
@@ -599,15 +741,1294 @@ pub async fn update_config(
 
     let updates = body.into_inner();
     updates.apply_to(&mut config);
+    // Keep rules replaced since startup rather than the ones loaded then
+    if let Ok(security_headers) = depot.obtain::<SecurityHeaders>() {
+        config.proxy.security_headers = security_headers.rules();
+    }
+    if let Ok(flow_tags) = depot.obtain::<FlowTags>() {
+        config.proxy.flow_tags = flow_tags.rules();
+    }
+    if let Ok(redactions) = depot.obtain::<Redactions>() {
+        config.plugins.redactions = redactions.rules();
+    }
+    if let Ok(network_conditions) = depot.obtain::<NetworkConditions>() {
+        let live = network_conditions.config();
+        config.proxy.network_profiles = live.profiles;
+        config.proxy.network_conditions = live.rules;
+    }
+    if let Ok(header_overrides) = depot.obtain::<HeaderOverrides>() {
+        let live = header_overrides.config();
+        config.proxy.header_profiles = live.profiles;
+        config.proxy.header_overrides = live.rules;
+    }
+    if let Ok(protobuf) = depot.obtain::<ProtobufDescriptors>() {
+        config.proxy.protobuf = protobuf.mappings();
+    }
 
     config.save(&config_path).map_err(|e| {
         warn!("Failed to save config: {}", e);
         StatusError::internal_server_error().brief(format!("Failed to save config: {}", e))
     })?;
 
+    audit::record(
+        depot,
+        AuditAction::ConfigUpdate,
+        Some(&config_path.display().to_string()),
+        serde_json::to_value(&updates).unwrap_or_default(),
+    )
+    .await;
+
     Ok(Json(RuntimeConfig::from_app_config(&config)))
 }
 
+// ---------------------------------------------------------------------------
+// Security header endpoints
+// ---------------------------------------------------------------------------
+
+fn security_headers(depot: &mut Depot) -> Result<SecurityHeaders, StatusError> {
+    depot
+        .obtain::<SecurityHeaders>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("Security headers not available"))
+}
+
+/// GET /api/manage/security-headers -- list the security header rules.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 500))]
+pub async fn get_security_headers(
+    depot: &mut Depot,
+) -> Result<Json<Vec<SecurityHeaderRule>>, StatusError> {
+    Ok(Json(security_headers(depot)?.rules()))
+}
+
+/// PUT /api/manage/security-headers -- replace the security header rules,
+/// applying them to new flows immediately and persisting them to disk.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 500))]
+pub async fn update_security_headers(
+    body: JsonBody<Vec<SecurityHeaderRule>>,
+    depot: &mut Depot,
+) -> Result<Json<Vec<SecurityHeaderRule>>, StatusError> {
+    let security_headers = security_headers(depot)?;
+    let mut config = depot
+        .obtain::<crate::config::AppConfig>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("Config not available"))?;
+    let config_path = depot
+        .obtain::<ConfigPath>()
+        .map(|p| p.0.clone())
+        .map_err(|_| StatusError::internal_server_error().brief("Config path not available"))?;
+
+    let rules = body.into_inner();
+    security_headers
+        .set_rules(rules.clone())
+        .map_err(|e| StatusError::bad_request().brief(format!("{:#}", e)))?;
+
+    config.proxy.security_headers = rules.clone();
+    config.save(&config_path).map_err(|e| {
+        warn!("Failed to save config: {}", e);
+        StatusError::internal_server_error().brief(format!("Failed to save config: {}", e))
+    })?;
+
+    audit::record(
+        depot,
+        AuditAction::ConfigUpdate,
+        Some("security-headers"),
+        serde_json::to_value(&rules).unwrap_or_default(),
+    )
+    .await;
+
+    Ok(Json(rules))
+}
+
+// ---------------------------------------------------------------------------
+// Redaction endpoints
+// ---------------------------------------------------------------------------
+
+fn redactions(depot: &mut Depot) -> Result<Redactions, StatusError> {
+    depot
+        .obtain::<Redactions>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("Redactions not available"))
+}
+
+/// GET /api/manage/redactions -- list the rules for data removed from events
+/// before plugins are given them.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 500))]
+pub async fn get_redactions(depot: &mut Depot) -> Result<Json<Vec<RedactionRule>>, StatusError> {
+    Ok(Json(redactions(depot)?.rules()))
+}
+
+/// PUT /api/manage/redactions -- replace the redaction rules, applying them
+/// to new events immediately and persisting them to disk.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 500))]
+pub async fn update_redactions(
+    body: JsonBody<Vec<RedactionRule>>,
+    depot: &mut Depot,
+) -> Result<Json<Vec<RedactionRule>>, StatusError> {
+    let redactions = redactions(depot)?;
+    let mut config = depot
+        .obtain::<crate::config::AppConfig>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("Config not available"))?;
+    let config_path = depot
+        .obtain::<ConfigPath>()
+        .map(|p| p.0.clone())
+        .map_err(|_| StatusError::internal_server_error().brief("Config path not available"))?;
+
+    let rules = body.into_inner();
+    redactions
+        .set_rules(rules.clone())
+        .map_err(|e| StatusError::bad_request().brief(format!("{:#}", e)))?;
+
+    config.plugins.redactions = rules.clone();
+    config.save(&config_path).map_err(|e| {
+        warn!("Failed to save config: {}", e);
+        StatusError::internal_server_error().brief(format!("Failed to save config: {}", e))
+    })?;
+
+    audit::record(
+        depot,
+        AuditAction::ConfigUpdate,
+        Some("redactions"),
+        serde_json::to_value(&rules).unwrap_or_default(),
+    )
+    .await;
+
+    Ok(Json(rules))
+}
+
+// ---------------------------------------------------------------------------
+// Network condition endpoints
+// ---------------------------------------------------------------------------
+
+fn network_conditions(depot: &mut Depot) -> Result<NetworkConditions, StatusError> {
+    depot
+        .obtain::<NetworkConditions>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("Network conditions not available"))
+}
+
+/// Apply `updated` to new flows and persist it to disk
+async fn replace_network_conditions(
+    depot: &mut Depot,
+    updated: NetworkConditionsConfig,
+) -> Result<(), StatusError> {
+    let network_conditions = network_conditions(depot)?;
+    let mut config = depot
+        .obtain::<crate::config::AppConfig>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("Config not available"))?;
+    let config_path = depot
+        .obtain::<ConfigPath>()
+        .map(|p| p.0.clone())
+        .map_err(|_| StatusError::internal_server_error().brief("Config path not available"))?;
+
+    network_conditions
+        .set(updated.clone())
+        .map_err(|e| StatusError::bad_request().brief(format!("{:#}", e)))?;
+
+    config.proxy.network_profiles = updated.profiles.clone();
+    config.proxy.network_conditions = updated.rules.clone();
+    config.save(&config_path).map_err(|e| {
+        warn!("Failed to save config: {}", e);
+        StatusError::internal_server_error().brief(format!("Failed to save config: {}", e))
+    })?;
+
+    audit::record(
+        depot,
+        AuditAction::ConfigUpdate,
+        Some("network-conditions"),
+        serde_json::to_value(&updated).unwrap_or_default(),
+    )
+    .await;
+    Ok(())
+}
+
+/// GET /api/manage/network-conditions -- list the network profiles defined
+/// besides the built-in ones, and the rules applying them.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 500))]
+pub async fn get_network_conditions(
+    depot: &mut Depot,
+) -> Result<Json<NetworkConditionsConfig>, StatusError> {
+    Ok(Json(network_conditions(depot)?.config()))
+}
+
+/// PUT /api/manage/network-conditions -- replace the network profiles and
+/// rules, applying them to new flows immediately and persisting them to disk.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 500))]
+pub async fn update_network_conditions(
+    body: JsonBody<NetworkConditionsConfig>,
+    depot: &mut Depot,
+) -> Result<Json<NetworkConditionsConfig>, StatusError> {
+    let updated = body.into_inner();
+    replace_network_conditions(depot, updated.clone()).await?;
+    Ok(Json(updated))
+}
+
+/// The profile to simulate for a client, or none for its real network
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ClientNetworkProfile {
+    pub profile: Option<String>,
+}
+
+/// PUT /api/manage/network-conditions/clients/{ip} -- switch a client to a
+/// network profile for every host, or back to its real network, replacing
+/// any rule for that client alone.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 500))]
+pub async fn set_client_network_profile(
+    ip: PathParam<String>,
+    body: JsonBody<ClientNetworkProfile>,
+    depot: &mut Depot,
+) -> Result<Json<NetworkConditionsConfig>, StatusError> {
+    let client: std::net::IpAddr = ip
+        .into_inner()
+        .parse()
+        .map_err(|_| StatusError::bad_request().brief("Invalid client address"))?;
+
+    let mut updated = network_conditions(depot)?.config();
+    updated
+        .rules
+        .retain(|rule| rule.host.is_some() || rule.client != Some(client));
+    if let Some(profile) = body.into_inner().profile {
+        // Ahead of host rules, so the client's profile wins
+        updated.rules.insert(
+            0,
+            NetworkConditionRule {
+                host: None,
+                client: Some(client),
+                profile,
+            },
+        );
+    }
+    replace_network_conditions(depot, updated.clone()).await?;
+    Ok(Json(updated))
+}
+
+// ---------------------------------------------------------------------------
+// Header override endpoints
+// ---------------------------------------------------------------------------
+
+fn header_overrides(depot: &mut Depot) -> Result<HeaderOverrides, StatusError> {
+    depot
+        .obtain::<HeaderOverrides>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("Header overrides not available"))
+}
+
+/// Apply `updated` to new requests and persist it to disk
+async fn replace_header_overrides(
+    depot: &mut Depot,
+    updated: HeaderOverridesConfig,
+) -> Result<(), StatusError> {
+    let header_overrides = header_overrides(depot)?;
+    let mut config = depot
+        .obtain::<crate::config::AppConfig>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("Config not available"))?;
+    let config_path = depot
+        .obtain::<ConfigPath>()
+        .map(|p| p.0.clone())
+        .map_err(|_| StatusError::internal_server_error().brief("Config path not available"))?;
+
+    header_overrides
+        .set(updated.clone())
+        .map_err(|e| StatusError::bad_request().brief(format!("{:#}", e)))?;
+
+    config.proxy.header_profiles = updated.profiles.clone();
+    config.proxy.header_overrides = updated.rules.clone();
+    config.save(&config_path).map_err(|e| {
+        warn!("Failed to save config: {}", e);
+        StatusError::internal_server_error().brief(format!("Failed to save config: {}", e))
+    })?;
+
+    audit::record(
+        depot,
+        AuditAction::ConfigUpdate,
+        Some("header-overrides"),
+        serde_json::to_value(&updated).unwrap_or_default(),
+    )
+    .await;
+    Ok(())
+}
+
+/// GET /api/manage/header-overrides -- list the header profiles defined
+/// besides the built-in ones, and the rules applying them.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 500))]
+pub async fn get_header_overrides(
+    depot: &mut Depot,
+) -> Result<Json<HeaderOverridesConfig>, StatusError> {
+    Ok(Json(header_overrides(depot)?.config()))
+}
+
+/// PUT /api/manage/header-overrides -- replace the header profiles and
+/// rules, applying them to new requests immediately and persisting them to
+/// disk.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 500))]
+pub async fn update_header_overrides(
+    body: JsonBody<HeaderOverridesConfig>,
+    depot: &mut Depot,
+) -> Result<Json<HeaderOverridesConfig>, StatusError> {
+    let updated = body.into_inner();
+    replace_header_overrides(depot, updated.clone()).await?;
+    Ok(Json(updated))
+}
+
+/// GET /api/manage/header-overrides/profiles -- list every header profile
+/// rules can apply, built-in ones included.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 500))]
+pub async fn list_header_profiles(
+    depot: &mut Depot,
+) -> Result<Json<Vec<HeaderProfile>>, StatusError> {
+    Ok(Json(header_overrides(depot)?.profiles()))
+}
+
+/// The profile to force on the requests to a host, or every host, or none to
+/// send them unchanged
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct HeaderProfileSelection {
+    pub host: Option<String>,
+    pub profile: Option<String>,
+}
+
+/// PUT /api/manage/header-overrides/selection -- switch a host, or every
+/// host, to a header profile or back to the client's headers, replacing any
+/// rule for exactly that host.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 500))]
+pub async fn select_header_profile(
+    body: JsonBody<HeaderProfileSelection>,
+    depot: &mut Depot,
+) -> Result<Json<HeaderOverridesConfig>, StatusError> {
+    let HeaderProfileSelection { host, profile } = body.into_inner();
+    let mut updated = header_overrides(depot)?.config();
+    updated.rules.retain(|rule| rule.host != host);
+    if let Some(profile) = profile {
+        let rule = HeaderOverrideRule {
+            host: host.clone(),
+            profile,
+        };
+        // Host rules go first so they win over the one for every host
+        match host {
+            Some(_) => updated.rules.insert(0, rule),
+            None => updated.rules.push(rule),
+        }
+    }
+    replace_header_overrides(depot, updated.clone()).await?;
+    Ok(Json(updated))
+}
+
+// ---------------------------------------------------------------------------
+// Mock endpoints
+// ---------------------------------------------------------------------------
+
+/// Largest OpenAPI document accepted for mocking
+const MAX_MOCK_DOCUMENT: usize = 16 * 1024 * 1024;
+
+fn mock_apis(depot: &mut Depot) -> Result<MockApis, StatusError> {
+    depot
+        .obtain::<MockApis>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("Mocks not available"))
+}
+
+/// GET /api/manage/mocks -- list the mocked APIs.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 500))]
+pub async fn list_mocks(depot: &mut Depot) -> Result<Json<Vec<MockSpecSummary>>, StatusError> {
+    let mut summaries: Vec<_> = mock_apis(depot)?
+        .specs()
+        .iter()
+        .map(MockSpec::summary)
+        .collect();
+    summaries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(Json(summaries))
+}
+
+/// PUT /api/manage/mocks/:name -- upload an OpenAPI 3 document, as JSON or
+/// YAML, and answer requests to its servers from it.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 500))]
+pub async fn upload_mock(
+    name: PathParam<String>,
+    req: &mut Request,
+    depot: &mut Depot,
+) -> Result<Json<MockSpecSummary>, StatusError> {
+    let mocks = mock_apis(depot)?;
+    let pool = db(depot)?;
+    let name = name.into_inner();
+
+    let payload = req
+        .payload_with_max_size(MAX_MOCK_DOCUMENT)
+        .await
+        .map_err(|e| StatusError::bad_request().brief(format!("Failed to read document: {}", e)))?;
+    let text = std::str::from_utf8(payload)
+        .map_err(|_| StatusError::bad_request().brief("Document is not UTF-8"))?;
+    let spec = mocks::parse_document(text)
+        .and_then(|document| MockSpec::new(name.clone(), document))
+        .map_err(|e| StatusError::bad_request().brief(format!("{:#}", e)))?;
+
+    StoredMockSpec::upsert(&pool, &name, spec.document())
+        .await
+        .map_err(|e| {
+            warn!("Failed to store mocked API: {}", e);
+            StatusError::internal_server_error().brief("Internal error")
+        })?;
+    let summary = spec.summary();
+    mocks.insert(spec);
+
+    audit::record(
+        depot,
+        AuditAction::MockUpload,
+        Some(&name),
+        serde_json::to_value(&summary).unwrap_or_default(),
+    )
+    .await;
+
+    Ok(Json(summary))
+}
+
+/// DELETE /api/manage/mocks/:name -- stop mocking an API.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 404, 500))]
+pub async fn delete_mock(
+    name: PathParam<String>,
+    depot: &mut Depot,
+) -> Result<&'static str, StatusError> {
+    let mocks = mock_apis(depot)?;
+    let pool = db(depot)?;
+    let name = name.into_inner();
+
+    let stored = StoredMockSpec::delete(&pool, &name).await.map_err(|e| {
+        warn!("Failed to delete mocked API: {}", e);
+        StatusError::internal_server_error().brief("Internal error")
+    })?;
+    if !(mocks.remove(&name) || stored) {
+        return Err(StatusError::not_found().brief("Mocked API not found"));
+    }
+
+    audit::record(
+        depot,
+        AuditAction::MockRemove,
+        Some(&name),
+        serde_json::json!({}),
+    )
+    .await;
+
+    Ok("Mocked API removed")
+}
+
+// ---------------------------------------------------------------------------
+// User script endpoints
+// ---------------------------------------------------------------------------
+
+/// Largest script accepted for injection
+const MAX_USER_SCRIPT: usize = 4 * 1024 * 1024;
+
+fn user_scripts_state(depot: &mut Depot) -> Result<UserScripts, StatusError> {
+    depot
+        .obtain::<UserScripts>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("User scripts not available"))
+}
+
+/// GET /api/manage/scripts -- list the scripts injected into pages.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 500))]
+pub async fn list_user_scripts(
+    depot: &mut Depot,
+) -> Result<Json<Vec<UserScriptSummary>>, StatusError> {
+    let mut summaries: Vec<_> = user_scripts_state(depot)?
+        .scripts()
+        .iter()
+        .map(UserScript::summary)
+        .collect();
+    summaries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(Json(summaries))
+}
+
+/// PUT /api/manage/scripts/:name -- register a userscript, or a JSON
+/// snippet, and inject it into the HTML pages it matches.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 500))]
+pub async fn upload_user_script(
+    name: PathParam<String>,
+    req: &mut Request,
+    depot: &mut Depot,
+) -> Result<Json<UserScriptSummary>, StatusError> {
+    let scripts = user_scripts_state(depot)?;
+    let pool = db(depot)?;
+    let name = name.into_inner();
+
+    let payload = req
+        .payload_with_max_size(MAX_USER_SCRIPT)
+        .await
+        .map_err(|e| StatusError::bad_request().brief(format!("Failed to read script: {}", e)))?;
+    let text = std::str::from_utf8(payload)
+        .map_err(|_| StatusError::bad_request().brief("Script is not UTF-8"))?;
+    let script = UserScript::parse(name.clone(), text)
+        .map_err(|e| StatusError::bad_request().brief(format!("{:#}", e)))?;
+
+    StoredUserScript::upsert(&pool, &name, script.source())
+        .await
+        .map_err(|e| {
+            warn!("Failed to store user script: {}", e);
+            StatusError::internal_server_error().brief("Internal error")
+        })?;
+    let summary = script.summary();
+    scripts.insert(script);
+
+    audit::record(
+        depot,
+        AuditAction::ScriptUpload,
+        Some(&name),
+        serde_json::to_value(&summary).unwrap_or_default(),
+    )
+    .await;
+
+    Ok(Json(summary))
+}
+
+/// DELETE /api/manage/scripts/:name -- stop injecting a script.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 404, 500))]
+pub async fn delete_user_script(
+    name: PathParam<String>,
+    depot: &mut Depot,
+) -> Result<&'static str, StatusError> {
+    let scripts = user_scripts_state(depot)?;
+    let pool = db(depot)?;
+    let name = name.into_inner();
+
+    let stored = StoredUserScript::delete(&pool, &name).await.map_err(|e| {
+        warn!("Failed to delete user script: {}", e);
+        StatusError::internal_server_error().brief("Internal error")
+    })?;
+    if !(scripts.remove(&name) || stored) {
+        return Err(StatusError::not_found().brief("Script not found"));
+    }
+
+    audit::record(
+        depot,
+        AuditAction::ScriptRemove,
+        Some(&name),
+        serde_json::json!({}),
+    )
+    .await;
+
+    Ok("Script removed")
+}
+
+// ---------------------------------------------------------------------------
+// Inferred API schema endpoints
+// ---------------------------------------------------------------------------
+
+fn api_schemas_state(depot: &mut Depot) -> Result<ApiSchemas, StatusError> {
+    depot
+        .obtain::<ApiSchemas>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("API schemas not available"))
+}
+
+/// GET /api/manage/schemas -- list the hosts with inferred API schemas.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 500))]
+pub async fn list_api_schemas(
+    depot: &mut Depot,
+) -> Result<Json<Vec<ApiSchemaSummary>>, StatusError> {
+    Ok(Json(api_schemas_state(depot)?.summaries()))
+}
+
+/// GET /api/manage/schemas/:host -- export the API schema inferred for a
+/// host as an OpenAPI document, in YAML or with `?format=json` in JSON.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 404, 500))]
+pub async fn export_api_schema(
+    host: PathParam<String>,
+    format: QueryParam<String, false>,
+    depot: &mut Depot,
+    res: &mut Response,
+) -> Result<(), StatusError> {
+    let host = host.into_inner();
+    let schema = api_schemas_state(depot)?
+        .get(&host)
+        .ok_or_else(|| StatusError::not_found().brief("No schema inferred for this host"))?;
+    let document = schema.to_openapi(&host.to_ascii_lowercase());
+    match format.into_inner().as_deref() {
+        None | Some("yaml") => {
+            let yaml = api_schemas::to_yaml(&document).map_err(|e| {
+                warn!("Failed to render API schema: {}", e);
+                StatusError::internal_server_error().brief("Internal error")
+            })?;
+            res.add_header(salvo::http::header::CONTENT_TYPE, "application/yaml", true)
+                .map_err(|_| StatusError::internal_server_error().brief("Internal error"))?;
+            res.render(yaml);
+        }
+        Some("json") => res.render(Json(document)),
+        Some(_) => return Err(StatusError::bad_request().brief("format must be yaml or json")),
+    }
+    Ok(())
+}
+
+/// DELETE /api/manage/schemas/:host -- forget the API schema inferred for a
+/// host.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 404, 500))]
+pub async fn delete_api_schema(
+    host: PathParam<String>,
+    depot: &mut Depot,
+) -> Result<&'static str, StatusError> {
+    let schemas = api_schemas_state(depot)?;
+    let pool = db(depot)?;
+    let host = host.into_inner();
+
+    let stored = StoredApiSchema::delete(&pool, &host).await.map_err(|e| {
+        warn!("Failed to delete inferred API schema: {}", e);
+        StatusError::internal_server_error().brief("Internal error")
+    })?;
+    if !(schemas.remove(&host) || stored) {
+        return Err(StatusError::not_found().brief("No schema inferred for this host"));
+    }
+
+    audit::record(
+        depot,
+        AuditAction::SchemaClear,
+        Some(&host),
+        serde_json::json!({}),
+    )
+    .await;
+
+    Ok("API schema removed")
+}
+
+// ---------------------------------------------------------------------------
+// Protobuf endpoints
+// ---------------------------------------------------------------------------
+
+/// Largest serialized `FileDescriptorSet` accepted
+const MAX_DESCRIPTOR_SET: usize = 16 * 1024 * 1024;
+
+fn protobuf(depot: &mut Depot) -> Result<ProtobufDescriptors, StatusError> {
+    depot
+        .obtain::<ProtobufDescriptors>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("Protobuf not available"))
+}
+
+/// GET /api/manage/protobuf/descriptors -- list the uploaded descriptor sets.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 500))]
+pub async fn list_protobuf_descriptors(
+    depot: &mut Depot,
+) -> Result<Json<Vec<DescriptorSetSummary>>, StatusError> {
+    let mut summaries: Vec<_> = protobuf(depot)?
+        .sets()
+        .iter()
+        .map(DescriptorSet::summary)
+        .collect();
+    summaries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(Json(summaries))
+}
+
+/// PUT /api/manage/protobuf/descriptors/:name -- upload a serialized
+/// `FileDescriptorSet` (as written by `protoc --descriptor_set_out`), whose
+/// message types mappings can then name.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 500))]
+pub async fn upload_protobuf_descriptors(
+    name: PathParam<String>,
+    req: &mut Request,
+    depot: &mut Depot,
+) -> Result<Json<DescriptorSetSummary>, StatusError> {
+    let descriptors = protobuf(depot)?;
+    let pool = db(depot)?;
+    let name = name.into_inner();
+
+    let payload = req
+        .payload_with_max_size(MAX_DESCRIPTOR_SET)
+        .await
+        .map_err(|e| {
+            StatusError::bad_request().brief(format!("Failed to read descriptor set: {}", e))
+        })?;
+    let set = DescriptorSet::new(name.clone(), payload.clone())
+        .map_err(|e| StatusError::bad_request().brief(format!("{:#}", e)))?;
+
+    StoredDescriptorSet::upsert(&pool, &name, set.bytes())
+        .await
+        .map_err(|e| {
+            warn!("Failed to store descriptor set: {}", e);
+            StatusError::internal_server_error().brief("Internal error")
+        })?;
+    let summary = set.summary();
+    descriptors.insert(set);
+
+    audit::record(
+        depot,
+        AuditAction::ProtobufUpload,
+        Some(&name),
+        serde_json::to_value(&summary).unwrap_or_default(),
+    )
+    .await;
+
+    Ok(Json(summary))
+}
+
+/// DELETE /api/manage/protobuf/descriptors/:name -- remove a descriptor set.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 404, 500))]
+pub async fn delete_protobuf_descriptors(
+    name: PathParam<String>,
+    depot: &mut Depot,
+) -> Result<&'static str, StatusError> {
+    let descriptors = protobuf(depot)?;
+    let pool = db(depot)?;
+    let name = name.into_inner();
+
+    let stored = StoredDescriptorSet::delete(&pool, &name)
+        .await
+        .map_err(|e| {
+            warn!("Failed to delete descriptor set: {}", e);
+            StatusError::internal_server_error().brief("Internal error")
+        })?;
+    if !(descriptors.remove(&name) || stored) {
+        return Err(StatusError::not_found().brief("Descriptor set not found"));
+    }
+
+    audit::record(
+        depot,
+        AuditAction::ProtobufRemove,
+        Some(&name),
+        serde_json::json!({}),
+    )
+    .await;
+
+    Ok("Descriptor set removed")
+}
+
+/// GET /api/manage/protobuf/mappings -- list which message types bodies to
+/// each host and path are decoded as.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 500))]
+pub async fn get_protobuf_mappings(
+    depot: &mut Depot,
+) -> Result<Json<Vec<ProtobufMapping>>, StatusError> {
+    Ok(Json(protobuf(depot)?.mappings()))
+}
+
+/// PUT /api/manage/protobuf/mappings -- replace the protobuf mappings,
+/// applying them to new flows immediately and persisting them to disk.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 500))]
+pub async fn update_protobuf_mappings(
+    body: JsonBody<Vec<ProtobufMapping>>,
+    depot: &mut Depot,
+) -> Result<Json<Vec<ProtobufMapping>>, StatusError> {
+    let descriptors = protobuf(depot)?;
+    let mut config = depot
+        .obtain::<crate::config::AppConfig>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("Config not available"))?;
+    let config_path = depot
+        .obtain::<ConfigPath>()
+        .map(|p| p.0.clone())
+        .map_err(|_| StatusError::internal_server_error().brief("Config path not available"))?;
+
+    let mappings = body.into_inner();
+    descriptors
+        .set_mappings(mappings.clone())
+        .map_err(|e| StatusError::bad_request().brief(format!("{:#}", e)))?;
+
+    config.proxy.protobuf = mappings.clone();
+    config.save(&config_path).map_err(|e| {
+        warn!("Failed to save config: {}", e);
+        StatusError::internal_server_error().brief(format!("Failed to save config: {}", e))
+    })?;
+
+    audit::record(
+        depot,
+        AuditAction::ConfigUpdate,
+        Some("protobuf"),
+        serde_json::to_value(&mappings).unwrap_or_default(),
+    )
+    .await;
+
+    Ok(Json(mappings))
+}
+
+// ---------------------------------------------------------------------------
+// Flow endpoints
+// ---------------------------------------------------------------------------
+
+async fn flow_log(depot: &mut Depot) -> Result<FlowLog, StatusError> {
+    let registry = depot
+        .obtain::<AppState>()
+        .map(|s| s.plugin_registry.clone())
+        .map_err(|_| StatusError::internal_server_error().brief("Internal server error"))?
+        .ok_or_else(|| StatusError::bad_request().brief("Plugin system is disabled"))?;
+    Ok(registry.read().await.flows().clone())
+}
+
+/// GET /api/manage/flows -- flows recently intercepted by the proxy, newest
+/// first, ex: to replay through a plugin with `witm plugin exercise`.
+///
+/// `filter` names a saved filter, whose fields are replaced by any others
+/// given.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 404, 500))]
+#[allow(clippy::too_many_arguments)]
+pub async fn list_flows(
+    filter: QueryParam<String, false>,
+    host: QueryParam<String, false>,
+    annotation: QueryParam<String, false>,
+    tag: QueryParam<String, false>,
+    since_millis: QueryParam<u64, false>,
+    until_millis: QueryParam<u64, false>,
+    min_status: QueryParam<u16, false>,
+    max_status: QueryParam<u16, false>,
+    min_duration_millis: QueryParam<u64, false>,
+    limit: QueryParam<usize, false>,
+    depot: &mut Depot,
+) -> Result<Json<Vec<FlowRecord>>, StatusError> {
+    let flows = flow_log(depot).await?;
+    let saved = match filter.into_inner() {
+        Some(name) => saved_flow_filter(depot, &name).await?,
+        None => FlowQuery::default(),
+    };
+    let query = saved.overridden_by(FlowQuery {
+        host: host.into_inner(),
+        since_millis: since_millis.into_inner(),
+        until_millis: until_millis.into_inner(),
+        annotation: annotation.into_inner(),
+        tag: tag.into_inner(),
+        min_status: min_status.into_inner(),
+        max_status: max_status.into_inner(),
+        min_duration_millis: min_duration_millis.into_inner(),
+        limit: limit.into_inner(),
+    });
+    Ok(Json(flows.query(&query)))
+}
+
+/// PUT /api/manage/flows/:id/tags/:tag -- tag a flow, so it can be listed
+/// with `?tag=`.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 404, 500))]
+pub async fn tag_flow(
+    id: PathParam<String>,
+    tag: PathParam<String>,
+    depot: &mut Depot,
+) -> Result<Json<FlowRecord>, StatusError> {
+    let flows = flow_log(depot).await?;
+    let (id, tag) = (id.into_inner(), tag.into_inner());
+    if !is_valid_tag(&tag) {
+        return Err(StatusError::bad_request()
+            .brief("Tags are at most 64 letters, digits and '-', '_', '.' or ':'"));
+    }
+    if !flows.tag(&id, &tag) {
+        return Err(StatusError::not_found().brief("Flow not found"));
+    }
+
+    audit::record(
+        depot,
+        AuditAction::FlowTag,
+        Some(&id),
+        serde_json::json!({ "tag": tag }),
+    )
+    .await;
+
+    flows
+        .get(&id)
+        .map(Json)
+        .ok_or_else(|| StatusError::not_found().brief("Flow not found"))
+}
+
+/// DELETE /api/manage/flows/:id/tags/:tag -- remove a tag from a flow.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 404, 500))]
+pub async fn untag_flow(
+    id: PathParam<String>,
+    tag: PathParam<String>,
+    depot: &mut Depot,
+) -> Result<Json<FlowRecord>, StatusError> {
+    let flows = flow_log(depot).await?;
+    let (id, tag) = (id.into_inner(), tag.into_inner());
+    if !flows.untag(&id, &tag) {
+        return Err(StatusError::not_found().brief("Flow or tag not found"));
+    }
+
+    audit::record(
+        depot,
+        AuditAction::FlowUntag,
+        Some(&id),
+        serde_json::json!({ "tag": tag }),
+    )
+    .await;
+
+    flows
+        .get(&id)
+        .map(Json)
+        .ok_or_else(|| StatusError::not_found().brief("Flow not found"))
+}
+
+/// GET /api/manage/flows/:id/trace -- the trace of a flow debugged with
+/// `X-Witmproxy-Debug: 1`, by the id returned in `x-witmproxy-trace`.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 404, 500))]
+pub async fn get_flow_trace(
+    id: PathParam<String>,
+    depot: &mut Depot,
+) -> Result<Json<Vec<TraceEntry>>, StatusError> {
+    let traces = depot
+        .obtain::<FlowTraces>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("Flow traces not available"))?;
+    traces
+        .get(&id.into_inner())
+        .map(Json)
+        .ok_or_else(|| StatusError::not_found().brief("No trace for this flow"))
+}
+
+/// GET /api/manage/flows/:id/timeline -- when each stage of a flow started
+/// and how long it took, as a waterfall: its connection and TLS handshake,
+/// each plugin, the upstream and writing the response to the client.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 404, 500))]
+pub async fn get_flow_timeline(
+    id: PathParam<String>,
+    depot: &mut Depot,
+) -> Result<Json<Waterfall>, StatusError> {
+    let registry = depot
+        .obtain::<AppState>()
+        .map(|s| s.plugin_registry.clone())
+        .map_err(|_| StatusError::internal_server_error().brief("Internal server error"))?
+        .ok_or_else(|| StatusError::bad_request().brief("Plugin system is disabled"))?;
+    let timelines = registry.read().await.timelines().clone();
+    timelines
+        .get(&id.into_inner())
+        .map(Json)
+        .ok_or_else(|| StatusError::not_found().brief("No timeline for this flow"))
+}
+
+/// GET /api/manage/flows/:id/storage -- the local storage keys plugins read
+/// and wrote while handling a flow, in order with their values, and the value
+/// each key was left with. Recorded with `plugins.snapshot_storage` enabled.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 404, 500))]
+pub async fn get_flow_storage(
+    id: PathParam<String>,
+    depot: &mut Depot,
+) -> Result<Json<FlowStorage>, StatusError> {
+    let registry = depot
+        .obtain::<AppState>()
+        .map(|s| s.plugin_registry.clone())
+        .map_err(|_| StatusError::internal_server_error().brief("Internal server error"))?
+        .ok_or_else(|| StatusError::bad_request().brief("Plugin system is disabled"))?;
+    let snapshots = registry.read().await.storage_snapshots().clone();
+    snapshots
+        .get(&id.into_inner())
+        .map(Json)
+        .ok_or_else(|| StatusError::not_found().brief("No storage snapshot for this flow"))
+}
+
+// ---------------------------------------------------------------------------
+// Capability audit endpoints
+// ---------------------------------------------------------------------------
+
+/// GET /api/manage/capability-audit -- each installed plugin's capabilities
+/// with their scopes, quotas, whether they're in effect and how often they
+/// were used in the last hour.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 500))]
+pub async fn get_capability_audit(
+    depot: &mut Depot,
+) -> Result<Json<Vec<PluginPosture>>, StatusError> {
+    let registry = depot
+        .obtain::<AppState>()
+        .map(|s| s.plugin_registry.clone())
+        .map_err(|_| StatusError::internal_server_error().brief("Internal server error"))?
+        .ok_or_else(|| StatusError::bad_request().brief("Plugin system is disabled"))?;
+    Ok(Json(registry.read().await.posture()))
+}
+
+/// GET /api/manage/capability-audit/:event -- the plugins given events of a
+/// kind, ex: `response`. With `?host=`, only those whose scope matches a GET
+/// request to the host, ex: which plugins can read response bodies from
+/// `www.bank.com`.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 500))]
+pub async fn get_event_audit(
+    event: PathParam<String>,
+    host: QueryParam<String, false>,
+    depot: &mut Depot,
+) -> Result<Json<EventPosture>, StatusError> {
+    let kind: EventKind = serde_json::from_value(serde_json::Value::String(event.into_inner()))
+        .map_err(|e| StatusError::bad_request().brief(e.to_string()))?;
+    let registry = depot
+        .obtain::<AppState>()
+        .map(|s| s.plugin_registry.clone())
+        .map_err(|_| StatusError::internal_server_error().brief("Internal server error"))?
+        .ok_or_else(|| StatusError::bad_request().brief("Plugin system is disabled"))?;
+    registry
+        .read()
+        .await
+        .event_posture(kind, host.into_inner().as_deref())
+        .map(Json)
+        .map_err(|e| StatusError::bad_request().brief(e.to_string()))
+}
+
+// ---------------------------------------------------------------------------
+// Traffic endpoints
+// ---------------------------------------------------------------------------
+
+/// GET /api/manage/traffic -- bytes each client profile has transferred in
+/// the current day, or month with `?period=month`, by host, with the
+/// profile's limits.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 500))]
+pub async fn get_traffic(
+    period: QueryParam<Period, false>,
+    depot: &mut Depot,
+) -> Result<Json<TrafficReport>, StatusError> {
+    let period = period.into_inner().unwrap_or(Period::Day);
+    let traffic = depot
+        .obtain::<Traffic>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("Traffic not available"))?;
+    let pool = db(depot)?;
+    // Include the traffic counted since it was last saved
+    let start = period.start(chrono::Utc::now());
+    let counted = match crate::db::traffic::persist(&pool, &traffic).await {
+        Ok(()) => StoredTraffic::list(&pool, period, start).await,
+        Err(e) => Err(e),
+    }
+    .map_err(|e| {
+        warn!("Failed to load traffic counters: {}", e);
+        StatusError::internal_server_error().brief("Internal error")
+    })?;
+    Ok(Json(traffic.report(period, start, counted)))
+}
+
+// ---------------------------------------------------------------------------
+// Flow filter endpoints
+// ---------------------------------------------------------------------------
+
+/// A flow query saved by name
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FlowFilter {
+    pub name: String,
+    pub query: FlowQuery,
+}
+
+impl TryFrom<StoredFlowFilter> for FlowFilter {
+    type Error = serde_json::Error;
+
+    fn try_from(stored: StoredFlowFilter) -> Result<Self, Self::Error> {
+        Ok(Self {
+            query: serde_json::from_str(&stored.query)?,
+            name: stored.name,
+        })
+    }
+}
+
+async fn saved_flow_filter(depot: &mut Depot, name: &str) -> Result<FlowQuery, StatusError> {
+    let pool = db(depot)?;
+    let stored = StoredFlowFilter::get(&pool, name)
+        .await
+        .map_err(|e| {
+            warn!("Failed to load flow filter: {}", e);
+            StatusError::internal_server_error().brief("Internal error")
+        })?
+        .ok_or_else(|| StatusError::not_found().brief("Flow filter not found"))?;
+    FlowFilter::try_from(stored)
+        .map(|filter| filter.query)
+        .map_err(|e| {
+            warn!("Invalid stored flow filter {}: {}", name, e);
+            StatusError::internal_server_error().brief("Internal error")
+        })
+}
+
+/// GET /api/manage/flow-filters -- list the saved flow filters.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 500))]
+pub async fn list_flow_filters(depot: &mut Depot) -> Result<Json<Vec<FlowFilter>>, StatusError> {
+    let pool = db(depot)?;
+    let stored = StoredFlowFilter::list(&pool).await.map_err(|e| {
+        warn!("Failed to list flow filters: {}", e);
+        StatusError::internal_server_error().brief("Internal error")
+    })?;
+    let filters = stored
+        .into_iter()
+        .filter_map(|stored| {
+            let name = stored.name.clone();
+            FlowFilter::try_from(stored)
+                .inspect_err(|e| warn!("Skipping invalid flow filter {}: {}", name, e))
+                .ok()
+        })
+        .collect();
+    Ok(Json(filters))
+}
+
+/// PUT /api/manage/flow-filters/:name -- save a flow query by name, ex:
+/// `{"min_status": 500}` as "api-errors", to list its flows with
+/// `GET /api/manage/flows?filter=api-errors`.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 500))]
+pub async fn save_flow_filter(
+    name: PathParam<String>,
+    body: JsonBody<FlowQuery>,
+    depot: &mut Depot,
+) -> Result<Json<FlowFilter>, StatusError> {
+    let pool = db(depot)?;
+    let name = name.into_inner();
+    if name.trim().is_empty() {
+        return Err(StatusError::bad_request().brief("Flow filter name is empty"));
+    }
+    let query = body.into_inner();
+    let json = serde_json::to_value(&query).unwrap_or_default();
+
+    StoredFlowFilter::upsert(&pool, &name, &json.to_string())
+        .await
+        .map_err(|e| {
+            warn!("Failed to store flow filter: {}", e);
+            StatusError::internal_server_error().brief("Internal error")
+        })?;
+
+    audit::record(depot, AuditAction::FlowFilterSave, Some(&name), json).await;
+
+    Ok(Json(FlowFilter { name, query }))
+}
+
+/// DELETE /api/manage/flow-filters/:name -- delete a saved flow filter.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 404, 500))]
+pub async fn delete_flow_filter(
+    name: PathParam<String>,
+    depot: &mut Depot,
+) -> Result<&'static str, StatusError> {
+    let pool = db(depot)?;
+    let name = name.into_inner();
+
+    let deleted = StoredFlowFilter::delete(&pool, &name).await.map_err(|e| {
+        warn!("Failed to delete flow filter: {}", e);
+        StatusError::internal_server_error().brief("Internal error")
+    })?;
+    if !deleted {
+        return Err(StatusError::not_found().brief("Flow filter not found"));
+    }
+
+    audit::record(
+        depot,
+        AuditAction::FlowFilterDelete,
+        Some(&name),
+        serde_json::json!({}),
+    )
+    .await;
+
+    Ok("Flow filter deleted")
+}
+
+// ---------------------------------------------------------------------------
+// Capture session endpoints
+// ---------------------------------------------------------------------------
+
+fn sessions(depot: &mut Depot) -> Result<CaptureSessions, StatusError> {
+    depot
+        .obtain::<CaptureSessions>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("Capture sessions not available"))
+}
+
+/// GET /api/manage/sessions -- list running and recently stopped capture
+/// sessions.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 500))]
+pub async fn list_sessions(depot: &mut Depot) -> Result<Json<Vec<SessionSummary>>, StatusError> {
+    Ok(Json(sessions(depot)?.list()))
+}
+
+/// POST /api/manage/sessions -- start a capture session, grouping the flows
+/// completed until it stops.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 500))]
+pub async fn start_session(
+    body: JsonBody<SessionSpec>,
+    depot: &mut Depot,
+) -> Result<Json<SessionSummary>, StatusError> {
+    let sessions = sessions(depot)?;
+    let spec = body.into_inner();
+    let details = serde_json::to_value(&spec).unwrap_or_default();
+    let summary = sessions
+        .start(spec)
+        .map_err(|e| StatusError::bad_request().brief(format!("{:#}", e)))?;
+
+    audit::record(depot, AuditAction::SessionStart, Some(&summary.id), details).await;
+
+    Ok(Json(summary))
+}
+
+/// POST /api/manage/sessions/:id/stop -- stop a capture session, writing
+/// its flows to its export targets.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 404, 500))]
+pub async fn stop_session(
+    id: PathParam<String>,
+    depot: &mut Depot,
+) -> Result<Json<SessionSummary>, StatusError> {
+    let sessions = sessions(depot)?;
+    let id = id.into_inner();
+    let summary = sessions
+        .stop(&id)
+        .await
+        .ok_or_else(|| StatusError::not_found().brief("Session not found"))?;
+
+    audit::record(
+        depot,
+        AuditAction::SessionStop,
+        Some(&id),
+        serde_json::json!({ "flows": summary.flows, "export_errors": summary.export_errors }),
+    )
+    .await;
+
+    Ok(Json(summary))
+}
+
+/// GET /api/manage/sessions/:id/flows -- the flows captured by a session,
+/// oldest first.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 404, 500))]
+pub async fn get_session_flows(
+    id: PathParam<String>,
+    depot: &mut Depot,
+) -> Result<Json<Vec<FlowRecord>>, StatusError> {
+    sessions(depot)?
+        .flows(&id.into_inner())
+        .map(Json)
+        .ok_or_else(|| StatusError::not_found().brief("Session not found"))
+}
+
+// ---------------------------------------------------------------------------
+// Flow tag rule endpoints
+// ---------------------------------------------------------------------------
+
+fn flow_tags(depot: &mut Depot) -> Result<FlowTags, StatusError> {
+    depot
+        .obtain::<FlowTags>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("Flow tags not available"))
+}
+
+/// GET /api/manage/flow-tags -- list the rules tagging flows as they
+/// complete.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 500))]
+pub async fn get_flow_tag_rules(depot: &mut Depot) -> Result<Json<Vec<FlowTagRule>>, StatusError> {
+    Ok(Json(flow_tags(depot)?.rules()))
+}
+
+/// PUT /api/manage/flow-tags -- replace the flow tag rules, applying them to
+/// new flows immediately and persisting them to disk.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 500))]
+pub async fn update_flow_tag_rules(
+    body: JsonBody<Vec<FlowTagRule>>,
+    depot: &mut Depot,
+) -> Result<Json<Vec<FlowTagRule>>, StatusError> {
+    let flow_tags = flow_tags(depot)?;
+    let mut config = depot
+        .obtain::<crate::config::AppConfig>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("Config not available"))?;
+    let config_path = depot
+        .obtain::<ConfigPath>()
+        .map(|p| p.0.clone())
+        .map_err(|_| StatusError::internal_server_error().brief("Config path not available"))?;
+
+    let rules = body.into_inner();
+    flow_tags
+        .set_rules(rules.clone())
+        .map_err(|e| StatusError::bad_request().brief(format!("{:#}", e)))?;
+
+    config.proxy.flow_tags = rules.clone();
+    config.save(&config_path).map_err(|e| {
+        warn!("Failed to save config: {}", e);
+        StatusError::internal_server_error().brief(format!("Failed to save config: {}", e))
+    })?;
+
+    audit::record(
+        depot,
+        AuditAction::ConfigUpdate,
+        Some("flow-tags"),
+        serde_json::to_value(&rules).unwrap_or_default(),
+    )
+    .await;
+
+    Ok(Json(rules))
+}
+
 /// Newtype for injecting the config file path via depot
 #[derive(Clone)]
 pub struct ConfigPath(pub std::path::PathBuf);
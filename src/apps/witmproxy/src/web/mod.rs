@@ -1,10 +1,12 @@
 pub mod acl_middleware;
+pub mod audit;
 pub mod auth;
 pub mod auth_endpoints;
 pub mod cert_distribution;
 pub mod device_detection;
 pub mod management;
 pub mod server;
+pub mod status;
 pub mod templates;
 
 use askama::Template;
@@ -32,6 +34,7 @@ mod auth_tests;
 pub struct AppState {
     pub ca: CertificateAuthority,
     pub plugin_registry: Option<Arc<RwLock<crate::plugins::registry::PluginRegistry>>>,
+    pub short_links: cert_distribution::ShortLinks,
 }
 
 #[derive(Debug, Serialize)]
@@ -114,6 +117,59 @@ pub async fn download_certificate(
     Ok(())
 }
 
+/// Largest OCSP request accepted, far above what a handful of CertIDs needs
+const MAX_OCSP_REQUEST: usize = 64 * 1024;
+
+fn ocsp_reply(res: &mut Response, response: Vec<u8>) {
+    res.status_code(salvo::http::StatusCode::OK)
+        .add_header(
+            salvo::http::header::CONTENT_TYPE,
+            "application/ocsp-response",
+            true,
+        )
+        .unwrap()
+        .body(response);
+}
+
+// OCSP responder for the certificates the proxy mints (RFC 6960), reporting
+// those minted with the `revoked` fault as revoked.
+#[endpoint(status_codes(200, 400))]
+pub async fn ocsp_post(
+    req: &mut Request,
+    res: &mut Response,
+    depot: &mut Depot,
+) -> Result<(), salvo::http::StatusError> {
+    let state = depot
+        .obtain::<AppState>()
+        .map_err(|_| salvo::http::StatusError::internal_server_error().brief("Internal error"))?;
+    let request = req
+        .payload_with_max_size(MAX_OCSP_REQUEST)
+        .await
+        .map_err(|_| salvo::http::StatusError::bad_request().brief("Invalid OCSP request"))?;
+    ocsp_reply(res, state.ca.ocsp_response(request));
+    Ok(())
+}
+
+// The GET form of the OCSP responder, with the request base64-encoded in the path
+#[endpoint(status_codes(200, 400))]
+pub async fn ocsp_get(
+    req: &mut Request,
+    res: &mut Response,
+    depot: &mut Depot,
+) -> Result<(), salvo::http::StatusError> {
+    use base64::Engine as _;
+
+    let state = depot
+        .obtain::<AppState>()
+        .map_err(|_| salvo::http::StatusError::internal_server_error().brief("Internal error"))?;
+    let encoded = req.param::<String>("request").unwrap_or_default();
+    let request = base64::engine::general_purpose::STANDARD
+        .decode(encoded.as_bytes())
+        .map_err(|_| salvo::http::StatusError::bad_request().brief("Invalid OCSP request"))?;
+    ocsp_reply(res, state.ca.ocsp_response(&request));
+    Ok(())
+}
+
 #[endpoint]
 pub async fn index_page(req: &mut salvo::Request, res: &mut salvo::Response) {
     let user_agent = req
@@ -1,15 +1,37 @@
-use super::{AppState, download_certificate, index_page};
+use super::{AppState, cert_distribution, download_certificate, index_page, ocsp_get, ocsp_post};
 use crate::cert::CertificateAuthority;
 use crate::config::AppConfig;
+use crate::db::audit::AuditAction;
+use crate::db::plugin_ratings::{PluginRating, RatingSummary};
+use crate::plugins::catalog::Listing;
+use crate::plugins::differential::DifferentialReport;
+use crate::plugins::dry_run::{self, DryRunResult, FlowFixture};
+use crate::plugins::quota::Quota;
 use crate::plugins::registry::PluginRegistry;
-use crate::web::{acl_middleware::acl_check, auth::jwt_auth, auth_endpoints, management};
+use crate::plugins::settings;
+use crate::proxy::ProxyStats;
+use crate::proxy::api_schemas::ApiSchemas;
+use crate::proxy::flow_tags::FlowTags;
+use crate::proxy::flow_trace::FlowTraces;
+use crate::proxy::header_overrides::HeaderOverrides;
+use crate::proxy::mocks::MockApis;
+use crate::proxy::network_profiles::NetworkConditions;
+use crate::proxy::protobuf::ProtobufDescriptors;
+use crate::proxy::security_headers::SecurityHeaders;
+use crate::proxy::sessions::CaptureSessions;
+use crate::proxy::traffic::Traffic;
+use crate::proxy::user_scripts::UserScripts;
+use crate::wasm::bindgen::witmproxy::plugin::capabilities::EventKind;
+use crate::wasm::bindgen::{InputSchema, UserInput};
+use crate::web::status::{self, RuntimeStatus};
+use crate::web::{acl_middleware::acl_check, audit, auth::jwt_auth, auth_endpoints, management};
 use anyhow::Result;
 use rust_embed::RustEmbed;
 use salvo::Writer;
 use salvo::conn::rustls::{Keycert, RustlsConfig};
 use salvo::cors::{AllowHeaders, AllowMethods, AllowOrigin, Cors};
 use salvo::oapi::endpoint;
-use salvo::oapi::extract::{FormFile, PathParam};
+use salvo::oapi::extract::{FormFile, PathParam, QueryParam};
 use salvo::prelude::ForceHttps;
 use salvo::serve_static::static_embed;
 use salvo::server::ServerHandle;
@@ -29,6 +51,18 @@ pub struct WebServer {
     config: AppConfig,
     config_path: Option<std::path::PathBuf>,
     db_pool: Option<SqlitePool>,
+    proxy_stats: Option<ProxyStats>,
+    security_headers: Option<SecurityHeaders>,
+    flow_tags: Option<FlowTags>,
+    sessions: Option<CaptureSessions>,
+    network_conditions: Option<NetworkConditions>,
+    header_overrides: Option<HeaderOverrides>,
+    traffic: Option<Traffic>,
+    mocks: Option<MockApis>,
+    user_scripts: Option<UserScripts>,
+    api_schemas: Option<ApiSchemas>,
+    protobuf: Option<ProtobufDescriptors>,
+    flow_traces: Option<FlowTraces>,
     shutdown_notify: Arc<Notify>,
     handle: Option<ServerHandle>,
 }
@@ -50,6 +84,18 @@ impl WebServer {
             config_path: None,
             plugin_registry,
             db_pool: None,
+            proxy_stats: None,
+            security_headers: None,
+            flow_tags: None,
+            sessions: None,
+            network_conditions: None,
+            header_overrides: None,
+            traffic: None,
+            mocks: None,
+            user_scripts: None,
+            api_schemas: None,
+            protobuf: None,
+            flow_traces: None,
             shutdown_notify: Arc::new(Notify::new()),
             handle: None,
         }
@@ -67,6 +113,89 @@ impl WebServer {
         self
     }
 
+    /// Set the proxy's live stats so the status endpoints can report on it.
+    pub fn with_proxy_stats(mut self, stats: ProxyStats) -> Self {
+        self.proxy_stats = Some(stats);
+        self
+    }
+
+    /// Set the proxy's live security header rules so the management API can
+    /// replace them.
+    pub fn with_security_headers(mut self, security_headers: SecurityHeaders) -> Self {
+        self.security_headers = Some(security_headers);
+        self
+    }
+
+    /// Set the proxy's live flow tag rules so the management API can
+    /// replace them.
+    pub fn with_flow_tags(mut self, flow_tags: FlowTags) -> Self {
+        self.flow_tags = Some(flow_tags);
+        self
+    }
+
+    /// Set the proxy's capture sessions so the management API can start and
+    /// stop them.
+    pub fn with_sessions(mut self, sessions: CaptureSessions) -> Self {
+        self.sessions = Some(sessions);
+        self
+    }
+
+    /// Set the proxy's live network condition rules so the management API
+    /// can switch clients between profiles.
+    pub fn with_network_conditions(mut self, network_conditions: NetworkConditions) -> Self {
+        self.network_conditions = Some(network_conditions);
+        self
+    }
+
+    /// Set the proxy's live header override rules so the management API can
+    /// switch the profiles forced on requests.
+    pub fn with_header_overrides(mut self, header_overrides: HeaderOverrides) -> Self {
+        self.header_overrides = Some(header_overrides);
+        self
+    }
+
+    /// Set the traffic the proxy counts by client profile so the management
+    /// API can report it.
+    pub fn with_traffic(mut self, traffic: Traffic) -> Self {
+        self.traffic = Some(traffic);
+        self
+    }
+
+    /// Set the proxy's mocked APIs so the management API can upload and
+    /// remove documents.
+    pub fn with_mocks(mut self, mocks: MockApis) -> Self {
+        self.mocks = Some(mocks);
+        self
+    }
+
+    /// Set the proxy's injected scripts so the management API can register
+    /// and remove them.
+    pub fn with_user_scripts(mut self, user_scripts: UserScripts) -> Self {
+        self.user_scripts = Some(user_scripts);
+        self
+    }
+
+    /// Set the proxy's inferred API schemas so the management API can
+    /// export them.
+    pub fn with_api_schemas(mut self, schemas: ApiSchemas) -> Self {
+        self.api_schemas = Some(schemas);
+        self
+    }
+
+    /// Set the proxy's protobuf descriptors so the management API can upload
+    /// descriptor sets and replace mappings.
+    pub fn with_protobuf(mut self, protobuf: ProtobufDescriptors) -> Self {
+        self.protobuf = Some(protobuf);
+        self
+    }
+
+    /// Set the proxy's traces of debugged flows so the management API can
+    /// return them.
+    pub fn with_flow_traces(mut self, traces: FlowTraces) -> Self {
+        self.flow_traces = Some(traces);
+        self
+    }
+
     /// Returns the actual bound listen address, if the server has been started
     pub fn listen_addr(&self) -> Option<SocketAddr> {
         self.listen_addr
@@ -87,6 +216,7 @@ impl WebServer {
         let state = AppState {
             ca: self.ca.clone(),
             plugin_registry: self.plugin_registry.clone(),
+            short_links: Default::default(),
         };
 
         salvo::http::request::set_global_secure_max_size(1024 * 1024 * 1024); // 1 GB
@@ -128,13 +258,27 @@ impl WebServer {
             .hoop(ForceHttps::new().https_port(self.listen_addr.unwrap().port()))
             .hoop(cors)
             .hoop(affix_state::inject(state))
+            .hoop(affix_state::inject(RuntimeStatus {
+                instance: self.config.telemetry.instance_id(),
+                profile: self.config.profile.clone(),
+                web_addr: self.listen_addr,
+                proxy_stats: self.proxy_stats.clone(),
+                started_at: std::time::Instant::now(),
+            }))
             .push(Router::with_path("/").get(index_page))
             .push(Router::with_path("/cert").get(download_certificate))
+            .push(Router::with_path("/qr").get(cert_distribution::qr_page))
+            .push(Router::with_path("/ocsp").post(ocsp_post))
+            .push(Router::with_path("/ocsp/{**request}").get(ocsp_get))
+            .push(Router::with_path("/c/{token}").get(cert_distribution::redeem_short_link))
             .push(
                 Router::with_path("/api/health")
                     .get(health_check)
                     .options(preflight),
             )
+            // Probes for supervisors (systemd, k8s) and dashboards
+            .push(Router::with_path("/healthz").get(status::healthz))
+            .push(Router::with_path("/readyz").get(status::readyz))
             // Static assets
             .push(Router::with_path("/static/{*path}").get(static_embed::<Assets>()));
 
@@ -147,6 +291,42 @@ impl WebServer {
                 .hoop(affix_state::inject(management::ConfigPath(
                     self.config_path.clone().unwrap_or_default(),
                 )));
+            if let Some(ref security_headers) = self.security_headers {
+                app = app.hoop(affix_state::inject(security_headers.clone()));
+            }
+            if let Some(ref flow_tags) = self.flow_tags {
+                app = app.hoop(affix_state::inject(flow_tags.clone()));
+            }
+            if let Some(ref sessions) = self.sessions {
+                app = app.hoop(affix_state::inject(sessions.clone()));
+            }
+            if let Some(ref network_conditions) = self.network_conditions {
+                app = app.hoop(affix_state::inject(network_conditions.clone()));
+            }
+            if let Some(ref header_overrides) = self.header_overrides {
+                app = app.hoop(affix_state::inject(header_overrides.clone()));
+            }
+            if let Some(ref traffic) = self.traffic {
+                app = app.hoop(affix_state::inject(traffic.clone()));
+            }
+            if let Some(ref registry) = self.plugin_registry {
+                app = app.hoop(affix_state::inject(registry.read().await.redactions()));
+            }
+            if let Some(ref mocks) = self.mocks {
+                app = app.hoop(affix_state::inject(mocks.clone()));
+            }
+            if let Some(ref user_scripts) = self.user_scripts {
+                app = app.hoop(affix_state::inject(user_scripts.clone()));
+            }
+            if let Some(ref api_schemas) = self.api_schemas {
+                app = app.hoop(affix_state::inject(api_schemas.clone()));
+            }
+            if let Some(ref protobuf) = self.protobuf {
+                app = app.hoop(affix_state::inject(protobuf.clone()));
+            }
+            if let Some(ref flow_traces) = self.flow_traces {
+                app = app.hoop(affix_state::inject(flow_traces.clone()));
+            }
 
             // Auth endpoints (unauthenticated, but need db pool + auth config)
             app = app
@@ -161,93 +341,7 @@ impl WebServer {
                         .options(preflight),
                 );
 
-            // Management endpoints (JWT + ACL protected)
-            // Routes ordered most-specific first to avoid prefix-matching issues
-            let manage_router = Router::new()
-                .hoop(jwt_auth)
-                .hoop(acl_check)
-                .push(
-                    Router::with_path("/api/manage/groups/{id}/permissions/{permission_id}")
-                        .delete(management::remove_group_permission)
-                        .options(preflight),
-                )
-                .push(
-                    Router::with_path("/api/manage/groups/{id}/permissions")
-                        .get(management::list_group_permissions)
-                        .post(management::add_group_permission)
-                        .options(preflight),
-                )
-                .push(
-                    Router::with_path("/api/manage/groups/{id}/members")
-                        .get(management::list_group_members)
-                        .post(management::add_group_member)
-                        .delete(management::remove_group_member)
-                        .options(preflight),
-                )
-                .push(
-                    Router::with_path("/api/manage/tenants/{id}/plugins/{ns}/{name}/enabled")
-                        .put(management::set_tenant_plugin_enabled)
-                        .options(preflight),
-                )
-                .push(
-                    Router::with_path("/api/manage/tenants/{id}/plugins/{ns}/{name}/config")
-                        .put(management::set_tenant_plugin_config)
-                        .options(preflight),
-                )
-                .push(
-                    Router::with_path("/api/manage/tenants/{id}/ip-mappings")
-                        .get(management::list_ip_mappings)
-                        .post(management::add_ip_mapping)
-                        .delete(management::remove_ip_mapping)
-                        .options(preflight),
-                )
-                .push(
-                    Router::with_path("/api/manage/groups/{id}")
-                        .delete(management::delete_group)
-                        .options(preflight),
-                )
-                .push(
-                    Router::with_path("/api/manage/tenants/{id}")
-                        .get(management::get_tenant)
-                        .put(management::update_tenant)
-                        .delete(management::delete_tenant)
-                        .options(preflight),
-                )
-                .push(
-                    Router::with_path("/api/manage/groups")
-                        .get(management::list_groups)
-                        .post(management::create_group)
-                        .options(preflight),
-                )
-                .push(
-                    Router::with_path("/api/manage/tenants")
-                        .get(management::list_tenants)
-                        .options(preflight),
-                )
-                .push(
-                    Router::with_path("/api/manage/config")
-                        .get(management::get_config)
-                        .put(management::update_config)
-                        .options(preflight),
-                )
-                .push(
-                    Router::with_path("/api/plugins")
-                        .get(list_plugins)
-                        .post(upsert_plugin)
-                        .options(preflight),
-                )
-                .push(
-                    Router::with_path("/api/plugins/{namespace}/{name}/enabled")
-                        .put(set_plugin_enabled)
-                        .options(preflight),
-                )
-                .push(
-                    Router::with_path("/api/plugins/{namespace}/{name}")
-                        .delete(delete_plugin)
-                        .options(preflight),
-                );
-
-            app = app.push(manage_router);
+            app = app.push(management_router());
         }
 
         let doc = OpenApi::new("witmproxy", env!("CARGO_PKG_VERSION"))
@@ -258,7 +352,8 @@ impl WebServer {
                     salvo::oapi::security::Http::new(salvo::oapi::security::HttpAuthScheme::Bearer)
                         .bearer_format("JWT")
                         .description(
-                            "JWT token obtained from /api/auth/login or /api/auth/register",
+                            "JWT obtained from /api/auth/login or /api/auth/register, \
+                             or an API token issued with `witm token create`",
                         ),
                 ),
             );
@@ -292,6 +387,296 @@ impl WebServer {
     }
 }
 
+/// Management endpoints (JWT + ACL protected), routed most-specific first
+/// to avoid prefix-matching issues
+pub(crate) fn management_router() -> Router {
+    Router::new()
+        .hoop(jwt_auth)
+        .hoop(acl_check)
+        // Lists the hosts being browsed, so unlike the probes it isn't public
+        .push(
+            Router::with_path("/api/status")
+                .get(status::status)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/groups/{id}/permissions/{permission_id}")
+                .delete(management::remove_group_permission)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/groups/{id}/permissions")
+                .get(management::list_group_permissions)
+                .post(management::add_group_permission)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/groups/{id}/members")
+                .get(management::list_group_members)
+                .post(management::add_group_member)
+                .delete(management::remove_group_member)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/tenants/{id}/plugins/{ns}/{name}/enabled")
+                .put(management::set_tenant_plugin_enabled)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/tenants/{id}/plugins/{ns}/{name}/config")
+                .put(management::set_tenant_plugin_config)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/tenants/{id}/ip-mappings")
+                .get(management::list_ip_mappings)
+                .post(management::add_ip_mapping)
+                .delete(management::remove_ip_mapping)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/groups/{id}")
+                .delete(management::delete_group)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/tenants/{id}")
+                .get(management::get_tenant)
+                .put(management::update_tenant)
+                .delete(management::delete_tenant)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/groups")
+                .get(management::list_groups)
+                .post(management::create_group)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/tenants")
+                .get(management::list_tenants)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/audit")
+                .get(audit::list_audit_log)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/config")
+                .get(management::get_config)
+                .put(management::update_config)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/security-headers")
+                .get(management::get_security_headers)
+                .put(management::update_security_headers)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/flow-tags")
+                .get(management::get_flow_tag_rules)
+                .put(management::update_flow_tag_rules)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/redactions")
+                .get(management::get_redactions)
+                .put(management::update_redactions)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/network-conditions")
+                .get(management::get_network_conditions)
+                .put(management::update_network_conditions)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/network-conditions/clients/{ip}")
+                .put(management::set_client_network_profile)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/header-overrides")
+                .get(management::get_header_overrides)
+                .put(management::update_header_overrides)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/header-overrides/profiles")
+                .get(management::list_header_profiles)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/header-overrides/selection")
+                .put(management::select_header_profile)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/mocks/{name}")
+                .put(management::upload_mock)
+                .delete(management::delete_mock)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/mocks")
+                .get(management::list_mocks)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/scripts/{name}")
+                .put(management::upload_user_script)
+                .delete(management::delete_user_script)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/scripts")
+                .get(management::list_user_scripts)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/schemas/{host}")
+                .get(management::export_api_schema)
+                .delete(management::delete_api_schema)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/schemas")
+                .get(management::list_api_schemas)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/protobuf/descriptors/{name}")
+                .put(management::upload_protobuf_descriptors)
+                .delete(management::delete_protobuf_descriptors)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/protobuf/descriptors")
+                .get(management::list_protobuf_descriptors)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/protobuf/mappings")
+                .get(management::get_protobuf_mappings)
+                .put(management::update_protobuf_mappings)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/flows")
+                .get(management::list_flows)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/flows/{id}/trace")
+                .get(management::get_flow_trace)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/flows/{id}/timeline")
+                .get(management::get_flow_timeline)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/flows/{id}/storage")
+                .get(management::get_flow_storage)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/flows/{id}/tags/{tag}")
+                .put(management::tag_flow)
+                .delete(management::untag_flow)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/sessions")
+                .get(management::list_sessions)
+                .post(management::start_session)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/sessions/{id}/flows")
+                .get(management::get_session_flows)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/sessions/{id}/stop")
+                .post(management::stop_session)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/capability-audit")
+                .get(management::get_capability_audit)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/capability-audit/{event}")
+                .get(management::get_event_audit)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/traffic")
+                .get(management::get_traffic)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/flow-filters")
+                .get(management::list_flow_filters)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/manage/flow-filters/{name}")
+                .put(management::save_flow_filter)
+                .delete(management::delete_flow_filter)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/cel/test")
+                .post(test_cel_expression)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/plugins")
+                .get(list_plugins)
+                .post(upsert_plugin)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/plugins/{namespace}/{name}/config")
+                .get(get_plugin_config)
+                .put(set_plugin_config)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/plugins/{namespace}/{name}/enabled")
+                .put(set_plugin_enabled)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/plugins/{namespace}/{name}/capabilities/{capability}/quota")
+                .put(set_capability_quota)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/plugins/{namespace}/{name}/candidate")
+                .get(get_candidate_report)
+                .put(set_candidate)
+                .delete(delete_candidate)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/plugins/{namespace}/{name}/rating")
+                .put(rate_plugin)
+                .options(preflight),
+        )
+        .push(
+            Router::with_path("/api/plugins/{namespace}/{name}")
+                .delete(delete_plugin)
+                .options(preflight),
+        )
+}
+
 /// Responds to CORS preflight OPTIONS requests with 204 No Content.
 /// The CORS hoop adds the Access-Control-Allow-* headers automatically.
 #[endpoint]
@@ -317,6 +702,12 @@ struct PluginSummary {
     url: String,
     enabled: bool,
     capabilities: Vec<PluginCapSummary>,
+    /// Settings the plugin accepts, for building its configuration form
+    settings: Vec<InputSchema>,
+    /// Categories, icon and screenshots from the plugin's metadata
+    #[serde(flatten)]
+    listing: Listing,
+    rating: Option<RatingSummary>,
 }
 
 #[derive(serde::Serialize)]
@@ -324,10 +715,16 @@ struct PluginCapSummary {
     kind: String,
     scope: String,
     granted: bool,
+    #[serde(skip_serializing_if = "Quota::is_unlimited")]
+    quota: Quota,
 }
 
 #[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 500))]
-async fn list_plugins(depot: &mut Depot, res: &mut salvo::Response) {
+async fn list_plugins(
+    category: QueryParam<String, false>,
+    depot: &mut Depot,
+    res: &mut salvo::Response,
+) {
     let registry = if let Ok(state) = depot.obtain::<AppState>() {
         state.plugin_registry.clone()
     } else {
@@ -339,10 +736,23 @@ async fn list_plugins(depot: &mut Depot, res: &mut salvo::Response) {
 
     if let Some(registry) = registry {
         let registry = registry.read().await;
+        let mut ratings = PluginRating::summaries(&registry.db.pool)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Failed to load plugin ratings: {}", e);
+                Default::default()
+            });
+        let category = category.into_inner();
         let plugins: Vec<PluginSummary> = registry
             .plugins()
             .values()
-            .map(|p| PluginSummary {
+            .map(|p| (p, p.listing()))
+            .filter(|(_, listing)| {
+                category
+                    .as_deref()
+                    .is_none_or(|category| listing.in_category(category))
+            })
+            .map(|(p, listing)| PluginSummary {
                 namespace: p.namespace.clone(),
                 name: p.name.clone(),
                 version: p.version.clone(),
@@ -358,8 +768,12 @@ async fn list_plugins(depot: &mut Depot, res: &mut salvo::Response) {
                         kind: c.inner.kind.to_string(),
                         scope: c.inner.scope.expression.clone(),
                         granted: c.granted,
+                        quota: c.quota.clone(),
                     })
                     .collect(),
+                settings: p.configuration_schema.clone(),
+                listing,
+                rating: ratings.remove(&p.id()),
             })
             .collect();
         res.status_code(salvo::http::StatusCode::OK);
@@ -431,11 +845,21 @@ async fn upsert_plugin(
             return;
         }
     };
+    let plugin_id = plugin.id();
+    let details = serde_json::json!({
+        "version": plugin.version,
+        "capabilities": plugin
+            .capabilities
+            .iter()
+            .map(|c| c.inner.kind.to_string())
+            .collect::<Vec<_>>(),
+    });
     let mut registry = registry.write().await;
 
     let result = registry.register_plugin(plugin).await;
     match result {
         Ok(_) => {
+            audit::record(depot, AuditAction::PluginInstall, Some(&plugin_id), details).await;
             res.status_code(salvo::http::StatusCode::OK);
             res.render(salvo::writing::Text::Plain(
                 "Plugin added/updated successfully",
@@ -486,6 +910,15 @@ async fn delete_plugin(
                 res.status_code(salvo::http::StatusCode::NOT_FOUND);
                 res.render(salvo::writing::Text::Plain("Plugin not found"));
             } else {
+                for plugin_id in &removed {
+                    audit::record(
+                        depot,
+                        AuditAction::PluginRemove,
+                        Some(plugin_id),
+                        serde_json::json!({}),
+                    )
+                    .await;
+                }
                 res.status_code(salvo::http::StatusCode::OK);
                 res.render(salvo::writing::Text::Plain("Plugin removed successfully"));
             }
@@ -531,14 +964,434 @@ async fn set_plugin_enabled(
 
     let mut reg = registry.write().await;
     let plugin_id = format!("{}/{}", ns, plugin_name);
-    if let Some(plugin) = reg.plugins_mut().get_mut(&plugin_id) {
-        plugin.enabled = enabled;
-        Ok(if enabled {
-            "Plugin enabled"
-        } else {
-            "Plugin disabled"
-        })
+    let Some(plugin) = reg.plugins_mut().get_mut(&plugin_id) else {
+        return Err(salvo::http::StatusError::not_found().brief("Plugin not found"));
+    };
+    plugin.enabled = enabled;
+    drop(reg);
+
+    audit::record(
+        depot,
+        AuditAction::PluginEnable,
+        Some(&plugin_id),
+        serde_json::json!({ "enabled": enabled }),
+    )
+    .await;
+    Ok(if enabled {
+        "Plugin enabled"
     } else {
-        Err(salvo::http::StatusError::not_found().brief("Plugin not found"))
+        "Plugin disabled"
+    })
+}
+
+#[derive(serde::Deserialize, salvo::oapi::ToSchema)]
+struct RatePluginBody {
+    /// From 1 to 5
+    stars: u8,
+}
+
+/// PUT /api/plugins/{namespace}/{name}/rating -- rate a plugin, replacing the
+/// caller's earlier rating of it.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 404, 500))]
+async fn rate_plugin(
+    namespace: PathParam<String>,
+    name: PathParam<String>,
+    body: salvo::oapi::extract::JsonBody<RatePluginBody>,
+    depot: &mut Depot,
+) -> Result<&'static str, salvo::http::StatusError> {
+    let registry = depot
+        .obtain::<AppState>()
+        .map(|s| s.plugin_registry.clone())
+        .map_err(|_| {
+            salvo::http::StatusError::internal_server_error().brief("Internal server error")
+        })?;
+
+    let registry = registry.ok_or_else(|| {
+        salvo::http::StatusError::bad_request().brief("Plugin system is disabled")
+    })?;
+
+    let ns = namespace.into_inner();
+    let plugin_name = name.into_inner();
+    let stars = body.into_inner().stars;
+    if !(1..=5).contains(&stars) {
+        return Err(salvo::http::StatusError::bad_request().brief("Ratings are from 1 to 5 stars"));
+    }
+
+    let reg = registry.read().await;
+    if !reg
+        .plugins()
+        .contains_key(&format!("{}/{}", ns, plugin_name))
+    {
+        return Err(salvo::http::StatusError::not_found().brief("Plugin not found"));
     }
+    PluginRating::rate(&reg.db.pool, &ns, &plugin_name, &audit::actor(depot), stars)
+        .await
+        .map_err(|e| {
+            warn!("Failed to rate plugin {}/{}: {}", ns, plugin_name, e);
+            salvo::http::StatusError::internal_server_error().brief("Failed to save rating")
+        })?;
+    Ok("Rating saved")
+}
+
+/// PUT /api/plugins/{namespace}/{name}/capabilities/{capability}/quota -- limit
+/// how much a plugin can use a capability, ex: `handle_event_request`. Takes
+/// effect on the next event.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 404, 500))]
+async fn set_capability_quota(
+    namespace: PathParam<String>,
+    name: PathParam<String>,
+    capability: PathParam<String>,
+    body: salvo::oapi::extract::JsonBody<Quota>,
+    depot: &mut Depot,
+) -> Result<&'static str, salvo::http::StatusError> {
+    let registry = depot
+        .obtain::<AppState>()
+        .map(|s| s.plugin_registry.clone())
+        .map_err(|_| {
+            salvo::http::StatusError::internal_server_error().brief("Internal server error")
+        })?;
+
+    let registry = registry.ok_or_else(|| {
+        salvo::http::StatusError::bad_request().brief("Plugin system is disabled")
+    })?;
+
+    let ns = namespace.into_inner();
+    let plugin_name = name.into_inner();
+    let capability_name = capability.into_inner();
+    let quota = body.into_inner();
+
+    let mut reg = registry.write().await;
+    let pool = reg.db.pool.clone();
+    let plugin_id = format!("{}/{}", ns, plugin_name);
+    let Some(plugin) = reg.plugins_mut().get_mut(&plugin_id) else {
+        return Err(salvo::http::StatusError::not_found().brief("Plugin not found"));
+    };
+    let Some(capability) = plugin
+        .capabilities
+        .iter_mut()
+        .find(|c| c.inner.kind.to_string() == capability_name)
+    else {
+        return Err(salvo::http::StatusError::not_found().brief("Capability not requested"));
+    };
+
+    let quota_json = serde_json::to_string(&quota).map_err(|e| {
+        salvo::http::StatusError::internal_server_error().brief(format!("Failed: {}", e))
+    })?;
+    sqlx::query(
+        "UPDATE plugin_capabilities SET quota = ? WHERE namespace = ? AND name = ? AND capability = ?",
+    )
+    .bind(&quota_json)
+    .bind(&ns)
+    .bind(&plugin_name)
+    .bind(&capability_name)
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        warn!("Failed to set capability quota: {}", e);
+        salvo::http::StatusError::internal_server_error().brief(format!("Failed: {}", e))
+    })?;
+    capability.quota = quota.clone();
+    drop(reg);
+
+    audit::record(
+        depot,
+        AuditAction::CapabilityGrant,
+        Some(&plugin_id),
+        serde_json::json!({ "capability": capability_name, "quota": quota }),
+    )
+    .await;
+    Ok("Capability quota updated")
+}
+
+/// PUT /api/plugins/{namespace}/{name}/candidate -- upload another version of
+/// an installed plugin to run alongside it: it's given a copy of each event the
+/// installed version handles, and where its outcome differs is recorded for
+/// the report. Live traffic keeps getting the installed version's outcome.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 404, 500))]
+async fn set_candidate(
+    namespace: PathParam<String>,
+    name: PathParam<String>,
+    file: FormFile,
+    depot: &mut Depot,
+) -> Result<&'static str, salvo::http::StatusError> {
+    let registry = depot
+        .obtain::<AppState>()
+        .map(|s| s.plugin_registry.clone())
+        .map_err(|_| {
+            salvo::http::StatusError::internal_server_error().brief("Internal server error")
+        })?;
+
+    let registry = registry.ok_or_else(|| {
+        salvo::http::StatusError::bad_request().brief("Plugin system is disabled")
+    })?;
+
+    let plugin_id = format!("{}/{}", namespace.into_inner(), name.into_inner());
+    let bytes = fs::read(file.path()).await.map_err(|e| {
+        warn!("Failed to read uploaded file: {}", e);
+        salvo::http::StatusError::internal_server_error().brief("Failed to read uploaded file")
+    })?;
+    let candidate = registry
+        .read()
+        .await
+        .plugin_from_component(bytes)
+        .await
+        .map_err(|e| {
+            salvo::http::StatusError::bad_request().brief(format!("Failed to parse plugin: {}", e))
+        })?;
+    if candidate.id() != plugin_id {
+        return Err(salvo::http::StatusError::bad_request().brief(format!(
+            "The upload is {}, not {}",
+            candidate.id(),
+            plugin_id
+        )));
+    }
+    let version = candidate.version.clone();
+
+    registry
+        .write()
+        .await
+        .add_candidate(candidate)
+        .map_err(|_| salvo::http::StatusError::not_found().brief("Plugin not found"))?;
+    audit::record(
+        depot,
+        AuditAction::PluginInstall,
+        Some(&plugin_id),
+        serde_json::json!({ "candidate": version }),
+    )
+    .await;
+    Ok("Candidate added")
+}
+
+/// GET /api/plugins/{namespace}/{name}/candidate -- how the candidate's
+/// outcomes compare to the installed version's.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 404, 500))]
+async fn get_candidate_report(
+    namespace: PathParam<String>,
+    name: PathParam<String>,
+    depot: &mut Depot,
+) -> Result<salvo::writing::Json<DifferentialReport>, salvo::http::StatusError> {
+    let registry = depot
+        .obtain::<AppState>()
+        .map(|s| s.plugin_registry.clone())
+        .map_err(|_| {
+            salvo::http::StatusError::internal_server_error().brief("Internal server error")
+        })?;
+
+    let registry = registry.ok_or_else(|| {
+        salvo::http::StatusError::bad_request().brief("Plugin system is disabled")
+    })?;
+
+    let plugin_id = format!("{}/{}", namespace.into_inner(), name.into_inner());
+    registry
+        .read()
+        .await
+        .differential_report(&plugin_id)
+        .map(salvo::writing::Json)
+        .ok_or_else(|| salvo::http::StatusError::not_found().brief("No candidate for plugin"))
+}
+
+/// DELETE /api/plugins/{namespace}/{name}/candidate -- stop running the
+/// candidate, discarding its report.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 404, 500))]
+async fn delete_candidate(
+    namespace: PathParam<String>,
+    name: PathParam<String>,
+    depot: &mut Depot,
+) -> Result<&'static str, salvo::http::StatusError> {
+    let registry = depot
+        .obtain::<AppState>()
+        .map(|s| s.plugin_registry.clone())
+        .map_err(|_| {
+            salvo::http::StatusError::internal_server_error().brief("Internal server error")
+        })?;
+
+    let registry = registry.ok_or_else(|| {
+        salvo::http::StatusError::bad_request().brief("Plugin system is disabled")
+    })?;
+
+    let plugin_id = format!("{}/{}", namespace.into_inner(), name.into_inner());
+    let Some(candidate) = registry.write().await.remove_candidate(&plugin_id) else {
+        return Err(salvo::http::StatusError::not_found().brief("No candidate for plugin"));
+    };
+    audit::record(
+        depot,
+        AuditAction::PluginRemove,
+        Some(&plugin_id),
+        serde_json::json!({ "candidate": candidate.version }),
+    )
+    .await;
+    Ok("Candidate removed")
+}
+
+/// GET /api/plugins/{namespace}/{name}/config -- global configuration of a plugin,
+/// as a map of input name to JSON-encoded value.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 404, 500))]
+async fn get_plugin_config(
+    namespace: PathParam<String>,
+    name: PathParam<String>,
+    depot: &mut Depot,
+) -> Result<salvo::writing::Json<std::collections::HashMap<String, String>>, salvo::http::StatusError>
+{
+    let registry = depot
+        .obtain::<AppState>()
+        .map(|s| s.plugin_registry.clone())
+        .map_err(|_| {
+            salvo::http::StatusError::internal_server_error().brief("Internal server error")
+        })?;
+
+    let registry = registry.ok_or_else(|| {
+        salvo::http::StatusError::bad_request().brief("Plugin system is disabled")
+    })?;
+
+    let plugin_id = format!("{}/{}", namespace.into_inner(), name.into_inner());
+    let reg = registry.read().await;
+    let Some(plugin) = reg.plugins().get(&plugin_id) else {
+        return Err(salvo::http::StatusError::not_found().brief("Plugin not found"));
+    };
+    let config = plugin
+        .configuration
+        .iter()
+        .map(|input| {
+            (
+                input.name.clone(),
+                serde_json::to_string(&input.value).unwrap_or_default(),
+            )
+        })
+        .collect();
+    Ok(salvo::writing::Json(config))
+}
+
+/// PUT /api/plugins/{namespace}/{name}/config -- set global configuration values
+/// of a plugin. Values are parsed into the types the plugin declares, and take
+/// effect on the next event.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 404, 500))]
+async fn set_plugin_config(
+    namespace: PathParam<String>,
+    name: PathParam<String>,
+    body: salvo::oapi::extract::JsonBody<management::SetPluginConfigRequest>,
+    depot: &mut Depot,
+) -> Result<&'static str, salvo::http::StatusError> {
+    let registry = depot
+        .obtain::<AppState>()
+        .map(|s| s.plugin_registry.clone())
+        .map_err(|_| {
+            salvo::http::StatusError::internal_server_error().brief("Internal server error")
+        })?;
+
+    let registry = registry.ok_or_else(|| {
+        salvo::http::StatusError::bad_request().brief("Plugin system is disabled")
+    })?;
+
+    let ns = namespace.into_inner();
+    let plugin_name = name.into_inner();
+    let body = body.into_inner();
+
+    let mut reg = registry.write().await;
+    let pool = reg.db.pool.clone();
+    let plugin_id = format!("{}/{}", ns, plugin_name);
+    let Some(plugin) = reg.plugins_mut().get_mut(&plugin_id) else {
+        return Err(salvo::http::StatusError::not_found().brief("Plugin not found"));
+    };
+
+    // Check every value before storing any
+    let mut inputs = Vec::with_capacity(body.config.len());
+    for (input_name, input_value) in &body.config {
+        let value = settings::check_input(&plugin.configuration_schema, input_name, input_value)
+            .map_err(|e| salvo::http::StatusError::bad_request().brief(e.to_string()))?;
+        inputs.push((input_name, value));
+    }
+
+    for (input_name, value) in inputs {
+        let value_json = serde_json::to_string(&value).map_err(|e| {
+            salvo::http::StatusError::internal_server_error().brief(format!("Failed: {}", e))
+        })?;
+        sqlx::query(
+            "INSERT OR REPLACE INTO plugin_configuration (namespace, name, input_name, input_value) VALUES (?, ?, ?, ?)",
+        )
+        .bind(&ns)
+        .bind(&plugin_name)
+        .bind(input_name)
+        .bind(&value_json)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            warn!("Failed to set plugin config: {}", e);
+            salvo::http::StatusError::internal_server_error().brief(format!("Failed: {}", e))
+        })?;
+
+        match plugin
+            .configuration
+            .iter_mut()
+            .find(|input| &input.name == input_name)
+        {
+            Some(input) => input.value = value,
+            None => plugin.configuration.push(UserInput {
+                name: input_name.clone(),
+                value,
+            }),
+        }
+    }
+    drop(reg);
+
+    audit::record(
+        depot,
+        AuditAction::PluginConfigure,
+        Some(&plugin_id),
+        serde_json::json!({ "inputs": body.config.keys().collect::<Vec<_>>() }),
+    )
+    .await;
+    Ok("Plugin config updated")
+}
+
+#[derive(serde::Deserialize, salvo::oapi::ToSchema)]
+struct CelTestBody {
+    /// Event kind the expression scopes, ex: "request" or "inbound_content"
+    event: String,
+    expression: String,
+    /// Synthetic flow to evaluate against
+    fixture: Option<FlowFixture>,
+    /// ID of a recently captured flow to evaluate against, instead of a fixture
+    flow_id: Option<String>,
+}
+
+/// POST /api/cel/test -- evaluate a capability scope expression against a
+/// captured flow or a synthetic fixture, without installing a plugin.
+#[endpoint(security(("bearer" = [])), status_codes(200, 400, 401, 403, 404, 500))]
+async fn test_cel_expression(
+    body: salvo::oapi::extract::JsonBody<CelTestBody>,
+    depot: &mut Depot,
+) -> Result<salvo::writing::Json<DryRunResult>, salvo::http::StatusError> {
+    let body = body.into_inner();
+    let kind: EventKind = serde_json::from_value(serde_json::Value::String(body.event))
+        .map_err(|e| salvo::http::StatusError::bad_request().brief(e.to_string()))?;
+
+    let fixture = match (body.fixture, body.flow_id) {
+        (Some(fixture), None) => fixture,
+        (None, Some(flow_id)) => {
+            let registry = depot
+                .obtain::<AppState>()
+                .map(|s| s.plugin_registry.clone())
+                .map_err(|_| {
+                    salvo::http::StatusError::internal_server_error().brief("Internal server error")
+                })?
+                .ok_or_else(|| {
+                    salvo::http::StatusError::bad_request().brief("Plugin system is disabled")
+                })?;
+            let flow = registry.read().await.flows().get(&flow_id);
+            let flow = flow.ok_or_else(|| {
+                salvo::http::StatusError::not_found().brief("Flow not found in the recent flow log")
+            })?;
+            FlowFixture::from(&flow)
+        }
+        _ => {
+            return Err(salvo::http::StatusError::bad_request()
+                .brief("Provide exactly one of fixture or flow_id"));
+        }
+    };
+
+    Ok(salvo::writing::Json(dry_run::evaluate(
+        kind,
+        &body.expression,
+        &fixture,
+    )))
 }
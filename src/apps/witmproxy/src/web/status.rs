@@ -0,0 +1,239 @@
+use salvo::http::StatusError;
+use salvo::oapi::{ToSchema, endpoint};
+use salvo::prelude::*;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::Instant;
+use tracing::warn;
+
+use super::AppState;
+use crate::proxy::ProxyStats;
+use crate::proxy::host_limits::HostQueueStatus;
+
+/// Runtime information about the running instance, injected into the depot
+/// so the health and status endpoints can report on it.
+#[derive(Clone)]
+pub struct RuntimeStatus {
+    /// See [TelemetryConfig::instance_id](crate::config::TelemetryConfig::instance_id)
+    pub instance: String,
+    /// The configuration profile applied, if any
+    pub profile: Option<String>,
+    pub web_addr: Option<SocketAddr>,
+    pub proxy_stats: Option<ProxyStats>,
+    pub started_at: Instant,
+}
+
+// ---------------------------------------------------------------------------
+// Response types
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StatusResponse {
+    pub version: String,
+    /// Identifies this instance when several run side by side
+    pub instance: String,
+    /// The configuration profile applied, if any
+    pub profile: Option<String>,
+    pub uptime_secs: u64,
+    pub ready: bool,
+    pub listeners: ListenerStatus,
+    pub active_connections: usize,
+    /// Upstream hosts with requests in flight under a per-host limit
+    pub upstream_queues: Vec<HostQueueStatus>,
+    pub plugins: PluginRegistryStatus,
+    pub db: DbStatus,
+    pub ca: CaStatus,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ListenerStatus {
+    pub proxy: Option<String>,
+    pub web: Option<String>,
+    /// Every bound proxy listener, starting with the primary one
+    pub proxies: Vec<ProxyListenerStatus>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProxyListenerStatus {
+    pub name: String,
+    pub addr: String,
+    pub mitm: String,
+    pub require_auth: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PluginRegistryStatus {
+    /// Whether the plugin system is enabled at all
+    pub enabled: bool,
+    pub loaded: usize,
+    pub active: usize,
+    /// Loaded plugins that are currently disabled and will not receive events
+    pub disabled: usize,
+    /// Plugins refused for failing integrity checks, by ID, with the reason
+    pub refused: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DbStatus {
+    pub configured: bool,
+    pub healthy: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CaStatus {
+    /// RFC 3339 expiry of the root certificate
+    pub expires_at: Option<String>,
+    pub days_remaining: Option<i64>,
+}
+
+// ---------------------------------------------------------------------------
+// Checks
+// ---------------------------------------------------------------------------
+
+async fn check_db(depot: &Depot) -> DbStatus {
+    let Ok(pool) = depot.obtain::<SqlitePool>() else {
+        return DbStatus {
+            configured: false,
+            healthy: true,
+            error: None,
+        };
+    };
+
+    match sqlx::query("SELECT 1").execute(pool).await {
+        Ok(_) => DbStatus {
+            configured: true,
+            healthy: true,
+            error: None,
+        },
+        Err(e) => {
+            warn!("Database health check failed: {}", e);
+            DbStatus {
+                configured: true,
+                healthy: false,
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+fn is_ready(runtime: Option<&RuntimeStatus>, db: &DbStatus) -> bool {
+    let proxy_bound = runtime
+        .and_then(|r| r.proxy_stats.as_ref())
+        .is_none_or(|stats| stats.listen_addr().is_some());
+    proxy_bound && db.healthy
+}
+
+// ---------------------------------------------------------------------------
+// Endpoints
+// ---------------------------------------------------------------------------
+
+/// GET /healthz -- liveness probe, succeeds as long as the web server responds.
+#[endpoint(status_codes(200))]
+pub async fn healthz() -> &'static str {
+    "OK"
+}
+
+/// GET /readyz -- readiness probe, succeeds once the proxy listener is bound
+/// and the database (if configured) is reachable.
+#[endpoint(status_codes(200, 503))]
+pub async fn readyz(depot: &mut Depot) -> Result<&'static str, StatusError> {
+    let db = check_db(depot).await;
+    if is_ready(depot.obtain::<RuntimeStatus>().ok(), &db) {
+        Ok("OK")
+    } else {
+        Err(StatusError::service_unavailable().brief("Not ready"))
+    }
+}
+
+/// GET /api/status -- detailed status for dashboards. Authenticated, as it
+/// lists the hosts being browsed.
+#[endpoint(security(("bearer" = [])), status_codes(200, 401, 403, 500))]
+pub async fn status(depot: &mut Depot) -> Result<Json<StatusResponse>, StatusError> {
+    let state = depot
+        .obtain::<AppState>()
+        .cloned()
+        .map_err(|_| StatusError::internal_server_error().brief("Internal error"))?;
+    let runtime = depot.obtain::<RuntimeStatus>().ok().cloned();
+    let db = check_db(depot).await;
+    let ready = is_ready(runtime.as_ref(), &db);
+
+    let plugins = match &state.plugin_registry {
+        Some(registry) => {
+            let registry = registry.read().await;
+            let loaded = registry.plugins().len();
+            let active = registry.plugins().values().filter(|p| p.enabled).count();
+            PluginRegistryStatus {
+                enabled: true,
+                loaded,
+                active,
+                disabled: loaded - active,
+                refused: registry.refused().clone().into_iter().collect(),
+            }
+        }
+        None => PluginRegistryStatus {
+            enabled: false,
+            loaded: 0,
+            active: 0,
+            disabled: 0,
+            refused: BTreeMap::new(),
+        },
+    };
+
+    let ca = match state.ca.root_certificate_expiry() {
+        Ok(expiry) => CaStatus {
+            expires_at: chrono::DateTime::from_timestamp(expiry.unix_timestamp(), 0)
+                .map(|dt| dt.to_rfc3339()),
+            days_remaining: Some((expiry - time::OffsetDateTime::now_utc()).whole_days()),
+        },
+        Err(e) => {
+            warn!("Failed to read root certificate expiry: {}", e);
+            CaStatus {
+                expires_at: None,
+                days_remaining: None,
+            }
+        }
+    };
+
+    let proxy_stats = runtime.as_ref().and_then(|r| r.proxy_stats.as_ref());
+    Ok(Json(StatusResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        instance: runtime
+            .as_ref()
+            .map(|r| r.instance.clone())
+            .unwrap_or_default(),
+        profile: runtime.as_ref().and_then(|r| r.profile.clone()),
+        uptime_secs: runtime
+            .as_ref()
+            .map(|r| r.started_at.elapsed().as_secs())
+            .unwrap_or_default(),
+        ready,
+        listeners: ListenerStatus {
+            proxy: proxy_stats
+                .and_then(|s| s.listen_addr())
+                .map(|a| a.to_string()),
+            web: runtime
+                .as_ref()
+                .and_then(|r| r.web_addr)
+                .map(|a| a.to_string()),
+            proxies: proxy_stats
+                .map(|s| s.listeners())
+                .unwrap_or_default()
+                .iter()
+                .map(|l| ProxyListenerStatus {
+                    name: l.name.clone(),
+                    addr: l.addr.to_string(),
+                    mitm: l.mitm.to_string(),
+                    require_auth: l.require_auth,
+                })
+                .collect(),
+        },
+        active_connections: proxy_stats.map(|s| s.active_connections()).unwrap_or(0),
+        upstream_queues: proxy_stats.map(|s| s.host_queues()).unwrap_or_default(),
+        plugins,
+        db,
+        ca,
+    }))
+}
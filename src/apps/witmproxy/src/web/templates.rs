@@ -54,3 +54,76 @@ impl InstructionsTemplate {
         }
     }
 }
+
+/// QR code for installing the certificate from a phone
+#[derive(Template)]
+#[template(path = "qr.html")]
+pub struct QrTemplate {
+    /// Inline SVG image of the QR code
+    pub svg: String,
+    pub short_link: String,
+    pub format: String,
+    pub ttl_minutes: u64,
+}
+
+/// Error page served by the proxy in place of an upstream response
+#[derive(Template)]
+#[template(path = "error.html")]
+pub struct ErrorPageTemplate {
+    pub status: u16,
+    pub reason: String,
+    pub title: String,
+    pub message: String,
+    /// Code of the error, ex: "upstream.unreachable" (empty for pages
+    /// served for no particular error)
+    pub code: String,
+    pub host: String,
+    pub flow_id: String,
+}
+
+impl ErrorPageTemplate {
+    pub fn new(
+        status: hyper::StatusCode,
+        title: impl Into<String>,
+        message: impl Into<String>,
+        code: impl Into<String>,
+        host: impl Into<String>,
+        flow_id: impl Into<String>,
+    ) -> Self {
+        Self {
+            status: status.as_u16(),
+            reason: status.canonical_reason().unwrap_or("Error").to_string(),
+            title: title.into(),
+            message: message.into(),
+            code: code.into(),
+            host: host.into(),
+            flow_id: flow_id.into(),
+        }
+    }
+}
+
+/// Page served when a plugin blocks a request
+#[derive(Template)]
+#[template(path = "block.html")]
+pub struct BlockPageTemplate {
+    pub reason: String,
+    pub plugin: String,
+    pub host: String,
+    pub flow_id: String,
+}
+
+impl BlockPageTemplate {
+    pub fn new(
+        reason: impl Into<String>,
+        plugin: impl Into<String>,
+        host: impl Into<String>,
+        flow_id: impl Into<String>,
+    ) -> Self {
+        Self {
+            reason: reason.into(),
+            plugin: plugin.into(),
+            host: host.into(),
+            flow_id: flow_id.into(),
+        }
+    }
+}
@@ -136,6 +136,8 @@ impl GuestPlugin for PluginInstance {
                 Some(Event::InboundContent(content))
             }
             Event::Timer(ctx) => Some(Event::Timer(ctx)),
+            Event::RawStream(ctx) => Some(Event::RawStream(ctx)),
+            Event::MqttMessage(message) => Some(Event::MqttMessage(message)),
         }
     }
 }